@@ -0,0 +1,25 @@
+// build.rs
+//! Records build information for the `GET /version` endpoint.
+
+use std::process::Command;
+
+fn main() {
+    // Prefer an explicit `GIT_COMMIT` (e.g. from a CI system or Docker build argument), since the
+    // repository may not be available at build time.
+    let commit = std::env::var("GIT_COMMIT").ok().or_else(|| {
+        Command::new("git")
+            .args(&["rev-parse", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|commit| commit.trim().to_string())
+    });
+
+    println!(
+        "cargo:rustc-env=MONITORING_RS_GIT_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+}
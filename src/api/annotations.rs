@@ -0,0 +1,64 @@
+// src/api/annotations.rs
+//! Annotating log entries and time ranges (see [`crate::log_database::annotation`]).
+//!
+//! - `GET /annotations` lists every annotation.
+//! - `POST /annotations` adds an annotation, taking a JSON body like
+//!   `{"entry": "<id>", "tags": ["incident-start"], "note": "..."}` for an entry (with an ID from
+//!   `GET /logs/:key/:value?annotations=true`), or
+//!   `{"selector": {"namespace": "payments"}, "start": 1600000000, "end": 1600003600, ...}` for a
+//!   time range. The annotation's author is the request's tenant.
+//! - `DELETE /annotations/:id` removes an annotation.
+
+use std::io;
+
+use crate::log_database::annotation::Annotation;
+
+use super::error::error_response;
+use super::{usage, State};
+
+pub(super) async fn list_annotations(req: tide::Request<State>) -> tide::Result {
+    let database = req.state().database.read().await;
+
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&database.annotations())?)
+        .build())
+}
+
+pub(super) async fn add_annotation(mut req: tide::Request<State>) -> tide::Result {
+    let mut annotation: Annotation = req.body_json().await?;
+    annotation.author = usage::tenant(&req);
+    let mut database = req.state().database.write().await;
+
+    Ok(match database.annotate(annotation) {
+        Ok(annotation) => tide::Response::builder(tide::StatusCode::Created)
+            .body(tide::Body::from_json(&annotation)?)
+            .build(),
+        Err(error) if error.kind() == io::ErrorKind::InvalidInput => error_response(
+            tide::StatusCode::BadRequest,
+            "bad_request",
+            error.to_string(),
+            None,
+        ),
+        Err(error) => return Err(error.into()),
+    })
+}
+
+pub(super) async fn remove_annotation(req: tide::Request<State>) -> tide::Result {
+    let id: u64 = req
+        .param("id")?
+        .parse()
+        .map_err(|error| tide::Error::new(tide::StatusCode::BadRequest, error))?;
+    let mut database = req.state().database.write().await;
+
+    Ok(match database.remove_annotation(id)? {
+        Some(annotation) => tide::Response::builder(tide::StatusCode::Ok)
+            .body(tide::Body::from_json(&annotation)?)
+            .build(),
+        None => error_response(
+            tide::StatusCode::NotFound,
+            "not_found",
+            format!("no annotation with ID {}", id),
+            None,
+        ),
+    })
+}
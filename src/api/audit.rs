@@ -0,0 +1,175 @@
+// src/api/audit.rs
+//! Auditing of queries made through the API.
+//!
+//! Every query is recorded as a JSON line in an internal stream of the log database, so the audit
+//! trail can be queried like any other logs (e.g. `GET /logs/__internal/query_audit`), though only
+//! with the `admin` scope when authentication is enabled. Queries that take at least
+//! [`Config::slow_query_threshold`](super::Config::slow_query_threshold) are also recorded in the
+//! `slow_queries` internal stream, and logged as warnings.
+//!
+//! Auditing is best-effort: queries only queue their records for an [`Auditor`] to write in the
+//! background, so they never wait for the database's write lock, and a record that can't be
+//! written is logged and dropped rather than failing the query.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_std::sync::RwLock;
+use async_std::task;
+use log::warn;
+
+use crate::log_database::{Database, QueryStats};
+use crate::LogEntry;
+
+/// The metadata key used to identify internal streams.
+pub(super) const INTERNAL_KEY: &str = "__internal";
+
+const QUERY_AUDIT_STREAM: &str = "query_audit";
+
+const SLOW_QUERY_STREAM: &str = "slow_queries";
+
+/// The most audit records waiting to be written. Records are dropped while the queue is full.
+const QUEUE_CAPACITY: usize = 1000;
+
+/// The most records written while holding the database lock, so queries aren't starved.
+const MAX_WRITE_BATCH: usize = 100;
+
+/// An audit record for a single query.
+#[derive(Debug, serde::Serialize)]
+pub(super) struct QueryRecord<'a> {
+    /// When the query completed, in milliseconds since the Unix epoch.
+    timestamp: u64,
+
+    /// The selector used for the query, e.g. `namespace=default`.
+    selector: &'a str,
+
+    /// The tenant the query was made on behalf of.
+    tenant: &'a str,
+
+    /// The remote address of the caller.
+    caller: &'a str,
+
+    /// The start of the queried time range, in milliseconds since the Unix epoch, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start: Option<u64>,
+
+    /// The end of the queried time range, in milliseconds since the Unix epoch, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end: Option<u64>,
+
+    /// How long the query took, in milliseconds.
+    duration_ms: u64,
+
+    #[serde(flatten)]
+    stats: QueryStats,
+}
+
+impl<'a> QueryRecord<'a> {
+    pub(super) fn new(
+        selector: &'a str,
+        tenant: &'a str,
+        caller: &'a str,
+        range: (Option<SystemTime>, Option<SystemTime>),
+        duration: Duration,
+        stats: QueryStats,
+    ) -> Self {
+        let (start, end) = range;
+        Self {
+            timestamp: since_epoch(SystemTime::now()),
+            selector,
+            tenant,
+            caller,
+            start: start.map(since_epoch),
+            end: end.map(since_epoch),
+            duration_ms: millis(duration),
+            stats,
+        }
+    }
+}
+
+/// Writes audit records to the database in a background task.
+#[derive(Debug)]
+pub(super) struct Auditor {
+    sender: async_channel::Sender<LogEntry>,
+}
+
+impl Auditor {
+    /// Start writing audit records to `database`. Writing stops once the `Auditor` is dropped.
+    pub(super) fn spawn(database: Arc<RwLock<Database>>) -> Self {
+        let (sender, receiver) = async_channel::bounded(QUEUE_CAPACITY);
+        task::spawn(write_records(database, receiver));
+        Self { sender }
+    }
+
+    /// Queue a query's record for the audit stream, and the slow query stream if it took at least
+    /// `slow_query_threshold`.
+    pub(super) fn record(&self, record: &QueryRecord<'_>, slow_query_threshold: Duration) {
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(error) => {
+                warn!("Failed to serialize query audit record: {}", error);
+                return;
+            }
+        };
+        let is_slow = Duration::from_millis(record.duration_ms) >= slow_query_threshold;
+
+        if is_slow {
+            warn!(target: "monitoring_rs::slow_query", "{}", line);
+        }
+
+        self.queue(internal_entry(QUERY_AUDIT_STREAM, line.clone()));
+        if is_slow {
+            self.queue(internal_entry(SLOW_QUERY_STREAM, line));
+        }
+    }
+
+    fn queue(&self, entry: LogEntry) {
+        if self.sender.try_send(entry).is_err() {
+            warn!("Dropped a query audit record because the audit queue is full");
+        }
+    }
+}
+
+async fn write_records(
+    database: Arc<RwLock<Database>>,
+    receiver: async_channel::Receiver<LogEntry>,
+) {
+    while let Ok(entry) = receiver.recv().await {
+        let mut database = database.write().await;
+        write_record(&mut database, &entry);
+
+        // Write whatever else has been queued while we have the lock.
+        for _ in 1..MAX_WRITE_BATCH {
+            match receiver.try_recv() {
+                Ok(entry) => write_record(&mut database, &entry),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+fn write_record(database: &mut Database, entry: &LogEntry) {
+    if let Err(error) = database.write(entry) {
+        warn!("Failed to write query audit record: {}", error);
+    }
+}
+
+fn internal_entry(stream: &str, line: String) -> LogEntry {
+    let mut metadata = HashMap::with_capacity(1);
+    metadata.insert(INTERNAL_KEY.to_string(), stream.to_string());
+    LogEntry {
+        line,
+        metadata,
+        timestamp: None,
+    }
+}
+
+fn since_epoch(time: SystemTime) -> u64 {
+    millis(time.duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
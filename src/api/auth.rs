@@ -0,0 +1,278 @@
+// src/api/auth.rs
+//! Authentication of API requests.
+//!
+//! Requests are authenticated with a bearer token (`Authorization: Bearer <token>`), which is
+//! checked against each configured [`AuthProvider`] in turn. The first provider to accept the token
+//! determines the request's [`Identity`]: the tenant that usage is accounted to, and the scopes
+//! the request is granted.
+//!
+//! Routes require a scope: `read` for queries, `write` for `POST /push`, `export` for `/exports`,
+//! and `admin` for the admin API. Requests without a token are rejected from those routes with
+//! `401 Unauthorized`, and requests without the required scope with `403 Forbidden`. Invalid
+//! tokens are rejected from every route. Internal streams (e.g. the [audit](super::audit) trail) can only be queried with the
+//! `admin` scope.
+//!
+//! If no providers are configured, authentication is disabled and every request is allowed.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::error::error_response;
+use super::{audit, State};
+
+/// The scope required to query logs.
+pub(super) const READ: RequireScope = RequireScope("read");
+
+/// The scope required to push logs.
+pub(super) const WRITE: RequireScope = RequireScope("write");
+
+/// The scope required to export logs to object storage.
+pub(super) const EXPORT: RequireScope = RequireScope("export");
+
+/// The scope required to use the admin API.
+pub(super) const ADMIN: RequireScope = RequireScope("admin");
+
+/// Something that can authenticate bearer tokens.
+#[tide::utils::async_trait]
+pub trait AuthProvider: Debug + Send + Sync {
+    /// Authenticate `token`, returning the identity it represents.
+    ///
+    /// # Errors
+    ///
+    /// If the token is not valid for this provider, a description of the problem is returned.
+    async fn authenticate(&self, token: &str) -> Result<Identity, String>;
+}
+
+/// The identity of an authenticated request.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Identity {
+    /// The tenant the request is made on behalf of, if known.
+    ///
+    /// If not set, the request is accounted to the `anonymous` tenant.
+    pub tenant: Option<String>,
+
+    /// The scopes granted to the request.
+    pub scopes: Vec<String>,
+}
+
+/// An [`AuthProvider`] for a fixed set of tokens.
+#[derive(Debug, Default)]
+pub struct StaticTokens(HashMap<String, Identity>);
+
+impl StaticTokens {
+    /// Construct a provider accepting the given `tokens`.
+    #[must_use]
+    pub fn new(tokens: Vec<StaticToken>) -> Self {
+        Self(
+            tokens
+                .into_iter()
+                .map(|token| (token.token, token.identity))
+                .collect(),
+        )
+    }
+}
+
+#[tide::utils::async_trait]
+impl AuthProvider for StaticTokens {
+    async fn authenticate(&self, token: &str) -> Result<Identity, String> {
+        self.0
+            .get(token)
+            .cloned()
+            .ok_or_else(|| "unrecognised token".to_string())
+    }
+}
+
+/// A token accepted by [`StaticTokens`], and the identity it represents.
+///
+/// This can be parsed from strings like `<token>:<tenant>:<scope>,<scope>`. The tenant may be
+/// empty, in which case queries are accounted to the `anonymous` tenant.
+#[derive(Clone, Debug)]
+pub struct StaticToken {
+    token: String,
+    identity: Identity,
+}
+
+impl FromStr for StaticToken {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut parts = input.splitn(3, ':');
+        let (token, tenant, scopes) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(token), Some(tenant), Some(scopes)) if !token.is_empty() => {
+                (token, tenant, scopes)
+            }
+            _ => {
+                return Err(
+                    "invalid static token: must be `<token>:<tenant>:<scope>,<scope>`".to_string(),
+                )
+            }
+        };
+
+        Ok(Self {
+            token: token.to_string(),
+            identity: Identity {
+                tenant: Some(tenant.to_string()).filter(|tenant| !tenant.is_empty()),
+                scopes: scopes
+                    .split(',')
+                    .filter(|scope| !scope.is_empty())
+                    .map(ToString::to_string)
+                    .collect(),
+            },
+        })
+    }
+}
+
+/// The reason a request's bearer token was found invalid by [`Authenticate`].
+#[derive(Clone, Debug)]
+struct InvalidToken(String);
+
+/// Middleware that authenticates bearer tokens, attaching an [`Identity`] to the request.
+///
+/// Requests with invalid tokens are passed on without an identity, and rejected by
+/// [`RejectInvalid`]. This lets middleware in between (i.e. rate limiting) treat them as
+/// unauthenticated.
+#[derive(Debug, Default)]
+pub(super) struct Authenticate;
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for Authenticate {
+    async fn handle(
+        &self,
+        mut req: tide::Request<State>,
+        next: tide::Next<'_, State>,
+    ) -> tide::Result {
+        let config = Arc::clone(&req.state().config);
+        if config.auth_providers.is_empty() {
+            return Ok(next.run(req).await);
+        }
+
+        let token = match req.header("Authorization") {
+            Some(values) => match values.last().as_str().strip_prefix("Bearer ") {
+                Some(token) => token.trim().to_string(),
+                None => {
+                    req.set_ext(InvalidToken("expected a bearer token".to_string()));
+                    return Ok(next.run(req).await);
+                }
+            },
+            None => return Ok(next.run(req).await),
+        };
+
+        let mut errors = Vec::new();
+        for provider in &config.auth_providers {
+            match provider.authenticate(&token).await {
+                Ok(identity) => {
+                    req.set_ext(identity);
+                    return Ok(next.run(req).await);
+                }
+                Err(error) => errors.push(error),
+            }
+        }
+        req.set_ext(InvalidToken(format!(
+            "invalid token: {}",
+            errors.join("; ")
+        )));
+        Ok(next.run(req).await)
+    }
+}
+
+/// Middleware that rejects requests whose bearer token [`Authenticate`] found invalid.
+#[derive(Debug, Default)]
+pub(super) struct RejectInvalid;
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for RejectInvalid {
+    async fn handle(&self, req: tide::Request<State>, next: tide::Next<'_, State>) -> tide::Result {
+        match req.ext::<InvalidToken>() {
+            Some(InvalidToken(message)) => Ok(unauthorized(message.clone())),
+            None => Ok(next.run(req).await),
+        }
+    }
+}
+
+/// Middleware that rejects requests without a particular scope.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct RequireScope(&'static str);
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for RequireScope {
+    async fn handle(&self, req: tide::Request<State>, next: tide::Next<'_, State>) -> tide::Result {
+        if req.state().config.auth_providers.is_empty() {
+            return Ok(next.run(req).await);
+        }
+
+        match req.ext::<Identity>() {
+            None => Ok(unauthorized("authentication required".to_string())),
+            Some(identity) if !identity.scopes.iter().any(|scope| scope == self.0) => {
+                Ok(error_response(
+                    tide::StatusCode::Forbidden,
+                    "forbidden",
+                    format!("missing scope `{}`", self.0),
+                    None,
+                ))
+            }
+            Some(_) => Ok(next.run(req).await),
+        }
+    }
+}
+
+/// Whether `req` has the scope required by `scope`, which it always does if authentication is
+/// disabled.
+pub(super) fn has_scope(req: &tide::Request<State>, scope: RequireScope) -> bool {
+    req.state().config.auth_providers.is_empty()
+        || req.ext::<Identity>().map_or(false, |identity| {
+            identity.scopes.iter().any(|s| s == scope.0)
+        })
+}
+
+/// Reject a query selecting internal streams by any of `keys` (see [`audit`](super::audit)) unless
+/// the request has the `admin` scope, since they record other users' queries.
+pub(super) fn forbid_internal<'a>(
+    req: &tide::Request<State>,
+    mut keys: impl Iterator<Item = &'a str>,
+) -> Option<tide::Response> {
+    if !keys.any(|key| key == audit::INTERNAL_KEY) || has_scope(req, ADMIN) {
+        return None;
+    }
+    Some(error_response(
+        tide::StatusCode::Forbidden,
+        "forbidden",
+        format!("internal streams require the `{}` scope", ADMIN.0),
+        None,
+    ))
+}
+
+fn unauthorized(message: String) -> tide::Response {
+    let mut response = error_response(
+        tide::StatusCode::Unauthorized,
+        "unauthorized",
+        message,
+        None,
+    );
+    response.insert_header("WWW-Authenticate", "Bearer");
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Identity, StaticToken};
+
+    #[test]
+    fn parse_static_token() {
+        let token: StaticToken = "s3cret:team-a:read,write".parse().unwrap();
+        assert_eq!(token.token, "s3cret");
+        assert_eq!(
+            token.identity,
+            Identity {
+                tenant: Some("team-a".to_string()),
+                scopes: vec!["read".to_string(), "write".to_string()],
+            }
+        );
+
+        let token: StaticToken = "s3cret::admin".parse().unwrap();
+        assert_eq!(token.identity.tenant, None);
+
+        assert!("s3cret:team-a".parse::<StaticToken>().is_err());
+    }
+}
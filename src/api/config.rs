@@ -0,0 +1,72 @@
+// src/api/config.rs
+//! Introspection of the running configuration.
+//!
+//! `GET /config` returns [`Config::effective_config`](super::Config::effective_config), which
+//! includes defaults and any environment or command line overrides, so that the configuration of
+//! a running process can be checked without access to its environment. Values of any keys that
+//! look like they hold secrets are redacted.
+
+use super::State;
+
+/// Keys containing any of these (case-insensitive) have their values redacted.
+const SECRET_KEY_FRAGMENTS: &[&str] = &["secret", "token", "password", "credential", "private"];
+
+/// The value that replaces redacted values.
+const REDACTED: &str = "[redacted]";
+
+pub(super) async fn get_config(req: tide::Request<State>) -> tide::Result {
+    let mut config = req.state().config.effective_config.clone();
+    redact(&mut config);
+
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&config)?)
+        .build())
+}
+
+/// Redact the values of any secret-looking keys in `value`, recursively.
+fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret(key) && !value.is_null() {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SECRET_KEY_FRAGMENTS
+        .iter()
+        .any(|fragment| key.contains(fragment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::redact;
+
+    #[test]
+    fn redacts_secret_keys() {
+        let mut config = serde_json::json!({
+            "listen": ["0.0.0.0:8000"],
+            "auth": { "api_token": "hunter2", "oidc_client_secret": null },
+            "peers": [{ "url": "http://peer", "password": "hunter2" }],
+        });
+        redact(&mut config);
+
+        assert_eq!(
+            config,
+            serde_json::json!({
+                "listen": ["0.0.0.0:8000"],
+                "auth": { "api_token": "[redacted]", "oidc_client_secret": null },
+                "peers": [{ "url": "http://peer", "password": "[redacted]" }],
+            })
+        );
+    }
+}
@@ -0,0 +1,116 @@
+// src/api/cors.rs
+//! Cross-origin resource sharing, so browser UIs hosted elsewhere can query the API directly.
+//!
+//! CORS is disabled unless [`Config::cors`](super::Config::cors) is set. Requests with an `Origin`
+//! header matching one of the allowed origins (or any origin, if `*` is allowed) get CORS response
+//! headers, and preflight `OPTIONS` requests are answered directly, before authentication. Requests
+//! from other origins are served without CORS headers, so browsers won't expose the responses.
+//!
+//! The API's own response headers (e.g. `X-Next-Cursor` and the query cost headers) are always
+//! exposed to scripts.
+
+use std::time::Duration;
+
+use super::State;
+
+/// The response headers that scripts may read.
+const EXPOSED_HEADERS: &[&str] = &[
+    "X-Next-Cursor",
+    "X-Query-Bytes-Scanned",
+    "X-Query-Bytes-Returned",
+    "X-Query-Duration-Micros",
+    super::error::REQUEST_ID_HEADER,
+];
+
+/// CORS configuration for the HTTP API.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The origins (like `https://dashboard.example.com`) allowed to make requests, or `*` for
+    /// any origin.
+    pub allowed_origins: Vec<String>,
+
+    /// The methods allowed in cross-origin requests.
+    pub allowed_methods: Vec<String>,
+
+    /// The request headers allowed in cross-origin requests.
+    pub allowed_headers: Vec<String>,
+
+    /// How long browsers may cache the result of a preflight request.
+    pub max_age: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec![
+                "GET".to_string(),
+                "POST".to_string(),
+                "PUT".to_string(),
+                "DELETE".to_string(),
+            ],
+            allowed_headers: vec![
+                "Authorization".to_string(),
+                "Content-Type".to_string(),
+                super::error::REQUEST_ID_HEADER.to_string(),
+            ],
+            max_age: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl Config {
+    fn allows(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin)
+    }
+}
+
+/// Middleware that adds CORS headers to responses, and answers preflight requests.
+#[derive(Debug, Default)]
+pub(super) struct Cors;
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for Cors {
+    async fn handle(&self, req: tide::Request<State>, next: tide::Next<'_, State>) -> tide::Result {
+        let config = match &req.state().config.cors {
+            Some(config) => config.clone(),
+            None => return Ok(next.run(req).await),
+        };
+        let origin = match req.header("Origin") {
+            Some(values) if config.allows(values.last().as_str()) => {
+                values.last().as_str().to_string()
+            }
+            _ => return Ok(next.run(req).await),
+        };
+
+        let preflight = req.method() == tide::http::Method::Options
+            && req.header("Access-Control-Request-Method").is_some();
+        let mut response = if preflight {
+            let mut response = tide::Response::new(tide::StatusCode::NoContent);
+            response.insert_header(
+                "Access-Control-Allow-Methods",
+                config.allowed_methods.join(", "),
+            );
+            response.insert_header(
+                "Access-Control-Allow-Headers",
+                config.allowed_headers.join(", "),
+            );
+            response.insert_header(
+                "Access-Control-Max-Age",
+                config.max_age.as_secs().to_string(),
+            );
+            response
+        } else {
+            let mut response = next.run(req).await;
+            response.insert_header("Access-Control-Expose-Headers", EXPOSED_HEADERS.join(", "));
+            response
+        };
+
+        // The allowed origin is echoed rather than `*`, so responses vary by origin.
+        response.insert_header("Access-Control-Allow-Origin", origin);
+        response.append_header("Vary", "Origin");
+        Ok(response)
+    }
+}
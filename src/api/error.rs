@@ -0,0 +1,163 @@
+// src/api/error.rs
+//! Structured error responses.
+//!
+//! Every API response with a 4xx or 5xx status has a JSON body like:
+//!
+//! ```json
+//! {
+//!   "code": "not_found",
+//!   "message": "Not Found",
+//!   "details": null,
+//!   "request_id": "16b4f2c07a3e1d00-2a"
+//! }
+//! ```
+//!
+//! - `code` is a stable, machine-readable error code. Codes currently used are `bad_request`,
+//!   `unauthorized`, `forbidden`, `not_found`, `method_not_allowed`, `conflict`, `limit_exceeded`,
+//!   `response_too_large`, `incompatible_protocol`, `internal`, and `unavailable`; `error` is used
+//!   for any other status.
+//! - `message` is a human-readable description of the error.
+//! - `details` is any additional, endpoint-specific information, or `null`.
+//! - `request_id` identifies the request, and is also returned in the [`REQUEST_ID_HEADER`]
+//!   response header of every response. Clients can supply their own ID in the same request
+//!   header.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tide::http::mime;
+
+use crate::log_database::limits::TooBusy;
+
+/// The header used to identify requests.
+pub(super) const REQUEST_ID_HEADER: &str = "X-Request-ID";
+
+/// Distinguishes request IDs generated by this process.
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A specific error code and message for an error response.
+///
+/// Handlers can attach this to responses (with [`error_response`]) to override the defaults,
+/// which are derived from the response status.
+#[derive(Clone, Debug)]
+pub(super) struct ApiError {
+    code: &'static str,
+    message: String,
+}
+
+#[derive(serde::Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+    details: Option<serde_json::Value>,
+    request_id: String,
+}
+
+/// Construct an error response with a specific `code` and `message`, and optional `details`.
+pub(super) fn error_response(
+    status: tide::StatusCode,
+    code: &'static str,
+    message: String,
+    details: Option<serde_json::Value>,
+) -> tide::Response {
+    let mut response = tide::Response::new(status);
+    response.insert_ext(ApiError { code, message });
+    if let Some(details) = details {
+        response.set_body(details);
+    }
+    response
+}
+
+/// Convert an error from a log database query into a response.
+///
+/// Queries rejected because too many are already running get a `429 Too Many Requests` response,
+/// and any other error is propagated.
+pub(super) fn query_error(error: io::Error) -> tide::Result {
+    match TooBusy::find(&error) {
+        Some(too_busy) => Ok(error_response(
+            tide::StatusCode::TooManyRequests,
+            "limit_exceeded",
+            too_busy.to_string(),
+            None,
+        )),
+        None => Err(error.into()),
+    }
+}
+
+/// Middleware that assigns request IDs and wraps error responses in the error envelope.
+#[derive(Debug, Default)]
+pub(super) struct ErrorEnvelope;
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for ErrorEnvelope {
+    async fn handle(&self, req: tide::Request<State>, next: tide::Next<'_, State>) -> tide::Result {
+        let request_id = req
+            .header(REQUEST_ID_HEADER)
+            .map_or_else(generate_request_id, |values| {
+                values.last().as_str().to_string()
+            });
+
+        let mut response = next.run(req).await;
+        response.insert_header(REQUEST_ID_HEADER, request_id.as_str());
+
+        let status = response.status();
+        if !status.is_client_error() && !status.is_server_error() {
+            return Ok(response);
+        }
+
+        let (code, message) = match response.ext::<ApiError>() {
+            Some(error) => (error.code, error.message.clone()),
+            None => (
+                default_code(status),
+                response.error().map_or_else(
+                    || status.canonical_reason().to_string(),
+                    ToString::to_string,
+                ),
+            ),
+        };
+
+        let details = if response.content_type() == Some(mime::JSON) {
+            response.take_body().into_json().await.ok()
+        } else {
+            None
+        };
+
+        response.set_body(tide::Body::from_json(&ErrorBody {
+            code,
+            message,
+            details,
+            request_id,
+        })?);
+        Ok(response)
+    }
+}
+
+fn default_code(status: tide::StatusCode) -> &'static str {
+    use tide::StatusCode;
+
+    match status {
+        StatusCode::BadRequest => "bad_request",
+        StatusCode::Unauthorized => "unauthorized",
+        StatusCode::Forbidden => "forbidden",
+        StatusCode::NotFound => "not_found",
+        StatusCode::MethodNotAllowed => "method_not_allowed",
+        StatusCode::Conflict => "conflict",
+        StatusCode::PayloadTooLarge | StatusCode::TooManyRequests => "limit_exceeded",
+        StatusCode::InternalServerError => "internal",
+        StatusCode::ServiceUnavailable => "unavailable",
+        _ => "error",
+    }
+}
+
+fn generate_request_id() -> String {
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!(
+        "{:x}-{:x}",
+        started_at,
+        NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
@@ -0,0 +1,216 @@
+// src/api/events.rs
+//! Querying the event [`Database`] (see [`Config::events`]), for processes that embed both stores.
+//!
+//! - `GET /events/streams?match=app=api&match=!canary` returns the labels of every stream
+//!   satisfying all of the `match`ed [`Matcher`]s (or every stream, if there are none).
+//! - `GET /events/query?match=app=api&start=1h` returns `{"events": [...], "warnings": [...]}` with
+//!   the events of the matching streams, and any [retention warnings](Database::query_with_warnings).
+//!   Event data is returned as text, replacing any invalid UTF-8.
+//! - `GET /events/query?match=app=api&start=1h&aggregate=count&bucket=1m` returns
+//!   `{"series": [...]}` with an [`Aggregation`] of the matching events per stream instead.
+//!   `aggregate` is `count` or `rate`, and `bucket` (required) and `window` (for `rate`, defaulting
+//!   to `bucket`) are durations like `30s` or `5m`.
+//!
+//! `start` and `end` are optional, and accept the same expressions as `GET /query` (see
+//! [`range`](super::range)). Time ranges can only be combined with `name=value` matchers, and not
+//! with two matchers for different values of the same name.
+//!
+//! Without an event database, these endpoints respond `404 Not Found`.
+//!
+//! [`Config::events`]: super::Config::events
+//! [`Database`]: crate::database::Database
+//! [`Database::query_with_warnings`]: crate::database::Database::query_with_warnings
+
+use std::convert::TryFrom;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::database::{Aggregation, Event, Labels, Matcher, Query};
+use crate::log_database::retention;
+use crate::record::Timestamp;
+
+use super::error::{error_response, query_error};
+use super::range::{parse_time, Offset};
+use super::State;
+
+pub(super) async fn list_streams(req: tide::Request<State>) -> tide::Result {
+    let database = match &req.state().config.events {
+        Some(database) => database,
+        None => return Ok(not_configured()),
+    };
+    let matchers = match parse_matchers(&req) {
+        Ok(matchers) => matchers,
+        Err(message) => return Ok(bad_request(message)),
+    };
+    let streams = database.lock().await.streams(&matchers);
+
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&streams)?)
+        .build())
+}
+
+pub(super) async fn query_events(req: tide::Request<State>) -> tide::Result {
+    let database = match &req.state().config.events {
+        Some(database) => database,
+        None => return Ok(not_configured()),
+    };
+    let parsed = parse_query(&req).and_then(|query| Ok((query, parse_aggregation(&req)?)));
+    let (query, aggregation) = match parsed {
+        Ok(parsed) => parsed,
+        Err(message) => return Ok(bad_request(message)),
+    };
+
+    let database = database.lock().await;
+    let body = match aggregation {
+        Some(aggregation) => match database.aggregate(&query, &aggregation) {
+            Ok(series) => serde_json::json!({ "series": series }),
+            Err(error) if error.kind() == io::ErrorKind::InvalidInput => {
+                return Ok(bad_request(error.to_string()))
+            }
+            Err(error) => return query_error(error),
+        },
+        None => match database.query_with_warnings(&query) {
+            Ok(result) => {
+                let events: Vec<_> = result.events.iter().map(event_json).collect();
+                serde_json::json!({
+                    "events": events,
+                    "warnings": result.warnings,
+                })
+            }
+            Err(error) => return query_error(error),
+        },
+    };
+
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&body)?)
+        .build())
+}
+
+fn not_configured() -> tide::Response {
+    error_response(
+        tide::StatusCode::NotFound,
+        "not_found",
+        "no event database is configured".to_string(),
+        None,
+    )
+}
+
+fn bad_request(message: String) -> tide::Response {
+    error_response(tide::StatusCode::BadRequest, "bad_request", message, None)
+}
+
+fn event_json(event: &Event) -> serde_json::Value {
+    serde_json::json!({
+        "timestamp": event.timestamp(),
+        "data": String::from_utf8_lossy(event.data()),
+        "value": event.value(),
+    })
+}
+
+/// Parse the `match` parameters of `req`'s query string.
+fn parse_matchers(req: &tide::Request<State>) -> Result<Vec<Matcher>, String> {
+    req.url()
+        .query_pairs()
+        .filter(|(name, _)| name == "match")
+        .map(|(_, value)| value.parse())
+        .collect()
+}
+
+/// Parse the `match`, `start`, `end`, and `tz` parameters of `req`'s query string into a
+/// [`Query`].
+fn parse_query(req: &tide::Request<State>) -> Result<Query, String> {
+    let matchers = parse_matchers(req)?;
+    let now = (req.state().config.clock)();
+    let tz = match req.url().query_pairs().find(|(name, _)| name == "tz") {
+        Some((_, tz)) => tz.parse()?,
+        None => Offset::default(),
+    };
+    let (mut start, mut end) = (None, None);
+    for (name, value) in req.url().query_pairs() {
+        match &*name {
+            "start" | "since" => start = Some(timestamp(parse_time(&name, &value, now, tz)?)),
+            "end" | "until" => end = Some(timestamp(parse_time(&name, &value, now, tz)?)),
+            _ => {}
+        }
+    }
+
+    if start.is_none() && end.is_none() {
+        return Ok(Query::Matchers(matchers));
+    }
+    let (start, end) = (start.unwrap_or(0), end.unwrap_or(Timestamp::MAX));
+    if end < start {
+        return Err("`end` is before `start`".to_string());
+    }
+    let mut labels = Labels::new();
+    for matcher in matchers {
+        let (name, value) = match matcher {
+            Matcher::Equal { name, value } => (name, value),
+            _ => {
+                return Err(
+                    "time ranges can only be combined with `name=value` matchers".to_string(),
+                )
+            }
+        };
+        if let Some(existing) = labels.get(&name) {
+            if *existing != value {
+                return Err(format!(
+                    "conflicting matchers `{}={}` and `{}={}`",
+                    name, existing, name, value
+                ));
+            }
+        }
+        labels.insert(name, value);
+    }
+    Ok(Query::Range {
+        matchers: labels,
+        start,
+        end,
+    })
+}
+
+/// Parse the `aggregate`, `bucket`, and `window` parameters of `req`'s query string.
+fn parse_aggregation(req: &tide::Request<State>) -> Result<Option<Aggregation>, String> {
+    let param = |name: &str| {
+        req.url()
+            .query_pairs()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let duration = |name: &str| -> Result<Option<Timestamp>, String> {
+        param(name)
+            .map(|value| {
+                let duration = retention::parse_duration(&value)
+                    .map_err(|error| format!("invalid {} `{}`: {}", name, value, error))?;
+                Ok(Timestamp::try_from(duration.as_millis()).unwrap_or(Timestamp::MAX))
+            })
+            .transpose()
+    };
+
+    let aggregate = match param("aggregate") {
+        Some(aggregate) => aggregate,
+        None if param("bucket").is_some() || param("window").is_some() => {
+            return Err("`bucket` and `window` require `aggregate`".to_string())
+        }
+        None => return Ok(None),
+    };
+    let bucket = duration("bucket")?.ok_or_else(|| "`aggregate` requires `bucket`".to_string())?;
+    let window = duration("window")?;
+    match &*aggregate {
+        "count" if window.is_some() => Err("`window` requires `aggregate=rate`".to_string()),
+        "count" => Ok(Some(Aggregation::Count { bucket })),
+        "rate" => Ok(Some(Aggregation::Rate {
+            bucket,
+            window: window.unwrap_or(bucket),
+        })),
+        _ => Err(format!(
+            "invalid aggregate `{}`, expected `count` or `rate`",
+            aggregate
+        )),
+    }
+}
+
+/// Convert `time` to a [`Timestamp`].
+fn timestamp(time: SystemTime) -> Timestamp {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Timestamp::try_from(since_epoch.as_millis()).unwrap_or(Timestamp::MAX)
+}
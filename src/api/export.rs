@@ -0,0 +1,395 @@
+// src/api/export.rs
+//! Exporting query results to object storage.
+//!
+//! Very large query results are impractical to return in an HTTP response. Instead,
+//! `POST /exports` starts an export in the background, taking a JSON body like
+//! `{"key": "namespace", "value": "payments", "contains": "error"}` (`contains`, `regex`, `fields`,
+//! and `format` are optional, as for `GET /logs`), and responds with `202 Accepted` and the export's ID. The
+//! export's progress can be polled with `GET /exports/:id`, or through the jobs API (see
+//! [`crate::jobs`]), which can also cancel it. Exports require the `export` scope, and at most
+//! [`MAX_CONCURRENT_EXPORTS`] can run at once.
+//!
+//! Results are written to the configured [`ObjectStore`] as newline-delimited JSON objects like
+//! `{"line": "..."}`, under the key `exports/<id>.ndjson`. They're read a page at a time, so the
+//! database isn't locked for the whole export, and streamed to the store, so they're never held in
+//! memory. A [`Manifest`] of the results, signed if an export signing key is configured, is written
+//! under `exports/<id>.manifest.json`, so that the export can later be verified (e.g. with
+//! `monitoring-rs verify`).
+//!
+//! Completed exports are recorded in the query audit trail and usage like other queries.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_std::task;
+
+use crate::log_database::projection::Projection;
+use crate::log_database::{Direction, QueryStats};
+use crate::manifest::{FileWriter, Manifest};
+
+use super::error::error_response;
+use super::{audit, auth, usage};
+use super::{ReadLogsParams, State};
+
+/// The kind of job used for exports.
+const EXPORT_JOB_KIND: &str = "export";
+
+/// The maximum number of exports that may run at once.
+pub const MAX_CONCURRENT_EXPORTS: usize = 2;
+
+/// A store for exported query results.
+pub trait ObjectStore: Send + Sync {
+    /// Start writing an object under `key`.
+    ///
+    /// The object should only be stored once the returned writer is
+    /// [finished](ObjectWriter::finish), so that failed or cancelled exports don't leave partial
+    /// objects behind.
+    ///
+    /// # Errors
+    ///
+    /// Any errors that occur when creating the object should be returned as `io::Error`s.
+    fn create(&self, key: &str) -> io::Result<Box<dyn ObjectWriter>>;
+}
+
+/// An object being written to an [`ObjectStore`].
+pub trait ObjectWriter: Write + Send {
+    /// Store the object, returning its location.
+    ///
+    /// # Errors
+    ///
+    /// Any errors that occur when storing the object should be returned as `io::Error`s.
+    fn finish(self: Box<Self>) -> io::Result<String>;
+}
+
+/// An [`ObjectStore`] that stores objects as files in a directory (e.g. a mounted bucket).
+///
+/// Objects are written to a temporary file next to their path, and renamed into place when they're
+/// finished.
+#[derive(Debug)]
+pub struct DirectoryStore {
+    root: PathBuf,
+}
+
+impl DirectoryStore {
+    /// Construct a store that will write objects into `root`.
+    #[must_use]
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+impl ObjectStore for DirectoryStore {
+    fn create(&self, key: &str) -> io::Result<Box<dyn ObjectWriter>> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut partial_path = path.clone().into_os_string();
+        partial_path.push(".partial");
+        let partial_path = PathBuf::from(partial_path);
+        let file = BufWriter::new(File::create(&partial_path)?);
+        Ok(Box::new(DirectoryObject {
+            path,
+            partial_path,
+            file: Some(file),
+        }))
+    }
+}
+
+/// An object being written by a [`DirectoryStore`].
+struct DirectoryObject {
+    path: PathBuf,
+    partial_path: PathBuf,
+
+    /// The partial file, until the object is finished.
+    file: Option<BufWriter<File>>,
+}
+
+impl DirectoryObject {
+    fn file(&mut self) -> &mut BufWriter<File> {
+        self.file.as_mut().expect("object already finished")
+    }
+}
+
+impl Write for DirectoryObject {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl ObjectWriter for DirectoryObject {
+    fn finish(mut self: Box<Self>) -> io::Result<String> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+            file.get_ref().sync_all()?;
+        }
+        fs::rename(&self.partial_path, &self.path)?;
+        Ok(format!("file://{}", self.path.display()))
+    }
+}
+
+impl Drop for DirectoryObject {
+    fn drop(&mut self) {
+        // An unfinished object is discarded.
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.partial_path);
+        }
+    }
+}
+
+/// The exports that are running, to limit how many run at once.
+#[derive(Debug, Default)]
+pub(super) struct Exports {
+    running: AtomicUsize,
+}
+
+/// Permission for an export to run, released when dropped.
+#[derive(Debug)]
+struct ExportPermit(Arc<Exports>);
+
+impl Exports {
+    /// Start an export, unless [`MAX_CONCURRENT_EXPORTS`] are already running.
+    fn acquire(self: &Arc<Self>) -> Option<ExportPermit> {
+        let running = self.running.fetch_add(1, Ordering::AcqRel);
+        let permit = ExportPermit(Arc::clone(self));
+        if running < MAX_CONCURRENT_EXPORTS {
+            Some(permit)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for ExportPermit {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ExportRequest {
+    key: String,
+    value: String,
+
+    #[serde(flatten)]
+    params: ReadLogsParams,
+}
+
+pub(super) async fn start_export(mut req: tide::Request<State>) -> tide::Result {
+    let store = match &req.state().config.export_store {
+        Some(store) => Arc::clone(store),
+        None => {
+            return Ok(error_response(
+                tide::StatusCode::NotFound,
+                "exports_disabled",
+                "no export store is configured".to_string(),
+                None,
+            ))
+        }
+    };
+
+    let ExportRequest { key, value, params } = req.body_json().await?;
+    if let Some(response) = auth::forbid_internal(&req, std::iter::once(key.as_str())) {
+        return Ok(response);
+    }
+    let filter = params.filter()?;
+    let projection = params.projection()?;
+    let selector = format!("{}={}{}", key, value, params.describe_filter());
+
+    let permit = match req.state().exports.acquire() {
+        Some(permit) => permit,
+        None => {
+            return Ok(error_response(
+                tide::StatusCode::TooManyRequests,
+                "limit_exceeded",
+                format!("at most {} exports may run at once", MAX_CONCURRENT_EXPORTS),
+                None,
+            ))
+        }
+    };
+
+    let projection = projection.map(Arc::new);
+    let state = req.state().clone();
+    let tenant = usage::tenant(&req);
+    let caller = req.remote().unwrap_or("unknown").to_string();
+    let job = state.config.jobs.start(EXPORT_JOB_KIND, selector.clone());
+    let id = job.id();
+    task::spawn(async move {
+        let _permit = permit;
+        let start = Instant::now();
+        let mut stats = QueryStats::default();
+        let result = async {
+            let name = format!("{}.ndjson", id);
+            let object = store.create(&format!("exports/{}", name))?;
+            let mut writer = FileWriter::new(name, object);
+            let mut lines = 0;
+            let mut cursor = None;
+            loop {
+                if job.is_cancelled() {
+                    return Ok(None);
+                }
+
+                // The lock is only held for each page, so writes aren't blocked by the export.
+                let page = state.database.read().await.query_page_filtered(
+                    &key,
+                    &value,
+                    filter.as_ref(),
+                    cursor.as_ref(),
+                    state.config.max_page_size,
+                    Direction::Forward,
+                )?;
+                let page = match page {
+                    Some(page) => page,
+                    None => break,
+                };
+                stats.streams = stats.streams.max(page.stats.streams);
+                stats.bytes_scanned += page.stats.bytes_scanned;
+                lines += page.lines.len();
+
+                let page_lines = page.lines;
+                let projection = projection.clone();
+                let (writer_, written) = blocking::unblock(move || {
+                    let written = write_lines(&mut writer, page_lines, projection.as_deref());
+                    (writer, written)
+                })
+                .await;
+                writer = writer_;
+                written?;
+
+                cursor = match page.next {
+                    Some(next) => Some(next),
+                    None => break,
+                };
+            }
+            stats.bytes_returned = writer.size();
+
+            let signing_key = state.config.export_signing_key.clone();
+            let locations = blocking::unblock(move || {
+                let (file, object) = writer.finish();
+                let location = object.finish()?;
+                let manifest = Manifest::from_files(vec![file], signing_key.as_deref());
+                let mut object = store.create(&format!("exports/{}.manifest.json", id))?;
+                serde_json::to_writer_pretty(&mut object, &manifest)?;
+                let manifest_location = object.finish()?;
+                Ok::<_, io::Error>((location, manifest_location))
+            })
+            .await?;
+            Ok::<_, io::Error>(Some((lines, locations)))
+        }
+        .await;
+
+        let duration = start.elapsed();
+        state
+            .usage
+            .record(&tenant, usage::Cost::new(stats, duration));
+        state.auditor.record(
+            &audit::QueryRecord::new(&selector, &tenant, &caller, (None, None), duration, stats),
+            state.config.slow_query_threshold,
+        );
+
+        match result {
+            Ok(Some((lines, (location, manifest_location)))) => job.complete(serde_json::json!({
+                "lines": lines,
+                "location": location,
+                "manifest": manifest_location,
+            })),
+            Ok(None) => job.cancelled(),
+            Err(error) => job.fail(error),
+        }
+    });
+
+    Ok(tide::Response::builder(tide::StatusCode::Accepted)
+        .body(tide::Body::from_json(&serde_json::json!({ "id": id }))?)
+        .build())
+}
+
+/// Write `lines` to `writer` as newline-delimited JSON objects, applying `projection` if given.
+fn write_lines(
+    writer: &mut impl Write,
+    lines: Vec<String>,
+    projection: Option<&Projection>,
+) -> io::Result<()> {
+    for line in lines {
+        let line = match projection {
+            Some(projection) => projection.apply(&line),
+            None => line,
+        };
+        serde_json::to_writer(&mut *writer, &serde_json::json!({ "line": line }))?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+pub(super) async fn get_export(req: tide::Request<State>) -> tide::Result {
+    let id: u64 = req
+        .param("id")?
+        .parse()
+        .map_err(|error| tide::Error::new(tide::StatusCode::BadRequest, error))?;
+
+    Ok(match req.state().config.jobs.get(id) {
+        Some(export) if export.kind == EXPORT_JOB_KIND => {
+            tide::Response::builder(tide::StatusCode::Ok)
+                .body(tide::Body::from_json(&export)?)
+                .build()
+        }
+        _ => error_response(
+            tide::StatusCode::NotFound,
+            "not_found",
+            format!("no export with ID {}", id),
+            None,
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use crate::test;
+
+    use super::{DirectoryStore, Exports, ObjectStore, MAX_CONCURRENT_EXPORTS};
+
+    #[test]
+    fn limits_concurrent_exports() {
+        let exports = Arc::new(Exports::default());
+        let mut permits: Vec<_> = (0..MAX_CONCURRENT_EXPORTS)
+            .map(|_| exports.acquire().unwrap())
+            .collect();
+        assert!(exports.acquire().is_none());
+
+        permits.pop();
+        assert!(exports.acquire().is_some());
+    }
+
+    #[test]
+    fn directory_store_writes_finished_objects() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let store = DirectoryStore::new(tempdir.path().to_path_buf());
+
+        let mut object = store.create("exports/1.ndjson")?;
+        object.write_all(b"hello")?;
+        let location = object.finish()?;
+        let path = tempdir.path().join("exports/1.ndjson");
+        assert_eq!(location, format!("file://{}", path.display()));
+        assert_eq!(std::fs::read_to_string(&path)?, "hello");
+
+        let mut object = store.create("exports/2.ndjson")?;
+        object.write_all(b"partial")?;
+        drop(object);
+        assert_eq!(
+            std::fs::read_dir(tempdir.path().join("exports"))?.count(),
+            1
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+// src/api/grafana.rs
+//! Endpoints for the Grafana JSON datasource plugin, so log statistics and annotations can be
+//! graphed without a custom datasource. The datasource's URL should be `<api>/api/v1/grafana`.
+//!
+//! - `GET /grafana` responds `200 OK`, for Grafana's connection test.
+//! - `POST /grafana/search` lists the available series, which are the counts recorded by the
+//!   database's [`StatsRecorder`] (e.g. `entries_ingested`). A `{"target": "..."}` body filters
+//!   them to those containing the given text.
+//! - `POST /grafana/query` returns each of the request's `targets` as a time series, with a data
+//!   point for every stats period that ends within the request's `range`. Log entries aren't
+//!   timestamped, so the number of entries ingested per stats period is the finest-grained count of
+//!   logs available.
+//! - `POST /grafana/annotations` returns the [annotations](super::annotations) within the request's
+//!   `range`. The annotation's `query` is a selector like `namespace=payments,app=api`, and selects
+//!   the annotations on the streams matching every `key=value` pair (or every annotation, if it's
+//!   empty). Range annotations span their range, and entry annotations are placed at the time they
+//!   were added.
+//!
+//! Ranges are RFC 3339 timestamps, as sent by Grafana (e.g. `2020-09-13T12:26:40.000Z`), and
+//! times are returned in milliseconds since the Unix epoch.
+//!
+//! These endpoints don't provide log counts per selector, or annotations derived from log
+//! patterns: only the database-wide counts above are graphed over time. Since those are the same
+//! stats served by `/stats/history`, the endpoints are part of the admin API and require the
+//! `admin` scope.
+//!
+//! [`StatsRecorder`]: crate::log_database::stats::StatsRecorder
+
+use crate::log_database::annotation::{Annotation, Target};
+use crate::log_database::stats::StatsRecord;
+use crate::record;
+
+use super::error::error_response;
+use super::query::parse_matcher;
+use super::State;
+
+/// The series that can be queried, which are fields of [`StatsRecord`].
+const SERIES: &[&str] = &[
+    "entries_ingested",
+    "bytes_ingested",
+    "streams_created",
+    "streams_expired",
+    "active_streams",
+    "queries",
+];
+
+#[derive(serde::Deserialize)]
+struct Range {
+    from: String,
+    to: String,
+}
+
+#[derive(serde::Deserialize)]
+struct SearchRequest {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(serde::Deserialize)]
+struct QueryRequest {
+    range: Range,
+    targets: Vec<QueryTarget>,
+}
+
+#[derive(serde::Deserialize)]
+struct QueryTarget {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(serde::Deserialize)]
+struct AnnotationsRequest {
+    range: Range,
+    annotation: serde_json::Value,
+}
+
+pub(super) async fn test_connection(_req: tide::Request<State>) -> tide::Result {
+    Ok(tide::Response::new(tide::StatusCode::Ok))
+}
+
+pub(super) async fn search(mut req: tide::Request<State>) -> tide::Result {
+    let request: SearchRequest = req.body_json().await?;
+    let series: Vec<_> = SERIES
+        .iter()
+        .filter(|series| series.contains(&request.target))
+        .collect();
+
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&series)?)
+        .build())
+}
+
+pub(super) async fn query(mut req: tide::Request<State>) -> tide::Result {
+    let request: QueryRequest = req.body_json().await?;
+    let (from, to) = match parse_range(&request.range) {
+        Ok(range) => range,
+        Err(message) => return Ok(bad_request(message)),
+    };
+    // Grafana sends empty targets for queries that haven't been filled in yet.
+    let targets: Vec<_> = request
+        .targets
+        .iter()
+        .map(|target| target.target.as_str())
+        .filter(|target| !target.is_empty())
+        .collect();
+    if let Some(target) = targets.iter().find(|target| !SERIES.contains(target)) {
+        return Ok(bad_request(format!(
+            "unknown target `{}`, expected one of: {}",
+            target,
+            SERIES.join(", ")
+        )));
+    }
+
+    let history = req.state().database.read().await.stats_history()?;
+    let periods: Vec<_> = history
+        .iter()
+        .filter(|record| (from..=to).contains(&millis(record.end)))
+        .collect();
+    let series: Vec<_> = targets
+        .iter()
+        .map(|target| {
+            let datapoints: Vec<_> = periods
+                .iter()
+                .map(|record| serde_json::json!([value(record, target), millis(record.end)]))
+                .collect();
+            serde_json::json!({
+                "target": target,
+                "datapoints": datapoints,
+            })
+        })
+        .collect();
+
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&series)?)
+        .build())
+}
+
+pub(super) async fn annotations(mut req: tide::Request<State>) -> tide::Result {
+    let request: AnnotationsRequest = req.body_json().await?;
+    let query = request
+        .annotation
+        .get("query")
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or_default();
+    let parsed = parse_range(&request.range).and_then(|range| Ok((range, parse_selector(query)?)));
+    let ((from, to), matchers) = match parsed {
+        Ok(parsed) => parsed,
+        Err(message) => return Ok(bad_request(message)),
+    };
+
+    let database = req.state().database.read().await;
+    let annotations: Vec<&Annotation> = if matchers.is_empty() {
+        database.annotations().iter().collect()
+    } else {
+        database.annotations_matching(&matchers)
+    };
+    let events: Vec<_> = annotations
+        .into_iter()
+        .filter_map(|annotation| {
+            let (time, time_end) = match &annotation.target {
+                Target::Range { start, end, .. } => (millis(*start), millis(*end)),
+                Target::Entry { .. } => {
+                    (millis(annotation.created_at), millis(annotation.created_at))
+                }
+            };
+            if time > to || time_end < from {
+                return None;
+            }
+            Some(serde_json::json!({
+                "annotation": request.annotation,
+                "time": time,
+                "timeEnd": time_end,
+                "title": annotation.tags.join(", "),
+                "text": annotation.note,
+                "tags": annotation.tags,
+            }))
+        })
+        .collect();
+
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&events)?)
+        .build())
+}
+
+fn bad_request(message: String) -> tide::Response {
+    error_response(tide::StatusCode::BadRequest, "bad_request", message, None)
+}
+
+/// The value of `series` in `record`.
+#[allow(clippy::cast_precision_loss)]
+fn value(record: &StatsRecord, series: &str) -> f64 {
+    match series {
+        "entries_ingested" => record.entries_ingested as f64,
+        "bytes_ingested" => record.bytes_ingested as f64,
+        "streams_created" => record.streams_created as f64,
+        "streams_expired" => record.streams_expired as f64,
+        "active_streams" => record.active_streams as f64,
+        "queries" => record.queries as f64,
+        _ => unreachable!("unknown series {}", series),
+    }
+}
+
+/// Convert seconds since the Unix epoch to milliseconds.
+fn millis(secs: u64) -> u64 {
+    secs.saturating_mul(1000)
+}
+
+/// Parse a comma-separated selector like `namespace=payments,app=api`.
+fn parse_selector(selector: &str) -> Result<Vec<(String, String)>, String> {
+    selector
+        .split(',')
+        .map(str::trim)
+        .filter(|matcher| !matcher.is_empty())
+        .map(parse_matcher)
+        .collect()
+}
+
+/// Parse `range` into milliseconds since the Unix epoch.
+fn parse_range(range: &Range) -> Result<(u64, u64), String> {
+    let from = parse_timestamp(&range.from)?;
+    let to = parse_timestamp(&range.to)?;
+    if to < from {
+        return Err("range `to` is before `from`".to_string());
+    }
+    Ok((from, to))
+}
+
+/// Parse an RFC 3339 timestamp, like `2020-09-13T12:26:40.000Z`, into milliseconds since the Unix
+/// epoch.
+fn parse_timestamp(value: &str) -> Result<u64, String> {
+    record::parse_rfc3339(value).ok_or_else(|| {
+        format!(
+            "invalid timestamp `{}`, expected e.g. 2020-09-13T12:26:40.000Z",
+            value
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_selector, parse_timestamp};
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Ok(0));
+        assert_eq!(
+            parse_timestamp("2020-09-13T12:26:40.000Z"),
+            Ok(1_600_000_000_000)
+        );
+        assert_eq!(
+            parse_timestamp("2020-09-13T12:26:40.5Z"),
+            Ok(1_600_000_000_500)
+        );
+        assert_eq!(
+            parse_timestamp("2024-02-29T00:00:00.123456Z"),
+            Ok(1_709_164_800_123)
+        );
+        for value in &[
+            "2020-09-13T12:26:40",
+            "2020-09-13 12:26:40Z",
+            "2020-13-13T12:26:40Z",
+            "1969-12-31T23:59:59Z",
+            "2020-09-13T12:26:40.x0Z",
+            "+020-09-13T12:26:40Z",
+        ] {
+            assert!(parse_timestamp(value).is_err(), "{}", value);
+        }
+
+        assert_eq!(
+            parse_selector(" namespace=payments, app=api,"),
+            Ok(vec![
+                ("namespace".to_string(), "payments".to_string()),
+                ("app".to_string(), "api".to_string())
+            ])
+        );
+        assert_eq!(parse_selector(""), Ok(vec![]));
+        assert!(parse_selector("namespace").is_err());
+    }
+}
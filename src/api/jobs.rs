@@ -0,0 +1,50 @@
+// src/api/jobs.rs
+//! Inspection and cancellation of background [jobs](crate::jobs).
+//!
+//! - `GET /jobs` lists running and recently finished jobs.
+//! - `GET /jobs/:id` returns a single job.
+//! - `POST /jobs/:id/cancel` requests cancellation of a running job.
+
+use super::error::error_response;
+use super::State;
+
+pub(super) async fn list_jobs(req: tide::Request<State>) -> tide::Result {
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&req.state().config.jobs.list())?)
+        .build())
+}
+
+pub(super) async fn get_job(req: tide::Request<State>) -> tide::Result {
+    let id = job_id(&req)?;
+    Ok(match req.state().config.jobs.get(id) {
+        Some(job) => tide::Response::builder(tide::StatusCode::Ok)
+            .body(tide::Body::from_json(&job)?)
+            .build(),
+        None => not_found(id),
+    })
+}
+
+pub(super) async fn cancel_job(req: tide::Request<State>) -> tide::Result {
+    let id = job_id(&req)?;
+    Ok(match req.state().config.jobs.cancel(id) {
+        Some(job) => tide::Response::builder(tide::StatusCode::Accepted)
+            .body(tide::Body::from_json(&job)?)
+            .build(),
+        None => not_found(id),
+    })
+}
+
+fn job_id(req: &tide::Request<State>) -> tide::Result<u64> {
+    req.param("id")?
+        .parse()
+        .map_err(|error| tide::Error::new(tide::StatusCode::BadRequest, error))
+}
+
+fn not_found(id: u64) -> tide::Response {
+    error_response(
+        tide::StatusCode::NotFound,
+        "not_found",
+        format!("no job with ID {}", id),
+        None,
+    )
+}
@@ -0,0 +1,204 @@
+// src/api/listen.rs
+//! Listeners for the HTTP API.
+//!
+//! The API can listen on any number of TCP addresses and Unix domain sockets at once (e.g. a
+//! public address for queries and a localhost-only address for operators).
+
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::{debug, info};
+use tide::listener::ConcurrentListener;
+
+use super::State;
+
+const UNIX_PREFIX: &str = "unix:";
+
+/// An address for the API to listen on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+    /// A TCP socket address.
+    Tcp(SocketAddr),
+
+    /// The path of a Unix domain socket.
+    Unix(PathBuf),
+}
+
+/// Parse a listen address like `0.0.0.0:8000`, `localhost:8001`, or `unix:/run/monitoring.sock`.
+///
+/// Host names are resolved when parsing, and the first resolved address is used.
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = input.strip_prefix(UNIX_PREFIX) {
+            if path.is_empty() {
+                return Err(format!(
+                    "invalid listen address {}: empty socket path",
+                    input
+                ));
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+
+        input
+            .to_socket_addrs()
+            .map_err(|error| format!("invalid listen address {}: {}", input, error))?
+            .next()
+            .map(Self::Tcp)
+            .ok_or_else(|| format!("invalid listen address {}: no addresses resolved", input))
+    }
+}
+
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Options for TCP listeners.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SocketOptions {
+    /// The TCP keepalive interval for accepted connections, or `None` to disable keepalive.
+    pub keepalive: Option<Duration>,
+
+    /// The maximum number of pending connections.
+    pub backlog: i32,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            keepalive: None,
+            backlog: 1024,
+        }
+    }
+}
+
+/// Bind a listener for each of `addrs`, returning a listener that accepts from all of them.
+///
+/// Any existing socket at the path of a Unix domain socket is removed first, since it will have
+/// been left behind by a previous run.
+///
+/// # Errors
+///
+/// - If something other than a socket exists at the path of a Unix domain socket, an error of kind
+///   [`io::ErrorKind::AlreadyExists`] is returned, so a mistyped path can't delete a file.
+/// - Propagates any `io::Error` that occurs when binding the listeners.
+pub fn bind(
+    addrs: &[ListenAddr],
+    options: &SocketOptions,
+) -> io::Result<ConcurrentListener<State>> {
+    let mut listener = ConcurrentListener::new();
+    for addr in addrs {
+        info!("Listening on {}", addr);
+        match addr {
+            ListenAddr::Tcp(addr) => listener.add(bind_tcp(*addr, options)?)?,
+            ListenAddr::Unix(path) => {
+                remove_stale_socket(path)?;
+                listener.add(UnixListener::bind(path)?)?;
+            }
+        }
+    }
+    Ok(listener)
+}
+
+/// Remove the socket at `path` left behind by a previous run, if any, refusing to remove anything
+/// else.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "can't listen on {}: it exists and isn't a socket",
+                path.display()
+            ),
+        ));
+    }
+    debug!("Removing existing socket {}", path.display());
+    fs::remove_file(path)
+}
+
+fn bind_tcp(addr: SocketAddr, options: &SocketOptions) -> io::Result<TcpListener> {
+    use socket2::{Domain, Socket, Type};
+
+    let domain = if addr.is_ipv4() {
+        Domain::ipv4()
+    } else {
+        Domain::ipv6()
+    };
+    let socket = Socket::new(domain, Type::stream(), None)?;
+    socket.set_reuse_address(true)?;
+
+    // Keepalive is inherited by accepted connections.
+    socket.set_keepalive(options.keepalive)?;
+
+    socket.bind(&addr.into())?;
+    socket.listen(options.backlog)?;
+    Ok(socket.into_tcp_listener())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+
+    use crate::test;
+
+    use super::{remove_stale_socket, ListenAddr};
+
+    #[test]
+    fn parse_listen_addrs() {
+        assert_eq!(
+            "0.0.0.0:8000".parse(),
+            Ok(ListenAddr::Tcp(([0, 0, 0, 0], 8000).into()))
+        );
+        assert_eq!(
+            "unix:/run/monitoring.sock".parse(),
+            Ok(ListenAddr::Unix(PathBuf::from("/run/monitoring.sock")))
+        );
+        assert!("unix:".parse::<ListenAddr>().is_err());
+        assert!("nonsense".parse::<ListenAddr>().is_err());
+
+        let addr: ListenAddr = "unix:/run/monitoring.sock".parse().unwrap();
+        assert_eq!(addr.to_string(), "unix:/run/monitoring.sock");
+    }
+
+    #[test]
+    fn only_removes_stale_sockets() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+
+        remove_stale_socket(&tempdir.path().join("missing.sock"))?;
+
+        let socket = tempdir.path().join("stale.sock");
+        drop(UnixListener::bind(&socket)?);
+        remove_stale_socket(&socket)?;
+        assert!(!socket.exists());
+
+        let file = tempdir.path().join("important.txt");
+        fs::write(&file, "data")?;
+        assert_eq!(
+            remove_stale_socket(&file).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert!(file.exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+// src/api/protocol.rs
+//! Versioning of the push protocol, used by agents forwarding entries to an aggregator's
+//! `POST /push`.
+//!
+//! A fleet is upgraded node by node, so agents and aggregators of different versions must be able
+//! to talk to each other. Pushing peers send the protocol version they speak in the
+//! [`VERSION_HEADER`], and any optional [features](FEATURES) they want in the [`FEATURES_HEADER`]
+//! (comma-separated). Requests without a version header are from agents that predate versioning,
+//! and are treated as version 1.
+//!
+//! The aggregator responds with the negotiated version and the features that version supports in
+//! the same headers. If the peer's version isn't supported, or it asks for a feature the
+//! aggregator doesn't support (or doesn't support in that version), the push is rejected with a
+//! `400 Bad Request` response with code `incompatible_protocol`, whose details describe what the
+//! aggregator does support. This way mismatches are reported explicitly, rather than entries
+//! being silently misinterpreted.
+//!
+//! Versions:
+//!
+//! 1. Newline-delimited JSON entries, each either `{"line": "...", "metadata": {...}}` or a
+//!    [`Record`](crate::record::Record). The first invalid entry fails the request.
+//! 2. As version 1, with the optional `skip-invalid` feature.
+
+use std::error::Error;
+use std::fmt;
+
+/// The header in which the protocol version is sent and returned.
+pub const VERSION_HEADER: &str = "X-Push-Protocol";
+
+/// The header in which features are requested and returned.
+pub const FEATURES_HEADER: &str = "X-Push-Features";
+
+/// The oldest protocol version that is supported.
+pub const MIN_VERSION: u32 = 1;
+
+/// The newest protocol version that is supported.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Optional protocol features, and the version that introduced each.
+///
+/// - `records`: entries may be [`Record`](crate::record::Record)s. Records were accepted before
+///   the protocol was versioned, so version 1 includes them.
+/// - `skip-invalid`: invalid entries are skipped and reported in the response's `rejected` list,
+///   rather than failing the request.
+pub const FEATURES: &[(&str, u32)] = &[("records", 1), ("skip-invalid", 2)];
+
+/// The version and features agreed with a pushing peer.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Negotiated {
+    /// The protocol version in use.
+    pub version: u32,
+
+    /// The optional features requested by the peer.
+    pub features: Vec<String>,
+}
+
+impl Negotiated {
+    /// Check whether the peer requested `feature`.
+    #[must_use]
+    pub fn has(&self, feature: &str) -> bool {
+        self.features.iter().any(|requested| requested == feature)
+    }
+
+    /// The features supported in the negotiated version, for the [`FEATURES_HEADER`].
+    #[must_use]
+    pub fn supported_features(&self) -> Vec<&'static str> {
+        supported_features(self.version)
+    }
+}
+
+/// Why a peer's protocol is incompatible.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Incompatible {
+    /// The version header couldn't be parsed.
+    InvalidVersion(String),
+
+    /// The version is outside [`MIN_VERSION`]`..=`[`CURRENT_VERSION`].
+    UnsupportedVersion(u32),
+
+    /// Features that aren't supported in the requested version.
+    UnsupportedFeatures(Vec<String>),
+}
+
+impl fmt::Display for Incompatible {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidVersion(version) => write!(f, "invalid protocol version `{}`", version),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported protocol version {} (supported versions are {} to {})",
+                version, MIN_VERSION, CURRENT_VERSION
+            ),
+            Self::UnsupportedFeatures(features) => {
+                write!(f, "unsupported protocol features: {}", features.join(", "))
+            }
+        }
+    }
+}
+
+impl Error for Incompatible {}
+
+/// Negotiate a protocol from the values of a peer's [`VERSION_HEADER`] and [`FEATURES_HEADER`].
+///
+/// # Errors
+///
+/// If the peer's version or any of its requested features aren't supported, an [`Incompatible`]
+/// error is returned.
+pub fn negotiate(
+    version: Option<&str>,
+    features: Option<&str>,
+) -> Result<Negotiated, Incompatible> {
+    let version = match version {
+        Some(version) => version
+            .trim()
+            .parse()
+            .map_err(|_| Incompatible::InvalidVersion(version.to_string()))?,
+        None => MIN_VERSION,
+    };
+    if !(MIN_VERSION..=CURRENT_VERSION).contains(&version) {
+        return Err(Incompatible::UnsupportedVersion(version));
+    }
+
+    let supported = supported_features(version);
+    let features: Vec<_> = features
+        .into_iter()
+        .flat_map(|features| features.split(','))
+        .map(str::trim)
+        .filter(|feature| !feature.is_empty())
+        .map(str::to_string)
+        .collect();
+    let unsupported: Vec<_> = features
+        .iter()
+        .filter(|feature| !supported.contains(&feature.as_str()))
+        .cloned()
+        .collect();
+    if !unsupported.is_empty() {
+        return Err(Incompatible::UnsupportedFeatures(unsupported));
+    }
+
+    Ok(Negotiated { version, features })
+}
+
+/// The features supported in `version`.
+fn supported_features(version: u32) -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|(_, introduced)| *introduced <= version)
+        .map(|(feature, _)| *feature)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{negotiate, Incompatible, Negotiated, CURRENT_VERSION, MIN_VERSION};
+
+    #[test]
+    fn negotiate_versions() {
+        // Agents that predate versioning speak version 1.
+        assert_eq!(
+            negotiate(None, None),
+            Ok(Negotiated {
+                version: MIN_VERSION,
+                features: vec![],
+            })
+        );
+        assert_eq!(negotiate(Some("2"), None).unwrap().version, 2);
+
+        assert_eq!(
+            negotiate(Some("0"), None),
+            Err(Incompatible::UnsupportedVersion(0))
+        );
+        assert_eq!(
+            negotiate(Some(&(CURRENT_VERSION + 1).to_string()), None),
+            Err(Incompatible::UnsupportedVersion(CURRENT_VERSION + 1))
+        );
+        assert_eq!(
+            negotiate(Some("two"), None),
+            Err(Incompatible::InvalidVersion("two".to_string()))
+        );
+    }
+
+    #[test]
+    fn negotiate_features() {
+        let negotiated = negotiate(Some("2"), Some("records, skip-invalid")).unwrap();
+        assert!(negotiated.has("skip-invalid"));
+        assert_eq!(
+            negotiated.supported_features(),
+            vec!["records", "skip-invalid"]
+        );
+
+        // Features are only available from the version that introduced them.
+        assert_eq!(
+            negotiate(Some("1"), Some("skip-invalid")),
+            Err(Incompatible::UnsupportedFeatures(vec![
+                "skip-invalid".to_string()
+            ]))
+        );
+        assert_eq!(
+            negotiate(None, Some("records")).unwrap().features,
+            vec!["records"]
+        );
+        assert_eq!(
+            negotiate(Some("2"), Some("gzip,records")),
+            Err(Incompatible::UnsupportedFeatures(vec!["gzip".to_string()]))
+        );
+    }
+}
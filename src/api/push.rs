@@ -0,0 +1,191 @@
+// src/api/push.rs
+//! Pushing log entries through the API.
+//!
+//! `POST /push` accepts newline-delimited JSON entries, each like
+//! `{"line": "...", "metadata": {"key": "value"}}`, or a [`Record`] like
+//! `{"timestamp": 1600000000000, "labels": {"key": "value"}, "body": "..."}` (entries without a
+//! timestamp are timestamped when they're written). The body is parsed and
+//! written one entry at a time as it's received, rather than buffered, so memory use per request
+//! is bounded by the size of the largest entry. Bodies larger than [`Config::max_push_body_size`] are rejected with
+//! `413 Payload Too Large`.
+//!
+//! If [`Config::access_log_parser`] is set, access log lines are parsed into metadata before
+//! they're written, and if [`Config::json_parser`] is set, JSON lines are parsed into metadata.
+//! Similarly, if [`Config::secret_detector`] is set, entries are checked for likely secrets, and
+//! if [`Config::geoip`] is set, entries are enriched with IP address metadata.
+//! Finally, if [`Config::derived_labels`] is set, labels are derived from the resulting metadata.
+//!
+//! Entries are written as they're parsed, so if a request fails part way through, the entries
+//! before the failure will already have been written. The response reports how many entries were
+//! accepted in either case (in the `details` of the error, if the request failed).
+//!
+//! Pushing peers negotiate a version of the push [`protocol`](super::protocol) using request
+//! headers. With the `skip-invalid` feature, invalid entries are skipped and reported in the
+//! response's `rejected` list (as `{"entry": <1-based position>, "error": "..."}`) rather than
+//! failing the request.
+//!
+//! [`Config::max_push_body_size`]: super::Config::max_push_body_size
+//! [`Config::access_log_parser`]: super::Config::access_log_parser
+//! [`Config::json_parser`]: super::Config::json_parser
+//! [`Config::secret_detector`]: super::Config::secret_detector
+//! [`Config::geoip`]: super::Config::geoip
+//! [`Config::derived_labels`]: super::Config::derived_labels
+
+use std::collections::HashMap;
+
+use async_std::io::prelude::BufReadExt;
+use async_std::io::ReadExt;
+
+use crate::record::Record;
+use crate::LogEntry;
+
+use super::error::error_response;
+use super::protocol::{self, Negotiated};
+use super::State;
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum PushEntry {
+    Entry {
+        line: String,
+        #[serde(default)]
+        metadata: HashMap<String, String>,
+    },
+    Record(Record),
+}
+
+pub(super) async fn push_logs(req: tide::Request<State>) -> tide::Result {
+    let negotiated = match protocol::negotiate(
+        req.header(protocol::VERSION_HEADER)
+            .map(|values| values.last().as_str()),
+        req.header(protocol::FEATURES_HEADER)
+            .map(|values| values.last().as_str()),
+    ) {
+        Ok(negotiated) => negotiated,
+        Err(error) => {
+            let mut response = error_response(
+                tide::StatusCode::BadRequest,
+                "incompatible_protocol",
+                error.to_string(),
+                Some(serde_json::json!({
+                    "min_version": protocol::MIN_VERSION,
+                    "current_version": protocol::CURRENT_VERSION,
+                    "features": protocol::FEATURES
+                        .iter()
+                        .map(|(feature, version)| ((*feature).to_string(), *version))
+                        .collect::<HashMap<_, _>>(),
+                })),
+            );
+            response.insert_header(
+                protocol::VERSION_HEADER,
+                protocol::CURRENT_VERSION.to_string(),
+            );
+            return Ok(response);
+        }
+    };
+
+    let mut response = push_entries(req, &negotiated).await?;
+    response.insert_header(protocol::VERSION_HEADER, negotiated.version.to_string());
+    response.insert_header(
+        protocol::FEATURES_HEADER,
+        negotiated.supported_features().join(","),
+    );
+    Ok(response)
+}
+
+async fn push_entries(mut req: tide::Request<State>, negotiated: &Negotiated) -> tide::Result {
+    let skip_invalid = negotiated.has("skip-invalid");
+    let max_body_size = req.state().config.max_push_body_size;
+    if req.len().map_or(false, |len| len > max_body_size) {
+        return Ok(body_too_large(max_body_size, 0));
+    }
+
+    // Read one byte past the limit, so we can tell if it was exceeded.
+    let mut body = req.take_body().take(max_body_size as u64 + 1);
+    let mut buf = String::new();
+    let mut read = 0;
+    let mut accepted = 0;
+    let mut entries = 0;
+    let mut rejected = Vec::new();
+    loop {
+        buf.clear();
+        let len = body
+            .read_line(&mut buf)
+            .await
+            .map_err(|error| tide::Error::new(tide::StatusCode::BadRequest, error))?;
+        if len == 0 {
+            break;
+        }
+
+        read += len;
+        if read > max_body_size {
+            return Ok(body_too_large(max_body_size, accepted));
+        }
+
+        let line = buf.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        entries += 1;
+        let entry: PushEntry = match serde_json::from_str(line) {
+            Ok(entry) => entry,
+            Err(error) if skip_invalid => {
+                rejected.push(serde_json::json!({ "entry": entries, "error": error.to_string() }));
+                continue;
+            }
+            Err(error) => {
+                return Ok(error_response(
+                    tide::StatusCode::BadRequest,
+                    "bad_request",
+                    format!("invalid entry {}: {}", entries, error),
+                    Some(serde_json::json!({ "accepted": accepted })),
+                ))
+            }
+        };
+
+        let mut entry = match entry {
+            PushEntry::Entry { line, metadata } => LogEntry {
+                line,
+                metadata,
+                timestamp: None,
+            },
+            PushEntry::Record(record) => LogEntry::from(record),
+        };
+        if let Some(parser) = &req.state().config.access_log_parser {
+            parser.parse(&mut entry);
+        }
+        if let Some(parser) = &req.state().config.json_parser {
+            parser.parse(&mut entry);
+        }
+        if let Some(detector) = &req.state().config.secret_detector {
+            detector.inspect(&mut entry);
+        }
+        if let Some(geoip) = &req.state().config.geoip {
+            geoip.enrich(&mut entry);
+        }
+        if let Some(derived_labels) = &req.state().config.derived_labels {
+            derived_labels.derive(&mut entry);
+        }
+        req.state().database.write().await.write(&entry)?;
+        accepted += 1;
+    }
+
+    let body = if skip_invalid {
+        serde_json::json!({ "accepted": accepted, "rejected": rejected })
+    } else {
+        serde_json::json!({ "accepted": accepted })
+    };
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(body)
+        .build())
+}
+
+fn body_too_large(max_body_size: usize, accepted: usize) -> tide::Response {
+    error_response(
+        tide::StatusCode::PayloadTooLarge,
+        "limit_exceeded",
+        format!("body exceeds {} bytes", max_body_size),
+        Some(serde_json::json!({ "accepted": accepted })),
+    )
+}
@@ -0,0 +1,321 @@
+// src/api/query.rs
+//! Querying logs by several labels (see [`Database::query_selection`]).
+//!
+//! `GET /query?match=ns=prod&match=app=api&start=1600000000&end=1600003600&limit=100` returns
+//! `{"lines": [...], "stats": {...}}` for the streams whose metadata contains every `match`ed
+//! `key=value` pair. Unlike `GET /logs/:key/*value`, values may contain any character (the query
+//! string is percent-decoded, and only the first `=` of a matcher separates the key from the
+//! value).
+//!
+//! - `start` and `end` (or `since` and `until`) are optional, in seconds since the Unix epoch or
+//!   relative to the server's clock (e.g. `since=15m` or `since=yesterday&tz=+02:00`, see
+//!   [`range`](super::range)). Log entries aren't timestamped, so they select the streams written
+//!   to within the range, rather than entries.
+//! - `limit` optionally caps the number of lines returned.
+//! - `fields` or `format` optionally reformat the returned lines, as for `GET /logs/:key/*value`
+//!   (see [`Projection`]).
+//! - `count_distinct=<field>` returns `{"count_distinct": {...}, "stats": {...}}` instead of lines,
+//!   with an estimate of the number of distinct values of the JSON `field` (see
+//!   [`Database::count_distinct`]). `precision` optionally sets the precision of the estimate
+//!   (see [`distinct`]). `limit`, `fields`, and `format` can't be combined with it.
+//!
+//! Instead of `match` parameters, `query` can select logs with a LogQL-style [`Query`], like
+//! `query={ns="prod",app="api"} |= "error"`. A `count_over_time` query returns
+//! `{"count": ..., "stats": {...}}` with the number of matching lines logged within its duration,
+//! and can't be combined with
+//! the other parameters.
+//!
+//! [`Database::query_selection`]: crate::log_database::Database::query_selection
+//! [`Database::count_distinct`]: crate::log_database::Database::count_distinct
+
+use std::time::Instant;
+
+use crate::log_collector::paths;
+use crate::log_database::distinct;
+use crate::log_database::projection::Projection;
+use crate::log_database::Selection;
+use crate::query::Query;
+
+use super::error::{error_response, query_error};
+use super::range::{parse_time, Offset};
+use super::{audit, auth, response_size, usage, State};
+
+pub(super) async fn query_logs(req: tide::Request<State>) -> tide::Result {
+    let parsed = parse_query(&req).and_then(|query| {
+        let selection = parse_selection(&req, query.as_ref())?;
+        let output = parse_output(&req, query.as_ref())?;
+        if selection.limit.is_some() && matches!(output, Output::CountDistinct { .. }) {
+            return Err("`limit` can't be combined with `count_distinct`".to_string());
+        }
+        Ok((selection, output))
+    });
+    let (selection, output) = match parsed {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            return Ok(error_response(
+                tide::StatusCode::BadRequest,
+                "bad_request",
+                message,
+                None,
+            ))
+        }
+    };
+    let keys = selection.matchers.iter().map(|(key, _)| key.as_str());
+    if let Some(response) = auth::forbid_internal(&req, keys) {
+        return Ok(response);
+    }
+    let tenant = usage::tenant(&req);
+
+    let max_response_bytes = req.state().config.max_response_bytes;
+    let start = Instant::now();
+    // The details of a `413 Payload Too Large` response, if the response would be too large.
+    let mut too_large = None;
+    let result = {
+        let database = req.state().database.read().await;
+        match &output {
+            Output::Lines(projection) => {
+                database
+                    .query_selection(&selection)
+                    .map(|(mut lines, stats)| {
+                        if let Some(projection) = projection {
+                            lines = lines.iter().map(|line| projection.apply(line)).collect();
+                        }
+                        if let Some(max_bytes) = max_response_bytes {
+                            let fit = response_size::lines_within(&lines, max_bytes);
+                            if fit < lines.len() {
+                                lines.truncate(fit);
+                                too_large = Some(serde_json::json!({ "lines": lines }));
+                                lines = Vec::new();
+                            }
+                        }
+                        (("lines", serde_json::json!(lines)), stats)
+                    })
+            }
+            Output::CountDistinct { field, precision } => database
+                .count_distinct(&selection, field, *precision)
+                .map(|(count, stats)| (("count_distinct", serde_json::json!(count)), stats)),
+            Output::Count => database
+                .count_selection(&selection)
+                .map(|(count, stats)| (("count", serde_json::json!(count)), stats)),
+        }
+    };
+    let ((name, body), stats) = match result {
+        Ok(result) => result,
+        Err(error) => return query_error(error),
+    };
+    let duration = start.elapsed();
+
+    let cost = usage::Cost::new(stats, duration);
+    req.state().usage.record(&tenant, cost);
+
+    let selector: Vec<_> = selection
+        .matchers
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    req.state().auditor.record(
+        &audit::QueryRecord::new(
+            &selector.join(","),
+            &tenant,
+            req.remote().unwrap_or("unknown"),
+            (selection.start, selection.end),
+            duration,
+            stats,
+        ),
+        req.state().config.slow_query_threshold,
+    );
+
+    if let (Some(details), Some(max_bytes)) = (too_large, max_response_bytes) {
+        let mut response = response_size::too_large(max_bytes, Some(details));
+        cost.set_headers(&mut response);
+        return Ok(response);
+    }
+
+    let mut response = tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&serde_json::json!({
+            name: body,
+            "stats": cost,
+        }))?)
+        .build();
+    cost.set_headers(&mut response);
+
+    Ok(response)
+}
+
+/// Parse the `query` parameter of `req`'s query string, if any.
+fn parse_query(req: &tide::Request<State>) -> Result<Option<Query>, String> {
+    match req.url().query_pairs().find(|(name, _)| name == "query") {
+        Some(_) if req.url().query_pairs().any(|(name, _)| name == "match") => {
+            Err("`match` can't be combined with `query`".to_string())
+        }
+        Some((_, query)) => Ok(Some(query.parse()?)),
+        None => Ok(None),
+    }
+}
+
+/// Parse the query string of `req` into a [`Selection`] (of `query`'s logs, if given), or a
+/// message describing why it's invalid.
+fn parse_selection(req: &tide::Request<State>, query: Option<&Query>) -> Result<Selection, String> {
+    let now = (req.state().config.clock)();
+    let mut selection = match query {
+        Some(query @ Query::CountOverTime { .. }) => {
+            let params = ["start", "since", "end", "until", "tz", "limit"];
+            if let Some((name, _)) = req
+                .url()
+                .query_pairs()
+                .find(|(name, _)| params.contains(&&**name))
+            {
+                return Err(format!(
+                    "`{}` can't be combined with `count_over_time`",
+                    name
+                ));
+            }
+            return Ok(query.selection(now));
+        }
+        Some(query) => query.selection(now),
+        None => Selection {
+            matchers: parse_matchers(req)?,
+            ..Selection::default()
+        },
+    };
+    let tz = match req.url().query_pairs().find(|(name, _)| name == "tz") {
+        Some((_, tz)) => tz.parse()?,
+        None => Offset::default(),
+    };
+    for (name, value) in req.url().query_pairs() {
+        match &*name {
+            "start" | "since" => selection.start = Some(parse_time(&name, &value, now, tz)?),
+            "end" | "until" => selection.end = Some(parse_time(&name, &value, now, tz)?),
+            "limit" => {
+                selection.limit = Some(
+                    value
+                        .parse()
+                        .map_err(|error| format!("invalid limit `{}`: {}", value, error))?,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    if let (Some(start), Some(end)) = (selection.start, selection.end) {
+        if end < start {
+            return Err("`end` is before `start`".to_string());
+        }
+    }
+    Ok(selection)
+}
+
+/// What a query returns.
+enum Output {
+    /// The matching lines, optionally reformatted.
+    Lines(Option<Projection>),
+
+    /// An estimate of the number of distinct values of `field`.
+    CountDistinct { field: String, precision: u8 },
+
+    /// The number of matching lines, for a `count_over_time` [`Query`].
+    Count,
+}
+
+/// Parse the `fields`, `format`, `count_distinct`, and `precision` parameters of `req`'s query
+/// string into an [`Output`] (or [`Output::Count`], if `query` is a `count_over_time` query).
+fn parse_output(req: &tide::Request<State>, query: Option<&Query>) -> Result<Output, String> {
+    if let Some(Query::CountOverTime { .. }) = query {
+        let params = ["fields", "format", "count_distinct", "precision"];
+        return match req
+            .url()
+            .query_pairs()
+            .find(|(name, _)| params.contains(&&**name))
+        {
+            Some((name, _)) => Err(format!(
+                "`{}` can't be combined with `count_over_time`",
+                name
+            )),
+            None => Ok(Output::Count),
+        };
+    }
+
+    let mut projection = None;
+    let mut count_distinct = None;
+    let mut precision = None;
+    for (name, value) in req.url().query_pairs() {
+        let parsed = match &*name {
+            "fields" => Projection::fields(&value)?,
+            "format" => Projection::template(&value)?,
+            "count_distinct" if value.is_empty() => {
+                return Err("`count_distinct` must name a field".to_string())
+            }
+            "count_distinct" => {
+                count_distinct = Some(value.into_owned());
+                continue;
+            }
+            "precision" => {
+                precision = Some(
+                    value
+                        .parse()
+                        .map_err(|error| format!("invalid precision `{}`: {}", value, error))?,
+                );
+                continue;
+            }
+            _ => continue,
+        };
+        if projection.replace(parsed).is_some() {
+            return Err("only one of `fields` and `format` may be given".to_string());
+        }
+    }
+
+    match (count_distinct, projection, precision) {
+        (Some(_), None, Some(precision))
+            if !(distinct::MIN_PRECISION..=distinct::MAX_PRECISION).contains(&precision) =>
+        {
+            Err(format!(
+                "invalid precision `{}`: must be between {} and {}",
+                precision,
+                distinct::MIN_PRECISION,
+                distinct::MAX_PRECISION
+            ))
+        }
+        (Some(field), None, precision) => Ok(Output::CountDistinct {
+            field,
+            precision: precision.unwrap_or(distinct::DEFAULT_PRECISION),
+        }),
+        (Some(_), Some(_), _) => {
+            Err("`fields` and `format` can't be combined with `count_distinct`".to_string())
+        }
+        (None, _, Some(_)) => Err("`precision` requires `count_distinct`".to_string()),
+        (None, projection, None) => Ok(Output::Lines(projection)),
+    }
+}
+
+/// Parse the `match` parameters of `req`'s query string, of which there must be at least one.
+pub(super) fn parse_matchers(req: &tide::Request<State>) -> Result<Vec<(String, String)>, String> {
+    let matchers = req
+        .url()
+        .query_pairs()
+        .filter(|(name, _)| name == "match")
+        .map(|(_, value)| parse_matcher(&value))
+        .collect::<Result<Vec<_>, _>>()?;
+    if matchers.is_empty() {
+        return Err("at least one `match` parameter is required".to_string());
+    }
+    Ok(matchers)
+}
+
+pub(super) fn parse_matcher(matcher: &str) -> Result<(String, String), String> {
+    let mut parts = matcher.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if !key.is_empty() => {
+            Ok((key.to_string(), match_value(key, value)))
+        }
+        _ => Err(format!("invalid matcher `{}`, expected key=value", matcher)),
+    }
+}
+
+/// The value to match for `key`, normalising `path`s the same way collectors do.
+pub(super) fn match_value(key: &str, value: &str) -> String {
+    if key == "path" {
+        paths::normalize_str(value)
+    } else {
+        value.to_string()
+    }
+}
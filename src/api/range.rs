@@ -0,0 +1,156 @@
+// src/api/range.rs
+//! Time range expressions for the query API.
+//!
+//! The bounds of a query's time range (`start` and `end`, or their aliases `since` and `until`)
+//! may be:
+//!
+//! - a number of seconds since the Unix epoch, e.g. `1600000000`,
+//! - `now`,
+//! - a duration ago, in the format of retention rules, e.g. `15m`, `2h`, or `7d`, or
+//! - `today` or `yesterday`, meaning the start of that day.
+//!
+//! Relative expressions are resolved on the server against [`Config::clock`]. Days start at
+//! midnight in the `tz` parameter's time zone, which is a fixed UTC offset like `+02:00`, `-0530`,
+//! or `UTC` (the default).
+//!
+//! [`Config::clock`]: super::Config::clock
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::log_database::retention;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A fixed offset from UTC, in seconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(super) struct Offset(i64);
+
+impl FromStr for Offset {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid tz `{}`, expected UTC or an offset like +02:00",
+                input
+            )
+        };
+        if input.eq_ignore_ascii_case("utc") || input == "Z" {
+            return Ok(Self(0));
+        }
+
+        let sign = match input.chars().next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => return Err(invalid()),
+        };
+        let digits: String = input[1..].chars().filter(|c| *c != ':').collect();
+        if !digits.chars().all(|c| c.is_ascii_digit()) || input[1..].matches(':').count() > 1 {
+            return Err(invalid());
+        }
+        let (hours, minutes) = match digits.len() {
+            2 => (&digits[..], "0"),
+            4 => (&digits[..2], &digits[2..]),
+            _ => return Err(invalid()),
+        };
+        let hours: i64 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i64 = minutes.parse().map_err(|_| invalid())?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(Self(sign * (hours * 60 + minutes) * 60))
+    }
+}
+
+/// Resolve the time range bound `value` of the parameter `name`, relative to `now` in the time
+/// zone `tz`.
+pub(super) fn parse_time(
+    name: &str,
+    value: &str,
+    now: SystemTime,
+    tz: Offset,
+) -> Result<SystemTime, String> {
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+        let secs = value
+            .parse()
+            .map_err(|error| format!("invalid {} `{}`: {}", name, value, error))?;
+        return UNIX_EPOCH
+            .checked_add(Duration::from_secs(secs))
+            .ok_or_else(|| format!("invalid {} `{}`: out of range", name, value));
+    }
+
+    match value {
+        "now" => Ok(now),
+        "today" => start_of_day(now, tz, 0),
+        "yesterday" => start_of_day(now, tz, 1),
+        _ => {
+            let ago = retention::parse_duration(value)
+                .map_err(|error| format!("invalid {} `{}`: {}", name, value, error))?;
+            now.checked_sub(ago)
+                .filter(|time| *time >= UNIX_EPOCH)
+                .ok_or_else(|| format!("invalid {} `{}`: before the Unix epoch", name, value))
+        }
+    }
+}
+
+/// The start of the day `days_ago` days before `now`, in the time zone `tz`.
+fn start_of_day(now: SystemTime, tz: Offset, days_ago: i64) -> Result<SystemTime, String> {
+    let now = now
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| "the clock is before the Unix epoch".to_string())?;
+    let local = i64::try_from(now.as_secs())
+        .unwrap_or(i64::MAX)
+        .saturating_add(tz.0);
+    let start = local - local.rem_euclid(SECONDS_PER_DAY) - days_ago * SECONDS_PER_DAY - tz.0;
+    let start =
+        u64::try_from(start).map_err(|_| "the day starts before the Unix epoch".to_string())?;
+    UNIX_EPOCH
+        .checked_add(Duration::from_secs(start))
+        .ok_or_else(|| "the day is out of range".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{parse_time, Offset};
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn relative_times() {
+        // 2020-09-13T12:26:40Z
+        let now = at(1_600_000_000);
+        let utc = Offset::default();
+        let parse = |value: &str, tz| parse_time("since", value, now, tz);
+
+        assert_eq!(parse("1500000000", utc), Ok(at(1_500_000_000)));
+        assert_eq!(parse("now", utc), Ok(now));
+        assert_eq!(parse("15m", utc), Ok(at(1_600_000_000 - 15 * 60)));
+        assert_eq!(parse("2h", utc), Ok(at(1_600_000_000 - 2 * 60 * 60)));
+        assert_eq!(parse("today", utc), Ok(at(1_599_955_200)));
+        assert_eq!(parse("yesterday", utc), Ok(at(1_599_868_800)));
+
+        let tokyo = "+09:00".parse().unwrap();
+        assert_eq!(parse("today", tokyo), Ok(at(1_599_955_200 - 9 * 60 * 60)));
+        let honolulu = "-1000".parse().unwrap();
+        assert_eq!(
+            parse("today", honolulu),
+            Ok(at(1_599_955_200 + 10 * 60 * 60))
+        );
+        assert_eq!("UTC".parse(), Ok(utc));
+        assert_eq!("-05".parse(), Ok(Offset(-5 * 60 * 60)));
+
+        assert!(parse("fortnight", utc).is_err());
+        assert!(parse("", utc).is_err());
+        assert!(parse("100000d", utc).is_err());
+        assert!(parse(&u64::MAX.to_string(), utc).is_err());
+        for tz in &["Europe/London", "+2", "+24:00", "+02:00:00", "02:00"] {
+            assert!(tz.parse::<Offset>().is_err(), "{}", tz);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+// src/api/rate_limit.rs
+//! Per-client rate limiting of queries.
+//!
+//! With [`Config::rate_limit`](super::Config::rate_limit) set, each client may make a burst of up
+//! to `burst` `GET` requests, refilled at `requests_per_second`. Further requests are rejected with
+//! `429 Too Many Requests`, and a `Retry-After` header with the number of seconds until a request
+//! would be allowed. Only `GET` requests are limited, so pushes are never rejected.
+//!
+//! Clients are identified by their [`Key`]: either the IP address they connect from, or the
+//! [`Identity`] their bearer token authenticates as (falling back to the IP address for requests
+//! without a valid token, or when authentication is disabled). The buckets of at most
+//! [`MAX_CLIENTS`] clients are kept, evicting the least recently seen.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::auth::Identity;
+use super::error::error_response;
+use super::State;
+
+/// The number of clients to track before forgetting the least recently seen.
+const MAX_CLIENTS: usize = 10_000;
+
+/// How clients are identified for rate limiting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    /// The IP address of the connection (`ip`).
+    RemoteAddr,
+
+    /// The identity of the request's bearer token (`token`).
+    Token,
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "ip" => Ok(Self::RemoteAddr),
+            "token" => Ok(Self::Token),
+            _ => Err(format!(
+                "invalid rate limit key `{}`, expected `ip` or `token`",
+                input
+            )),
+        }
+    }
+}
+
+/// Rate limiting configuration for the HTTP API.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The sustained number of requests each client may make per second.
+    pub requests_per_second: NonZeroU32,
+
+    /// The number of requests each client may make at once.
+    pub burst: NonZeroU32,
+
+    /// How clients are identified.
+    pub key: Key,
+}
+
+/// The token bucket of every client.
+#[derive(Debug, Default)]
+pub(super) struct Limiter(Mutex<Buckets>);
+
+#[derive(Debug, Default)]
+struct Buckets {
+    clock: u64,
+    buckets: HashMap<String, Bucket>,
+    by_last_used: BTreeMap<u64, String>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    last_used: u64,
+}
+
+impl Buckets {
+    /// Get `client`'s bucket, creating a full one (and evicting the least recently used bucket if
+    /// there are [`MAX_CLIENTS`]) if it has none.
+    fn get(&mut self, client: String, burst: f64, now: Instant) -> &mut Bucket {
+        if self.buckets.len() >= MAX_CLIENTS && !self.buckets.contains_key(&client) {
+            let oldest = self.by_last_used.keys().next().copied();
+            if let Some(client) = oldest.and_then(|oldest| self.by_last_used.remove(&oldest)) {
+                self.buckets.remove(&client);
+            }
+        }
+
+        self.clock += 1;
+        let bucket = self.buckets.entry(client.clone()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            last_used: 0,
+        });
+        self.by_last_used.remove(&bucket.last_used);
+        bucket.last_used = self.clock;
+        self.by_last_used.insert(self.clock, client);
+        bucket
+    }
+}
+
+impl Bucket {
+    /// Add the tokens accrued since the bucket was last updated, returning the new total.
+    fn refill(&mut self, rate: f64, burst: f64, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+        self.tokens
+    }
+}
+
+impl Limiter {
+    /// Take a token from `client`'s bucket, or return how long until one is available.
+    fn acquire(&self, config: &Config, client: String, now: Instant) -> Result<(), Duration> {
+        let rate = f64::from(config.requests_per_second.get());
+        let burst = f64::from(config.burst.get());
+
+        let mut buckets = self.0.lock().expect("rate limiter lock poisoned");
+        let bucket = buckets.get(client, burst, now);
+        if bucket.refill(rate, burst, now) >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Identify the client making `req`.
+fn client(req: &tide::Request<State>, key: Key) -> String {
+    if key == Key::Token {
+        if let Some(identity) = req.ext::<Identity>() {
+            return format!(
+                "identity:{}:{}",
+                identity.tenant.as_deref().unwrap_or(""),
+                identity.scopes.join(",")
+            );
+        }
+    }
+    match req.peer_addr() {
+        Some(addr) => match addr.parse::<SocketAddr>() {
+            Ok(addr) => format!("ip:{}", addr.ip()),
+            Err(_) => format!("addr:{}", addr),
+        },
+        None => "unknown".to_string(),
+    }
+}
+
+/// Middleware that rejects `GET` requests from clients that exceed the rate limit.
+#[derive(Debug, Default)]
+pub(super) struct RateLimit;
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for RateLimit {
+    async fn handle(&self, req: tide::Request<State>, next: tide::Next<'_, State>) -> tide::Result {
+        let config = match &req.state().config.rate_limit {
+            Some(config) if req.method() == tide::http::Method::Get => config,
+            _ => return Ok(next.run(req).await),
+        };
+
+        let client = client(&req, config.key);
+        let acquired = req
+            .state()
+            .rate_limiter
+            .acquire(config, client, Instant::now());
+        match acquired {
+            Ok(()) => Ok(next.run(req).await),
+            Err(wait) => {
+                let retry_after = (wait.as_secs() + u64::from(wait.subsec_nanos() > 0)).max(1);
+                let mut response = error_response(
+                    tide::StatusCode::TooManyRequests,
+                    "limit_exceeded",
+                    format!("rate limit exceeded, retry in {}s", retry_after),
+                    None,
+                );
+                response.insert_header("Retry-After", retry_after.to_string());
+                Ok(response)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::time::{Duration, Instant};
+
+    use super::{Config, Key, Limiter, MAX_CLIENTS};
+
+    #[test]
+    fn token_buckets() {
+        let config = Config {
+            requests_per_second: NonZeroU32::new(2).unwrap(),
+            burst: NonZeroU32::new(3).unwrap(),
+            key: Key::RemoteAddr,
+        };
+        let limiter = Limiter::default();
+        let now = Instant::now();
+        let acquire =
+            |client: &str, at: Duration| limiter.acquire(&config, client.to_string(), now + at);
+
+        for _ in 0..3 {
+            assert_eq!(acquire("a", Duration::from_secs(0)), Ok(()));
+        }
+        assert_eq!(
+            acquire("a", Duration::from_secs(0)),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(acquire("b", Duration::from_secs(0)), Ok(()));
+
+        assert_eq!(acquire("a", Duration::from_millis(500)), Ok(()));
+        assert!(acquire("a", Duration::from_millis(500)).is_err());
+        assert_eq!(acquire("a", Duration::from_secs(10)), Ok(()));
+        assert_eq!(acquire("a", Duration::from_secs(10)), Ok(()));
+        assert_eq!(acquire("a", Duration::from_secs(10)), Ok(()));
+        assert!(acquire("a", Duration::from_secs(10)).is_err());
+
+        assert_eq!("token".parse(), Ok(Key::Token));
+        assert!("cookie".parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let config = Config {
+            requests_per_second: NonZeroU32::new(1).unwrap(),
+            burst: NonZeroU32::new(1).unwrap(),
+            key: Key::RemoteAddr,
+        };
+        let limiter = Limiter::default();
+        let now = Instant::now();
+
+        for client in 0..MAX_CLIENTS {
+            assert_eq!(limiter.acquire(&config, client.to_string(), now), Ok(()));
+        }
+        assert!(limiter.acquire(&config, "0".to_string(), now).is_err());
+        assert_eq!(limiter.acquire(&config, "new".to_string(), now), Ok(()));
+
+        let buckets = limiter.0.lock().unwrap();
+        assert_eq!(buckets.buckets.len(), MAX_CLIENTS);
+        assert_eq!(buckets.by_last_used.len(), MAX_CLIENTS);
+        assert!(buckets.buckets.contains_key("0"));
+        assert!(!buckets.buckets.contains_key("1"));
+    }
+}
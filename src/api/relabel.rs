@@ -0,0 +1,198 @@
+// src/api/relabel.rs
+//! Merging and splitting streams, e.g. to migrate historical data after relabelling rules change.
+//!
+//! - `POST /admin/streams/merge` takes a JSON body like
+//!   `{"selector": {"app": "old"}, "labels": {"app": "new", "pod": null}}`, and moves every entry
+//!   of the selected streams onto their relabelled metadata (`null` removes a label).
+//! - `POST /admin/streams/split` takes the same body plus a predicate: one of `contains`, `regex`,
+//!   or `field` with `equals` (matching JSON lines whose `field` equals the given value). Only the
+//!   matching entries are moved.
+//!
+//! Both respond with `202 Accepted`, the ID of the background job doing the rewrite, and the
+//! [`Plan`](crate::log_database::relabel::Plan) of streams it will rewrite. Progress can be polled
+//! through the jobs API (see [`crate::jobs`]), which can also cancel the rewrite between streams.
+//! With `?dry_run=true`, only the plan is returned. Streams subject to a legal hold are skipped.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+
+use async_std::task;
+
+use crate::log_database::filter::LineFilter;
+use crate::log_database::relabel::{Predicate, Relabel};
+
+use super::error::error_response;
+use super::State;
+
+/// The kind of job used for merges.
+const MERGE_JOB_KIND: &str = "stream_merge";
+
+/// The kind of job used for splits.
+const SPLIT_JOB_KIND: &str = "stream_split";
+
+#[derive(serde::Deserialize)]
+struct RelabelRequest {
+    selector: BTreeMap<String, String>,
+    labels: BTreeMap<String, Option<String>>,
+
+    #[serde(flatten)]
+    predicate: PredicateParams,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct PredicateParams {
+    /// Move entries containing this text.
+    contains: Option<String>,
+
+    /// Move entries matching this regular expression.
+    regex: Option<String>,
+
+    /// Move JSON entries with this field equal to `equals`.
+    field: Option<String>,
+
+    /// The value `field` must have.
+    equals: Option<String>,
+}
+
+impl PredicateParams {
+    fn predicate(self) -> tide::Result<Option<Predicate>> {
+        match (self.contains, self.regex, self.field, self.equals) {
+            (None, None, None, None) => Ok(None),
+            (Some(contains), None, None, None) => {
+                Ok(Some(Predicate::Line(LineFilter::Contains(contains))))
+            }
+            (None, Some(regex), None, None) => regex::Regex::new(&regex)
+                .map(|regex| Some(Predicate::Line(LineFilter::Regex(regex))))
+                .map_err(|error| tide::Error::new(tide::StatusCode::BadRequest, error)),
+            (None, None, Some(name), Some(value)) => Ok(Some(Predicate::Field { name, value })),
+            _ => Err(tide::Error::from_str(
+                tide::StatusCode::BadRequest,
+                "exactly one of `contains`, `regex`, or `field` (with `equals`) may be given",
+            )),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct RelabelParams {
+    /// Report which streams would be rewritten, without rewriting anything.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+pub(super) async fn merge_streams(mut req: tide::Request<State>) -> tide::Result {
+    let RelabelRequest {
+        selector, labels, ..
+    } = req.body_json().await?;
+    let RelabelParams { dry_run } = req.query()?;
+    start(
+        req.state(),
+        dry_run,
+        MERGE_JOB_KIND,
+        Relabel {
+            selector,
+            labels,
+            predicate: None,
+        },
+    )
+    .await
+}
+
+pub(super) async fn split_streams(mut req: tide::Request<State>) -> tide::Result {
+    let RelabelRequest {
+        selector,
+        labels,
+        predicate,
+    } = req.body_json().await?;
+    let predicate = match predicate.predicate()? {
+        Some(predicate) => predicate,
+        None => {
+            return Err(tide::Error::from_str(
+                tide::StatusCode::BadRequest,
+                "one of `contains`, `regex`, or `field` (with `equals`) must be given",
+            ))
+        }
+    };
+    let RelabelParams { dry_run } = req.query()?;
+    start(
+        req.state(),
+        dry_run,
+        SPLIT_JOB_KIND,
+        Relabel {
+            selector,
+            labels,
+            predicate: Some(predicate),
+        },
+    )
+    .await
+}
+
+async fn start(state: &State, dry_run: bool, kind: &'static str, relabel: Relabel) -> tide::Result {
+    let plan = match state.database.read().await.relabel_plan(&relabel) {
+        Ok(plan) => plan,
+        Err(error) if error.kind() == io::ErrorKind::InvalidInput => {
+            return Ok(error_response(
+                tide::StatusCode::BadRequest,
+                "bad_request",
+                error.to_string(),
+                None,
+            ))
+        }
+        Err(error) => return Err(error.into()),
+    };
+    if dry_run {
+        return Ok(tide::Response::builder(tide::StatusCode::Ok)
+            .body(tide::Body::from_json(&plan)?)
+            .build());
+    }
+
+    let database = Arc::clone(&state.database);
+    let job = state.config.jobs.start(
+        kind,
+        format!("relabel {:?} with {:?}", relabel.selector, relabel.labels),
+    );
+    let id = job.id();
+    let response = serde_json::json!({
+        "id": id,
+        "streams": plan.streams,
+        "held": plan.held,
+    });
+    task::spawn(async move {
+        let result = async {
+            let (mut streams, mut moved, mut kept) = (0, 0, 0);
+            for (i, metadata) in plan.streams.iter().enumerate() {
+                if job.is_cancelled() {
+                    return Ok(None);
+                }
+                // The lock is taken per stream, so writes can continue during a long rewrite.
+                let outcome = database.write().await.relabel_stream(metadata, &relabel)?;
+                if let Some(outcome) = outcome {
+                    streams += 1;
+                    moved += outcome.moved;
+                    kept += outcome.kept;
+                }
+                #[allow(clippy::cast_precision_loss)]
+                job.set_progress((i + 1) as f64 / plan.streams.len() as f64);
+            }
+            Ok::<_, io::Error>(Some((streams, moved, kept)))
+        }
+        .await;
+
+        match result {
+            Ok(Some((streams, moved, kept))) => job.complete(serde_json::json!({
+                "streams": streams,
+                "moved": moved,
+                "kept": kept,
+                "held": plan.held,
+            })),
+            Ok(None) => job.cancelled(),
+            Err(error) => job.fail(error),
+        }
+    });
+
+    Ok(tide::Response::builder(tide::StatusCode::Accepted)
+        .body(tide::Body::from_json(&response)?)
+        .build())
+}
@@ -0,0 +1,69 @@
+// src/api/request_metrics.rs
+//! Per-route request metrics.
+//!
+//! Each route records its requests with the [`RecordRequest`] middleware, labelled by the route's
+//! template (e.g. `/logs/:key/*value`) rather than the raw path, so that the number of series
+//! stays bounded. The metrics are rendered by `GET /metrics`.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::log_database::metrics::LatencyRecorder;
+use crate::metrics::RouteMetrics;
+
+use super::State;
+
+/// Request metrics for all routes since the API started.
+#[derive(Debug, Default)]
+pub(super) struct RequestMetrics(Mutex<BTreeMap<(&'static str, String), Route>>);
+
+#[derive(Debug, Default)]
+struct Route {
+    responses: BTreeMap<String, u64>,
+    latency: LatencyRecorder,
+}
+
+impl RequestMetrics {
+    pub(super) fn snapshot(&self) -> Vec<RouteMetrics> {
+        self.0
+            .lock()
+            .expect("request metrics lock poisoned")
+            .iter()
+            .map(|((route, method), metrics)| RouteMetrics {
+                route: *route,
+                method: method.clone(),
+                responses: metrics.responses.clone(),
+                latency: metrics.latency.snapshot(),
+            })
+            .collect()
+    }
+}
+
+/// Middleware that records the requests to a route.
+#[derive(Debug)]
+pub(super) struct RecordRequest(pub(super) &'static str);
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for RecordRequest {
+    async fn handle(&self, req: tide::Request<State>, next: tide::Next<'_, State>) -> tide::Result {
+        let method = req.method().to_string();
+        let state = req.state().clone();
+
+        let start = Instant::now();
+        let response = next.run(req).await;
+        let duration = start.elapsed();
+
+        let status_class = format!("{}xx", u16::from(response.status()) / 100);
+        let mut routes = state
+            .request_metrics
+            .0
+            .lock()
+            .expect("request metrics lock poisoned");
+        let route = routes.entry((self.0, method)).or_default();
+        *route.responses.entry(status_class).or_default() += 1;
+        route.latency.record(duration);
+
+        Ok(response)
+    }
+}
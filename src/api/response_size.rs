@@ -0,0 +1,69 @@
+// src/api/response_size.rs
+//! Capping the size of query responses (see [`Config::max_response_bytes`]).
+//!
+//! Responses are serialized in memory, so a query matching a lot of logs can use a lot of memory.
+//! With a cap on the size of the lines in a response:
+//!
+//! - Pages of `GET /logs/:key/*value` results (when a `limit`, `cursor`, or `direction` is given)
+//!   are cut short to the lines that fit, with an `X-Truncated: true` header. The `X-Next-Cursor`
+//!   header continues from the first line that was cut.
+//! - Other responses that would exceed the cap are rejected with `413 Payload Too Large`, and a
+//!   `response_too_large` error. Where possible, the error's `details` are an object with the
+//!   `lines` that fit, and, for `GET /logs/:key/*value`, a `next_cursor` to continue from by paging.
+//!
+//! A line that doesn't fit within the cap by itself can never be returned, so requests for it are
+//! always rejected.
+//!
+//! [`Config::max_response_bytes`]: super::Config::max_response_bytes
+
+use super::error::error_response;
+
+/// The header marking a page that was cut short to fit within the cap.
+pub(super) const TRUNCATED_HEADER: &str = "X-Truncated";
+
+/// The number of leading `lines` that fit within `max_bytes` when serialized as a JSON array.
+pub(super) fn lines_within(lines: &[String], max_bytes: usize) -> usize {
+    // The opening bracket, then each line followed by a comma or the closing bracket.
+    let mut bytes = 1;
+    for (count, line) in lines.iter().enumerate() {
+        bytes += serde_json::to_string(line).map_or(line.len(), |json| json.len()) + 1;
+        if bytes > max_bytes {
+            return count;
+        }
+    }
+    lines.len()
+}
+
+/// A response rejecting a request whose response would exceed `max_bytes`.
+pub(super) fn too_large(max_bytes: usize, details: Option<serde_json::Value>) -> tide::Response {
+    error_response(
+        tide::StatusCode::PayloadTooLarge,
+        "response_too_large",
+        format!(
+            "the response would exceed the limit of {} bytes, page through the results instead",
+            max_bytes
+        ),
+        details,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lines_within;
+
+    #[test]
+    fn count_lines_within() {
+        let lines: Vec<_> = ["hello", "wor\"ld", "!"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        let len = |count| serde_json::to_string(&lines[..count]).unwrap().len();
+
+        assert_eq!(lines_within(&lines, len(3)), 3);
+        assert_eq!(lines_within(&lines, len(3) - 1), 2);
+        assert_eq!(lines_within(&lines, len(2)), 2);
+        assert_eq!(lines_within(&lines, len(1)), 1);
+        assert_eq!(lines_within(&lines, len(1) - 1), 0);
+        assert_eq!(lines_within(&[], 0), 0);
+    }
+}
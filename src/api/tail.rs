@@ -0,0 +1,83 @@
+// src/api/tail.rs
+//! Live tailing of logs.
+//!
+//! `GET /tail?match=ns=prod&match=app=api` streams entries as they're written to the streams
+//! whose metadata contains every `match`ed `key=value` pair (see [`query`](super::query) for the
+//! matcher syntax), as [server-sent events]. Each entry is sent as an `entry` event with data
+//! like `{"line": "...", "metadata": {...}}`. If the client falls behind and entries are dropped
+//! (see [`log_database::tail`]), a `dropped` event is sent with the total number dropped so far
+//! before the next entry.
+//!
+//! A `heartbeat` event with empty data is sent after [`HEARTBEAT_INTERVAL`] without entries, so
+//! that subscriptions of clients that have disconnected are noticed and closed even if nothing
+//! more is written to the matched streams. If [`MAX_SUBSCRIPTIONS`] clients are already tailing,
+//! the request gets a `429 Too Many Requests` response.
+//!
+//! [server-sent events]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+//! [`log_database::tail`]: crate::log_database::tail
+//! [`MAX_SUBSCRIPTIONS`]: crate::log_database::tail::MAX_SUBSCRIPTIONS
+
+use std::time::Duration;
+
+use async_std::future::timeout;
+
+use super::auth;
+use super::error::error_response;
+use super::query::parse_matchers;
+use super::State;
+use crate::log_database::tail::MAX_SUBSCRIPTIONS;
+
+/// How long to wait for an entry before sending a `heartbeat` event.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+pub(super) async fn tail_logs(req: tide::Request<State>) -> tide::Result {
+    let matchers = match parse_matchers(&req) {
+        Ok(matchers) => matchers,
+        Err(message) => {
+            return Ok(error_response(
+                tide::StatusCode::BadRequest,
+                "bad_request",
+                message,
+                None,
+            ))
+        }
+    };
+    if let Some(response) = auth::forbid_internal(&req, matchers.iter().map(|(key, _)| &**key)) {
+        return Ok(response);
+    }
+    let subscription = match req.state().database.write().await.subscribe(matchers) {
+        Some(subscription) => subscription,
+        None => {
+            return Ok(error_response(
+                tide::StatusCode::TooManyRequests,
+                "limit_exceeded",
+                format!("at most {} tails may be open at once", MAX_SUBSCRIPTIONS),
+                None,
+            ))
+        }
+    };
+
+    Ok(tide::sse::upgrade(req, move |_req, sender| {
+        let subscription = subscription.clone();
+        async move {
+            let mut dropped = 0;
+            loop {
+                let entry = match timeout(HEARTBEAT_INTERVAL, subscription.recv()).await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => break,
+                    Err(_) => {
+                        sender.send("heartbeat", "", None).await?;
+                        continue;
+                    }
+                };
+                if subscription.dropped() > dropped {
+                    dropped = subscription.dropped();
+                    sender.send("dropped", dropped.to_string(), None).await?;
+                }
+                let data = serde_json::json!({ "line": entry.line, "metadata": entry.metadata });
+                sender.send("entry", data.to_string(), None).await?;
+            }
+            Ok(())
+        }
+    }))
+}
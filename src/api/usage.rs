@@ -0,0 +1,156 @@
+// src/api/usage.rs
+//! Per-tenant accounting of query costs.
+//!
+//! Each query's cost is reported to the caller in response headers (and optionally the response
+//! body), and accumulated per tenant so that usage can be reviewed through `GET /usage`. Tenants
+//! are identified by the authenticated [`Identity`], or the [`TENANT_HEADER`] request header if
+//! authentication is disabled.
+//!
+//! At most [`MAX_TENANTS`] tenants are tracked; queries from further tenants are accounted to a
+//! shared `other` tenant. When authentication is enabled, `GET /usage` only includes the caller's
+//! own tenant, unless the caller has the `admin` scope.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::log_database::QueryStats;
+
+use super::auth::{self, Identity};
+use super::State;
+
+/// The request header used to identify the tenant making a request.
+pub(super) const TENANT_HEADER: &str = "X-Tenant-ID";
+
+/// The tenant that requests without a known tenant are accounted to.
+const DEFAULT_TENANT: &str = "anonymous";
+
+/// The most tenants whose usage is tracked separately.
+pub(super) const MAX_TENANTS: usize = 1000;
+
+/// The tenant that queries are accounted to once [`MAX_TENANTS`] tenants are tracked.
+const OTHER_TENANT: &str = "other";
+
+/// The estimated cost of one or more queries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub(super) struct Cost {
+    /// The number of bytes read from storage.
+    pub(super) bytes_scanned: u64,
+
+    /// The number of bytes returned to the caller.
+    pub(super) bytes_returned: u64,
+
+    /// The wall-clock time spent executing the query, including waiting for the database.
+    pub(super) duration_micros: u64,
+}
+
+impl Cost {
+    pub(super) fn new(stats: QueryStats, duration: Duration) -> Self {
+        Self {
+            bytes_scanned: stats.bytes_scanned,
+            bytes_returned: stats.bytes_returned,
+            duration_micros: u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+        }
+    }
+
+    /// Report the cost in `response`'s headers.
+    pub(super) fn set_headers(&self, response: &mut tide::Response) {
+        response.insert_header("X-Query-Bytes-Scanned", self.bytes_scanned.to_string());
+        response.insert_header("X-Query-Bytes-Returned", self.bytes_returned.to_string());
+        response.insert_header("X-Query-Duration-Micros", self.duration_micros.to_string());
+    }
+
+    fn add(&mut self, other: Self) {
+        self.bytes_scanned = self.bytes_scanned.saturating_add(other.bytes_scanned);
+        self.bytes_returned = self.bytes_returned.saturating_add(other.bytes_returned);
+        self.duration_micros = self.duration_micros.saturating_add(other.duration_micros);
+    }
+}
+
+/// The accumulated usage of a single tenant.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub(super) struct TenantUsage {
+    /// The number of queries made by the tenant.
+    pub(super) queries: u64,
+
+    /// The total cost of the tenant's queries.
+    #[serde(flatten)]
+    pub(super) cost: Cost,
+}
+
+/// Usage accumulated for all tenants since the API started.
+#[derive(Debug, Default)]
+pub(super) struct Usage(Mutex<HashMap<String, TenantUsage>>);
+
+impl Usage {
+    pub(super) fn record(&self, tenant: &str, cost: Cost) {
+        let mut usage = self.0.lock().expect("usage lock poisoned");
+        // The tenant may come from a request header, so unknown tenants can't grow the map forever.
+        let tenant = if usage.len() >= MAX_TENANTS && !usage.contains_key(tenant) {
+            OTHER_TENANT
+        } else {
+            tenant
+        };
+        let tenant_usage = usage.entry(tenant.to_string()).or_default();
+        tenant_usage.queries += 1;
+        tenant_usage.cost.add(cost);
+    }
+
+    pub(super) fn snapshot(&self) -> HashMap<String, TenantUsage> {
+        self.0.lock().expect("usage lock poisoned").clone()
+    }
+}
+
+/// Identify the tenant making a request.
+///
+/// Authenticated requests are accounted to their [`Identity`]'s tenant. The [`TENANT_HEADER`] is
+/// only trusted while authentication is disabled, since otherwise any caller could charge their
+/// queries to another tenant.
+pub(super) fn tenant(req: &tide::Request<State>) -> String {
+    if let Some(identity) = req.ext::<Identity>() {
+        return identity
+            .tenant
+            .clone()
+            .unwrap_or_else(|| DEFAULT_TENANT.to_string());
+    }
+    if !req.state().config.auth_providers.is_empty() {
+        return DEFAULT_TENANT.to_string();
+    }
+    req.header(TENANT_HEADER).map_or_else(
+        || DEFAULT_TENANT.to_string(),
+        |values| values.last().as_str().to_string(),
+    )
+}
+
+pub(super) async fn get_usage(req: tide::Request<State>) -> tide::Result {
+    let mut usage = req.state().usage.snapshot();
+    if !auth::has_scope(&req, auth::ADMIN) {
+        let tenant = tenant(&req);
+        usage.retain(|name, _| *name == tenant);
+    }
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&usage)?)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cost, Usage, MAX_TENANTS};
+
+    #[test]
+    fn limits_tenants() {
+        let usage = Usage::default();
+        for tenant in 0..MAX_TENANTS {
+            usage.record(&tenant.to_string(), Cost::default());
+        }
+        usage.record("new", Cost::default());
+        usage.record("0", Cost::default());
+
+        let snapshot = usage.snapshot();
+        assert_eq!(snapshot.len(), MAX_TENANTS + 1);
+        assert!(snapshot.get("new").is_none());
+        assert_eq!(snapshot["other"].queries, 1);
+        assert_eq!(snapshot["0"].queries, 2);
+    }
+}
@@ -0,0 +1,72 @@
+// src/api/version.rs
+//! Version and capability discovery.
+//!
+//! `GET /version` reports the version of `monitoring-rs` that is running, along with the API
+//! capabilities it supports, so that tooling and peers can adapt to mixed-version deployments.
+//! Capabilities are only ever added to a given version, so clients should check for the presence
+//! of the capabilities they need rather than comparing versions.
+
+use super::{protocol, State};
+
+/// The version of the crate.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The git commit the binary was built from, or `unknown` (see `build.rs`).
+const GIT_COMMIT: &str = env!("MONITORING_RS_GIT_COMMIT");
+
+/// Enabled optional cargo features. There are no optional features yet.
+const FEATURES: &[&str] = &[];
+
+#[derive(serde::Serialize)]
+struct Version {
+    version: &'static str,
+    git_commit: &'static str,
+    features: &'static [&'static str],
+    capabilities: Capabilities,
+}
+
+#[derive(serde::Serialize)]
+struct Capabilities {
+    /// Formats in which logs can be returned.
+    response_formats: &'static [&'static str],
+
+    /// Formats in which logs can be pushed to `POST /push`.
+    push_formats: &'static [&'static str],
+
+    /// The range of push [protocol](super::protocol) versions that are supported.
+    push_protocol_versions: [u32; 2],
+
+    /// Query parameters supported by `GET /logs/:key/:value`.
+    query_features: &'static [&'static str],
+
+    /// The format of error responses.
+    error_format: &'static str,
+}
+
+const CAPABILITIES: Capabilities = Capabilities {
+    response_formats: &["json"],
+    push_formats: &["ndjson"],
+    push_protocol_versions: [protocol::MIN_VERSION, protocol::CURRENT_VERSION],
+    query_features: &[
+        "contains",
+        "regex",
+        "stats",
+        "limit",
+        "cursor",
+        "direction",
+        "fields",
+        "format",
+    ],
+    error_format: "envelope",
+};
+
+pub(super) async fn get_version(_req: tide::Request<State>) -> tide::Result {
+    Ok(tide::Response::builder(tide::StatusCode::Ok)
+        .body(tide::Body::from_json(&Version {
+            version: VERSION,
+            git_commit: GIT_COMMIT,
+            features: FEATURES,
+            capabilities: CAPABILITIES,
+        })?)
+        .build())
+}
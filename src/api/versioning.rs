@@ -0,0 +1,47 @@
+// src/api/versioning.rs
+//! API versioning.
+//!
+//! Every endpoint is served under [`API_PREFIX`] (e.g. `GET /api/v1/query`), so the API can evolve
+//! without breaking existing clients. Errors from versioned endpoints always have a structured body
+//! (see [`error`](super::error)).
+//!
+//! The unversioned paths (e.g. `GET /query`) remain as deprecated aliases of the current version.
+//! Their responses have a `Deprecation: true` header, and a `Link` header pointing at the
+//! versioned path, so clients can find and migrate away from them.
+
+use super::{Server, State};
+
+/// The path prefix of the current version of the API.
+pub(super) const API_PREFIX: &str = "/api/v1";
+
+/// Add the routes added by `routes` under [`API_PREFIX`], and at their deprecated unversioned
+/// paths.
+pub(super) fn versioned(app: &mut Server, state: &State, routes: impl Fn(&mut Server)) {
+    let mut versioned = tide::with_state(state.clone());
+    routes(&mut versioned);
+    app.at(API_PREFIX).nest(versioned);
+
+    routes(app);
+}
+
+/// Middleware that marks responses to unversioned paths as deprecated.
+#[derive(Debug, Default)]
+pub(super) struct Deprecated;
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for Deprecated {
+    async fn handle(&self, req: tide::Request<State>, next: tide::Next<'_, State>) -> tide::Result {
+        let path = req.url().path().to_string();
+        let mut response = next.run(req).await;
+
+        // `/` is the UI rather than an API endpoint.
+        if path != "/" && path != API_PREFIX && !path.starts_with(&format!("{}/", API_PREFIX)) {
+            response.insert_header("Deprecation", "true");
+            response.insert_header(
+                "Link",
+                format!("<{}{}>; rel=\"successor-version\"", API_PREFIX, path),
+            );
+        }
+        Ok(response)
+    }
+}
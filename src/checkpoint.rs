@@ -0,0 +1,237 @@
+// src/checkpoint.rs
+//! Content checkpoints, for re-reading files without ingesting their content twice.
+//!
+//! A [`Checkpoint`] records how many bytes of a file have been ingested, along with a hash of
+//! those bytes. When the file is read again, the same number of bytes are hashed: if the hash
+//! matches, the file has at most been appended to since, so reading can resume at the checkpoint.
+//! Otherwise the file has been replaced or rewritten, and must be read from the start.
+//!
+//! [`Checkpoints`] persists the checkpoints of many files as a JSON file, keyed by path (or by
+//! another identifier, like an inode number).
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read};
+use std::iter;
+use std::path::PathBuf;
+
+/// How far into a file has been ingested.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Checkpoint {
+    /// The number of bytes that have been ingested.
+    pub offset: u64,
+
+    /// The [`Hasher`] hash of the ingested bytes.
+    pub hash: u64,
+}
+
+impl Checkpoint {
+    /// Check whether `reader` starts with the content that was checkpointed.
+    ///
+    /// This consumes up to [`offset`](Self::offset) bytes from `reader`. If they match, the
+    /// [`Hasher`] to continue from is returned, and `reader` is positioned at the checkpoint.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when reading are returned.
+    pub fn verify(&self, reader: &mut impl Read) -> io::Result<Option<Hasher>> {
+        let mut hasher = Hasher::new();
+        let read = io::copy(&mut reader.by_ref().take(self.offset), &mut hasher)?;
+        Ok(if hasher.checkpoint(read) == *self {
+            Some(hasher)
+        } else {
+            None
+        })
+    }
+}
+
+/// An incremental (FNV-1a) hash of the content of a file.
+///
+/// `Hasher` implements [`io::Write`], so content that is skipped rather than read can be hashed
+/// with [`io::copy`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Hasher(u64);
+
+impl Hasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    /// A hasher for empty content.
+    #[must_use]
+    pub fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    /// Add `bytes` to the hashed content.
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// The checkpoint for the content hashed so far, which is `offset` bytes long.
+    #[must_use]
+    pub fn checkpoint(&self, offset: u64) -> Checkpoint {
+        Checkpoint {
+            offset,
+            hash: self.0,
+        }
+    }
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The checkpoints of many files, stored at a path.
+#[derive(Debug)]
+pub struct Checkpoints {
+    path: PathBuf,
+    checkpoints: BTreeMap<String, Checkpoint>,
+}
+
+impl Checkpoints {
+    /// Load the checkpoints stored at `path`, or none if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when reading `path` are returned. An error of kind
+    /// [`io::ErrorKind::InvalidData`] is returned if it can't be parsed.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let checkpoints = if path.exists() {
+            serde_json::from_slice(&fs::read(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Self { path, checkpoints })
+    }
+
+    /// The checkpoint of the file at `key`, if there is one.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<Checkpoint> {
+        self.checkpoints.get(key).copied()
+    }
+
+    /// Whether there are no checkpoints.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.checkpoints.is_empty()
+    }
+
+    /// Set the checkpoint of the file at `key`, and store the checkpoints.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when storing the checkpoints are returned.
+    pub fn set(&mut self, key: &str, checkpoint: Checkpoint) -> io::Result<()> {
+        self.set_all(iter::once((key.to_string(), checkpoint)))
+    }
+
+    /// Set the checkpoints of several files, by key, and store the checkpoints once.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when storing the checkpoints are returned.
+    pub fn set_all(
+        &mut self,
+        checkpoints: impl IntoIterator<Item = (String, Checkpoint)>,
+    ) -> io::Result<()> {
+        self.checkpoints.extend(checkpoints);
+        self.store()
+    }
+
+    /// Remove the checkpoints whose keys don't satisfy `keep`, and store the checkpoints if any
+    /// were removed.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when storing the checkpoints are returned.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) -> io::Result<()> {
+        let len = self.checkpoints.len();
+        self.checkpoints.retain(|key, _| keep(key));
+        if self.checkpoints.len() == len {
+            Ok(())
+        } else {
+            self.store()
+        }
+    }
+
+    fn store(&self) -> io::Result<()> {
+        // Write a temporary file and rename it, so a crash can't leave partial checkpoints.
+        let mut temporary_path = OsString::from(&self.path);
+        temporary_path.push(".tmp");
+        fs::write(&temporary_path, serde_json::to_vec(&self.checkpoints)?)?;
+        fs::rename(&temporary_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::test;
+
+    use super::{Checkpoints, Hasher};
+
+    #[test]
+    fn verify_checkpoints() -> test::Result {
+        let mut hasher = Hasher::new();
+        hasher.update(b"hello\n");
+        let checkpoint = hasher.checkpoint(6);
+
+        // Appended content resumes at the checkpoint.
+        let mut appended = Cursor::new(b"hello\nworld\n".to_vec());
+        let mut resumed = checkpoint.verify(&mut appended)?.expect("expected a match");
+        assert_eq!(appended.position(), 6);
+        resumed.update(b"world\n");
+        let mut expected = Hasher::new();
+        expected.update(b"hello\nworld\n");
+        assert_eq!(resumed, expected);
+
+        // Rewritten or truncated content doesn't.
+        assert!(checkpoint
+            .verify(&mut Cursor::new(b"howdy\nworld\n".to_vec()))?
+            .is_none());
+        assert!(checkpoint
+            .verify(&mut Cursor::new(b"hel".to_vec()))?
+            .is_none());
+
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("checkpoints");
+        let mut checkpoints = Checkpoints::load(&path)?;
+        assert_eq!(checkpoints.get("app.log"), None);
+        checkpoints.set("app.log", checkpoint)?;
+        assert_eq!(Checkpoints::load(&path)?.get("app.log"), Some(checkpoint));
+
+        checkpoints.set_all(vec![
+            ("other.log".to_string(), checkpoint),
+            ("app.log".to_string(), Hasher::new().checkpoint(0)),
+        ])?;
+        checkpoints.retain(|key| key != "other.log")?;
+        let checkpoints = Checkpoints::load(&path)?;
+        assert_eq!(
+            checkpoints.get("app.log"),
+            Some(Hasher::new().checkpoint(0))
+        );
+        assert_eq!(checkpoints.get("other.log"), None);
+        assert!(!checkpoints.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+// src/database/aggregate.rs
+//! Aggregation of events into compact series.
+//!
+//! Aggregations are computed from event timestamps alone, which are kept in the in-memory index,
+//! so no events need to be read from the log. Timestamps are treated as milliseconds.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io;
+
+use super::index::{self, Index};
+use super::{Labels, Query, Timestamp};
+
+/// An aggregation of matching events.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Aggregation {
+    /// The number of events in each `bucket`-long interval.
+    ///
+    /// Each point is timestamped with the start of its interval.
+    Count {
+        /// The length of each interval.
+        bucket: Timestamp,
+    },
+
+    /// The per-second rate of events over the `window` before the end of each `bucket`-long
+    /// interval.
+    ///
+    /// Each point is timestamped with the start of its interval.
+    Rate {
+        /// The length of each interval.
+        bucket: Timestamp,
+
+        /// The length of the window over which the rate is calculated.
+        window: Timestamp,
+    },
+}
+
+/// The aggregated values for a single stream.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Series {
+    /// The labels of the stream.
+    pub labels: Labels,
+
+    /// The aggregated values, in timestamp order. Intervals with a value of `0` are omitted.
+    pub points: Vec<Point>,
+}
+
+/// A single aggregated value.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+pub struct Point {
+    /// The start of the interval.
+    pub timestamp: Timestamp,
+
+    /// The aggregated value.
+    pub value: f64,
+}
+
+pub(super) fn aggregate(
+    index: &Index,
+    query: &Query,
+    aggregation: &Aggregation,
+) -> io::Result<Vec<Series>> {
+    let (bucket, window) = match *aggregation {
+        Aggregation::Count { bucket } => (bucket, bucket),
+        Aggregation::Rate { bucket, window } => (bucket, window),
+    };
+    if bucket == 0 || window == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "aggregation bucket and window must be greater than 0",
+        ));
+    }
+
+    let mut series: Vec<_> = index
+        .iter()
+        .filter(|(labels, _)| query.matches_stream(labels))
+        .filter_map(|(labels, records)| {
+            let mut timestamps: Vec<_> = records
+                .iter()
+                .map(|(timestamp, _)| *timestamp)
+                .filter(|timestamp| query.matches_timestamp(*timestamp))
+                .collect();
+            if timestamps.is_empty() {
+                return None;
+            }
+            timestamps.sort_unstable();
+
+            let points = match *aggregation {
+                Aggregation::Count { bucket } => count(&timestamps, bucket),
+                Aggregation::Rate { bucket, window } => rate(&timestamps, bucket, window),
+            };
+            Some(Series {
+                labels: index::to_labels(labels),
+                points,
+            })
+        })
+        .collect();
+
+    series.sort_by(|a, b| a.labels.cmp(&b.labels));
+    Ok(series)
+}
+
+/// Count the sorted `timestamps` in each `bucket`.
+#[allow(clippy::cast_precision_loss)]
+fn count(timestamps: &[Timestamp], bucket: Timestamp) -> Vec<Point> {
+    let mut counts = BTreeMap::<_, u64>::new();
+    for timestamp in timestamps {
+        *counts.entry(timestamp - timestamp % bucket).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .map(|(timestamp, count)| Point {
+            timestamp,
+            value: count as f64,
+        })
+        .collect()
+}
+
+/// Calculate the per-second rate of the sorted `timestamps` over `window` before the end of each
+/// `bucket`.
+#[allow(clippy::cast_precision_loss)]
+fn rate(timestamps: &[Timestamp], bucket: Timestamp, window: Timestamp) -> Vec<Point> {
+    let first = timestamps[0] - timestamps[0] % bucket;
+    let last = timestamps[timestamps.len() - 1].saturating_add(window);
+
+    let mut points = Vec::new();
+    let mut start = first;
+    while start <= last {
+        let end = start.saturating_add(bucket);
+        let count =
+            lower_bound(timestamps, end) - lower_bound(timestamps, end.saturating_sub(window));
+        if count > 0 {
+            points.push(Point {
+                timestamp: start,
+                value: count as f64 / (window as f64 / 1000.0),
+            });
+        }
+        start = end;
+    }
+    points
+}
+
+/// The index of the first of the sorted `timestamps` that is at least `timestamp`.
+fn lower_bound(timestamps: &[Timestamp], timestamp: Timestamp) -> usize {
+    timestamps
+        .binary_search_by(|probe| {
+            if *probe < timestamp {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        })
+        .unwrap_err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count, rate, Point};
+
+    #[test]
+    fn count_buckets() {
+        assert_eq!(
+            count(&[0, 999, 1000, 3500], 1000),
+            vec![
+                Point {
+                    timestamp: 0,
+                    value: 2.0
+                },
+                Point {
+                    timestamp: 1000,
+                    value: 1.0
+                },
+                Point {
+                    timestamp: 3000,
+                    value: 1.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rate_windows() {
+        // Two events in each of the two windows ending at 2000 and 3000.
+        assert_eq!(
+            rate(&[500, 1500, 2500], 1000, 2000),
+            vec![
+                Point {
+                    timestamp: 0,
+                    value: 0.5
+                },
+                Point {
+                    timestamp: 1000,
+                    value: 1.0
+                },
+                Point {
+                    timestamp: 2000,
+                    value: 1.0
+                },
+                Point {
+                    timestamp: 3000,
+                    value: 0.5
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,202 @@
+// src/database/engine.rs
+//! Interchangeable storage engines for events.
+
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::{Database, Event, Labels, OpenError, PushError, Query, QueryError};
+
+/// The operations every storage engine supports.
+///
+/// This is the subset of [`Database`]'s interface that alternative engines can reasonably
+/// implement, so that engines can be compared (e.g. by `loadgen`) and swapped via [`Engine`].
+pub trait StorageEngine {
+    /// Open the engine's data at `path`, creating it if it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// An [`OpenError`] is returned if the data at `path` can't be opened or restored.
+    fn open(path: &Path) -> Result<Self, OpenError>
+    where
+        Self: Sized;
+
+    /// Push a new `event` into the stream identified by `labels`.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when writing the event are returned.
+    fn push(&self, labels: &Labels, event: Event) -> Result<(), PushError>;
+
+    /// Push several `events` into the stream identified by `labels`, in order.
+    ///
+    /// By default this pushes each event in turn, but engines may write the batch more
+    /// efficiently.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when writing the events are returned.
+    fn push_batch(&self, labels: &Labels, events: Vec<Event>) -> Result<(), PushError> {
+        events
+            .into_iter()
+            .try_for_each(|event| self.push(labels, event))
+    }
+
+    /// Find events matching the given `query`, in the order they were pushed.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when running the query are returned.
+    fn query(&self, query: &Query) -> Result<Vec<Event>, QueryError>;
+
+    /// Call `visit` with every stored event and its stream's labels, in the order they were
+    /// pushed.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when reading events, or returned by `visit`, are returned.
+    fn scan(&self, visit: &mut dyn FnMut(Labels, Event) -> io::Result<()>) -> io::Result<()>;
+
+    /// Make sure all pushed events are persisted.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when persisting events are returned.
+    fn flush(&self) -> io::Result<()>;
+}
+
+impl StorageEngine for Database {
+    fn open(path: &Path) -> Result<Self, OpenError> {
+        Database::open(path)
+    }
+
+    fn push(&self, labels: &Labels, event: Event) -> Result<(), PushError> {
+        Database::push(self, labels, event)
+    }
+
+    fn push_batch(&self, labels: &Labels, events: Vec<Event>) -> Result<(), PushError> {
+        self.stream(labels).push_batch(events)
+    }
+
+    fn query(&self, query: &Query) -> Result<Vec<Event>, QueryError> {
+        Database::query(self, query)
+    }
+
+    fn scan(&self, visit: &mut dyn FnMut(Labels, Event) -> io::Result<()>) -> io::Result<()> {
+        Database::scan(self, visit)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Database::flush(self)
+    }
+}
+
+/// A choice of [`StorageEngine`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Engine {
+    /// The append-only JSON log implemented by [`Database`].
+    Json,
+
+    /// A B-tree stored with [`sanakirja`], implemented by
+    /// [`SanakirjaDatabase`](super::SanakirjaDatabase).
+    #[cfg(feature = "sanakirja")]
+    Sanakirja,
+}
+
+impl Engine {
+    /// Open the chosen engine's data at `path`.
+    ///
+    /// # Errors
+    ///
+    /// See [`StorageEngine::open`].
+    pub fn open(self, path: impl AsRef<Path>) -> Result<Box<dyn StorageEngine>, OpenError> {
+        let path = path.as_ref();
+        match self {
+            Self::Json => Ok(Box::new(Database::open(path)?)),
+            #[cfg(feature = "sanakirja")]
+            Self::Sanakirja => Ok(Box::new(<super::SanakirjaDatabase as StorageEngine>::open(
+                path,
+            )?)),
+        }
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "json" => Ok(Self::Json),
+            #[cfg(feature = "sanakirja")]
+            "sanakirja" => Ok(Self::Sanakirja),
+            _ => Err(format!("unrecognised storage engine `{}`", input)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::test;
+
+    use super::super::{Event, Query};
+    use super::Engine;
+
+    #[test]
+    fn json_engine() -> test::Result {
+        check_engine(Engine::Json)
+    }
+
+    #[cfg(feature = "sanakirja")]
+    #[test]
+    fn sanakirja_engine() -> test::Result {
+        check_engine(Engine::Sanakirja)
+    }
+
+    fn check_engine(engine: Engine) -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("data");
+
+        let mut labels = BTreeMap::new();
+        labels.insert("l1".to_string(), "v1".to_string());
+        let mut other_labels = BTreeMap::new();
+        other_labels.insert("l1".to_string(), "v2".to_string());
+
+        {
+            let db = engine.open(&path)?;
+            db.push(&labels, Event::new(0, b"e1".to_vec()))?;
+            db.push(&other_labels, Event::new(1, b"e2".to_vec()))?;
+            db.push(&labels, Event::new(2, b"e3".to_vec()))?;
+            db.flush()?;
+        }
+
+        let db = engine.open(&path)?;
+        db.push_batch(
+            &labels,
+            vec![Event::new(3, b"e4".to_vec()), Event::new(4, b"e5".to_vec())],
+        )?;
+
+        let query = Query::Range {
+            matchers: labels,
+            start: 1,
+            end: 5,
+        };
+        assert_eq!(
+            db.query(&query)?,
+            vec![
+                Event::new(2, b"e3".to_vec()),
+                Event::new(3, b"e4".to_vec()),
+                Event::new(4, b"e5".to_vec())
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+// src/database/gaps.rs
+//! Detection of gaps in streams, i.e. periods without any events.
+//!
+//! Gaps are computed from event timestamps in the in-memory index, and from [`Rollup`]s for events
+//! that have been rolled up, so no events need to be read from the log. A roll-up only records its
+//! first and last events, so the time between them is treated as covered.
+//!
+//! A gap in one stream may just mean the application was quiet. Comparing the gaps of several
+//! streams from the same collector, or with the collector's own event history, helps distinguish
+//! that from collection being broken.
+//!
+//! [`Rollup`]: super::Rollup
+
+use std::collections::BTreeMap;
+use std::io;
+
+use super::index::{self, Index};
+use super::{Labels, Query, RollupSeries, Timestamp};
+
+/// A period in which a stream had no events.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Serialize)]
+pub struct Gap {
+    /// The timestamp of the last event before the gap, or the start of the query's range.
+    pub start: Timestamp,
+
+    /// The timestamp of the first event after the gap, or the end of the query's range.
+    pub end: Timestamp,
+}
+
+/// The gaps found in a single stream.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
+pub struct GapReport {
+    /// The labels of the stream.
+    pub labels: Labels,
+
+    /// The stream's gaps, in timestamp order.
+    pub gaps: Vec<Gap>,
+}
+
+pub(super) fn gaps(
+    index: &Index,
+    rollups: Vec<RollupSeries>,
+    query: &Query,
+    threshold: Timestamp,
+) -> io::Result<Vec<GapReport>> {
+    if threshold == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "gap threshold must be greater than 0",
+        ));
+    }
+
+    // The spans of time known to be covered by each stream's events, as `(first, last)`.
+    let mut streams = BTreeMap::<Labels, Vec<(Timestamp, Timestamp)>>::new();
+    for (labels, records) in index.iter() {
+        if query.matches_stream(labels) {
+            streams.entry(index::to_labels(labels)).or_default().extend(
+                records
+                    .iter()
+                    .map(|(timestamp, _)| (*timestamp, *timestamp))
+                    .filter(|(timestamp, _)| query.matches_timestamp(*timestamp)),
+            );
+        }
+    }
+    for series in rollups {
+        streams.entry(series.labels).or_default().extend(
+            series
+                .rollups
+                .iter()
+                .map(|rollup| (rollup.first.timestamp(), rollup.last.timestamp())),
+        );
+    }
+
+    Ok(streams
+        .into_iter()
+        .map(|(labels, mut spans)| {
+            spans.sort_unstable();
+            GapReport {
+                labels,
+                gaps: find_gaps(&spans, query, threshold),
+            }
+        })
+        .collect())
+}
+
+/// Find the gaps of at least `threshold` between the sorted `spans`.
+///
+/// For range queries, the gaps between the start of the range and the first span, and between the
+/// last span and the end of the range, are included. Otherwise only gaps between spans are found.
+fn find_gaps(spans: &[(Timestamp, Timestamp)], query: &Query, threshold: Timestamp) -> Vec<Gap> {
+    let (mut covered_until, end) = match query {
+        Query::Range { start, end, .. } => (Some(*start), Some(*end)),
+        Query::Label { .. } | Query::Matchers(_) => (None, None),
+    };
+
+    let mut gaps = Vec::new();
+    let mut push = |start: Timestamp, end: Timestamp| {
+        if end.saturating_sub(start) >= threshold {
+            gaps.push(Gap { start, end });
+        }
+    };
+    for (first, last) in spans {
+        if let Some(covered_until) = covered_until {
+            push(covered_until, *first);
+        }
+        covered_until = Some(covered_until.map_or(*last, |covered| covered.max(*last)));
+    }
+    if let (Some(covered_until), Some(end)) = (covered_until, end) {
+        push(covered_until, end);
+    }
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_gaps, Gap, Query};
+
+    #[test]
+    fn find_gaps_in_spans() {
+        let range = Query::Range {
+            matchers: Default::default(),
+            start: 0,
+            end: 10_000,
+        };
+        let spans = [
+            (500, 500),
+            (1000, 1000),
+            (2000, 4000),
+            (2500, 2500),
+            (6000, 6000),
+        ];
+
+        assert_eq!(
+            find_gaps(&spans, &range, 1000),
+            vec![
+                Gap {
+                    start: 1000,
+                    end: 2000
+                },
+                Gap {
+                    start: 4000,
+                    end: 6000
+                },
+                Gap {
+                    start: 6000,
+                    end: 10_000
+                },
+            ]
+        );
+        assert_eq!(
+            find_gaps(&spans, &Query::Matchers(Vec::new()), 1500),
+            vec![Gap {
+                start: 4000,
+                end: 6000
+            }]
+        );
+        assert_eq!(
+            find_gaps(&[], &range, 1000),
+            vec![Gap {
+                start: 0,
+                end: 10_000
+            }]
+        );
+        assert_eq!(find_gaps(&[], &Query::Matchers(Vec::new()), 1), vec![]);
+    }
+}
@@ -0,0 +1,79 @@
+// src/database/horizon.rs
+//! Retention horizons of the event [`Database`](super::Database).
+//!
+//! [`prune`](super::Database::prune) removes events for good, so without a record of it a query
+//! reaching back past the removed events would silently return partial results. The horizon of a
+//! stream is the latest timestamp of any event pruned from it: the stream's events at or before
+//! its horizon may have been removed. Horizons are persisted alongside the log, and queries whose
+//! time range reaches a matched stream's horizon are given a [`RetentionWarning`].
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{Labels, Timestamp};
+
+/// A warning that a query's time range extends past the events retained for a stream.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
+pub struct RetentionWarning {
+    /// The labels of the stream.
+    pub labels: Labels,
+
+    /// The latest timestamp of an event pruned from the stream.
+    pub pruned_through: Timestamp,
+
+    /// The earliest timestamp of an event still in the stream, if any remain.
+    pub earliest_available: Option<Timestamp>,
+}
+
+/// The horizons of a database's streams.
+#[derive(Debug, Default)]
+pub(super) struct Horizons {
+    horizons: BTreeMap<Labels, Timestamp>,
+}
+
+impl Horizons {
+    /// Load the horizons of the database with its log at `log_path`.
+    pub(super) fn load(log_path: &Path) -> io::Result<Self> {
+        let path = horizons_path(log_path);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let horizons: Vec<(Labels, Timestamp)> = serde_json::from_slice(&fs::read(path)?)?;
+        Ok(Self {
+            horizons: horizons.into_iter().collect(),
+        })
+    }
+
+    pub(super) fn iter(&self) -> impl Iterator<Item = (&Labels, Timestamp)> {
+        self.horizons
+            .iter()
+            .map(|(labels, horizon)| (labels, *horizon))
+    }
+
+    /// Advance the horizons of streams from which events have been `pruned`.
+    pub(super) fn advance(
+        &mut self,
+        log_path: &Path,
+        pruned: impl IntoIterator<Item = (Labels, Timestamp)>,
+    ) -> io::Result<()> {
+        for (labels, timestamp) in pruned {
+            let horizon = self.horizons.entry(labels).or_insert(timestamp);
+            *horizon = (*horizon).max(timestamp);
+        }
+        self.save(log_path)
+    }
+
+    fn save(&self, log_path: &Path) -> io::Result<()> {
+        let horizons: Vec<_> = self.horizons.iter().collect();
+        fs::write(horizons_path(log_path), serde_json::to_vec(&horizons)?)
+    }
+}
+
+fn horizons_path(log_path: &Path) -> PathBuf {
+    let mut path = OsString::from(log_path);
+    path.push(".horizons");
+    PathBuf::from(path)
+}
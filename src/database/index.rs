@@ -0,0 +1,174 @@
+// src/database/index.rs
+//! The in-memory index of each stream's records.
+//!
+//! Kubernetes labels are highly repetitive: many streams share label names and most of their
+//! values (namespaces, app names, etc.). The index therefore interns label names and values, so
+//! each distinct string is stored once and shared between streams with [`Arc`]s. Streams are also
+//! looked up by a hash of their labels, so pushing to an existing stream doesn't need to allocate
+//! at all.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::Arc;
+
+use super::{Labels, Timestamp};
+
+/// A stream's labels, with names and values shared with other streams.
+pub(super) type InternedLabels = BTreeMap<Arc<str>, Arc<str>>;
+
+/// The timestamps and offsets of each stream's records in the log, in the order they were written.
+#[derive(Debug, Default)]
+pub(super) struct Index {
+    strings: HashSet<Arc<str>>,
+    streams: Vec<Stream>,
+
+    /// The IDs (positions in `streams`) of the streams with each hash of labels.
+    ids: HashMap<u64, Vec<usize>>,
+    hasher: RandomState,
+}
+
+#[derive(Debug)]
+struct Stream {
+    labels: InternedLabels,
+    records: Vec<(Timestamp, u64)>,
+}
+
+impl Index {
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a record at `offset` to the stream identified by `labels`, creating it if necessary.
+    ///
+    /// Returns the ID of the stream, which can be used with [`push_to`](Self::push_to) until the
+    /// index is rebuilt.
+    pub(super) fn push(&mut self, labels: &Labels, timestamp: Timestamp, offset: u64) -> usize {
+        let hash = self.hash(labels);
+        let streams = &self.streams;
+        let existing = self.ids.get(&hash).and_then(|ids| {
+            ids.iter()
+                .copied()
+                .find(|id| same_labels(&streams[*id].labels, labels))
+        });
+
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                let labels = labels
+                    .iter()
+                    .map(|(name, value)| (self.intern(name), self.intern(value)))
+                    .collect();
+                self.streams.push(Stream {
+                    labels,
+                    records: Vec::new(),
+                });
+                let id = self.streams.len() - 1;
+                self.ids.entry(hash).or_default().push(id);
+                id
+            }
+        };
+        self.push_to(id, timestamp, offset);
+        id
+    }
+
+    /// Add a record at `offset` to the stream with the given `id`.
+    pub(super) fn push_to(&mut self, id: usize, timestamp: Timestamp, offset: u64) {
+        self.streams[id].records.push((timestamp, offset));
+    }
+
+    /// The records of the stream identified by `labels`, if it exists.
+    pub(super) fn get(&self, labels: &Labels) -> Option<&[(Timestamp, u64)]> {
+        let streams = &self.streams;
+        self.ids.get(&self.hash(labels))?.iter().find_map(|id| {
+            let stream = &streams[*id];
+            if same_labels(&stream.labels, labels) {
+                Some(stream.records.as_slice())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Iterate over each stream's labels and records.
+    pub(super) fn iter(&self) -> impl Iterator<Item = (&InternedLabels, &[(Timestamp, u64)])> {
+        self.streams
+            .iter()
+            .map(|stream| (&stream.labels, stream.records.as_slice()))
+    }
+
+    /// Iterate over each stream's records.
+    pub(super) fn records(&self) -> impl Iterator<Item = &[(Timestamp, u64)]> {
+        self.streams.iter().map(|stream| stream.records.as_slice())
+    }
+
+    fn intern(&mut self, string: &str) -> Arc<str> {
+        if let Some(interned) = self.strings.get(string) {
+            return Arc::clone(interned);
+        }
+        let interned: Arc<str> = Arc::from(string);
+        self.strings.insert(Arc::clone(&interned));
+        interned
+    }
+
+    /// Hash `labels` so that equal [`Labels`] and [`InternedLabels`] hash equally.
+    fn hash(&self, labels: &Labels) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        for (name, value) in labels {
+            name.as_str().hash(&mut hasher);
+            value.as_str().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+}
+
+/// Convert interned labels back into [`Labels`].
+pub(super) fn to_labels(labels: &InternedLabels) -> Labels {
+    labels
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn same_labels(interned: &InternedLabels, labels: &Labels) -> bool {
+    interned.len() == labels.len()
+        && interned
+            .iter()
+            .zip(labels)
+            .all(|((a_name, a_value), (b_name, b_value))| {
+                **a_name == **b_name && **a_value == **b_value
+            })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use super::{to_labels, Index};
+
+    #[test]
+    fn shares_strings() {
+        let labels = |pod: &str| -> BTreeMap<String, String> {
+            vec![("namespace", "payments"), ("pod", pod)]
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let mut index = Index::new();
+        index.push(&labels("a"), 0, 0);
+        index.push(&labels("b"), 1, 10);
+        index.push(&labels("a"), 2, 20);
+
+        let streams: Vec<_> = index.iter().collect();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(to_labels(streams[0].0), labels("a"));
+        assert_eq!(streams[0].1, &[(0, 0), (2, 20)][..]);
+        assert_eq!(to_labels(streams[1].0), labels("b"));
+        assert_eq!(streams[1].1, &[(1, 10)][..]);
+
+        let namespace = |id: usize| Arc::clone(&streams[id].0["namespace"]);
+        assert!(Arc::ptr_eq(&namespace(0), &namespace(1)));
+    }
+}
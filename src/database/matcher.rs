@@ -0,0 +1,140 @@
+// src/database/matcher.rs
+//! Label matchers, for selecting streams in a [`Query`](super::Query).
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// A condition on the labels of a stream.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Matcher {
+    /// The stream has the label `name` with the given `value`.
+    Equal {
+        /// The label name to match.
+        name: String,
+
+        /// The label value to match.
+        value: String,
+    },
+
+    /// The stream does not have the label `name` with the given `value`.
+    ///
+    /// This includes streams without the label at all.
+    NotEqual {
+        /// The label name to match.
+        name: String,
+
+        /// The label value that must not match.
+        value: String,
+    },
+
+    /// The stream has the label `name`, with any value.
+    Present {
+        /// The label name to match.
+        name: String,
+    },
+
+    /// The stream does not have the label `name`.
+    Absent {
+        /// The label name to match.
+        name: String,
+    },
+}
+
+impl Matcher {
+    /// Check whether a stream with the given `labels` satisfies the matcher.
+    ///
+    /// `labels` is usually [`Labels`](super::Labels), but any map of string-like names and values
+    /// can be used.
+    #[must_use]
+    pub fn matches<K, V>(&self, labels: &BTreeMap<K, V>) -> bool
+    where
+        K: Borrow<str> + Ord,
+        V: AsRef<str>,
+    {
+        let get = |name: &str| labels.get(name).map(AsRef::as_ref);
+        match self {
+            Self::Equal { name, value } => get(name) == Some(value.as_str()),
+            Self::NotEqual { name, value } => get(name) != Some(value.as_str()),
+            Self::Present { name } => labels.contains_key(name.as_str()),
+            Self::Absent { name } => !labels.contains_key(name.as_str()),
+        }
+    }
+}
+
+/// Matchers can be parsed from strings like `name=value`, `name!=value`, `name` (present), and
+/// `!name` (absent).
+impl FromStr for Matcher {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (name, matcher) = if let Some(index) = input.find("!=") {
+            let name = &input[..index];
+            let value = input[index + 2..].to_string();
+            (
+                name,
+                Self::NotEqual {
+                    name: name.to_string(),
+                    value,
+                },
+            )
+        } else if let Some(index) = input.find('=') {
+            let name = &input[..index];
+            let value = input[index + 1..].to_string();
+            (
+                name,
+                Self::Equal {
+                    name: name.to_string(),
+                    value,
+                },
+            )
+        } else if let Some(name) = input.strip_prefix('!') {
+            (
+                name,
+                Self::Absent {
+                    name: name.to_string(),
+                },
+            )
+        } else {
+            (
+                input,
+                Self::Present {
+                    name: input.to_string(),
+                },
+            )
+        };
+
+        if name.is_empty() {
+            return Err(format!("invalid matcher `{}`: missing label name", input));
+        }
+        Ok(matcher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::Matcher;
+
+    #[test]
+    fn parse_and_match() {
+        let mut labels = BTreeMap::new();
+        labels.insert("app".to_string(), "api".to_string());
+
+        let matches = |matcher: &str| matcher.parse::<Matcher>().unwrap().matches(&labels);
+        assert!(matches("app=api"));
+        assert!(!matches("app=web"));
+        assert!(matches("app!=web"));
+        assert!(!matches("app!=api"));
+        assert!(matches("env!=prod"));
+        assert!(matches("app"));
+        assert!(!matches("env"));
+        assert!(matches("!env"));
+        assert!(!matches("!app"));
+        assert!(matches("app="));
+
+        assert!("=api".parse::<Matcher>().is_err());
+        assert!("!".parse::<Matcher>().is_err());
+    }
+}
@@ -0,0 +1,172 @@
+// src/database/migrate.rs
+//! Migration of events between storage engines.
+//!
+//! [`migrate`] copies every event from one [`StorageEngine`] to another, in the order they were
+//! pushed. It's resumable: events already in the destination are assumed to be the first events
+//! of the source (i.e. from an interrupted migration) and are skipped. Once copied, the
+//! destination is verified by comparing a digest of both engines' contents.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+
+use super::{Event, Labels, StorageEngine};
+
+/// How many events to copy between flushes of the destination (and progress reports).
+const BATCH_SIZE: u64 = 10_000;
+
+/// The outcome of a [`migrate`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MigrationReport {
+    /// The number of events in the source.
+    pub total: u64,
+
+    /// The number of events skipped because they were already in the destination.
+    pub skipped: u64,
+
+    /// The number of events copied.
+    pub copied: u64,
+}
+
+/// The progress of a [`migrate`], reported after each batch of events.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Progress {
+    /// The number of events processed so far (skipped or copied).
+    pub processed: u64,
+
+    /// The number of events in the source.
+    pub total: u64,
+}
+
+/// Copy every event from `source` to `destination`, calling `progress` periodically.
+///
+/// # Errors
+///
+/// - Any [`io::Error`]s encountered when reading from `source` or writing to `destination` are
+///   returned. The migration can be resumed by calling `migrate` again.
+/// - If the destination doesn't match the source afterwards, an error of kind
+///   [`io::ErrorKind::InvalidData`] is returned. This can happen if the destination contained
+///   events that weren't from the source, or if the source changed during the migration.
+pub fn migrate(
+    source: &dyn StorageEngine,
+    destination: &dyn StorageEngine,
+    progress: &mut dyn FnMut(Progress),
+) -> io::Result<MigrationReport> {
+    let total = Digest::of(source)?.count;
+    let skipped = Digest::of(destination)?.count;
+    if skipped > total {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "destination has more events ({}) than source ({})",
+                skipped, total
+            ),
+        ));
+    }
+
+    let mut processed = 0;
+    source.scan(&mut |labels, event| {
+        processed += 1;
+        if processed > skipped {
+            destination.push(&labels, event)?;
+        }
+        if processed % BATCH_SIZE == 0 {
+            destination.flush()?;
+            progress(Progress { processed, total });
+        }
+        Ok(())
+    })?;
+    destination.flush()?;
+    progress(Progress { processed, total });
+
+    if Digest::of(source)? != Digest::of(destination)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "destination does not match source after migration",
+        ));
+    }
+
+    Ok(MigrationReport {
+        total,
+        skipped,
+        copied: processed - skipped,
+    })
+}
+
+/// A summary of an engine's contents, for verification.
+#[derive(Debug, Eq, PartialEq)]
+struct Digest {
+    count: u64,
+    hash: u64,
+}
+
+impl Digest {
+    fn of(engine: &dyn StorageEngine) -> io::Result<Self> {
+        let mut count = 0;
+        let mut hasher = DefaultHasher::new();
+        engine.scan(&mut |labels: Labels, event: Event| {
+            count += 1;
+            labels.hash(&mut hasher);
+            event.hash(&mut hasher);
+            Ok(())
+        })?;
+        Ok(Self {
+            count,
+            hash: hasher.finish(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::test;
+
+    use super::super::{Database, Event, Query};
+    use super::{migrate, MigrationReport};
+
+    #[test]
+    fn resume_and_verify() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let source = Database::open(tempdir.path().join("source"))?;
+        let destination = Database::open(tempdir.path().join("destination"))?;
+
+        let mut labels = BTreeMap::new();
+        labels.insert("l1".to_string(), "v1".to_string());
+        for timestamp in 0..5 {
+            source.push(&labels, Event::new(timestamp, b"e".to_vec()))?;
+        }
+
+        // Simulate an interrupted migration.
+        destination.push(&labels, Event::new(0, b"e".to_vec()))?;
+        destination.push(&labels, Event::new(1, b"e".to_vec()))?;
+
+        let mut reports = Vec::new();
+        let report = migrate(&source, &destination, &mut |progress| {
+            reports.push(progress)
+        })?;
+        assert_eq!(
+            report,
+            MigrationReport {
+                total: 5,
+                skipped: 2,
+                copied: 3,
+            }
+        );
+        assert_eq!(reports.last().map(|progress| progress.processed), Some(5));
+
+        let query = Query::Label {
+            name: "l1".to_string(),
+            value: "v1".to_string(),
+        };
+        assert_eq!(destination.query(&query)?, source.query(&query)?);
+
+        // A destination with different events fails verification.
+        let other = Database::open(tempdir.path().join("other"))?;
+        other.push(&labels, Event::new(9, b"x".to_vec()))?;
+        assert!(migrate(&source, &other, &mut |_| {}).is_err());
+
+        Ok(())
+    }
+}
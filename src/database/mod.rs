@@ -1,16 +1,85 @@
 // src/database/mod.rs
 //! A time-series-esque database for storing and querying append-only streams of events.
 
-use std::cell::RefCell;
-use std::collections::BTreeMap;
-use std::fs::{self, File};
+mod aggregate;
+mod engine;
+mod gaps;
+mod horizon;
+mod index;
+mod matcher;
+pub mod migrate;
+mod retention;
+mod rollup;
+#[cfg(feature = "sanakirja")]
+mod sanakirja_engine;
+mod shadow;
+mod snapshot;
+mod standby;
+mod stream;
+mod wal;
+
+use std::borrow::Borrow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
+use log::warn;
+
+use crate::checkpoint::{Checkpoints, Hasher};
+use crate::record::{Record, Timestamp};
+
+pub use self::aggregate::{Aggregation, Point, Series};
+pub use self::engine::{Engine, StorageEngine};
+pub use self::gaps::{Gap, GapReport};
+pub use self::horizon::RetentionWarning;
+pub use self::matcher::Matcher;
+pub use self::retention::Retention;
+pub use self::rollup::{Rollup, RollupRule, RollupSeries};
+#[cfg(feature = "sanakirja")]
+pub use self::sanakirja_engine::SanakirjaDatabase;
+pub use self::shadow::{Shadow, ShadowReport};
+pub use self::snapshot::SnapshotId;
+pub use self::standby::Standby;
+pub use self::stream::StreamHandle;
+pub use self::wal::WalConfig;
+
+use self::index::Index;
+
+/// The maximum number of consecutive events in one stream that are pushed together when merging.
+const MERGE_BATCH_SIZE: usize = 1024;
+
 /// A time-series-esque database for storing and querying append-only stream of events.
-#[derive(serde::Deserialize, serde::Serialize)]
+///
+/// Events are stored in an append-only log file, one JSON record per line. Only an index of each
+/// stream's record offsets is kept in memory, and matching records are read back from the log when
+/// querying.
+///
+/// Writes to the log are buffered, but each event is also appended to a write-ahead log, which is
+/// synced to disk shortly after the event is pushed (see [`WalConfig`]) and replayed when the
+/// database is next opened. Events are guaranteed to be persisted once [`flush`](Self::flush)
+/// or [`close`](Self::close) has returned successfully. Dropping the database also flushes it, but
+/// any error is only logged.
 pub struct Database {
     path: PathBuf,
-    events: RefCell<Vec<(Labels, Event)>>,
+
+    index: RefCell<Index>,
+
+    /// Incremented whenever the index is rebuilt, invalidating [`StreamHandle`]s' stream IDs.
+    generation: Cell<u64>,
+
+    /// The length of the log, including buffered writes.
+    len: Cell<u64>,
+
+    snapshots: RefCell<snapshot::Snapshots>,
+    rollups: RefCell<rollup::Rollups>,
+    horizons: RefCell<horizon::Horizons>,
+
+    wal: RefCell<wal::Wal>,
+    writer: RefCell<BufWriter<File>>,
+    reader: RefCell<BufReader<File>>,
 }
 
 /// A structure describing database queries.
@@ -23,6 +92,60 @@ pub enum Query {
         /// The label value to match.
         value: String,
     },
+
+    /// A query that will find events in a time range from streams matching all of the given
+    /// labels.
+    Range {
+        /// The labels that a stream must have. If empty, every stream matches.
+        matchers: Labels,
+
+        /// The earliest timestamp to include.
+        start: Timestamp,
+
+        /// The timestamp at which to stop (exclusive).
+        end: Timestamp,
+    },
+
+    /// A query that will find events from streams satisfying all of the given matchers.
+    ///
+    /// Unlike the other queries, this can select streams by the absence of labels or values. If
+    /// `matchers` is empty, every stream matches.
+    Matchers(Vec<Matcher>),
+}
+
+impl Query {
+    /// Check whether events from a stream with the given `labels` may match the query.
+    ///
+    /// This accepts any map of labels, so that it can be used with the index's interned labels.
+    fn matches_stream<K, V>(&self, labels: &BTreeMap<K, V>) -> bool
+    where
+        K: Borrow<str> + Ord,
+        V: AsRef<str>,
+    {
+        match self {
+            Query::Label { name, value } => Self::matches_equal(labels, name, value),
+            Query::Range { matchers, .. } => matchers
+                .iter()
+                .all(|(name, value)| Self::matches_equal(labels, name, value)),
+            Query::Matchers(matchers) => matchers.iter().all(|matcher| matcher.matches(labels)),
+        }
+    }
+
+    fn matches_equal<K, V>(labels: &BTreeMap<K, V>, name: &str, value: &str) -> bool
+    where
+        K: Borrow<str> + Ord,
+        V: AsRef<str>,
+    {
+        labels.get(name).map(AsRef::as_ref) == Some(value)
+    }
+
+    /// Check whether events with the given `timestamp` (from a matching stream) match the query.
+    fn matches_timestamp(&self, timestamp: Timestamp) -> bool {
+        match self {
+            Query::Label { .. } | Query::Matchers(_) => true,
+            Query::Range { start, end, .. } => *start <= timestamp && timestamp < *end,
+        }
+    }
 }
 
 /// Labels used to identify a stream.
@@ -30,32 +153,123 @@ pub enum Query {
 /// For now this is just a type alias, but our requirements may diverge from `BTreeMap` in future.
 pub type Labels = BTreeMap<String, String>;
 
-/// The type used for timestamps.
-///
-/// `u64` gives us ~585 million years at millisecond resolution. This is obviously more than we
-/// need, but `u32` only gives us 50 days which is obviously too few!
-///
-/// This is not public. The alias just exists to make changing the timestamp type easier.
-type Timestamp = u64;
+/// The parts of a record needed to index it.
+#[derive(serde::Deserialize)]
+struct IndexEntry {
+    timestamp: Timestamp,
+}
 
 /// An event that can be stored by [`Database`].
-#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct Event {
     timestamp: Timestamp,
     data: Vec<u8>,
+
+    // Events logged before values were introduced have no `value` field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<Value>,
 }
 
 impl Event {
     /// Construct a new [`Event`] with a `timestamp` and some `data`.
     #[must_use]
     pub fn new(timestamp: Timestamp, data: Vec<u8>) -> Self {
-        Event { timestamp, data }
+        Event {
+            timestamp,
+            data,
+            value: None,
+        }
+    }
+
+    /// Construct a new [`Event`] with a `timestamp` and a numeric `value`, and no data.
+    #[must_use]
+    pub fn sample(timestamp: Timestamp, value: Value) -> Self {
+        Event::new(timestamp, Vec::new()).with_value(value)
+    }
+
+    /// Attach a numeric `value` to the event, alongside its data.
+    #[must_use]
+    pub fn with_value(mut self, value: Value) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    /// The event's timestamp.
+    #[must_use]
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    /// The event's data.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The event's numeric value, if it has one.
+    #[must_use]
+    pub fn value(&self) -> Option<Value> {
+        self.value
+    }
+
+    /// Consume the event, returning its data.
+    #[must_use]
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// A numeric sample carried by an [`Event`].
+///
+/// Values are compared and hashed by their bit patterns, so that events remain [`Eq`] and
+/// [`Hash`]. In particular, a `NaN` value is equal to an identical `NaN`.
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Value {
+    /// A measurement that can go up or down, e.g. a queue length.
+    Gauge(f64),
+
+    /// A cumulative measurement that only increases, except when reset to zero.
+    Counter(f64),
+}
+
+impl Value {
+    /// The numeric value of the sample.
+    #[must_use]
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Self::Gauge(value) | Self::Counter(value) => value,
+        }
+    }
+
+    fn key(self) -> (u8, u64) {
+        match self {
+            Self::Gauge(value) => (0, value.to_bits()),
+            Self::Counter(value) => (1, value.to_bits()),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Value {}
+
+impl std::hash::Hash for Value {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
     }
 }
 
 /// Possible error situations when opening a database.
 #[derive(Debug)]
 pub enum OpenError {
+    /// An I/O error occurred when opening the database file for writing.
+    Io(std::io::Error),
+
     /// An error occurred when trying to restore from an existing database.
     Restore(RestoreError),
 }
@@ -82,9 +296,31 @@ pub enum RestoreError {
     Deserialize(serde_json::Error),
 }
 
+impl std::fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "error restoring database")
+    }
+}
+
+impl std::error::Error for RestoreError {}
+
+/// Possible error situations when pushing to a database.
+pub type PushError = std::io::Error;
+
 /// Possible error situations when querying a database.
 pub type QueryError = std::io::Error;
 
+/// The results of [`Database::query_with_warnings`].
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct QueryResult {
+    /// The events matching the query, in the order they were pushed.
+    pub events: Vec<Event>,
+
+    /// A warning for each matched stream from which events in the query's time range may have
+    /// been pruned.
+    pub warnings: Vec<RetentionWarning>,
+}
+
 impl Database {
     /// Open a database at the given `path`.
     ///
@@ -94,32 +330,160 @@ impl Database {
     ///
     /// # Errors
     ///
-    /// - Any [`io::Error`]s that occur when reading or writing directories or files are propagated.
-    /// - If `path` is not a directory, a [`NotDirectory`] error is returned.
+    /// - Any [`io::Error`]s that occur when opening `path` for writing are returned as
+    ///   [`OpenError::Io`].
     /// - If restoring from `path` fails, a [`RestoreError`] is returned.
-    ///
-    /// [`io::Error`]: std::io::Error
-    /// [`NotDirectory`]: OpenError::NotDirectory
     pub fn open(path: impl AsRef<Path>) -> Result<Self, OpenError> {
+        Self::open_with(path, &WalConfig::default())
+    }
+
+    /// Open a database at the given `path`, with the given write-ahead log configuration.
+    ///
+    /// Any events in the write-ahead log that weren't written to `path` (e.g. because the process
+    /// crashed) are replayed first. See [`open`](Self::open).
+    ///
+    /// # Errors
+    ///
+    /// See [`open`](Self::open). Errors replaying the write-ahead log are returned as
+    /// [`RestoreError::Io`].
+    pub fn open_with(path: impl AsRef<Path>, wal_config: &WalConfig) -> Result<Self, OpenError> {
         let path = path.as_ref();
-        if path.exists() {
-            let contents = fs::read(&path)
-                .map_err(RestoreError::Io)
-                .map_err(OpenError::Restore)?;
-            serde_json::from_slice(&contents)
-                .map_err(RestoreError::Deserialize)
-                .map_err(OpenError::Restore)
+        wal::recover(path)
+            .map_err(RestoreError::Io)
+            .map_err(OpenError::Restore)?;
+        let (index, len) = if path.exists() {
+            Self::restore(path).map_err(OpenError::Restore)?
         } else {
-            Ok(Database {
-                path: path.to_path_buf(),
-                events: RefCell::new(Vec::new()),
-            })
-        }
+            (Index::new(), 0)
+        };
+
+        let writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(OpenError::Io)?;
+        let reader = File::open(path).map_err(OpenError::Io)?;
+        let snapshots = snapshot::Snapshots::load(path)
+            .map_err(RestoreError::Io)
+            .map_err(OpenError::Restore)?;
+        let rollups = rollup::Rollups::load(path)
+            .map_err(RestoreError::Io)
+            .map_err(OpenError::Restore)?;
+        let horizons = horizon::Horizons::load(path)
+            .map_err(RestoreError::Io)
+            .map_err(OpenError::Restore)?;
+
+        Ok(Database {
+            path: path.to_path_buf(),
+            index: RefCell::new(index),
+            generation: Cell::new(0),
+            len: Cell::new(len),
+            snapshots: RefCell::new(snapshots),
+            rollups: RefCell::new(rollups),
+            horizons: RefCell::new(horizons),
+            wal: RefCell::new(wal::Wal::open(path, wal_config).map_err(OpenError::Io)?),
+            writer: RefCell::new(BufWriter::new(writer)),
+            reader: RefCell::new(BufReader::new(reader)),
+        })
+    }
+
+    /// Rebuild the index from the log at `path`, returning it and the length of the log.
+    fn restore(path: &Path) -> Result<(Index, u64), RestoreError> {
+        let mut reader = BufReader::new(File::open(path).map_err(RestoreError::Io)?);
+        let mut index = Index::new();
+        let mut len = 0;
+        read_index(&mut reader, &mut index, &mut len, false)?;
+        Ok((index, len))
     }
 
     /// Push a new `event` into the stream identified by `labels`.
-    pub fn push(&self, labels: &Labels, event: Event) {
-        self.events.borrow_mut().push((labels.clone(), event));
+    ///
+    /// The event is immediately queryable, but is only guaranteed to be persisted after the next
+    /// [`flush`](Self::flush).
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when writing the event are returned. The event is not added
+    /// to the database in that case.
+    pub fn push(&self, labels: &Labels, event: Event) -> Result<(), PushError> {
+        self.stream(labels).push(event)
+    }
+
+    /// Push a [`Record`] into the stream identified by its labels.
+    ///
+    /// See [`push`](Self::push).
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when writing the event are returned.
+    pub fn push_record(&self, record: Record) -> Result<(), PushError> {
+        let (labels, event) = record.into_event();
+        self.push(&labels, event)
+    }
+
+    /// Get a handle for pushing events into the stream identified by `labels`.
+    ///
+    /// Pushing many events through one handle is cheaper than calling [`push`](Self::push) for
+    /// each of them. See [`StreamHandle`].
+    #[must_use]
+    pub fn stream<'a>(&'a self, labels: &'a Labels) -> StreamHandle<'a> {
+        StreamHandle::new(self, labels)
+    }
+
+    /// Write any buffered events to disk.
+    ///
+    /// This is a checkpoint: once the log has been synced, the write-ahead log is emptied.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when writing or syncing the database file, or emptying the
+    /// write-ahead log, are returned.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut writer = self.writer.borrow_mut();
+        writer.flush()?;
+        writer.get_ref().sync_data()?;
+        self.wal.borrow_mut().clear()
+    }
+
+    /// Flush and close the database.
+    ///
+    /// Unlike dropping the database, this reports any errors that occur when flushing.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when flushing are returned.
+    pub fn close(self) -> io::Result<()> {
+        self.flush()
+    }
+
+    /// Create a snapshot of the database as it is now.
+    ///
+    /// Queries pinned to the snapshot with [`query_snapshot`](Self::query_snapshot) will only see
+    /// events pushed before it was created. Snapshots are persisted alongside the log, so they
+    /// survive restarts until they're [released](Self::release_snapshot).
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when persisting the snapshot are returned.
+    pub fn create_snapshot(&self) -> io::Result<SnapshotId> {
+        self.snapshots
+            .borrow_mut()
+            .create(&self.path, self.len.get())
+    }
+
+    /// The IDs of all snapshots, oldest first.
+    #[must_use]
+    pub fn snapshots(&self) -> Vec<SnapshotId> {
+        self.snapshots.borrow().all().collect()
+    }
+
+    /// Release the snapshot `id`, returning whether it existed.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when persisting the change are returned.
+    pub fn release_snapshot(&self, id: SnapshotId) -> io::Result<bool> {
+        self.snapshots.borrow_mut().release(&self.path, id)
     }
 
     /// Find events in the database matching the given `query`.
@@ -128,32 +492,512 @@ impl Database {
     ///
     /// Any [`io::Error`]s encountered when running the query are returned.
     pub fn query(&self, query: &Query) -> Result<Vec<Event>, QueryError> {
-        let results = match query {
-            Query::Label { name, value } => self
-                .events
-                .borrow()
-                .iter()
-                .filter_map(|(labels, event)| {
-                    if labels.get(name) == Some(value) {
-                        Some(event.clone())
-                    } else {
-                        None
-                    }
-                })
-                .collect(),
+        self.query_until(query, self.len.get())
+    }
+
+    /// Find events matching the given `query`, with [warnings](Self::retention_warnings) for any
+    /// matched streams whose events in the query's time range may have been pruned.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when running the query are returned.
+    pub fn query_with_warnings(&self, query: &Query) -> Result<QueryResult, QueryError> {
+        Ok(QueryResult {
+            events: self.query(query)?,
+            warnings: self.retention_warnings(query),
+        })
+    }
+
+    /// Check whether `query`'s time range reaches back to events that have been
+    /// [pruned](Self::prune) from the streams it matches.
+    ///
+    /// Label and matcher queries have no start, so they reach every pruned event. Returns a
+    /// warning for each affected stream, ordered by labels.
+    #[must_use]
+    pub fn retention_warnings(&self, query: &Query) -> Vec<RetentionWarning> {
+        let start = match query {
+            Query::Range { start, .. } => *start,
+            Query::Label { .. } | Query::Matchers(_) => 0,
         };
+        let index = self.index.borrow();
+        self.horizons
+            .borrow()
+            .iter()
+            .filter(|(labels, horizon)| start <= *horizon && query.matches_stream(*labels))
+            .map(|(labels, pruned_through)| {
+                let earliest_available = index
+                    .get(labels)
+                    .and_then(|records| records.iter().map(|(timestamp, _)| *timestamp).min());
+                RetentionWarning {
+                    labels: labels.clone(),
+                    pruned_through,
+                    earliest_available,
+                }
+            })
+            .collect()
+    }
+
+    /// The labels of every stream satisfying all of `matchers`, in order.
+    ///
+    /// If `matchers` is empty, every stream is returned.
+    #[must_use]
+    pub fn streams(&self, matchers: &[Matcher]) -> Vec<Labels> {
+        let mut streams: Vec<_> = self
+            .index
+            .borrow()
+            .iter()
+            .map(|(labels, _)| labels)
+            .filter(|labels| matchers.iter().all(|matcher| matcher.matches(labels)))
+            .map(index::to_labels)
+            .collect();
+        streams.sort();
+        streams
+    }
+
+    /// Call `visit` with every event and its stream's labels, in the order they were pushed.
+    ///
+    /// Events are read from the log one at a time, so this can be used to process the entire
+    /// database without holding it in memory.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when reading the log, or returned by `visit`, are returned.
+    pub fn scan(&self, visit: &mut dyn FnMut(Labels, Event) -> io::Result<()>) -> io::Result<()> {
+        // Buffered records must be written before they can be read.
+        self.writer.borrow_mut().flush()?;
+
+        let mut reader = BufReader::new(File::open(&self.path)?).take(self.len.get());
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let (labels, event) = serde_json::from_slice(&line)?;
+            visit(labels, event)?;
+        }
+        Ok(())
+    }
+
+    /// Push every event in `other` into this database, returning the number of events merged.
+    ///
+    /// Events are appended in the order they were pushed into `other`, after any events already
+    /// in this database. Nothing is deduplicated, so merging the same database twice duplicates
+    /// its events.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when reading `other` or writing the events are returned.
+    /// Events merged before the error remain in this database.
+    pub fn merge(&self, other: &Self) -> io::Result<usize> {
+        self.push_all(|visit| other.scan(visit))
+    }
+
+    /// Push every event in the log at `path` into this database, returning the number of events
+    /// imported.
+    ///
+    /// `path` can be a copy of another database's log (e.g. from another node, or a backup), or
+    /// the log of a database that isn't open. See [`merge`](Self::merge).
+    ///
+    /// Imports are idempotent: a [`Checkpoint`](crate::checkpoint::Checkpoint) of each imported
+    /// log is kept alongside this database's log, so importing the same log again only imports
+    /// events appended to it since. If the log no longer starts with the checkpointed content, it
+    /// is imported from the start.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when reading `path` or writing the events are returned. An
+    /// error of kind [`io::ErrorKind::InvalidData`] is returned if a record can't be parsed. Events
+    /// imported before the error remain in this database, but the checkpoint isn't advanced, so
+    /// they will be imported again by a later import of the same log.
+    pub fn import(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let path = path.as_ref();
+        let key = path.canonicalize()?.to_string_lossy().to_string();
+        let mut imports = Checkpoints::load(imports_path(&self.path))?;
+
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut hasher = Hasher::new();
+        let mut offset = 0;
+        if let Some(checkpoint) = imports.get(&key) {
+            if let Some(resumed) = checkpoint.verify(&mut reader)? {
+                hasher = resumed;
+                offset = checkpoint.offset;
+            } else {
+                warn!(
+                    "{} has changed since it was last imported, importing it from the start",
+                    path.display()
+                );
+                reader.seek(SeekFrom::Start(0))?;
+            }
+        }
+
+        let count = self.push_all(|visit| {
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                if reader.read_until(b'\n', &mut line)? == 0 {
+                    break;
+                }
+                let (labels, event) = serde_json::from_slice(&line)?;
+                visit(labels, event)?;
+                hasher.update(&line);
+                offset += line.len() as u64;
+            }
+            Ok(())
+        })?;
+        imports.set(&key, hasher.checkpoint(offset))?;
+        Ok(count)
+    }
+
+    /// Aggregate events matching the given `query` into a series per stream.
+    ///
+    /// This only uses the in-memory index, so is much cheaper than querying the events themselves.
+    /// See [`Aggregation`] for the available aggregations.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidInput`] is returned if `aggregation` has a zero
+    /// bucket or window.
+    pub fn aggregate(
+        &self,
+        query: &Query,
+        aggregation: &Aggregation,
+    ) -> Result<Vec<Series>, QueryError> {
+        aggregate::aggregate(&self.index.borrow(), query, aggregation)
+    }
+
+    /// Find the gaps of at least `threshold` in each stream matching `query`.
+    ///
+    /// Like [`aggregate`](Self::aggregate), this only uses the in-memory index (and roll-ups). For
+    /// range queries, gaps at the start and end of the range are included. Streams are reported
+    /// even if they have no gaps, ordered by labels.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidInput`] is returned if `threshold` is zero.
+    pub fn gaps(&self, query: &Query, threshold: Timestamp) -> Result<Vec<GapReport>, QueryError> {
+        gaps::gaps(
+            &self.index.borrow(),
+            self.query_rollups(query),
+            query,
+            threshold,
+        )
+    }
+
+    /// Find events matching the given `query` as they were when the snapshot `id` was created.
+    ///
+    /// # Errors
+    ///
+    /// - If there is no snapshot `id`, an error of kind [`io::ErrorKind::NotFound`] is returned.
+    /// - Any [`io::Error`]s encountered when running the query are returned.
+    pub fn query_snapshot(&self, query: &Query, id: SnapshotId) -> Result<Vec<Event>, QueryError> {
+        if !self.snapshots.borrow().contains(id) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no snapshot with ID {}", id),
+            ));
+        }
+        self.query_until(query, id)
+    }
+
+    /// Replace old events with per-interval [`Rollup`]s, according to `rules`.
+    ///
+    /// Each stream uses the first rule whose matchers it has, and streams matching no rule are left
+    /// alone. Only whole intervals that end at least `max_age` before `now` are rolled up. Rolled
+    /// up events are removed from the log, and can be found with
+    /// [`query_rollups`](Self::query_rollups) instead.
+    ///
+    /// Returns the number of events that were rolled up.
+    ///
+    /// # Errors
+    ///
+    /// - An error of kind [`io::ErrorKind::InvalidInput`] is returned if a rule has a zero
+    ///   interval.
+    /// - Removing events rewrites the log, which would invalidate snapshots, so an error of kind
+    ///   [`io::ErrorKind::Other`] is returned if any snapshots are held.
+    /// - Any [`io::Error`]s encountered when reading or rewriting the log are returned.
+    pub fn roll_up(&self, rules: &[RollupRule], now: Timestamp) -> io::Result<usize> {
+        if rules.iter().any(|rule| rule.interval == 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "roll-up interval must be greater than 0",
+            ));
+        }
+        self.ensure_no_snapshots("roll up events")?;
+
+        let mut expired = Vec::new();
+        for (labels, records) in self.index.borrow().iter() {
+            let rule = match rollup::rule_for(rules, labels) {
+                Some(rule) => rule,
+                None => continue,
+            };
+            let cutoff = now.saturating_sub(rule.max_age);
+            let cutoff = cutoff - cutoff % rule.interval;
+            let offsets: Vec<_> = records
+                .iter()
+                .filter(|(timestamp, _)| *timestamp < cutoff)
+                .map(|(_, offset)| *offset)
+                .collect();
+            if !offsets.is_empty() {
+                expired.push((index::to_labels(labels), rule.interval, offsets));
+            }
+        }
+        if expired.is_empty() {
+            return Ok(0);
+        }
+
+        // Buffered records must be written before they can be read.
+        self.writer.borrow_mut().flush()?;
+
+        let mut rollups = self.rollups.borrow().clone();
+        let mut removed = HashSet::new();
+        for (labels, interval, offsets) in expired {
+            for event in read_events(&mut self.reader.borrow_mut(), &offsets)? {
+                rollups.add(&labels, interval, event);
+            }
+            removed.extend(offsets);
+        }
 
-        Ok(results)
+        // Save the roll-ups first, so that a failure part way through can only duplicate events
+        // rather than lose them.
+        rollups.save(&self.path)?;
+        *self.rollups.borrow_mut() = rollups;
+        self.remove_records(&removed)?;
+
+        Ok(removed.len())
+    }
+
+    /// Remove events according to `retention`, as of `now`.
+    ///
+    /// Returns the number of events that were removed. Queries reaching back to the removed
+    /// events are given [`RetentionWarning`]s by
+    /// [`query_with_warnings`](Self::query_with_warnings).
+    ///
+    /// # Errors
+    ///
+    /// - Removing events rewrites the log, which would invalidate snapshots, so an error of kind
+    ///   [`io::ErrorKind::Other`] is returned if any snapshots are held.
+    /// - Any [`io::Error`]s encountered when rewriting the log are returned.
+    pub fn prune(&self, retention: &Retention, now: Timestamp) -> io::Result<usize> {
+        self.ensure_no_snapshots("prune events")?;
+
+        let expired =
+            retention::expired_offsets(&self.index.borrow(), self.len.get(), retention, now);
+        if expired.is_empty() {
+            return Ok(0);
+        }
+
+        // Advance the horizons first, so that a failure part way through can only cause spurious
+        // warnings rather than missing ones.
+        let pruned: Vec<_> = self
+            .index
+            .borrow()
+            .iter()
+            .filter_map(|(labels, records)| {
+                let horizon = records
+                    .iter()
+                    .filter(|(_, offset)| expired.contains(offset))
+                    .map(|(timestamp, _)| *timestamp)
+                    .max()?;
+                Some((index::to_labels(labels), horizon))
+            })
+            .collect();
+        self.horizons.borrow_mut().advance(&self.path, pruned)?;
+
+        // Buffered records must be written before the log is rewritten.
+        self.writer.borrow_mut().flush()?;
+        self.remove_records(&expired)?;
+        Ok(expired.len())
+    }
+
+    /// Find the roll-ups of streams matching `query`, ordered by labels.
+    ///
+    /// For range queries, roll-ups are included if their interval overlaps the range.
+    #[must_use]
+    pub fn query_rollups(&self, query: &Query) -> Vec<RollupSeries> {
+        self.rollups.borrow().query(query)
+    }
+
+    /// Fail with an error of kind [`io::ErrorKind::Other`] if any snapshots are held, since
+    /// `action` would invalidate them.
+    fn ensure_no_snapshots(&self, action: &str) -> io::Result<()> {
+        if self.snapshots.borrow().all().next().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("cannot {} while snapshots are held", action),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Push the events visited by `read`, batching consecutive events in the same stream.
+    fn push_all(
+        &self,
+        read: impl FnOnce(&mut dyn FnMut(Labels, Event) -> io::Result<()>) -> io::Result<()>,
+    ) -> io::Result<usize> {
+        let mut batch: Option<(Labels, Vec<Event>)> = None;
+        let mut count = 0;
+        read(&mut |labels, event| {
+            count += 1;
+            if let Some((batch_labels, events)) = &mut batch {
+                if *batch_labels == labels && events.len() < MERGE_BATCH_SIZE {
+                    events.push(event);
+                    return Ok(());
+                }
+            }
+            if let Some((labels, events)) = batch.replace((labels, vec![event])) {
+                self.stream(&labels).push_batch(events)?;
+            }
+            Ok(())
+        })?;
+        if let Some((labels, events)) = batch {
+            self.stream(&labels).push_batch(events)?;
+        }
+        Ok(count)
+    }
+
+    /// Rewrite the log without the records at `offsets`, and rebuild the index.
+    fn remove_records(&self, offsets: &HashSet<u64>) -> io::Result<()> {
+        // Rewriting the log changes record offsets, so the write-ahead log must be empty first.
+        self.flush()?;
+
+        let mut temporary_path = OsString::from(&self.path);
+        temporary_path.push(".tmp");
+        let temporary_path = PathBuf::from(temporary_path);
+
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut writer = BufWriter::new(File::create(&temporary_path)?);
+        let mut offset = 0;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                break;
+            }
+            if !offsets.contains(&offset) {
+                writer.write_all(&line)?;
+            }
+            offset += read as u64;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_data()?;
+        fs::rename(&temporary_path, &self.path)?;
+
+        let (index, len) = Self::restore(&self.path).map_err(|error| match error {
+            RestoreError::Io(error) => error,
+            RestoreError::Deserialize(error) => error.into(),
+        })?;
+        *self.writer.borrow_mut() =
+            BufWriter::new(OpenOptions::new().append(true).open(&self.path)?);
+        *self.reader.borrow_mut() = BufReader::new(File::open(&self.path)?);
+        *self.index.borrow_mut() = index;
+        self.generation.set(self.generation.get() + 1);
+        self.len.set(len);
+        Ok(())
     }
+
+    /// Find events matching `query` in the first `len` bytes of the log.
+    fn query_until(&self, query: &Query, len: u64) -> Result<Vec<Event>, QueryError> {
+        let mut offsets = matching_offsets(&self.index.borrow(), query);
+        offsets.retain(|offset| *offset < len);
+        if offsets.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Buffered records must be written before they can be read.
+        self.writer.borrow_mut().flush()?;
+
+        read_events(&mut self.reader.borrow_mut(), &offsets)
+    }
+}
+
+/// Add the complete records in `reader` from `*len` onwards to `index`, updating `*len`.
+///
+/// If `allow_partial`, a trailing record without a newline (e.g. one that's still being written)
+/// is left out. Otherwise, it's treated like any other record.
+fn read_index(
+    reader: &mut BufReader<File>,
+    index: &mut Index,
+    len: &mut u64,
+    allow_partial: bool,
+) -> Result<usize, RestoreError> {
+    reader
+        .seek(SeekFrom::Start(*len))
+        .map_err(RestoreError::Io)?;
+
+    let mut records = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(RestoreError::Io)?;
+        if read == 0 || (allow_partial && line.last() != Some(&b'\n')) {
+            break;
+        }
+
+        let (labels, entry): (Labels, IndexEntry) =
+            serde_json::from_slice(&line).map_err(RestoreError::Deserialize)?;
+        index.push(&labels, entry.timestamp, *len);
+        *len += read as u64;
+        records += 1;
+    }
+    Ok(records)
+}
+
+/// Find the offsets of records matching `query`, in the order they were written.
+fn matching_offsets(index: &Index, query: &Query) -> Vec<u64> {
+    let mut offsets: Vec<u64> = index
+        .iter()
+        .filter(|(labels, _)| query.matches_stream(labels))
+        .flat_map(|(_, records)| {
+            records
+                .iter()
+                .filter(move |(timestamp, _)| query.matches_timestamp(*timestamp))
+                .map(|(_, offset)| *offset)
+        })
+        .collect();
+
+    // Offsets increase with each write, so sorting them restores the order events were pushed.
+    offsets.sort_unstable();
+    offsets
+}
+
+/// Read the events at `offsets` from `reader`.
+fn read_events(reader: &mut BufReader<File>, offsets: &[u64]) -> io::Result<Vec<Event>> {
+    let mut events = Vec::with_capacity(offsets.len());
+    let mut line = Vec::new();
+    for offset in offsets {
+        reader.seek(SeekFrom::Start(*offset))?;
+        line.clear();
+        reader.read_until(b'\n', &mut line)?;
+
+        let (_, event): (serde::de::IgnoredAny, Event) = serde_json::from_slice(&line)?;
+        events.push(event);
+    }
+    Ok(events)
 }
 
 impl Drop for Database {
     fn drop(&mut self) {
-        let file = File::create(&self.path).expect("create file");
-        serde_json::to_writer(file, &self).expect("serialize database");
+        if let Err(error) = self.flush() {
+            warn!(
+                "Failed to flush database {}: {}",
+                self.path.display(),
+                error
+            );
+        }
     }
 }
 
+/// The path of the checkpoints of logs imported into the database with its log at `log_path`.
+fn imports_path(log_path: &Path) -> PathBuf {
+    let mut path = OsString::from(log_path);
+    path.push(".imports");
+    PathBuf::from(path)
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
@@ -162,16 +1006,19 @@ mod tests {
 
     use crate::test;
 
-    use super::{Database, Event, OpenError, Query, RestoreError};
+    use super::{
+        Database, Event, Gap, OpenError, Query, RestoreError, Retention, RetentionWarning,
+        RollupRule, Value,
+    };
 
     #[test]
     fn fresh_database() -> test::Result {
         let tempdir = tempfile::tempdir()?;
         let db = Database::open(tempdir.path().join("data"))?;
 
-        db.push(&make_labels(&[("l1", "v1")]), make_event(0, "e1"));
-        db.push(&make_labels(&[("l1", "v2")]), make_event(1, "e2"));
-        db.push(&make_labels(&[("l2", "v1")]), make_event(2, "e3"));
+        db.push(&make_labels(&[("l1", "v1")]), make_event(0, "e1"))?;
+        db.push(&make_labels(&[("l1", "v2")]), make_event(1, "e2"))?;
+        db.push(&make_labels(&[("l2", "v1")]), make_event(2, "e3"))?;
 
         let query = Query::Label {
             name: "l1".to_string(),
@@ -187,9 +1034,9 @@ mod tests {
         let tempdir = tempfile::tempdir()?;
         let db = Database::open(tempdir.path().join("data"))?;
 
-        db.push(&make_labels(&[("l1", "v1")]), make_event(0, "e1"));
-        db.push(&make_labels(&[("l1", "v2")]), make_event(1, "e2"));
-        db.push(&make_labels(&[("l2", "v1")]), make_event(2, "e3"));
+        db.push(&make_labels(&[("l1", "v1")]), make_event(0, "e1"))?;
+        db.push(&make_labels(&[("l1", "v2")]), make_event(1, "e2"))?;
+        db.push(&make_labels(&[("l2", "v1")]), make_event(2, "e3"))?;
         drop(db);
 
         let db = Database::open(tempdir.path().join("data"))?;
@@ -203,6 +1050,626 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn valued_events() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("data");
+
+        // An event logged before values were introduced.
+        fs::write(
+            &path,
+            "[{\"l1\":\"v1\"},{\"timestamp\":0,\"data\":[101,49]}]\n",
+        )?;
+
+        let db = Database::open(&path)?;
+        let labels = make_labels(&[("l1", "v1")]);
+        db.push(&labels, Event::sample(1, Value::Gauge(1.5)))?;
+        db.push(&labels, make_event(2, "e3").with_value(Value::Counter(7.0)))?;
+        drop(db);
+
+        let db = Database::open(&path)?;
+        let events = db.query(&Query::Label {
+            name: "l1".to_string(),
+            value: "v1".to_string(),
+        })?;
+        assert_eq!(
+            events,
+            vec![
+                make_event(0, "e1"),
+                Event::sample(1, Value::Gauge(1.5)),
+                make_event(2, "e3").with_value(Value::Counter(7.0)),
+            ]
+        );
+        let values: Vec<_> = events.iter().map(|event| event.value()).collect();
+        assert_eq!(
+            values,
+            vec![None, Some(Value::Gauge(1.5)), Some(Value::Counter(7.0))]
+        );
+        assert_eq!(events[2].data(), b"e3");
+        assert!((events[1].value().unwrap().as_f64() - 1.5).abs() < f64::EPSILON);
+
+        Ok(())
+    }
+
+    #[test]
+    fn interleaved_streams() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let db = Database::open(tempdir.path().join("data"))?;
+
+        db.push(&make_labels(&[("l1", "v1")]), make_event(0, "e1"))?;
+        db.push(
+            &make_labels(&[("l1", "v1"), ("l2", "v1")]),
+            make_event(1, "e2"),
+        )?;
+        db.push(&make_labels(&[("l1", "v2")]), make_event(2, "e3"))?;
+        db.push(&make_labels(&[("l1", "v1")]), make_event(3, "e4"))?;
+
+        let query = Query::Label {
+            name: "l1".to_string(),
+            value: "v1".to_string(),
+        };
+        let expected = vec![
+            make_event(0, "e1"),
+            make_event(1, "e2"),
+            make_event(3, "e4"),
+        ];
+        assert_eq!(db.query(&query)?, expected);
+        drop(db);
+
+        let db = Database::open(tempdir.path().join("data"))?;
+        assert_eq!(db.query(&query)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_handles() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("data");
+        let db = Database::open(&path)?;
+
+        let labels = make_labels(&[("l1", "v1")]);
+        let stream = db.stream(&labels);
+        stream.push(make_event(0, "e0"))?;
+        db.push(&make_labels(&[("l1", "v2")]), make_event(1, "e1"))?;
+        stream.push_batch(vec![make_event(2, "e2"), make_event(3, "e3")])?;
+        stream.push_batch(vec![])?;
+
+        // Pruning rewrites the log and rebuilds the index, so the handle must find its stream again.
+        let retention = Retention {
+            max_age: Some(2),
+            max_size: None,
+        };
+        assert_eq!(db.prune(&retention, 4)?, 2);
+        stream.push(make_event(4, "e4"))?;
+
+        let query = Query::Label {
+            name: "l1".to_string(),
+            value: "v1".to_string(),
+        };
+        let expected = vec![
+            make_event(2, "e2"),
+            make_event(3, "e3"),
+            make_event(4, "e4"),
+        ];
+        assert_eq!(db.query(&query)?, expected);
+        drop(db);
+
+        let db = Database::open(&path)?;
+        assert_eq!(db.query(&query)?, expected);
+        assert_eq!(db.streams(&[]), vec![labels]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn crash_recovery() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("data");
+
+        let labels = make_labels(&[("l1", "v1")]);
+        let db = Database::open(&path)?;
+        db.push(&labels, make_event(0, "e0"))?;
+        db.flush()?;
+        db.push(&labels, make_event(1, "e1"))?;
+        db.push(&labels, make_event(2, "e2"))?;
+
+        // Simulate a crash: buffered writes are lost, and the last push was only partly logged.
+        std::mem::forget(db);
+        let mut wal = fs::OpenOptions::new()
+            .append(true)
+            .open(tempdir.path().join("data.wal"))?;
+        std::io::Write::write_all(&mut wal, b"999 [{\"l1\":\"v1\"},{\"timest")?;
+
+        let db = Database::open(&path)?;
+        let query = Query::Label {
+            name: "l1".to_string(),
+            value: "v1".to_string(),
+        };
+        let expected = vec![
+            make_event(0, "e0"),
+            make_event(1, "e1"),
+            make_event(2, "e2"),
+        ];
+        assert_eq!(db.query(&query)?, expected);
+        drop(db);
+
+        let db = Database::open(&path)?;
+        assert_eq!(db.query(&query)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_and_import() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let node1 = Database::open(tempdir.path().join("node1"))?;
+        let node2 = Database::open(tempdir.path().join("node2"))?;
+
+        let l1 = make_labels(&[("node", "1")]);
+        let l2 = make_labels(&[("node", "2")]);
+        node1.push(&l1, make_event(0, "e0"))?;
+        node2.push(&l2, make_event(1, "e1"))?;
+        node2.push(&l2, make_event(2, "e2"))?;
+        node2.push(&l1, make_event(3, "e3"))?;
+
+        assert_eq!(node1.merge(&node2)?, 3);
+        let query = Query::Label {
+            name: "node".to_string(),
+            value: "1".to_string(),
+        };
+        assert_eq!(
+            node1.query(&query)?,
+            vec![make_event(0, "e0"), make_event(3, "e3")]
+        );
+        assert_eq!(node1.streams(&[]), vec![l1, l2]);
+        node2.close()?;
+
+        let central = Database::open(tempdir.path().join("central"))?;
+        assert_eq!(central.import(tempdir.path().join("node2"))?, 3);
+        node1.close()?;
+        assert_eq!(central.import(tempdir.path().join("node1"))?, 4);
+        assert_eq!(
+            central.query(&query)?,
+            vec![
+                make_event(3, "e3"),
+                make_event(0, "e0"),
+                make_event(3, "e3")
+            ]
+        );
+
+        fs::write(tempdir.path().join("invalid"), "not json\n")?;
+        let error = central.import(tempdir.path().join("invalid")).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        Ok(())
+    }
+
+    #[test]
+    fn import_is_idempotent() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let node = Database::open(tempdir.path().join("node"))?;
+        let labels = make_labels(&[("node", "1")]);
+        node.push(&labels, make_event(0, "e0"))?;
+        node.flush()?;
+
+        let central = Database::open(tempdir.path().join("central"))?;
+        assert_eq!(central.import(tempdir.path().join("node"))?, 1);
+        assert_eq!(central.import(tempdir.path().join("node"))?, 0);
+
+        // Only events appended since the last import are imported.
+        node.push(&labels, make_event(1, "e1"))?;
+        node.close()?;
+        assert_eq!(central.import(tempdir.path().join("node"))?, 1);
+
+        let query = Query::Label {
+            name: "node".to_string(),
+            value: "1".to_string(),
+        };
+        assert_eq!(
+            central.query(&query)?,
+            vec![make_event(0, "e0"), make_event(1, "e1")]
+        );
+
+        // A log that was rewritten is imported from the start.
+        let other = Database::open(tempdir.path().join("other"))?;
+        other.push(&labels, make_event(2, "e2"))?;
+        other.close()?;
+        fs::copy(tempdir.path().join("other"), tempdir.path().join("node"))?;
+        assert_eq!(central.import(tempdir.path().join("node"))?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn retention_warnings() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("data");
+        let db = Database::open(&path)?;
+
+        let a = make_labels(&[("l1", "v1")]);
+        let b = make_labels(&[("l1", "v2")]);
+        for timestamp in 0..4 {
+            db.push(&a, make_event(timestamp, "a"))?;
+        }
+        db.push(&b, make_event(0, "b"))?;
+        db.push(&b, make_event(5, "b"))?;
+
+        let range = |start| Query::Range {
+            matchers: a.clone(),
+            start,
+            end: 10,
+        };
+        assert_eq!(db.query_with_warnings(&range(0))?.warnings, vec![]);
+
+        let retention = Retention {
+            max_age: Some(2),
+            max_size: None,
+        };
+        assert_eq!(db.prune(&retention, 4)?, 3);
+
+        let result = db.query_with_warnings(&range(1))?;
+        assert_eq!(result.events, vec![make_event(2, "a"), make_event(3, "a")]);
+        assert_eq!(
+            result.warnings,
+            vec![RetentionWarning {
+                labels: a.clone(),
+                pruned_through: 1,
+                earliest_available: Some(2),
+            }]
+        );
+        assert_eq!(db.retention_warnings(&range(2)), vec![]);
+        drop(db);
+
+        let db = Database::open(&path)?;
+        let query = Query::Label {
+            name: "l1".to_string(),
+            value: "v2".to_string(),
+        };
+        assert_eq!(
+            db.retention_warnings(&query),
+            vec![RetentionWarning {
+                labels: b,
+                pruned_through: 0,
+                earliest_available: Some(5),
+            }]
+        );
+        assert_eq!(db.retention_warnings(&range(1)).len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn range_query() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let db = Database::open(tempdir.path().join("data"))?;
+
+        db.push(&make_labels(&[("l1", "v1")]), make_event(10, "e1"))?;
+        db.push(
+            &make_labels(&[("l1", "v1"), ("l2", "v1")]),
+            make_event(20, "e2"),
+        )?;
+        db.push(&make_labels(&[("l1", "v2")]), make_event(20, "e3"))?;
+        db.push(&make_labels(&[("l1", "v1")]), make_event(30, "e4"))?;
+
+        let query = Query::Range {
+            matchers: make_labels(&[("l1", "v1")]),
+            start: 15,
+            end: 30,
+        };
+        assert_eq!(db.query(&query)?, vec![make_event(20, "e2")]);
+
+        let query = Query::Range {
+            matchers: make_labels(&[]),
+            start: 0,
+            end: 25,
+        };
+        assert_eq!(
+            db.query(&query)?,
+            vec![
+                make_event(10, "e1"),
+                make_event(20, "e2"),
+                make_event(20, "e3")
+            ]
+        );
+        drop(db);
+
+        let db = Database::open(tempdir.path().join("data"))?;
+        let query = Query::Range {
+            matchers: make_labels(&[("l1", "v1"), ("l2", "v1")]),
+            start: 0,
+            end: 100,
+        };
+        assert_eq!(db.query(&query)?, vec![make_event(20, "e2")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn matchers_query() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let db = Database::open(tempdir.path().join("data"))?;
+
+        db.push(
+            &make_labels(&[("app", "api"), ("env", "prod")]),
+            make_event(0, "e1"),
+        )?;
+        db.push(
+            &make_labels(&[("app", "api"), ("env", "dev")]),
+            make_event(1, "e2"),
+        )?;
+        db.push(&make_labels(&[("app", "api")]), make_event(2, "e3"))?;
+        db.push(
+            &make_labels(&[("app", "web"), ("env", "prod")]),
+            make_event(3, "e4"),
+        )?;
+
+        let query = |matchers: &[&str]| -> Result<Query, String> {
+            Ok(Query::Matchers(
+                matchers
+                    .iter()
+                    .map(|matcher| matcher.parse())
+                    .collect::<Result<_, _>>()?,
+            ))
+        };
+        assert_eq!(
+            db.query(&query(&["app=api", "env!=prod"])?)?,
+            vec![make_event(1, "e2"), make_event(2, "e3")]
+        );
+        assert_eq!(
+            db.query(&query(&["app=api", "env"])?)?,
+            vec![make_event(0, "e1"), make_event(1, "e2")]
+        );
+        assert_eq!(db.query(&query(&["!env"])?)?, vec![make_event(2, "e3")]);
+        assert_eq!(db.query(&query(&[])?)?.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_streams() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let db = Database::open(tempdir.path().join("data"))?;
+
+        db.push(&make_labels(&[("app", "web")]), make_event(0, "e1"))?;
+        db.push(
+            &make_labels(&[("app", "api"), ("env", "prod")]),
+            make_event(1, "e2"),
+        )?;
+        db.push(&make_labels(&[("app", "web")]), make_event(2, "e3"))?;
+
+        assert_eq!(
+            db.streams(&[]),
+            vec![
+                make_labels(&[("app", "api"), ("env", "prod")]),
+                make_labels(&[("app", "web")]),
+            ]
+        );
+        assert_eq!(
+            db.streams(&["env".parse()?]),
+            vec![make_labels(&[("app", "api"), ("env", "prod")])]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_query() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let db = Database::open(tempdir.path().join("data"))?;
+
+        let query = Query::Label {
+            name: "l1".to_string(),
+            value: "v1".to_string(),
+        };
+
+        db.push(&make_labels(&[("l1", "v1")]), make_event(0, "e1"))?;
+        let snapshot = db.create_snapshot()?;
+        db.push(&make_labels(&[("l1", "v1")]), make_event(1, "e2"))?;
+
+        assert_eq!(
+            db.query_snapshot(&query, snapshot)?,
+            vec![make_event(0, "e1")]
+        );
+        assert_eq!(db.query(&query)?.len(), 2);
+        drop(db);
+
+        let db = Database::open(tempdir.path().join("data"))?;
+        assert_eq!(db.snapshots(), vec![snapshot]);
+        assert_eq!(
+            db.query_snapshot(&query, snapshot)?,
+            vec![make_event(0, "e1")]
+        );
+
+        assert!(db.release_snapshot(snapshot)?);
+        assert!(!db.release_snapshot(snapshot)?);
+        assert!(db.query_snapshot(&query, snapshot).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn gaps_in_streams() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let db = Database::open(tempdir.path().join("data"))?;
+
+        for timestamp in &[1, 3, 8, 12, 14, 25] {
+            db.push(&make_labels(&[("l1", "v1")]), make_event(*timestamp, "e"))?;
+        }
+        db.push(&make_labels(&[("l1", "v2")]), make_event(40, "e"))?;
+        db.roll_up(
+            &[RollupRule {
+                matchers: make_labels(&[("l1", "v1")]),
+                max_age: 10,
+                interval: 10,
+            }],
+            30,
+        )?;
+
+        let query = Query::Range {
+            matchers: make_labels(&[]),
+            start: 0,
+            end: 30,
+        };
+        let reports = db.gaps(&query, 5)?;
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].labels, make_labels(&[("l1", "v1")]));
+        // The roll-ups cover [1, 8] and [12, 14].
+        assert_eq!(
+            reports[0].gaps,
+            vec![Gap { start: 14, end: 25 }, Gap { start: 25, end: 30 }]
+        );
+        assert_eq!(reports[1].gaps, vec![Gap { start: 0, end: 30 }]);
+
+        let reports = db.gaps(&Query::Matchers(Vec::new()), 5)?;
+        assert_eq!(reports[0].gaps, vec![Gap { start: 14, end: 25 }]);
+        assert_eq!(reports[1].gaps, vec![]);
+
+        assert!(db.gaps(&query, 0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rolled_up_database() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("data");
+        let db = Database::open(&path)?;
+
+        db.push(&make_labels(&[("l1", "v1")]), make_event(1, "e1"))?;
+        db.push(&make_labels(&[("l1", "v2")]), make_event(2, "e2"))?;
+        db.push(&make_labels(&[("l1", "v1")]), make_event(8, "e3"))?;
+        db.push(&make_labels(&[("l1", "v1")]), make_event(12, "e4"))?;
+        db.push(&make_labels(&[("l1", "v1")]), make_event(25, "e5"))?;
+
+        let rules = [RollupRule {
+            matchers: make_labels(&[("l1", "v1")]),
+            max_age: 10,
+            interval: 10,
+        }];
+
+        // Only the intervals [0, 10) and [10, 20) have ended at least 10 before 30.
+        assert_eq!(db.roll_up(&rules, 30)?, 3);
+        assert_eq!(db.roll_up(&rules, 30)?, 0);
+
+        let query = Query::Range {
+            matchers: make_labels(&[]),
+            start: 0,
+            end: 30,
+        };
+        assert_eq!(
+            db.query(&query)?,
+            vec![make_event(2, "e2"), make_event(25, "e5")]
+        );
+
+        let rollups = db.query_rollups(&query);
+        assert_eq!(rollups.len(), 1);
+        assert_eq!(rollups[0].labels, make_labels(&[("l1", "v1")]));
+        let counts: Vec<_> = rollups[0]
+            .rollups
+            .iter()
+            .map(|rollup| (rollup.start, rollup.count))
+            .collect();
+        assert_eq!(counts, vec![(0, 2), (10, 1)]);
+        assert_eq!(rollups[0].rollups[0].first, make_event(1, "e1"));
+        assert_eq!(rollups[0].rollups[0].last, make_event(8, "e3"));
+
+        db.push(&make_labels(&[("l1", "v1")]), make_event(26, "e6"))?;
+        db.close()?;
+
+        let db = Database::open(&path)?;
+        assert_eq!(
+            db.query(&query)?,
+            vec![
+                make_event(2, "e2"),
+                make_event(25, "e5"),
+                make_event(26, "e6")
+            ]
+        );
+        assert_eq!(db.query_rollups(&query), rollups);
+
+        db.create_snapshot()?;
+        assert!(db.roll_up(&rules, 100).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pruned_database() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("data");
+        let db = Database::open(&path)?;
+
+        for timestamp in 0..10 {
+            db.push(&make_labels(&[("l1", "v1")]), make_event(timestamp, "e"))?;
+        }
+
+        let retention = Retention {
+            max_age: Some(5),
+            max_size: None,
+        };
+        assert_eq!(db.prune(&retention, 10)?, 5);
+        assert_eq!(db.prune(&retention, 10)?, 0);
+
+        let query = Query::Label {
+            name: "l1".to_string(),
+            value: "v1".to_string(),
+        };
+        let timestamps = |events: Vec<Event>| -> Vec<_> {
+            events.into_iter().map(|event| event.timestamp).collect()
+        };
+        assert_eq!(timestamps(db.query(&query)?), vec![5, 6, 7, 8, 9]);
+
+        let record_size = fs::metadata(&path)?.len() / 5;
+        let retention = Retention {
+            max_age: None,
+            max_size: Some(record_size * 2),
+        };
+        assert_eq!(db.prune(&retention, 10)?, 3);
+        db.close()?;
+
+        let db = Database::open(&path)?;
+        assert_eq!(timestamps(db.query(&query)?), vec![8, 9]);
+
+        db.create_snapshot()?;
+        assert!(db.prune(&retention, 100).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn flushed_database() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let db = Database::open(tempdir.path().join("data"))?;
+
+        db.push(&make_labels(&[("l1", "v1")]), make_event(0, "e1"))?;
+        db.flush()?;
+        db.push(&make_labels(&[("l1", "v1")]), make_event(1, "e2"))?;
+        db.flush()?;
+
+        // Simulate a crash, which would skip `Drop`.
+        std::mem::forget(db);
+
+        let db = Database::open(tempdir.path().join("data"))?;
+
+        let query = Query::Label {
+            name: "l1".to_string(),
+            value: "v1".to_string(),
+        };
+        assert_eq!(
+            db.query(&query)?,
+            vec![make_event(0, "e1"), make_event(1, "e2")]
+        );
+
+        db.push(&make_labels(&[("l1", "v1")]), make_event(2, "e3"))?;
+        db.close()?;
+
+        let db = Database::open(tempdir.path().join("data"))?;
+        assert_eq!(db.query(&query)?.len(), 3);
+
+        Ok(())
+    }
+
     #[test]
     fn restore_io_error() -> test::Result {
         let tempdir = tempfile::tempdir()?;
@@ -244,9 +1711,6 @@ mod tests {
     }
 
     fn make_event(timestamp: u64, data: impl AsRef<[u8]>) -> Event {
-        Event {
-            timestamp,
-            data: data.as_ref().into(),
-        }
+        Event::new(timestamp, data.as_ref().into())
     }
 }
@@ -0,0 +1,136 @@
+// src/database/retention.rs
+//! Age- and size-based retention of events.
+//!
+//! [`Database::prune`](super::Database::prune) removes events that are older than the configured
+//! maximum age, and then the oldest remaining events until the log fits within the configured
+//! maximum size.
+
+use std::collections::HashSet;
+
+use super::index::Index;
+use super::Timestamp;
+
+/// Limits on the events kept by a [`Database`](super::Database).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Retention {
+    /// Events with timestamps at least this far before the time of pruning are removed. If not
+    /// set, events are kept regardless of age.
+    pub max_age: Option<Timestamp>,
+
+    /// The maximum size of the log, in bytes. If it's larger, the first events pushed are removed
+    /// until it fits. If not set, the log can grow without limit.
+    pub max_size: Option<u64>,
+}
+
+/// Find the offsets of the records in a log of length `len` that `retention` would remove at
+/// `now`.
+pub(super) fn expired_offsets(
+    index: &Index,
+    len: u64,
+    retention: &Retention,
+    now: Timestamp,
+) -> HashSet<u64> {
+    let mut records: Vec<_> = index.records().flatten().copied().collect();
+    records.sort_unstable_by_key(|(_, offset)| *offset);
+
+    let mut expired = HashSet::new();
+    if let Some(max_age) = retention.max_age {
+        let cutoff = now.saturating_sub(max_age);
+        expired.extend(
+            records
+                .iter()
+                .filter(|(timestamp, _)| *timestamp < cutoff)
+                .map(|(_, offset)| *offset),
+        );
+    }
+
+    if let Some(max_size) = retention.max_size {
+        // Each record extends to the start of the next, or the end of the log.
+        let ends = records
+            .iter()
+            .skip(1)
+            .map(|(_, offset)| *offset)
+            .chain(std::iter::once(len));
+        let sizes: Vec<_> = records
+            .iter()
+            .zip(ends)
+            .map(|((_, offset), end)| (*offset, end - offset))
+            .collect();
+
+        let mut size: u64 = sizes
+            .iter()
+            .filter(|(offset, _)| !expired.contains(offset))
+            .map(|(_, size)| size)
+            .sum();
+        for (offset, record_size) in sizes {
+            if size <= max_size {
+                break;
+            }
+            if expired.insert(offset) {
+                size -= record_size;
+            }
+        }
+    }
+
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::super::index::Index;
+    use super::{expired_offsets, Retention};
+
+    #[test]
+    fn expire_by_age_and_size() {
+        let mut a = BTreeMap::new();
+        a.insert("l1".to_string(), "v1".to_string());
+        let mut b = BTreeMap::new();
+        b.insert("l1".to_string(), "v2".to_string());
+
+        // Records of 10 bytes each, with b's first record pushed late.
+        let mut index = Index::new();
+        let records = [
+            (&a, 5, 0),
+            (&a, 20, 10),
+            (&b, 1, 20),
+            (&a, 30, 30),
+            (&b, 40, 40),
+        ];
+        for (labels, timestamp, offset) in &records {
+            index.push(labels, *timestamp, *offset);
+        }
+
+        let expired = |retention| {
+            let mut offsets: Vec<_> = expired_offsets(&index, 50, &retention, 50)
+                .into_iter()
+                .collect();
+            offsets.sort_unstable();
+            offsets
+        };
+
+        assert_eq!(expired(Retention::default()), Vec::<u64>::new());
+        assert_eq!(
+            expired(Retention {
+                max_age: Some(40),
+                max_size: None,
+            }),
+            vec![0, 20]
+        );
+        assert_eq!(
+            expired(Retention {
+                max_age: None,
+                max_size: Some(25),
+            }),
+            vec![0, 10, 20]
+        );
+        assert_eq!(
+            expired(Retention {
+                max_age: Some(40),
+                max_size: Some(20),
+            }),
+            vec![0, 10, 20]
+        );
+    }
+}
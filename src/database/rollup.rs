@@ -0,0 +1,232 @@
+// src/database/rollup.rs
+//! Roll-ups of old events into per-interval summaries.
+//!
+//! [`Database::roll_up`](super::Database::roll_up) replaces events older than a rule's `max_age`
+//! with one [`Rollup`] per stream and interval, recording the number of events and the first and
+//! last of them. Roll-ups are small, so they're kept in memory and persisted as a whole alongside
+//! the log.
+
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::index::InternedLabels;
+use super::{Event, Labels, Query, Timestamp};
+
+/// A rule selecting streams whose old events should be rolled up.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RollupRule {
+    /// The labels that a stream must have for the rule to apply. If empty, every stream matches.
+    pub matchers: Labels,
+
+    /// Events at least this old are rolled up.
+    pub max_age: Timestamp,
+
+    /// The length of each roll-up interval.
+    pub interval: Timestamp,
+}
+
+impl RollupRule {
+    fn matches<K, V>(&self, labels: &BTreeMap<K, V>) -> bool
+    where
+        K: Borrow<str> + Ord,
+        V: AsRef<str>,
+    {
+        self.matchers.iter().all(|(name, value)| {
+            labels.get(name.as_str()).map(AsRef::as_ref) == Some(value.as_str())
+        })
+    }
+}
+
+/// A summary of the events from one stream in one interval.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Rollup {
+    /// The start of the interval.
+    pub start: Timestamp,
+
+    /// The end of the interval (exclusive).
+    pub end: Timestamp,
+
+    /// The number of events in the interval.
+    pub count: u64,
+
+    /// The first event pushed in the interval.
+    pub first: Event,
+
+    /// The last event pushed in the interval.
+    pub last: Event,
+}
+
+/// The roll-ups of a single stream.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct RollupSeries {
+    /// The labels of the stream.
+    pub labels: Labels,
+
+    /// The stream's roll-ups, in timestamp order.
+    pub rollups: Vec<Rollup>,
+}
+
+/// The roll-ups of a database.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Rollups {
+    streams: HashMap<Labels, BTreeMap<Timestamp, Rollup>>,
+}
+
+impl Rollups {
+    /// Load the roll-ups of the database with its log at `log_path`.
+    pub(super) fn load(log_path: &Path) -> io::Result<Self> {
+        let path = rollups_path(log_path);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        // JSON objects can only have string keys, so streams are stored as a list.
+        let streams: Vec<(Labels, Vec<Rollup>)> = serde_json::from_slice(&fs::read(path)?)?;
+        let streams = streams
+            .into_iter()
+            .map(|(labels, rollups)| {
+                let rollups = rollups
+                    .into_iter()
+                    .map(|rollup| (rollup.start, rollup))
+                    .collect();
+                (labels, rollups)
+            })
+            .collect();
+        Ok(Self { streams })
+    }
+
+    /// Add `event`, from the stream with `labels`, to the roll-up of its `interval`-long interval.
+    ///
+    /// Events must be added in the order they were pushed.
+    pub(super) fn add(&mut self, labels: &Labels, interval: Timestamp, event: Event) {
+        let start = event.timestamp - event.timestamp % interval;
+        let rollups = self.streams.entry(labels.clone()).or_default();
+        match rollups.get_mut(&start) {
+            Some(rollup) => {
+                rollup.count += 1;
+                rollup.last = event;
+            }
+            None => {
+                rollups.insert(
+                    start,
+                    Rollup {
+                        start,
+                        end: start + interval,
+                        count: 1,
+                        first: event.clone(),
+                        last: event,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Find the roll-ups of streams matching `query` whose intervals overlap its time range.
+    pub(super) fn query(&self, query: &Query) -> Vec<RollupSeries> {
+        let mut series: Vec<_> = self
+            .streams
+            .iter()
+            .filter(|(labels, _)| query.matches_stream(labels))
+            .filter_map(|(labels, rollups)| {
+                let rollups: Vec<_> = rollups
+                    .values()
+                    .filter(|rollup| overlaps(query, rollup))
+                    .cloned()
+                    .collect();
+                if rollups.is_empty() {
+                    return None;
+                }
+                Some(RollupSeries {
+                    labels: labels.clone(),
+                    rollups,
+                })
+            })
+            .collect();
+        series.sort_by(|a, b| a.labels.cmp(&b.labels));
+        series
+    }
+
+    /// Persist the roll-ups alongside the log at `log_path`.
+    ///
+    /// The file is replaced atomically, so a failure leaves the previous roll-ups intact.
+    pub(super) fn save(&self, log_path: &Path) -> io::Result<()> {
+        let streams: Vec<(&Labels, Vec<&Rollup>)> = self
+            .streams
+            .iter()
+            .map(|(labels, rollups)| (labels, rollups.values().collect()))
+            .collect();
+
+        let path = rollups_path(log_path);
+        let mut temporary_path = OsString::from(&path);
+        temporary_path.push(".tmp");
+        fs::write(&temporary_path, serde_json::to_vec(&streams)?)?;
+        fs::rename(temporary_path, path)
+    }
+}
+
+/// Find the first of `rules` that applies to the stream with `labels`.
+pub(super) fn rule_for<'a>(
+    rules: &'a [RollupRule],
+    labels: &InternedLabels,
+) -> Option<&'a RollupRule> {
+    rules.iter().find(|rule| rule.matches(labels))
+}
+
+fn overlaps(query: &Query, rollup: &Rollup) -> bool {
+    match query {
+        Query::Label { .. } | Query::Matchers(_) => true,
+        Query::Range { start, end, .. } => rollup.start < *end && *start < rollup.end,
+    }
+}
+
+fn rollups_path(log_path: &Path) -> PathBuf {
+    let mut path = OsString::from(log_path);
+    path.push(".rollups");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::super::{Event, Query};
+    use super::{Rollup, RollupSeries, Rollups};
+
+    #[test]
+    fn add_and_query() {
+        let mut labels = BTreeMap::new();
+        labels.insert("l1".to_string(), "v1".to_string());
+
+        let mut rollups = Rollups::default();
+        for (timestamp, data) in &[(12, "e1"), (15, "e2"), (11, "e3"), (23, "e4")] {
+            rollups.add(
+                &labels,
+                10,
+                Event::new(*timestamp, data.as_bytes().to_vec()),
+            );
+        }
+
+        let query = Query::Range {
+            matchers: labels.clone(),
+            start: 0,
+            end: 20,
+        };
+        assert_eq!(
+            rollups.query(&query),
+            vec![RollupSeries {
+                labels,
+                rollups: vec![Rollup {
+                    start: 10,
+                    end: 20,
+                    count: 3,
+                    first: Event::new(12, b"e1".to_vec()),
+                    last: Event::new(11, b"e3".to_vec()),
+                }],
+            }]
+        );
+    }
+}
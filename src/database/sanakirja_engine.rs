@@ -0,0 +1,133 @@
+// src/database/sanakirja_engine.rs
+//! A [`StorageEngine`] backed by a [`sanakirja`] B-tree.
+
+use std::cell::Cell;
+use std::io;
+use std::path::Path;
+
+use sanakirja::btree::{self, UDb};
+use sanakirja::{Commit, Env, RootDb};
+
+use super::{Event, Labels, OpenError, PushError, Query, QueryError, RestoreError, StorageEngine};
+
+/// The initial size of the database file (it grows as needed).
+const INITIAL_SIZE: u64 = 1 << 20;
+
+/// The number of concurrent versions (i.e. a writer alongside a reader).
+const VERSIONS: usize = 2;
+
+/// The root slot in which the events B-tree is stored.
+const EVENTS_ROOT: usize = 0;
+
+/// A [`StorageEngine`] storing events in a [`sanakirja`] B-tree.
+///
+/// Each event is stored as a `(labels, event)` JSON record, keyed by its big-endian sequence
+/// number so that the tree iterates in the order events were pushed. There's no index, so queries
+/// scan every record.
+///
+/// Every push is committed in its own transaction, so events are persisted as soon as
+/// [`push`](StorageEngine::push) returns.
+pub struct SanakirjaDatabase {
+    env: Env,
+
+    /// The sequence number of the next event.
+    next_sequence: Cell<u64>,
+}
+
+impl SanakirjaDatabase {
+    fn events<T: RootDb>(txn: &T) -> io::Result<UDb<[u8], [u8]>> {
+        txn.root_db(EVENTS_ROOT)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing events tree"))
+    }
+}
+
+impl StorageEngine for SanakirjaDatabase {
+    fn open(path: &Path) -> Result<Self, OpenError> {
+        let env = Env::new(path, INITIAL_SIZE, VERSIONS)
+            .map_err(to_io_error)
+            .map_err(OpenError::Io)?;
+
+        let mut txn = Env::mut_txn_begin(&env)
+            .map_err(to_io_error)
+            .map_err(OpenError::Io)?;
+        let existing: Option<UDb<[u8], [u8]>> = txn.root_db(EVENTS_ROOT);
+        let next_sequence = if let Some(db) = existing {
+            let mut count = 0;
+            for entry in btree::iter(&txn, &db, None)
+                .map_err(to_io_error)
+                .map_err(RestoreError::Io)
+                .map_err(OpenError::Restore)?
+            {
+                entry
+                    .map_err(to_io_error)
+                    .map_err(RestoreError::Io)
+                    .map_err(OpenError::Restore)?;
+                count += 1;
+            }
+            count
+        } else {
+            let db: UDb<[u8], [u8]> = btree::create_db_(&mut txn)
+                .map_err(to_io_error)
+                .map_err(OpenError::Io)?;
+            txn.set_root(EVENTS_ROOT, db.db);
+            0
+        };
+        txn.commit().map_err(to_io_error).map_err(OpenError::Io)?;
+
+        Ok(Self {
+            env,
+            next_sequence: Cell::new(next_sequence),
+        })
+    }
+
+    fn push(&self, labels: &Labels, event: Event) -> Result<(), PushError> {
+        let record = serde_json::to_vec(&(labels, &event))?;
+        let sequence = self.next_sequence.get();
+
+        let mut txn = Env::mut_txn_begin(&self.env).map_err(to_io_error)?;
+        let mut db = Self::events(&txn)?;
+        btree::put(&mut txn, &mut db, &sequence.to_be_bytes()[..], &record[..])
+            .map_err(to_io_error)?;
+        txn.set_root(EVENTS_ROOT, db.db);
+        txn.commit().map_err(to_io_error)?;
+
+        self.next_sequence.set(sequence + 1);
+        Ok(())
+    }
+
+    fn query(&self, query: &Query) -> Result<Vec<Event>, QueryError> {
+        let txn = Env::txn_begin(&self.env).map_err(to_io_error)?;
+        let db = Self::events(&txn)?;
+
+        let mut events = Vec::new();
+        for entry in btree::iter(&txn, &db, None).map_err(to_io_error)? {
+            let (_, record) = entry.map_err(to_io_error)?;
+            let (labels, event): (Labels, Event) = serde_json::from_slice(record)?;
+            if query.matches_stream(&labels) && query.matches_timestamp(event.timestamp) {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+
+    fn scan(&self, visit: &mut dyn FnMut(Labels, Event) -> io::Result<()>) -> io::Result<()> {
+        let txn = Env::txn_begin(&self.env).map_err(to_io_error)?;
+        let db = Self::events(&txn)?;
+
+        for entry in btree::iter(&txn, &db, None).map_err(to_io_error)? {
+            let (_, record) = entry.map_err(to_io_error)?;
+            let (labels, event) = serde_json::from_slice(record)?;
+            visit(labels, event)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        // Every push is committed immediately, so there's nothing to flush.
+        Ok(())
+    }
+}
+
+fn to_io_error(error: sanakirja::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error)
+}
@@ -0,0 +1,214 @@
+// src/database/shadow.rs
+//! Shadow writes, for evaluating a new storage engine against an existing one.
+//!
+//! A [`Shadow`] engine duplicates every write to a second (shadow) engine, while continuing to
+//! serve queries from the primary. Periodically, a query is also run against the shadow and the
+//! results are compared. Divergences are logged and counted, along with the time spent in each
+//! engine, in a [`ShadowReport`], so a new engine can be trusted before switching to it.
+//!
+//! Failures of the shadow engine are logged and counted, but never fail the operation.
+
+use std::cell::{Cell, RefCell};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use super::{Event, Labels, OpenError, PushError, Query, QueryError, StorageEngine};
+
+/// How often queries are compared by a [`Shadow`] opened with [`StorageEngine::open`].
+const DEFAULT_COMPARE_EVERY: u64 = 10;
+
+/// A [`StorageEngine`] that shadows writes to a primary engine `P` onto a second engine `S`.
+pub struct Shadow<P, S> {
+    primary: P,
+    shadow: S,
+
+    /// Compare every `compare_every`th query.
+    compare_every: u64,
+
+    queries: Cell<u64>,
+    report: RefCell<ShadowReport>,
+}
+
+/// A summary of a [`Shadow`] engine's comparisons.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShadowReport {
+    /// The number of events pushed.
+    pub pushes: u64,
+
+    /// The number of events that couldn't be pushed to the shadow.
+    pub shadow_push_errors: u64,
+
+    /// The number of queries whose results were compared.
+    pub comparisons: u64,
+
+    /// The number of compared queries whose results differed.
+    pub divergences: u64,
+
+    /// The number of compared queries that failed on the shadow.
+    pub shadow_query_errors: u64,
+
+    /// The total time spent pushing to the primary.
+    pub primary_push_time: Duration,
+
+    /// The total time spent pushing to the shadow.
+    pub shadow_push_time: Duration,
+
+    /// The total time the primary spent on compared queries.
+    pub primary_query_time: Duration,
+
+    /// The total time the shadow spent on compared queries.
+    pub shadow_query_time: Duration,
+}
+
+impl<P: StorageEngine, S: StorageEngine> Shadow<P, S> {
+    /// Construct an engine that shadows writes to `primary` onto `shadow`, comparing every
+    /// `compare_every`th query (or none, if `0`).
+    #[must_use]
+    pub fn new(primary: P, shadow: S, compare_every: u64) -> Self {
+        Self {
+            primary,
+            shadow,
+            compare_every,
+            queries: Cell::new(0),
+            report: RefCell::default(),
+        }
+    }
+
+    /// A summary of the comparisons so far.
+    #[must_use]
+    pub fn report(&self) -> ShadowReport {
+        self.report.borrow().clone()
+    }
+}
+
+impl<P: StorageEngine, S: StorageEngine> StorageEngine for Shadow<P, S> {
+    /// Open the primary engine at `path`, and the shadow engine at `path` with a `.shadow`
+    /// suffix, comparing every 10th query.
+    fn open(path: &Path) -> Result<Self, OpenError> {
+        let primary = P::open(path)?;
+        let shadow = S::open(&shadow_path(path))?;
+        Ok(Self::new(primary, shadow, DEFAULT_COMPARE_EVERY))
+    }
+
+    fn push(&self, labels: &Labels, event: Event) -> Result<(), PushError> {
+        let start = Instant::now();
+        self.primary.push(labels, event.clone())?;
+        let primary_time = start.elapsed();
+
+        let start = Instant::now();
+        let result = self.shadow.push(labels, event);
+        let shadow_time = start.elapsed();
+
+        let mut report = self.report.borrow_mut();
+        report.pushes += 1;
+        report.primary_push_time += primary_time;
+        report.shadow_push_time += shadow_time;
+        if let Err(error) = result {
+            warn!("Failed to push to shadow engine: {}", error);
+            report.shadow_push_errors += 1;
+        }
+        Ok(())
+    }
+
+    fn query(&self, query: &Query) -> Result<Vec<Event>, QueryError> {
+        let queries = self.queries.get() + 1;
+        self.queries.set(queries);
+        if self.compare_every == 0 || queries % self.compare_every != 0 {
+            return self.primary.query(query);
+        }
+
+        let start = Instant::now();
+        let events = self.primary.query(query)?;
+        let primary_time = start.elapsed();
+
+        let start = Instant::now();
+        let result = self.shadow.query(query);
+        let shadow_time = start.elapsed();
+
+        let mut report = self.report.borrow_mut();
+        report.comparisons += 1;
+        report.primary_query_time += primary_time;
+        report.shadow_query_time += shadow_time;
+        match result {
+            Ok(shadow_events) if shadow_events == events => {}
+            Ok(shadow_events) => {
+                warn!(
+                    "Shadow engine diverged: query returned {} events, but primary returned {}",
+                    shadow_events.len(),
+                    events.len()
+                );
+                report.divergences += 1;
+            }
+            Err(error) => {
+                warn!("Failed to query shadow engine: {}", error);
+                report.shadow_query_errors += 1;
+            }
+        }
+        Ok(events)
+    }
+
+    fn scan(&self, visit: &mut dyn FnMut(Labels, Event) -> io::Result<()>) -> io::Result<()> {
+        self.primary.scan(visit)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.primary.flush()?;
+        if let Err(error) = self.shadow.flush() {
+            warn!("Failed to flush shadow engine: {}", error);
+        }
+        Ok(())
+    }
+}
+
+fn shadow_path(path: &Path) -> PathBuf {
+    let mut shadow_path = OsString::from(path);
+    shadow_path.push(".shadow");
+    PathBuf::from(shadow_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::test;
+
+    use super::super::{Database, Event, Query, StorageEngine};
+    use super::Shadow;
+
+    #[test]
+    fn compare_queries() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let db = Shadow::new(
+            Database::open(tempdir.path().join("primary"))?,
+            Database::open(tempdir.path().join("shadow"))?,
+            2,
+        );
+
+        let mut labels = BTreeMap::new();
+        labels.insert("l1".to_string(), "v1".to_string());
+        let query = Query::Label {
+            name: "l1".to_string(),
+            value: "v1".to_string(),
+        };
+
+        db.push(&labels, Event::new(0, b"e1".to_vec()))?;
+        for _ in 0..4 {
+            assert_eq!(db.query(&query)?, vec![Event::new(0, b"e1".to_vec())]);
+        }
+        let report = db.report();
+        assert_eq!(report.pushes, 1);
+        assert_eq!(report.comparisons, 2);
+        assert_eq!(report.divergences, 0);
+
+        // Write to the shadow directly, so that it diverges.
+        db.shadow.push(&labels, Event::new(1, b"e2".to_vec()))?;
+        db.query(&query)?;
+        db.query(&query)?;
+        assert_eq!(db.report().divergences, 1);
+        Ok(())
+    }
+}
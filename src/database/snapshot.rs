@@ -0,0 +1,73 @@
+// src/database/snapshot.rs
+//! Snapshots of the event [`Database`](super::Database).
+//!
+//! The log is append-only, so the state of the database at any moment is described entirely by
+//! the length of its log. A snapshot records that length, and queries pinned to the snapshot
+//! ignore any records written after it, so their results are reproducible. Snapshots are
+//! persisted alongside the log, and anything that removes records must preserve those visible to
+//! a snapshot until it's released.
+
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Identifies a snapshot.
+pub type SnapshotId = u64;
+
+/// The snapshots of a database.
+#[derive(Debug, Default)]
+pub(super) struct Snapshots {
+    ids: BTreeSet<SnapshotId>,
+}
+
+impl Snapshots {
+    /// Load the snapshots of the database with its log at `log_path`.
+    pub(super) fn load(log_path: &Path) -> io::Result<Self> {
+        let path = snapshots_path(log_path);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let ids = serde_json::from_slice(&fs::read(path)?)?;
+        Ok(Self { ids })
+    }
+
+    pub(super) fn contains(&self, id: SnapshotId) -> bool {
+        self.ids.contains(&id)
+    }
+
+    pub(super) fn all(&self) -> impl Iterator<Item = SnapshotId> + '_ {
+        self.ids.iter().copied()
+    }
+
+    /// Create a snapshot of a log with length `len`.
+    pub(super) fn create(&mut self, log_path: &Path, len: u64) -> io::Result<SnapshotId> {
+        if self.ids.insert(len) {
+            if let Err(error) = self.save(log_path) {
+                self.ids.remove(&len);
+                return Err(error);
+            }
+        }
+        Ok(len)
+    }
+
+    /// Release the snapshot `id`, returning whether it existed.
+    pub(super) fn release(&mut self, log_path: &Path, id: SnapshotId) -> io::Result<bool> {
+        if !self.ids.remove(&id) {
+            return Ok(false);
+        }
+        self.save(log_path)?;
+        Ok(true)
+    }
+
+    fn save(&self, log_path: &Path) -> io::Result<()> {
+        fs::write(snapshots_path(log_path), serde_json::to_vec(&self.ids)?)
+    }
+}
+
+fn snapshots_path(log_path: &Path) -> PathBuf {
+    let mut path = OsString::from(log_path);
+    path.push(".snapshots");
+    PathBuf::from(path)
+}
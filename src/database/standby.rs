@@ -0,0 +1,184 @@
+// src/database/standby.rs
+//! Warm standby databases for fast failover.
+//!
+//! A [`Standby`] loads and validates the index of a database whose log is being written by
+//! another (primary) process, without writing to it. It can [`refresh`](Standby::refresh) to pick
+//! up new records, answer queries, and be [`promote`](Standby::promote)d to a writable
+//! [`Database`] when the primary fails. Since the index is already loaded, promotion only has to
+//! read records written since the last refresh.
+
+use std::cell::{Cell, RefCell};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use super::horizon::Horizons;
+use super::rollup::Rollups;
+use super::snapshot::Snapshots;
+use super::wal::{self, Wal, WalConfig};
+use super::{
+    matching_offsets, read_events, read_index, Database, Event, Index, OpenError, Query,
+    QueryError, RestoreError,
+};
+
+/// A read-only database that can be promoted to a writable [`Database`].
+pub struct Standby {
+    path: PathBuf,
+    index: Index,
+
+    /// The length of the log covered by `index`.
+    len: u64,
+
+    reader: BufReader<File>,
+}
+
+impl Standby {
+    /// Open the database at `path` in standby mode.
+    ///
+    /// Unlike [`Database::open`], `path` must already exist, and a trailing record that's only
+    /// partially written (e.g. because the primary is writing it) is skipped until the next
+    /// [`refresh`](Self::refresh).
+    ///
+    /// # Errors
+    ///
+    /// If reading or validating the log at `path` fails, a [`RestoreError`] is returned.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RestoreError> {
+        let path = path.as_ref();
+        let reader = File::open(path).map_err(RestoreError::Io)?;
+
+        let mut standby = Self {
+            path: path.to_path_buf(),
+            index: Index::new(),
+            len: 0,
+            reader: BufReader::new(reader),
+        };
+        standby.refresh()?;
+        Ok(standby)
+    }
+
+    /// Index any records written since the last refresh, returning how many there were.
+    ///
+    /// # Errors
+    ///
+    /// If reading or validating the new records fails, a [`RestoreError`] is returned.
+    pub fn refresh(&mut self) -> Result<usize, RestoreError> {
+        read_index(&mut self.reader, &mut self.index, &mut self.len, true)
+    }
+
+    /// Find events matching the given `query`, as of the last refresh.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`](std::io::Error)s encountered when running the query are returned.
+    pub fn query(&mut self, query: &Query) -> Result<Vec<Event>, QueryError> {
+        let offsets = matching_offsets(&self.index, query);
+        read_events(&mut self.reader, &offsets)
+    }
+
+    /// Promote the standby to a writable [`Database`].
+    ///
+    /// Any remaining records are indexed, and a trailing partial record (e.g. from a primary that
+    /// crashed part way through a write) is removed so that new records can be appended.
+    ///
+    /// # Errors
+    ///
+    /// - If reading or validating the remaining records fails, a [`RestoreError`] is returned.
+    /// - Any [`io::Error`](std::io::Error)s that occur when opening the log for writing are
+    ///   returned as [`OpenError::Io`].
+    pub fn promote(mut self) -> Result<Database, OpenError> {
+        // Records in the write-ahead log replace those in the log, so any already indexed are
+        // rewritten unchanged.
+        wal::recover(&self.path)
+            .map_err(RestoreError::Io)
+            .map_err(OpenError::Restore)?;
+        self.refresh().map_err(OpenError::Restore)?;
+
+        let writer = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(OpenError::Io)?;
+        writer.set_len(self.len).map_err(OpenError::Io)?;
+        let snapshots = Snapshots::load(&self.path)
+            .map_err(RestoreError::Io)
+            .map_err(OpenError::Restore)?;
+        let rollups = Rollups::load(&self.path)
+            .map_err(RestoreError::Io)
+            .map_err(OpenError::Restore)?;
+        let horizons = Horizons::load(&self.path)
+            .map_err(RestoreError::Io)
+            .map_err(OpenError::Restore)?;
+
+        Ok(Database {
+            path: self.path,
+            index: RefCell::new(self.index),
+            generation: Cell::new(0),
+            len: Cell::new(self.len),
+            snapshots: RefCell::new(snapshots),
+            rollups: RefCell::new(rollups),
+            horizons: RefCell::new(horizons),
+            wal: RefCell::new(Wal::open(&self.path, &WalConfig::default()).map_err(OpenError::Io)?),
+            writer: RefCell::new(BufWriter::new(writer)),
+            reader: RefCell::new(self.reader),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    use crate::test;
+
+    use super::super::{Database, Event, Query};
+    use super::Standby;
+
+    #[test]
+    fn follow_and_promote() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("data");
+        let labels = [("l1".to_string(), "v1".to_string())]
+            .iter()
+            .cloned()
+            .collect();
+        let query = Query::Label {
+            name: "l1".to_string(),
+            value: "v1".to_string(),
+        };
+
+        let primary = Database::open(&path)?;
+        primary.push(&labels, Event::new(0, b"e1".to_vec()))?;
+        primary.flush()?;
+
+        let mut standby = Standby::open(&path)?;
+        assert_eq!(standby.query(&query)?, vec![Event::new(0, b"e1".to_vec())]);
+
+        primary.push(&labels, Event::new(1, b"e2".to_vec()))?;
+        primary.flush()?;
+        assert_eq!(standby.refresh()?, 1);
+
+        // Simulate the primary crashing part way through a write.
+        std::mem::forget(primary);
+        OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(br#"[{"l1":"v1"},{"times"#)?;
+        assert_eq!(standby.refresh()?, 0);
+
+        let database = standby.promote()?;
+        database.push(&labels, Event::new(2, b"e3".to_vec()))?;
+        database.close()?;
+
+        let database = Database::open(&path)?;
+        assert_eq!(
+            database.query(&query)?,
+            vec![
+                Event::new(0, b"e1".to_vec()),
+                Event::new(1, b"e2".to_vec()),
+                Event::new(2, b"e3".to_vec()),
+            ]
+        );
+
+        Ok(())
+    }
+}
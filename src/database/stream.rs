@@ -0,0 +1,109 @@
+// src/database/stream.rs
+//! Handles for pushing events into a single stream.
+
+use std::cell::Cell;
+use std::io::Write;
+
+use super::{Database, Event, Labels, PushError};
+
+/// A handle for pushing events into the stream identified by some labels.
+///
+/// Handles are created by [`Database::stream`]. The stream's labels are serialized once, and its
+/// index entry is looked up on the first push, so pushing through a handle avoids the per-event
+/// overhead of [`Database::push`]. This makes handles (and [`push_batch`](Self::push_batch)) the
+/// better choice for hot ingestion paths.
+pub struct StreamHandle<'a> {
+    database: &'a Database,
+    labels: &'a Labels,
+
+    /// The start of every record in the stream: `[`, the serialized labels, and `,`.
+    prefix: Vec<u8>,
+
+    /// The stream's ID in the index, and the index generation in which it's valid.
+    id: Cell<Option<(u64, usize)>>,
+}
+
+impl<'a> StreamHandle<'a> {
+    pub(super) fn new(database: &'a Database, labels: &'a Labels) -> Self {
+        let mut prefix = vec![b'['];
+        // `unwrap` is OK because string maps always serialize successfully.
+        serde_json::to_writer(&mut prefix, labels).unwrap();
+        prefix.push(b',');
+
+        Self {
+            database,
+            labels,
+            prefix,
+            id: Cell::new(None),
+        }
+    }
+
+    /// The labels of the stream.
+    #[must_use]
+    pub fn labels(&self) -> &Labels {
+        self.labels
+    }
+
+    /// Push a new `event` into the stream.
+    ///
+    /// See [`Database::push`].
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`](std::io::Error)s encountered when writing the event are returned. The
+    /// event is not added to the database in that case.
+    pub fn push(&self, event: Event) -> Result<(), PushError> {
+        self.push_batch(std::iter::once(event))
+    }
+
+    /// Push several `events` into the stream, in order.
+    ///
+    /// The events are serialized together and written to the log in a single write.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`](std::io::Error)s encountered when writing the events are returned. None
+    /// of the events are added to the database in that case.
+    pub fn push_batch(&self, events: impl IntoIterator<Item = Event>) -> Result<(), PushError> {
+        let mut records = Vec::new();
+        let mut entries = Vec::new();
+        for event in events {
+            entries.push((event.timestamp, records.len() as u64));
+            records.extend_from_slice(&self.prefix);
+            serde_json::to_writer(&mut records, &event)?;
+            records.extend_from_slice(b"]\n");
+        }
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let database = self.database;
+        let offset = database.len.get();
+        database.wal.borrow_mut().append(offset, &records)?;
+        database.writer.borrow_mut().write_all(&records)?;
+        database.len.set(offset + records.len() as u64);
+
+        // The index is rebuilt when the log is rewritten, which invalidates stream IDs.
+        let generation = database.generation.get();
+        let mut id = match self.id.get() {
+            Some((id_generation, id)) if id_generation == generation => Some(id),
+            _ => None,
+        };
+
+        let mut index = database.index.borrow_mut();
+        for (timestamp, record_offset) in entries {
+            let record_offset = offset + record_offset;
+            match id {
+                Some(id) => index.push_to(id, timestamp, record_offset),
+                None => id = Some(index.push(self.labels, timestamp, record_offset)),
+            }
+        }
+        self.id.set(id.map(|id| (generation, id)));
+        drop(index);
+
+        if database.wal.borrow().needs_checkpoint() {
+            database.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+// src/database/wal.rs
+//! A write-ahead log, making buffered pushes durable.
+//!
+//! [`Database`](super::Database) buffers writes to its log, so without the write-ahead log (WAL) a
+//! crash would lose every event pushed since the last [`flush`](super::Database::flush). Instead,
+//! each record is first appended to the WAL (at `<log>.wal`) without buffering, prefixed by the
+//! offset it will have in the log. A background thread syncs the WAL to disk every
+//! [`WalConfig::sync_interval`], so pushes are durable shortly after they return.
+//!
+//! When the WAL grows past [`WalConfig::checkpoint_size`], or the database is flushed, the log is
+//! flushed and synced and the WAL is emptied (a checkpoint). When a database is opened, any
+//! records left in the WAL are replayed into the log, replacing whatever part of them the log
+//! already contains. A trailing partial record in the WAL (from a crash during a push) is ignored.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::warn;
+
+/// Configuration for a [`Database`](super::Database)'s write-ahead log.
+#[derive(Clone, Debug)]
+pub struct WalConfig {
+    /// How often the WAL is synced to disk, if it has been written. Pushes may be lost if the
+    /// machine crashes within this long of them returning.
+    pub sync_interval: Duration,
+
+    /// The size of the WAL, in bytes, at which the log is synced and the WAL emptied.
+    pub checkpoint_size: u64,
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        Self {
+            sync_interval: Duration::from_millis(100),
+            checkpoint_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+/// An open write-ahead log.
+pub(super) struct Wal {
+    file: File,
+    len: u64,
+    checkpoint_size: u64,
+    syncer: Syncer,
+}
+
+impl Wal {
+    /// Open the (already [`recover`]ed) WAL of the log at `log_path`.
+    pub(super) fn open(log_path: &Path, config: &WalConfig) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(wal_path(log_path))?;
+        let len = file.metadata()?.len();
+        let syncer = Syncer::start(file.try_clone()?, config.sync_interval);
+        Ok(Self {
+            file,
+            len,
+            checkpoint_size: config.checkpoint_size,
+            syncer,
+        })
+    }
+
+    /// Append the newline-terminated `records` that will be written to the log at `offset`.
+    pub(super) fn append(&mut self, offset: u64, records: &[u8]) -> io::Result<()> {
+        let mut entries = Vec::with_capacity(records.len() + 16);
+        let mut start = 0;
+        for (end, _) in records
+            .iter()
+            .enumerate()
+            .filter(|(_, byte)| **byte == b'\n')
+        {
+            write!(entries, "{} ", offset + start as u64)?;
+            entries.extend_from_slice(&records[start..=end]);
+            start = end + 1;
+        }
+
+        self.file.write_all(&entries)?;
+        self.len += entries.len() as u64;
+        self.syncer.state.dirty.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Whether the WAL has grown enough that a checkpoint should be taken.
+    pub(super) fn needs_checkpoint(&self) -> bool {
+        self.len >= self.checkpoint_size
+    }
+
+    /// Empty the WAL, once the log has been synced.
+    pub(super) fn clear(&mut self) -> io::Result<()> {
+        if self.len == 0 {
+            return Ok(());
+        }
+        self.file.set_len(0)?;
+        self.file.sync_data()?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// Replay the WAL of the log at `log_path` into the log, then empty the WAL.
+///
+/// Returns the number of records replayed.
+pub(super) fn recover(log_path: &Path) -> io::Result<usize> {
+    let wal_path = wal_path(log_path);
+    let mut reader = match File::open(&wal_path) {
+        Ok(file) => BufReader::new(file),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(error) => return Err(error),
+    };
+
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    let mut len = log.metadata()?.len();
+    let mut records = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        reader.read_until(b'\n', &mut line)?;
+        if line.last() != Some(&b'\n') {
+            // The end of the WAL, or a partial record from a crash during a push.
+            break;
+        }
+        let (offset, record) = match parse_entry(&line[..line.len() - 1]) {
+            Some(entry) => entry,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("write-ahead log {} is corrupt", wal_path.display()),
+                ))
+            }
+        };
+
+        // Everything in the log after the first record came from the WAL, so it can be replaced.
+        // A record can also be superseded by a later one at the same offset, if writing it to the
+        // log failed.
+        if offset > len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "write-ahead log {} has a record at offset {}, past the end of the log ({})",
+                    wal_path.display(),
+                    offset,
+                    len
+                ),
+            ));
+        }
+        if records == 0 || offset < len {
+            log.set_len(offset)?;
+            len = offset;
+        }
+
+        log.write_all(record)?;
+        log.write_all(b"\n")?;
+        len += record.len() as u64 + 1;
+        records += 1;
+    }
+
+    log.sync_data()?;
+    OpenOptions::new().write(true).open(&wal_path)?.set_len(0)?;
+    Ok(records)
+}
+
+/// Parse a WAL entry into the offset and (newline-less) record.
+fn parse_entry(line: &[u8]) -> Option<(u64, &[u8])> {
+    let space = line.iter().position(|byte| *byte == b' ')?;
+    let offset = std::str::from_utf8(&line[..space]).ok()?.parse().ok()?;
+    let record = &line[space + 1..];
+    if record.is_empty() {
+        return None;
+    }
+    Some((offset, record))
+}
+
+fn wal_path(log_path: &Path) -> PathBuf {
+    let mut path = OsString::from(log_path);
+    path.push(".wal");
+    PathBuf::from(path)
+}
+
+/// A background thread that syncs the WAL when it has been written.
+struct Syncer {
+    state: Arc<SyncState>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct SyncState {
+    dirty: AtomicBool,
+    stopped: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl Syncer {
+    fn start(file: File, interval: Duration) -> Self {
+        let state = Arc::new(SyncState {
+            dirty: AtomicBool::new(false),
+            stopped: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+
+        let thread = {
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                let mut stopped = state.stopped.lock().expect("WAL sync lock poisoned");
+                loop {
+                    stopped = state
+                        .wake
+                        .wait_timeout(stopped, interval)
+                        .expect("WAL sync lock poisoned")
+                        .0;
+                    if state.dirty.swap(false, Ordering::AcqRel) {
+                        if let Err(error) = file.sync_data() {
+                            warn!("Failed to sync write-ahead log: {}", error);
+                            state.dirty.store(true, Ordering::Release);
+                        }
+                    }
+                    if *stopped {
+                        break;
+                    }
+                }
+            })
+        };
+
+        Self {
+            state,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Syncer {
+    fn drop(&mut self) {
+        *self.state.stopped.lock().expect("WAL sync lock poisoned") = true;
+        self.state.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("Write-ahead log sync thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test;
+
+    use super::{recover, wal_path};
+
+    #[test]
+    fn replay() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let log_path = tempdir.path().join("data");
+
+        // The first record was synced by a checkpoint, the second only partially flushed.
+        fs::write(&log_path, "r0\nr1-part")?;
+        fs::write(
+            wal_path(&log_path),
+            "3 r1-full\n11 r2-failed\n11 r2\n14 r3-pa",
+        )?;
+
+        assert_eq!(recover(&log_path)?, 3);
+        assert_eq!(fs::read_to_string(&log_path)?, "r0\nr1-full\nr2\n");
+        assert_eq!(fs::read(wal_path(&log_path))?.len(), 0);
+
+        // Recovering again (e.g. after a crash during recovery) changes nothing.
+        assert_eq!(recover(&log_path)?, 0);
+        assert_eq!(fs::read_to_string(&log_path)?, "r0\nr1-full\nr2\n");
+
+        fs::write(wal_path(&log_path), "99 r9\n")?;
+        assert!(recover(&log_path).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+// src/jobs/mod.rs
+//! Tracking of long-running background work.
+//!
+//! Maintenance work (e.g. retention, index compaction, and exports) is registered with [`Jobs`] as
+//! it starts, and reports its progress and outcome through the returned [`Job`] handle. This lets
+//! operators see what's running, how long it took, and why it failed, and request cancellation.
+//!
+//! Cancellation is cooperative: [`Jobs::cancel`] only sets a flag, which the job should check with
+//! [`Job::is_cancelled`] at convenient points.
+//!
+//! Finished jobs can also be reported elsewhere (e.g. to a chat webhook) with a
+//! [`Notifier`](notify::Notifier).
+//!
+//! Saved queries can be run on a schedule as jobs, with their results summarised to a webhook or
+//! by email (see [`report`]).
+
+pub mod notify;
+pub mod report;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use self::notify::Notifier;
+
+/// The number of finished jobs that are remembered.
+const FINISHED_JOBS_CAPACITY: usize = 100;
+
+/// The state of a job.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// The job is still running.
+    Running,
+
+    /// The job finished successfully.
+    Completed,
+
+    /// The job failed.
+    Failed,
+
+    /// The job stopped after being cancelled.
+    Cancelled,
+}
+
+impl FromStr for JobState {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "running" => Ok(Self::Running),
+            "completed" => Ok(Self::Completed),
+            "failed" => Ok(Self::Failed),
+            "cancelled" => Ok(Self::Cancelled),
+            _ => Err(format!(
+                "invalid job state `{}`: must be `running`, `completed`, `failed`, or `cancelled`",
+                input
+            )),
+        }
+    }
+}
+
+/// The status of a job.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct JobStatus {
+    /// The job's ID.
+    pub id: u64,
+
+    /// The kind of job, e.g. `retention`.
+    pub kind: &'static str,
+
+    /// A description of what the job is doing.
+    pub description: String,
+
+    /// The job's state.
+    pub state: JobState,
+
+    /// Whether cancellation has been requested.
+    pub cancel_requested: bool,
+
+    /// The fraction of the job that's been completed, if known.
+    pub progress: Option<f64>,
+
+    /// When the job started, in milliseconds since the Unix epoch.
+    pub started_at: u64,
+
+    /// When the job finished, in milliseconds since the Unix epoch.
+    pub finished_at: Option<u64>,
+
+    /// The error the job failed with.
+    pub error: Option<String>,
+
+    /// A summary of what the job did.
+    pub result: Option<serde_json::Value>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    status: Mutex<JobStatus>,
+    cancelled: AtomicBool,
+}
+
+/// A registry of background jobs.
+#[derive(Debug, Default)]
+pub struct Jobs {
+    next_id: AtomicU64,
+    entries: Mutex<BTreeMap<u64, Arc<Entry>>>,
+    notifier: Option<Arc<dyn Notifier>>,
+}
+
+impl Jobs {
+    /// Construct a registry that calls `notifier` whenever a job finishes.
+    #[must_use]
+    pub fn with_notifier(notifier: Arc<dyn Notifier>) -> Self {
+        Self {
+            notifier: Some(notifier),
+            ..Self::default()
+        }
+    }
+
+    /// Register a new running job.
+    #[must_use]
+    pub fn start(&self, kind: &'static str, description: String) -> Job {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let entry = Arc::new(Entry {
+            status: Mutex::new(JobStatus {
+                id,
+                kind,
+                description,
+                state: JobState::Running,
+                cancel_requested: false,
+                progress: None,
+                started_at: now_millis(),
+                finished_at: None,
+                error: None,
+                result: None,
+            }),
+            cancelled: AtomicBool::new(false),
+        });
+
+        let mut entries = self.entries.lock().expect("jobs lock poisoned");
+        entries.insert(id, Arc::clone(&entry));
+        Self::evict(&mut entries);
+
+        Job {
+            entry,
+            notifier: self.notifier.clone(),
+        }
+    }
+
+    /// The status of every known job, oldest first.
+    #[must_use]
+    pub fn list(&self) -> Vec<JobStatus> {
+        self.entries
+            .lock()
+            .expect("jobs lock poisoned")
+            .values()
+            .map(|entry| entry.status())
+            .collect()
+    }
+
+    /// The status of the job with the given `id`, if it's known.
+    #[must_use]
+    pub fn get(&self, id: u64) -> Option<JobStatus> {
+        self.entries
+            .lock()
+            .expect("jobs lock poisoned")
+            .get(&id)
+            .map(|entry| entry.status())
+    }
+
+    /// Request cancellation of the job with the given `id`, returning its status if it's known.
+    pub fn cancel(&self, id: u64) -> Option<JobStatus> {
+        let entries = self.entries.lock().expect("jobs lock poisoned");
+        let entry = entries.get(&id)?;
+
+        let mut status = entry.status.lock().expect("job lock poisoned");
+        if status.state == JobState::Running {
+            entry.cancelled.store(true, Ordering::Relaxed);
+            status.cancel_requested = true;
+        }
+        Some(status.clone())
+    }
+
+    /// Forget the oldest finished jobs, if there are too many.
+    fn evict(entries: &mut BTreeMap<u64, Arc<Entry>>) {
+        let finished: Vec<_> = entries
+            .iter()
+            .filter(|(_, entry)| entry.status().state != JobState::Running)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(FINISHED_JOBS_CAPACITY))
+        {
+            entries.remove(id);
+        }
+    }
+}
+
+impl Entry {
+    fn status(&self) -> JobStatus {
+        self.status.lock().expect("job lock poisoned").clone()
+    }
+}
+
+/// A handle used by a running job to report its progress and outcome.
+///
+/// If the handle is dropped without calling one of [`complete`](Self::complete),
+/// [`fail`](Self::fail), or [`cancelled`](Self::cancelled), the job is marked as failed.
+#[derive(Debug)]
+pub struct Job {
+    entry: Arc<Entry>,
+    notifier: Option<Arc<dyn Notifier>>,
+}
+
+impl Job {
+    /// The job's ID.
+    #[must_use]
+    pub fn id(&self) -> u64 {
+        self.entry.status.lock().expect("job lock poisoned").id
+    }
+
+    /// Check whether cancellation has been requested.
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.entry.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Report the fraction of the job that's been completed.
+    pub fn set_progress(&self, progress: f64) {
+        self.entry
+            .status
+            .lock()
+            .expect("job lock poisoned")
+            .progress = Some(progress);
+    }
+
+    /// Mark the job as completed, with a summary of what it did.
+    pub fn complete(self, result: serde_json::Value) {
+        self.finish(JobState::Completed, None, Some(result));
+    }
+
+    /// Mark the job as failed with `error`.
+    pub fn fail(self, error: impl Display) {
+        self.finish(JobState::Failed, Some(error.to_string()), None);
+    }
+
+    /// Mark the job as having stopped in response to cancellation.
+    pub fn cancelled(self) {
+        self.finish(JobState::Cancelled, None, None);
+    }
+
+    fn finish(&self, state: JobState, error: Option<String>, result: Option<serde_json::Value>) {
+        let status = {
+            let mut status = self.entry.status.lock().expect("job lock poisoned");
+            if status.state != JobState::Running {
+                return;
+            }
+            status.state = state;
+            status.finished_at = Some(now_millis());
+            status.error = error;
+            status.result = result;
+            if state == JobState::Completed {
+                status.progress = Some(1.0);
+            }
+            status.clone()
+        };
+
+        if let Some(notifier) = &self.notifier {
+            notifier.notify(&status);
+        }
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        self.finish(
+            JobState::Failed,
+            Some("job stopped without reporting an outcome".to_string()),
+            None,
+        );
+    }
+}
+
+fn now_millis() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    u64::try_from(now.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::notify::Notifier;
+    use super::{JobState, JobStatus, Jobs};
+
+    #[derive(Debug, Default)]
+    struct RecordingNotifier(Mutex<Vec<JobState>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, status: &JobStatus) {
+            self.0.lock().unwrap().push(status.state);
+        }
+    }
+
+    #[test]
+    fn job_lifecycle() {
+        let jobs = Jobs::default();
+
+        let job = jobs.start("test", "first".to_string());
+        let id = job.id();
+        job.set_progress(0.5);
+        assert_eq!(jobs.get(id).unwrap().state, JobState::Running);
+        assert_eq!(jobs.get(id).unwrap().progress, Some(0.5));
+
+        job.complete(serde_json::json!({ "removed": 3 }));
+        let status = jobs.get(id).unwrap();
+        assert_eq!(status.state, JobState::Completed);
+        assert_eq!(status.result, Some(serde_json::json!({ "removed": 3 })));
+        assert!(status.finished_at.is_some());
+
+        let job = jobs.start("test", "second".to_string());
+        assert!(jobs.cancel(job.id()).unwrap().cancel_requested);
+        assert!(job.is_cancelled());
+        let id = job.id();
+        job.cancelled();
+        assert_eq!(jobs.get(id).unwrap().state, JobState::Cancelled);
+
+        let job = jobs.start("test", "third".to_string());
+        let id = job.id();
+        drop(job);
+        assert_eq!(jobs.get(id).unwrap().state, JobState::Failed);
+
+        assert_eq!(jobs.list().len(), 3);
+        assert!(jobs.get(99).is_none());
+    }
+
+    #[test]
+    fn evicts_old_finished_jobs() {
+        let jobs = Jobs::default();
+        let running = jobs.start("test", "running".to_string());
+        for _ in 0..super::FINISHED_JOBS_CAPACITY + 10 {
+            jobs.start("test", "finished".to_string()).cancelled();
+        }
+
+        // Finished jobs are evicted when the next job starts, so the last is still remembered.
+        let list = jobs.list();
+        assert_eq!(list.len(), super::FINISHED_JOBS_CAPACITY + 2);
+        assert_eq!(list[0].id, running.id());
+    }
+
+    #[test]
+    fn notifies_finished_jobs() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let jobs = Jobs::with_notifier(Arc::clone(&notifier) as Arc<dyn Notifier>);
+
+        let job = jobs.start("test", "first".to_string());
+        job.set_progress(0.5);
+        assert!(notifier.0.lock().unwrap().is_empty());
+        job.complete(serde_json::Value::Null);
+
+        jobs.start("test", "second".to_string()).fail("oops");
+
+        assert_eq!(
+            *notifier.0.lock().unwrap(),
+            vec![JobState::Completed, JobState::Failed]
+        );
+    }
+}
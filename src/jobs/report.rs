@@ -0,0 +1,631 @@
+// src/jobs/report.rs
+//! Scheduled reports of saved queries.
+//!
+//! A [`Report`] periodically runs a saved query against the log database as a `report` job,
+//! summarises the results (the number of matching lines, the most common values of a field, and
+//! some sample lines), and delivers the summary to a webhook or by email. Reports are configured
+//! with JSON like:
+//!
+//! ```json
+//! {
+//!   "name": "payment errors",
+//!   "every": "1d",
+//!   "query": {
+//!     "match": ["namespace=payments"],
+//!     "contains": "error",
+//!     "group_by": "code",
+//!     "top": 5,
+//!     "samples": 3
+//!   },
+//!   "target": {"webhook": "https://hooks.slack.com/services/..."}
+//! }
+//! ```
+//!
+//! - `query.contains` or `query.regex` optionally filter the matching lines, and
+//!   `query.group_by` optionally names a JSON field to count the `top` values of (see
+//!   [`projection::field`]).
+//! - Webhooks are posted `{"text": "...", "report": {...}}`, in the same Slack-compatible format
+//!   as [job notifications](super::notify).
+//! - `"target": {"smtp": {"server": "mail:25", "from": "...", "to": ["..."]}}` emails the summary
+//!   instead. Only plain SMTP is spoken (without TLS or authentication), so this is intended for a
+//!   local relay. Each connection, read, and write with the relay times out after 30 seconds.
+//!
+//! Log entries aren't timestamped, so each run covers the streams written to since the last
+//! successful run (see [`Database::query_selection`]), including their earlier entries.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_std::io::prelude::{BufReadExt, WriteExt};
+use async_std::io::{timeout, BufReader};
+use async_std::net::TcpStream;
+use async_std::sync::RwLock;
+use async_std::task;
+use log::warn;
+
+use crate::log_database::filter::LineFilter;
+use crate::log_database::projection;
+use crate::log_database::retention::parse_duration;
+use crate::log_database::{Database, Selection};
+
+use super::notify::redact_url;
+use super::Jobs;
+
+/// The kind of job used for reports.
+pub const REPORT_JOB_KIND: &str = "report";
+
+/// How long to wait to connect to, read from, or write to an SMTP relay.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A saved query, run on a schedule and delivered to a [`Target`].
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// The name of the report, used as the job description and in the summary.
+    pub name: String,
+
+    /// How often to run the report.
+    pub every: Duration,
+
+    /// The query to run.
+    pub query: SavedQuery,
+
+    /// Where to deliver the summary.
+    pub target: Target,
+}
+
+/// A query whose results are summarised by a [`Report`].
+#[derive(Clone, Debug)]
+pub struct SavedQuery {
+    /// The `(key, value)` pairs that a stream's metadata must contain to be queried.
+    pub matchers: Vec<(String, String)>,
+
+    /// A filter that lines must match to be counted, if any.
+    pub filter: Option<LineFilter>,
+
+    /// A JSON field to count the most common values of, if any.
+    pub group_by: Option<String>,
+
+    /// The number of most common values to report.
+    pub top: usize,
+
+    /// The number of sample lines to report.
+    pub samples: usize,
+}
+
+/// Where a [`Report`] is delivered.
+#[derive(Clone, Debug)]
+pub enum Target {
+    /// Post the summary to a (Slack-compatible) webhook URL.
+    Webhook(surf::Url),
+
+    /// Email the summary through an SMTP relay.
+    Smtp(Smtp),
+}
+
+/// An SMTP relay, and the addresses to send from and to.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Smtp {
+    /// The `host:port` of the relay.
+    pub server: String,
+
+    /// The sender's address.
+    pub from: String,
+
+    /// The recipients' addresses.
+    pub to: Vec<String>,
+}
+
+/// The summarised results of a [`Report`].
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Summary {
+    /// The name of the report.
+    pub name: String,
+
+    /// The number of matching lines.
+    pub count: usize,
+
+    /// The field whose values were counted, if any.
+    pub group_by: Option<String>,
+
+    /// The most common values of `group_by`, most common first.
+    pub top_groups: Vec<Group>,
+
+    /// The first few matching lines.
+    pub samples: Vec<String>,
+}
+
+/// A value of a [`Summary::group_by`] field, and how many lines had it.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Group {
+    /// The field's value.
+    pub value: String,
+
+    /// The number of matching lines with the value.
+    pub count: usize,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReportConfig {
+    name: String,
+    every: String,
+    query: QueryConfig,
+    target: TargetConfig,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueryConfig {
+    #[serde(rename = "match")]
+    matchers: Vec<String>,
+    #[serde(default)]
+    contains: Option<String>,
+    #[serde(default)]
+    regex: Option<String>,
+    #[serde(default)]
+    group_by: Option<String>,
+    #[serde(default = "default_top")]
+    top: usize,
+    #[serde(default = "default_samples")]
+    samples: usize,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum TargetConfig {
+    Webhook(String),
+    Smtp(Smtp),
+}
+
+fn default_top() -> usize {
+    5
+}
+
+fn default_samples() -> usize {
+    3
+}
+
+impl FromStr for Report {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: ReportConfig =
+            serde_json::from_str(s).map_err(|error| format!("invalid report: {}", error))?;
+        let error = |message: String| format!("invalid report `{}`: {}", config.name, message);
+
+        let every = parse_duration(&config.every).map_err(error)?;
+        if every == Duration::from_secs(0) {
+            return Err(error("`every` must be greater than 0".to_string()));
+        }
+
+        let matchers = config
+            .query
+            .matchers
+            .iter()
+            .map(|matcher| {
+                let mut parts = matcher.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) if !key.is_empty() => {
+                        Ok((key.to_string(), value.to_string()))
+                    }
+                    _ => Err(error(format!(
+                        "invalid matcher `{}`, expected key=value",
+                        matcher
+                    ))),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if matchers.is_empty() {
+            return Err(error("at least one matcher is required".to_string()));
+        }
+
+        let filter = match (&config.query.contains, &config.query.regex) {
+            (None, None) => None,
+            (Some(contains), None) => Some(LineFilter::Contains(contains.clone())),
+            (None, Some(regex)) => Some(LineFilter::Regex(
+                regex::Regex::new(regex).map_err(|regex_error| error(regex_error.to_string()))?,
+            )),
+            (Some(_), Some(_)) => {
+                return Err(error(
+                    "only one of `contains` and `regex` may be given".to_string(),
+                ))
+            }
+        };
+
+        let target = match &config.target {
+            TargetConfig::Webhook(url) => Target::Webhook(
+                surf::Url::parse(url)
+                    .map_err(|url_error| error(format!("invalid URL {}: {}", url, url_error)))?,
+            ),
+            TargetConfig::Smtp(smtp) if smtp.to.is_empty() => {
+                return Err(error("at least one recipient is required".to_string()))
+            }
+            TargetConfig::Smtp(smtp) => Target::Smtp(smtp.clone()),
+        };
+
+        Ok(Self {
+            name: config.name.clone(),
+            every,
+            query: SavedQuery {
+                matchers,
+                filter,
+                group_by: config.query.group_by.clone(),
+                top: config.query.top,
+                samples: config.query.samples,
+            },
+            target,
+        })
+    }
+}
+
+impl Report {
+    /// Run the report every [`every`](Self::every), as a [`REPORT_JOB_KIND`] job in `jobs`.
+    ///
+    /// Failed runs are logged and reported through the job, and the next run covers the period
+    /// since the last successful run. This never returns.
+    pub async fn run(self, database: Arc<RwLock<Database>>, jobs: Arc<Jobs>) {
+        let mut since = SystemTime::now();
+        loop {
+            task::sleep(self.every).await;
+
+            let now = SystemTime::now();
+            let job = jobs.start(REPORT_JOB_KIND, self.name.clone());
+            let result = async {
+                let summary = self.summarise(&*database.read().await, since)?;
+                self.target.deliver(&summary).await?;
+                Ok::<_, io::Error>(summary)
+            }
+            .await;
+
+            match result {
+                Ok(summary) => {
+                    since = now;
+                    job.complete(serde_json::json!({
+                        "count": summary.count,
+                        "target": self.target.to_string(),
+                    }));
+                }
+                Err(error) => {
+                    warn!("Report `{}` failed: {}", self.name, error);
+                    job.fail(error);
+                }
+            }
+        }
+    }
+
+    /// Run the report's query over the streams written to since `since`, and summarise the
+    /// results.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` from the query.
+    pub fn summarise(&self, database: &Database, since: SystemTime) -> io::Result<Summary> {
+        let selection = Selection {
+            matchers: self.query.matchers.clone(),
+            start: Some(since),
+            ..Selection::default()
+        };
+        let (lines, _) = database.query_selection(&selection)?;
+
+        let mut count = 0;
+        let mut groups = HashMap::<_, usize>::new();
+        let mut samples = Vec::new();
+        let matching = lines.into_iter().filter(|line| {
+            self.query
+                .filter
+                .as_ref()
+                .map_or(true, |filter| filter.matches(line))
+        });
+        for line in matching {
+            count += 1;
+            if let Some(group_by) = &self.query.group_by {
+                if let Some(value) = projection::field(&line, group_by) {
+                    *groups.entry(value).or_default() += 1;
+                }
+            }
+            if samples.len() < self.query.samples {
+                samples.push(line);
+            }
+        }
+
+        let mut top_groups: Vec<_> = groups
+            .into_iter()
+            .map(|(value, count)| Group { value, count })
+            .collect();
+        top_groups.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        top_groups.truncate(self.query.top);
+
+        Ok(Summary {
+            name: self.name.clone(),
+            count,
+            group_by: self.query.group_by.clone(),
+            top_groups,
+            samples,
+        })
+    }
+}
+
+impl Summary {
+    /// A human-readable rendering of the summary.
+    #[must_use]
+    pub fn text(&self) -> String {
+        let mut text = format!("Report {}: {} matching lines", self.name, self.count);
+        if let Some(group_by) = &self.group_by {
+            let groups: Vec<_> = self
+                .top_groups
+                .iter()
+                .map(|group| format!("{} ({})", group.value, group.count))
+                .collect();
+            if !groups.is_empty() {
+                text.push_str(&format!("\nTop {}: {}", group_by, groups.join(", ")));
+            }
+        }
+        for sample in &self.samples {
+            text.push_str(&format!("\n> {}", sample));
+        }
+        text
+    }
+}
+
+impl Target {
+    /// Deliver `summary` to the target.
+    ///
+    /// # Errors
+    ///
+    /// Any failure to deliver the summary is returned as an `io::Error`.
+    pub async fn deliver(&self, summary: &Summary) -> io::Result<()> {
+        match self {
+            Self::Webhook(url) => {
+                let body = serde_json::json!({
+                    "text": summary.text(),
+                    "report": summary,
+                });
+                let response = surf::post(url.clone())
+                    .body(body)
+                    .await
+                    .map_err(|error| io::Error::new(io::ErrorKind::Other, error.to_string()))?;
+                if response.status().is_success() {
+                    Ok(())
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("webhook responded with status {}", response.status()),
+                    ))
+                }
+            }
+            Self::Smtp(smtp) => {
+                smtp.send(&format!("Report: {}", summary.name), &summary.text())
+                    .await
+            }
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Webhook(url) => write!(f, "webhook {}", redact_url(url)),
+            Self::Smtp(smtp) => write!(f, "smtp {} to {}", smtp.server, smtp.to.join(", ")),
+        }
+    }
+}
+
+impl Smtp {
+    /// Send a plain text email with `subject` and `body` through the relay.
+    ///
+    /// # Errors
+    ///
+    /// Any connection error, timeout, or unexpected reply from the relay, is returned as an
+    /// `io::Error`.
+    pub async fn send(&self, subject: &str, body: &str) -> io::Result<()> {
+        let mut stream = timeout(SMTP_TIMEOUT, TcpStream::connect(&self.server)).await?;
+        let mut reader = BufReader::new(stream.clone());
+
+        reply(&mut reader, &[220]).await?;
+        command(&mut stream, &mut reader, "EHLO localhost", &[250]).await?;
+        command(
+            &mut stream,
+            &mut reader,
+            &format!("MAIL FROM:<{}>", self.from),
+            &[250],
+        )
+        .await?;
+        for to in &self.to {
+            // `251` means the relay will forward the message to another server.
+            command(
+                &mut stream,
+                &mut reader,
+                &format!("RCPT TO:<{}>", to),
+                &[250, 251],
+            )
+            .await?;
+        }
+        command(&mut stream, &mut reader, "DATA", &[354]).await?;
+
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            self.from,
+            self.to.join(", "),
+            subject
+        );
+        for line in body.lines() {
+            // Lines starting with `.` are escaped, so they can't end the message early.
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message.push('.');
+        command(&mut stream, &mut reader, &message, &[250]).await?;
+        command(&mut stream, &mut reader, "QUIT", &[221]).await
+    }
+}
+
+/// Send an SMTP `command`, and check that the reply has one of the `expected` codes.
+async fn command(
+    stream: &mut TcpStream,
+    reader: &mut BufReader<TcpStream>,
+    command: &str,
+    expected: &[u16],
+) -> io::Result<()> {
+    timeout(SMTP_TIMEOUT, async {
+        stream.write_all(command.as_bytes()).await?;
+        stream.write_all(b"\r\n").await
+    })
+    .await?;
+    reply(reader, expected).await
+}
+
+/// Read an SMTP reply, which may span several lines, and check that it has one of the `expected`
+/// codes.
+async fn reply(reader: &mut BufReader<TcpStream>, expected: &[u16]) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if timeout(SMTP_TIMEOUT, reader.read_line(&mut line)).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "SMTP connection closed",
+            ));
+        }
+        let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+        if !code.map_or(false, |code| expected.contains(&code)) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("unexpected SMTP reply: {}", line.trim_end()),
+            ));
+        }
+        // Continuation lines have a `-` after the code.
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use async_std::io::prelude::{BufReadExt, WriteExt};
+    use async_std::io::BufReader;
+    use async_std::net::TcpListener;
+    use async_std::task;
+
+    use crate::test::{self, log_entry, temp_database};
+
+    use super::{Report, Smtp, Target};
+
+    #[test]
+    fn summarise_reports() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+        for (code, message) in &[
+            (500, "refused"),
+            (503, "timeout"),
+            (500, "refused"),
+            (200, "ok"),
+        ] {
+            let line = format!(r#"{{"code":{},"message":"{}"}}"#, code, message);
+            database.write(&log_entry(&line, &[("ns", "payments")]))?;
+        }
+        database.write(&log_entry("error", &[("ns", "other")]))?;
+
+        let report: Report = r#"{
+            "name": "payment errors",
+            "every": "1d",
+            "query": {
+                "match": ["ns=payments"],
+                "regex": "refused|timeout",
+                "group_by": "code",
+                "top": 1,
+                "samples": 1
+            },
+            "target": {"webhook": "http://localhost:1234/hook?token=s3cret"}
+        }"#
+        .parse()?;
+        assert_eq!(report.every, Duration::from_secs(24 * 60 * 60));
+        assert_eq!(report.target.to_string(), "webhook http://localhost:1234");
+
+        let summary = report.summarise(&database, UNIX_EPOCH)?;
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.top_groups.len(), 1);
+        assert_eq!(summary.top_groups[0].value, "500");
+        assert_eq!(summary.top_groups[0].count, 2);
+        assert_eq!(
+            summary.text(),
+            concat!(
+                "Report payment errors: 3 matching lines\n",
+                "Top code: 500 (2)\n",
+                r#"> {"code":500,"message":"refused"}"#,
+            )
+        );
+
+        let parse = |every: &str, matchers: &str, target: &str| {
+            format!(
+                r#"{{"name": "x", "every": "{}", "query": {{"match": [{}]}}, "target": {}}}"#,
+                every, matchers, target
+            )
+            .parse::<Report>()
+        };
+        assert!(parse("1d", r#""a=b""#, r#"{"webhook": "http://x"}"#).is_ok());
+        assert!(parse("1d", "", r#"{"webhook": "http://x"}"#).is_err());
+        assert!(parse("0s", r#""a=b""#, r#"{"webhook": "http://x"}"#).is_err());
+        assert!(parse("1d", r#""a=b""#, r#"{"ftp": "x"}"#).is_err());
+
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn send_mail() -> test::Result {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let server = listener.local_addr()?.to_string();
+        let relay = task::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            let mut reader = BufReader::new(stream.clone());
+            let mut transcript = Vec::new();
+            stream.write_all(b"220 relay ready\r\n").await?;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).await?;
+                let line = line.trim_end().to_string();
+                let reply: &[u8] = match line.as_str() {
+                    "EHLO localhost" => b"250-relay\r\n250 8BITMIME\r\n",
+                    "DATA" => b"354 go ahead\r\n",
+                    "." => b"250 queued\r\n",
+                    "QUIT" => b"221 bye\r\n",
+                    _ if line.starts_with("MAIL") => b"250 ok\r\n",
+                    _ if line.starts_with("RCPT") => b"251 will forward\r\n",
+                    _ => b"",
+                };
+                stream.write_all(reply).await?;
+                transcript.push(line.clone());
+                if line == "QUIT" {
+                    return Ok::<_, std::io::Error>(transcript);
+                }
+            }
+        });
+
+        let smtp = Smtp {
+            server,
+            from: "monitoring@example.com".to_string(),
+            to: vec!["oncall@example.com".to_string()],
+        };
+        assert_eq!(
+            Target::Smtp(smtp.clone()).to_string(),
+            format!("smtp {} to oncall@example.com", smtp.server)
+        );
+        smtp.send("Report: errors", "3 errors\n.hidden").await?;
+
+        let transcript = relay.await?;
+        assert_eq!(transcript[1], "MAIL FROM:<monitoring@example.com>");
+        assert_eq!(transcript[2], "RCPT TO:<oncall@example.com>");
+        assert!(transcript.contains(&"Subject: Report: errors".to_string()));
+        assert!(transcript.contains(&"..hidden".to_string()));
+
+        Ok(())
+    }
+}
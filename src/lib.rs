@@ -21,9 +21,16 @@
 )]
 
 pub mod api;
+pub mod checkpoint;
 pub mod database;
+pub mod jobs;
 pub mod log_collector;
 pub mod log_database;
+pub mod manifest;
+pub mod metrics;
+pub mod query;
+pub mod record;
+pub mod sink;
 
 #[cfg(test)]
 pub mod test;
@@ -31,11 +38,17 @@ pub mod test;
 use std::collections::HashMap;
 
 /// A log entry that can be processed by the various parts of this library.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct LogEntry {
     /// A line of text in the log.
     pub line: String,
 
     /// Metadata associated with this log line.
     pub metadata: HashMap<String, String>,
+
+    /// When the line was logged, if known (e.g. from a container runtime's log format).
+    ///
+    /// Entries without a timestamp are timestamped when they're converted to
+    /// [`Record`](record::Record)s.
+    pub timestamp: Option<record::Timestamp>,
 }
@@ -0,0 +1,431 @@
+// src/log_collector/access_log.rs
+//! Parsing of HTTP access logs into metadata.
+//!
+//! [`AccessLogParser`] recognises common access log formats and adds the request's details to an
+//! entry's metadata, so that e.g. server errors can be found with `/logs/http_status/500`:
+//!
+//! - `http_method`: the request method.
+//! - `http_path`: the request path, without any query string.
+//! - `http_status`: the response status code.
+//! - `http_latency_seconds`: the time taken to serve the request, if logged.
+//! - `http_user_agent_family`: the browser or client family (e.g. `Firefox`, `curl`, or `Bot`).
+//!
+//! Each distinct set of metadata is stored as a separate stream, so `http_path` and
+//! `http_latency_seconds` can create very many streams. They can be left out with
+//! [`Config::fields`].
+//!
+//! Lines that aren't in a recognised format are left unchanged.
+
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::LogEntry;
+
+/// The pattern of Common Log Format lines, optionally extended to the Combined Log Format and
+/// followed by a request time in seconds (as with nginx's `$request_time`).
+const CLF_PATTERN: &str = concat!(
+    r#"^\S+ \S+ \S+ \[[^\]]+\] "(?P<method>[A-Z]+) (?P<path>\S+)[^"]*" (?P<status>\d{3}) \S+"#,
+    r#"(?: "[^"]*" "(?P<agent>[^"]*)"(?: (?P<latency>\d+(?:\.\d+)?))?)?"#,
+);
+
+/// User-agent families, recognised by a substring of the user-agent, in the order they're checked.
+///
+/// Browsers include the names of the browsers they're derived from, so e.g. Edge must be checked
+/// before Chrome, and Chrome before Safari.
+const USER_AGENT_FAMILIES: &[(&str, &str)] = &[
+    ("bot", "Bot"),
+    ("Bot", "Bot"),
+    ("spider", "Bot"),
+    ("crawl", "Bot"),
+    ("kube-probe/", "kube-probe"),
+    ("Edg/", "Edge"),
+    ("OPR/", "Opera"),
+    ("Chrome/", "Chrome"),
+    ("Firefox/", "Firefox"),
+    ("Safari/", "Safari"),
+    ("MSIE ", "Internet Explorer"),
+    ("Trident/", "Internet Explorer"),
+    ("curl/", "curl"),
+    ("Wget/", "Wget"),
+    ("python-requests/", "Python Requests"),
+    ("Go-http-client/", "Go"),
+    ("okhttp/", "OkHttp"),
+];
+
+/// An access log format.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AccessLogFormat {
+    /// Try each format in turn.
+    Auto,
+
+    /// The Common Log Format, or the Combined Log Format (which extends it).
+    Clf,
+
+    /// The Combined Log Format, as used by default by Apache and nginx.
+    Combined,
+
+    /// JSON objects, as produced by nginx's `log_format ... escape=json`.
+    ///
+    /// Common variable names are recognised (e.g. `request_method` or `method`, `request_time`,
+    /// and `http_user_agent`).
+    NginxJson,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "auto" => Ok(Self::Auto),
+            "clf" => Ok(Self::Clf),
+            "combined" => Ok(Self::Combined),
+            "nginx-json" => Ok(Self::NginxJson),
+            _ => Err(format!("unrecognised access log format `{}`", input)),
+        }
+    }
+}
+
+/// A field extracted from access log lines.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Field {
+    /// The request method, as `http_method`.
+    Method,
+
+    /// The request path, as `http_path`.
+    Path,
+
+    /// The response status code, as `http_status`.
+    Status,
+
+    /// The time taken to serve the request, as `http_latency_seconds`.
+    Latency,
+
+    /// The user-agent family, as `http_user_agent_family`.
+    UserAgentFamily,
+}
+
+impl Field {
+    /// All fields.
+    pub const ALL: &'static [Field] = &[
+        Field::Method,
+        Field::Path,
+        Field::Status,
+        Field::Latency,
+        Field::UserAgentFamily,
+    ];
+
+    /// The metadata key the field is stored as.
+    #[must_use]
+    pub fn key(self) -> &'static str {
+        match self {
+            Self::Method => "http_method",
+            Self::Path => "http_path",
+            Self::Status => "http_status",
+            Self::Latency => "http_latency_seconds",
+            Self::UserAgentFamily => "http_user_agent_family",
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "method" => Ok(Self::Method),
+            "path" => Ok(Self::Path),
+            "status" => Ok(Self::Status),
+            "latency" => Ok(Self::Latency),
+            "user_agent_family" => Ok(Self::UserAgentFamily),
+            _ => Err(format!("unrecognised access log field `{}`", input)),
+        }
+    }
+}
+
+/// Configuration for an [`AccessLogParser`].
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The format of access log lines.
+    pub format: AccessLogFormat,
+
+    /// The fields to add to entries' metadata.
+    pub fields: Vec<Field>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            format: AccessLogFormat::Auto,
+            fields: Field::ALL.to_vec(),
+        }
+    }
+}
+
+/// Parses access log lines into metadata.
+#[derive(Debug)]
+pub struct AccessLogParser {
+    config: Config,
+    clf: Regex,
+}
+
+/// The details of a request, parsed from an access log line.
+struct Request {
+    method: Option<String>,
+    path: Option<String>,
+    status: Option<String>,
+    latency: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AccessLogParser {
+    /// Construct an `AccessLogParser` with the given `config`.
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            clf: Regex::new(CLF_PATTERN).expect("invalid access log pattern"),
+        }
+    }
+
+    /// Parse `entry`'s line, adding the configured fields to its metadata.
+    ///
+    /// Returns whether the line was in a recognised format.
+    pub fn parse(&self, entry: &mut LogEntry) -> bool {
+        let request = match self.parse_line(&entry.line) {
+            Some(request) => request,
+            None => return false,
+        };
+
+        for field in &self.config.fields {
+            let value = match field {
+                Field::Method => request.method.clone(),
+                Field::Path => request.path.clone(),
+                Field::Status => request.status.clone(),
+                Field::Latency => request.latency.clone(),
+                Field::UserAgentFamily => request
+                    .user_agent
+                    .as_deref()
+                    .and_then(user_agent_family)
+                    .map(str::to_string),
+            };
+            if let Some(value) = value {
+                entry.metadata.insert(field.key().to_string(), value);
+            }
+        }
+        true
+    }
+
+    fn parse_line(&self, line: &str) -> Option<Request> {
+        match self.config.format {
+            AccessLogFormat::Auto => {
+                if line.trim_start().starts_with('{') {
+                    parse_json(line)
+                } else {
+                    self.parse_clf(line, false)
+                }
+            }
+            AccessLogFormat::Clf => self.parse_clf(line, false),
+            AccessLogFormat::Combined => self.parse_clf(line, true),
+            AccessLogFormat::NginxJson => parse_json(line),
+        }
+    }
+
+    fn parse_clf(&self, line: &str, combined: bool) -> Option<Request> {
+        let captures = self.clf.captures(line)?;
+        if combined && captures.name("agent").is_none() {
+            return None;
+        }
+
+        let capture = |name| captures.name(name).map(|value| value.as_str().to_string());
+        Some(Request {
+            method: capture("method"),
+            path: capture("path").map(strip_query),
+            status: capture("status"),
+            latency: capture("latency"),
+            user_agent: capture("agent"),
+        })
+    }
+}
+
+fn parse_json(line: &str) -> Option<Request> {
+    let object = match serde_json::from_str(line).ok()? {
+        serde_json::Value::Object(object) => object,
+        _ => return None,
+    };
+
+    // Values may be logged as strings or numbers, depending on the log format.
+    let get = |keys: &[&str]| {
+        keys.iter().find_map(|key| match object.get(*key)? {
+            serde_json::Value::String(value) if !value.is_empty() && value != "-" => {
+                Some(value.clone())
+            }
+            serde_json::Value::Number(value) => Some(value.to_string()),
+            _ => None,
+        })
+    };
+
+    // `request` is the whole request line, e.g. `GET /path HTTP/1.1`.
+    let request_line = get(&["request"]).unwrap_or_default();
+    let request_parts: Vec<_> = request_line
+        .split(' ')
+        .filter(|part| !part.is_empty())
+        .collect();
+    let request_part = |index: usize| request_parts.get(index).map(|part| (*part).to_string());
+    let method = get(&["request_method", "method"]).or_else(|| request_part(0));
+    let path = get(&["uri", "request_uri", "path"]).or_else(|| request_part(1));
+    let status = get(&["status"]);
+
+    // Only lines that look like requests are access log lines.
+    if method.is_none() && status.is_none() {
+        return None;
+    }
+
+    Some(Request {
+        method,
+        path: path.map(strip_query),
+        status,
+        latency: get(&["request_time", "duration", "latency"]),
+        user_agent: get(&["http_user_agent", "user_agent"]),
+    })
+}
+
+fn strip_query(mut path: String) -> String {
+    if let Some(index) = path.find('?') {
+        path.truncate(index);
+    }
+    path
+}
+
+/// Classify a user-agent string into a family.
+fn user_agent_family(user_agent: &str) -> Option<&'static str> {
+    if user_agent.is_empty() || user_agent == "-" {
+        return None;
+    }
+    let family = USER_AGENT_FAMILIES
+        .iter()
+        .find(|(pattern, _)| user_agent.contains(pattern))
+        .map_or("Other", |(_, family)| *family);
+    Some(family)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::log_entry;
+
+    use super::{user_agent_family, AccessLogFormat, AccessLogParser, Config, Field};
+
+    #[test]
+    fn parse_clf() {
+        let parser = AccessLogParser::new(Config::default());
+        let line = concat!(
+            r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "#,
+            r#""GET /apache_pb.gif?x=1 HTTP/1.0" 200 2326"#,
+        );
+        let mut entry = log_entry(line, &[]);
+        assert!(parser.parse(&mut entry));
+        assert_eq!(
+            entry.metadata,
+            log_entry(
+                "",
+                &[
+                    ("http_method", "GET"),
+                    ("http_path", "/apache_pb.gif"),
+                    ("http_status", "200")
+                ]
+            )
+            .metadata
+        );
+    }
+
+    #[test]
+    fn parse_combined() {
+        let parser = AccessLogParser::new(Config {
+            format: AccessLogFormat::Combined,
+            fields: Field::ALL.to_vec(),
+        });
+        let line = concat!(
+            r#"10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "POST /api/orders HTTP/1.1" 503 19 "-" "#,
+            r#""Mozilla/5.0 (X11; Linux x86_64; rv:85.0) Gecko/20100101 Firefox/85.0" 0.250"#,
+        );
+        let mut entry = log_entry(line, &[("app", "web")]);
+        assert!(parser.parse(&mut entry));
+        assert_eq!(
+            entry.metadata,
+            log_entry(
+                "",
+                &[
+                    ("app", "web"),
+                    ("http_method", "POST"),
+                    ("http_path", "/api/orders"),
+                    ("http_status", "503"),
+                    ("http_latency_seconds", "0.250"),
+                    ("http_user_agent_family", "Firefox")
+                ]
+            )
+            .metadata
+        );
+
+        let mut entry = log_entry(
+            r#"127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.0" 200 2326"#,
+            &[],
+        );
+        assert!(!parser.parse(&mut entry));
+        assert!(entry.metadata.is_empty());
+    }
+
+    #[test]
+    fn parse_nginx_json() {
+        let parser = AccessLogParser::new(Config {
+            format: AccessLogFormat::Auto,
+            fields: vec![Field::Method, Field::Status, Field::UserAgentFamily],
+        });
+        let line = concat!(
+            r#"{"time": "2021-03-01T12:00:00+00:00", "request": "GET /healthz HTTP/1.1", "#,
+            r#""status": 200, "request_time": "0.001", "http_user_agent": "kube-probe/1.20"}"#,
+        );
+        let mut entry = log_entry(line, &[]);
+        assert!(parser.parse(&mut entry));
+        assert_eq!(
+            entry.metadata,
+            log_entry(
+                "",
+                &[
+                    ("http_method", "GET"),
+                    ("http_status", "200"),
+                    ("http_user_agent_family", "kube-probe")
+                ]
+            )
+            .metadata
+        );
+
+        let mut entry = log_entry(r#"{"level": "info", "msg": "started"}"#, &[]);
+        assert!(!parser.parse(&mut entry));
+        let mut entry = log_entry("starting server", &[]);
+        assert!(!parser.parse(&mut entry));
+    }
+
+    #[test]
+    fn user_agent_families() {
+        let family = user_agent_family;
+        assert_eq!(
+            family("Mozilla/5.0 (Windows NT 10.0) Chrome/88.0 Safari/537.36 Edg/88.0"),
+            Some("Edge")
+        );
+        assert_eq!(
+            family("Mozilla/5.0 (Macintosh) Chrome/88.0 Safari/537.36"),
+            Some("Chrome")
+        );
+        assert_eq!(
+            family("Mozilla/5.0 (iPhone) Version/14.0 Safari/604.1"),
+            Some("Safari")
+        );
+        assert_eq!(
+            family("Mozilla/5.0 (compatible; Googlebot/2.1)"),
+            Some("Bot")
+        );
+        assert_eq!(family("curl/7.68.0"), Some("curl"));
+        assert_eq!(family("my-client/1.0"), Some("Other"));
+        assert_eq!(family("-"), None);
+    }
+}
@@ -0,0 +1,254 @@
+// src/log_collector/compressed.rs
+//! Reading of compressed (rotated) log files.
+//!
+//! Log rotation commonly compresses old files with `gzip` or `zstd`. These files never change, so
+//! rather than being watched they are read once, with streaming decompression, when backfilling.
+//! Positions within compressed files are always expressed in uncompressed bytes, since compressed
+//! streams can't be seeked into, and so are [checkpoints](crate::checkpoint) of how much of a file
+//! has been read.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use log::warn;
+
+use crate::checkpoint::{Checkpoint, Hasher};
+use crate::LogEntry;
+
+use super::paths;
+
+/// The file extensions of supported compressed files.
+const EXTENSIONS: &[&str] = &["gz", "zst"];
+
+/// Check whether `path` is a supported compressed file, based on its extension.
+pub(super) fn is_compressed(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| EXTENSIONS.contains(&extension))
+}
+
+/// Strip any compressed file extension from `path`.
+pub(super) fn strip_extension(path: &str) -> &str {
+    for extension in EXTENSIONS {
+        if let Some(stripped) = path
+            .strip_suffix(extension)
+            .and_then(|path| path.strip_suffix('.'))
+        {
+            return stripped;
+        }
+    }
+    path
+}
+
+/// A compressed log file being read from start to finish.
+pub(super) struct CompressedFile {
+    path: String,
+    reader: Box<dyn BufRead + Send>,
+    offset: u64,
+    hasher: Hasher,
+    entry_buf: String,
+}
+
+impl CompressedFile {
+    /// Open the compressed file at `path`, skipping the first `offset` uncompressed bytes.
+    pub(super) fn open(path: &Path, offset: u64) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let reader: Box<dyn BufRead + Send> =
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some("gz") => Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file))),
+                Some("zst") => Box::new(BufReader::new(zstd::stream::read::Decoder::new(file)?)),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unsupported compressed file: {}", path.display()),
+                    ))
+                }
+            };
+
+        let mut compressed_file = Self {
+            path: paths::normalize(path),
+            reader,
+            offset: 0,
+            hasher: Hasher::new(),
+            entry_buf: String::new(),
+        };
+        compressed_file.offset = io::copy(
+            &mut (&mut compressed_file.reader).take(offset),
+            &mut compressed_file.hasher,
+        )?;
+        Ok(compressed_file)
+    }
+
+    /// Open the compressed file at `path`, resuming from `checkpoint` if the file still starts with
+    /// the checkpointed content, or from the start otherwise.
+    pub(super) fn resume(path: &Path, checkpoint: Checkpoint) -> io::Result<Self> {
+        let compressed_file = Self::open(path, checkpoint.offset)?;
+        if compressed_file.checkpoint() == checkpoint {
+            Ok(compressed_file)
+        } else {
+            warn!(
+                "{} has changed since it was checkpointed, reading it from the start",
+                path.display()
+            );
+            Self::open(path, 0)
+        }
+    }
+
+    /// The path of the file, as it will appear in entry metadata.
+    pub(super) fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The number of uncompressed bytes consumed so far.
+    pub(super) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// A checkpoint of the content consumed so far.
+    pub(super) fn checkpoint(&self) -> Checkpoint {
+        self.hasher.checkpoint(self.offset)
+    }
+
+    /// Read the next entry from the file, or `None` if the file has been fully read.
+    ///
+    /// A final line without a trailing newline is still returned, since the file can't grow.
+    pub(super) fn read_entry(&mut self) -> io::Result<Option<LogEntry>> {
+        self.entry_buf.clear();
+        let read = self
+            .reader
+            .read_line(&mut self.entry_buf)
+            .map_err(|error| {
+                io::Error::new(
+                    error.kind(),
+                    format!(
+                        "error reading {} at uncompressed offset {}: {}",
+                        self.path, self.offset, error
+                    ),
+                )
+            })?;
+        if read == 0 {
+            return Ok(None);
+        }
+        self.offset += read as u64;
+        self.hasher.update(self.entry_buf.as_bytes());
+
+        if self.entry_buf.ends_with('\n') {
+            self.entry_buf.pop();
+        }
+
+        let mut metadata = HashMap::new();
+        metadata.insert("path".to_string(), self.path.clone());
+        Ok(Some(LogEntry {
+            line: self.entry_buf.clone(),
+            metadata,
+            timestamp: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+
+    use crate::test::{self, log_entry};
+
+    use super::{is_compressed, strip_extension, CompressedFile};
+
+    #[test]
+    fn detects_compressed_files() {
+        assert!(is_compressed(Path::new("app.log.1.gz")));
+        assert!(is_compressed(Path::new("app.log.2.zst")));
+        assert!(!is_compressed(Path::new("app.log")));
+
+        assert_eq!(strip_extension("app_ns_c-1.log.gz"), "app_ns_c-1.log");
+        assert_eq!(strip_extension("app_ns_c-1.log"), "app_ns_c-1.log");
+    }
+
+    #[test]
+    fn reads_gzip_from_offset() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("app.log.1.gz");
+
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&path)?, flate2::Compression::default());
+        encoder.write_all(b"hello\nworld\nunterminated")?;
+        encoder.finish()?;
+
+        let path_str = path.to_str().unwrap();
+        let mut file = CompressedFile::open(&path, 0)?;
+        assert_eq!(
+            file.read_entry()?,
+            Some(log_entry("hello", &[("path", path_str)]))
+        );
+        assert_eq!(file.offset(), 6);
+
+        let mut file = CompressedFile::open(&path, file.offset())?;
+        assert_eq!(
+            file.read_entry()?,
+            Some(log_entry("world", &[("path", path_str)]))
+        );
+        assert_eq!(
+            file.read_entry()?,
+            Some(log_entry("unterminated", &[("path", path_str)]))
+        );
+        assert_eq!(file.read_entry()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resumes_from_checkpoint() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("app.log.1.zst");
+        let write = |content: &[u8]| -> test::Result {
+            let mut encoder = zstd::stream::write::Encoder::new(File::create(&path)?, 0)?;
+            encoder.write_all(content)?;
+            encoder.finish()?;
+            Ok(())
+        };
+        let path_str = path.to_str().unwrap();
+
+        write(b"hello\nworld\n")?;
+        let mut file = CompressedFile::open(&path, 0)?;
+        file.read_entry()?;
+        let checkpoint = file.checkpoint();
+
+        let mut file = CompressedFile::resume(&path, checkpoint)?;
+        assert_eq!(
+            file.read_entry()?,
+            Some(log_entry("world", &[("path", path_str)]))
+        );
+
+        write(b"howdy\nworld\n")?;
+        let mut file = CompressedFile::resume(&path, checkpoint)?;
+        assert_eq!(
+            file.read_entry()?,
+            Some(log_entry("howdy", &[("path", path_str)]))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_zstd() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("app.log.1.zst");
+
+        let mut encoder = zstd::stream::write::Encoder::new(File::create(&path)?, 0)?;
+        encoder.write_all(b"hello\n")?;
+        encoder.finish()?;
+
+        let mut file = CompressedFile::open(&path, 0)?;
+        assert_eq!(
+            file.read_entry()?,
+            Some(log_entry("hello", &[("path", path.to_str().unwrap())]))
+        );
+        assert_eq!(file.read_entry()?, None);
+
+        Ok(())
+    }
+}
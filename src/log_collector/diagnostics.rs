@@ -0,0 +1,303 @@
+// src/log_collector/diagnostics.rs
+//! Diagnostics for files the collector is not permitted to watch or read.
+//!
+//! On hardened nodes, a Linux Security Module (SELinux or AppArmor) can deny access to log files
+//! even when their mode and ownership would allow it. The resulting `EACCES`/`EPERM` errors say
+//! nothing about why, so [`Diagnostics`] detects the active LSM and attaches a suggestion of what
+//! to check to permission errors. Recent failures are also kept for the `/debug/collector`
+//! endpoint, along with the [`Strategy`] used to watch each path (including which paths have been
+//! demoted to [`Strategy::Spillover`]).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A Linux Security Module that may be denying access to files.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lsm {
+    /// SELinux, and whether it's enforcing (rather than permissive).
+    SeLinux {
+        /// Whether SELinux is enforcing its policy.
+        enforcing: bool,
+    },
+
+    /// AppArmor.
+    AppArmor,
+}
+
+/// A failure to watch or read a file due to permissions.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Failure {
+    /// The operation that failed (e.g. `open`).
+    pub operation: &'static str,
+
+    /// The underlying error.
+    pub error: String,
+
+    /// A suggestion of what to check.
+    pub hint: String,
+
+    /// When the failure last occurred, in seconds since the Unix epoch.
+    pub last_seen: u64,
+}
+
+/// How the collector watches a path.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// Native watches (`inotify` or `kqueue`) alone.
+    Native,
+
+    /// Native watches, verified by periodic polling, for paths on network filesystems where
+    /// native watches miss changes.
+    Hybrid,
+
+    /// No watch: the path was demoted because the collector's active file limit was reached, so
+    /// only its growth is periodically sampled (see
+    /// [`directory::Config::max_active_files`](super::directory::Config::max_active_files)).
+    Spillover,
+}
+
+/// A snapshot of the collector's diagnostics.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Report {
+    /// The Linux Security Module detected on this node, if any.
+    pub lsm: Option<Lsm>,
+
+    /// Current permission failures, by path.
+    pub failures: BTreeMap<PathBuf, Failure>,
+
+    /// The strategy used to watch each watched path.
+    pub strategies: BTreeMap<PathBuf, Strategy>,
+}
+
+/// Diagnostics shared between a collector and the API.
+#[derive(Debug)]
+pub struct Diagnostics {
+    lsm: Option<Lsm>,
+    failures: Mutex<BTreeMap<PathBuf, Failure>>,
+    strategies: Mutex<BTreeMap<PathBuf, Strategy>>,
+}
+
+impl Diagnostics {
+    /// Construct a `Diagnostics`, detecting the active Linux Security Module.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_lsm(detect_lsm(Path::new("/")))
+    }
+
+    fn with_lsm(lsm: Option<Lsm>) -> Self {
+        Self {
+            lsm,
+            failures: Mutex::new(BTreeMap::new()),
+            strategies: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Add diagnostics to `error` if it was caused by permissions, and record it as a failure.
+    ///
+    /// Other errors are returned unchanged. The kind of the returned error is always the same as
+    /// `error`'s.
+    pub(super) fn check(
+        &self,
+        path: &Path,
+        operation: &'static str,
+        error: io::Error,
+    ) -> io::Error {
+        if error.kind() != io::ErrorKind::PermissionDenied {
+            return error;
+        }
+
+        let hint = self.hint(path);
+        let message = format!(
+            "unable to {} {}: {} ({})",
+            operation,
+            path.display(),
+            error,
+            hint
+        );
+
+        let last_seen = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.failures
+            .lock()
+            .expect("diagnostics lock poisoned")
+            .insert(
+                path.to_path_buf(),
+                Failure {
+                    operation,
+                    error: error.to_string(),
+                    hint,
+                    last_seen,
+                },
+            );
+
+        io::Error::new(error.kind(), message)
+    }
+
+    /// Forget any failure recorded for `path`, e.g. once it has been read successfully.
+    pub(super) fn clear(&self, path: &Path) {
+        self.failures
+            .lock()
+            .expect("diagnostics lock poisoned")
+            .remove(path);
+    }
+
+    /// Forget the strategy used to watch `path`, once it's no longer watched.
+    pub(super) fn unwatched(&self, path: &Path) {
+        self.strategies
+            .lock()
+            .expect("diagnostics lock poisoned")
+            .remove(path);
+    }
+
+    /// Record the strategy used to watch `path`.
+    pub(super) fn watching(&self, path: &Path, strategy: Strategy) {
+        self.strategies
+            .lock()
+            .expect("diagnostics lock poisoned")
+            .insert(path.to_path_buf(), strategy);
+    }
+
+    /// Take a snapshot of the current diagnostics.
+    #[must_use]
+    pub fn report(&self) -> Report {
+        Report {
+            lsm: self.lsm.clone(),
+            failures: self
+                .failures
+                .lock()
+                .expect("diagnostics lock poisoned")
+                .clone(),
+            strategies: self
+                .strategies
+                .lock()
+                .expect("diagnostics lock poisoned")
+                .clone(),
+        }
+    }
+
+    fn hint(&self, path: &Path) -> String {
+        match self.lsm {
+            Some(Lsm::SeLinux { enforcing: true }) => format!(
+                "SELinux is enforcing: check for AVC denials with `ausearch -m avc -ts recent`, \
+                 and that {} is labelled for container logs (e.g. `container_log_t`) or that \
+                 the agent runs with a type permitted to read it (e.g. `spc_t`)",
+                path.display()
+            ),
+            Some(Lsm::AppArmor) => format!(
+                "AppArmor is enabled: check for denials with `dmesg | grep 'apparmor=\"DENIED\"'`, \
+                 and that the agent's profile allows reading {} (or run it unconfined)",
+                path.display()
+            ),
+            Some(Lsm::SeLinux { enforcing: false }) | None => format!(
+                "no enforcing LSM detected: check the mode and ownership of {} and its parent \
+                 directories, and the user the agent runs as",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Detect the active Linux Security Module, using the filesystem mounted at `root`.
+fn detect_lsm(root: &Path) -> Option<Lsm> {
+    let read = |path: &str| fs::read_to_string(root.join(path)).ok();
+
+    let selinux = || {
+        read("sys/fs/selinux/enforce").map(|enforce| Lsm::SeLinux {
+            enforcing: enforce.trim() == "1",
+        })
+    };
+
+    // `/sys/kernel/security/lsm` lists the active LSMs, but requires securityfs to be mounted.
+    if let Some(lsms) = read("sys/kernel/security/lsm") {
+        for lsm in lsms.trim().split(',') {
+            match lsm {
+                "selinux" => return selinux().or(Some(Lsm::SeLinux { enforcing: true })),
+                "apparmor" => return Some(Lsm::AppArmor),
+                _ => {}
+            }
+        }
+        return None;
+    }
+
+    selinux().or_else(|| {
+        read("sys/module/apparmor/parameters/enabled")
+            .filter(|enabled| enabled.trim() == "Y")
+            .map(|_| Lsm::AppArmor)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    use crate::test;
+
+    use super::{detect_lsm, Diagnostics, Lsm};
+
+    #[test]
+    fn detects_lsm() -> test::Result {
+        let root = tempfile::tempdir()?;
+        assert_eq!(detect_lsm(root.path()), None);
+
+        fs::create_dir_all(root.path().join("sys/module/apparmor/parameters"))?;
+        fs::write(
+            root.path().join("sys/module/apparmor/parameters/enabled"),
+            "Y\n",
+        )?;
+        assert_eq!(detect_lsm(root.path()), Some(Lsm::AppArmor));
+
+        fs::create_dir_all(root.path().join("sys/fs/selinux"))?;
+        fs::write(root.path().join("sys/fs/selinux/enforce"), "0")?;
+        assert_eq!(
+            detect_lsm(root.path()),
+            Some(Lsm::SeLinux { enforcing: false })
+        );
+
+        fs::create_dir_all(root.path().join("sys/kernel/security"))?;
+        fs::write(
+            root.path().join("sys/kernel/security/lsm"),
+            "capability,yama,apparmor\n",
+        )?;
+        assert_eq!(detect_lsm(root.path()), Some(Lsm::AppArmor));
+
+        Ok(())
+    }
+
+    #[test]
+    fn annotates_permission_errors() {
+        let diagnostics = Diagnostics::with_lsm(Some(Lsm::SeLinux { enforcing: true }));
+        let path = Path::new("/var/log/containers/app.log");
+
+        let error = diagnostics.check(
+            path,
+            "open",
+            io::Error::from(io::ErrorKind::PermissionDenied),
+        );
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(error.to_string().contains("ausearch"));
+        assert_eq!(diagnostics.report().failures.len(), 1);
+
+        diagnostics.clear(path);
+        assert!(diagnostics.report().failures.is_empty());
+
+        let error = diagnostics.check(path, "open", io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(diagnostics.report().failures.is_empty());
+    }
+}
@@ -1,20 +1,163 @@
 //! A log collector that watches a directory of log files.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::ffi::OsStr;
 use std::fs::{self, File};
+use std::future::Future;
 use std::io::{self, BufRead, BufReader, Seek};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use log::{debug, trace, warn};
+use async_io::{Async, Timer};
+use async_std::stream::Stream;
 
+use log::{debug, info, trace, warn};
+
+use crate::checkpoint::{Checkpoint, Checkpoints, Hasher};
+use crate::metrics::Metric;
 use crate::LogEntry;
 
-use super::watcher::{watcher, Event as _, Watcher};
+use super::compressed::{self, CompressedFile};
+use super::diagnostics::{Diagnostics, Strategy};
+use super::filesystem;
+use super::format::{Format, Parser};
+use super::ownership::{self, Marker};
+use super::paths;
+use super::watcher::{watcher, Event as _, Watcher, TIMEOUT_CHECK_INTERVAL};
+
+/// The delay before first retrying a file that could not be opened due to permissions.
+const RETRY_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The maximum delay between retries of a file that could not be opened due to permissions.
+const RETRY_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// How often paths on network filesystems are polled for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the positions of followed files are checkpointed.
+const POSITION_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many of the last bytes read from a file are kept, to detect the file being rewritten.
+const TAIL_LEN: usize = 64;
+
+/// How long after a file is rotated a new file at its path is read from the start.
+const REPLACEMENT_WINDOW: Duration = Duration::from_secs(60);
+
+/// How often the sizes of files demoted to spillover are sampled.
+const SPILLOVER_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The `spillover` metadata value of entries summarising the growth of demoted files.
+pub const SPILLOVER_METADATA_KEY: &str = "spillover";
+
+/// The number of files that could not be opened due to permissions, and are awaiting retry.
+pub static PERMISSION_DENIED_FILES: Metric = Metric::gauge(
+    "monitoring_rs_permission_denied_files",
+    "Number of log files that could not be opened due to permissions, awaiting retry.",
+);
+
+/// The number of log files demoted to spillover because the active file limit was reached.
+pub static SPILLOVER_FILES: Metric = Metric::gauge(
+    "monitoring_rs_spillover_files",
+    "Number of log files demoted to spillover sampling because the active file limit was reached.",
+);
+
+/// The number of times a log file could not be opened due to permissions.
+pub static PERMISSION_DENIED_TOTAL: Metric = Metric::counter(
+    "monitoring_rs_permission_denied_total",
+    "Number of times a log file could not be opened due to permissions.",
+);
+
+/// Where to start reading newly discovered files.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadFrom {
+    /// Read the whole file.
+    Beginning,
+
+    /// Only read lines written after the file was discovered.
+    End,
+}
+
+impl Default for ReadFrom {
+    fn default() -> Self {
+        Self::End
+    }
+}
 
 /// Configuration for [`initialize`].
 pub struct Config {
     /// The root path from which to collect logs.
     pub root_path: PathBuf,
+
+    /// Whether to write an ownership marker into `root_path`, to detect other collectors
+    /// collecting the same files.
+    ///
+    /// See [`ownership`](super::ownership) for details.
+    pub ownership_marker: bool,
+
+    /// Whether to read compressed (`.gz` or `.zst`) files found in `root_path` at startup.
+    ///
+    /// Compressed files are never watched, since they are typically rotated files that won't
+    /// change. When this is enabled, their contents are emitted before any new lines from watched
+    /// files.
+    pub backfill_compressed: bool,
+
+    /// Where to store [checkpoints](crate::checkpoint) of backfilled compressed files, if anywhere.
+    ///
+    /// Without checkpoints, compressed files are backfilled in full every time the collector is
+    /// initialized. With them, only content that hasn't already been backfilled is read, so files
+    /// that were completely backfilled before a restart are skipped.
+    pub backfill_checkpoints: Option<PathBuf>,
+
+    /// Where to store [checkpoints](crate::checkpoint) of how far each followed file has been
+    /// read, if anywhere.
+    ///
+    /// Without checkpoints, files are read from their end when the collector is initialized, so
+    /// lines written while it wasn't running are never collected. With them, files found at
+    /// initialization resume from their checkpoint. Checkpoints are keyed by the file's device and
+    /// inode numbers, so a file that was renamed in the meantime still resumes, and verified
+    /// against the file's content, so a file that was replaced is read from the start. Files with
+    /// no checkpoint are also read from the start, unless there are no checkpoints at all (e.g. the
+    /// first time the collector runs).
+    ///
+    /// Positions are checkpointed at most once a second, after the lines read before them have
+    /// been emitted, so lines may be collected twice after a crash but are never skipped.
+    pub position_checkpoints: Option<PathBuf>,
+
+    /// Where to start reading files that are discovered, including those found when the collector
+    /// is initialized.
+    ///
+    /// [`ReadFrom::Beginning`] ingests files in full, e.g. to backfill existing logs. Files that
+    /// resume from [`position_checkpoints`](Self::position_checkpoints) ignore this, as do files
+    /// promoted from spillover (see [`max_active_files`](Self::max_active_files)), which are
+    /// always read from their end.
+    pub read_from: ReadFrom,
+
+    /// The maximum number of files to follow at once, or `None` for no limit.
+    ///
+    /// When more files than this are found, the least recently active files are demoted to
+    /// spillover: they're no longer watched or read, and instead their sizes are sampled every 10
+    /// seconds. The growth of any demoted files is emitted as a single JSON summary entry (like
+    /// `{"files": 12, "bytes": 3456, "growth": {"<path>": 3000, ...}}`) with `spillover=true`
+    /// metadata and the root path as its `path`.
+    ///
+    /// When a demoted file grows while a followed file has been idle since the previous sample,
+    /// they swap places. Lines written to a file while it's demoted are never collected. Demoted
+    /// files are reported by [`Diagnostics`] with [`Strategy::Spillover`].
+    pub max_active_files: Option<usize>,
+
+    /// The format of the lines in the log files, e.g. [`Format::Docker`] or [`Format::Cri`] to
+    /// unwrap the envelopes written by container runtimes.
+    pub format: Format,
+
+    /// Diagnostics for files that can't be watched or read due to permissions.
+    pub diagnostics: Arc<Diagnostics>,
 }
 
 #[derive(Debug)]
@@ -57,11 +200,137 @@ impl std::fmt::Display for Event<'_> {
     }
 }
 
+/// A file that could not be opened due to permissions, and will be retried.
+#[derive(Debug)]
+struct DeniedFile {
+    canonical_path: PathBuf,
+    backoff: Duration,
+    next_attempt: Instant,
+}
+
 #[derive(Debug)]
 struct WatchedFile {
     paths: Vec<String>,
+    canonical_path: PathBuf,
     reader: BufReader<File>,
     entry_buf: String,
+    parser: Parser,
+    last_active: u64,
+    progress: Option<Progress>,
+
+    /// The (up to) [`TAIL_LEN`] bytes before the read position.
+    tail: Vec<u8>,
+}
+
+impl WatchedFile {
+    /// Read any new lines into `entries`, returning whether anything was read (so the file's
+    /// activity can be updated).
+    fn read_lines(&mut self, entries: &mut Vec<LogEntry>) -> io::Result<bool> {
+        let mut read = false;
+        loop {
+            let start = self.entry_buf.len();
+            if self.reader.read_line(&mut self.entry_buf)? == 0 {
+                break;
+            }
+            read = true;
+            let complete = self.entry_buf.ends_with('\n');
+            let bytes = &self.entry_buf.as_bytes()[start..];
+            if let Some(progress) = &mut self.progress {
+                progress.read(bytes, complete);
+            }
+            self.tail.extend_from_slice(bytes);
+            if self.tail.len() > TAIL_LEN {
+                self.tail.drain(..self.tail.len() - TAIL_LEN);
+            }
+            if complete {
+                self.entry_buf.pop();
+
+                if let Some(parsed) = self.parser.parse(&self.entry_buf) {
+                    for path in &self.paths {
+                        let mut metadata = HashMap::new();
+                        metadata.insert("path".to_string(), path.clone());
+                        entries.push(parsed.clone().into_entry(metadata));
+                    }
+                }
+
+                self.entry_buf.clear();
+            }
+        }
+        Ok(read)
+    }
+
+    /// Whether the file has been truncated since it was last read.
+    ///
+    /// A file is truncated if it's shorter than the read position, or if the bytes before the read
+    /// position have changed. The latter happens when a file is truncated and then written past the
+    /// read position before the truncation is noticed, e.g. when a busy file is rotated with
+    /// `copytruncate`.
+    fn truncated(&mut self) -> io::Result<bool> {
+        let len = self.reader.get_ref().metadata()?.len();
+        let seekpos = self.reader.seek(io::SeekFrom::Current(0))?;
+        if len < seekpos {
+            return Ok(true);
+        }
+        match read_tail(self.reader.get_ref(), seekpos) {
+            Ok(tail) => Ok(tail != self.tail),
+            // The file was truncated since its length was checked.
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(true),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// How far a followed file has been read, for checkpointing.
+#[derive(Debug)]
+struct Progress {
+    /// The key of the file's checkpoint.
+    key: String,
+
+    /// The hash of the content read so far.
+    hasher: Hasher,
+    offset: u64,
+
+    /// The checkpoint after the last complete line that was read.
+    line_end: Checkpoint,
+
+    /// The checkpoint that was last stored, if any.
+    stored: Option<Checkpoint>,
+}
+
+impl Progress {
+    fn new(key: String, hasher: Hasher, offset: u64, stored: Option<Checkpoint>) -> Self {
+        Self {
+            key,
+            hasher,
+            offset,
+            line_end: hasher.checkpoint(offset),
+            stored,
+        }
+    }
+
+    /// Record that `bytes` have been read, `complete` if they end a line.
+    fn read(&mut self, bytes: &[u8], complete: bool) {
+        self.hasher.update(bytes);
+        self.offset += bytes.len() as u64;
+        if complete {
+            self.line_end = self.hasher.checkpoint(self.offset);
+        }
+    }
+
+    /// Record that the file has been truncated, and will be read from the start.
+    fn reset(&mut self) {
+        self.hasher = Hasher::new();
+        self.offset = 0;
+        self.line_end = self.hasher.checkpoint(0);
+    }
+}
+
+/// A file that was demoted because of the active file limit, whose size is sampled instead.
+#[derive(Debug)]
+struct SpilledFile {
+    /// The paths the file was watched by, with the canonical path last.
+    paths: Vec<PathBuf>,
+    size: u64,
 }
 
 pub(super) struct Collector<W: Watcher> {
@@ -71,6 +340,31 @@ pub(super) struct Collector<W: Watcher> {
     watched_paths: HashMap<PathBuf, W::Descriptor>,
     watcher: W,
     entry_buf: std::vec::IntoIter<LogEntry>,
+    marker: Option<Marker>,
+    /// Compressed files waiting to be backfilled, opened one at a time as they're reached.
+    backfill: VecDeque<PathBuf>,
+    backfilling: Option<(CompressedFile, Parser)>,
+    checkpoints: Option<Checkpoints>,
+    positions: Option<Checkpoints>,
+    resume_unknown: bool,
+    initialized: bool,
+    read_from: ReadFrom,
+    position_interval: Duration,
+    next_position_checkpoint: Instant,
+    denied: HashMap<PathBuf, DeniedFile>,
+    diagnostics: Arc<Diagnostics>,
+    poll_root: bool,
+    polled_files: HashSet<W::Descriptor>,
+    poll_interval: Duration,
+    max_active_files: Option<usize>,
+    format: Format,
+    activity: u64,
+    spilled: HashMap<PathBuf, SpilledFile>,
+    spilled_paths: HashMap<PathBuf, PathBuf>,
+    sampled_activity: u64,
+    next_sample: Instant,
+    rotated_paths: HashMap<PathBuf, Instant>,
+    rotated_files: HashSet<String>,
 }
 
 /// Initialize a `Collector` that watches a directory of log files.
@@ -84,6 +378,20 @@ pub(super) struct Collector<W: Watcher> {
 /// `root_path`. In that situation, `LogEntry` records will have just one of the paths, and the
 /// chosen path might change after restarts.
 ///
+/// Paths on network filesystems (NFS or SMB), where native watches miss changes made by other
+/// clients, are also polled for changes every second. The strategy used for each path is reported
+/// by [`Diagnostics`].
+///
+/// Files that are renamed or deleted (e.g. when rotated) are read to the end and then no longer
+/// followed, even if they were renamed to another path in `root_path`. A new file created at the
+/// same path shortly afterwards is read from the start, regardless of `config.read_from`. Files
+/// that are truncated in place (e.g. by `copytruncate` rotation) are also read from the start,
+/// even if they've been written past the previous read position by the time it's noticed.
+///
+/// Files that cannot be opened due to permissions are not fatal. They are retried with exponential
+/// backoff (whenever the collector wakes up), and a diagnostic entry is emitted with the file's
+/// `path` the first time they are denied.
+///
 /// # Errors
 ///
 /// Propagates any `io::Error`s that occur during initialization.
@@ -92,12 +400,136 @@ pub fn initialize(config: Config) -> io::Result<impl super::Collector> {
     Collector::initialize(config, watcher)
 }
 
+/// Initialize an [`AsyncCollector`](super::AsyncCollector) that watches a directory of log files.
+///
+/// This behaves like [`initialize`], but waits for changes on the async executor rather than
+/// blocking a thread. Files are still read synchronously, as they change.
+///
+/// # Errors
+///
+/// Propagates any `io::Error`s that occur during initialization.
+pub fn initialize_async(config: Config) -> io::Result<impl super::AsyncCollector + Send + Unpin> {
+    let watcher = watcher()?;
+    Streaming::new(Collector::initialize(config, watcher)?)
+}
+
+/// The options of a `directory` collector in a [`Registry`](super::Registry).
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Options {
+    root_path: PathBuf,
+    #[serde(default)]
+    ownership_marker: bool,
+    #[serde(default)]
+    backfill_compressed: bool,
+    #[serde(default)]
+    backfill_checkpoints: Option<PathBuf>,
+    #[serde(default)]
+    position_checkpoints: Option<PathBuf>,
+    #[serde(default)]
+    read_from: ReadFrom,
+    #[serde(default)]
+    max_active_files: Option<usize>,
+    #[serde(default)]
+    format: Format,
+    #[serde(default)]
+    once: bool,
+}
+
+/// Initialize a collector from its options (see [`Initializer`](super::Initializer)).
+///
+/// The options are `root_path` (required), `ownership_marker`, `backfill_compressed`,
+/// `backfill_checkpoints`, `position_checkpoints`, `read_from` (`beginning` or `end`),
+/// `max_active_files`, and `format` (`plain`, `docker`, `cri`, or `auto`), as in [`Config`].
+///
+/// With `once: true`, a [one-shot](super::once) collector is initialized instead, which reads the
+/// files currently in `root_path` and then ends. It uses `backfill_checkpoints` for its
+/// checkpoints and `format`, and ignores the other options.
+///
+/// # Errors
+///
+/// If the options are invalid, or initialization fails, an [`Error`](super::Error) is returned.
+pub fn from_options(
+    options: &super::Options,
+    context: &super::Context,
+) -> Result<Box<dyn super::Collector + Send>, super::Error> {
+    let options: Options = super::parse_options("directory", options)?;
+    if options.once {
+        return Ok(Box::new(options.once_collector()?));
+    }
+    Ok(Box::new(initialize(options.into_config(context))?))
+}
+
+/// Initialize an asynchronous collector from its options (see
+/// [`AsyncInitializer`](super::AsyncInitializer)).
+///
+/// The options are the same as for [`from_options`]. One-shot collectors are run on a dedicated
+/// thread.
+///
+/// # Errors
+///
+/// If the options are invalid, or initialization fails, an [`Error`](super::Error) is returned.
+pub fn from_options_async(
+    options: &super::Options,
+    context: &super::Context,
+) -> Result<Box<dyn super::AsyncCollector + Send + Unpin>, super::Error> {
+    let options: Options = super::parse_options("directory", options)?;
+    if options.once {
+        return Ok(Box::new(super::Unblocked::new(options.once_collector()?)));
+    }
+    Ok(Box::new(initialize_async(options.into_config(context))?))
+}
+
+impl Options {
+    fn once_collector(self) -> io::Result<impl super::Collector + Send> {
+        super::once::initialize(super::once::Config {
+            root_path: self.root_path,
+            checkpoints: self.backfill_checkpoints,
+            format: self.format,
+        })
+    }
+
+    fn into_config(self, context: &super::Context) -> Config {
+        Config {
+            root_path: self.root_path,
+            ownership_marker: self.ownership_marker,
+            backfill_compressed: self.backfill_compressed,
+            backfill_checkpoints: self.backfill_checkpoints,
+            position_checkpoints: self.position_checkpoints,
+            read_from: self.read_from,
+            max_active_files: self.max_active_files,
+            format: self.format,
+            diagnostics: Arc::clone(&context.diagnostics),
+        }
+    }
+}
+
 impl<W: Watcher> Collector<W> {
     pub(super) fn initialize(config: Config, mut watcher: W) -> io::Result<Self> {
-        let Config { root_path } = config;
+        let Config {
+            root_path,
+            ownership_marker,
+            backfill_compressed,
+            backfill_checkpoints,
+            position_checkpoints,
+            read_from,
+            max_active_files,
+            format,
+            diagnostics,
+        } = config;
+        let positions = position_checkpoints.map(Checkpoints::load).transpose()?;
 
         debug!("Initialising watch on root path {:?}", root_path);
-        let root_wd = watcher.watch_directory(&root_path.canonicalize()?)?;
+        let root_wd = watcher
+            .watch_directory(&root_path.canonicalize()?)
+            .map_err(|error| diagnostics.check(&root_path, "watch", error))?;
+        let root_strategy = strategy(&root_path);
+        diagnostics.watching(&root_path, root_strategy);
+        let marker = if ownership_marker {
+            Some(Marker::claim(&root_path))
+        } else {
+            None
+        };
 
         let mut collector = Self {
             root_path,
@@ -106,11 +538,50 @@ impl<W: Watcher> Collector<W> {
             watched_paths: HashMap::new(),
             watcher,
             entry_buf: vec![].into_iter(),
+            marker,
+            backfill: VecDeque::new(),
+            backfilling: None,
+            checkpoints: backfill_checkpoints.map(Checkpoints::load).transpose()?,
+            resume_unknown: positions
+                .as_ref()
+                .map_or(false, |positions| !positions.is_empty()),
+            positions,
+            initialized: false,
+            read_from,
+            position_interval: POSITION_CHECKPOINT_INTERVAL,
+            next_position_checkpoint: Instant::now() + POSITION_CHECKPOINT_INTERVAL,
+            denied: HashMap::new(),
+            diagnostics,
+            poll_root: root_strategy == Strategy::Hybrid,
+            polled_files: HashSet::new(),
+            poll_interval: POLL_INTERVAL,
+            max_active_files,
+            format,
+            activity: 0,
+            spilled: HashMap::new(),
+            spilled_paths: HashMap::new(),
+            sampled_activity: 0,
+            next_sample: Instant::now() + SPILLOVER_INTERVAL,
+            rotated_paths: HashMap::new(),
+            rotated_files: HashSet::new(),
         };
+        let mut diagnostics = Vec::new();
+        let mut present = HashSet::new();
 
         for entry in fs::read_dir(&collector.root_path)? {
             let entry = entry?;
-            if collector.watched_paths.contains_key(&entry.path()) {
+            if let Ok(metadata) = fs::metadata(entry.path()) {
+                present.insert(position_key(&metadata));
+            }
+            if collector.watched_paths.contains_key(&entry.path()) || is_marker(&entry.path()) {
+                continue;
+            }
+
+            if compressed::is_compressed(&entry.path()) {
+                if backfill_compressed {
+                    debug!("Queueing {:?} for backfill", entry.path());
+                    collector.backfill.push_back(entry.path());
+                }
                 continue;
             }
 
@@ -124,42 +595,95 @@ impl<W: Watcher> Collector<W> {
                     canonical_path: canonical_path.clone(),
                 }
             );
-            collector.handle_event_create(path, canonical_path)?;
+            collector.create_or_defer(path, canonical_path, read_from, &mut diagnostics)?;
         }
+        if let Some(positions) = &mut collector.positions {
+            // Forget the checkpoints of files that have since been removed.
+            positions.retain(|key| present.contains(key))?;
+        }
+        collector.initialized = true;
+        for watched_file in collector.watched_files.values_mut() {
+            // Read files that didn't start at their end.
+            watched_file.read_lines(&mut diagnostics)?;
+        }
+        collector.enforce_active_limit()?;
+        collector.entry_buf = diagnostics.into_iter();
 
         Ok(collector)
     }
 
     fn collect_entries(&mut self) -> io::Result<Vec<LogEntry>> {
-        let watcher_events = self.watcher.read_events_blocking()?;
+        let watcher_events = if self.polling() {
+            self.watcher.read_events_timeout(self.poll_interval)?
+        } else {
+            self.watcher.read_events_blocking()?
+        };
+        self.handle_events(watcher_events)
+    }
 
-        let mut entries = Vec::new();
-        let mut read_file = |watched_file: &mut WatchedFile| -> io::Result<()> {
-            while watched_file.reader.read_line(&mut watched_file.entry_buf)? != 0 {
-                if watched_file.entry_buf.ends_with('\n') {
-                    watched_file.entry_buf.pop();
+    /// Whether any paths need to be polled for changes (or denied files retried), every
+    /// `poll_interval`.
+    fn polling(&self) -> bool {
+        self.poll_root
+            || !self.polled_files.is_empty()
+            || !self.spilled.is_empty()
+            || !self.denied.is_empty()
+    }
 
-                    let mut metadata = HashMap::new();
-                    for path in &watched_file.paths {
-                        metadata.insert("path".to_string(), path.clone());
-                        entries.push(LogEntry {
-                            line: watched_file.entry_buf.clone(),
-                            metadata: metadata.clone(),
-                        });
-                    }
+    /// Collect the entries written since `watcher_events` (or since `poll_interval` passed, if
+    /// there are none).
+    fn handle_events(&mut self, watcher_events: Vec<W::Event>) -> io::Result<Vec<LogEntry>> {
+        let polling = self.polling();
+        let mut removed = Vec::new();
+        let mut others = Vec::new();
+        for watcher_event in watcher_events {
+            trace!("Received inotify event: {:?}", watcher_event);
+            let descriptor = watcher_event.descriptor().clone();
+            if watcher_event.removed() && descriptor != self.root_wd {
+                removed.push(descriptor);
+            } else {
+                others.push(descriptor);
+            }
+        }
 
-                    watched_file.entry_buf.clear();
-                }
+        // Finish reading removed files before anything else, then look for files replacing them.
+        let mut descriptors = removed.clone();
+        descriptors.extend(
+            others
+                .into_iter()
+                .filter(|descriptor| !removed.contains(descriptor)),
+        );
+        if !removed.is_empty() {
+            descriptors.push(self.root_wd.clone());
+        }
+        if polling {
+            // Check polled paths as though they had events, in case their watches missed any.
+            if self.poll_root {
+                descriptors.push(self.root_wd.clone());
             }
-            Ok(())
-        };
+            descriptors.extend(self.polled_files.iter().cloned());
+        }
 
-        for watcher_event in watcher_events {
-            trace!("Received inotify event: {:?}", watcher_event);
+        if let Some(marker) = &mut self.marker {
+            marker.refresh();
+        }
+        if Instant::now() >= self.next_position_checkpoint {
+            self.checkpoint_positions()?;
+        }
+
+        let mut entries = Vec::new();
+        let mut diagnostics = Vec::new();
+
+        for descriptor in descriptors {
+            if removed.contains(&descriptor) && !self.watched_files.contains_key(&descriptor) {
+                // The file has already been retired, e.g. this is `IGNORED` after `DELETE_SELF`.
+                continue;
+            }
 
             let mut new_paths = Vec::new();
+            let mut active = false;
 
-            for event in self.check_event(&watcher_event)? {
+            for event in self.check_event(&descriptor)? {
                 debug!("{}", event);
 
                 let watched_file = match event {
@@ -177,25 +701,394 @@ impl<W: Watcher> Collector<W> {
                     }
                 };
 
-                read_file(watched_file)?;
+                active |= watched_file.read_lines(&mut entries)?;
+            }
+            if active {
+                self.touch(&descriptor);
+            }
+            if removed.contains(&descriptor) {
+                self.retire(&descriptor)?;
             }
 
             for (path, canonical_path) in new_paths {
-                let watched_file = self.handle_event_create(path, canonical_path)?;
-                read_file(watched_file)?;
+                let read_from = self.read_from_for(&path);
+                let wd = self.create_or_defer(path, canonical_path, read_from, &mut diagnostics)?;
+                if let Some(wd) = wd {
+                    // `unwrap` is OK since `create_or_defer` registered `wd`.
+                    self.watched_files
+                        .get_mut(&wd)
+                        .unwrap()
+                        .read_lines(&mut entries)?;
+                }
+            }
+        }
+
+        for wd in self.retry_denied(&mut diagnostics)? {
+            // `unwrap` is OK since `retry_denied` registered `wd`.
+            self.watched_files
+                .get_mut(&wd)
+                .unwrap()
+                .read_lines(&mut entries)?;
+        }
+
+        self.enforce_active_limit()?;
+        if !self.spilled.is_empty() && Instant::now() >= self.next_sample {
+            if let Some(summary) = self.sample_spilled(&mut diagnostics)? {
+                entries.push(summary);
+            }
+        }
+
+        diagnostics.extend(entries);
+        Ok(diagnostics)
+    }
+
+    /// Store the positions of followed files that have been read since they were last stored.
+    ///
+    /// This is only called once the entries read before the positions have been emitted, so a
+    /// checkpoint never skips lines that weren't collected.
+    fn checkpoint_positions(&mut self) -> io::Result<()> {
+        self.next_position_checkpoint = Instant::now() + self.position_interval;
+        let positions = match &mut self.positions {
+            Some(positions) => positions,
+            None => return Ok(()),
+        };
+
+        let mut changed = Vec::new();
+        for watched_file in self.watched_files.values_mut() {
+            if let Some(progress) = &mut watched_file.progress {
+                if progress.stored != Some(progress.line_end) {
+                    progress.stored = Some(progress.line_end);
+                    changed.push((progress.key.clone(), progress.line_end));
+                }
+            }
+        }
+        if changed.is_empty() {
+            return Ok(());
+        }
+        trace!("Checkpointing the positions of {} files", changed.len());
+        positions.set_all(changed)
+    }
+
+    /// Stop following the file watched by `wd`, which was renamed or deleted (e.g. rotated).
+    ///
+    /// A new file at any of its paths (within [`REPLACEMENT_WINDOW`]) is read from the start, since
+    /// it was created to replace it. If the file was renamed within the root path it isn't followed
+    /// at its new path, since it has already been read to the end.
+    fn retire(&mut self, wd: &W::Descriptor) -> io::Result<()> {
+        let watched_file = match self.watched_files.remove(wd) {
+            Some(watched_file) => watched_file,
+            None => return Ok(()),
+        };
+        debug!("{} was rotated", watched_file.paths.join(", "));
+
+        if let Err(error) = self.watcher.unwatch(wd) {
+            // The watch is removed automatically when a file is deleted.
+            debug!("Unable to remove watch for rotated file: {}", error);
+        }
+        self.polled_files.remove(wd);
+
+        let paths: Vec<_> = self
+            .watched_paths
+            .iter()
+            .filter(|(_, path_wd)| *path_wd == wd)
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.watched_paths.retain(|_, path_wd| path_wd != wd);
+        let now = Instant::now();
+        for path in paths {
+            self.diagnostics.unwatched(&path);
+            self.rotated_paths.insert(path, now);
+        }
+        self.rotated_files
+            .insert(position_key(&watched_file.reader.get_ref().metadata()?));
+        Ok(())
+    }
+
+    /// Where to start reading a newly discovered file at `path`.
+    fn read_from_for(&mut self, path: &Path) -> ReadFrom {
+        if self.rotated_paths.remove(path).is_some() {
+            debug!("{} replaces a rotated file", path.display());
+            ReadFrom::Beginning
+        } else {
+            self.read_from
+        }
+    }
+
+    /// Record that the file watched by `wd` is active.
+    fn touch(&mut self, wd: &W::Descriptor) {
+        if let Some(watched_file) = self.watched_files.get_mut(wd) {
+            self.activity += 1;
+            watched_file.last_active = self.activity;
+        }
+    }
+
+    /// Demote the least recently active files until no more than `max_active_files` are followed.
+    fn enforce_active_limit(&mut self) -> io::Result<()> {
+        let max_active_files = match self.max_active_files {
+            Some(max_active_files) if self.watched_files.len() > max_active_files => {
+                max_active_files
+            }
+            _ => return Ok(()),
+        };
+
+        let mut by_activity: Vec<_> = self
+            .watched_files
+            .iter()
+            .map(|(wd, watched_file)| (watched_file.last_active, wd.clone()))
+            .collect();
+        by_activity.sort_by_key(|(last_active, _)| *last_active);
+
+        let excess = self.watched_files.len() - max_active_files;
+        info!(
+            "More than {} files are active, demoting {} to spillover",
+            max_active_files, excess
+        );
+        for (_, wd) in by_activity.into_iter().take(excess) {
+            self.demote(&wd)?;
+        }
+        Ok(())
+    }
+
+    /// Stop following the file watched by `wd`, and sample its size instead.
+    fn demote(&mut self, wd: &W::Descriptor) -> io::Result<()> {
+        let watched_file = match self.watched_files.remove(wd) {
+            Some(watched_file) => watched_file,
+            None => return Ok(()),
+        };
+        if let Err(error) = self.watcher.unwatch(wd) {
+            // The file is no longer followed either way, so it's sampled like any other.
+            warn!("Unable to remove watch for demoted file: {}", error);
+        }
+        self.polled_files.remove(wd);
+
+        let canonical_path = watched_file.canonical_path.clone();
+        let mut paths: Vec<_> = self
+            .watched_paths
+            .iter()
+            .filter(|(_, path_wd)| *path_wd == wd)
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.watched_paths.retain(|_, path_wd| path_wd != wd);
+        paths.sort_by_key(|path| path == &canonical_path);
+
+        debug!("Demoting {} to spillover", watched_file.paths.join(", "));
+        for path in &paths {
+            self.diagnostics.watching(path, Strategy::Spillover);
+            self.spilled_paths
+                .insert(path.clone(), canonical_path.clone());
+        }
+        let size = watched_file.reader.get_ref().metadata()?.len();
+        self.spilled
+            .insert(canonical_path, SpilledFile { paths, size });
+        self.update_spillover_gauge();
+        Ok(())
+    }
+
+    /// Sample the sizes of demoted files, returning an entry summarising any growth.
+    ///
+    /// Demoted files that grew are promoted back into any free slots (e.g. left by rotated files),
+    /// and then in place of followed files that have been idle since the previous sample.
+    fn sample_spilled(&mut self, diagnostics: &mut Vec<LogEntry>) -> io::Result<Option<LogEntry>> {
+        let mut growth = Vec::new();
+        let mut removed = Vec::new();
+        for (canonical_path, spilled) in &mut self.spilled {
+            let size = match fs::metadata(canonical_path) {
+                Ok(metadata) => metadata.len(),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    removed.push(canonical_path.clone());
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            // A smaller file has been truncated, and everything in it is new.
+            let delta = if size < spilled.size {
+                size
+            } else {
+                size - spilled.size
+            };
+            spilled.size = size;
+            if delta != 0 {
+                growth.push((delta, canonical_path.clone()));
+            }
+        }
+        for canonical_path in removed {
+            debug!("Demoted file {} has been removed", canonical_path.display());
+            self.forget_spilled(&canonical_path);
+        }
+
+        let entry = if growth.is_empty() {
+            None
+        } else {
+            let summary: serde_json::Map<_, _> = growth
+                .iter()
+                .map(|(delta, canonical_path)| {
+                    let path = &self.spilled[canonical_path].paths[0];
+                    (paths::normalize(path), (*delta).into())
+                })
+                .collect();
+            let mut metadata = HashMap::new();
+            metadata.insert("path".to_string(), paths::normalize(&self.root_path));
+            metadata.insert(SPILLOVER_METADATA_KEY.to_string(), "true".to_string());
+            Some(LogEntry {
+                line: serde_json::json!({
+                    "files": self.spilled.len(),
+                    "bytes": growth.iter().map(|(delta, _)| delta).sum::<u64>(),
+                    "growth": summary,
+                })
+                .to_string(),
+                metadata,
+                timestamp: None,
+            })
+        };
+
+        // Promote the fastest growing demoted files into free slots, and then swap them for followed
+        // files that have been idle.
+        growth.sort();
+        growth.reverse();
+        let free = self.max_active_files.map_or(0, |max_active_files| {
+            max_active_files.saturating_sub(self.watched_files.len())
+        });
+        let mut growth = growth.into_iter();
+        for (_, canonical_path) in growth.by_ref().take(free) {
+            self.promote(&canonical_path, diagnostics)?;
+        }
+        let mut idle: Vec<_> = self
+            .watched_files
+            .iter()
+            .filter(|(_, watched_file)| watched_file.last_active <= self.sampled_activity)
+            .map(|(wd, watched_file)| (watched_file.last_active, wd.clone()))
+            .collect();
+        idle.sort_by_key(|(last_active, _)| *last_active);
+        for ((_, canonical_path), (_, wd)) in growth.zip(idle) {
+            self.demote(&wd)?;
+            self.promote(&canonical_path, diagnostics)?;
+        }
+
+        self.sampled_activity = self.activity;
+        self.next_sample = Instant::now() + SPILLOVER_INTERVAL;
+        Ok(entry)
+    }
+
+    /// Follow the demoted file at `canonical_path` again.
+    fn promote(
+        &mut self,
+        canonical_path: &Path,
+        diagnostics: &mut Vec<LogEntry>,
+    ) -> io::Result<()> {
+        let spilled = match self.forget_spilled(canonical_path) {
+            Some(spilled) => spilled,
+            None => return Ok(()),
+        };
+        info!("Promoting {} from spillover", canonical_path.display());
+        // Other paths are re-registered along with the first.
+        for path in &spilled.paths {
+            if !self.watched_paths.contains_key(path) {
+                // Lines written while the file was demoted aren't collected.
+                self.create_or_defer(
+                    path.clone(),
+                    canonical_path.to_path_buf(),
+                    ReadFrom::End,
+                    diagnostics,
+                )?;
+            }
+            if let Some(wd) = self.watched_paths.get(path) {
+                let strategy = if self.polled_files.contains(wd) {
+                    Strategy::Hybrid
+                } else {
+                    Strategy::Native
+                };
+                self.diagnostics.watching(path, strategy);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop sampling the demoted file at `canonical_path`, returning it if it was demoted.
+    fn forget_spilled(&mut self, canonical_path: &Path) -> Option<SpilledFile> {
+        let spilled = self.spilled.remove(canonical_path)?;
+        for path in &spilled.paths {
+            self.spilled_paths.remove(path);
+        }
+        self.update_spillover_gauge();
+        Some(spilled)
+    }
+
+    fn update_spillover_gauge(&self) {
+        SPILLOVER_FILES.set(i64::try_from(self.spilled.len()).unwrap_or(i64::MAX));
+    }
+
+    fn backfill_entry(&mut self) -> io::Result<Option<LogEntry>> {
+        loop {
+            if let Some((file, parser)) = &mut self.backfilling {
+                if let Some(entry) = file.read_entry()? {
+                    if let Some(entry) = parser.parse_entry(entry) {
+                        return Ok(Some(entry));
+                    }
+                    continue;
+                }
+
+                debug!(
+                    "Finished backfilling {} ({} bytes uncompressed)",
+                    file.path(),
+                    file.offset()
+                );
+                if let Some(checkpoints) = &mut self.checkpoints {
+                    checkpoints.set(file.path(), file.checkpoint())?;
+                }
+                self.backfilling = None;
+            }
+
+            // Files are only opened once they're reached, so a directory full of rotated files
+            // doesn't hold a descriptor and decoder for each of them.
+            let path = match self.backfill.pop_front() {
+                Some(path) => path,
+                None => return Ok(None),
+            };
+            match self.open_backfill(&path) {
+                Ok(file) => self.backfilling = Some((file, Parser::new(self.format))),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    warn!("Skipping backfill of {}: {}", path.display(), error);
+                }
+                Err(error) => return Err(error),
             }
         }
+    }
 
-        Ok(entries)
+    /// Open a compressed file to backfill, resuming from its checkpoint if it has one.
+    fn open_backfill(&self, path: &Path) -> io::Result<CompressedFile> {
+        let checkpoint = self
+            .checkpoints
+            .as_ref()
+            .and_then(|checkpoints| checkpoints.get(&paths::normalize(path)));
+        match checkpoint {
+            Some(checkpoint) => CompressedFile::resume(path, checkpoint),
+            None => CompressedFile::open(path, 0),
+        }
     }
 
-    fn check_event(&mut self, watcher_event: &W::Event) -> io::Result<Vec<Event>> {
-        if watcher_event.descriptor() == &self.root_wd {
+    fn check_event(&mut self, descriptor: &W::Descriptor) -> io::Result<Vec<Event>> {
+        if descriptor == &self.root_wd {
             let mut events = Vec::new();
+            let mut rotated_files = HashSet::new();
 
             for entry in fs::read_dir(&self.root_path)? {
                 let entry = entry?;
-                if self.watched_paths.contains_key(&entry.path()) {
+                if !self.rotated_files.is_empty() {
+                    if let Ok(metadata) = fs::metadata(entry.path()) {
+                        let key = position_key(&metadata);
+                        if self.rotated_files.contains(&key) {
+                            // The file was renamed when it was rotated, and has already been read.
+                            rotated_files.insert(key);
+                            continue;
+                        }
+                    }
+                }
+                if self.watched_paths.contains_key(&entry.path())
+                    || self.denied.contains_key(&entry.path())
+                    || self.spilled_paths.contains_key(&entry.path())
+                    || is_marker(&entry.path())
+                    || compressed::is_compressed(&entry.path())
+                {
                     continue;
                 }
 
@@ -206,82 +1099,322 @@ impl<W: Watcher> Collector<W> {
                     canonical_path,
                 });
             }
+            self.rotated_files = rotated_files;
+            self.rotated_paths
+                .retain(|_, rotated| rotated.elapsed() < REPLACEMENT_WINDOW);
 
             return Ok(events);
         }
 
-        let watched_file = match self.watched_files.get_mut(watcher_event.descriptor()) {
+        let watched_file = match self.watched_files.get_mut(descriptor) {
             None => {
                 warn!(
                     "Received event for unregistered watch descriptor: {:?}",
-                    watcher_event
+                    descriptor
                 );
                 return Ok(vec![]);
             }
             Some(watched_file) => watched_file,
         };
 
-        let metadata = watched_file.reader.get_ref().metadata()?;
-        let seekpos = watched_file.reader.seek(io::SeekFrom::Current(0))?;
-
-        if seekpos <= metadata.len() {
-            Ok(vec![Event::Append { watched_file }])
-        } else {
+        if watched_file.truncated()? {
             Ok(vec![Event::Truncate { watched_file }])
+        } else {
+            Ok(vec![Event::Append { watched_file }])
+        }
+    }
+
+    /// Start watching a new file, or schedule a retry if it can't be opened due to permissions.
+    ///
+    /// Returns the descriptor of the watched file, if it could be watched.
+    fn create_or_defer(
+        &mut self,
+        path: PathBuf,
+        canonical_path: PathBuf,
+        read_from: ReadFrom,
+        diagnostics: &mut Vec<LogEntry>,
+    ) -> io::Result<Option<W::Descriptor>> {
+        match self.handle_event_create(path.clone(), canonical_path.clone(), read_from) {
+            Ok(wd) => {
+                self.diagnostics.clear(&path);
+                if self.denied.remove(&path).is_some() {
+                    info!("Permission to read {} has been granted", path.display());
+                    self.update_denied_gauge();
+                }
+                Ok(Some(wd))
+            }
+            Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+                PERMISSION_DENIED_TOTAL.inc();
+
+                if let Some(denied) = self.denied.get_mut(&path) {
+                    denied.backoff = (denied.backoff * 2).min(RETRY_MAX_BACKOFF);
+                    denied.next_attempt = Instant::now() + denied.backoff;
+                    return Ok(None);
+                }
+
+                warn!(
+                    "Unable to read {}, will retry in the background: {}",
+                    path.display(),
+                    error
+                );
+
+                let mut metadata = HashMap::new();
+                metadata.insert("path".to_string(), paths::normalize(&path));
+                diagnostics.push(LogEntry {
+                    line: format!(
+                        "monitoring-rs: unable to read {} ({}), will retry until it is readable",
+                        path.display(),
+                        error
+                    ),
+                    metadata,
+                    timestamp: None,
+                });
+
+                self.denied.insert(
+                    path,
+                    DeniedFile {
+                        canonical_path,
+                        backoff: RETRY_INITIAL_BACKOFF,
+                        next_attempt: Instant::now() + RETRY_INITIAL_BACKOFF,
+                    },
+                );
+                self.update_denied_gauge();
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Retry any denied files that are due, returning the descriptors of any now being watched.
+    ///
+    /// Files that become readable are read from their checkpoint or the start, regardless of their
+    /// `read_from`, so that lines written while they were denied aren't skipped.
+    fn retry_denied(&mut self, diagnostics: &mut Vec<LogEntry>) -> io::Result<Vec<W::Descriptor>> {
+        let now = Instant::now();
+        let due: Vec<_> = self
+            .denied
+            .iter()
+            .filter(|(_, denied)| denied.next_attempt <= now)
+            .map(|(path, denied)| (path.clone(), denied.canonical_path.clone()))
+            .collect();
+
+        let mut wds = Vec::new();
+        for (path, canonical_path) in due {
+            if !path.exists() {
+                debug!("Denied file {} has been removed", path.display());
+                self.denied.remove(&path);
+                self.update_denied_gauge();
+                continue;
+            }
+
+            let read_from = ReadFrom::Beginning;
+            if let Some(wd) = self.create_or_defer(path, canonical_path, read_from, diagnostics)? {
+                wds.push(wd);
+            }
         }
+        Ok(wds)
+    }
+
+    fn update_denied_gauge(&self) {
+        PERMISSION_DENIED_FILES.set(i64::try_from(self.denied.len()).unwrap_or(i64::MAX));
     }
 
     fn handle_event_create(
         &mut self,
         path: PathBuf,
         canonical_path: PathBuf,
-    ) -> io::Result<&mut WatchedFile> {
+        read_from: ReadFrom,
+    ) -> io::Result<W::Descriptor> {
         if let Some(wd) = self.watched_paths.get(&canonical_path) {
             let wd = wd.clone();
 
             // unwrap is safe because we any `wd` in `watched_paths` must be present in `watched_files`
             let watched_file = self.watched_files.get_mut(&wd).unwrap();
-            watched_file.paths.push(path.to_string_lossy().to_string());
+            watched_file.paths.push(paths::normalize(&path));
 
-            self.watched_paths.insert(path, wd);
-            Ok(watched_file)
+            self.watched_paths.insert(path, wd.clone());
+            Ok(wd)
         } else {
-            let wd = self.watcher.watch_file(&canonical_path)?;
-
-            let mut reader = BufReader::new(File::open(&canonical_path)?);
-            reader.seek(io::SeekFrom::End(0))?;
+            // Open the file before watching it, so we don't leak a watch if it can't be read.
+            let file = File::open(&canonical_path)
+                .map_err(|error| self.diagnostics.check(&path, "open", error))?;
+            let mut reader = BufReader::new(file);
+            let progress = self.seek_start(&path, &mut reader, read_from)?;
+            let offset = reader.seek(io::SeekFrom::Current(0))?;
+            let tail = read_tail(reader.get_ref(), offset)?;
+
+            let wd = self
+                .watcher
+                .watch_file(&canonical_path)
+                .map_err(|error| self.diagnostics.check(&path, "watch", error))?;
+
+            let strategy = strategy(&canonical_path);
+            if strategy == Strategy::Hybrid {
+                self.polled_files.insert(wd.clone());
+            }
+            self.diagnostics.watching(&path, strategy);
 
-            let mut paths = vec![path.to_string_lossy().to_string()];
+            let mut file_paths = vec![paths::normalize(&path)];
             if canonical_path != path && canonical_path.starts_with(&self.root_path) {
-                paths.push(canonical_path.to_string_lossy().to_string());
+                file_paths.push(paths::normalize(&canonical_path));
             }
 
             if canonical_path != path {
-                self.watched_paths.insert(canonical_path, wd.clone());
+                self.watched_paths
+                    .insert(canonical_path.clone(), wd.clone());
             }
             self.watched_paths.insert(path, wd.clone());
 
-            Ok(self.watched_files.entry(wd).or_insert(WatchedFile {
-                paths,
+            self.activity += 1;
+            self.watched_files.entry(wd.clone()).or_insert(WatchedFile {
+                paths: file_paths,
+                canonical_path,
                 reader,
                 entry_buf: String::new(),
-            }))
+                parser: Parser::new(self.format),
+                last_active: self.activity,
+                progress,
+                tail,
+            });
+            Ok(wd)
         }
     }
 
-    fn handle_event_truncate(watched_file: &mut WatchedFile) -> io::Result<()> {
-        watched_file.reader.seek(io::SeekFrom::Start(0))?;
-        watched_file.entry_buf.clear();
-        Ok(())
-    }
-}
-
-impl<W: Watcher> super::Collector for Collector<W> {}
+    /// Position `reader` where collection of the newly followed file at `path` should start,
+    /// returning its progress if positions are checkpointed.
+    ///
+    /// Files start where `read_from` says, except at initialization (or when a file that was denied
+    /// becomes readable), when files resume from their checkpoint (see
+    /// [`Config::position_checkpoints`]).
+    fn seek_start(
+        &self,
+        path: &Path,
+        reader: &mut BufReader<File>,
+        read_from: ReadFrom,
+    ) -> io::Result<Option<Progress>> {
+        let key = match &self.positions {
+            Some(_) => Some(position_key(&reader.get_ref().metadata()?)),
+            None => None,
+        };
+
+        if !self.initialized || self.denied.contains_key(path) {
+            let checkpoint = self
+                .positions
+                .as_ref()
+                .zip(key.as_ref())
+                .and_then(|(positions, key)| positions.get(key));
+            if let (Some(checkpoint), Some(key)) = (checkpoint, key.clone()) {
+                if let Some(hasher) = checkpoint.verify(reader)? {
+                    debug!(
+                        "Resuming {} at offset {}",
+                        path.display(),
+                        checkpoint.offset
+                    );
+                    return Ok(Some(Progress::new(
+                        key,
+                        hasher,
+                        checkpoint.offset,
+                        Some(checkpoint),
+                    )));
+                }
+                info!(
+                    "{} has changed since it was checkpointed, reading it from the start",
+                    path.display()
+                );
+                reader.seek(io::SeekFrom::Start(0))?;
+                return Ok(Some(Progress::new(key, Hasher::new(), 0, None)));
+            }
+        }
+
+        let read_from = if !self.initialized && self.resume_unknown {
+            ReadFrom::Beginning
+        } else {
+            read_from
+        };
+        match (read_from, key) {
+            (ReadFrom::Beginning, key) => {
+                debug!("Reading {} from the start", path.display());
+                Ok(key.map(|key| Progress::new(key, Hasher::new(), 0, None)))
+            }
+            (ReadFrom::End, None) => {
+                reader.seek(io::SeekFrom::End(0))?;
+                Ok(None)
+            }
+            (ReadFrom::End, Some(key)) => {
+                // Hash the content that's skipped, so that the position can be checkpointed.
+                let mut hasher = Hasher::new();
+                let offset = io::copy(reader, &mut hasher)?;
+                Ok(Some(Progress::new(key, hasher, offset, None)))
+            }
+        }
+    }
+
+    fn handle_event_truncate(watched_file: &mut WatchedFile) -> io::Result<()> {
+        watched_file.reader.seek(io::SeekFrom::Start(0))?;
+        watched_file.entry_buf.clear();
+        watched_file.tail.clear();
+        watched_file.parser.reset();
+        if let Some(progress) = &mut watched_file.progress {
+            progress.reset();
+        }
+        Ok(())
+    }
+}
+
+/// Choose the strategy for watching `path`, based on the filesystem it's on.
+fn strategy(path: &Path) -> Strategy {
+    match filesystem::network_filesystem(path) {
+        Ok(None) => Strategy::Native,
+        Ok(Some(filesystem)) => {
+            info!(
+                "{} is on a network filesystem ({}), it will also be polled for changes",
+                path.display(),
+                filesystem
+            );
+            Strategy::Hybrid
+        }
+        Err(error) => {
+            warn!(
+                "Unable to determine the filesystem of {}, assuming it's local: {}",
+                path.display(),
+                error
+            );
+            Strategy::Native
+        }
+    }
+}
+
+/// Read the (up to) [`TAIL_LEN`] bytes of `file` before `offset`.
+fn read_tail(file: &File, offset: u64) -> io::Result<Vec<u8>> {
+    let len = usize::try_from(offset).map_or(TAIL_LEN, |offset| offset.min(TAIL_LEN));
+    let mut tail = vec![0; len];
+    file.read_exact_at(&mut tail, offset - len as u64)?;
+    Ok(tail)
+}
+
+/// The key of a file's position checkpoint, from its device and inode numbers.
+fn position_key(metadata: &fs::Metadata) -> String {
+    format!("{}:{}", metadata.dev(), metadata.ino())
+}
+
+fn is_marker(path: &Path) -> bool {
+    path.file_name() == Some(OsStr::new(ownership::MARKER_FILE_NAME))
+}
+
+impl<W: Watcher> super::Collector for Collector<W> {}
 
 impl<W: Watcher> Iterator for Collector<W> {
     type Item = Result<LogEntry, io::Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.entry_buf.len() == 0 {
+            match self.backfill_entry() {
+                Ok(None) => {}
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Err(error) => return Some(Err(error)),
+            }
+        }
+
         while self.entry_buf.len() == 0 {
             let entries = match self.collect_entries() {
                 Ok(entries) => entries,
@@ -294,19 +1427,140 @@ impl<W: Watcher> Iterator for Collector<W> {
     }
 }
 
+/// A [`Collector`] that waits for changes asynchronously, see [`initialize_async`].
+pub(super) struct Streaming<W: Watcher> {
+    /// Becomes readable when the watcher has events, if the watcher has a file descriptor.
+    ///
+    /// This is declared first, so it's deregistered before the watcher closes the descriptor.
+    readiness: Option<Async<Readiness>>,
+
+    /// Fires when polled paths should next be checked (or, without `readiness`, when the watcher
+    /// should next be checked for events).
+    timer: Option<Timer>,
+    collector: Collector<W>,
+}
+
+/// A watcher's file descriptor, which is owned (and closed) by the watcher.
+#[derive(Debug)]
+struct Readiness(RawFd);
+
+impl AsRawFd for Readiness {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl<W: Watcher> Streaming<W> {
+    pub(super) fn new(collector: Collector<W>) -> io::Result<Self> {
+        let readiness = match collector.watcher.readiness_fd() {
+            Some(fd) => Some(Async::new(Readiness(fd))?),
+            None => None,
+        };
+        Ok(Self {
+            readiness,
+            timer: None,
+            collector,
+        })
+    }
+
+    /// Wait for watcher events, or for polled paths to be due a check (in which case there may be
+    /// no events).
+    fn poll_events(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Vec<W::Event>>> {
+        loop {
+            let events = match self.collector.watcher.read_events() {
+                Ok(events) => events,
+                Err(error) => return Poll::Ready(Err(error)),
+            };
+            if !events.is_empty() {
+                self.timer = None;
+                return Poll::Ready(Ok(events));
+            }
+
+            let polling = self.collector.polling();
+            if polling || self.readiness.is_none() {
+                let interval = if polling {
+                    self.collector.poll_interval
+                } else {
+                    TIMEOUT_CHECK_INTERVAL
+                };
+                let timer = self.timer.get_or_insert_with(|| Timer::after(interval));
+                if Pin::new(timer).poll(cx).is_ready() {
+                    self.timer = None;
+                    if polling {
+                        return Poll::Ready(Ok(events));
+                    }
+                    continue;
+                }
+            }
+
+            match &self.readiness {
+                Some(readiness) => match readiness.poll_readable(cx) {
+                    Poll::Ready(Ok(())) => continue,
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                    Poll::Pending => return Poll::Pending,
+                },
+                // The timer will wake us.
+                None => return Poll::Pending,
+            }
+        }
+    }
+}
+
+// Nothing is pinned, the collector's state is only ever accessed through `&mut`.
+impl<W: Watcher> Unpin for Streaming<W> {}
+
+impl<W: Watcher> super::AsyncCollector for Streaming<W> {}
+
+impl<W: Watcher> Stream for Streaming<W> {
+    type Item = Result<LogEntry, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(entry) = this.collector.entry_buf.next() {
+                return Poll::Ready(Some(Ok(entry)));
+            }
+            match this.collector.backfill_entry() {
+                Ok(None) => {}
+                Ok(Some(entry)) => return Poll::Ready(Some(Ok(entry))),
+                Err(error) => return Poll::Ready(Some(Err(error))),
+            }
+
+            let events = match this.poll_events(cx) {
+                Poll::Ready(Ok(events)) => events,
+                Poll::Ready(Err(error)) => return Poll::Ready(Some(Err(error))),
+                Poll::Pending => return Poll::Pending,
+            };
+            match this.collector.handle_events(events) {
+                Ok(entries) => this.collector.entry_buf = entries.into_iter(),
+                Err(error) => return Poll::Ready(Some(Err(error))),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::io::{self, Write};
     use std::os::unix;
     use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
+    use async_std::future::timeout;
+    use async_std::stream::StreamExt;
+    use async_std::task;
     use tempfile::TempDir;
 
+    use crate::log_collector::diagnostics::{Diagnostics, Strategy};
+    use crate::log_collector::ownership::MARKER_FILE_NAME;
     use crate::log_collector::watcher::{mock, watcher};
     use crate::test::{self, log_entry};
+    use crate::LogEntry;
 
-    use super::{Collector, Config};
+    use super::super::format::Format;
+    use super::{Collector, Config, ReadFrom, Streaming};
 
     #[test]
     fn initialize_with_symlink() -> test::Result {
@@ -318,6 +1572,14 @@ mod tests {
 
         let config = Config {
             root_path: root_path.clone(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
         };
         let mut watcher = mock::Watcher::new();
         let mut collector = Collector::initialize(config, watcher.clone())?;
@@ -352,6 +1614,14 @@ mod tests {
 
         let config = Config {
             root_path: root_dir.path().to_path_buf(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
         };
         let mut watcher = mock::Watcher::new();
         let mut collector = Collector::initialize(config, watcher.clone())?;
@@ -377,7 +1647,17 @@ mod tests {
         let dst_path = root_path.join("linked.log");
         unix::fs::symlink(&src_path, &dst_path)?;
 
-        let config = Config { root_path };
+        let config = Config {
+            root_path,
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
         let mut watcher = mock::Watcher::new();
         let mut collector = Collector::initialize(config, watcher.clone())?;
 
@@ -420,6 +1700,14 @@ mod tests {
 
         let config = Config {
             root_path: root_path.clone(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
         };
         let mut watcher = mock::Watcher::new();
         let mut collector = Collector::initialize(config, watcher.clone())?;
@@ -454,6 +1742,14 @@ mod tests {
         let tempdir = tempfile::tempdir()?;
         let config = Config {
             root_path: tempdir.path().to_path_buf(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
         };
         let mut collector = Collector::initialize(config, watcher()?)?;
 
@@ -471,6 +1767,14 @@ mod tests {
         let tempdir = tempfile::tempdir()?;
         let config = Config {
             root_path: tempdir.path().to_path_buf(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
         };
         let mut collector = Collector::initialize(config, watcher()?)?;
 
@@ -493,11 +1797,64 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn collect_entries_docker_format() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let config = Config {
+            root_path: tempdir.path().to_path_buf(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Docker,
+            diagnostics: Arc::default(),
+        };
+        let mut collector = Collector::initialize(config, watcher()?)?;
+
+        let (file_path, mut file) = create_log_file(&tempdir)?;
+        let path = file_path.to_str().unwrap();
+
+        collector.collect_entries()?;
+
+        writeln!(
+            file,
+            r#"{{"log":"hello\n","stream":"stdout","time":"2021-01-01T00:00:00Z"}}"#
+        )?;
+        writeln!(file, r#"{{"log":"wor","stream":"stderr"}}"#)?;
+        writeln!(file, r#"{{"log":"ld!\n","stream":"stderr"}}"#)?;
+        writeln!(file, "plain")?;
+
+        let entries = collector.collect_entries()?;
+        assert_eq!(
+            entries,
+            vec![
+                LogEntry {
+                    timestamp: Some(1_609_459_200_000),
+                    ..log_entry("hello", &[("path", path), ("stream", "stdout")])
+                },
+                log_entry("world!", &[("path", path), ("stream", "stderr")]),
+                log_entry("plain", &[("path", path)]),
+            ]
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn iterator_yields_entries() -> test::Result {
         let tempdir = tempfile::tempdir()?;
         let config = Config {
             root_path: tempdir.path().to_path_buf(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
         };
         let mut collector = Collector::initialize(config, watcher()?)?;
 
@@ -521,6 +1878,609 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn ownership_marker_is_not_collected() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let config = Config {
+            root_path: tempdir.path().to_path_buf(),
+            ownership_marker: true,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
+        let collector = Collector::initialize(config, mock::Watcher::new())?;
+
+        assert!(tempdir.path().join(MARKER_FILE_NAME).exists());
+        assert!(collector.watched_files.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn backfills_compressed_files() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("test.log.1.gz");
+
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&path)?, flate2::Compression::default());
+        encoder.write_all(b"old\n")?;
+        encoder.finish()?;
+
+        let config = Config {
+            root_path: tempdir.path().to_path_buf(),
+            ownership_marker: false,
+            backfill_compressed: true,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
+        let mut collector = Collector::initialize(config, mock::Watcher::new())?;
+        assert!(collector.watched_files.is_empty());
+
+        assert_eq!(
+            collector.next().expect("expected a backfilled entry")?,
+            log_entry("old", &[("path", path.to_str().unwrap())])
+        );
+        assert!(collector.backfill_entry()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn skips_checkpointed_backfill() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let checkpoint_dir = tempfile::tempdir()?;
+        let path = tempdir.path().join("test.log.1.gz");
+
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&path)?, flate2::Compression::default());
+        encoder.write_all(b"old\n")?;
+        encoder.finish()?;
+
+        let config = || Config {
+            root_path: tempdir.path().to_path_buf(),
+            ownership_marker: false,
+            backfill_compressed: true,
+            backfill_checkpoints: Some(checkpoint_dir.path().join("checkpoints")),
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
+        let mut collector = Collector::initialize(config(), mock::Watcher::new())?;
+        assert_eq!(
+            collector.backfill_entry()?,
+            Some(log_entry("old", &[("path", path.to_str().unwrap())]))
+        );
+        assert!(collector.backfill_entry()?.is_none());
+
+        // Backfilling again after a restart doesn't repeat the file.
+        let mut collector = Collector::initialize(config(), mock::Watcher::new())?;
+        assert!(collector.backfill_entry()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resumes_from_position_checkpoints() -> test::Result {
+        let root_dir = tempfile::tempdir()?;
+        let checkpoint_dir = tempfile::tempdir()?;
+        let root_path = root_dir.path().canonicalize()?;
+        let path = root_path.join("test.log");
+        let rotated_path = root_path.join("test.log.1");
+        fs::write(&path, "old\n")?;
+
+        let config = || Config {
+            root_path: root_path.clone(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: Some(checkpoint_dir.path().join("positions")),
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
+        let sorted = |entries: &mut dyn Iterator<Item = LogEntry>| {
+            let mut entries: Vec<_> = entries.collect();
+            entries.sort_by(|a, b| a.line.cmp(&b.line));
+            entries
+        };
+
+        // Without any checkpoints, files are read from their end.
+        let mut watcher = mock::Watcher::new();
+        let mut collector = Collector::initialize(config(), watcher.clone())?;
+        assert_eq!(collector.entry_buf.len(), 0);
+        watcher.simulate_write(&path, "one\ntw")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("one", &[("path", path.to_str().unwrap())])]
+        );
+        collector.checkpoint_positions()?;
+        drop(collector);
+
+        // After a restart, files resume from their checkpoint, even if they've been renamed, and
+        // new files are read from the start.
+        let mut file = fs::OpenOptions::new().append(true).open(&path)?;
+        writeln!(file, "o")?;
+        writeln!(file, "three")?;
+        fs::rename(&path, &rotated_path)?;
+        fs::write(&path, "new\n")?;
+
+        let mut collector = Collector::initialize(config(), mock::Watcher::new())?;
+        assert_eq!(
+            sorted(&mut collector.entry_buf),
+            vec![
+                log_entry("new", &[("path", path.to_str().unwrap())]),
+                log_entry("three", &[("path", rotated_path.to_str().unwrap())]),
+                log_entry("two", &[("path", rotated_path.to_str().unwrap())]),
+            ]
+        );
+        collector.checkpoint_positions()?;
+        drop(collector);
+
+        // Files whose content has been replaced are read from the start.
+        fs::write(&rotated_path, "replaced\n")?;
+        let mut collector = Collector::initialize(config(), mock::Watcher::new())?;
+        assert_eq!(
+            sorted(&mut collector.entry_buf),
+            vec![log_entry(
+                "replaced",
+                &[("path", rotated_path.to_str().unwrap())]
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_from_beginning() -> test::Result {
+        let root_dir = tempfile::tempdir()?;
+        let root_path = root_dir.path().canonicalize()?;
+        let existing_path = root_path.join("existing.log");
+        fs::write(&existing_path, "old\n")?;
+
+        let config = Config {
+            root_path: root_path.clone(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::Beginning,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
+        let mut watcher = mock::Watcher::new();
+        let mut collector = Collector::initialize(config, watcher.clone())?;
+        assert_eq!(
+            collector.entry_buf.by_ref().collect::<Vec<_>>(),
+            vec![log_entry(
+                "old",
+                &[("path", existing_path.to_str().unwrap())]
+            )]
+        );
+
+        // Files discovered later are also read in full.
+        let path = watcher.simulate_new_file(&root_path)?;
+        fs::write(&path, "new\n")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("new", &[("path", path.to_str().unwrap())])]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn follows_rotated_files() -> test::Result {
+        let root_dir = tempfile::tempdir()?;
+        let root_path = root_dir.path().canonicalize()?;
+
+        let config = Config {
+            root_path: root_path.clone(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
+        let mut watcher = mock::Watcher::new();
+        let mut collector = Collector::initialize(config, watcher.clone())?;
+
+        let path = watcher.simulate_new_file(&root_path)?;
+        watcher.simulate_write(&path, "one\n")?;
+        let path_meta = &[("path", path.to_str().unwrap())];
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("one", path_meta)]
+        );
+
+        // The rotated file is read to the end, and its replacement from the start.
+        let rotated_path = root_path.join("test.log.1");
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(b"two\n")?;
+        watcher.simulate_rename(&path, &rotated_path)?;
+        fs::write(&path, "three\n")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("two", path_meta), log_entry("three", path_meta)]
+        );
+        assert!(!collector.watched_paths.contains_key(&rotated_path));
+
+        watcher.simulate_write(&path, "four\n")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("four", path_meta)]
+        );
+
+        // Deleted files are also read to the end.
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(b"five\n")?;
+        watcher.simulate_remove(&path)?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("five", path_meta)]
+        );
+        assert!(collector.watched_files.is_empty());
+        assert!(collector.watched_paths.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_truncated_files_from_start() -> test::Result {
+        let root_dir = tempfile::tempdir()?;
+        let root_path = root_dir.path().canonicalize()?;
+
+        let config = Config {
+            root_path: root_path.clone(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
+        let mut watcher = mock::Watcher::new();
+        let mut collector = Collector::initialize(config, watcher.clone())?;
+
+        let path = watcher.simulate_new_file(&root_path)?;
+        let path_meta = &[("path", path.to_str().unwrap())];
+        watcher.simulate_write(&path, "one\ntwo\n")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("one", path_meta), log_entry("two", path_meta)]
+        );
+
+        // A file that's shorter than the read position has been truncated.
+        File::create(&path)?;
+        watcher.simulate_write(&path, "three\n")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("three", path_meta)]
+        );
+
+        // A truncated file may be written past the read position before the truncation is seen.
+        File::create(&path)?;
+        watcher.simulate_write(&path, "a line longer than the last\n")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("a line longer than the last", path_meta)]
+        );
+
+        // ...or to exactly the read position.
+        File::create(&path)?;
+        watcher.simulate_write(&path, "a line longer than the next\n")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("a line longer than the next", path_meta)]
+        );
+
+        // Appends are still read from the read position.
+        watcher.simulate_write(&path, "four\n")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("four", path_meta)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn streams_entries() -> test::Result {
+        let root_dir = tempfile::tempdir()?;
+        let root_path = root_dir.path().canonicalize()?;
+
+        let config = Config {
+            root_path: root_path.clone(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
+        let mut watcher = mock::Watcher::new();
+        let collector = Collector::initialize(config, watcher.clone())?;
+        let mut stream = Streaming::new(collector)?;
+
+        // Entries are streamed as the watcher reports changes.
+        let path = watcher.simulate_new_file(&root_path)?;
+        watcher.simulate_write(&path, "hello\nworld\n")?;
+        let path_meta = &[("path", path.to_str().unwrap())];
+        assert_eq!(
+            task::block_on(stream.next()).transpose()?,
+            Some(log_entry("hello", path_meta))
+        );
+        assert_eq!(
+            task::block_on(stream.next()).transpose()?,
+            Some(log_entry("world", path_meta))
+        );
+
+        // The stream waits for the next change.
+        let waiting = task::block_on(timeout(Duration::from_millis(100), stream.next()));
+        assert!(waiting.is_err());
+
+        watcher.simulate_write(&path, "again\n")?;
+        assert_eq!(
+            task::block_on(stream.next()).transpose()?,
+            Some(log_entry("again", path_meta))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn polls_network_paths() -> test::Result {
+        let root_dir = tempfile::tempdir()?;
+        let root_path = root_dir.path().canonicalize()?;
+        let diagnostics = Arc::new(Diagnostics::new());
+
+        let config = Config {
+            root_path: root_path.clone(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::clone(&diagnostics),
+        };
+        let mut collector = Collector::initialize(config, mock::Watcher::new())?;
+        assert_eq!(
+            diagnostics.report().strategies.get(&root_path),
+            Some(&Strategy::Native)
+        );
+
+        // Simulate the root being on a network filesystem, where the watcher sees no events.
+        collector.poll_root = true;
+        collector.poll_interval = Duration::from_millis(1);
+
+        let path = root_path.join("test.log");
+        let mut file = File::create(&path)?;
+        assert_eq!(collector.collect_entries()?, vec![]);
+        assert!(collector.watched_paths.contains_key(&path));
+
+        collector.polled_files.insert(path.clone());
+        file.write_all(b"hello\n")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("hello", &[("path", path.to_str().unwrap())])]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn spills_over_inactive_files() -> test::Result {
+        let root_dir = tempfile::tempdir()?;
+        let root_path = root_dir.path().canonicalize()?;
+        let diagnostics = Arc::new(Diagnostics::new());
+        let a_path = root_path.join("a.log");
+        let b_path = root_path.join("b.log");
+        let c_path = root_path.join("c.log");
+        File::create(&a_path)?;
+        let mut b_file = File::create(&b_path)?;
+
+        let config = Config {
+            root_path: root_path.clone(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: Some(2),
+            format: Format::Plain,
+            diagnostics: Arc::clone(&diagnostics),
+        };
+        let mut watcher = mock::Watcher::new();
+        let mut collector = Collector::initialize(config, watcher.clone())?;
+        collector.poll_root = true;
+        collector.poll_interval = Duration::from_millis(1);
+
+        // `b.log` is the least recently active when `c.log` appears, so it's demoted.
+        watcher.simulate_write(&a_path, "a\n")?;
+        collector.collect_entries()?;
+        File::create(&c_path)?;
+        assert_eq!(collector.collect_entries()?, vec![]);
+        assert_eq!(collector.watched_files.len(), 2);
+        assert!(collector.spilled_paths.contains_key(&b_path));
+        assert_eq!(
+            diagnostics.report().strategies.get(&b_path),
+            Some(&Strategy::Spillover)
+        );
+
+        // The growth of `b.log` is summarised, but its lines aren't collected.
+        b_file.write_all(b"hello\n")?;
+        collector.next_sample = Instant::now();
+        let entries = collector.collect_entries()?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].metadata.get("spillover").map(String::as_str),
+            Some("true")
+        );
+        let summary: serde_json::Value = serde_json::from_str(&entries[0].line)?;
+        assert_eq!(
+            summary,
+            serde_json::json!({
+                "files": 1,
+                "bytes": 6,
+                "growth": { b_path.to_str().unwrap(): 6 },
+            })
+        );
+
+        // Once the followed files have been idle for a sample, `b.log` is promoted again.
+        b_file.write_all(b"hello\n")?;
+        collector.next_sample = Instant::now();
+        assert_eq!(collector.collect_entries()?.len(), 1);
+        assert!(collector.watched_paths.contains_key(&b_path));
+        assert!(collector.spilled_paths.contains_key(&a_path));
+        assert_eq!(
+            diagnostics.report().strategies.get(&b_path),
+            Some(&Strategy::Native)
+        );
+
+        watcher.simulate_write(&b_path, "followed\n")?;
+        assert_eq!(
+            collector.collect_entries()?,
+            vec![log_entry("followed", &[("path", b_path.to_str().unwrap())])]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn promotes_spilled_files_into_free_slots() -> test::Result {
+        let root_dir = tempfile::tempdir()?;
+        let root_path = root_dir.path().canonicalize()?;
+        let a_path = root_path.join("a.log");
+        let b_path = root_path.join("b.log");
+        let c_path = root_path.join("c.log");
+        File::create(&a_path)?;
+        let mut b_file = File::create(&b_path)?;
+
+        let config = Config {
+            root_path: root_path.clone(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: Some(2),
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
+        let mut watcher = mock::Watcher::new();
+        let mut collector = Collector::initialize(config, watcher.clone())?;
+        collector.poll_root = true;
+        collector.poll_interval = Duration::from_millis(1);
+
+        watcher.simulate_write(&a_path, "a\n")?;
+        collector.collect_entries()?;
+        File::create(&c_path)?;
+        collector.collect_entries()?;
+        assert!(collector.spilled_paths.contains_key(&b_path));
+
+        // Removing `a.log` frees a slot, so `b.log` is promoted although `c.log` isn't idle.
+        watcher.simulate_remove(&a_path)?;
+        collector.collect_entries()?;
+        assert_eq!(collector.watched_files.len(), 1);
+
+        b_file.write_all(b"hello\n")?;
+        watcher.simulate_write(&c_path, "c\n")?;
+        collector.next_sample = Instant::now();
+        assert_eq!(collector.collect_entries()?.len(), 2);
+        assert!(collector.watched_paths.contains_key(&b_path));
+        assert!(collector.watched_paths.contains_key(&c_path));
+        assert!(collector.spilled.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn retries_permission_denied_files() -> test::Result {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tempdir = tempfile::tempdir()?;
+        let (file_path, mut file) = create_log_file(&tempdir)?;
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o000))?;
+
+        // Permissions aren't enforced for root, in which case there's nothing to test.
+        if File::open(&file_path).is_ok() {
+            return Ok(());
+        }
+
+        let config = Config {
+            root_path: tempdir.path().to_path_buf(),
+            ownership_marker: false,
+            backfill_compressed: false,
+            backfill_checkpoints: None,
+            position_checkpoints: None,
+            read_from: ReadFrom::End,
+            max_active_files: None,
+            format: Format::Plain,
+            diagnostics: Arc::default(),
+        };
+        let mut collector = Collector::initialize(config, mock::Watcher::new())?;
+        assert!(collector.watched_files.is_empty());
+        assert_eq!(collector.denied.len(), 1);
+
+        let diagnostic = collector.next().expect("expected a diagnostic entry")?;
+        assert_eq!(
+            diagnostic.metadata.get("path").map(String::as_str),
+            file_path.to_str()
+        );
+
+        // Lines written while the file can't be read are still collected once it can.
+        writeln!(file, "written while denied")?;
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o644))?;
+        collector
+            .denied
+            .values_mut()
+            .for_each(|denied| denied.next_attempt = Instant::now());
+
+        let mut diagnostics = Vec::new();
+        assert_eq!(collector.retry_denied(&mut diagnostics)?.len(), 1);
+        assert!(diagnostics.is_empty());
+        assert!(collector.denied.is_empty());
+        assert_eq!(collector.watched_files.len(), 1);
+
+        let mut entries = Vec::new();
+        for watched_file in collector.watched_files.values_mut() {
+            watched_file.read_lines(&mut entries)?;
+        }
+        assert_eq!(
+            entries,
+            vec![log_entry(
+                "written while denied",
+                &[("path", file_path.to_str().unwrap())]
+            )]
+        );
+
+        Ok(())
+    }
+
     fn create_log_file(tempdir: &TempDir) -> io::Result<(PathBuf, File)> {
         let path = tempdir.path().join("test.log");
         let file = File::create(&path)?;
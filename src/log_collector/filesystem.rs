@@ -0,0 +1,71 @@
+// src/log_collector/filesystem.rs
+//! Detection of network filesystems, on which native file watches can't be relied upon.
+//!
+//! `inotify` and `kqueue` only see changes made through the local kernel. On NFS or SMB mounts,
+//! writes made by other clients (or by the server) never generate events, so watches silently
+//! miss them. Paths on these filesystems are watched with a hybrid strategy instead: native
+//! watches are kept, but are verified by periodically polling the paths.
+
+use std::io;
+use std::path::Path;
+
+/// Check whether `path` is on a network filesystem, returning the filesystem's name if so.
+///
+/// # Errors
+///
+/// Propagates any errors from `statfs`.
+#[cfg(target_os = "linux")]
+pub(super) fn network_filesystem(path: &Path) -> io::Result<Option<&'static str>> {
+    /// The `statfs` magic numbers of network filesystems (see `statfs(2)`).
+    const NETWORK_FILESYSTEMS: &[(u32, &str)] = &[
+        (0x6969, "nfs"),
+        (0x517b, "smb"),
+        (0xff53_4d42, "cifs"),
+        (0xfe53_4d42, "smb2"),
+    ];
+
+    let statfs = nix::sys::statfs::statfs(path)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+
+    // Magic numbers are 32 bits, even where `f_type` is wider or signed.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let magic = statfs.filesystem_type().0 as u32;
+    Ok(NETWORK_FILESYSTEMS
+        .iter()
+        .find(|(network_magic, _)| *network_magic == magic)
+        .map(|(_, name)| *name))
+}
+
+/// Check whether `path` is on a network filesystem, returning the filesystem's name if so.
+///
+/// # Errors
+///
+/// Propagates any errors from `statfs`.
+#[cfg(target_os = "macos")]
+pub(super) fn network_filesystem(path: &Path) -> io::Result<Option<&'static str>> {
+    /// The `f_fstypename`s of network filesystems.
+    const NETWORK_FILESYSTEMS: &[&str] = &["nfs", "smbfs", "afpfs", "webdav"];
+
+    let statfs = nix::sys::statfs::statfs(path)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+    let name = statfs.filesystem_type_name();
+    Ok(NETWORK_FILESYSTEMS
+        .iter()
+        .find(|network_name| **network_name == name)
+        .copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test;
+
+    use super::network_filesystem;
+
+    #[test]
+    fn temporary_directories_are_local() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        assert_eq!(network_filesystem(tempdir.path())?, None);
+        assert!(network_filesystem(&tempdir.path().join("missing")).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+// src/log_collector/format.rs
+//! Parsing of the formats container runtimes write logs in.
+//!
+//! Container runtimes don't write the lines of a container's output to its log file as-is, but
+//! wrap each line in an envelope recording when it was written, and to which stream. Collectors
+//! configured with a [`Format`] unwrap each line they read: the entry's line is the line the
+//! container wrote, the stream (`stdout` or `stderr`) is added to its metadata as `stream`, and
+//! the time is used as its [timestamp](crate::LogEntry::timestamp).
+//!
+//! Lines that aren't in the expected format are collected unchanged, so mixing formats (e.g. during
+//! a migration between runtimes) loses no lines.
+
+use std::collections::HashMap;
+
+use crate::record::{self, Timestamp};
+use crate::LogEntry;
+
+/// The metadata key of the stream a container wrote a line to.
+pub const STREAM_METADATA_KEY: &str = "stream";
+
+/// The maximum size of a line reassembled from partial lines, in bytes.
+///
+/// A line that grows beyond this is emitted in pieces, to bound memory use when a container never
+/// writes a newline.
+const MAX_LINE_SIZE: usize = 1024 * 1024;
+
+/// The format of the lines in log files.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Lines are collected as-is.
+    Plain,
+
+    /// Docker's `json-file` format, with a JSON object per line like
+    /// `{"log":"message\n","stream":"stdout","time":"2021-01-01T00:00:00.000000000Z"}`.
+    ///
+    /// Docker splits lines longer than 16KiB across several objects, only the last of which ends
+    /// with a newline, and these are reassembled into one line.
+    Docker,
+
+    /// The CRI logging format used by containerd and CRI-O, with lines like
+    /// `2021-01-01T00:00:00.000000000Z stdout F message`.
+    ///
+    /// Lines split into several parts are tagged `P` (partial) on every part but the last, which
+    /// is tagged `F` (full), and these are reassembled into one line.
+    Cri,
+
+    /// Either [`Docker`](Self::Docker) or [`Cri`](Self::Cri), detected line by line.
+    Auto,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::Plain
+    }
+}
+
+/// A line written by a container, and the stream and time it was written to.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Parsed {
+    pub(super) line: String,
+    pub(super) stream: Option<String>,
+    pub(super) timestamp: Option<Timestamp>,
+}
+
+impl Parsed {
+    /// Convert the parsed line to a [`LogEntry`] with the given `metadata`, and its stream.
+    pub(super) fn into_entry(self, mut metadata: HashMap<String, String>) -> LogEntry {
+        if let Some(stream) = self.stream {
+            metadata.insert(STREAM_METADATA_KEY.to_string(), stream);
+        }
+        LogEntry {
+            line: self.line,
+            metadata,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// Parses the lines of one log file, reassembling lines that were split across several lines of
+/// the file.
+#[derive(Debug)]
+pub(super) struct Parser {
+    format: Format,
+
+    /// The start of partial lines, by stream.
+    partial: HashMap<String, Parsed>,
+}
+
+impl Parser {
+    pub(super) fn new(format: Format) -> Self {
+        Self {
+            format,
+            partial: HashMap::new(),
+        }
+    }
+
+    /// Forget any partial lines, e.g. because the file was truncated.
+    pub(super) fn reset(&mut self) {
+        self.partial.clear();
+    }
+
+    /// Parse a `line` read from the file (without its trailing newline), or `None` if it's the
+    /// start of a line that continues on a later line of the file.
+    pub(super) fn parse(&mut self, line: &str) -> Option<Parsed> {
+        let (parsed, complete) = match self.format {
+            Format::Plain => None,
+            Format::Docker => parse_docker(line),
+            Format::Cri => parse_cri(line),
+            Format::Auto => parse_docker(line).or_else(|| parse_cri(line)),
+        }
+        .unwrap_or_else(|| {
+            let parsed = Parsed {
+                line: line.to_string(),
+                stream: None,
+                timestamp: None,
+            };
+            (parsed, true)
+        });
+        let stream = match parsed.stream.clone() {
+            Some(stream) => stream,
+            None => return Some(parsed),
+        };
+
+        let parsed = match self.partial.remove(&stream) {
+            Some(mut partial) => {
+                partial.line.push_str(&parsed.line);
+                partial
+            }
+            None => parsed,
+        };
+        if complete || parsed.line.len() >= MAX_LINE_SIZE {
+            Some(parsed)
+        } else {
+            self.partial.insert(stream, parsed);
+            None
+        }
+    }
+
+    /// Parse the line of `entry`, keeping its metadata. See [`parse`](Self::parse).
+    pub(super) fn parse_entry(&mut self, entry: LogEntry) -> Option<LogEntry> {
+        if self.format == Format::Plain {
+            return Some(entry);
+        }
+        let parsed = self.parse(&entry.line)?;
+        Some(parsed.into_entry(entry.metadata))
+    }
+}
+
+/// Parse a line in Docker's `json-file` format, and whether it's complete.
+fn parse_docker(line: &str) -> Option<(Parsed, bool)> {
+    #[derive(serde::Deserialize)]
+    struct DockerLine {
+        log: String,
+        #[serde(default)]
+        stream: Option<String>,
+        #[serde(default)]
+        time: Option<String>,
+    }
+
+    let DockerLine { log, stream, time } = serde_json::from_str(line).ok()?;
+    let (log, complete) = match log.strip_suffix('\n') {
+        Some(log) => (log.to_string(), true),
+        None => (log, false),
+    };
+    let parsed = Parsed {
+        line: log,
+        stream: Some(stream.unwrap_or_default()),
+        timestamp: time.as_deref().and_then(record::parse_rfc3339),
+    };
+    Some((parsed, complete))
+}
+
+/// Parse a line in the CRI logging format, and whether it's complete.
+fn parse_cri(line: &str) -> Option<(Parsed, bool)> {
+    let mut parts = line.splitn(4, ' ');
+    let timestamp = record::parse_rfc3339(parts.next()?)?;
+    let stream = parts.next().filter(|stream| !stream.is_empty())?;
+    // The tag may have further `:`-separated flags after `P` or `F`.
+    let complete = match parts.next()?.split(':').next() {
+        Some("F") => true,
+        Some("P") => false,
+        _ => return None,
+    };
+    let parsed = Parsed {
+        line: parts.next().unwrap_or_default().to_string(),
+        stream: Some(stream.to_string()),
+        timestamp: Some(timestamp),
+    };
+    Some((parsed, complete))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, Parsed, Parser, MAX_LINE_SIZE};
+
+    fn parsed(line: &str, stream: Option<&str>, timestamp: Option<u64>) -> Option<Parsed> {
+        Some(Parsed {
+            line: line.to_string(),
+            stream: stream.map(str::to_string),
+            timestamp,
+        })
+    }
+
+    #[test]
+    fn parse_cri() {
+        let mut parser = Parser::new(Format::Cri);
+        assert_eq!(
+            parser.parse("2021-01-01T00:00:00.5Z stdout F hello  world "),
+            parsed("hello  world ", Some("stdout"), Some(1_609_459_200_500))
+        );
+        assert_eq!(
+            parser.parse("2021-01-01T00:00:01Z stderr F"),
+            parsed("", Some("stderr"), Some(1_609_459_201_000))
+        );
+        for line in &[
+            "not cri",
+            "yesterday stdout F message",
+            "2021-01-01T00:00:00Z stdout X message",
+            "2021-01-01T00:00:00Z  F message",
+            r#"{"log":"docker\n"}"#,
+        ] {
+            assert_eq!(parser.parse(line), parsed(line, None, None), "{}", line);
+        }
+
+        assert_eq!(parser.parse("2021-01-01T00:00:01Z stdout P par"), None);
+        assert_eq!(
+            parser.parse("2021-01-01T00:00:02Z stderr F other"),
+            parsed("other", Some("stderr"), Some(1_609_459_202_000))
+        );
+        assert_eq!(parser.parse("2021-01-01T00:00:03Z stdout P:x ti"), None);
+        assert_eq!(
+            parser.parse("2021-01-01T00:00:04Z stdout F al"),
+            parsed("partial", Some("stdout"), Some(1_609_459_201_000))
+        );
+
+        let mut parser = Parser::new(Format::Auto);
+        assert_eq!(
+            parser.parse("2021-01-01T00:00:00Z stdout F cri"),
+            parsed("cri", Some("stdout"), Some(1_609_459_200_000))
+        );
+        assert_eq!(
+            parser.parse(r#"{"log":"docker\n","stream":"stderr"}"#),
+            parsed("docker", Some("stderr"), None)
+        );
+        assert_eq!(parser.parse("plain"), parsed("plain", None, None));
+    }
+
+    #[test]
+    fn parse_docker() {
+        let mut parser = Parser::new(Format::Docker);
+        assert_eq!(
+            parser.parse(
+                r#"{"log":"hello \"world\"\n","stream":"stdout","time":"2021-01-01T00:00:00.5Z"}"#
+            ),
+            parsed(r#"hello "world""#, Some("stdout"), Some(1_609_459_200_500))
+        );
+        assert_eq!(parser.parse("not json"), parsed("not json", None, None));
+        assert_eq!(
+            parser.parse(r#"{"stream":"stdout"}"#),
+            parsed(r#"{"stream":"stdout"}"#, None, None)
+        );
+
+        let time = r#""time":"2021-01-01T00:00:01Z""#;
+        assert_eq!(
+            parser.parse(&format!(r#"{{"log":"par","stream":"stderr",{}}}"#, time)),
+            None
+        );
+        assert_eq!(
+            parser.parse(r#"{"log":"other\n","stream":"stdout","time":"invalid"}"#),
+            parsed("other", Some("stdout"), None)
+        );
+        assert_eq!(
+            parser.parse(r#"{"log":"tial\n","stream":"stderr","time":"2021-01-01T00:00:02Z"}"#),
+            parsed("partial", Some("stderr"), Some(1_609_459_201_000))
+        );
+
+        let chunk = "x".repeat(MAX_LINE_SIZE / 2);
+        let partial = format!(r#"{{"log":"{}","stream":"stdout"}}"#, chunk);
+        assert_eq!(parser.parse(&partial), None);
+        assert_eq!(
+            parser.parse(&partial),
+            parsed(&chunk.repeat(2), Some("stdout"), None)
+        );
+
+        parser.parse(&partial);
+        parser.reset();
+        assert_eq!(
+            parser.parse(r#"{"log":"new\n","stream":"stdout"}"#),
+            parsed("new", Some("stdout"), None)
+        );
+
+        let mut parser = Parser::new(Format::Plain);
+        assert_eq!(
+            parser.parse("2021-01-01T00:00:00Z stdout F raw"),
+            parsed("2021-01-01T00:00:00Z stdout F raw", None, None)
+        );
+        assert_eq!(
+            parser.parse(r#"{"log":"raw\n"}"#),
+            parsed(r#"{"log":"raw\n"}"#, None, None)
+        );
+    }
+}
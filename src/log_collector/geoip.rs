@@ -0,0 +1,278 @@
+// src/log_collector/geoip.rs
+//! Enrichment of log entries with the location and network of IP addresses.
+//!
+//! [`GeoIp`] extracts an IP address from each of the configured fields of an entry, looks it up in
+//! local [MaxMind DB] files (e.g. GeoLite2 Country and ASN), and adds metadata for what it finds.
+//! For a field `client_ip`, the added keys are:
+//!
+//! - `client_ip_country`: the ISO 3166-1 country code.
+//! - `client_ip_asn`: the autonomous system number.
+//! - `client_ip_as_org`: the organisation of the autonomous system.
+//!
+//! Fields are metadata keys, except for `line`, which uses the first IP address in the log line.
+//!
+//! Database files are checked for changes every [`Config::reload_interval`], and reloaded if they
+//! have been modified, so they can be updated (e.g. by `geoipupdate`) without restarting.
+//!
+//! [MaxMind DB]: https://maxmind.github.io/MaxMind-DB/
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{info, warn};
+use maxminddb::{MaxMindDBError, Reader};
+
+use crate::LogEntry;
+
+/// The field that refers to the log line, rather than a metadata key.
+pub const LINE_FIELD: &str = "line";
+
+/// Characters that separate IP addresses from surrounding text in log lines, in addition to
+/// whitespace.
+const DELIMITERS: &[char] = &['"', '\'', ',', ';', '(', ')', '<', '>', '='];
+
+/// Configuration for [`GeoIp`].
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The MaxMind DB files to look addresses up in. Each address is looked up in every database,
+    /// and the first value found for each key is used.
+    pub databases: Vec<PathBuf>,
+
+    /// The fields to extract IP addresses from.
+    pub fields: Vec<String>,
+
+    /// How often to check the database files for changes.
+    pub reload_interval: Duration,
+}
+
+/// Enriches log entries with the country and autonomous system of IP addresses.
+#[derive(Debug)]
+pub struct GeoIp {
+    fields: Vec<String>,
+    reload_interval: Duration,
+    databases: Mutex<Vec<Database>>,
+}
+
+struct Database {
+    path: PathBuf,
+    reader: Reader<Vec<u8>>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+/// The parts of a GeoIP2/GeoLite2 Country or ASN record that are used.
+#[derive(serde::Deserialize)]
+struct Record {
+    country: Option<Country>,
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct Country {
+    iso_code: Option<String>,
+}
+
+impl GeoIp {
+    /// Open the databases in `config`.
+    ///
+    /// # Errors
+    ///
+    /// Any errors encountered when opening the databases are returned.
+    pub fn open(config: Config) -> io::Result<Self> {
+        let databases = config
+            .databases
+            .into_iter()
+            .map(Database::open)
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            fields: config.fields,
+            reload_interval: config.reload_interval,
+            databases: Mutex::new(databases),
+        })
+    }
+
+    /// Add metadata for the IP addresses in `entry`'s configured fields.
+    ///
+    /// Fields without an IP address, and addresses that aren't in any database, are skipped.
+    ///
+    /// Returns the number of keys added.
+    pub fn enrich(&self, entry: &mut LogEntry) -> usize {
+        let mut databases = self.databases.lock().expect("geoip lock poisoned");
+        for database in databases.iter_mut() {
+            if database.checked.elapsed() >= self.reload_interval {
+                database.reload();
+            }
+        }
+
+        let mut added = 0;
+        for field in &self.fields {
+            let address = if field == LINE_FIELD {
+                find_address(&entry.line)
+            } else {
+                entry
+                    .metadata
+                    .get(field)
+                    .map(String::as_str)
+                    .and_then(find_address)
+            };
+            let address = match address {
+                Some(address) => address,
+                None => continue,
+            };
+
+            let mut country = None;
+            let mut asn = None;
+            let mut as_org = None;
+            for database in databases.iter() {
+                let record = match database.reader.lookup::<Record>(address) {
+                    Ok(record) => record,
+                    Err(MaxMindDBError::AddressNotFoundError(_)) => continue,
+                    Err(error) => {
+                        warn!(
+                            "Failed to look up {} in {}: {}",
+                            address,
+                            database.path.display(),
+                            error
+                        );
+                        continue;
+                    }
+                };
+                country = country.or_else(|| record.country.and_then(|country| country.iso_code));
+                asn = asn.or(record.autonomous_system_number);
+                as_org = as_org.or(record.autonomous_system_organization);
+            }
+
+            let values = [
+                ("country", country),
+                ("asn", asn.map(|asn| asn.to_string())),
+                ("as_org", as_org),
+            ];
+            for (suffix, value) in values.iter() {
+                if let Some(value) = value {
+                    entry
+                        .metadata
+                        .insert(format!("{}_{}", field, suffix), value.clone());
+                    added += 1;
+                }
+            }
+        }
+        added
+    }
+}
+
+impl fmt::Debug for Database {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Database")
+            .field("path", &self.path)
+            .field("modified", &self.modified)
+            .finish()
+    }
+}
+
+impl Database {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let modified = fs::metadata(&path)?.modified().ok();
+        let reader = Reader::open_readfile(&path).map_err(|error| to_io_error(&path, &error))?;
+        Ok(Self {
+            path,
+            reader,
+            modified,
+            checked: Instant::now(),
+        })
+    }
+
+    /// Reload the database if its file has been modified.
+    ///
+    /// If the new file can't be read, the old database continues to be used.
+    fn reload(&mut self) {
+        self.checked = Instant::now();
+
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified());
+        match modified {
+            Ok(modified) if Some(modified) != self.modified => {}
+            Ok(_) => return,
+            Err(error) => {
+                warn!("Failed to check {}: {}", self.path.display(), error);
+                return;
+            }
+        }
+
+        match Self::open(self.path.clone()) {
+            Ok(database) => {
+                info!("Reloaded GeoIP database {}", self.path.display());
+                *self = database;
+            }
+            Err(error) => warn!("Failed to reload GeoIP database: {}", error),
+        }
+    }
+}
+
+/// Find the first IP address (optionally with a port) in `text`.
+fn find_address(text: &str) -> Option<IpAddr> {
+    text.split(|c: char| c.is_whitespace() || DELIMITERS.contains(&c))
+        .find_map(|token| {
+            token
+                .parse::<IpAddr>()
+                .ok()
+                .or_else(|| token.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+                .or_else(|| {
+                    let token = token.trim_start_matches('[').trim_end_matches(']');
+                    token.parse::<IpAddr>().ok()
+                })
+        })
+}
+
+fn to_io_error(path: &Path, error: &MaxMindDBError) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid GeoIP database {}: {}", path.display(), error),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use crate::test::log_entry;
+
+    use super::{find_address, Config, GeoIp};
+
+    #[test]
+    fn find_addresses() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().ok();
+
+        assert_eq!(find_address("203.0.113.7"), ip("203.0.113.7"));
+        assert_eq!(
+            find_address(r#"203.0.113.7 - - "GET / HTTP/1.1" 200"#),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            find_address("connection from 198.51.100.2:51234 closed"),
+            ip("198.51.100.2")
+        );
+        assert_eq!(find_address("client=2001:db8::1"), ip("2001:db8::1"));
+        assert_eq!(find_address("peer [2001:db8::2]:443"), ip("2001:db8::2"));
+        assert_eq!(find_address("version 1.2.3 released"), None);
+    }
+
+    #[test]
+    fn no_databases() {
+        let geoip = GeoIp::open(Config {
+            databases: Vec::new(),
+            fields: vec!["line".to_string(), "client_ip".to_string()],
+            reload_interval: Duration::from_secs(60),
+        })
+        .unwrap();
+
+        let mut entry = log_entry("GET / from 203.0.113.7", &[("client_ip", "198.51.100.2")]);
+        assert_eq!(geoip.enrich(&mut entry), 0);
+        assert_eq!(entry.metadata.len(), 1);
+    }
+}
@@ -0,0 +1,168 @@
+// src/log_collector/json.rs
+//! Parsing of structured JSON log lines into metadata.
+//!
+//! Applications that log a JSON object per line, like
+//! `{"level": "error", "logger": "api.orders", "message": "payment declined"}`, can have selected
+//! fields of each object lifted into an entry's metadata, so that e.g. errors can be found with
+//! `/logs/level/error`. [`JsonParser`] adds each of [`Config::fields`] present in the object to the
+//! metadata under the same key, and replaces the line with the object's message (the first of
+//! [`Config::message_keys`] present), so the stored line is what the application logged rather than
+//! its envelope. Objects without a message are kept as the line.
+//!
+//! Fields may name nested values with `.`-separated paths (e.g. `log.level`), and are looked up as
+//! a literal key first. String, number, and boolean values are added as text, and other values
+//! (objects, arrays, and `null`) are ignored.
+//!
+//! Each distinct set of metadata is stored as a separate stream, so fields with many values (like
+//! `trace_id`) can create very many streams.
+//!
+//! Lines that aren't JSON objects are left unchanged.
+
+use crate::LogEntry;
+
+/// Configuration for a [`JsonParser`].
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The fields to add to entries' metadata.
+    pub fields: Vec<String>,
+
+    /// The fields that may hold the message, in the order they're tried.
+    pub message_keys: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            fields: vec!["level".to_string(), "logger".to_string()],
+            message_keys: vec!["message".to_string(), "msg".to_string()],
+        }
+    }
+}
+
+/// Parses JSON log lines into metadata.
+#[derive(Debug)]
+pub struct JsonParser {
+    config: Config,
+}
+
+impl JsonParser {
+    /// Construct a `JsonParser` with the given `config`.
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Parse `entry`'s line, adding the configured fields to its metadata and replacing the line
+    /// with the message, if any.
+    ///
+    /// Returns whether the line was a JSON object.
+    pub fn parse(&self, entry: &mut LogEntry) -> bool {
+        if !entry.line.trim_start().starts_with('{') {
+            return false;
+        }
+        let object = match serde_json::from_str(&entry.line) {
+            Ok(object @ serde_json::Value::Object(_)) => object,
+            _ => return false,
+        };
+
+        for field in &self.config.fields {
+            if let Some(value) = lookup(&object, field).and_then(text) {
+                entry.metadata.insert(field.clone(), value);
+            }
+        }
+        let message = self
+            .config
+            .message_keys
+            .iter()
+            .find_map(|key| lookup(&object, key).and_then(text));
+        if let Some(message) = message {
+            entry.line = message;
+        }
+        true
+    }
+}
+
+/// Look up `field` in `object`, as a literal key or else a `.`-separated path.
+fn lookup<'a>(object: &'a serde_json::Value, field: &str) -> Option<&'a serde_json::Value> {
+    if let Some(value) = object.get(field) {
+        return Some(value);
+    }
+    if !field.contains('.') {
+        return None;
+    }
+    field
+        .split('.')
+        .try_fold(object, |value, key| value.get(key))
+}
+
+/// The text of a scalar JSON `value`.
+fn text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(value) => Some(value.clone()),
+        serde_json::Value::Number(value) => Some(value.to_string()),
+        serde_json::Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::log_entry;
+
+    use super::{Config, JsonParser};
+
+    #[test]
+    fn parse_json() {
+        let parser = JsonParser::new(Config {
+            fields: vec![
+                "level".to_string(),
+                "logger".to_string(),
+                "trace_id".to_string(),
+                "log.origin.file".to_string(),
+                "attempt".to_string(),
+                "context".to_string(),
+            ],
+            ..Config::default()
+        });
+
+        let line = concat!(
+            r#"{"level": "error", "logger": "api.orders", "msg": "payment declined", "#,
+            r#""trace_id": "abc123", "attempt": 2, "context": {"order": 1}, "#,
+            r#""log": {"origin": {"file": "orders.py"}}}"#,
+        );
+        let mut entry = log_entry(line, &[("app", "api")]);
+        assert!(parser.parse(&mut entry));
+        assert_eq!(
+            entry,
+            log_entry(
+                "payment declined",
+                &[
+                    ("app", "api"),
+                    ("level", "error"),
+                    ("logger", "api.orders"),
+                    ("trace_id", "abc123"),
+                    ("log.origin.file", "orders.py"),
+                    ("attempt", "2"),
+                ]
+            )
+        );
+
+        // A literal key takes precedence over a path, and `message` over `msg`.
+        let line = r#"{"log.origin.file": "a.py", "message": "one", "msg": "two"}"#;
+        let mut entry = log_entry(line, &[]);
+        assert!(parser.parse(&mut entry));
+        assert_eq!(entry, log_entry("one", &[("log.origin.file", "a.py")]));
+
+        // Objects without a message are kept as the line.
+        let line = r#"{"level": "info", "event": "started"}"#;
+        let mut entry = log_entry(line, &[]);
+        assert!(parser.parse(&mut entry));
+        assert_eq!(entry, log_entry(line, &[("level", "info")]));
+
+        for line in &["starting server", "[1, 2]", r#"{"level": "info""#] {
+            let mut entry = log_entry(line, &[]);
+            assert!(!parser.parse(&mut entry));
+            assert_eq!(entry, log_entry(line, &[]));
+        }
+    }
+}
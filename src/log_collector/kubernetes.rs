@@ -4,11 +4,15 @@
 use std::collections::{BTreeMap, HashMap};
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use k8s_openapi::api::core::v1::Pod;
 use kube::api::Meta;
 
-use crate::log_collector::directory;
+use crate::log_collector::compressed;
+use crate::log_collector::diagnostics::Diagnostics;
+use crate::log_collector::directory::{self, ReadFrom};
+use crate::log_collector::format::{Format, STREAM_METADATA_KEY};
 use crate::log_collector::watcher::Watcher;
 use crate::LogEntry;
 
@@ -20,6 +24,46 @@ pub struct Config {
     ///
     /// This will default to the default Kubernetes log directory (`/var/log/containers`) if empty.
     pub root_path: Option<PathBuf>,
+
+    /// Whether to write an ownership marker into the root path, to detect other collectors
+    /// collecting the same files.
+    ///
+    /// See [`ownership`](super::ownership) for details.
+    pub ownership_marker: bool,
+
+    /// Whether to read compressed rotated log files found in the root path at startup.
+    ///
+    /// See [`directory::Config::backfill_compressed`] for details.
+    pub backfill_compressed: bool,
+
+    /// Where to store checkpoints of backfilled compressed files, if anywhere.
+    ///
+    /// See [`directory::Config::backfill_checkpoints`] for details.
+    pub backfill_checkpoints: Option<PathBuf>,
+
+    /// Where to store checkpoints of how far each container log file has been read, if anywhere.
+    ///
+    /// See [`directory::Config::position_checkpoints`] for details.
+    pub position_checkpoints: Option<PathBuf>,
+
+    /// Where to start reading container log files that are discovered.
+    ///
+    /// See [`directory::Config::read_from`] for details.
+    pub read_from: ReadFrom,
+
+    /// The maximum number of files to follow at once, or `None` for no limit.
+    ///
+    /// See [`directory::Config::max_active_files`] for details.
+    pub max_active_files: Option<usize>,
+
+    /// The format of the container log files, usually [`Format::Auto`] to handle nodes using
+    /// either Docker or a CRI runtime (like containerd).
+    ///
+    /// The `stream` metadata of parsed lines is kept alongside the pod's metadata.
+    pub format: Format,
+
+    /// Diagnostics for files that can't be watched or read due to permissions.
+    pub diagnostics: Arc<Diagnostics>,
 }
 
 /// Initialize a [`Collector`](super::Collector) that collects logs from containers on a Kubernetes
@@ -54,6 +98,14 @@ pub fn initialize(config: Config) -> io::Result<impl super::Collector> {
                 root_path: config
                     .root_path
                     .unwrap_or_else(|| PathBuf::from(DEFAULT_ROOT_PATH)),
+                ownership_marker: config.ownership_marker,
+                backfill_compressed: config.backfill_compressed,
+                backfill_checkpoints: config.backfill_checkpoints,
+                position_checkpoints: config.position_checkpoints,
+                read_from: config.read_from,
+                max_active_files: config.max_active_files,
+                format: config.format,
+                diagnostics: config.diagnostics,
             },
             watcher,
         )?,
@@ -61,6 +113,59 @@ pub fn initialize(config: Config) -> io::Result<impl super::Collector> {
     })
 }
 
+/// The options of a `kubernetes` collector in a [`Registry`](super::Registry).
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Options {
+    root_path: Option<PathBuf>,
+    #[serde(default)]
+    ownership_marker: bool,
+    #[serde(default)]
+    backfill_compressed: bool,
+    #[serde(default)]
+    backfill_checkpoints: Option<PathBuf>,
+    #[serde(default)]
+    position_checkpoints: Option<PathBuf>,
+    #[serde(default)]
+    read_from: ReadFrom,
+    #[serde(default)]
+    max_active_files: Option<usize>,
+    #[serde(default = "default_format")]
+    format: Format,
+}
+
+fn default_format() -> Format {
+    Format::Auto
+}
+
+/// Initialize a collector from its options (see [`Initializer`](super::Initializer)).
+///
+/// The options are `root_path`, `ownership_marker`, `backfill_compressed`,
+/// `backfill_checkpoints`, `position_checkpoints`, `read_from` (`beginning` or `end`),
+/// `max_active_files`, and `format` (`plain`, `docker`, `cri`, or `auto`, defaulting to `auto`), as
+/// in [`Config`].
+///
+/// # Errors
+///
+/// If the options are invalid, or initialization fails, an [`Error`](super::Error) is returned.
+pub fn from_options(
+    options: &super::Options,
+    context: &super::Context,
+) -> Result<Box<dyn super::Collector + Send>, super::Error> {
+    let options: Options = super::parse_options("kubernetes", options)?;
+    Ok(Box::new(initialize(Config {
+        root_path: options.root_path,
+        ownership_marker: options.ownership_marker,
+        backfill_compressed: options.backfill_compressed,
+        backfill_checkpoints: options.backfill_checkpoints,
+        position_checkpoints: options.position_checkpoints,
+        read_from: options.read_from,
+        max_active_files: options.max_active_files,
+        format: options.format,
+        diagnostics: Arc::clone(&context.diagnostics),
+    })?))
+}
+
 /// A log collector that collects logs from containers on a Kubernetes node.
 ///
 /// Under-the-hood this wraps a [`directory`](super::directory) collector and post-
@@ -78,7 +183,9 @@ impl<W: Watcher> Collector<W> {
         use std::convert::TryInto;
 
         // TODO: `unwrap` is not ideal, since we could feasibly have log files without a file stem.
-        let stem = Path::new(path).file_stem().unwrap();
+        let stem = Path::new(compressed::strip_extension(path))
+            .file_stem()
+            .unwrap();
 
         // `unwrap` is OK since we converted from `str` above.
         let stem = stem.to_str().unwrap();
@@ -150,7 +257,13 @@ impl<W: Watcher> Iterator for Collector<W> {
                 self.metadata_cache.entry(path).or_insert(metadata)
             };
 
+            let stream = entry.metadata.remove(STREAM_METADATA_KEY);
             entry.metadata = metadata.clone();
+            if let Some(stream) = stream {
+                entry
+                    .metadata
+                    .insert(STREAM_METADATA_KEY.to_string(), stream);
+            }
             entry
         }))
     }
@@ -1,16 +1,588 @@
 // log_collector/mod.rs
 
 //! The interface for log collection in `monitoring-rs`.
+//!
+//! Collectors are configured with JSON objects like `{"type": "directory", "root_path": "..."}`.
+//! A [`Registry`] maps each `type` to an [`Initializer`] for the collector, which is given the
+//! remaining fields. Embedders can register their own collectors alongside the built-in
+//! `directory`, `kubernetes`, `stdin`, and `syslog` collectors.
+//!
+//! A [`Collector`] blocks the thread it runs on while waiting for entries. An [`AsyncCollector`]
+//! instead runs on the async executor, and [`Registry::initialize_async`] initializes one for any
+//! registered collector, running those without an asynchronous implementation on a dedicated
+//! thread (see [`Unblocked`]).
+//!
+//! Any collector's configuration may also include `labels`, an object of static metadata to add
+//! to each of its entries (e.g. `{"type": "syslog", "udp": "0.0.0.0:514", "labels": {"source":
+//! "syslog"}}`). Metadata from the collector itself takes precedence. Several collectors can be run
+//! at once by combining them with [`Merged`].
 
+pub mod access_log;
+mod compressed;
+pub mod diagnostics;
 pub mod directory;
+mod filesystem;
+pub mod format;
+pub mod geoip;
+pub mod json;
 pub mod kubernetes;
+pub mod multiline;
+pub mod once;
+pub mod ordering;
+pub mod ownership;
+pub mod paths;
+pub mod secrets;
+pub mod stdin;
+pub mod syslog;
+pub mod templates;
 mod watcher;
 
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
 use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::thread;
 
+use async_std::stream::Stream;
+use async_std::task;
+
+use crate::record::Record;
 use crate::LogEntry;
 
+use self::diagnostics::Diagnostics;
+
 /// A log collector can be any type that can be used as an `Iterator` of [`LogEntry`]s.
 ///
-/// This is currently just a marker trait, but this could change as new log collectors are added.
-pub trait Collector: Iterator<Item = Result<LogEntry, io::Error>> {}
+/// Collectors don't timestamp entries themselves, but [`records`](Self::records) adapts a
+/// collector to produce [`Record`]s timestamped when each entry is collected.
+pub trait Collector: Iterator<Item = Result<LogEntry, io::Error>> {
+    /// Adapt the collector to produce [`Record`]s.
+    fn records(self) -> Records<Self>
+    where
+        Self: Sized,
+    {
+        Records(self)
+    }
+}
+
+/// An iterator of the [`Record`]s from a [`Collector`]. See [`Collector::records`].
+#[derive(Debug)]
+pub struct Records<C>(C);
+
+impl<C: Collector> Iterator for Records<C> {
+    type Item = Result<Record, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|entry| entry.map(Record::from))
+    }
+}
+
+/// The number of entries an [`Unblocked`] collector may collect ahead of them being consumed.
+const UNBLOCKED_BUFFER_SIZE: usize = 1000;
+
+/// An asynchronous log collector can be any type that can be used as a `Stream` of [`LogEntry`]s.
+pub trait AsyncCollector: Stream<Item = Result<LogEntry, io::Error>> {}
+
+/// An [`AsyncCollector`] that runs a blocking [`Collector`] on a dedicated thread.
+#[derive(Debug)]
+pub struct Unblocked {
+    receiver: async_channel::Receiver<Result<LogEntry, io::Error>>,
+}
+
+impl Unblocked {
+    /// Run `collector` on a dedicated thread.
+    ///
+    /// The thread stops once the `Unblocked` collector is dropped and `collector` produces another
+    /// entry.
+    pub fn new<C>(collector: C) -> Self
+    where
+        C: Iterator<Item = Result<LogEntry, io::Error>> + Send + 'static,
+    {
+        let (sender, receiver) = async_channel::bounded(UNBLOCKED_BUFFER_SIZE);
+        thread::spawn(move || {
+            for entry in collector {
+                if task::block_on(sender.send(entry)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { receiver }
+    }
+}
+
+impl Stream for Unblocked {
+    type Item = Result<LogEntry, io::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+impl AsyncCollector for Unblocked {}
+
+/// A collector adding static labels to the metadata of another collector's entries.
+///
+/// Labels don't replace metadata that the collector added itself.
+#[derive(Debug)]
+pub struct Labelled<C> {
+    collector: C,
+    labels: BTreeMap<String, String>,
+}
+
+impl<C> Labelled<C> {
+    /// Add `labels` to the entries of `collector`.
+    pub fn new(collector: C, labels: BTreeMap<String, String>) -> Self {
+        Self { collector, labels }
+    }
+
+    fn label(&self, mut entry: LogEntry) -> LogEntry {
+        for (key, value) in &self.labels {
+            entry
+                .metadata
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        entry
+    }
+}
+
+impl<C: Iterator<Item = Result<LogEntry, io::Error>>> Iterator for Labelled<C> {
+    type Item = Result<LogEntry, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.collector.next()?;
+        Some(entry.map(|entry| self.label(entry)))
+    }
+}
+
+impl<C: Iterator<Item = Result<LogEntry, io::Error>>> Collector for Labelled<C> {}
+
+impl<C: Stream<Item = Result<LogEntry, io::Error>> + Unpin> Stream for Labelled<C> {
+    type Item = Result<LogEntry, io::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let entry = match Pin::new(&mut self.collector).poll_next(cx) {
+            Poll::Ready(Some(entry)) => entry,
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => return Poll::Pending,
+        };
+        Poll::Ready(Some(entry.map(|entry| self.label(entry))))
+    }
+}
+
+impl<C: Stream<Item = Result<LogEntry, io::Error>> + Unpin> AsyncCollector for Labelled<C> {}
+
+/// An [`AsyncCollector`] combining the entries of several collectors, in the order they arrive.
+///
+/// Collectors are polled in turn, so a busy collector can't hold back the others. The merged
+/// collector ends once all of its collectors have ended.
+pub struct Merged {
+    collectors: Vec<Box<dyn AsyncCollector + Send + Unpin>>,
+    next: usize,
+}
+
+impl fmt::Debug for Merged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Merged")
+            .field("collectors", &self.collectors.len())
+            .finish()
+    }
+}
+
+impl Merged {
+    /// Combine the entries of `collectors`.
+    #[must_use]
+    pub fn new(collectors: Vec<Box<dyn AsyncCollector + Send + Unpin>>) -> Self {
+        Self {
+            collectors,
+            next: 0,
+        }
+    }
+}
+
+impl Stream for Merged {
+    type Item = Result<LogEntry, io::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let mut polled = 0;
+        while polled < self.collectors.len() {
+            let index = self.next % self.collectors.len();
+            match Pin::new(&mut self.collectors[index]).poll_next(cx) {
+                Poll::Ready(Some(entry)) => {
+                    self.next = index + 1;
+                    return Poll::Ready(Some(entry));
+                }
+                Poll::Ready(None) => {
+                    self.collectors.remove(index);
+                    self.next = index;
+                }
+                Poll::Pending => {
+                    self.next = index + 1;
+                    polled += 1;
+                }
+            }
+        }
+        if self.collectors.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl AsyncCollector for Merged {}
+
+/// The fields of a collector's configuration, excluding `type`.
+pub type Options = serde_json::Map<String, serde_json::Value>;
+
+/// What a collector may need from the rest of the process when it's initialized.
+#[derive(Clone, Debug)]
+pub struct Context {
+    /// Diagnostics for files that can't be watched or read due to permissions.
+    pub diagnostics: Arc<Diagnostics>,
+}
+
+/// Initializes a collector from its options.
+pub type Initializer = fn(&Options, &Context) -> Result<Box<dyn Collector + Send>, Error>;
+
+/// Initializes an asynchronous collector from its options.
+pub type AsyncInitializer =
+    fn(&Options, &Context) -> Result<Box<dyn AsyncCollector + Send + Unpin>, Error>;
+
+/// An error in a collector's configuration, or when initializing it.
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration isn't a JSON object with a string `type`.
+    Invalid(String),
+
+    /// No collector is registered for the `type`.
+    UnknownType(String),
+
+    /// The collector's options are invalid.
+    Options {
+        /// The collector's type.
+        kind: String,
+
+        /// Why the options are invalid.
+        message: String,
+    },
+
+    /// Initializing the collector failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Invalid(message) => write!(f, "invalid collector configuration: {}", message),
+            Self::UnknownType(kind) => write!(f, "unknown collector type `{}`", kind),
+            Self::Options { kind, message } => {
+                write!(f, "invalid `{}` collector: {}", kind, message)
+            }
+            Self::Io(error) => write!(f, "failed to initialize collector: {}", error),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// A mapping from collector `type`s to their [`Initializer`]s.
+#[derive(Clone)]
+pub struct Registry {
+    initializers: BTreeMap<String, Initializer>,
+    async_initializers: BTreeMap<String, AsyncInitializer>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.kinds()).finish()
+    }
+}
+
+impl Default for Registry {
+    /// A registry with the built-in collectors: `directory`, `kubernetes`, `stdin`, and `syslog`.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register("directory", directory::from_options);
+        registry.register_async("directory", directory::from_options_async);
+        registry.register("kubernetes", kubernetes::from_options);
+        registry.register("stdin", stdin::from_options);
+        registry.register("syslog", syslog::from_options);
+        registry
+    }
+}
+
+impl Registry {
+    /// A registry with no collectors.
+    #[must_use]
+    pub fn empty() -> Self {
+        Self {
+            initializers: BTreeMap::new(),
+            async_initializers: BTreeMap::new(),
+        }
+    }
+
+    /// Register a collector `type`, replacing any existing registration.
+    pub fn register(&mut self, kind: &str, initializer: Initializer) {
+        self.initializers.insert(kind.to_string(), initializer);
+    }
+
+    /// Register an asynchronous implementation of a collector `type`, which is used by
+    /// [`initialize_async`](Self::initialize_async) instead of running the collector registered
+    /// with [`register`](Self::register) on a dedicated thread.
+    pub fn register_async(&mut self, kind: &str, initializer: AsyncInitializer) {
+        self.async_initializers
+            .insert(kind.to_string(), initializer);
+    }
+
+    /// The registered collector types.
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.initializers.keys().map(String::as_str)
+    }
+
+    /// Initialize a collector from a JSON `config` object.
+    ///
+    /// # Errors
+    ///
+    /// If `config` is invalid, its `type` isn't registered, or the collector fails to initialize,
+    /// an [`Error`] is returned.
+    pub fn initialize(
+        &self,
+        config: &serde_json::Value,
+        context: &Context,
+    ) -> Result<Box<dyn Collector + Send>, Error> {
+        let (kind, options, labels) = split_config(config)?;
+        let initializer = self
+            .initializers
+            .get(&kind)
+            .ok_or_else(|| Error::UnknownType(kind.clone()))?;
+        let collector = initializer(&options, context)?;
+        if labels.is_empty() {
+            return Ok(collector);
+        }
+        Ok(Box::new(Labelled::new(collector, labels)))
+    }
+
+    /// Initialize an asynchronous collector from a JSON `config` object.
+    ///
+    /// Collector types without an asynchronous implementation are run on a dedicated thread (see
+    /// [`Unblocked`]).
+    ///
+    /// # Errors
+    ///
+    /// If `config` is invalid, its `type` isn't registered, or the collector fails to initialize,
+    /// an [`Error`] is returned.
+    pub fn initialize_async(
+        &self,
+        config: &serde_json::Value,
+        context: &Context,
+    ) -> Result<Box<dyn AsyncCollector + Send + Unpin>, Error> {
+        let (kind, options, labels) = split_config(config)?;
+        let collector = match self.async_initializers.get(&kind) {
+            Some(initializer) => initializer(&options, context)?,
+            None => {
+                let initializer = self
+                    .initializers
+                    .get(&kind)
+                    .ok_or_else(|| Error::UnknownType(kind.clone()))?;
+                Box::new(Unblocked::new(initializer(&options, context)?))
+            }
+        };
+        if labels.is_empty() {
+            return Ok(collector);
+        }
+        Ok(Box::new(Labelled::new(collector, labels)))
+    }
+}
+
+/// Split a collector's `config` into its `type`, options, and `labels`.
+fn split_config(
+    config: &serde_json::Value,
+) -> Result<(String, Options, BTreeMap<String, String>), Error> {
+    let mut options = match config {
+        serde_json::Value::Object(options) => options.clone(),
+        _ => return Err(Error::Invalid("expected a JSON object".to_string())),
+    };
+    let kind = match options.remove("type") {
+        Some(serde_json::Value::String(kind)) => kind,
+        _ => return Err(Error::Invalid("`type` must be a string".to_string())),
+    };
+    let labels = match options.remove("labels") {
+        Some(labels) => serde_json::from_value(labels).map_err(|error| Error::Options {
+            kind: kind.clone(),
+            message: format!("invalid `labels`: {}", error),
+        })?,
+        None => BTreeMap::new(),
+    };
+    Ok((kind, options, labels))
+}
+
+/// Deserialize a collector's `options`, reporting errors as [`Error::Options`].
+fn parse_options<T: serde::de::DeserializeOwned>(
+    kind: &str,
+    options: &Options,
+) -> Result<T, Error> {
+    serde_json::from_value(serde_json::Value::Object(options.clone())).map_err(|error| {
+        Error::Options {
+            kind: kind.to_string(),
+            message: error.to_string(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io;
+    use std::sync::Arc;
+
+    use async_std::stream::StreamExt;
+    use async_std::task;
+
+    use crate::test::log_entry;
+
+    use super::diagnostics::Diagnostics;
+    use super::{AsyncCollector, Context, Error, Labelled, Merged, Registry, Unblocked};
+
+    #[test]
+    fn initialize_collectors() -> crate::test::Result {
+        let registry = Registry::default();
+        assert_eq!(
+            registry.kinds().collect::<Vec<_>>(),
+            vec!["directory", "kubernetes", "stdin", "syslog"]
+        );
+
+        let tempdir = tempfile::tempdir()?;
+        let context = Context {
+            diagnostics: Arc::new(Diagnostics::new()),
+        };
+        let config = serde_json::json!({ "type": "directory", "root_path": tempdir.path() });
+        assert!(registry.initialize(&config, &context).is_ok());
+        assert!(registry.initialize_async(&config, &context).is_ok());
+
+        let initialize = |config| registry.initialize(&config, &context).err().unwrap();
+        assert!(matches!(
+            initialize(serde_json::json!({ "type": "journald" })),
+            Error::UnknownType(kind) if kind == "journald"
+        ));
+        assert!(matches!(
+            initialize(serde_json::json!([])),
+            Error::Invalid(_)
+        ));
+        assert!(matches!(
+            initialize(serde_json::json!({ "type": "directory" })),
+            Error::Options { .. }
+        ));
+        assert!(matches!(
+            initialize(serde_json::json!({ "type": "directory", "root": "/" })),
+            Error::Options { .. }
+        ));
+        assert!(matches!(
+            initialize(serde_json::json!({ "type": "syslog" })),
+            Error::Options { .. }
+        ));
+        assert!(matches!(
+            initialize(serde_json::json!({ "type": "stdin", "path": "-" })),
+            Error::Options { .. }
+        ));
+
+        // Types without an asynchronous implementation are initialized in the same way.
+        assert!(matches!(
+            registry
+                .initialize_async(&serde_json::json!({ "type": "syslog" }), &context)
+                .err()
+                .unwrap(),
+            Error::Options { .. }
+        ));
+
+        let config = serde_json::json!({
+            "type": "directory",
+            "root_path": tempdir.path(),
+            "labels": { "source": "files" },
+        });
+        assert!(registry.initialize(&config, &context).is_ok());
+        assert!(matches!(
+            initialize(serde_json::json!({
+                "type": "directory",
+                "root_path": tempdir.path(),
+                "labels": ["files"],
+            })),
+            Error::Options { .. }
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn label_entries() {
+        let mut labels = BTreeMap::new();
+        labels.insert("source".to_string(), "files".to_string());
+        labels.insert("path".to_string(), "ignored".to_string());
+
+        let entries = vec![Ok(log_entry("hello", &[("path", "/var/log/app.log")]))];
+        let labelled: Vec<_> = Labelled::new(entries.into_iter(), labels)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            labelled,
+            vec![log_entry(
+                "hello",
+                &[("path", "/var/log/app.log"), ("source", "files")]
+            )]
+        );
+    }
+
+    #[test]
+    fn merge_collectors() {
+        let collector = |lines: &[&'static str]| {
+            let entries: Vec<_> = lines.iter().map(|line| Ok(log_entry(line, &[]))).collect();
+            Box::new(Unblocked::new(entries.into_iter())) as Box<dyn AsyncCollector + Send + Unpin>
+        };
+        let mut merged = Merged::new(vec![
+            collector(&["a1", "a2", "a3"]),
+            collector(&[]),
+            collector(&["b1"]),
+        ]);
+
+        let mut lines = Vec::new();
+        while let Some(entry) = task::block_on(merged.next()) {
+            lines.push(entry.unwrap().line);
+        }
+        lines.sort();
+        assert_eq!(lines, vec!["a1", "a2", "a3", "b1"]);
+
+        // Errors are passed through.
+        let error =
+            Unblocked::new(vec![Err(io::Error::new(io::ErrorKind::Other, "oops"))].into_iter());
+        let mut merged = Merged::new(vec![
+            Box::new(error) as Box<dyn AsyncCollector + Send + Unpin>
+        ]);
+        assert!(task::block_on(merged.next()).unwrap().is_err());
+        assert!(task::block_on(merged.next()).is_none());
+    }
+}
@@ -0,0 +1,351 @@
+// src/log_collector/multiline.rs
+//! Merging of multi-line log messages, like stack traces, into single entries.
+//!
+//! Programs often write a single message across several lines, e.g. a Java exception followed by
+//! a line per stack frame. Collectors produce an entry per line, so [`Multiline`] sits between a
+//! collector and the database, merging the lines of each message into one entry whose line is the
+//! message's lines joined by `\n`.
+//!
+//! A line matching [`Config::start`] starts a new message. Lines following it are appended to the
+//! message if they match [`Config::continuation`] (or, if that isn't set, if they don't match
+//! `start`). Messages are merged per stream (i.e. entries with the same metadata), and are emitted
+//! when a line that doesn't continue them arrives, when they reach [`Config::max_lines`], or when
+//! no line has been appended for [`Config::timeout`], so the last message of a quiet stream isn't
+//! held back indefinitely.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::LogEntry;
+
+/// Configuration for [`Multiline`].
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Lines matching this start a new message.
+    pub start: Regex,
+
+    /// Lines matching this continue the previous message. If not set, lines that don't match
+    /// [`start`](Self::start) continue the previous message.
+    pub continuation: Option<Regex>,
+
+    /// How long to wait for further lines of a message before emitting it.
+    pub timeout: Duration,
+
+    /// The maximum number of lines in a message. Longer messages are emitted in pieces.
+    pub max_lines: usize,
+}
+
+/// A message being merged.
+#[derive(Debug)]
+struct Pending {
+    entry: LogEntry,
+    lines: usize,
+    deadline: Instant,
+}
+
+/// Merges the lines of multi-line messages, without regard for how entries arrive.
+#[derive(Debug)]
+struct Merger {
+    config: Config,
+
+    /// Messages being merged, by stream.
+    pending: BTreeMap<Vec<(String, String)>, Pending>,
+}
+
+impl Merger {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Add `entry`, arriving at `now`, returning any messages it completes.
+    fn push(&mut self, entry: LogEntry, now: Instant) -> Vec<LogEntry> {
+        let mut complete = Vec::new();
+        let key = stream_key(&entry);
+        let continues = self.continues(&entry.line);
+
+        if let Some(pending) = self.pending.get_mut(&key) {
+            if continues {
+                pending.entry.line.push('\n');
+                pending.entry.line.push_str(&entry.line);
+                pending.lines += 1;
+                pending.deadline = now + self.config.timeout;
+                if pending.lines >= self.config.max_lines {
+                    complete.extend(self.pending.remove(&key).map(|pending| pending.entry));
+                }
+                return complete;
+            }
+            complete.extend(self.pending.remove(&key).map(|pending| pending.entry));
+        }
+
+        if self.config.max_lines <= 1 {
+            complete.push(entry);
+        } else {
+            let deadline = now + self.config.timeout;
+            self.pending.insert(
+                key,
+                Pending {
+                    entry,
+                    lines: 1,
+                    deadline,
+                },
+            );
+        }
+        complete
+    }
+
+    /// Remove the messages that have timed out by `now`.
+    fn expire(&mut self, now: Instant) -> Vec<LogEntry> {
+        let expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| self.pending.remove(&key))
+            .map(|pending| pending.entry)
+            .collect()
+    }
+
+    /// Remove all messages, e.g. because no more lines will arrive.
+    fn flush(&mut self) -> Vec<LogEntry> {
+        let pending = std::mem::take(&mut self.pending);
+        pending
+            .into_iter()
+            .map(|(_, pending)| pending.entry)
+            .collect()
+    }
+
+    /// When the next message will time out, if any are pending.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.deadline).min()
+    }
+
+    /// Whether `line` continues a pending message.
+    fn continues(&self, line: &str) -> bool {
+        match &self.config.continuation {
+            Some(continuation) => continuation.is_match(line),
+            None => !self.config.start.is_match(line),
+        }
+    }
+}
+
+/// A [`Collector`](super::Collector) merging the lines of multi-line messages from another
+/// collector.
+///
+/// The wrapped collector is run on a separate thread, so that messages can be emitted when they
+/// time out even while the collector is waiting for more entries.
+#[derive(Debug)]
+pub struct Multiline {
+    merger: Merger,
+    receiver: Receiver<io::Result<LogEntry>>,
+    ready: VecDeque<io::Result<LogEntry>>,
+    done: bool,
+}
+
+impl Multiline {
+    /// Merge the lines of multi-line messages from `collector`.
+    pub fn new<C>(collector: C, config: Config) -> Self
+    where
+        C: Iterator<Item = io::Result<LogEntry>> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for entry in collector {
+                if sender.send(entry).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            merger: Merger::new(config),
+            receiver,
+            ready: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+impl Iterator for Multiline {
+    type Item = io::Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.ready.pop_front() {
+                return Some(entry);
+            }
+            if self.done {
+                return None;
+            }
+
+            let received = match self.merger.next_deadline() {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    self.receiver.recv_timeout(timeout)
+                }
+                None => self
+                    .receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(Ok(entry)) => {
+                    let complete = self.merger.push(entry, Instant::now());
+                    self.ready.extend(complete.into_iter().map(Ok));
+                }
+                Ok(Err(error)) => {
+                    self.ready.extend(self.merger.flush().into_iter().map(Ok));
+                    self.ready.push_back(Err(error));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let expired = self.merger.expire(Instant::now());
+                    self.ready.extend(expired.into_iter().map(Ok));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.ready.extend(self.merger.flush().into_iter().map(Ok));
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+impl super::Collector for Multiline {}
+
+/// Identify the stream an entry belongs to.
+fn stream_key(entry: &LogEntry) -> Vec<(String, String)> {
+    let mut key: Vec<_> = entry
+        .metadata
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    key.sort();
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+    use std::time::{Duration, Instant};
+
+    use regex::Regex;
+
+    use crate::LogEntry;
+
+    use super::{Config, Merger, Multiline};
+
+    fn entry(stream: &str, line: &str) -> LogEntry {
+        let mut metadata = HashMap::new();
+        metadata.insert("stream".to_string(), stream.to_string());
+        LogEntry {
+            line: line.to_string(),
+            metadata,
+            timestamp: None,
+        }
+    }
+
+    fn lines(entries: Vec<LogEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.line).collect()
+    }
+
+    fn config() -> Config {
+        Config {
+            start: Regex::new(r"^\d{4}-\d{2}-\d{2} ").unwrap(),
+            continuation: None,
+            timeout: Duration::from_secs(1),
+            max_lines: 3,
+        }
+    }
+
+    #[test]
+    fn merge_lines() {
+        let now = Instant::now();
+        let mut merger = Merger::new(config());
+
+        assert!(merger.push(entry("a", "2021-01-01 error"), now).is_empty());
+        assert!(merger.push(entry("a", "  at Foo.bar"), now).is_empty());
+        assert!(merger.push(entry("b", "2021-01-01 other"), now).is_empty());
+        assert_eq!(
+            lines(merger.push(entry("a", "2021-01-02 next"), now)),
+            vec!["2021-01-01 error\n  at Foo.bar"]
+        );
+
+        // Messages are emitted in pieces once they reach `max_lines`.
+        assert!(merger.push(entry("b", "  one"), now).is_empty());
+        assert_eq!(
+            lines(merger.push(entry("b", "  two"), now)),
+            vec!["2021-01-01 other\n  one\n  two"]
+        );
+        assert!(merger.push(entry("b", "  three"), now).is_empty());
+
+        // Messages time out after the last line is appended.
+        let later = now + Duration::from_millis(500);
+        assert!(merger.push(entry("a", "  at Foo.baz"), later).is_empty());
+        assert_eq!(merger.next_deadline(), Some(now + Duration::from_secs(1)));
+        assert_eq!(
+            lines(merger.expire(now + Duration::from_secs(1))),
+            vec!["  three"]
+        );
+        assert_eq!(lines(merger.flush()), vec!["2021-01-02 next\n  at Foo.baz"]);
+        assert_eq!(merger.next_deadline(), None);
+
+        let mut merger = Merger::new(Config {
+            continuation: Some(Regex::new(r"^\s").unwrap()),
+            ..config()
+        });
+        assert!(merger.push(entry("a", "Traceback:"), now).is_empty());
+        assert!(merger.push(entry("a", "  File \"x.py\""), now).is_empty());
+        assert_eq!(
+            lines(merger.push(entry("a", "ValueError"), now)),
+            vec!["Traceback:\n  File \"x.py\""]
+        );
+    }
+
+    #[test]
+    fn merge_collector() {
+        let entries = vec![
+            Ok(entry("a", "2021-01-01 error")),
+            Ok(entry("a", "  at Foo.bar")),
+            Err(io::Error::new(io::ErrorKind::Other, "oops")),
+            Ok(entry("a", "2021-01-02 next")),
+            Ok(entry("a", "  at Foo.baz")),
+        ];
+        let mut multiline = Multiline::new(entries.into_iter(), config());
+
+        assert_eq!(
+            multiline.next().unwrap().unwrap().line,
+            "2021-01-01 error\n  at Foo.bar"
+        );
+        assert!(multiline.next().unwrap().is_err());
+        assert_eq!(
+            multiline.next().unwrap().unwrap().line,
+            "2021-01-02 next\n  at Foo.baz"
+        );
+        assert!(multiline.next().is_none());
+
+        // Pending messages are emitted when they time out, while the collector is still running.
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut multiline = Multiline::new(
+            receiver.into_iter(),
+            Config {
+                timeout: Duration::from_millis(10),
+                ..config()
+            },
+        );
+        sender.send(Ok(entry("a", "2021-01-01 quiet"))).unwrap();
+        assert_eq!(multiline.next().unwrap().unwrap().line, "2021-01-01 quiet");
+        drop(sender);
+        assert!(multiline.next().is_none());
+    }
+}
@@ -0,0 +1,291 @@
+// src/log_collector/once.rs
+//! One-shot collection of a directory of log files.
+//!
+//! Rather than watching files, a one-shot collector reads everything currently in its root path
+//! and then ends. This suits cron-driven batch hosts and CI log capture, where a daemon is
+//! unwanted. Plain and compressed (`.gz` or `.zst`) files are both read, in path order.
+//!
+//! With [checkpoints](crate::checkpoint), each run only reads content that earlier runs haven't.
+//! A file's checkpoint is stored once the file has been read to the end, so a run that is
+//! interrupted part way through a file will read that file again. The final line of a plain file
+//! is left for the next run if it has no trailing newline, since it may still be being written.
+
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+use crate::checkpoint::{Checkpoint, Checkpoints, Hasher};
+use crate::LogEntry;
+
+use super::compressed::{self, CompressedFile};
+use super::format::{Format, Parser};
+use super::ownership;
+use super::paths;
+
+/// Configuration for [`initialize`].
+pub struct Config {
+    /// The root path from which to collect logs.
+    pub root_path: PathBuf,
+
+    /// Where to store [checkpoints](crate::checkpoint) of the files that have been read, if
+    /// anywhere.
+    ///
+    /// Without checkpoints, every file is read in full every time.
+    pub checkpoints: Option<PathBuf>,
+
+    /// The format of the lines in the log files.
+    pub format: Format,
+}
+
+/// Initialize a [`Collector`](super::Collector) that reads the log files currently in
+/// `config.root_path`, and then ends.
+///
+/// The files are listed when the collector is initialized. Files created afterwards are ignored,
+/// and files that have been removed (or can't be opened due to permissions) by the time they're
+/// reached are skipped with a warning.
+///
+/// # Errors
+///
+/// Propagates any `io::Error`s that occur when listing the root path, or loading checkpoints.
+pub fn initialize(config: Config) -> io::Result<impl super::Collector> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&config.root_path)? {
+        let path = entry?.path();
+        if path.file_name() != Some(OsStr::new(ownership::MARKER_FILE_NAME)) && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(Collector {
+        files: files.into(),
+        current: None,
+        parser: Parser::new(config.format),
+        format: config.format,
+        checkpoints: config.checkpoints.map(Checkpoints::load).transpose()?,
+    })
+}
+
+struct Collector {
+    files: VecDeque<PathBuf>,
+    current: Option<Source>,
+    parser: Parser,
+    format: Format,
+    checkpoints: Option<Checkpoints>,
+}
+
+impl Collector {
+    fn open(&self, path: &Path) -> io::Result<Source> {
+        let checkpoint = self
+            .checkpoints
+            .as_ref()
+            .and_then(|checkpoints| checkpoints.get(&paths::normalize(path)));
+        if compressed::is_compressed(path) {
+            let file = match checkpoint {
+                Some(checkpoint) => CompressedFile::resume(path, checkpoint)?,
+                None => CompressedFile::open(path, 0)?,
+            };
+            Ok(Source::Compressed(file))
+        } else {
+            Ok(Source::Plain(PlainFile::open(path, checkpoint)?))
+        }
+    }
+
+    fn read_entry(&mut self) -> io::Result<Option<LogEntry>> {
+        loop {
+            if let Some(source) = &mut self.current {
+                if let Some(entry) = source.read_entry()? {
+                    match self.parser.parse_entry(entry) {
+                        Some(entry) => return Ok(Some(entry)),
+                        None => continue,
+                    }
+                }
+
+                debug!("Finished reading {}", source.path());
+                if let Some(checkpoints) = &mut self.checkpoints {
+                    checkpoints.set(source.path(), source.checkpoint())?;
+                }
+                self.current = None;
+            }
+
+            let path = match self.files.pop_front() {
+                Some(path) => path,
+                None => return Ok(None),
+            };
+            match self.open(&path) {
+                Ok(source) => {
+                    self.current = Some(source);
+                    self.parser = Parser::new(self.format);
+                }
+                Err(error)
+                    if error.kind() == io::ErrorKind::NotFound
+                        || error.kind() == io::ErrorKind::PermissionDenied =>
+                {
+                    warn!("Skipping {}: {}", path.display(), error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+impl super::Collector for Collector {}
+
+impl Iterator for Collector {
+    type Item = io::Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+enum Source {
+    Plain(PlainFile),
+    Compressed(CompressedFile),
+}
+
+impl Source {
+    fn path(&self) -> &str {
+        match self {
+            Self::Plain(file) => &file.path,
+            Self::Compressed(file) => file.path(),
+        }
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        match self {
+            Self::Plain(file) => file.hasher.checkpoint(file.offset),
+            Self::Compressed(file) => file.checkpoint(),
+        }
+    }
+
+    fn read_entry(&mut self) -> io::Result<Option<LogEntry>> {
+        match self {
+            Self::Plain(file) => file.read_entry(),
+            Self::Compressed(file) => file.read_entry(),
+        }
+    }
+}
+
+/// An uncompressed log file being read up to its last complete line.
+struct PlainFile {
+    path: String,
+    reader: BufReader<File>,
+    offset: u64,
+    hasher: Hasher,
+    entry_buf: String,
+}
+
+impl PlainFile {
+    /// Open the file at `path`, resuming from `checkpoint` if the file still starts with the
+    /// checkpointed content, or from the start otherwise.
+    fn open(path: &Path, checkpoint: Option<Checkpoint>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let (offset, hasher) = match checkpoint {
+            Some(checkpoint) => match checkpoint.verify(&mut reader)? {
+                Some(hasher) => (checkpoint.offset, hasher),
+                None => {
+                    warn!(
+                        "{} has changed since it was checkpointed, reading it from the start",
+                        path.display()
+                    );
+                    reader.seek(SeekFrom::Start(0))?;
+                    (0, Hasher::new())
+                }
+            },
+            None => (0, Hasher::new()),
+        };
+        Ok(Self {
+            path: paths::normalize(path),
+            reader,
+            offset,
+            hasher,
+            entry_buf: String::new(),
+        })
+    }
+
+    fn read_entry(&mut self) -> io::Result<Option<LogEntry>> {
+        self.entry_buf.clear();
+        let read = self.reader.read_line(&mut self.entry_buf)?;
+        if read == 0 || !self.entry_buf.ends_with('\n') {
+            return Ok(None);
+        }
+        self.offset += read as u64;
+        self.hasher.update(self.entry_buf.as_bytes());
+        self.entry_buf.pop();
+
+        let mut metadata = HashMap::new();
+        metadata.insert("path".to_string(), self.path.clone());
+        Ok(Some(LogEntry {
+            line: self.entry_buf.clone(),
+            metadata,
+            timestamp: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, Write};
+
+    use crate::test::{self, log_entry};
+
+    use super::super::format::Format;
+    use super::{initialize, Config};
+
+    #[test]
+    fn reads_each_line_once() -> test::Result {
+        let root = tempfile::tempdir()?;
+        let checkpoint_dir = tempfile::tempdir()?;
+        let config = || Config {
+            root_path: root.path().to_path_buf(),
+            checkpoints: Some(checkpoint_dir.path().join("checkpoints")),
+            format: Format::Plain,
+        };
+        let collect = || -> io::Result<Vec<_>> { initialize(config())?.collect() };
+
+        let plain_path = root.path().join("app.log");
+        let compressed_path = root.path().join("app.log.1.gz");
+        let plain = plain_path.to_str().unwrap();
+        let compressed = compressed_path.to_str().unwrap();
+
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(&compressed_path)?,
+            flate2::Compression::default(),
+        );
+        encoder.write_all(b"rotated\n")?;
+        encoder.finish()?;
+        fs::write(&plain_path, "hello\npartial")?;
+
+        assert_eq!(
+            collect()?,
+            vec![
+                log_entry("hello", &[("path", plain)]),
+                log_entry("rotated", &[("path", compressed)]),
+            ]
+        );
+        assert_eq!(collect()?, vec![]);
+
+        OpenOptions::new()
+            .append(true)
+            .open(&plain_path)?
+            .write_all(b" line\nworld\n")?;
+        assert_eq!(
+            collect()?,
+            vec![
+                log_entry("partial line", &[("path", plain)]),
+                log_entry("world", &[("path", plain)]),
+            ]
+        );
+
+        fs::write(&plain_path, "replaced\n")?;
+        assert_eq!(collect()?, vec![log_entry("replaced", &[("path", plain)])]);
+
+        Ok(())
+    }
+}
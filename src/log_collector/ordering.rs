@@ -0,0 +1,221 @@
+// src/log_collector/ordering.rs
+//! Per-stream ordering of collected entries.
+//!
+//! Entries are tagged with a per-stream sequence number by a [`Sequencer`] as soon as they are
+//! read. However entries are then processed (e.g. concurrently, or by multiple workers), a
+//! [`ReorderBuffer`] in front of the sink restores the original order of each stream before they
+//! are written. Entries from different streams may still be interleaved differently.
+//!
+//! This is only needed where entries of a stream can overtake each other. The collection pipeline
+//! processes entries one at a time, in the order each collector reads them, so it doesn't use
+//! these. Both keep state for every stream they've seen, so long-lived users should periodically
+//! [evict idle streams](Sequencer::evict_idle).
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// An item tagged with its position in a stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sequenced<K, T> {
+    /// The stream the item belongs to.
+    pub stream: K,
+
+    /// The position of the item in its stream, starting from 0.
+    pub sequence: u64,
+
+    /// The item itself.
+    pub item: T,
+}
+
+/// Assigns sequence numbers to items as they are read.
+#[derive(Debug)]
+pub struct Sequencer<K> {
+    /// The next sequence number of each stream, and when the stream was last tagged.
+    next: HashMap<K, (u64, Instant)>,
+}
+
+impl<K: Clone + Eq + Hash> Sequencer<K> {
+    /// Construct a `Sequencer` with no known streams.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            next: HashMap::new(),
+        }
+    }
+
+    /// Tag `item` as the next item in `stream`.
+    pub fn tag<T>(&mut self, stream: K, item: T) -> Sequenced<K, T> {
+        let (next, last_tagged) = self
+            .next
+            .entry(stream.clone())
+            .or_insert((0, Instant::now()));
+        let sequence = *next;
+        *next += 1;
+        *last_tagged = Instant::now();
+        Sequenced {
+            stream,
+            sequence,
+            item,
+        }
+    }
+
+    /// Forget the streams that haven't been tagged for at least `max_idle`, returning them.
+    ///
+    /// Evicted streams start again from 0, so they must also be
+    /// [forgotten](ReorderBuffer::forget) by the [`ReorderBuffer`] their items are pushed to.
+    pub fn evict_idle(&mut self, max_idle: Duration) -> Vec<K> {
+        let mut evicted = Vec::new();
+        self.next.retain(|stream, (_, last_tagged)| {
+            if last_tagged.elapsed() < max_idle {
+                return true;
+            }
+            evicted.push(stream.clone());
+            false
+        });
+        evicted
+    }
+}
+
+impl<K: Clone + Eq + Hash> Default for Sequencer<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Restores the order of items tagged by a [`Sequencer`].
+///
+/// Every tagged item must eventually be pushed, otherwise later items in the same stream will be
+/// held indefinitely.
+#[derive(Debug)]
+pub struct ReorderBuffer<K, T> {
+    streams: HashMap<K, StreamBuffer<T>>,
+}
+
+#[derive(Debug)]
+struct StreamBuffer<T> {
+    next: u64,
+    pending: BTreeMap<u64, T>,
+}
+
+impl<K: Eq + Hash, T> ReorderBuffer<K, T> {
+    /// Construct an empty `ReorderBuffer`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Push a tagged item, returning any items from its stream that are now ready, in order.
+    pub fn push(&mut self, sequenced: Sequenced<K, T>) -> Vec<T> {
+        let stream = self
+            .streams
+            .entry(sequenced.stream)
+            .or_insert_with(|| StreamBuffer {
+                next: 0,
+                pending: BTreeMap::new(),
+            });
+
+        if sequenced.sequence < stream.next {
+            // This can only happen if an item was pushed twice, so drop the duplicate.
+            return Vec::new();
+        }
+        stream.pending.insert(sequenced.sequence, sequenced.item);
+
+        let mut ready = Vec::new();
+        while let Some(item) = stream.pending.remove(&stream.next) {
+            ready.push(item);
+            stream.next += 1;
+        }
+        ready
+    }
+
+    /// Forget `stream`, returning any items still held for it, in order.
+    ///
+    /// This should only be called once every item tagged for `stream` has been pushed (e.g. after
+    /// [`Sequencer::evict_idle`]), since its next item is expected to have sequence number 0.
+    pub fn forget(&mut self, stream: &K) -> Vec<T> {
+        self.streams.remove(stream).map_or_else(Vec::new, |stream| {
+            stream.pending.into_iter().map(|(_, item)| item).collect()
+        })
+    }
+
+    /// The number of items held waiting for earlier items in their stream.
+    #[must_use]
+    pub fn pending(&self) -> usize {
+        self.streams
+            .values()
+            .map(|stream| stream.pending.len())
+            .sum()
+    }
+}
+
+impl<K: Eq + Hash, T> Default for ReorderBuffer<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{ReorderBuffer, Sequencer};
+
+    #[test]
+    fn sequences_per_stream() {
+        let mut sequencer = Sequencer::new();
+
+        assert_eq!(sequencer.tag("a", ()).sequence, 0);
+        assert_eq!(sequencer.tag("a", ()).sequence, 1);
+        assert_eq!(sequencer.tag("b", ()).sequence, 0);
+        assert_eq!(sequencer.tag("a", ()).sequence, 2);
+    }
+
+    #[test]
+    fn restores_stream_order() {
+        let mut sequencer = Sequencer::new();
+        let a: Vec<_> = (0..3).map(|i| sequencer.tag("a", i)).collect();
+        let b: Vec<_> = (0..2).map(|i| sequencer.tag("b", i)).collect();
+
+        let mut buffer = ReorderBuffer::new();
+        let mut a = a.into_iter();
+        let (a0, a1, a2) = (a.next().unwrap(), a.next().unwrap(), a.next().unwrap());
+        let mut b = b.into_iter();
+        let (b0, b1) = (b.next().unwrap(), b.next().unwrap());
+
+        assert_eq!(buffer.push(a2), Vec::<i32>::new());
+        assert_eq!(buffer.push(b1), vec![]);
+        assert_eq!(buffer.push(a1), vec![]);
+        assert_eq!(buffer.pending(), 3);
+
+        assert_eq!(buffer.push(b0), vec![0, 1]);
+        assert_eq!(buffer.push(a0.clone()), vec![0, 1, 2]);
+        assert_eq!(buffer.pending(), 0);
+
+        // Duplicates are dropped.
+        assert_eq!(buffer.push(a0), vec![]);
+        assert_eq!(buffer.pending(), 0);
+    }
+
+    #[test]
+    fn evicts_idle_streams() {
+        let mut sequencer = Sequencer::new();
+        let mut buffer = ReorderBuffer::new();
+        sequencer.tag("a", 0);
+        buffer.push(sequencer.tag("a", 1));
+        assert_eq!(buffer.pending(), 1);
+
+        assert_eq!(
+            sequencer.evict_idle(Duration::from_secs(60)),
+            Vec::<&str>::new()
+        );
+        assert_eq!(sequencer.evict_idle(Duration::from_secs(0)), vec!["a"]);
+        assert_eq!(buffer.forget(&"a"), vec![1]);
+        assert_eq!(buffer.pending(), 0);
+
+        // The stream starts again from the beginning.
+        assert_eq!(buffer.push(sequencer.tag("a", 2)), vec![2]);
+    }
+}
@@ -0,0 +1,179 @@
+// src/log_collector/ownership.rs
+//! Ownership markers for detecting duplicate collection.
+//!
+//! If two agents collect the same files every line will be ingested twice, which is easy to do by
+//! accident (e.g. after a botched rollout). When enabled, collectors write a marker file into the
+//! directory they're collecting, identifying themselves and refreshed periodically. If a collector
+//! finds a fresh marker written by someone else, it warns loudly and reports it through
+//! [`DUPLICATE_COLLECTION_DETECTED`] and [`DUPLICATE_COLLECTION_ACTIVE`].
+//!
+//! Watched directories are often mounted read-only, so failing to write a marker is not an error.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{error, warn};
+
+use crate::metrics::Metric;
+
+/// The name of the marker file written into collected directories.
+pub(super) const MARKER_FILE_NAME: &str = ".monitoring-rs-owner";
+
+/// Markers that haven't been refreshed for this long are considered abandoned.
+const STALE_AFTER: Duration = Duration::from_secs(60);
+
+/// How often a collector refreshes its marker (and checks for other owners).
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Distinguishes markers claimed by the same process.
+static NEXT_MARKER: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of times another collector has been detected collecting the same directory.
+pub static DUPLICATE_COLLECTION_DETECTED: Metric = Metric::counter(
+    "monitoring_rs_duplicate_collection_detected_total",
+    "Number of times another collector was detected collecting the same directory.",
+);
+
+/// Whether another collector was seen collecting the same directory at the last check.
+pub static DUPLICATE_COLLECTION_ACTIVE: Metric = Metric::gauge(
+    "monitoring_rs_duplicate_collection_active",
+    "Whether another collector was seen collecting the same directory at the last check.",
+);
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+struct Owner {
+    id: String,
+    hostname: Option<String>,
+    pid: u32,
+}
+
+/// An ownership marker claimed by this process.
+#[derive(Debug)]
+pub(super) struct Marker {
+    path: PathBuf,
+    owner: Owner,
+    last_refresh: Instant,
+    writable: bool,
+}
+
+impl Marker {
+    /// Claim the marker in `directory`, warning if another collector currently owns it.
+    pub(super) fn claim(directory: &Path) -> Self {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let pid = process::id();
+
+        let mut marker = Self {
+            path: directory.join(MARKER_FILE_NAME),
+            owner: Owner {
+                id: format!(
+                    "{}-{}-{}",
+                    pid,
+                    started_at.as_nanos(),
+                    NEXT_MARKER.fetch_add(1, Ordering::Relaxed)
+                ),
+                hostname: std::env::var("HOSTNAME").ok(),
+                pid,
+            },
+            last_refresh: Instant::now(),
+            writable: true,
+        };
+        marker.check_other_owner();
+        marker.write();
+        marker
+    }
+
+    /// Refresh the marker, if it's due, checking whether another collector has claimed it.
+    pub(super) fn refresh(&mut self) {
+        if self.last_refresh.elapsed() < REFRESH_INTERVAL {
+            return;
+        }
+        self.last_refresh = Instant::now();
+        self.check_other_owner();
+        self.write();
+    }
+
+    /// Check whether another collector owns the marker, updating the metrics accordingly.
+    fn check_other_owner(&self) -> bool {
+        let other = match self.read_fresh_owner() {
+            Some(owner) if owner.id != self.owner.id => owner,
+            _ => {
+                DUPLICATE_COLLECTION_ACTIVE.set(0);
+                return false;
+            }
+        };
+
+        error!(
+            "DUPLICATE COLLECTION: {} is also being collected by {} (pid {} on {}). Every line \
+             will be ingested more than once until one of the collectors is stopped.",
+            self.path.parent().unwrap_or(&self.path).display(),
+            other.id,
+            other.pid,
+            other.hostname.as_deref().unwrap_or("unknown host"),
+        );
+        DUPLICATE_COLLECTION_DETECTED.inc();
+        DUPLICATE_COLLECTION_ACTIVE.set(1);
+        true
+    }
+
+    fn read_fresh_owner(&self) -> Option<Owner> {
+        let modified = fs::metadata(&self.path).ok()?.modified().ok()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age > STALE_AFTER {
+            return None;
+        }
+        serde_json::from_slice(&fs::read(&self.path).ok()?).ok()
+    }
+
+    fn write(&mut self) {
+        if !self.writable {
+            return;
+        }
+
+        let result = serde_json::to_vec(&self.owner)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| fs::write(&self.path, contents));
+        if let Err(error) = result {
+            warn!(
+                "Unable to write ownership marker {}, duplicate collection will not be detected: {}",
+                self.path.display(),
+                error
+            );
+            self.writable = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::test;
+
+    use super::{Marker, MARKER_FILE_NAME};
+
+    #[test]
+    fn detects_other_owner() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+
+        let first = Marker::claim(tempdir.path());
+        assert!(tempdir.path().join(MARKER_FILE_NAME).exists());
+        assert_eq!(first.read_fresh_owner(), Some(first.owner.clone()));
+
+        // The metrics are shared with other tests' markers, so only the results are checked.
+        let second = Marker::claim(tempdir.path());
+        assert!(first.check_other_owner());
+        assert!(!second.check_other_owner());
+
+        fs::remove_file(tempdir.path().join(MARKER_FILE_NAME))?;
+        assert!(!first.check_other_owner());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+// log_collector/paths.rs
+
+//! Normalisation of the `path` metadata that collectors attach to log entries.
+//!
+//! The same file can be spelled many ways, particularly on Windows (`C:\Logs\App.log`,
+//! `c:/logs/app.log`, `\\?\C:\Logs\App.log`, ...). Collectors normalise paths with [`normalize`]
+//! before using them as metadata, and the API normalises `path` matchers the same way, so queries
+//! by `path` behave consistently whichever spelling is used.
+//!
+//! Paths are normalised according to their [`Style`]:
+//!
+//! - Both styles collapse repeated separators, drop `.` components, and drop trailing separators.
+//!   `..` components are kept, since resolving them could change the file being referred to when
+//!   symlinks are involved.
+//! - Windows paths additionally have long-path prefixes (`\\?\` and `\\?\UNC\`) removed, use `/`
+//!   as the separator, and are lowercased, since Windows file systems are case-insensitive.
+
+use std::path::Path;
+
+/// The long-path prefix for local Windows paths.
+const LONG_PATH_PREFIX: &str = r"\\?\";
+
+/// The long-path prefix for Windows UNC paths.
+const LONG_UNC_PATH_PREFIX: &str = r"\\?\UNC\";
+
+/// The conventions of a path.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Style {
+    /// `/`-separated, case-sensitive paths.
+    Unix,
+
+    /// `\`- or `/`-separated, case-insensitive paths, with drive letters or UNC prefixes.
+    Windows,
+}
+
+impl Style {
+    /// Detect the style of `path`.
+    ///
+    /// Paths starting with a drive letter (`C:`) or with `\\` are Windows paths. Other paths are
+    /// Unix paths, so `\` in Unix file names is left alone.
+    #[must_use]
+    pub fn of(path: &str) -> Self {
+        let bytes = path.as_bytes();
+        let drive = bytes.len() >= 2
+            && bytes[0].is_ascii_alphabetic()
+            && bytes[1] == b':'
+            && (bytes.len() == 2 || bytes[2] == b'\\' || bytes[2] == b'/');
+        if drive || path.starts_with(r"\\") {
+            Self::Windows
+        } else {
+            Self::Unix
+        }
+    }
+}
+
+/// Normalise `path` for use as `path` metadata.
+#[must_use]
+pub fn normalize(path: &Path) -> String {
+    normalize_str(&path.to_string_lossy())
+}
+
+/// Normalise the textual `path` for use as, or comparison with, `path` metadata.
+#[must_use]
+pub fn normalize_str(path: &str) -> String {
+    match Style::of(path) {
+        Style::Unix => join(path.starts_with('/'), "", path.split('/')),
+        Style::Windows => {
+            let (prefix, rest) = if path.starts_with(LONG_UNC_PATH_PREFIX) {
+                ("//", &path[LONG_UNC_PATH_PREFIX.len()..])
+            } else if path.starts_with(LONG_PATH_PREFIX) {
+                ("", &path[LONG_PATH_PREFIX.len()..])
+            } else if path.starts_with(r"\\") {
+                ("//", &path[2..])
+            } else {
+                ("", path)
+            };
+            let mut normalized = join(false, prefix, rest.split(|c| c == '\\' || c == '/'));
+            if normalized.len() == 2 && normalized.ends_with(':') && rest.len() > 2 {
+                // Keep the separator of drive roots, since `C:` alone is relative.
+                normalized.push('/');
+            }
+            normalized.to_lowercase()
+        }
+    }
+}
+
+fn join<'a>(absolute: bool, prefix: &str, components: impl Iterator<Item = &'a str>) -> String {
+    let components: Vec<_> = components
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect();
+    let mut joined = String::from(prefix);
+    if absolute {
+        joined.push('/');
+    }
+    joined.push_str(&components.join("/"));
+    if joined.is_empty() {
+        joined.push('.');
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{normalize, normalize_str, Style};
+
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(Style::of("/var/log/app.log"), Style::Unix);
+        assert_eq!(Style::of(r"C:\Logs"), Style::Windows);
+        assert_eq!(Style::of(r"\\server\share"), Style::Windows);
+        assert_eq!(Style::of("c:not-a-drive"), Style::Unix);
+
+        assert_eq!(normalize(Path::new("/var/log/app.log")), "/var/log/app.log");
+        assert_eq!(normalize_str("/var//log/./app.log"), "/var/log/app.log");
+        assert_eq!(normalize_str("/var/log/"), "/var/log");
+        assert_eq!(normalize_str("/"), "/");
+        assert_eq!(normalize_str("logs/../App.log"), "logs/../App.log");
+        assert_eq!(normalize_str(r"/tmp/a\b.log"), r"/tmp/a\b.log");
+
+        for path in &[
+            r"C:\Logs\App.log",
+            "c:/logs/app.log",
+            r"\\?\C:\Logs\App.log",
+            r"C:\Logs\\.\App.log",
+        ] {
+            assert_eq!(normalize_str(path), "c:/logs/app.log");
+        }
+        assert_eq!(normalize_str(r"C:\"), "c:/");
+        assert_eq!(normalize_str("C:"), "c:");
+        assert_eq!(
+            normalize_str(r"\\Server\Share\App.log"),
+            "//server/share/app.log"
+        );
+        assert_eq!(
+            normalize_str(r"\\?\UNC\Server\Share\App.log"),
+            "//server/share/app.log"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+// src/log_collector/stdin.rs
+//! A log collector that reads lines from standard input.
+//!
+//! Each line becomes a [`LogEntry`] whose metadata is [`Config::labels`], which makes it easy to
+//! pipe test data into `monitoring-rs`, or to run it as a sidecar collecting a supervised
+//! process's output (e.g. `my-app 2>&1 | monitoring-rs --log-collector stdin`). Trailing `\n` and
+//! `\r\n` are removed, and invalid UTF-8 is replaced. The collector ends when input is closed.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use crate::LogEntry;
+
+/// Configuration for [`initialize`].
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// Metadata to add to each entry.
+    pub labels: HashMap<String, String>,
+}
+
+/// Initialize a [`Collector`](super::Collector) that reads lines from standard input.
+#[must_use]
+pub fn initialize(config: Config) -> impl super::Collector {
+    Collector::new(io::BufReader::new(io::stdin()), config)
+}
+
+/// The options of a `stdin` collector in a [`Registry`](super::Registry).
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Options {}
+
+/// Initialize a collector from its options (see [`Initializer`](super::Initializer)).
+///
+/// There are no options besides `labels`, which the [`Registry`](super::Registry) adds to each
+/// entry.
+///
+/// # Errors
+///
+/// If the options are invalid, an [`Error`](super::Error) is returned.
+pub fn from_options(
+    options: &super::Options,
+    _context: &super::Context,
+) -> Result<Box<dyn super::Collector + Send>, super::Error> {
+    let Options {} = super::parse_options("stdin", options)?;
+    Ok(Box::new(initialize(Config::default())))
+}
+
+struct Collector<R> {
+    reader: R,
+    labels: HashMap<String, String>,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> Collector<R> {
+    fn new(reader: R, config: Config) -> Self {
+        Self {
+            reader,
+            labels: config.labels,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl<R: BufRead> super::Collector for Collector<R> {}
+
+impl<R: BufRead> Iterator for Collector<R> {
+    type Item = io::Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buf.clear();
+        match self.reader.read_until(b'\n', &mut self.buf) {
+            Ok(0) => None,
+            Ok(_) => {
+                if self.buf.ends_with(b"\n") {
+                    self.buf.pop();
+                    if self.buf.ends_with(b"\r") {
+                        self.buf.pop();
+                    }
+                }
+                Some(Ok(LogEntry {
+                    line: String::from_utf8_lossy(&self.buf).into_owned(),
+                    metadata: self.labels.clone(),
+                    timestamp: None,
+                }))
+            }
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::test::log_entry;
+
+    use super::{Collector, Config};
+
+    #[test]
+    fn read_lines() {
+        let input = Cursor::new(b"one\ntwo\r\n\n\xfffour".to_vec());
+        let mut config = Config::default();
+        config
+            .labels
+            .insert("source".to_string(), "test".to_string());
+
+        let entries = Collector::new(input, config)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                log_entry("one", &[("source", "test")]),
+                log_entry("two", &[("source", "test")]),
+                log_entry("", &[("source", "test")]),
+                log_entry("\u{fffd}four", &[("source", "test")]),
+            ]
+        );
+    }
+}
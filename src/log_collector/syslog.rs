@@ -0,0 +1,711 @@
+// src/log_collector/syslog.rs
+//! A log collector that listens for syslog messages over UDP and TCP.
+//!
+//! Both [RFC 3164] (BSD) and [RFC 5424] messages are accepted. Each message becomes a
+//! [`LogEntry`] whose line is the message's content, with metadata:
+//!
+//! - `facility` and `severity`: the names of the message's facility (e.g. `local0`) and severity
+//!   (e.g. `err`), from its priority.
+//! - `hostname`: the message's hostname, or the sender's IP address if it has none.
+//! - `appname`: the message's app name (the tag of an RFC 3164 message), if it has one.
+//!
+//! Messages without a valid priority are treated as `user.notice`, as RFC 3164 recommends, with the
+//! whole message as the line.
+//!
+//! Over UDP, each datagram is a message. Over TCP, messages are either preceded by their length
+//! and a space ("octet counting"), or terminated by a newline, as in [RFC 6587]. At most
+//! [`MAX_CONNECTIONS`] TCP connections are served at once (further connections are closed
+//! immediately), and connections are closed after [`READ_TIMEOUT`] without data.
+//!
+//! Up to [`BUFFER_SIZE`] received messages are buffered until the collector is read from. When the
+//! buffer is full, receiving stops until there is space, so TCP senders are slowed down and UDP
+//! datagrams may be dropped by the OS.
+//!
+//! [RFC 3164]: https://tools.ietf.org/html/rfc3164
+//! [RFC 5424]: https://tools.ietf.org/html/rfc5424
+//! [RFC 6587]: https://tools.ietf.org/html/rfc6587
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::LogEntry;
+
+/// The maximum size of a message, in bytes. Longer messages are truncated.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// How long to wait before receiving again after failing to receive a UDP message. The wait
+/// doubles with each consecutive failure, up to [`MAX_RECEIVE_BACKOFF`].
+const RECEIVE_BACKOFF: Duration = Duration::from_millis(10);
+
+/// The longest wait before receiving again after failing to receive a UDP message.
+const MAX_RECEIVE_BACKOFF: Duration = Duration::from_secs(5);
+
+/// The maximum number of TCP connections served at once.
+pub const MAX_CONNECTIONS: usize = 256;
+
+/// How long a TCP connection may go without sending data before it's closed.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The number of received messages buffered until the collector is read from.
+pub const BUFFER_SIZE: usize = 1024;
+
+/// The priority of messages without a valid priority: `user.notice`.
+const DEFAULT_PRIORITY: usize = 13;
+
+/// The names of facilities, by number.
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+/// The names of severities, by number.
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+/// Configuration for [`initialize`].
+pub struct Config {
+    /// The address on which to receive syslog messages over UDP, if any.
+    pub udp: Option<SocketAddr>,
+
+    /// The address on which to accept TCP connections carrying syslog messages, if any.
+    pub tcp: Option<SocketAddr>,
+}
+
+/// Initialize a [`Collector`](super::Collector) that listens for syslog messages.
+///
+/// The sockets are bound when the collector is initialized, and messages are received on
+/// background threads (one for UDP, and one per TCP connection, up to [`MAX_CONNECTIONS`]). Errors receiving messages, or on
+/// individual connections, are logged rather than ending the collector.
+///
+/// # Errors
+///
+/// Propagates any `io::Error`s that occur when binding the sockets.
+pub fn initialize(config: Config) -> io::Result<impl super::Collector> {
+    Collector::bind(config)
+}
+
+/// The options of a `syslog` collector in a [`Registry`](super::Registry).
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Options {
+    #[serde(default)]
+    udp: Option<SocketAddr>,
+    #[serde(default)]
+    tcp: Option<SocketAddr>,
+}
+
+/// Initialize a collector from its options (see [`Initializer`](super::Initializer)).
+///
+/// The options are `udp` and `tcp`, as in [`Config`] (e.g. `"0.0.0.0:514"`), of which at least
+/// one is required.
+///
+/// # Errors
+///
+/// If the options are invalid, or initialization fails, an [`Error`](super::Error) is returned.
+pub fn from_options(
+    options: &super::Options,
+    _context: &super::Context,
+) -> Result<Box<dyn super::Collector + Send>, super::Error> {
+    let options: Options = super::parse_options("syslog", options)?;
+    if options.udp.is_none() && options.tcp.is_none() {
+        return Err(super::Error::Options {
+            kind: "syslog".to_string(),
+            message: "at least one of `udp` and `tcp` is required".to_string(),
+        });
+    }
+    Ok(Box::new(initialize(Config {
+        udp: options.udp,
+        tcp: options.tcp,
+    })?))
+}
+
+struct Collector {
+    entries: mpsc::Receiver<LogEntry>,
+    udp_addr: Option<SocketAddr>,
+    tcp_addr: Option<SocketAddr>,
+}
+
+impl Collector {
+    fn bind(config: Config) -> io::Result<Self> {
+        let (sender, entries) = mpsc::sync_channel(BUFFER_SIZE);
+        let mut collector = Self {
+            entries,
+            udp_addr: None,
+            tcp_addr: None,
+        };
+
+        if let Some(addr) = config.udp {
+            let socket = UdpSocket::bind(addr)?;
+            collector.udp_addr = Some(socket.local_addr()?);
+            let sender = sender.clone();
+            thread::Builder::new()
+                .name("syslog-udp".to_string())
+                .spawn(move || receive_udp(|buf| socket.recv_from(buf), &sender))?;
+        }
+        if let Some(addr) = config.tcp {
+            let listener = TcpListener::bind(addr)?;
+            collector.tcp_addr = Some(listener.local_addr()?);
+            thread::Builder::new()
+                .name("syslog-tcp".to_string())
+                .spawn(move || accept_tcp(&listener, &sender))?;
+        }
+
+        Ok(collector)
+    }
+}
+
+impl super::Collector for Collector {}
+
+impl Iterator for Collector {
+    type Item = io::Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.recv().ok().map(Ok)
+    }
+}
+
+/// Receive datagrams with `recv` until the collector is dropped.
+///
+/// Failures are retried after [`RECEIVE_BACKOFF`], doubling while they persist, so that a
+/// persistently failing socket doesn't busy-loop.
+fn receive_udp(
+    mut recv: impl FnMut(&mut [u8]) -> io::Result<(usize, SocketAddr)>,
+    sender: &mpsc::SyncSender<LogEntry>,
+) {
+    let mut buf = vec![0; MAX_MESSAGE_SIZE];
+    let mut backoff = RECEIVE_BACKOFF;
+    loop {
+        let (len, peer) = match recv(&mut buf) {
+            Ok(received) => {
+                backoff = RECEIVE_BACKOFF;
+                received
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => {
+                warn!(
+                    "Failed to receive syslog message (retrying in {:?}): {}",
+                    backoff, error
+                );
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_RECEIVE_BACKOFF);
+                continue;
+            }
+        };
+        if let Some(entry) = parse_frame(&buf[..len], peer.ip()) {
+            if sender.send(entry).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn accept_tcp(listener: &TcpListener, sender: &mpsc::SyncSender<LogEntry>) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!("Failed to accept syslog connection: {}", error);
+                continue;
+            }
+        };
+        if connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+            warn!(
+                "Closing syslog connection: already serving {} connections",
+                MAX_CONNECTIONS
+            );
+            continue;
+        }
+        if let Err(error) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+            warn!("Failed to set timeout of syslog connection: {}", error);
+            continue;
+        }
+
+        let sender = sender.clone();
+        let connection = Connection::new(&connections);
+        let spawned = thread::Builder::new()
+            .name("syslog-tcp-connection".to_string())
+            .spawn(move || {
+                let _connection = connection;
+                let peer = match stream.peer_addr() {
+                    Ok(peer) => peer,
+                    Err(error) => {
+                        warn!("Failed to get address of syslog connection: {}", error);
+                        return;
+                    }
+                };
+                debug!("Accepted syslog connection from {}", peer);
+                match receive_tcp(stream, peer.ip(), &sender) {
+                    Ok(()) => debug!("Syslog connection from {} closed", peer),
+                    Err(error) => warn!("Syslog connection from {} failed: {}", peer, error),
+                }
+            });
+        if let Err(error) = spawned {
+            warn!("Failed to start thread for syslog connection: {}", error);
+        }
+    }
+}
+
+/// Counts an open TCP connection towards [`MAX_CONNECTIONS`] until it's dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    fn new(connections: &Arc<AtomicUsize>) -> Self {
+        connections.fetch_add(1, Ordering::SeqCst);
+        Self(Arc::clone(connections))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Receive messages from `stream` until it's closed, times out, or the collector is dropped.
+fn receive_tcp(
+    stream: TcpStream,
+    peer: IpAddr,
+    sender: &mpsc::SyncSender<LogEntry>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        let first = match reader.fill_buf()?.first() {
+            Some(first) => *first,
+            None => return Ok(()),
+        };
+        if first.is_ascii_digit() {
+            (&mut reader).take(16).read_until(b' ', &mut buf)?;
+            let len = std::str::from_utf8(&buf)
+                .ok()
+                .and_then(|len| len.trim_end().parse::<usize>().ok())
+                .filter(|len| *len <= MAX_MESSAGE_SIZE)
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid message length")
+                })?;
+            buf.clear();
+            buf.resize(len, 0);
+            reader.read_exact(&mut buf)?;
+        } else {
+            (&mut reader)
+                .take(MAX_MESSAGE_SIZE as u64)
+                .read_until(b'\n', &mut buf)?;
+            if buf.len() == MAX_MESSAGE_SIZE && buf.last() != Some(&b'\n') {
+                // The message was truncated, so the rest of it isn't a message of its own.
+                skip_line(&mut reader)?;
+            }
+        }
+
+        if let Some(entry) = parse_frame(&buf, peer) {
+            if sender.send(entry).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Discard input from `reader` up to and including the next newline.
+fn skip_line(reader: &mut impl BufRead) -> io::Result<()> {
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            return Ok(());
+        }
+        match available.iter().position(|byte| *byte == b'\n') {
+            Some(newline) => {
+                reader.consume(newline + 1);
+                return Ok(());
+            }
+            None => {
+                let len = available.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Parse a received message, ignoring trailing line endings (and NULs), or `None` if it's empty.
+fn parse_frame(frame: &[u8], peer: IpAddr) -> Option<LogEntry> {
+    let message = String::from_utf8_lossy(frame);
+    let message = message.trim_end_matches(|c| c == '\n' || c == '\r' || c == '\0');
+    if message.is_empty() {
+        None
+    } else {
+        Some(parse(message, peer))
+    }
+}
+
+/// Parse a syslog `message` received from `peer`.
+fn parse(message: &str, peer: IpAddr) -> LogEntry {
+    let (priority, rest) = parse_priority(message).unwrap_or((DEFAULT_PRIORITY, message));
+    let header = match rest.strip_prefix("1 ") {
+        Some(rest) => parse_rfc5424(rest),
+        None => parse_rfc3164(rest),
+    };
+    let (hostname, appname, line) = header.unwrap_or((None, None, rest));
+
+    let mut metadata = HashMap::new();
+    metadata.insert("facility".to_string(), FACILITIES[priority / 8].to_string());
+    metadata.insert("severity".to_string(), SEVERITIES[priority % 8].to_string());
+    metadata.insert(
+        "hostname".to_string(),
+        hostname.map_or_else(|| peer.to_string(), str::to_string),
+    );
+    if let Some(appname) = appname {
+        metadata.insert("appname".to_string(), appname.to_string());
+    }
+    LogEntry {
+        line: line.to_string(),
+        metadata,
+        timestamp: None,
+    }
+}
+
+/// The parts of a message's header: its hostname, app name, and content.
+type Header<'a> = (Option<&'a str>, Option<&'a str>, &'a str);
+
+/// Parse a message's leading `<priority>`, returning the priority and the rest of the message.
+fn parse_priority(message: &str) -> Option<(usize, &str)> {
+    let rest = message.strip_prefix('<')?;
+    let end = rest.find('>').filter(|end| (1..=3).contains(end))?;
+    let digits = &rest[..end];
+    if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let priority = digits.parse().ok().filter(|priority| *priority < 24 * 8)?;
+    Some((priority, &rest[end + 1..]))
+}
+
+/// Parse the header of an RFC 5424 message after its version, like
+/// `2003-10-11T22:14:15.003Z host app 1234 ID47 [exampleSDID@32473 iut="3"] message`.
+fn parse_rfc5424(rest: &str) -> Option<Header<'_>> {
+    let (_timestamp, rest) = token(rest)?;
+    let (hostname, rest) = token(rest)?;
+    let (appname, rest) = token(rest)?;
+    let (_procid, rest) = token(rest)?;
+    let (_msgid, rest) = token(rest)?;
+
+    let content = if let Some(rest) = rest.strip_prefix('-') {
+        rest
+    } else if rest.starts_with('[') {
+        let mut rest = rest;
+        while rest.starts_with('[') {
+            rest = skip_sd_element(rest)?;
+        }
+        rest
+    } else {
+        return None;
+    };
+    if !content.is_empty() && !content.starts_with(' ') {
+        return None;
+    }
+    let content = content.strip_prefix(' ').unwrap_or(content);
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    Some((nil(hostname), nil(appname), content))
+}
+
+/// Skip the structured data element at the start of `rest`, like `[id name="value"]`.
+fn skip_sd_element(rest: &str) -> Option<&str> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in rest.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            ']' if !quoted => return Some(&rest[index + 1..]),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse the header of an RFC 3164 message, like `Oct 11 22:14:15 host app[1234]: message`.
+///
+/// The timestamp and hostname are optional, since many local senders omit them.
+fn parse_rfc3164(rest: &str) -> Option<Header<'_>> {
+    let bytes = rest.as_bytes();
+    let has_timestamp = bytes.len() > 16
+        && bytes[..3].iter().all(u8::is_ascii_alphabetic)
+        && bytes[3] == b' '
+        && bytes[6] == b' '
+        && bytes[9] == b':'
+        && bytes[12] == b':'
+        && bytes[15] == b' ';
+    let (hostname, rest) = if has_timestamp {
+        let (hostname, rest) = token(&rest[16..])?;
+        (Some(hostname), rest)
+    } else {
+        (None, rest)
+    };
+
+    let tag_len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "-_./".contains(c)))
+        .filter(|len| (1..=48).contains(len));
+    let (appname, content) = match tag_len {
+        Some(len) => {
+            let after_tag = &rest[len..];
+            let after_pid = match after_tag.strip_prefix('[') {
+                Some(pid) => pid.find(']').map(|end| &pid[end + 1..]),
+                None => Some(after_tag),
+            };
+            match after_pid.and_then(|after_pid| after_pid.strip_prefix(':')) {
+                Some(content) => (Some(&rest[..len]), content),
+                None => (None, rest),
+            }
+        }
+        None => (None, rest),
+    };
+    let content = content.strip_prefix(' ').unwrap_or(content);
+    Some((hostname, appname, content))
+}
+
+/// Split the space-terminated token at the start of `input` from the rest of `input`.
+fn token(input: &str) -> Option<(&str, &str)> {
+    let end = input.find(' ')?;
+    Some((&input[..end], &input[end + 1..]))
+}
+
+/// `value`, unless it's the RFC 5424 nil value (`-`).
+fn nil(value: &str) -> Option<&str> {
+    if value == "-" {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use crate::test::{self, log_entry};
+
+    use super::{
+        parse, receive_udp, Collector, Config, MAX_CONNECTIONS, MAX_MESSAGE_SIZE, RECEIVE_BACKOFF,
+    };
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    #[test]
+    fn parse_messages() {
+        assert_eq!(
+            parse(
+                "<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 - 'su root' failed",
+                PEER
+            ),
+            log_entry(
+                "'su root' failed",
+                &[
+                    ("facility", "auth"),
+                    ("severity", "crit"),
+                    ("hostname", "mymachine.example.com"),
+                    ("appname", "su"),
+                ]
+            )
+        );
+        assert_eq!(
+            parse(
+                r#"<165>1 2003-08-24T05:14:15.000003-07:00 - - - - [id a="x\"]" b="y"][id2] msg"#,
+                PEER
+            ),
+            log_entry(
+                "msg",
+                &[
+                    ("facility", "local4"),
+                    ("severity", "notice"),
+                    ("hostname", "10.0.0.1"),
+                ]
+            )
+        );
+        assert_eq!(
+            parse("<13>1 - host app - - [id]", PEER),
+            log_entry(
+                "",
+                &[
+                    ("facility", "user"),
+                    ("severity", "notice"),
+                    ("hostname", "host"),
+                    ("appname", "app"),
+                ]
+            )
+        );
+        assert_eq!(
+            parse("<0>Oct 11 22:14:15 router sshd[1234]: Accepted key", PEER),
+            log_entry(
+                "Accepted key",
+                &[
+                    ("facility", "kern"),
+                    ("severity", "emerg"),
+                    ("hostname", "router"),
+                    ("appname", "sshd"),
+                ]
+            )
+        );
+        assert_eq!(
+            parse("<191>cron: job: done", PEER),
+            log_entry(
+                "job: done",
+                &[
+                    ("facility", "local7"),
+                    ("severity", "debug"),
+                    ("hostname", "10.0.0.1"),
+                    ("appname", "cron"),
+                ]
+            )
+        );
+        for message in &["<192>no priority", "no priority", "<1a>x: no priority"] {
+            assert_eq!(
+                parse(message, PEER),
+                log_entry(
+                    message,
+                    &[
+                        ("facility", "user"),
+                        ("severity", "notice"),
+                        ("hostname", "10.0.0.1"),
+                    ]
+                ),
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn receive_messages() -> test::Result {
+        let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+        let mut collector = Collector::bind(Config {
+            udp: Some(localhost),
+            tcp: Some(localhost),
+        })?;
+        let metadata = &[
+            ("facility", "user"),
+            ("severity", "notice"),
+            ("hostname", "127.0.0.1"),
+            ("appname", "app"),
+        ];
+
+        let socket = UdpSocket::bind(localhost)?;
+        socket.send_to(b"<13>app: over udp\n", collector.udp_addr.unwrap())?;
+        assert_eq!(
+            collector.next().transpose()?,
+            Some(log_entry("over udp", metadata))
+        );
+
+        let mut stream = TcpStream::connect(collector.tcp_addr.unwrap())?;
+        stream.write_all(b"<13>app: line one\r\n<13>app: line two\n")?;
+        stream.write_all(b"21 <13>app: counted\nline")?;
+        stream.write_all(b"<13>app: last")?;
+        drop(stream);
+        for line in &["line one", "line two", "counted\nline", "last"] {
+            assert_eq!(
+                collector.next().transpose()?,
+                Some(log_entry(line, metadata))
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn discards_rest_of_truncated_messages() -> test::Result {
+        let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+        let mut collector = Collector::bind(Config {
+            udp: None,
+            tcp: Some(localhost),
+        })?;
+
+        let mut stream = TcpStream::connect(collector.tcp_addr.unwrap())?;
+        stream.write_all(b"<13>app: ")?;
+        stream.write_all(&vec![b'x'; MAX_MESSAGE_SIZE])?;
+        stream.write_all(b"\n<13>app: next\n")?;
+        drop(stream);
+
+        let truncated = collector.next().unwrap()?;
+        assert!(truncated.line.bytes().all(|byte| byte == b'x'));
+        assert_eq!(collector.next().unwrap()?.line, "next");
+
+        Ok(())
+    }
+
+    #[test]
+    fn backs_off_after_receive_failures() {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        // Once a message is received it can't be sent, so receiving stops.
+        drop(receiver);
+
+        let mut failures = 3;
+        let start = Instant::now();
+        receive_udp(
+            |buf| {
+                if failures == 0 {
+                    buf[..5].copy_from_slice(b"hello");
+                    return Ok((5, SocketAddr::from((PEER, 514))));
+                }
+                failures -= 1;
+                Err(io::Error::new(io::ErrorKind::Other, "broken"))
+            },
+            &sender,
+        );
+        assert!(start.elapsed() >= RECEIVE_BACKOFF * 7);
+    }
+
+    #[test]
+    fn limits_connections() -> test::Result {
+        let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+        let mut collector = Collector::bind(Config {
+            udp: None,
+            tcp: Some(localhost),
+        })?;
+
+        let mut streams = Vec::new();
+        for _ in 0..MAX_CONNECTIONS {
+            let mut stream = TcpStream::connect(collector.tcp_addr.unwrap())?;
+            stream.write_all(b"<13>app: hello\n")?;
+            // Wait for the message, so the connection has been counted.
+            assert_eq!(collector.next().unwrap()?.line, "hello");
+            streams.push(stream);
+        }
+
+        let mut stream = TcpStream::connect(collector.tcp_addr.unwrap())?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        assert_eq!(stream.read(&mut [0])?, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+// src/log_collector/templates.rs
+//! Labels derived from templates over existing metadata.
+//!
+//! A [`Rule`] like `service={namespace}/{container_name}` adds a `service` key to each entry's
+//! metadata, built by substituting the entry's `namespace` and `container_name` values into the
+//! template. This saves every query from concatenating the values itself. `{{` and `}}` in a
+//! template are a literal `{` and `}`.
+//!
+//! Templates can refer to other derived labels, in which case those are derived first. Rules that
+//! refer to each other in a cycle are rejected by [`DerivedLabels::new`]. A rule is skipped for an
+//! entry that lacks any of the keys it refers to, and derived values longer than
+//! [`Config::max_length`] are truncated, so a template can't produce unbounded labels.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::LogEntry;
+
+/// Configuration for [`DerivedLabels`].
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The labels to derive.
+    pub rules: Vec<Rule>,
+
+    /// The maximum length, in bytes, of a derived value.
+    pub max_length: usize,
+}
+
+/// A label to derive, parsed from `name=template`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    name: String,
+    template: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Segment {
+    Literal(String),
+    Key(String),
+}
+
+/// An error constructing [`DerivedLabels`].
+#[derive(Debug, Eq, PartialEq)]
+pub enum ConfigError {
+    /// More than one rule derives the label.
+    Duplicate(String),
+
+    /// The rules deriving these labels refer to each other in a cycle.
+    Cycle(Vec<String>),
+}
+
+/// Adds labels derived from templates to log entries.
+#[derive(Debug)]
+pub struct DerivedLabels {
+    /// The rules, ordered so that each comes after any it refers to.
+    rules: Vec<Rule>,
+    max_length: usize,
+}
+
+impl Rule {
+    /// The name of the derived label.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The keys the template refers to.
+    fn keys(&self) -> impl Iterator<Item = &str> {
+        self.template.iter().filter_map(|segment| match segment {
+            Segment::Key(key) => Some(key.as_str()),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    /// Render the template with values from `metadata`, or `None` if any are missing.
+    fn render(&self, metadata: &HashMap<String, String>) -> Option<String> {
+        let mut value = String::new();
+        for segment in &self.template {
+            match segment {
+                Segment::Literal(literal) => value.push_str(literal),
+                Segment::Key(key) => value.push_str(metadata.get(key)?),
+            }
+        }
+        Some(value)
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let equals = s
+            .find('=')
+            .ok_or_else(|| format!("invalid derived label {:?}: expected name=template", s))?;
+        let name = s[..equals].trim();
+        if name.is_empty() {
+            return Err(format!("invalid derived label {:?}: empty name", s));
+        }
+        let template = parse_template(&s[equals + 1..])
+            .map_err(|error| format!("invalid derived label {:?}: {}", s, error))?;
+        Ok(Self {
+            name: name.to_string(),
+            template,
+        })
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Duplicate(name) => write!(f, "label {} is derived more than once", name),
+            Self::Cycle(names) => write!(
+                f,
+                "derived labels refer to each other in a cycle: {}",
+                names.join(" -> ")
+            ),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl DerivedLabels {
+    /// Construct a `DerivedLabels` from the rules in `config`.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if a label is derived by more than one rule, or if rules refer to each
+    /// other in a cycle.
+    pub fn new(config: Config) -> Result<Self, ConfigError> {
+        let mut rules = BTreeMap::new();
+        for rule in config.rules {
+            if rules.contains_key(&rule.name) {
+                return Err(ConfigError::Duplicate(rule.name));
+            }
+            rules.insert(rule.name.clone(), rule);
+        }
+
+        let mut ordered = Vec::with_capacity(rules.len());
+        let mut visited = BTreeSet::new();
+        let names: Vec<_> = rules.keys().cloned().collect();
+        for name in names {
+            visit(
+                &name,
+                &mut rules,
+                &mut visited,
+                &mut Vec::new(),
+                &mut ordered,
+            )?;
+        }
+
+        Ok(Self {
+            rules: ordered,
+            max_length: config.max_length,
+        })
+    }
+
+    /// Add the derived labels to `entry`'s metadata, replacing any existing values.
+    ///
+    /// Returns the number of labels added.
+    pub fn derive(&self, entry: &mut LogEntry) -> usize {
+        let mut added = 0;
+        for rule in &self.rules {
+            if let Some(mut value) = rule.render(&entry.metadata) {
+                truncate(&mut value, self.max_length);
+                entry.metadata.insert(rule.name.clone(), value);
+                added += 1;
+            }
+        }
+        added
+    }
+}
+
+/// Depth-first search from the rule `name`, appending rules to `ordered` after their dependencies.
+///
+/// `path` holds the rules currently being visited, so meeting one of them again means a cycle.
+fn visit(
+    name: &str,
+    rules: &mut BTreeMap<String, Rule>,
+    visited: &mut BTreeSet<String>,
+    path: &mut Vec<String>,
+    ordered: &mut Vec<Rule>,
+) -> Result<(), ConfigError> {
+    if let Some(start) = path.iter().position(|visiting| visiting == name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name.to_string());
+        return Err(ConfigError::Cycle(cycle));
+    }
+    if visited.contains(name) {
+        return Ok(());
+    }
+    let keys: Vec<String> = match rules.get(name) {
+        Some(rule) => rule.keys().map(str::to_string).collect(),
+        // Not a derived label, so it comes from the entry itself.
+        None => return Ok(()),
+    };
+
+    path.push(name.to_string());
+    for key in &keys {
+        visit(key, rules, visited, path, ordered)?;
+    }
+    path.pop();
+
+    visited.insert(name.to_string());
+    if let Some(rule) = rules.remove(name) {
+        ordered.push(rule);
+    }
+    Ok(())
+}
+
+fn parse_template(template: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut key = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some('{') | None => return Err("unclosed '{'".to_string()),
+                        Some(c) => key.push(c),
+                    }
+                }
+                if key.is_empty() {
+                    return Err("empty key".to_string());
+                }
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Key(key));
+            }
+            '}' => return Err("unmatched '}'".to_string()),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+/// Truncate `value` to at most `max_length` bytes, on a character boundary.
+fn truncate(value: &mut String, max_length: usize) {
+    if value.len() <= max_length {
+        return;
+    }
+    let mut end = max_length;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value.truncate(end);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::log_entry;
+
+    use super::{Config, ConfigError, DerivedLabels, Rule};
+
+    fn derived_labels(rules: &[&str], max_length: usize) -> Result<DerivedLabels, ConfigError> {
+        DerivedLabels::new(Config {
+            rules: rules.iter().map(|rule| rule.parse().unwrap()).collect(),
+            max_length,
+        })
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule: Rule = "service = {namespace}/{container_name}".parse().unwrap();
+        assert_eq!(rule.name(), "service");
+        assert_eq!(
+            rule.keys().collect::<Vec<_>>(),
+            vec!["namespace", "container_name"]
+        );
+
+        assert!("service".parse::<Rule>().is_err());
+        assert!("={namespace}".parse::<Rule>().is_err());
+        assert!("service={namespace".parse::<Rule>().is_err());
+        assert!("service={}".parse::<Rule>().is_err());
+        assert!("service=namespace}".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn derive_labels() {
+        let derived = derived_labels(
+            &[
+                "id={service}:{pod_name}",
+                "service={namespace}/{container_name}",
+                "braces={{{namespace}}}",
+            ],
+            256,
+        )
+        .unwrap();
+
+        let mut entry = log_entry(
+            "hello",
+            &[
+                ("namespace", "payments"),
+                ("container_name", "api"),
+                ("pod_name", "api-0"),
+            ],
+        );
+        assert_eq!(derived.derive(&mut entry), 3);
+        assert_eq!(entry.metadata["service"], "payments/api");
+        assert_eq!(entry.metadata["id"], "payments/api:api-0");
+        assert_eq!(entry.metadata["braces"], "{payments}");
+
+        // Rules missing a key are skipped, as are rules depending on them.
+        let mut entry = log_entry("hello", &[("namespace", "payments"), ("pod_name", "api-0")]);
+        assert_eq!(derived.derive(&mut entry), 1);
+        assert!(!entry.metadata.contains_key("service"));
+        assert!(!entry.metadata.contains_key("id"));
+    }
+
+    #[test]
+    fn truncate_values() {
+        let derived = derived_labels(&["long={a}{a}"], 5).unwrap();
+        let mut entry = log_entry("hello", &[("a", "ééé")]);
+        derived.derive(&mut entry);
+        assert_eq!(entry.metadata["long"], "éé");
+    }
+
+    #[test]
+    fn reject_cycles() {
+        assert_eq!(
+            derived_labels(&["a={b}", "b={c}-{a}"], 256).unwrap_err(),
+            ConfigError::Cycle(vec!["a".to_string(), "b".to_string(), "a".to_string()])
+        );
+        assert_eq!(
+            derived_labels(&["a={a}"], 256).unwrap_err(),
+            ConfigError::Cycle(vec!["a".to_string(), "a".to_string()])
+        );
+        assert_eq!(
+            derived_labels(&["a=x", "a=y"], 256).unwrap_err(),
+            ConfigError::Duplicate("a".to_string())
+        );
+    }
+}
@@ -1,9 +1,10 @@
 // src/log_collector/watcher/inotify.rs
 //! [`Watcher`] implementation for linux, based on `inotify`.
 use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::Path;
 
-use inotify::{Inotify, WatchDescriptor, WatchMask};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
 
 use crate::log_collector::watcher;
 
@@ -14,17 +15,31 @@ type Descriptor = WatchDescriptor;
 impl watcher::Descriptor for Descriptor {}
 
 #[derive(Debug)]
-pub(super) struct Event(WatchDescriptor);
+pub(super) struct Event {
+    descriptor: WatchDescriptor,
+    removed: bool,
+}
 
 impl watcher::Event<Descriptor> for Event {
     fn descriptor(&self) -> &Descriptor {
-        &self.0
+        &self.descriptor
+    }
+
+    fn removed(&self) -> bool {
+        self.removed
     }
 }
 
 impl<S> From<inotify::Event<S>> for Event {
     fn from(inotify_event: inotify::Event<S>) -> Self {
-        Self(inotify_event.wd)
+        // `IGNORED` follows `DELETE_SELF`, once the watch has been removed.
+        let removed = inotify_event
+            .mask
+            .intersects(EventMask::MOVE_SELF | EventMask::DELETE_SELF | EventMask::IGNORED);
+        Self {
+            descriptor: inotify_event.wd,
+            removed,
+        }
     }
 }
 
@@ -46,7 +61,7 @@ impl watcher::Watcher for Watcher {
         })
     }
 
-    /// Watch a directory for newly created files.
+    /// Watch a directory for newly created files, including files moved into it.
     ///
     /// # Callee responsibilities
     ///
@@ -61,13 +76,14 @@ impl watcher::Watcher for Watcher {
     ///
     /// Propagates any `io::Error` caused when attempting to register the watch.
     fn watch_directory(&mut self, path: &Path) -> io::Result<Self::Descriptor> {
-        let descriptor = self
-            .inner
-            .add_watch(path, WatchMask::CREATE | WatchMask::DONT_FOLLOW)?;
+        let descriptor = self.inner.add_watch(
+            path,
+            WatchMask::CREATE | WatchMask::MOVED_TO | WatchMask::DONT_FOLLOW,
+        )?;
         Ok(descriptor)
     }
 
-    /// Watch a file for writes.
+    /// Watch a file for writes, renames, and deletion.
     ///
     /// # Callee responsibilities
     ///
@@ -82,12 +98,20 @@ impl watcher::Watcher for Watcher {
     ///
     /// Propagates any `io::Error` caused when attempting to register the watch.
     fn watch_file(&mut self, path: &Path) -> io::Result<Self::Descriptor> {
-        let descriptor = self
-            .inner
-            .add_watch(path, WatchMask::MODIFY | WatchMask::DONT_FOLLOW)?;
+        let descriptor = self.inner.add_watch(
+            path,
+            WatchMask::MODIFY
+                | WatchMask::MOVE_SELF
+                | WatchMask::DELETE_SELF
+                | WatchMask::DONT_FOLLOW,
+        )?;
         Ok(descriptor)
     }
 
+    fn unwatch(&mut self, descriptor: &Self::Descriptor) -> io::Result<()> {
+        self.inner.rm_watch(descriptor.clone())
+    }
+
     fn read_events(&mut self) -> io::Result<Vec<Self::Event>> {
         let inotify_events = self.inner.read_events(&mut self.buffer)?;
         Ok(inotify_events.map(Event::from).collect())
@@ -97,4 +121,8 @@ impl watcher::Watcher for Watcher {
         let inotify_events = self.inner.read_events_blocking(&mut self.buffer)?;
         Ok(inotify_events.map(Event::from).collect())
     }
+
+    fn readiness_fd(&self) -> Option<RawFd> {
+        Some(self.inner.as_raw_fd())
+    }
 }
@@ -2,7 +2,7 @@
 /// [`Watcher`] implementation for `MacOS`, based on `kqueue`.
 use std::fs::File;
 use std::io;
-use std::os::unix::io::{IntoRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::path::Path;
 use std::time::Duration;
 
@@ -22,13 +22,23 @@ impl watcher::Event<Descriptor> for Event {
     /// # Panics
     ///
     /// This will panic if the event's flags don't correspond with the filters supplied in
-    /// [`Watcher::add_watch`], e.g. if the event is not for a file, or it is not a write event.
+    /// [`Watcher::add_watch`], e.g. if the event is not for a file, or it is not a write, delete, or
+    /// rename event.
     fn descriptor(&self) -> &Descriptor {
         match (&self.ident, &self.data) {
-            (Ident::Fd(fd), EventData::Vnode(Vnode::Write)) => fd,
+            (Ident::Fd(fd), EventData::Vnode(Vnode::Write))
+            | (Ident::Fd(fd), EventData::Vnode(Vnode::Delete))
+            | (Ident::Fd(fd), EventData::Vnode(Vnode::Rename)) => fd,
             _ => panic!("kqueue returned an unexpected event: {:?}", self),
         }
     }
+
+    fn removed(&self) -> bool {
+        matches!(
+            self.data,
+            EventData::Vnode(Vnode::Delete) | EventData::Vnode(Vnode::Rename)
+        )
+    }
 }
 
 pub(super) struct Watcher {
@@ -42,7 +52,8 @@ impl Watcher {
     /// files and directories is to register the `EVFILT_VNODE` and `NOTE_WRITE` flags, which is
     /// described as "A write occurred on the file referenced by the descriptor.".
     /// Observationally this seems to correspond with what we want: events for files created
-    /// in watched directories, and writes to watched files.
+    /// in watched directories, and writes to watched files. Files are also registered with
+    /// `NOTE_DELETE` and `NOTE_RENAME` (see [`Watcher::watch_file`]), to detect rotation.
     ///
     /// # Callee responsibilities
     ///
@@ -55,12 +66,15 @@ impl Watcher {
     /// # Errors
     ///
     /// Propagates any `io::Error` caused when attempting to register the watch.
-    fn add_watch(&mut self, path: &Path) -> io::Result<<Self as watcher::Watcher>::Descriptor> {
+    fn add_watch(
+        &mut self,
+        path: &Path,
+        flags: FilterFlag,
+    ) -> io::Result<<Self as watcher::Watcher>::Descriptor> {
         let file = File::open(path)?;
         let fd = file.into_raw_fd();
 
-        self.inner
-            .add_fd(fd, EventFilter::EVFILT_VNODE, FilterFlag::NOTE_WRITE)?;
+        self.inner.add_fd(fd, EventFilter::EVFILT_VNODE, flags)?;
         self.inner.watch()?;
 
         Ok(fd)
@@ -89,10 +103,10 @@ impl watcher::Watcher for Watcher {
     ///
     /// Propagates any `io::Error` caused when attempting to register the watch.
     fn watch_directory(&mut self, path: &Path) -> io::Result<Self::Descriptor> {
-        self.add_watch(path)
+        self.add_watch(path, FilterFlag::NOTE_WRITE)
     }
 
-    /// Watch a file for writes.
+    /// Watch a file for writes, renames, and deletion.
     ///
     /// # Caller responsibilities
     ///
@@ -105,7 +119,26 @@ impl watcher::Watcher for Watcher {
     ///
     /// Propagates any `io::Error` caused when attempting to register the watch.
     fn watch_file(&mut self, path: &Path) -> io::Result<Self::Descriptor> {
-        self.add_watch(path)
+        self.add_watch(
+            path,
+            FilterFlag::NOTE_WRITE | FilterFlag::NOTE_DELETE | FilterFlag::NOTE_RENAME,
+        )
+    }
+
+    /// Stop watching a file or directory, closing the file descriptor opened by
+    /// [`Watcher::add_watch`].
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` caused when attempting to remove the watch.
+    fn unwatch(&mut self, descriptor: &Self::Descriptor) -> io::Result<()> {
+        self.inner
+            .remove_fd(*descriptor, EventFilter::EVFILT_VNODE)?;
+        self.inner.watch()?;
+
+        // Safety: `descriptor` was opened by `add_watch`, and is no longer used by `inner`.
+        drop(unsafe { File::from_raw_fd(*descriptor) });
+        Ok(())
     }
 
     fn read_events(&mut self) -> io::Result<Vec<Self::Event>> {
@@ -117,4 +150,8 @@ impl watcher::Watcher for Watcher {
         let kq_event = self.inner.iter().next();
         Ok(kq_event.into_iter().collect())
     }
+
+    fn readiness_fd(&self) -> Option<RawFd> {
+        Some(self.inner.as_raw_fd())
+    }
 }
@@ -1,7 +1,7 @@
 // src/log_collector/watcher/mock.rs
 //! Mock [`Watcher`](crate::log_collector::watcher::Watcher) implementation.
 use std::cell::RefCell;
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::io;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
@@ -20,19 +20,23 @@ impl watcher::Descriptor for Descriptor {}
 
 /// The event type for [`Watcher`].
 ///
-/// This is the most trivial way that we can represent events. Since the only thing we need from a
-/// [`watcher::Event`](crate::log_collector::watcher::Event) is a
-/// [`watcher::Descriptor`](crate::log_collector::watcher::Descriptor), we can just use the same
-/// representation as [`Descriptor`].
-type Event = PathBuf;
+/// This is the most trivial way that we can represent events: the only things we need from a
+/// [`watcher::Event`](crate::log_collector::watcher::Event) are its
+/// [`watcher::Descriptor`](crate::log_collector::watcher::Descriptor), and whether the watched file
+/// was removed.
+#[derive(Debug)]
+pub(crate) struct Event {
+    descriptor: Descriptor,
+    removed: bool,
+}
 
 impl watcher::Event<Descriptor> for Event {
-    /// Get the descriptor for this event.
-    ///
-    /// For this implementation, the `Event` and `Descriptor` have the same representation, so this
-    /// is exactly `&self`.
     fn descriptor(&self) -> &Descriptor {
-        &self
+        &self.descriptor
+    }
+
+    fn removed(&self) -> bool {
+        self.removed
     }
 }
 
@@ -47,7 +51,7 @@ pub(crate) struct Watcher {
 /// The inner-type of [`Watcher`] that maintains the list of watched paths and pushed events.
 struct Mock {
     watched_paths: Vec<PathBuf>,
-    pending_events: Vec<PathBuf>,
+    pending_events: Vec<Event>,
 }
 
 impl Watcher {
@@ -78,7 +82,7 @@ impl Watcher {
 
         let path = dir_path.join("test.log");
         File::create(&path)?;
-        self.mock.borrow_mut().pending_events.push(dir_path.clone());
+        self.push_event(dir_path, false);
 
         Ok(path)
     }
@@ -101,10 +105,59 @@ impl Watcher {
         );
 
         write!(OpenOptions::new().append(true).open(path)?, "{}", text)?;
-        self.mock.borrow_mut().pending_events.push(path.clone());
+        self.push_event(path, false);
 
         Ok(())
     }
+
+    /// Simulate a watched file being renamed (e.g. rotated) to `new_path`.
+    ///
+    /// An event for the file, marked as [`removed`](watcher::Event::removed), is pushed for later
+    /// collection.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the given `path` is not in `watched_paths`.
+    pub(crate) fn simulate_rename(&mut self, path: &PathBuf, new_path: &Path) -> io::Result<()> {
+        assert!(
+            self.mock.borrow().watched_paths.contains(path),
+            "Can't simulate rename of unwatched path: {:?}",
+            path
+        );
+
+        fs::rename(path, new_path)?;
+        self.push_event(path, true);
+
+        Ok(())
+    }
+
+    /// Simulate a watched file being deleted.
+    ///
+    /// An event for the file, marked as [`removed`](watcher::Event::removed), is pushed for later
+    /// collection.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the given `path` is not in `watched_paths`.
+    pub(crate) fn simulate_remove(&mut self, path: &PathBuf) -> io::Result<()> {
+        assert!(
+            self.mock.borrow().watched_paths.contains(path),
+            "Can't simulate removal of unwatched path: {:?}",
+            path
+        );
+
+        fs::remove_file(path)?;
+        self.push_event(path, true);
+
+        Ok(())
+    }
+
+    fn push_event(&mut self, descriptor: &PathBuf, removed: bool) {
+        self.mock.borrow_mut().pending_events.push(Event {
+            descriptor: descriptor.clone(),
+            removed,
+        });
+    }
 }
 
 impl Clone for Watcher {
@@ -117,7 +170,7 @@ impl Clone for Watcher {
 
 impl watcher::Watcher for Watcher {
     type Descriptor = PathBuf;
-    type Event = PathBuf;
+    type Event = Event;
 
     fn new() -> io::Result<Self> {
         Ok(Self::new())
@@ -193,6 +246,26 @@ impl watcher::Watcher for Watcher {
         Ok(canonical_path)
     }
 
+    /// Stop watching a file or directory.
+    ///
+    /// This forgets that `descriptor` has been watched, and drops any pending events for it.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `descriptor` is not being watched.
+    fn unwatch(&mut self, descriptor: &Self::Descriptor) -> io::Result<()> {
+        let mock = &mut *self.mock.borrow_mut();
+        let index = mock
+            .watched_paths
+            .iter()
+            .position(|path| path == descriptor)
+            .unwrap_or_else(|| panic!("called unwatch with unwatched path {:?}", descriptor));
+        mock.watched_paths.remove(index);
+        mock.pending_events
+            .retain(|event| &event.descriptor != descriptor);
+        Ok(())
+    }
+
     /// Read some events about the registered directories and files.
     ///
     /// This pops whatever [`Event`]s have been supplied through [`push_event`].
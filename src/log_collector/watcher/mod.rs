@@ -19,7 +19,10 @@ pub(crate) mod mock;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::io;
+use std::os::unix::io::RawFd;
 use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[cfg(target_os = "linux")]
 use self::inotify as imp;
@@ -27,6 +30,9 @@ use self::inotify as imp;
 #[cfg(target_os = "macos")]
 use self::kqueue as imp;
 
+/// How often [`Watcher::read_events_timeout`] checks for events.
+pub(super) const TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
 pub(super) fn watcher() -> io::Result<impl Watcher> {
     imp::Watcher::new()
 }
@@ -40,10 +46,16 @@ pub(super) trait Descriptor: Clone + Debug + Eq + Hash + PartialEq + Send {}
 
 /// A platform-agnostic interface to file system events.
 ///
-/// This currently only exposes the `Descriptor` of the registered watch. Clients can use this to
-/// to correlate events with the corresponding `watch_*` call.
+/// This exposes the `Descriptor` of the registered watch, which clients can use to correlate
+/// events with the corresponding `watch_*` call, and whether a watched file was removed.
 pub(super) trait Event<D: Descriptor>: Debug {
     fn descriptor(&self) -> &D;
+
+    /// Whether the watched file was renamed or deleted (e.g. when it was rotated).
+    ///
+    /// Further writes to the file aren't expected, and a new file may replace it at its path. The
+    /// watch may already have been removed, in which case no more events are emitted for it.
+    fn removed(&self) -> bool;
 }
 
 /// A platform-agnostic file and directory watching API.
@@ -51,7 +63,8 @@ pub(super) trait Event<D: Descriptor>: Debug {
 /// This API is intended to be used to drive log collectors, specifically:
 ///
 /// - Generate events when new files are added to a directory (see [`Self::watch_directory`]).
-/// - Generate events when new content is written to a file (see [`Self::watch_file`]).
+/// - Generate events when new content is written to a file, or the file is renamed or deleted (see
+///   [`Self::watch_file`]).
 ///
 /// The API is necessarily very 'lowest common denominator', and leaves a lot of behaviour
 /// implementation-defined. See the notes on callee responsibilities in [`Self::watch_directory`]
@@ -82,7 +95,7 @@ pub(super) trait Watcher {
     /// Watch a directory for newly created files.
     ///
     /// Calling this function should cause the target `Watcher` to emit [`Event`]s whenever a file
-    /// is created in the directory at the given `path`.
+    /// is created in (or moved into) the directory at the given `path`.
     ///
     /// # Callee responsibilities
     ///
@@ -101,10 +114,11 @@ pub(super) trait Watcher {
     /// Propagates any `io::Error` caused when attempting to register the watch.
     fn watch_directory(&mut self, path: &Path) -> io::Result<Self::Descriptor>;
 
-    /// Watch a file for writes.
+    /// Watch a file for writes, renames, and deletion.
     ///
     /// Calling this function should cause the target `Watcher` to emit [`Event`]s whenever the file
-    /// at the given `path` is written to.
+    /// at the given `path` is written to, and [`removed`](Event::removed) events when it's renamed
+    /// or deleted.
     ///
     /// # Callee responsibilities
     ///
@@ -123,6 +137,17 @@ pub(super) trait Watcher {
     /// Propagates any `io::Error` caused when attempting to register the watch.
     fn watch_file(&mut self, path: &Path) -> io::Result<Self::Descriptor>;
 
+    /// Stop watching a file or directory.
+    ///
+    /// No more events should be emitted for `descriptor`, although events that were already
+    /// pending may still be. The path may be watched again later, possibly with a different
+    /// `Descriptor`.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` caused when attempting to remove the watch.
+    fn unwatch(&mut self, descriptor: &Self::Descriptor) -> io::Result<()>;
+
     /// Read some events about the registered directories and files.
     ///
     /// This must never block, and should just return an empty `Vec` if no events are ready.
@@ -140,6 +165,36 @@ pub(super) trait Watcher {
     ///
     /// Propagates any `io::Error` caused when attempting to read events.
     fn read_events_blocking(&mut self) -> io::Result<Vec<Self::Event>>;
+
+    /// Read some events about the registered directories and files, waiting up to `timeout` for
+    /// any to occur.
+    ///
+    /// This returns an empty `Vec` if no events occur before `timeout`. The default implementation
+    /// checks for events with [`read_events`](Self::read_events) every 50ms.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` caused when attempting to read events.
+    fn read_events_timeout(&mut self, timeout: Duration) -> io::Result<Vec<Self::Event>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let events = self.read_events()?;
+            let now = Instant::now();
+            if !events.is_empty() || now >= deadline {
+                return Ok(events);
+            }
+            thread::sleep(TIMEOUT_CHECK_INTERVAL.min(deadline - now));
+        }
+    }
+
+    /// A file descriptor that becomes readable when events are ready, if the watcher has one.
+    ///
+    /// This allows waiting for events asynchronously, then reading them with
+    /// [`read_events`](Self::read_events). Without one, callers must check for events periodically.
+    /// The default implementation returns `None`.
+    fn readiness_fd(&self) -> Option<RawFd> {
+        None
+    }
 }
 
 /// Tests for the `target_os`' `Watcher` implementation.
@@ -148,7 +203,7 @@ pub(super) trait Watcher {
 /// work for either).
 #[cfg(test)]
 mod tests {
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::io::Write;
 
     use super::{imp, Event, Watcher as _};
@@ -193,4 +248,25 @@ mod tests {
         let event_descriptors: Vec<_> = events.iter().map(Event::descriptor).collect();
         assert_eq!(event_descriptors, vec![&descriptor]);
     }
+
+    #[test]
+    fn watch_file_removal_events() {
+        let tempdir = tempfile::tempdir().expect("unable to create tempdir");
+        let file_path = tempdir.path().join("test.log");
+        File::create(&file_path).expect("failed to create temp file");
+
+        let mut watcher = imp::Watcher::new().expect("unable to create watcher");
+        let descriptor = watcher
+            .watch_file(&file_path)
+            .expect("unable to watch file");
+
+        fs::rename(&file_path, tempdir.path().join("test.log.1")).expect("unable to rename file");
+
+        let events = watcher
+            .read_events_blocking()
+            .expect("failed to read events");
+        assert!(events
+            .iter()
+            .any(|event| event.descriptor() == &descriptor && event.removed()));
+    }
 }
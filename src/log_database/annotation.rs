@@ -0,0 +1,178 @@
+// src/log_database/annotation.rs
+//! Annotations on entries in the log [`Database`](super::Database).
+//!
+//! An annotation attaches tags and a note to either a single entry, identified by the ID returned
+//! by [`Database::query_entries`](super::Database::query_entries), or to a time range on the
+//! streams matching a selector (e.g. the window of an incident). Annotations are kept in a sidecar
+//! file in the data directory, so log files are never modified, and are returned alongside the
+//! results of queries on the streams they apply to.
+//!
+//! Annotations on an entry are removed along with its log file (e.g. by retention).
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::Cursor;
+
+/// The name of the file in the data directory in which annotations are persisted.
+pub(super) const ANNOTATIONS_FILE_NAME: &str = "annotations";
+
+/// Tags and a note attached to an entry or time range.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Annotation {
+    /// Identifies the annotation. This is assigned when the annotation is added.
+    #[serde(default)]
+    pub id: u64,
+
+    /// What the annotation applies to.
+    #[serde(flatten)]
+    pub target: Target,
+
+    /// Short labels for the annotation (e.g. `incident-start`).
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// A free-text note.
+    #[serde(default)]
+    pub note: String,
+
+    /// Who added the annotation.
+    #[serde(default)]
+    pub author: String,
+
+    /// When the annotation was added, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub created_at: u64,
+}
+
+/// What an [`Annotation`] applies to.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum Target {
+    /// A single entry.
+    Entry {
+        /// The entry's ID (see [`Entry::id`](super::Entry::id)).
+        entry: String,
+    },
+
+    /// A time range on the streams matching a selector.
+    Range {
+        /// The `(key, value)` pairs that a stream's metadata must contain to be annotated.
+        selector: BTreeMap<String, String>,
+
+        /// The start of the range, in seconds since the Unix epoch.
+        start: u64,
+
+        /// The end of the range (inclusive), in seconds since the Unix epoch.
+        end: u64,
+    },
+}
+
+/// The annotations on a database.
+#[derive(Debug, Default)]
+pub(super) struct Annotations {
+    annotations: Vec<Annotation>,
+    next_id: u64,
+}
+
+impl Annotations {
+    /// Restore annotations from `data_directory`, if any have been persisted.
+    pub(super) fn load(data_directory: &Path) -> io::Result<Self> {
+        let path = data_directory.join(ANNOTATIONS_FILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let annotations: Vec<Annotation> = serde_json::from_slice(&fs::read(&path)?)?;
+        let next_id = annotations.iter().map(|annotation| annotation.id + 1).max();
+        Ok(Self {
+            annotations,
+            next_id: next_id.unwrap_or_default(),
+        })
+    }
+
+    pub(super) fn all(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /// The annotations on the log file `key`, which has the given `metadata`.
+    pub(super) fn for_stream<'a>(
+        &'a self,
+        key: &'a str,
+        metadata: &'a HashMap<String, String>,
+    ) -> impl Iterator<Item = &'a Annotation> {
+        self.annotations
+            .iter()
+            .filter(move |annotation| match &annotation.target {
+                Target::Entry { entry } => entry_segment(entry) == Some(key),
+                Target::Range { selector, .. } => selector
+                    .iter()
+                    .all(|(name, value)| metadata.get(name) == Some(value)),
+            })
+    }
+
+    /// Add `annotation`, assigning its ID and creation time, and return the stored annotation.
+    pub(super) fn add(
+        &mut self,
+        data_directory: &Path,
+        mut annotation: Annotation,
+    ) -> io::Result<Annotation> {
+        annotation.id = self.next_id;
+        annotation.created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default();
+
+        self.next_id += 1;
+        self.annotations.push(annotation.clone());
+        self.save(data_directory)?;
+        Ok(annotation)
+    }
+
+    pub(super) fn remove(
+        &mut self,
+        data_directory: &Path,
+        id: u64,
+    ) -> io::Result<Option<Annotation>> {
+        let position = match self
+            .annotations
+            .iter()
+            .position(|annotation| annotation.id == id)
+        {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        let annotation = self.annotations.remove(position);
+        self.save(data_directory)?;
+        Ok(Some(annotation))
+    }
+
+    /// Remove the annotations on entries in the log file `key`, once it has been removed.
+    pub(super) fn remove_stream(&mut self, data_directory: &Path, key: &str) -> io::Result<()> {
+        let len = self.annotations.len();
+        self.annotations
+            .retain(|annotation| match &annotation.target {
+                Target::Entry { entry } => entry_segment(entry) != Some(key),
+                Target::Range { .. } => true,
+            });
+        if self.annotations.len() == len {
+            return Ok(());
+        }
+        self.save(data_directory)
+    }
+
+    fn save(&self, data_directory: &Path) -> io::Result<()> {
+        fs::write(
+            data_directory.join(ANNOTATIONS_FILE_NAME),
+            serde_json::to_vec(&self.annotations)?,
+        )
+    }
+}
+
+/// The log file of the entry with ID `entry`, if it's a valid ID.
+fn entry_segment(entry: &str) -> Option<&str> {
+    entry.parse::<Cursor>().ok()?;
+    entry.splitn(2, '.').next()
+}
@@ -0,0 +1,146 @@
+// src/log_database/bloom.rs
+//! Bloom filters over the tokenised content of log files.
+//!
+//! Each log file has a bloom filter containing every token of every line written to it. A search
+//! term can only appear in a line if all the complete tokens in the term appear in that line, so a
+//! log file whose filter is missing any of the term's complete tokens can be skipped entirely.
+//!
+//! Tokens are maximal runs of alphanumeric characters (and `_`). Tokens at the start or end of a
+//! search term may be partial (e.g. `rror` in `error`), so they are not checked.
+
+use std::convert::TryInto;
+
+/// The number of bits in each filter.
+///
+/// At 4KiB per log file this is cheap to keep in memory, and keeps the false positive rate below
+/// 1% for up to ~3,000 distinct tokens per file.
+const FILTER_BITS: usize = 1 << 15;
+
+const FILTER_WORDS: usize = FILTER_BITS / 64;
+
+/// The number of bit positions set for each token.
+///
+/// Each position is taken from 4 bytes of the token's MD5 digest, so there can be at most 4.
+const HASHES: usize = 4;
+
+/// A fixed-size bloom filter of tokens.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct BloomFilter {
+    words: Vec<u64>,
+}
+
+impl BloomFilter {
+    pub(super) fn new() -> Self {
+        Self {
+            words: vec![0; FILTER_WORDS],
+        }
+    }
+
+    /// Restore a filter from the output of [`to_bytes`](Self::to_bytes).
+    ///
+    /// Returns `None` if `bytes` is not a valid filter (e.g. if it was written with a different
+    /// filter size).
+    pub(super) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != FILTER_WORDS * 8 {
+            return None;
+        }
+
+        let words = bytes
+            .chunks_exact(8)
+            // `unwrap` is OK since `chunks_exact` guarantees the length.
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        Some(Self { words })
+    }
+
+    pub(super) fn to_bytes(&self) -> Vec<u8> {
+        self.words
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    /// Add every token in `line` to the filter.
+    pub(super) fn insert_line(&mut self, line: &str) {
+        for token in tokens(line) {
+            for bit in Self::bits(token) {
+                self.words[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+    }
+
+    /// Check whether a line added to the filter might contain `term`.
+    ///
+    /// A `false` result is definitive, but `true` results may be false positives.
+    pub(super) fn may_contain(&self, term: &str) -> bool {
+        complete_tokens(term).all(|token| {
+            Self::bits(token)
+                .iter()
+                .all(|bit| self.words[bit / 64] & (1 << (bit % 64)) != 0)
+        })
+    }
+
+    fn bits(token: &str) -> [usize; HASHES] {
+        let digest = md5::compute(token);
+        let mut bits = [0; HASHES];
+        for (bit, bytes) in bits.iter_mut().zip(digest.chunks_exact(4)) {
+            // `unwrap` is OK since `chunks_exact` guarantees the length.
+            let hash = u32::from_le_bytes(bytes.try_into().unwrap());
+            *bit = hash as usize % FILTER_BITS;
+        }
+        bits
+    }
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn tokens(input: &str) -> impl Iterator<Item = &str> {
+    input
+        .split(|c: char| !is_token_char(c))
+        .filter(|token| !token.is_empty())
+}
+
+/// The tokens in `term` that must appear in full in any line containing `term`.
+fn complete_tokens(term: &str) -> impl Iterator<Item = &str> {
+    let starts_partial = term.chars().next().map_or(false, is_token_char);
+    let ends_partial = term.chars().last().map_or(false, is_token_char);
+
+    let tokens: Vec<_> = tokens(term).collect();
+    let start = usize::from(starts_partial);
+    let end = if ends_partial {
+        tokens.len().saturating_sub(1)
+    } else {
+        tokens.len()
+    };
+
+    tokens.into_iter().take(end).skip(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BloomFilter;
+
+    #[test]
+    fn may_contain() {
+        let mut filter = BloomFilter::new();
+        filter.insert_line("connection refused by upstream");
+
+        assert!(filter.may_contain("refused"));
+        assert!(filter.may_contain("ion refused by ups"));
+        assert!(filter.may_contain(" refused "));
+        assert!(!filter.may_contain(" accepted "));
+        assert!(!filter.may_contain("connection accepted by upstream"));
+    }
+
+    #[test]
+    fn roundtrip_bytes() {
+        let mut filter = BloomFilter::new();
+        filter.insert_line("hello world");
+
+        let restored = BloomFilter::from_bytes(&filter.to_bytes());
+        assert_eq!(restored, Some(filter));
+        assert_eq!(BloomFilter::from_bytes(b"nonsense"), None);
+    }
+}
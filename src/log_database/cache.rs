@@ -0,0 +1,118 @@
+// src/log_database/cache.rs
+//! An LRU cache of query results for the log [`Database`](super::Database).
+//!
+//! Results are keyed by the query's `(key, value)` pair. Since a query for `(key, value)` only
+//! reads log files whose metadata includes that pair, writing to a log file only needs to
+//! invalidate the cached results for the pairs in that file's metadata.
+
+use std::collections::HashMap;
+
+/// A cache of recent query results, evicting the least recently used results when full.
+#[derive(Debug)]
+pub(super) struct QueryCache {
+    capacity: usize,
+    clock: u64,
+    entries: HashMap<(String, String), CacheEntry>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    lines: Vec<String>,
+    last_used: u64,
+}
+
+impl QueryCache {
+    /// Construct an empty cache holding results for at most `capacity` queries.
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            entries: HashMap::with_capacity(capacity),
+        }
+    }
+
+    pub(super) fn get(&mut self, key: &str, value: &str) -> Option<Vec<String>> {
+        self.clock += 1;
+        let clock = self.clock;
+
+        // We can't look up a `(String, String)` key with `(&str, &str)`, so we have to allocate.
+        let entry = self
+            .entries
+            .get_mut(&(key.to_string(), value.to_string()))?;
+        entry.last_used = clock;
+        Some(entry.lines.clone())
+    }
+
+    pub(super) fn insert(&mut self, key: &str, value: &str, lines: Vec<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let cache_key = (key.to_string(), value.to_string());
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&cache_key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(cache_key, _)| cache_key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        self.entries.insert(
+            cache_key,
+            CacheEntry {
+                lines,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    /// Invalidate any results that could include lines from a log file with the given `metadata`.
+    pub(super) fn invalidate(&mut self, metadata: &HashMap<String, String>) {
+        if self.entries.is_empty() {
+            return;
+        }
+        for (key, value) in metadata {
+            self.entries.remove(&(key.clone(), value.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::QueryCache;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = QueryCache::new(2);
+
+        cache.insert("a", "1", vec!["a1".to_string()]);
+        cache.insert("b", "1", vec!["b1".to_string()]);
+        assert!(cache.get("a", "1").is_some());
+
+        cache.insert("c", "1", vec!["c1".to_string()]);
+        assert_eq!(cache.get("a", "1"), Some(vec!["a1".to_string()]));
+        assert_eq!(cache.get("b", "1"), None);
+        assert_eq!(cache.get("c", "1"), Some(vec!["c1".to_string()]));
+    }
+
+    #[test]
+    fn invalidates_by_metadata() {
+        let mut cache = QueryCache::new(2);
+
+        cache.insert("a", "1", vec!["a1".to_string()]);
+        cache.insert("b", "1", vec!["b1".to_string()]);
+
+        let mut metadata = HashMap::new();
+        metadata.insert("a".to_string(), "1".to_string());
+        cache.invalidate(&metadata);
+
+        assert_eq!(cache.get("a", "1"), None);
+        assert_eq!(cache.get("b", "1"), Some(vec!["b1".to_string()]));
+    }
+}
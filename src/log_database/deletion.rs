@@ -0,0 +1,77 @@
+// src/log_database/deletion.rs
+//! Deleting streams from the log [`Database`](super::Database) by selector.
+//!
+//! Deletion can't be undone, so [`Database::delete`](super::Database::delete) can also be run as a
+//! dry run, which reports the [`Impact`] of the deletion without removing anything. Streams subject
+//! to a legal hold are never deleted, and are reported separately.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+
+use super::DATA_FILE_RECORD_SEPARATOR;
+
+/// The streams, entries, and bytes removed (or that would be removed) by a deletion.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct Impact {
+    /// Whether this was a dry run, in which case nothing was removed.
+    pub dry_run: bool,
+
+    /// The streams that were (or would be) removed, ordered by their metadata.
+    pub streams: Vec<StreamImpact>,
+
+    /// The metadata of matching streams that were skipped because of a legal hold.
+    pub held: Vec<BTreeMap<String, String>>,
+
+    /// The total number of entries in `streams`.
+    pub entries: usize,
+
+    /// The total size of `streams`' data files, in bytes.
+    pub bytes: u64,
+}
+
+/// A single stream removed (or that would be removed) by a deletion.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct StreamImpact {
+    /// The stream's metadata.
+    pub metadata: BTreeMap<String, String>,
+
+    /// The number of entries in the stream.
+    pub entries: usize,
+
+    /// The size of the stream's data file, in bytes.
+    pub bytes: u64,
+}
+
+impl Impact {
+    pub(super) fn add(&mut self, stream: StreamImpact) {
+        self.entries += stream.entries;
+        self.bytes += stream.bytes;
+        self.streams.push(stream);
+    }
+}
+
+/// Count the entries in the data `file`, returning the count and the file's size.
+pub(super) fn count_entries(mut file: &File) -> io::Result<(usize, u64)> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(file);
+    let mut separators = 0;
+    let mut bytes = 0;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        separators += buffer
+            .iter()
+            .filter(|byte| **byte == DATA_FILE_RECORD_SEPARATOR)
+            .count();
+        let len = buffer.len();
+        bytes += len as u64;
+        reader.consume(len);
+    }
+
+    // Separators are only written between records.
+    let entries = if bytes == 0 { 0 } else { separators + 1 };
+    Ok((entries, bytes))
+}
@@ -0,0 +1,75 @@
+// src/log_database/filter.rs
+//! Filters for the content of log lines.
+
+use regex::Regex;
+
+/// A filter that log lines must match to be returned by
+/// [`Database::query_filtered`](super::Database::query_filtered).
+#[derive(Clone, Debug)]
+pub enum LineFilter {
+    /// Match lines containing the given text.
+    ///
+    /// These filters can use the database's bloom filters to skip log files that cannot contain
+    /// the text.
+    Contains(String),
+
+    /// Match lines matching the given regular expression.
+    Regex(Regex),
+
+    /// Match lines that don't match the given filter.
+    Not(Box<LineFilter>),
+}
+
+impl LineFilter {
+    /// Check whether `line` matches this filter.
+    #[must_use]
+    pub fn matches(&self, line: &str) -> bool {
+        match self {
+            Self::Contains(term) => line.contains(term.as_str()),
+            Self::Regex(regex) => regex.is_match(line),
+            Self::Not(filter) => !filter.matches(line),
+        }
+    }
+
+    /// Text that any matching line must contain, if known.
+    pub(super) fn required_text(&self) -> Option<&str> {
+        match self {
+            Self::Contains(term) => Some(term),
+            Self::Regex(_) | Self::Not(_) => None,
+        }
+    }
+}
+
+impl PartialEq for LineFilter {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Contains(term), Self::Contains(other)) => term == other,
+            (Self::Regex(regex), Self::Regex(other)) => regex.as_str() == other.as_str(),
+            (Self::Not(filter), Self::Not(other)) => filter == other,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::LineFilter;
+
+    #[test]
+    fn matches() {
+        let filter = LineFilter::Contains("refused".to_string());
+        assert!(filter.matches("connection refused"));
+        assert!(!filter.matches("connection accepted"));
+
+        let filter = LineFilter::Regex(Regex::new("^conn.*(refused|reset)$").unwrap());
+        assert!(filter.matches("connection reset"));
+        assert!(!filter.matches("connection accepted"));
+
+        let filter = LineFilter::Not(Box::new(filter));
+        assert!(!filter.matches("connection reset"));
+        assert!(filter.matches("connection accepted"));
+        assert_eq!(filter.required_text(), None);
+    }
+}
@@ -0,0 +1,562 @@
+// src/log_database/format.rs
+//! Versioning of the on-disk format of the log [`Database`](super::Database).
+//!
+//! The format version of a data directory is recorded in a marker file. [`Database::open`] refuses
+//! to open directories with any other version than [`CURRENT_VERSION`], and [`migrate`] upgrades
+//! older directories one version at a time, so that changes to the storage format don't require
+//! data directories to be deleted.
+//!
+//! Directories written before versioning was introduced have no marker, and are treated as version
+//! `0`.
+//!
+//! Migrations that rewrite files stage every rewritten file before replacing any of them, so an
+//! interrupted migration never leaves a mix of old and new files behind.
+//!
+//! [`Database::open`]: super::Database::open
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use log::info;
+
+use super::annotation::{Annotation, Target, ANNOTATIONS_FILE_NAME};
+use super::partition::Partitioning;
+use super::{
+    push_timestamp, Cursor, DATA_FILE_EXTENSION, DATA_FILE_RECORD_SEPARATOR, TIMESTAMP_LEN,
+};
+
+/// The name of the file in the data directory in which the format version is recorded.
+pub(super) const VERSION_FILE_NAME: &str = "format-version";
+
+/// The name of the file in the data directory that marks a migration's rewritten files as staged.
+const STAGED_FILE_NAME: &str = "format-migration-staged";
+
+/// The extension appended to the paths of rewritten files while they're staged.
+const STAGED_EXTENSION: &str = "migrated";
+
+/// The format version written by this version of `monitoring-rs`.
+///
+/// - `0`: Records separated by a sentinel byte, with no version marker.
+/// - `1`: As `0`, with a version marker.
+/// - `2`: As `1`, with each record starting with its entry's timestamp.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// A migration from one format version to the next.
+struct Migration {
+    /// The version migrated from. The migration produces version `from + 1`.
+    from: u32,
+
+    description: &'static str,
+
+    /// Migrate the data in the given directories: the data directory, followed by any partition
+    /// directories. This must not write the version marker.
+    apply: fn(&[PathBuf]) -> io::Result<()>,
+}
+
+/// All migrations, in order.
+static MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "add format version marker",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        from: 1,
+        description: "timestamp entries",
+        apply: timestamp_entries,
+    },
+];
+
+/// Read the format version of the data directory at `data_directory`.
+///
+/// # Errors
+///
+/// Propagates any `io::Error` that occurs when reading the directory, or if the version marker is
+/// invalid.
+pub fn read_version(data_directory: &Path) -> io::Result<u32> {
+    let path = data_directory.join(VERSION_FILE_NAME);
+    if path.exists() {
+        let version = fs::read_to_string(&path)?;
+        return version.trim().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid format version in {}: {}", path.display(), version),
+            )
+        });
+    }
+
+    // An empty directory can be used as-is.
+    if fs::read_dir(data_directory)?.next().is_none() {
+        Ok(CURRENT_VERSION)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Record `version` as the format version of the data directory at `data_directory`.
+pub(super) fn write_version(data_directory: &Path, version: u32) -> io::Result<()> {
+    fs::write(
+        data_directory.join(VERSION_FILE_NAME),
+        format!("{}\n", version),
+    )
+}
+
+/// Upgrade the data directory at `data_directory`, and the directories of any `partitioning`, to
+/// [`CURRENT_VERSION`].
+///
+/// Migrations are applied one version at a time, and the version marker is updated after each, so
+/// an interrupted migration can be resumed. Returns the versions that were migrated from.
+///
+/// # Errors
+///
+/// Propagates any `io::Error` that occurs during migration. An error is also returned if the
+/// directory was written by a newer version of `monitoring-rs`.
+pub fn migrate(data_directory: &Path, partitioning: Option<&Partitioning>) -> io::Result<Vec<u32>> {
+    migrate_with(data_directory, partitioning, MIGRATIONS, CURRENT_VERSION)
+}
+
+fn migrate_with(
+    data_directory: &Path,
+    partitioning: Option<&Partitioning>,
+    migrations: &[Migration],
+    current_version: u32,
+) -> io::Result<Vec<u32>> {
+    let mut version = read_version(data_directory)?;
+    if version > current_version {
+        return Err(newer_version_error(
+            data_directory,
+            version,
+            current_version,
+        ));
+    }
+
+    let mut directories = vec![data_directory.to_path_buf()];
+    if let Some(partitioning) = partitioning {
+        directories.extend(partitioning.directories(data_directory)?);
+    }
+
+    let mut applied = Vec::new();
+    for migration in migrations
+        .iter()
+        .filter(|migration| migration.from >= version)
+    {
+        info!(
+            "Migrating {} from format version {} to {}: {}",
+            data_directory.display(),
+            migration.from,
+            migration.from + 1,
+            migration.description
+        );
+        (migration.apply)(&directories)?;
+        version = migration.from + 1;
+        write_version(data_directory, version)?;
+        applied.push(migration.from);
+    }
+
+    if !data_directory.join(VERSION_FILE_NAME).exists() {
+        write_version(data_directory, current_version)?;
+    }
+
+    // The staged marker is only removed once the version marker has moved on, so a resumed
+    // migration can't rewrite files that were already rewritten.
+    match fs::remove_file(data_directory.join(STAGED_FILE_NAME)) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(applied),
+    }
+}
+
+/// Prefix every record with a timestamp, and move the annotations on entries to their records' new
+/// offsets.
+///
+/// Entries weren't timestamped before, so each is given the time its log file was last written.
+fn timestamp_entries(directories: &[PathBuf]) -> io::Result<()> {
+    let data_directory = &directories[0];
+    let mut paths = data_files(directories)?;
+    let annotations_path = data_directory.join(ANNOTATIONS_FILE_NAME);
+    if annotations_path.exists() {
+        paths.push(annotations_path.clone());
+    }
+
+    // The offsets of each log file's records, by key, for moving annotations.
+    let mut offsets: HashMap<String, Vec<u64>> = HashMap::new();
+    rewrite_files(data_directory, &paths, |path, contents| {
+        if path == annotations_path {
+            return move_annotations(&contents, &offsets);
+        }
+        if contents.is_empty() {
+            return Ok(contents);
+        }
+
+        let modified = fs::metadata(path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let timestamp = modified.as_secs() * 1000 + u64::from(modified.subsec_millis());
+
+        let mut rewritten = Vec::with_capacity(contents.len());
+        let mut record_offsets = Vec::new();
+        let mut offset = 0;
+        for (i, record) in contents
+            .split(|byte| *byte == DATA_FILE_RECORD_SEPARATOR)
+            .enumerate()
+        {
+            if i != 0 {
+                rewritten.push(DATA_FILE_RECORD_SEPARATOR);
+            }
+            push_timestamp(&mut rewritten, timestamp);
+            rewritten.extend_from_slice(record);
+            record_offsets.push(offset);
+            offset += record.len() as u64 + 1;
+        }
+
+        if let Some(key) = path.file_stem().and_then(OsStr::to_str) {
+            offsets.insert(key.to_string(), record_offsets);
+        }
+        Ok(rewritten)
+    })
+}
+
+/// Move the annotations on entries in the serialized `annotations` from their records' offsets
+/// before they were timestamped to their offsets after. `offsets` holds the offsets of each log
+/// file's records before they were timestamped.
+fn move_annotations(
+    annotations: &[u8],
+    offsets: &HashMap<String, Vec<u64>>,
+) -> io::Result<Vec<u8>> {
+    let mut annotations: Vec<Annotation> = serde_json::from_slice(annotations)?;
+    for annotation in &mut annotations {
+        let entry = match &mut annotation.target {
+            Target::Entry { entry } => entry,
+            Target::Range { .. } => continue,
+        };
+        let mut cursor: Cursor = match entry.parse() {
+            Ok(cursor) => cursor,
+            Err(_) => continue,
+        };
+        let record_offsets = match offsets.get(&cursor.segment) {
+            Some(record_offsets) => record_offsets,
+            None => continue,
+        };
+
+        // Every record up to and including the entry's has gained a timestamp.
+        let records = match record_offsets.binary_search(&cursor.offset) {
+            Ok(index) => index,
+            Err(index) => index,
+        };
+        cursor.offset += (records * TIMESTAMP_LEN) as u64;
+        *entry = cursor.to_string();
+    }
+    Ok(serde_json::to_vec(&annotations)?)
+}
+
+/// The data files in `directories`.
+fn data_files(directories: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for directory in directories {
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension() == Some(OsStr::new(DATA_FILE_EXTENSION)) && path.is_file() {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Replace the contents of each of `paths` with the result of `rewrite`, as part of migrating the
+/// data directory `data_directory`.
+///
+/// `rewrite` is called with each path and its current contents, in order. Every rewritten file is
+/// staged next to its original before any original is replaced, and a marker recording the
+/// directory's version is written once they all have been. If the migration is interrupted before
+/// then, it starts again from the original files. If it's interrupted afterwards, only the staged
+/// files that haven't yet replaced their originals are moved, and `rewrite` isn't called again.
+fn rewrite_files(
+    data_directory: &Path,
+    paths: &[PathBuf],
+    mut rewrite: impl FnMut(&Path, Vec<u8>) -> io::Result<Vec<u8>>,
+) -> io::Result<()> {
+    let version = read_version(data_directory)?.to_string();
+    let marker = data_directory.join(STAGED_FILE_NAME);
+    let staged = match fs::read_to_string(&marker) {
+        Ok(staged) => staged.trim() == version,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => false,
+        Err(error) => return Err(error),
+    };
+
+    if !staged {
+        for path in paths {
+            let contents = rewrite(path, fs::read(path)?)?;
+            fs::write(staged_path(path), contents)?;
+        }
+        fs::write(&marker, format!("{}\n", version))?;
+    }
+
+    for path in paths {
+        let staged_path = staged_path(path);
+        if staged_path.exists() {
+            fs::rename(&staged_path, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// The path at which the rewritten contents of `path` are staged.
+fn staged_path(path: &Path) -> PathBuf {
+    let mut staged = path.as_os_str().to_owned();
+    staged.push(".");
+    staged.push(STAGED_EXTENSION);
+    PathBuf::from(staged)
+}
+
+/// Check that the data directory at `data_directory` can be opened, writing the version marker
+/// into new directories.
+pub(super) fn check(data_directory: &Path) -> io::Result<()> {
+    let version = read_version(data_directory)?;
+    if version > CURRENT_VERSION {
+        return Err(newer_version_error(
+            data_directory,
+            version,
+            CURRENT_VERSION,
+        ));
+    }
+    if version < CURRENT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "data directory {} has format version {}, but {} is required: it must be migrated \
+                 first",
+                data_directory.display(),
+                version,
+                CURRENT_VERSION
+            ),
+        ));
+    }
+    if !data_directory.join(VERSION_FILE_NAME).exists() {
+        write_version(data_directory, CURRENT_VERSION)?;
+    }
+    Ok(())
+}
+
+fn newer_version_error(data_directory: &Path, version: u32, current_version: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "data directory {} has format version {}, which is newer than the supported version {}",
+            data_directory.display(),
+            version,
+            current_version
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+
+    use crate::log_database::annotation::{Annotation, Target, ANNOTATIONS_FILE_NAME};
+    use crate::log_database::partition::Partitioning;
+    use crate::log_database::{DATA_FILE_RECORD_SEPARATOR, TIMESTAMP_LEN};
+    use crate::test;
+
+    use super::{
+        check, data_files, migrate, migrate_with, read_version, rewrite_files, staged_path,
+        write_version, Migration, CURRENT_VERSION, STAGED_FILE_NAME,
+    };
+
+    /// Migrations ending with one that appends `!` to every data file, which would be noticed if
+    /// it were applied twice.
+    static TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            from: 0,
+            description: "add format version marker",
+            apply: |_| Ok(()),
+        },
+        Migration {
+            from: 1,
+            description: "shout",
+            apply: shout,
+        },
+    ];
+
+    fn shout(directories: &[PathBuf]) -> io::Result<()> {
+        rewrite_files(
+            &directories[0],
+            &data_files(directories)?,
+            |_, mut contents| {
+                contents.push(b'!');
+                Ok(contents)
+            },
+        )
+    }
+
+    #[test]
+    fn new_directory_is_current() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        assert_eq!(read_version(tempdir.path())?, CURRENT_VERSION);
+
+        check(tempdir.path())?;
+        assert_eq!(read_version(tempdir.path())?, CURRENT_VERSION);
+        assert_eq!(migrate(tempdir.path(), None)?, Vec::<u32>::new());
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrates_unversioned_directory() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        fs::write(tempdir.path().join("abc.dat"), "hello")?;
+
+        assert_eq!(read_version(tempdir.path())?, 0);
+        assert!(check(tempdir.path()).is_err());
+
+        assert_eq!(migrate(tempdir.path(), None)?, vec![0, 1]);
+        assert_eq!(read_version(tempdir.path())?, CURRENT_VERSION);
+        check(tempdir.path())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn timestamps_entries() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let abc = tempdir.path().join("abc.dat");
+        let mut contents = b"hello".to_vec();
+        contents.push(DATA_FILE_RECORD_SEPARATOR);
+        contents.extend_from_slice(b"world");
+        fs::write(&abc, contents)?;
+        fs::write(tempdir.path().join("def.dat"), "")?;
+        fs::write(
+            tempdir.path().join(ANNOTATIONS_FILE_NAME),
+            r#"[
+                {"id":0,"entry":"abc.0","tags":[],"note":"","author":"","created_at":0},
+                {"id":1,"entry":"abc.6","tags":[],"note":"","author":"","created_at":0},
+                {"id":2,"selector":{},"start":0,"end":1,"tags":[],"note":"","author":"","created_at":0}
+            ]"#,
+        )?;
+        write_version(tempdir.path(), 1)?;
+
+        assert_eq!(migrate(tempdir.path(), None)?, vec![1]);
+
+        let contents = fs::read(&abc)?;
+        let records: Vec<_> = contents
+            .split(|byte| *byte == DATA_FILE_RECORD_SEPARATOR)
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0][..TIMESTAMP_LEN], records[1][..TIMESTAMP_LEN]);
+        assert_eq!(&records[0][TIMESTAMP_LEN..], b"hello");
+        assert_eq!(&records[1][TIMESTAMP_LEN..], b"world");
+        assert_eq!(fs::read(tempdir.path().join("def.dat"))?, b"");
+
+        let annotations: Vec<Annotation> =
+            serde_json::from_slice(&fs::read(tempdir.path().join(ANNOTATIONS_FILE_NAME))?)?;
+        let targets: Vec<_> = annotations
+            .into_iter()
+            .map(|annotation| annotation.target)
+            .collect();
+        assert_eq!(
+            targets,
+            vec![
+                Target::Entry {
+                    entry: "abc.0".to_string()
+                },
+                Target::Entry {
+                    entry: "abc.16".to_string()
+                },
+                Target::Range {
+                    selector: Default::default(),
+                    start: 0,
+                    end: 1
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn migration_rewrites_data_files() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let partition = tempdir.path().join("partitions").join("a");
+        fs::create_dir_all(&partition)?;
+        fs::write(tempdir.path().join("abc.dat"), "hello")?;
+        fs::write(tempdir.path().join("abc.json"), "{}")?;
+        fs::write(partition.join("def.dat"), "world")?;
+
+        let partitioning = Partitioning {
+            label: "partition".to_string(),
+            partitions: vec![],
+        };
+        let applied = migrate_with(tempdir.path(), Some(&partitioning), TEST_MIGRATIONS, 2)?;
+        assert_eq!(applied, vec![0, 1]);
+        assert_eq!(read_version(tempdir.path())?, 2);
+        assert_eq!(
+            fs::read_to_string(tempdir.path().join("abc.dat"))?,
+            "hello!"
+        );
+        assert_eq!(fs::read_to_string(tempdir.path().join("abc.json"))?, "{}");
+        assert_eq!(fs::read_to_string(partition.join("def.dat"))?, "world!");
+        assert!(!staged_path(&tempdir.path().join("abc.dat")).exists());
+        assert!(!tempdir.path().join(STAGED_FILE_NAME).exists());
+
+        assert_eq!(
+            migrate_with(tempdir.path(), None, TEST_MIGRATIONS, 2)?,
+            Vec::<u32>::new()
+        );
+        assert_eq!(
+            fs::read_to_string(tempdir.path().join("abc.dat"))?,
+            "hello!"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn resumes_interrupted_migration() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let abc = tempdir.path().join("abc.dat");
+        let def = tempdir.path().join("def.dat");
+        write_version(tempdir.path(), 1)?;
+
+        // Interrupted while staging: the staged file is discarded and rewritten.
+        fs::write(&abc, "hello")?;
+        fs::write(staged_path(&abc), "partial")?;
+        migrate_with(tempdir.path(), None, TEST_MIGRATIONS, 2)?;
+        assert_eq!(fs::read_to_string(&abc)?, "hello!");
+
+        // Interrupted while replacing: only the remaining staged file is moved.
+        write_version(tempdir.path(), 1)?;
+        fs::write(&def, "world!")?;
+        fs::write(staged_path(&abc), "hello!")?;
+        fs::write(tempdir.path().join(STAGED_FILE_NAME), "1\n")?;
+        migrate_with(tempdir.path(), None, TEST_MIGRATIONS, 2)?;
+        assert_eq!(fs::read_to_string(&abc)?, "hello!");
+        assert_eq!(fs::read_to_string(&def)?, "world!");
+
+        // A marker left by an earlier migration is ignored.
+        write_version(tempdir.path(), 1)?;
+        fs::write(tempdir.path().join(STAGED_FILE_NAME), "0\n")?;
+        migrate_with(tempdir.path(), None, TEST_MIGRATIONS, 2)?;
+        assert_eq!(fs::read_to_string(&abc)?, "hello!!");
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_newer_version() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        write_version(tempdir.path(), CURRENT_VERSION + 1)?;
+
+        assert!(check(tempdir.path()).is_err());
+        assert!(migrate(tempdir.path(), None).is_err());
+
+        Ok(())
+    }
+}
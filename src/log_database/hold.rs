@@ -0,0 +1,118 @@
+// src/log_database/hold.rs
+//! Legal holds for the log [`Database`](super::Database).
+//!
+//! A legal hold exempts every stream matching its selector from retention (and any other
+//! destructive operation) until the hold is released. Holds are persisted in the data directory so
+//! that they survive restarts, and every change is recorded in the audit log.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The name of the file in the data directory in which holds are persisted.
+pub(super) const HOLDS_FILE_NAME: &str = "legal-holds";
+
+/// The `log` target used for audit records of hold changes.
+pub(super) const AUDIT_TARGET: &str = "monitoring_rs::audit";
+
+/// A legal hold on the streams matching a selector.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Hold {
+    /// The `(key, value)` pairs that a stream's metadata must contain to be held.
+    ///
+    /// This also identifies the hold – placing a hold with the same selector as an existing hold
+    /// replaces it.
+    pub selector: BTreeMap<String, String>,
+
+    /// A free-text justification for the hold (e.g. a case reference).
+    pub reason: String,
+
+    /// When the hold was placed, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub placed_at: u64,
+}
+
+impl Hold {
+    /// Check whether this hold applies to a stream with the given `metadata`.
+    #[must_use]
+    pub fn matches(&self, metadata: &HashMap<String, String>) -> bool {
+        self.selector
+            .iter()
+            .all(|(key, value)| metadata.get(key) == Some(value))
+    }
+}
+
+/// The set of holds placed on a database.
+#[derive(Debug, Default)]
+pub(super) struct Holds(Vec<Hold>);
+
+impl Holds {
+    /// Restore holds from `data_directory`, if any have been persisted.
+    pub(super) fn load(data_directory: &Path) -> io::Result<Self> {
+        let path = data_directory.join(HOLDS_FILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let holds = serde_json::from_slice(&fs::read(&path)?)?;
+        Ok(Self(holds))
+    }
+
+    pub(super) fn all(&self) -> &[Hold] {
+        &self.0
+    }
+
+    /// Check whether any hold applies to a stream with the given `metadata`.
+    pub(super) fn is_held(&self, metadata: &HashMap<String, String>) -> bool {
+        self.0.iter().any(|hold| hold.matches(metadata))
+    }
+
+    pub(super) fn place(&mut self, data_directory: &Path, mut hold: Hold) -> io::Result<()> {
+        hold.placed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default();
+
+        log::info!(
+            target: AUDIT_TARGET,
+            "Placed legal hold on {:?}: {}",
+            hold.selector,
+            hold.reason
+        );
+
+        self.0.retain(|existing| existing.selector != hold.selector);
+        self.0.push(hold);
+        self.save(data_directory)
+    }
+
+    pub(super) fn release(
+        &mut self,
+        data_directory: &Path,
+        selector: &BTreeMap<String, String>,
+    ) -> io::Result<Option<Hold>> {
+        let position = match self.0.iter().position(|hold| &hold.selector == selector) {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        let hold = self.0.remove(position);
+
+        log::info!(
+            target: AUDIT_TARGET,
+            "Released legal hold on {:?} (placed at {}): {}",
+            hold.selector,
+            hold.placed_at,
+            hold.reason
+        );
+
+        self.save(data_directory)?;
+        Ok(Some(hold))
+    }
+
+    fn save(&self, data_directory: &Path) -> io::Result<()> {
+        fs::write(
+            data_directory.join(HOLDS_FILE_NAME),
+            serde_json::to_vec(&self.0)?,
+        )
+    }
+}
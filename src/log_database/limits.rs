@@ -0,0 +1,222 @@
+// src/log_database/limits.rs
+//! Limits on the size of lines written to the log [`Database`](super::Database), and on the
+//! number of concurrent queries.
+//!
+//! A single application logging enormous lines can otherwise exhaust memory when its stream is
+//! queried. Lines longer than [`Config::max_line_size`](super::Config::max_line_size) bytes are
+//! handled according to an [`OversizedLinePolicy`].
+//!
+//! Similarly, a burst of queries (e.g. from dashboards refreshing) can starve writes. At most
+//! [`Config::max_concurrent_queries`](super::Config::max_concurrent_queries) queries run at once,
+//! and any more fail immediately with a [`TooBusy`] error rather than queueing.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What to do with lines longer than the maximum line size.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OversizedLinePolicy {
+    /// Keep the start of the line, discarding the rest.
+    Truncate,
+
+    /// Discard the whole line.
+    Drop,
+
+    /// Split the line into several lines, each within the limit.
+    Split,
+}
+
+impl Default for OversizedLinePolicy {
+    fn default() -> Self {
+        Self::Truncate
+    }
+}
+
+impl FromStr for OversizedLinePolicy {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "truncate" => Ok(Self::Truncate),
+            "drop" => Ok(Self::Drop),
+            "split" => Ok(Self::Split),
+            _ => Err(format!(
+                "invalid oversized line policy `{}`: must be `truncate`, `drop`, or `split`",
+                input
+            )),
+        }
+    }
+}
+
+impl OversizedLinePolicy {
+    /// Apply the policy to `line`, returning the lines that should be written instead.
+    ///
+    /// Lines are only ever cut at character boundaries. A line is never cut before its first
+    /// character, so a single character longer than `max` (only possible for `max < 4`) is kept.
+    pub(super) fn apply(self, line: &str, max: usize) -> Vec<&str> {
+        match self {
+            Self::Truncate => vec![&line[..boundary(line, max)]],
+            Self::Drop => vec![],
+            Self::Split => {
+                let mut lines = Vec::new();
+                let mut rest = line;
+                while !rest.is_empty() {
+                    let (head, tail) = rest.split_at(boundary(rest, max));
+                    lines.push(head);
+                    rest = tail;
+                }
+                lines
+            }
+        }
+    }
+}
+
+/// The error returned when a query is rejected because too many are already running.
+///
+/// Queries return [`io::Error`]s, so this is wrapped in one of kind [`io::ErrorKind::Other`]. Use
+/// [`TooBusy::find`] to check for it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TooBusy {
+    /// The maximum number of concurrent queries.
+    pub max_concurrent_queries: usize,
+}
+
+impl TooBusy {
+    /// Find a `TooBusy` error wrapped in `error`, if there is one.
+    #[must_use]
+    pub fn find(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for TooBusy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "too many concurrent queries (at most {} may run at once)",
+            self.max_concurrent_queries
+        )
+    }
+}
+
+impl Error for TooBusy {}
+
+/// Limits the number of queries running at once.
+#[derive(Debug)]
+pub(super) struct QueryLimiter {
+    max: Option<usize>,
+    running: AtomicUsize,
+}
+
+/// Permission for a query to run, released when dropped.
+#[derive(Debug)]
+pub(super) struct QueryPermit<'a>(&'a QueryLimiter);
+
+impl QueryLimiter {
+    pub(super) fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            running: AtomicUsize::new(0),
+        }
+    }
+
+    /// Start a query, failing with [`TooBusy`] if the limit has been reached.
+    pub(super) fn acquire(&self) -> io::Result<QueryPermit<'_>> {
+        let running = self.running.fetch_add(1, Ordering::AcqRel);
+        let permit = QueryPermit(self);
+        match self.max {
+            Some(max) if running >= max => Err(io::Error::new(
+                io::ErrorKind::Other,
+                TooBusy {
+                    max_concurrent_queries: max,
+                },
+            )),
+            _ => Ok(permit),
+        }
+    }
+}
+
+impl Drop for QueryPermit<'_> {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The largest character boundary in `line` that is at most `max` (but after the first character).
+fn boundary(line: &str, max: usize) -> usize {
+    if line.len() <= max {
+        return line.len();
+    }
+
+    let mut index = max;
+    while !line.is_char_boundary(index) {
+        index -= 1;
+    }
+    if index == 0 {
+        index = line.chars().next().map_or(0, char::len_utf8);
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OversizedLinePolicy, QueryLimiter, TooBusy};
+
+    #[test]
+    fn apply_policies() {
+        assert_eq!(
+            OversizedLinePolicy::Truncate.apply("hello world", 5),
+            vec!["hello"]
+        );
+        assert_eq!(
+            OversizedLinePolicy::Drop.apply("hello world", 5),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            OversizedLinePolicy::Split.apply("hello world", 5),
+            vec!["hello", " worl", "d"]
+        );
+    }
+
+    #[test]
+    fn cuts_at_char_boundaries() {
+        // `é` is 2 bytes long.
+        assert_eq!(OversizedLinePolicy::Truncate.apply("héllo", 2), vec!["h"]);
+        assert_eq!(
+            OversizedLinePolicy::Split.apply("héllo", 2),
+            vec!["h", "é", "ll", "o"]
+        );
+        assert_eq!(OversizedLinePolicy::Split.apply("éé", 1), vec!["é", "é"]);
+    }
+
+    #[test]
+    fn limit_queries() {
+        let limiter = QueryLimiter::new(Some(2));
+        let first = limiter.acquire().unwrap();
+        let _second = limiter.acquire().unwrap();
+
+        let error = limiter.acquire().unwrap_err();
+        assert_eq!(
+            TooBusy::find(&error),
+            Some(&TooBusy {
+                max_concurrent_queries: 2
+            })
+        );
+
+        // Rejected queries don't hold a permit, and finished queries release theirs.
+        drop(first);
+        assert!(limiter.acquire().is_ok());
+
+        let unlimited = QueryLimiter::new(None);
+        let _permits: Vec<_> = (0..10).map(|_| unlimited.acquire().unwrap()).collect();
+    }
+
+    #[test]
+    fn parse_policies() {
+        assert_eq!("split".parse(), Ok(OversizedLinePolicy::Split));
+        assert!("explode".parse::<OversizedLinePolicy>().is_err());
+    }
+}
@@ -0,0 +1,192 @@
+// src/log_database/metrics.rs
+//! Operational metrics for the log [`Database`](super::Database).
+//!
+//! Counters are updated as the database is used, and a point-in-time [`DatabaseMetrics`] snapshot
+//! can be taken with [`Database::metrics`](super::Database::metrics).
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const LATENCY_BUCKET_COUNT: usize = 8;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; LATENCY_BUCKET_COUNT] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// A snapshot of the database's metrics.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct DatabaseMetrics {
+    /// The number of entries written since the database was opened.
+    pub entries_written: u64,
+
+    /// The number of bytes written to log files since the database was opened.
+    pub bytes_written: u64,
+
+    /// The number of entries longer than the maximum line size since the database was opened.
+    pub oversized_entries: u64,
+
+    /// The number of streams (distinct metadata sets) currently stored.
+    pub active_streams: usize,
+
+    /// The number of streams created since the database was opened.
+    pub streams_created: u64,
+
+    /// The number of streams removed by retention since the database was opened.
+    pub streams_expired: u64,
+
+    /// The number of log file handles currently held open.
+    pub open_file_handles: usize,
+
+    /// The latency of queries since the database was opened.
+    pub query_latency: LatencyHistogram,
+}
+
+/// A cumulative histogram of latencies.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct LatencyHistogram {
+    /// The buckets of the histogram, in increasing order.
+    ///
+    /// Buckets are cumulative, so each includes the observations of all the buckets before it.
+    /// Observations greater than the last bucket are only included in `count`.
+    pub buckets: Vec<Bucket>,
+
+    /// The total number of observations.
+    pub count: u64,
+
+    /// The sum of all observations, in seconds.
+    pub sum_seconds: f64,
+}
+
+/// A single bucket of a [`LatencyHistogram`].
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Bucket {
+    /// The (inclusive) upper bound of the bucket, in seconds.
+    pub le_seconds: f64,
+
+    /// The number of observations less than or equal to `le_seconds`.
+    pub count: u64,
+}
+
+/// Records observations into a [`LatencyHistogram`].
+#[derive(Debug, Default)]
+pub struct LatencyRecorder {
+    buckets: [AtomicU64; LATENCY_BUCKET_COUNT],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl LatencyRecorder {
+    /// Record an observation of `duration`.
+    pub fn record(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(index) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+
+        // Saturate rather than wrap, though it would take ~584,000 years of queries to overflow.
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// A snapshot of the observations so far.
+    #[must_use]
+    pub fn snapshot(&self) -> LatencyHistogram {
+        let mut cumulative = 0;
+        let buckets = LATENCY_BUCKETS
+            .iter()
+            .zip(&self.buckets)
+            .map(|(le_seconds, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                Bucket {
+                    le_seconds: *le_seconds,
+                    count: cumulative,
+                }
+            })
+            .collect();
+
+        LatencyHistogram {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum_seconds: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+                .as_secs_f64(),
+        }
+    }
+}
+
+/// Counters updated by the database.
+#[derive(Debug, Default)]
+pub(super) struct Recorder {
+    entries_written: AtomicU64,
+    bytes_written: AtomicU64,
+    oversized_entries: AtomicU64,
+    streams_created: AtomicU64,
+    streams_expired: AtomicU64,
+    query_latency: LatencyRecorder,
+}
+
+impl Recorder {
+    pub(super) fn record_write(&self, bytes: usize) {
+        self.entries_written.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_oversized(&self) {
+        self.oversized_entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_stream_created(&self) {
+        self.streams_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_stream_expired(&self) {
+        self.streams_expired.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_query(&self, duration: Duration) {
+        self.query_latency.record(duration);
+    }
+
+    pub(super) fn snapshot(
+        &self,
+        active_streams: usize,
+        open_file_handles: usize,
+    ) -> DatabaseMetrics {
+        DatabaseMetrics {
+            entries_written: self.entries_written.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            oversized_entries: self.oversized_entries.load(Ordering::Relaxed),
+            active_streams,
+            streams_created: self.streams_created.load(Ordering::Relaxed),
+            streams_expired: self.streams_expired.load(Ordering::Relaxed),
+            open_file_handles,
+            query_latency: self.query_latency.snapshot(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Recorder;
+
+    #[test]
+    fn histogram_is_cumulative() {
+        let recorder = Recorder::default();
+        recorder.record_query(Duration::from_micros(500));
+        recorder.record_query(Duration::from_millis(20));
+        recorder.record_query(Duration::from_secs(10));
+
+        let histogram = recorder.snapshot(0, 0).query_latency;
+        let counts: Vec<_> = histogram
+            .buckets
+            .iter()
+            .map(|bucket| bucket.count)
+            .collect();
+        assert_eq!(counts, vec![1, 1, 1, 2, 2, 2, 2, 2]);
+        assert_eq!(histogram.count, 3);
+        assert!((histogram.sum_seconds - 10.0205).abs() < 1e-9);
+    }
+}
@@ -2,33 +2,232 @@
 
 //! The interface for log storage in `monitoring-rs`.
 
-use std::collections::{hash_map, HashMap, HashSet};
+pub mod annotation;
+mod bloom;
+mod cache;
+pub mod deletion;
+pub mod distinct;
+pub mod filter;
+pub mod format;
+pub mod hold;
+pub mod limits;
+pub mod metrics;
+pub mod partition;
+pub mod projection;
+pub mod recovery;
+pub mod relabel;
+pub mod retention;
+pub mod stats;
+pub mod tail;
+pub mod writer;
+
+use std::collections::{hash_map, BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::record::{self, Record, Timestamp};
 use crate::LogEntry;
 
 const DATA_FILE_EXTENSION: &str = "dat";
 const METADATA_FILE_EXTENSION: &str = "json";
+const BLOOM_FILE_EXTENSION: &str = "bloom";
 const DATA_FILE_RECORD_SEPARATOR: u8 = 147;
 
+/// The length of the timestamp at the start of each record: the entry's [`Timestamp`] as
+/// zero-padded hexadecimal.
+const TIMESTAMP_LEN: usize = 16;
+
 /// The configuration needed to open a database.
 pub struct Config {
     /// The directory in which the database should store its data.
     pub data_directory: PathBuf,
+
+    /// Retention rules, in priority order.
+    ///
+    /// See [`retention`] for how the rules are evaluated.
+    pub retention: Vec<retention::Rule>,
+
+    /// The number of query results to keep in an in-memory LRU cache.
+    ///
+    /// Cached results are invalidated whenever a matching log file is written. Set to `0` to
+    /// disable the cache.
+    pub query_cache_capacity: usize,
+
+    /// The maximum number of queries that may run at once, or `None` for no limit.
+    ///
+    /// Queries beyond the limit fail immediately with [`limits::TooBusy`].
+    pub max_concurrent_queries: Option<usize>,
+
+    /// The maximum length of a line, in bytes, or `None` for no limit.
+    ///
+    /// Longer lines are handled according to `oversized_line_policy`.
+    pub max_line_size: Option<usize>,
+
+    /// What to do with lines longer than `max_line_size`.
+    pub oversized_line_policy: limits::OversizedLinePolicy,
+
+    /// What to do with unrecognised files in `data_directory` when opening the database.
+    pub unknown_file_policy: recovery::UnknownFilePolicy,
+
+    /// How to partition log files into subdirectories by a label, if at all.
+    ///
+    /// See [`partition`] for the layout and per-partition retention.
+    pub partitioning: Option<partition::Partitioning>,
+}
+
+/// A page of query results from [`Database::query_page`].
+#[derive(Debug, PartialEq)]
+pub struct Page {
+    /// The lines in this page.
+    pub lines: Vec<String>,
+
+    /// A cursor that can be used to fetch the next page, if there may be more lines.
+    pub next: Option<Cursor>,
+
+    /// Statistics about the work done to produce this page.
+    pub stats: QueryStats,
+}
+
+/// The order in which [`Database::query_page_filtered`] returns lines.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Oldest lines first.
+    Forward,
+
+    /// Newest lines first.
+    Backward,
+}
+
+impl Default for Direction {
+    fn default() -> Self {
+        Self::Forward
+    }
+}
+
+/// A query for [`Database::query_selection`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selection {
+    /// The `(key, value)` pairs that a log file's metadata must all contain to be queried.
+    pub matchers: Vec<(String, String)>,
+
+    /// Filters that returned lines must all match.
+    pub filters: Vec<filter::LineFilter>,
+
+    /// Only query log files that have been written to since this time.
+    pub start: Option<SystemTime>,
+
+    /// Only query log files that were created before this time.
+    pub end: Option<SystemTime>,
+
+    /// The maximum number of lines to return, or `None` for no limit.
+    pub limit: Option<usize>,
+}
+
+impl Selection {
+    /// Check whether `line` matches all of the selection's [filters](Self::filters).
+    #[must_use]
+    pub fn matches(&self, line: &str) -> bool {
+        self.filters.iter().all(|filter| filter.matches(line))
+    }
+
+    /// Check whether an entry logged at `timestamp` is between the selection's
+    /// [start](Self::start) and [end](Self::end).
+    #[must_use]
+    pub fn logged_within(&self, timestamp: Timestamp) -> bool {
+        let time = UNIX_EPOCH + Duration::from_millis(timestamp);
+        self.start.map_or(true, |start| time >= start) && self.end.map_or(true, |end| time <= end)
+    }
+}
+
+/// An entry returned by [`Database::query_entries`].
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    /// Identifies the entry, e.g. for [annotations](annotation). This is the entry's [`Cursor`].
+    pub id: Cursor,
+
+    /// When the entry was logged.
+    pub timestamp: Timestamp,
+
+    /// The entry's line.
+    pub line: String,
+}
+
+/// Statistics about the work done by a query.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize)]
+pub struct QueryStats {
+    /// The number of log files that were read.
+    pub streams: usize,
+
+    /// The number of bytes read from log files.
+    pub bytes_scanned: u64,
+
+    /// The number of bytes in the lines returned by the query.
+    pub bytes_returned: u64,
+}
+
+/// An opaque position in the results of a query.
+///
+/// Cursors can be serialized with `to_string` and parsed with `str::parse`, so they can be handed
+/// to API clients and later used to resume a query.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cursor {
+    /// The key of the log file to resume from.
+    segment: String,
+
+    /// The byte offset of the next record in the log file.
+    offset: u64,
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{:x}", self.segment, self.offset)
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut parts = input.splitn(2, '.');
+        match (parts.next(), parts.next()) {
+            (Some(segment), Some(offset)) if !segment.is_empty() => Ok(Cursor {
+                segment: segment.to_string(),
+                offset: u64::from_str_radix(offset, 16)
+                    .map_err(|_| format!("invalid cursor `{}`", input))?,
+            }),
+            _ => Err(format!("invalid cursor `{}`", input)),
+        }
+    }
+}
+
+/// Statistics about the index entries for a single metadata key.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct IndexStats {
+    /// The number of distinct values of the key.
+    pub values: usize,
+
+    /// The total number of references from the key's values to log files.
+    pub references: usize,
+
+    /// An estimate of the memory used by the key's index entries, in bytes.
+    pub estimated_bytes: usize,
 }
 
 enum FileType {
     DataFile,
     MetadataFile,
+    BloomFile,
 }
 
 /// A log database supporting key-value rerieval.
 ///
-/// **Note:** the functionality of this database is extremely minimal just now, and is missing vital
-/// features like retention management.
+/// **Note:** the functionality of this database is extremely minimal just now.
 ///
 /// That said, it should be decently fast for storing and querying UTF-8 log entries with key-value
 /// metadata (via [`LogEntry`](crate::LogEntry)).
@@ -41,87 +240,199 @@ enum FileType {
 ///   necessary (and updating the index if so).
 /// - Reads are performed using a `key=value` pair. The index is used to identify the files that
 ///   contain relevant records, and these files are then scanned in their entirety.
+/// - Retention is applied per log file by [`Database::apply_retention`], based on the first
+///   [`retention::Rule`] that matches the file's metadata and the time it was last written. Log
+///   files matching a legal [`Hold`](hold::Hold) are exempt from retention.
+/// - Log files can optionally be [partitioned](partition) into subdirectories by a label, with
+///   their own retention rules and size budgets.
+/// - A [bloom filter](bloom) of the tokens in each log file is maintained and persisted alongside
+///   it, allowing searches for text to skip log files that cannot contain it.
+/// - Query results can optionally be cached in memory, in which case writes invalidate the cached
+///   results for each `(key, value)` pair of the written entry's metadata.
 ///
 /// The structure, interface, and storage approach of the database is likely to change in future.
 pub struct Database {
     data_directory: PathBuf,
+    partitioning: Option<partition::Partitioning>,
+    directories: HashMap<String, PathBuf>,
     files: HashMap<String, File>,
+    metadata: HashMap<String, HashMap<String, String>>,
     index: HashMap<(String, String), HashSet<String>>,
+    retention: Vec<retention::Rule>,
+    holds: hold::Holds,
+    annotations: annotation::Annotations,
+    cache: Option<Mutex<cache::QueryCache>>,
+    query_limiter: limits::QueryLimiter,
+    subscribers: tail::Subscribers,
+    blooms: HashMap<String, bloom::BloomFilter>,
+    dirty_blooms: HashSet<String>,
+    recorder: metrics::Recorder,
+    max_line_size: Option<usize>,
+    oversized_line_policy: limits::OversizedLinePolicy,
+    recovery_report: recovery::RecoveryReport,
 }
 
 impl Database {
+    /// Open the database in `config.data_directory`.
+    ///
+    /// The data directory must be empty or have the [current format](format::CURRENT_VERSION).
+    /// Older directories can be upgraded with [`format::migrate`].
+    ///
+    /// Truncated trailing records (e.g. from a crash part way through a write) are repaired,
+    /// unrecognised files are handled according to [`Config::unknown_file_policy`], and a summary
+    /// of what was found is available from [`recovery_report`](Self::recovery_report).
+    ///
     /// # Errors
     ///
     /// Propagates any `io::Error` that ocurrs when opening the database.
     pub fn open(config: Config) -> io::Result<Self> {
+        format::check(&config.data_directory)?;
+
         let mut files = HashMap::new();
+        let mut stream_metadata = HashMap::new();
         let mut index = HashMap::new();
-        for entry in fs::read_dir(&config.data_directory)? {
-            let entry = entry?;
-            let path = entry.path();
-
-            let extension = path.extension().and_then(OsStr::to_str);
-            let file_type = match extension {
-                Some(DATA_FILE_EXTENSION) => FileType::DataFile,
-                Some(METADATA_FILE_EXTENSION) => FileType::MetadataFile,
-                _ => {
-                    return Err(Self::error(format!(
-                        "invalid data file {}: extension must be `{}` or `{}`",
-                        path.display(),
-                        DATA_FILE_EXTENSION,
-                        METADATA_FILE_EXTENSION
-                    )))
-                }
-            };
+        let mut blooms = HashMap::new();
+        let mut directories = HashMap::new();
+        let mut recovery_report = recovery::RecoveryReport::default();
+        let mut data_directories = vec![config.data_directory.clone()];
+        if let Some(partitioning) = &config.partitioning {
+            data_directories.extend(partitioning.directories(&config.data_directory)?);
+        }
+        for directory in &data_directories {
+            for entry in fs::read_dir(directory)? {
+                let entry = entry?;
+                let path = entry.path();
 
-            let metadata = fs::metadata(&path)?;
-            if !metadata.is_file() {
-                return Err(Self::error(format!(
-                    "invalid data file {}: not a file",
-                    path.display()
-                )));
-            }
+                if path.file_name() == Some(OsStr::new(hold::HOLDS_FILE_NAME))
+                    || path.file_name() == Some(OsStr::new(annotation::ANNOTATIONS_FILE_NAME))
+                    || path.file_name() == Some(OsStr::new(format::VERSION_FILE_NAME))
+                    || path.file_name() == Some(OsStr::new(recovery::QUARANTINE_DIRECTORY_NAME))
+                    || (directory == &config.data_directory
+                        && path.file_name()
+                            == Some(OsStr::new(partition::PARTITIONS_DIRECTORY_NAME)))
+                {
+                    continue;
+                }
 
-            let key_hash = path.file_stem().ok_or_else(|| {
-                Self::error(format!(
-                    "invalid data file name {}: empty file stem",
-                    path.display()
-                ))
-            })?;
+                let metadata = fs::metadata(&path)?;
+                let (file_type, key_hash) = match Self::classify(&path, &metadata) {
+                    Ok(classified) => classified,
+                    Err(reason) => {
+                        recovery::skip_unknown_file(
+                            directory,
+                            &path,
+                            &reason,
+                            config.unknown_file_policy,
+                            &mut recovery_report,
+                        )?;
+                        continue;
+                    }
+                };
 
-            let key_hash = key_hash.to_str().ok_or_else(|| {
-                Self::error(format!(
-                    "invalid data file name {}: non-utf8 file name",
-                    path.display()
-                ))
-            })?;
+                let mut file = OpenOptions::new().append(true).read(true).open(&path)?;
+                match file_type {
+                    FileType::DataFile => {
+                        let removed = recovery::repair_tail(&mut file)?;
+                        if removed != 0 {
+                            recovery_report
+                                .repaired_data_files
+                                .push((key_hash.clone(), removed));
+                        }
+                        if directory != &config.data_directory {
+                            directories.insert(key_hash.clone(), directory.clone());
+                        }
+                        files.insert(key_hash, file);
+                    }
+                    FileType::MetadataFile => {
+                        let metadata: HashMap<String, String> = serde_json::from_reader(file)?;
+                        let key = Self::hash(&metadata);
 
-            let file = OpenOptions::new().append(true).read(true).open(&path)?;
-            match file_type {
-                FileType::DataFile => {
-                    files.insert(key_hash.to_string(), file);
-                }
-                FileType::MetadataFile => {
-                    let metadata = serde_json::from_reader(file)?;
-                    let key = Self::hash(&metadata);
+                        for meta in &metadata {
+                            let keys = index
+                                .entry((meta.0.to_string(), meta.1.to_string()))
+                                .or_insert_with(|| HashSet::with_capacity(1));
 
-                    for meta in metadata {
-                        let keys = index
-                            .entry((meta.0.to_string(), meta.1.to_string()))
-                            .or_insert_with(|| HashSet::with_capacity(1));
+                            if !keys.contains(&key) {
+                                keys.insert(key.clone());
+                            }
+                        }
 
-                        if !keys.contains(&key) {
-                            keys.insert(key.clone());
+                        stream_metadata.insert(key, metadata);
+                    }
+                    FileType::BloomFile => {
+                        let modified = metadata.modified()?;
+                        if let Some(filter) = bloom::BloomFilter::from_bytes(&fs::read(&path)?) {
+                            blooms.insert(key_hash, (filter, modified));
                         }
                     }
                 }
             }
         }
-        Ok(Database {
+        recovery_report.streams = stream_metadata.len();
+        recovery_report.orphaned_data_files = files
+            .keys()
+            .filter(|key| !stream_metadata.contains_key(*key))
+            .cloned()
+            .collect();
+        recovery_report.orphaned_metadata_files = stream_metadata
+            .keys()
+            .filter(|key| !files.contains_key(*key))
+            .cloned()
+            .collect();
+        if recovery_report.is_clean() {
+            log::debug!(
+                "Opened {} with {} streams",
+                config.data_directory.display(),
+                recovery_report.streams
+            );
+        } else {
+            log::warn!(
+                "Recovered {} with problems: {:?}",
+                config.data_directory.display(),
+                recovery_report
+            );
+        }
+
+        let holds = hold::Holds::load(&config.data_directory)?;
+        let annotations = annotation::Annotations::load(&config.data_directory)?;
+        let mut database = Database {
             data_directory: config.data_directory,
+            partitioning: config.partitioning,
+            directories,
             files,
+            metadata: stream_metadata,
             index,
-        })
+            retention: config.retention,
+            holds,
+            annotations,
+            cache: match config.query_cache_capacity {
+                0 => None,
+                capacity => Some(Mutex::new(cache::QueryCache::new(capacity))),
+            },
+            query_limiter: limits::QueryLimiter::new(config.max_concurrent_queries),
+            subscribers: tail::Subscribers::default(),
+            blooms: HashMap::new(),
+            dirty_blooms: HashSet::new(),
+            recorder: metrics::Recorder::default(),
+            max_line_size: config.max_line_size,
+            oversized_line_policy: config.oversized_line_policy,
+            recovery_report,
+        };
+
+        // Bloom filters are persisted lazily, so any that are older than their log file are
+        // rebuilt.
+        let keys: Vec<_> = database.files.keys().cloned().collect();
+        for key in keys {
+            let modified = database.files[&key].metadata()?.modified()?;
+            match blooms.remove(&key) {
+                Some((filter, bloom_modified)) if bloom_modified >= modified => {
+                    database.blooms.insert(key, filter);
+                }
+                _ => database.rebuild_bloom_filter(key)?,
+            }
+        }
+
+        Ok(database)
     }
 
     /// The number of log files currently being persisted.
@@ -130,6 +441,85 @@ impl Database {
         self.files.len()
     }
 
+    /// A summary of what was found when the database was opened.
+    #[must_use]
+    pub fn recovery_report(&self) -> &recovery::RecoveryReport {
+        &self.recovery_report
+    }
+
+    /// A snapshot of the database's [metrics](metrics::DatabaseMetrics).
+    #[must_use]
+    pub fn metrics(&self) -> metrics::DatabaseMetrics {
+        self.recorder
+            .snapshot(self.metadata.len(), self.files.len())
+    }
+
+    /// A point-in-time summary of the database's contents and disk usage.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when reading the sizes of log files.
+    pub fn stats(&self) -> io::Result<stats::DatabaseStats> {
+        let mut disk_bytes = 0;
+        for key in self.files.keys() {
+            for extension in &[
+                DATA_FILE_EXTENSION,
+                METADATA_FILE_EXTENSION,
+                BLOOM_FILE_EXTENSION,
+            ] {
+                let mut path = self.path(key);
+                path.set_extension(extension);
+                match fs::metadata(&path) {
+                    Ok(metadata) => disk_bytes += metadata.len(),
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+
+        let mut cardinality = BTreeMap::new();
+        for (key, _) in self.index.keys() {
+            *cardinality.entry(key.clone()).or_default() += 1;
+        }
+
+        Ok(stats::DatabaseStats {
+            disk_bytes,
+            cardinality,
+            metrics: self.metrics(),
+        })
+    }
+
+    /// Flush every log file to disk, and persist any changed bloom filters.
+    ///
+    /// Returns the number of log files that were flushed.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when syncing log files or writing bloom filters.
+    pub fn flush(&mut self) -> io::Result<usize> {
+        for file in self.files.values() {
+            file.sync_data()?;
+        }
+        self.persist_bloom_filters()?;
+        Ok(self.files.len())
+    }
+
+    /// The statistics recorded by [`StatsRecorder`](stats::StatsRecorder)s, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when querying the database, or when parsing the
+    /// stored statistics.
+    pub fn stats_history(&self) -> io::Result<Vec<stats::StatsRecord>> {
+        let lines = self
+            .query(stats::STATS_STREAM_KEY, stats::STATS_STREAM_VALUE)?
+            .unwrap_or_default();
+        lines
+            .iter()
+            .map(|line| serde_json::from_str(line).map_err(io::Error::from))
+            .collect()
+    }
+
     /// An iterator of the keys currently in the index.
     #[must_use]
     pub fn index_keys(&self) -> hash_map::Keys<'_, (String, String), HashSet<String>> {
@@ -140,186 +530,2430 @@ impl Database {
     ///
     /// Propagates any `io::Error` that occurs when querying the database.
     pub fn query(&self, key: &str, value: &str) -> io::Result<Option<Vec<String>>> {
+        Ok(self.query_with_stats(key, value)?.0)
+    }
+
+    /// Query the database, additionally returning [`QueryStats`] describing the work done.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when querying the database.
+    pub fn query_with_stats(
+        &self,
+        key: &str,
+        value: &str,
+    ) -> io::Result<(Option<Vec<String>>, QueryStats)> {
+        self.query_filtered(key, value, None)
+    }
+
+    /// Query the database, returning only the lines that match `filter`.
+    ///
+    /// For [`LineFilter::Contains`](filter::LineFilter::Contains) filters, log files whose bloom
+    /// filters show they cannot contain the text are skipped without being read. Filtered results
+    /// are never cached.
+    ///
+    /// # Errors
+    ///
+    /// - If [`Config::max_concurrent_queries`] queries are already running, an error wrapping
+    ///   [`limits::TooBusy`] is returned.
+    /// - Propagates any other `io::Error` that occurs when querying the database.
+    pub fn query_filtered(
+        &self,
+        key: &str,
+        value: &str,
+        filter: Option<&filter::LineFilter>,
+    ) -> io::Result<(Option<Vec<String>>, QueryStats)> {
+        let _permit = self.query_limiter.acquire()?;
+        let start = Instant::now();
+        let result = self.query_filtered_inner(key, value, filter);
+        self.recorder.record_query(start.elapsed());
+        result
+    }
+
+    fn query_filtered_inner(
+        &self,
+        key: &str,
+        value: &str,
+        filter: Option<&filter::LineFilter>,
+    ) -> io::Result<(Option<Vec<String>>, QueryStats)> {
+        let mut stats = QueryStats::default();
         let keys = match self.index.get(&(key.to_string(), value.to_string())) {
-            None => return Ok(None),
+            None => return Ok((None, stats)),
             Some(keys) => keys,
         };
 
+        let cache = if filter.is_none() {
+            self.cache.as_ref()
+        } else {
+            None
+        };
+
+        if let Some(cache) = cache {
+            if let Some(lines) = cache.lock().expect("cache lock poisoned").get(key, value) {
+                stats.bytes_returned = lines.iter().map(|line| line.len() as u64).sum();
+                return Ok((Some(lines), stats));
+            }
+        }
+
+        let required_text = filter.and_then(filter::LineFilter::required_text);
         let mut lines = Vec::new();
         for key in keys {
-            if let Some(lines_) = self.read(key)? {
-                lines.extend(lines_);
+            if let Some(text) = required_text {
+                if !self.segment_may_contain(key, text) {
+                    continue;
+                }
+            }
+
+            if let Some((lines_, _)) = self.read_from(key, 0, usize::MAX, &mut stats)? {
+                match filter {
+                    Some(filter) => {
+                        lines.extend(lines_.into_iter().filter(|line| filter.matches(line)));
+                    }
+                    None => lines.extend(lines_),
+                }
             }
         }
+        stats.bytes_returned = lines.iter().map(|line| line.len() as u64).sum();
+
+        if let Some(cache) = cache {
+            cache
+                .lock()
+                .expect("cache lock poisoned")
+                .insert(key, value, lines.clone());
+        }
 
-        Ok(Some(lines))
+        Ok((Some(lines), stats))
     }
 
+    /// Query the database like [`query_filtered`](Self::query_filtered), returning entries with
+    /// their IDs.
+    ///
+    /// Log files are read in a stable order, and results are never cached.
+    ///
     /// # Errors
     ///
-    /// Propagates any `io::Error` that occurs when querying the database.
-    pub fn write(&mut self, entry: &LogEntry) -> io::Result<()> {
-        let key = Self::hash(&entry.metadata);
-
-        for meta in &entry.metadata {
-            let keys = self
-                .index
-                .entry((meta.0.to_string(), meta.1.to_string()))
-                .or_insert_with(|| HashSet::with_capacity(1));
+    /// See [`query_filtered`](Self::query_filtered).
+    pub fn query_entries(
+        &self,
+        key: &str,
+        value: &str,
+        filter: Option<&filter::LineFilter>,
+    ) -> io::Result<(Option<Vec<Entry>>, QueryStats)> {
+        let _permit = self.query_limiter.acquire()?;
+        let start = Instant::now();
+        let mut stats = QueryStats::default();
+        let mut keys = match self.index.get(&(key.to_string(), value.to_string())) {
+            None => return Ok((None, stats)),
+            Some(keys) => keys.iter().collect::<Vec<_>>(),
+        };
+        keys.sort();
 
-            // We'd ideally use `HashSet::get_or_insert_owned`, but it's currently unstable
-            // ([#60896](https://github.com/rust-lang/rust/issues/60896)).
-            if !keys.contains(&key) {
-                keys.insert(key.clone());
+        let mut entries = Vec::new();
+        for key in keys {
+            if let Some((records, _)) = self.read_records_from(key, 0, usize::MAX, &mut stats)? {
+                let records = records
+                    .into_iter()
+                    .filter(|(_, _, line)| filter.map_or(true, |filter| filter.matches(line)))
+                    .map(|(offset, timestamp, line)| Entry {
+                        id: Cursor {
+                            segment: key.clone(),
+                            offset,
+                        },
+                        timestamp,
+                        line,
+                    });
+                entries.extend(records);
             }
         }
+        stats.bytes_returned = entries.iter().map(|entry| entry.line.len() as u64).sum();
+        self.recorder.record_query(start.elapsed());
 
-        let (file, needs_delimeter) = if let Some(file) = self.files.get_mut(&key) {
-            (file, true)
-        } else {
-            let mut entry_path = self.data_directory.clone();
-            entry_path.push(&key);
+        Ok((Some(entries), stats))
+    }
 
-            let mut metadata_path = entry_path;
-            metadata_path.set_extension(METADATA_FILE_EXTENSION);
-            fs::write(&metadata_path, serde_json::to_vec(&entry.metadata)?)?;
+    /// Query a page of at most `limit` lines, optionally resuming from a previous page's `cursor`.
+    ///
+    /// Log files are read in a stable order, so a [`Cursor`] returned in [`Page::next`] can be used
+    /// to continue the query from where the page ended, without re-reading earlier lines. Lines
+    /// written to the log files after a cursor was returned will be included in subsequent pages.
+    ///
+    /// # Errors
+    ///
+    /// - If `limit` is `0`, an error of kind [`io::ErrorKind::InvalidInput`] is returned, since the
+    ///   page's cursor couldn't advance.
+    /// - See [`query_filtered`](Self::query_filtered).
+    pub fn query_page(
+        &self,
+        key: &str,
+        value: &str,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> io::Result<Option<Page>> {
+        self.query_page_filtered(key, value, None, cursor, limit, Direction::Forward)
+    }
 
-            let mut data_path = metadata_path;
-            data_path.set_extension(DATA_FILE_EXTENSION);
+    /// Like [`query_page`](Self::query_page), but only returning lines matching `filter`, and
+    /// reading in the given `direction`.
+    ///
+    /// With [`Direction::Backward`], log files are read in the reverse order, and lines are
+    /// returned newest first. Each page reads whole log files up to the cursor, so paging backward
+    /// through large log files is more expensive than paging forward. Cursors must be used with the
+    /// direction that returned them.
+    ///
+    /// # Errors
+    ///
+    /// See [`query_page`](Self::query_page).
+    pub fn query_page_filtered(
+        &self,
+        key: &str,
+        value: &str,
+        filter: Option<&filter::LineFilter>,
+        cursor: Option<&Cursor>,
+        limit: usize,
+        direction: Direction,
+    ) -> io::Result<Option<Page>> {
+        if limit == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "page limit must be at least 1",
+            ));
+        }
+        let _permit = self.query_limiter.acquire()?;
+        let mut keys = match self.index.get(&(key.to_string(), value.to_string())) {
+            None => return Ok(None),
+            Some(keys) => keys.iter().collect::<Vec<_>>(),
+        };
+        keys.sort();
 
-            let file = OpenOptions::new()
-                .append(true)
-                .create(true)
-                .read(true)
-                .open(&data_path)?;
+        let mut page = match direction {
+            Direction::Forward => self.page_forward(&keys, filter, cursor, limit)?,
+            Direction::Backward => {
+                keys.reverse();
+                self.page_backward(&keys, filter, cursor, limit)?
+            }
+        };
+        page.stats.bytes_returned = page.lines.iter().map(|line| line.len() as u64).sum();
+        Ok(Some(page))
+    }
 
-            // Using `.or_insert` here is annoying since we know there is no entry, but
-            // `hash_map::entry::insert` is unstable
-            // ([#65225](https://github.com/rust-lang/rust/issues/65225)).
-            let file = self.files.entry(key).or_insert(file);
+    fn page_forward(
+        &self,
+        keys: &[&String],
+        filter: Option<&filter::LineFilter>,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> io::Result<Page> {
+        let required_text = filter.and_then(filter::LineFilter::required_text);
+        let mut stats = QueryStats::default();
+        let mut lines = Vec::new();
+        for key in keys {
+            let mut offset = match cursor {
+                Some(cursor) if *key < &cursor.segment => continue,
+                Some(cursor) if *key == &cursor.segment => cursor.offset,
+                _ => 0,
+            };
+            if let Some(text) = required_text {
+                if !self.segment_may_contain(key, text) {
+                    continue;
+                }
+            }
 
-            (file, false)
-        };
+            loop {
+                if lines.len() == limit {
+                    return Ok(Page {
+                        lines,
+                        next: Some(Cursor {
+                            segment: (*key).clone(),
+                            offset,
+                        }),
+                        stats,
+                    });
+                }
 
-        if needs_delimeter {
-            file.write_all(&[DATA_FILE_RECORD_SEPARATOR])?;
+                let remaining = limit - lines.len();
+                let (lines_, next_offset) =
+                    match self.read_from(key, offset, remaining, &mut stats)? {
+                        Some(read) => read,
+                        None => break,
+                    };
+                match filter {
+                    Some(filter) => {
+                        lines.extend(lines_.into_iter().filter(|line| filter.matches(line)));
+                    }
+                    None => lines.extend(lines_),
+                }
+                match next_offset {
+                    Some(next_offset) => offset = next_offset,
+                    None => break,
+                }
+            }
         }
-        file.write_all(entry.line.as_ref())?;
 
-        Ok(())
+        Ok(Page {
+            lines,
+            next: None,
+            stats,
+        })
     }
 
-    fn read(&self, key: &str) -> io::Result<Option<Vec<String>>> {
-        let mut file = match self.files.get(key) {
-            Some(file) => file,
-            None => return Ok(None),
-        };
-
-        file.seek(SeekFrom::Start(0))?;
-        let mut reader = BufReader::new(file);
+    /// Like [`page_forward`](Self::page_forward), but with `keys` in reverse order. Cursors point
+    /// just past the newest line that hasn't been returned from a log file.
+    fn page_backward(
+        &self,
+        keys: &[&String],
+        filter: Option<&filter::LineFilter>,
+        cursor: Option<&Cursor>,
+        limit: usize,
+    ) -> io::Result<Page> {
+        let required_text = filter.and_then(filter::LineFilter::required_text);
+        let mut stats = QueryStats::default();
         let mut lines = Vec::new();
+        for key in keys {
+            let end = match cursor {
+                Some(cursor) if *key > &cursor.segment => continue,
+                Some(cursor) if *key == &cursor.segment => cursor.offset,
+                _ => u64::MAX,
+            };
+            if lines.len() == limit {
+                return Ok(Page {
+                    lines,
+                    next: Some(Cursor {
+                        segment: (*key).clone(),
+                        offset: end,
+                    }),
+                    stats,
+                });
+            }
+            if let Some(text) = required_text {
+                if !self.segment_may_contain(key, text) {
+                    continue;
+                }
+            }
 
-        loop {
-            let mut line_bytes = Vec::new();
-            let bytes_read = reader.read_until(DATA_FILE_RECORD_SEPARATOR, &mut line_bytes)?;
-            if bytes_read == 0 {
-                break;
+            let records = match self.read_records_from(key, 0, usize::MAX, &mut stats)? {
+                Some((records, _)) => records,
+                None => continue,
+            };
+            let mut records = records
+                .into_iter()
+                .rev()
+                .filter(|(offset, _, line)| {
+                    *offset < end && filter.map_or(true, |filter| filter.matches(line))
+                })
+                .peekable();
+            while lines.len() < limit {
+                match records.next() {
+                    Some((_, _, line)) => lines.push(line),
+                    None => break,
+                }
             }
-            if line_bytes.last() == Some(&DATA_FILE_RECORD_SEPARATOR) {
-                line_bytes.pop();
+            if let Some((offset, _, _)) = records.peek() {
+                return Ok(Page {
+                    lines,
+                    next: Some(Cursor {
+                        segment: (*key).clone(),
+                        offset: offset + 1,
+                    }),
+                    stats,
+                });
             }
-            let line = String::from_utf8(line_bytes).map_err(|error| {
-                Self::error(format!(
-                    "corrupt data file for key {}: invalid utf8: {}",
-                    key, error
-                ))
-            })?;
-            lines.push(line);
         }
 
-        Ok(Some(lines))
+        Ok(Page {
+            lines,
+            next: None,
+            stats,
+        })
     }
 
-    fn hash(metadata: &HashMap<String, String>) -> String {
-        let mut digest = [0_u8; 16];
-        for (key, value) in metadata.iter() {
-            let mut context = md5::Context::new();
-            context.consume(key);
-            context.consume(value);
-            let entry_digest = context.compute();
+    /// Query the log files matching every matcher in `selection`.
+    ///
+    /// Log entries aren't timestamped, so [`Selection::start`] and [`Selection::end`] apply to
+    /// whole log files: a log file is skipped if it was last written before `start`, or (where the
+    /// filesystem records creation times) was created after `end`. Log files are read in a stable
+    /// order, and reading stops once [`Selection::limit`] lines matching [`Selection::filters`]
+    /// have been read. Results are never cached.
+    ///
+    /// # Errors
+    ///
+    /// - If `selection` has no matchers, an error of kind [`io::ErrorKind::InvalidInput`] is
+    ///   returned.
+    /// - See [`query_filtered`](Self::query_filtered).
+    pub fn query_selection(&self, selection: &Selection) -> io::Result<(Vec<String>, QueryStats)> {
+        let _permit = self.query_limiter.acquire()?;
+        let start = Instant::now();
+        let mut stats = QueryStats::default();
+        let keys = self.select(selection)?;
 
-            for (digest_byte, entry_byte) in digest.iter_mut().zip(entry_digest.iter()) {
-                *digest_byte ^= entry_byte;
+        let limit = selection.limit.unwrap_or(usize::MAX);
+        let mut lines = Vec::new();
+        for key in keys {
+            if lines.len() == limit {
+                break;
             }
-        }
-        format!("{:x}", md5::Digest(digest))
+            if !self.written_within(key, selection.start, selection.end)?
+                || !self.selection_may_match(key, selection)
+            {
+                continue;
+            }
+            if selection.filters.is_empty() {
+                if let Some((lines_, _)) =
+                    self.read_from(key, 0, limit - lines.len(), &mut stats)?
+                {
+                    lines.extend(lines_);
+                }
+            } else if let Some((lines_, _)) = self.read_from(key, 0, usize::MAX, &mut stats)? {
+                let remaining = limit - lines.len();
+                lines.extend(
+                    lines_
+                        .into_iter()
+                        .filter(|line| selection.matches(line))
+                        .take(remaining),
+                );
+            }
+        }
+        stats.bytes_returned = lines.iter().map(|line| line.len() as u64).sum();
+        self.recorder.record_query(start.elapsed());
+
+        Ok((lines, stats))
+    }
+
+    /// Estimate the number of distinct values of `field` in the log files matching every matcher
+    /// in `selection`, using a [`HyperLogLog`](distinct::HyperLogLog) sketch with `precision`.
+    ///
+    /// Lines are treated as JSON objects, and `field` may use `.` to refer to fields of nested
+    /// objects (as for [projections](projection)). Lines without the field are skipped. Log files
+    /// are selected as for [`query_selection`](Self::query_selection), but
+    /// [`Selection::limit`] is ignored. Lines are scanned one at a time, so only the sketch is kept
+    /// in memory.
+    ///
+    /// # Errors
+    ///
+    /// - If `selection` has no matchers, or `precision` is out of range, an error of kind
+    ///   [`io::ErrorKind::InvalidInput`] is returned.
+    /// - See [`query_filtered`](Self::query_filtered).
+    pub fn count_distinct(
+        &self,
+        selection: &Selection,
+        field: &str,
+        precision: u8,
+    ) -> io::Result<(distinct::DistinctCount, QueryStats)> {
+        let mut sketch = distinct::HyperLogLog::new(precision)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let _permit = self.query_limiter.acquire()?;
+        let start = Instant::now();
+        let mut stats = QueryStats::default();
+
+        let mut lines = 0;
+        for key in self.select(selection)? {
+            if !self.written_within(key, selection.start, selection.end)?
+                || !self.selection_may_match(key, selection)
+            {
+                continue;
+            }
+            self.scan(key, &mut stats, |_, line| {
+                if !selection.matches(line) {
+                    return;
+                }
+                if let Some(value) = projection::field(line, field) {
+                    sketch.insert(&value);
+                    lines += 1;
+                }
+            })?;
+        }
+        self.recorder.record_query(start.elapsed());
+
+        let count = distinct::DistinctCount {
+            field: field.to_string(),
+            estimate: sketch.estimate(),
+            standard_error: sketch.standard_error(),
+            precision: sketch.precision(),
+            lines,
+        };
+        Ok((count, stats))
+    }
+
+    /// Count the lines in the log files matching every matcher in `selection`, that match all of
+    /// its [filters](Selection::filters) and were logged between its [start](Selection::start) and
+    /// [end](Selection::end).
+    ///
+    /// Log files are selected as for [`query_selection`](Self::query_selection), but
+    /// [`Selection::limit`] is ignored. Lines are scanned one at a time, so none are kept in
+    /// memory.
+    ///
+    /// # Errors
+    ///
+    /// - If `selection` has no matchers, an error of kind [`io::ErrorKind::InvalidInput`] is
+    ///   returned.
+    /// - See [`query_filtered`](Self::query_filtered).
+    pub fn count_selection(&self, selection: &Selection) -> io::Result<(usize, QueryStats)> {
+        let _permit = self.query_limiter.acquire()?;
+        let start = Instant::now();
+        let mut stats = QueryStats::default();
+
+        let mut count = 0;
+        for key in self.select(selection)? {
+            if !self.written_within(key, selection.start, selection.end)?
+                || !self.selection_may_match(key, selection)
+            {
+                continue;
+            }
+            self.scan(key, &mut stats, |timestamp, line| {
+                if selection.logged_within(timestamp) && selection.matches(line) {
+                    count += 1;
+                }
+            })?;
+        }
+        self.recorder.record_query(start.elapsed());
+
+        Ok((count, stats))
+    }
+
+    /// Check whether the log file for `key` may contain lines matching `selection`'s filters,
+    /// according to its bloom filter.
+    fn selection_may_match(&self, key: &str, selection: &Selection) -> bool {
+        selection
+            .filters
+            .iter()
+            .filter_map(filter::LineFilter::required_text)
+            .all(|text| self.segment_may_contain(key, text))
+    }
+
+    /// The keys of the log files matching every matcher in `selection`, in a stable order.
+    fn select(&self, selection: &Selection) -> io::Result<Vec<&String>> {
+        let mut matchers = selection.matchers.iter();
+        let mut keys: Vec<_> = match matchers.next() {
+            Some(matcher) => self.index.get(matcher).into_iter().flatten().collect(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "at least one matcher is required",
+                ))
+            }
+        };
+        for matcher in matchers {
+            let matching = self.index.get(matcher);
+            keys.retain(|key| matching.map_or(false, |matching| matching.contains(*key)));
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Call `f` with the timestamp and line of each entry in the log file for `key`, without
+    /// collecting them.
+    ///
+    /// The work done is accumulated into `stats`.
+    fn scan(
+        &self,
+        key: &str,
+        stats: &mut QueryStats,
+        mut f: impl FnMut(Timestamp, &str),
+    ) -> io::Result<()> {
+        let mut file = match self.files.get(key) {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        stats.streams += 1;
+
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(file);
+        let mut line_bytes = Vec::new();
+        loop {
+            line_bytes.clear();
+            let bytes_read = reader.read_until(DATA_FILE_RECORD_SEPARATOR, &mut line_bytes)?;
+            if bytes_read == 0 {
+                return Ok(());
+            }
+            stats.bytes_scanned += bytes_read as u64;
+
+            if line_bytes.last() == Some(&DATA_FILE_RECORD_SEPARATOR) {
+                line_bytes.pop();
+            }
+            let (timestamp, line) = Self::split_record(key, &line_bytes)?;
+            f(timestamp, line);
+        }
+    }
+
+    /// Check whether the log file for `key` may have been written to between `start` and `end`.
+    fn written_within(
+        &self,
+        key: &str,
+        start: Option<SystemTime>,
+        end: Option<SystemTime>,
+    ) -> io::Result<bool> {
+        let metadata = match self.files.get(key) {
+            Some(file) if start.is_some() || end.is_some() => file.metadata()?,
+            _ => return Ok(true),
+        };
+        if let Some(start) = start {
+            if metadata.modified()? < start {
+                return Ok(false);
+            }
+        }
+        if let (Some(end), Ok(created)) = (end, metadata.created()) {
+            if created > end {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Write a [`Record`] to the database, keeping its timestamp.
+    ///
+    /// Log files only store timestamped lines, so a metric sample's value is not stored (samples
+    /// belong in the event [`database`](crate::database)). See [`write`](Self::write).
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when writing the record.
+    pub fn write_record(&mut self, record: Record) -> io::Result<()> {
+        self.write(&LogEntry::from(record))
+    }
+
+    /// Write an entry to the database.
+    ///
+    /// The entry is stored with its [timestamp](LogEntry::timestamp), or the current time if it has
+    /// none. Lines longer than the configured maximum line size are handled according to the
+    /// configured [`OversizedLinePolicy`](limits::OversizedLinePolicy), with every part of a split
+    /// line keeping the entry's timestamp.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when querying the database.
+    pub fn write(&mut self, entry: &LogEntry) -> io::Result<()> {
+        let timestamp = entry.timestamp.unwrap_or_else(record::now);
+        let max_line_size = match self.max_line_size {
+            Some(max_line_size) if entry.line.len() > max_line_size => max_line_size,
+            _ => return self.write_line(&entry.metadata, timestamp, &entry.line),
+        };
+
+        self.recorder.record_oversized();
+        for line in self.oversized_line_policy.apply(&entry.line, max_line_size) {
+            self.write_line(&entry.metadata, timestamp, line)?;
+        }
+        Ok(())
+    }
+
+    fn write_line(
+        &mut self,
+        metadata: &HashMap<String, String>,
+        timestamp: Timestamp,
+        line: &str,
+    ) -> io::Result<()> {
+        self.append_line(metadata, timestamp, line)?;
+        self.subscribers.publish(metadata, timestamp, line);
+        Ok(())
+    }
+
+    /// Append `line`, timestamped with `timestamp`, to the log file for `metadata`, without
+    /// publishing it to subscribers.
+    fn append_line(
+        &mut self,
+        metadata: &HashMap<String, String>,
+        timestamp: Timestamp,
+        line: &str,
+    ) -> io::Result<()> {
+        let key = Self::hash(metadata);
+
+        if let Some(cache) = &mut self.cache {
+            cache
+                .get_mut()
+                .expect("cache lock poisoned")
+                .invalidate(metadata);
+        }
+
+        for meta in metadata {
+            let keys = self
+                .index
+                .entry((meta.0.to_string(), meta.1.to_string()))
+                .or_insert_with(|| HashSet::with_capacity(1));
+
+            // We'd ideally use `HashSet::get_or_insert_owned`, but it's currently unstable
+            // ([#60896](https://github.com/rust-lang/rust/issues/60896)).
+            if !keys.contains(&key) {
+                keys.insert(key.clone());
+            }
+        }
+
+        let (file, needs_delimeter) = if let Some(file) = self.files.get_mut(&key) {
+            (file, true)
+        } else {
+            let directory = match &self.partitioning {
+                Some(partitioning) => partitioning.directory(&self.data_directory, metadata),
+                None => self.data_directory.clone(),
+            };
+            if directory != self.data_directory {
+                fs::create_dir_all(&directory)?;
+                self.directories.insert(key.clone(), directory.clone());
+            }
+            let entry_path = directory.join(&key);
+
+            let mut metadata_path = entry_path;
+            metadata_path.set_extension(METADATA_FILE_EXTENSION);
+            fs::write(&metadata_path, serde_json::to_vec(metadata)?)?;
+            self.metadata.insert(key.clone(), metadata.clone());
+
+            let mut data_path = metadata_path;
+            data_path.set_extension(DATA_FILE_EXTENSION);
+
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .read(true)
+                .open(&data_path)?;
+
+            // Using `.or_insert` here is annoying since we know there is no entry, but
+            // `hash_map::entry::insert` is unstable
+            // ([#65225](https://github.com/rust-lang/rust/issues/65225)).
+            let file = self.files.entry(key.clone()).or_insert(file);
+            self.recorder.record_stream_created();
+
+            (file, false)
+        };
+
+        let mut record = Vec::with_capacity(1 + TIMESTAMP_LEN + line.len());
+        if needs_delimeter {
+            record.push(DATA_FILE_RECORD_SEPARATOR);
+        }
+        push_timestamp(&mut record, timestamp);
+        record.extend_from_slice(line.as_bytes());
+        file.write_all(&record)?;
+        self.recorder
+            .record_write(line.len() + usize::from(needs_delimeter));
+
+        self.blooms
+            .entry(key.clone())
+            .or_insert_with(bloom::BloomFilter::new)
+            .insert_line(line);
+        self.dirty_blooms.insert(key);
+
+        Ok(())
+    }
+
+    /// Subscribe to entries written from now on whose metadata contains every `(key, value)` pair
+    /// in `matchers`.
+    ///
+    /// Returns `None` if there are already [`tail::MAX_SUBSCRIPTIONS`] open subscriptions. See
+    /// [`tail`] for how slow subscribers are handled.
+    pub fn subscribe(&mut self, matchers: Vec<(String, String)>) -> Option<tail::Subscription> {
+        self.subscribers.subscribe(matchers)
+    }
+
+    /// Check whether any log file with the metadata `key=value` may contain `term`.
+    ///
+    /// This uses only the in-memory bloom filters, so it is very cheap. A `false` result is
+    /// definitive, but a `true` result may be a false positive.
+    #[must_use]
+    pub fn may_contain(&self, key: &str, value: &str, term: &str) -> bool {
+        self.index
+            .get(&(key.to_string(), value.to_string()))
+            .map_or(false, |keys| {
+                keys.iter().any(|key| self.segment_may_contain(key, term))
+            })
+    }
+
+    /// Persist any bloom filters that have changed since they were last persisted.
+    ///
+    /// This is also done when the database is dropped, but any filters that are not persisted
+    /// will be rebuilt when the database is next opened.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when writing the filters.
+    pub fn persist_bloom_filters(&mut self) -> io::Result<()> {
+        for key in mem::take(&mut self.dirty_blooms) {
+            if let Some(filter) = self.blooms.get(&key) {
+                let mut path = self.path(&key);
+                path.set_extension(BLOOM_FILE_EXTENSION);
+                fs::write(&path, filter.to_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild the bloom filters for every log file from their contents.
+    ///
+    /// This should be called after any operation that rewrites log files (e.g. compaction).
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when reading log files or writing the filters.
+    pub fn rebuild_bloom_filters(&mut self) -> io::Result<()> {
+        let keys: Vec<_> = self.files.keys().cloned().collect();
+        for key in keys {
+            self.rebuild_bloom_filter(key)?;
+        }
+        self.persist_bloom_filters()
+    }
+
+    fn rebuild_bloom_filter(&mut self, key: String) -> io::Result<()> {
+        let mut filter = bloom::BloomFilter::new();
+        if let Some((lines, _)) = self.read_from(&key, 0, usize::MAX, &mut QueryStats::default())? {
+            for line in lines {
+                filter.insert_line(&line);
+            }
+        }
+        self.blooms.insert(key.clone(), filter);
+        self.dirty_blooms.insert(key);
+        Ok(())
+    }
+
+    fn segment_may_contain(&self, key: &str, term: &str) -> bool {
+        self.blooms
+            .get(key)
+            .map_or(true, |filter| filter.may_contain(term))
+    }
+
+    /// Report the memory used by the index, by metadata key.
+    #[must_use]
+    pub fn index_stats(&self) -> BTreeMap<String, IndexStats> {
+        let mut stats = BTreeMap::<String, IndexStats>::new();
+        for ((key, value), keys) in &self.index {
+            let key_stats = stats.entry(key.clone()).or_default();
+            key_stats.values += 1;
+            key_stats.references += keys.len();
+            key_stats.estimated_bytes += mem::size_of::<((String, String), HashSet<String>)>()
+                + key.capacity()
+                + value.capacity()
+                + keys.capacity() * mem::size_of::<String>()
+                + keys.iter().map(String::capacity).sum::<usize>();
+        }
+        stats
+    }
+
+    /// Compact the index, returning the number of references that were dropped.
+    ///
+    /// This drops references to log files that no longer exist, removes values that no longer
+    /// reference any log files, and shrinks the remaining allocations to fit.
+    pub fn compact_index(&mut self) -> usize {
+        let files = &self.files;
+        let mut dropped = 0;
+
+        self.index.retain(|_, keys| {
+            let len = keys.len();
+            keys.retain(|key| files.contains_key(key));
+            dropped += len - keys.len();
+
+            keys.shrink_to_fit();
+            !keys.is_empty()
+        });
+        self.index.shrink_to_fit();
+        self.metadata.retain(|key, _| files.contains_key(key));
+        self.metadata.shrink_to_fit();
+
+        dropped
+    }
+
+    /// The retention rules of the database, in priority order.
+    #[must_use]
+    pub fn retention_rules(&self) -> &[retention::Rule] {
+        &self.retention
+    }
+
+    /// The retention that applies to each stream, as evaluated by [`apply_retention`].
+    ///
+    /// [`apply_retention`]: Self::apply_retention
+    #[must_use]
+    pub fn stream_retention(&self) -> Vec<retention::StreamRetention> {
+        self.metadata
+            .values()
+            .map(|metadata| retention::StreamRetention {
+                metadata: metadata.clone(),
+                max_age: self.find_rule(metadata).map(|rule| rule.max_age),
+                held: self.holds.is_held(metadata),
+            })
+            .collect()
+    }
+
+    /// The legal holds currently placed on the database.
+    #[must_use]
+    pub fn holds(&self) -> &[hold::Hold] {
+        self.holds.all()
+    }
+
+    /// Place a legal hold, exempting matching log files from retention until it's released.
+    ///
+    /// If a hold already exists with the same selector, it is replaced.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when persisting the hold.
+    pub fn place_hold(&mut self, hold: hold::Hold) -> io::Result<()> {
+        self.holds.place(&self.data_directory, hold)
+    }
+
+    /// Release the legal hold with the given `selector`, returning it if it existed.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when persisting the change.
+    pub fn release_hold(
+        &mut self,
+        selector: &BTreeMap<String, String>,
+    ) -> io::Result<Option<hold::Hold>> {
+        self.holds.release(&self.data_directory, selector)
+    }
+
+    /// The annotations on the database.
+    #[must_use]
+    pub fn annotations(&self) -> &[annotation::Annotation] {
+        self.annotations.all()
+    }
+
+    /// The annotations on the log files matching `key` and `value`.
+    #[must_use]
+    pub fn annotations_for(&self, key: &str, value: &str) -> Vec<&annotation::Annotation> {
+        self.annotations_matching(&[(key.to_string(), value.to_string())])
+    }
+
+    /// The annotations on the log files matching every `(key, value)` pair in `matchers`, or none
+    /// if `matchers` is empty.
+    #[must_use]
+    pub fn annotations_matching(
+        &self,
+        matchers: &[(String, String)],
+    ) -> Vec<&annotation::Annotation> {
+        let selection = Selection {
+            matchers: matchers.to_vec(),
+            ..Selection::default()
+        };
+        let mut annotations: Vec<_> = self
+            .select(&selection)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|key| Some((key, self.metadata.get(key)?)))
+            .flat_map(|(key, metadata)| self.annotations.for_stream(key, metadata))
+            .collect();
+        annotations.sort_by_key(|annotation| annotation.id);
+        annotations.dedup_by_key(|annotation| annotation.id);
+        annotations
+    }
+
+    /// Add an annotation, returning it with its assigned ID and creation time.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidInput`] is returned if the annotation is on an
+    /// entry that doesn't exist (including IDs that don't point at the start of an entry), or on a
+    /// range that ends before it starts. Any other `io::Error` that occurs when persisting the
+    /// annotation is propagated.
+    pub fn annotate(
+        &mut self,
+        annotation: annotation::Annotation,
+    ) -> io::Result<annotation::Annotation> {
+        match &annotation.target {
+            annotation::Target::Entry { entry } => {
+                let cursor: Cursor = entry
+                    .parse()
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+                if !self.is_record_start(&cursor)? {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("no entry with ID `{}`", entry),
+                    ));
+                }
+            }
+            annotation::Target::Range { start, end, .. } => {
+                if end < start {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "range ends before it starts",
+                    ));
+                }
+            }
+        }
+        self.annotations.add(&self.data_directory, annotation)
+    }
+
+    /// Check whether `cursor` points at the start of a record, rather than part way through one or
+    /// past the end of its log file.
+    fn is_record_start(&self, cursor: &Cursor) -> io::Result<bool> {
+        let mut file = match self.files.get(&cursor.segment) {
+            Some(file) => file,
+            None => return Ok(false),
+        };
+        if cursor.offset >= file.metadata()?.len() {
+            return Ok(false);
+        }
+        if cursor.offset == 0 {
+            return Ok(true);
+        }
+
+        // Separators are written before every record but the first.
+        let mut previous = [0];
+        file.seek(SeekFrom::Start(cursor.offset - 1))?;
+        file.read_exact(&mut previous)?;
+        Ok(previous[0] == DATA_FILE_RECORD_SEPARATOR)
+    }
+
+    /// Remove the annotation with the given `id`, returning it if it existed.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when persisting the change.
+    pub fn remove_annotation(&mut self, id: u64) -> io::Result<Option<annotation::Annotation>> {
+        self.annotations.remove(&self.data_directory, id)
+    }
+
+    /// Remove log files that have expired according to the configured retention rules.
+    ///
+    /// Each log file is checked against the retention rules in order, and the first matching rule
+    /// determines its maximum age. A log file's age is the time between `now` and when it was last
+    /// written. Log files that match no rule, or that are subject to a legal hold, are never
+    /// removed.
+    ///
+    /// If log files are [partitioned](partition), the rules of a log file's partition are used
+    /// instead, if it has any. Partitions larger than their `max_bytes` then have their least
+    /// recently written log files removed until they fit.
+    ///
+    /// Returns the number of log files that were removed.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when inspecting or removing log files.
+    pub fn apply_retention(&mut self, now: SystemTime) -> io::Result<usize> {
+        let mut expired = Vec::new();
+        for (key, file) in &self.files {
+            let metadata = match self.metadata.get(key) {
+                Some(metadata) if !self.holds.is_held(metadata) => metadata,
+                _ => continue,
+            };
+            let rule = match self.find_rule(metadata) {
+                Some(rule) => rule,
+                None => continue,
+            };
+
+            let modified = file.metadata()?.modified()?;
+            let age = now.duration_since(modified).unwrap_or_default();
+            if age > rule.max_age {
+                expired.push(key.clone());
+            }
+        }
+
+        for key in &expired {
+            self.remove(key)?;
+            self.recorder.record_stream_expired();
+        }
+
+        Ok(expired.len() + self.apply_partition_budgets()?)
+    }
+
+    /// The retention rule that applies to a log file with `metadata`.
+    fn find_rule(&self, metadata: &HashMap<String, String>) -> Option<&retention::Rule> {
+        match &self.partitioning {
+            Some(partitioning) => partitioning.find_rule(&self.retention, metadata),
+            None => retention::find_rule(&self.retention, metadata),
+        }
+    }
+
+    /// Remove the least recently written log files from partitions that exceed their `max_bytes`,
+    /// returning the number removed.
+    fn apply_partition_budgets(&mut self) -> io::Result<usize> {
+        let partitioning = match &self.partitioning {
+            Some(partitioning) => partitioning,
+            None => return Ok(0),
+        };
+
+        let mut partitions = HashMap::<_, (u64, Vec<_>)>::new();
+        for (key, file) in &self.files {
+            let metadata = match self.metadata.get(key) {
+                Some(metadata) => metadata,
+                None => continue,
+            };
+            let max_bytes = match partitioning
+                .value(metadata)
+                .and_then(|value| partitioning.partition(value))
+                .and_then(|partition| partition.max_bytes.map(|max| (&partition.value, max)))
+            {
+                Some(max_bytes) => max_bytes,
+                None => continue,
+            };
+
+            let file_metadata = file.metadata()?;
+            let (size, candidates) = partitions.entry(max_bytes).or_default();
+            *size += file_metadata.len();
+            if !self.holds.is_held(metadata) {
+                candidates.push((file_metadata.modified()?, file_metadata.len(), key.clone()));
+            }
+        }
+
+        let mut evicted = Vec::new();
+        for ((value, max_bytes), (mut size, mut candidates)) in partitions {
+            if size <= max_bytes {
+                continue;
+            }
+            candidates.sort();
+            let before = evicted.len();
+            for (_, len, key) in candidates {
+                if size <= max_bytes {
+                    break;
+                }
+                size -= len;
+                evicted.push(key);
+            }
+            log::info!(
+                "Partition {}={} exceeded {} bytes, removing {} log files",
+                partitioning.label,
+                value,
+                max_bytes,
+                evicted.len() - before
+            );
+        }
+
+        for key in &evicted {
+            self.remove(key)?;
+            self.recorder.record_stream_expired();
+        }
+        Ok(evicted.len())
+    }
+
+    /// Delete the log files whose metadata contains every `(key, value)` pair in `selector`.
+    ///
+    /// Log files subject to a legal hold are never deleted. If `dry_run` is set nothing is
+    /// removed, but the returned [`deletion::Impact`] describes what would have been.
+    ///
+    /// # Errors
+    ///
+    /// - If `selector` is empty (which would match every log file), an error of kind
+    ///   [`io::ErrorKind::InvalidInput`] is returned.
+    /// - Propagates any `io::Error` that occurs when inspecting or removing log files.
+    pub fn delete(
+        &mut self,
+        selector: &BTreeMap<String, String>,
+        dry_run: bool,
+    ) -> io::Result<deletion::Impact> {
+        if selector.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "selector must not be empty",
+            ));
+        }
+
+        let mut matching: Vec<_> = self
+            .metadata
+            .iter()
+            .filter(|(_, metadata)| {
+                selector
+                    .iter()
+                    .all(|(key, value)| metadata.get(key) == Some(value))
+            })
+            .map(|(key, metadata)| (metadata.clone().into_iter().collect(), key.clone()))
+            .collect();
+        matching.sort();
+
+        let mut impact = deletion::Impact {
+            dry_run,
+            ..deletion::Impact::default()
+        };
+        let mut deleted = Vec::new();
+        for (metadata, key) in matching {
+            if self.holds.is_held(&self.metadata[&key]) {
+                impact.held.push(metadata);
+                continue;
+            }
+            let (entries, bytes) = match self.files.get(&key) {
+                Some(file) => deletion::count_entries(file)?,
+                None => continue,
+            };
+            impact.add(deletion::StreamImpact {
+                metadata,
+                entries,
+                bytes,
+            });
+            deleted.push(key);
+        }
+
+        if !dry_run {
+            for key in &deleted {
+                self.remove(key)?;
+            }
+            log::info!(
+                target: hold::AUDIT_TARGET,
+                "Deleted {} streams ({} entries, {} bytes) matching {:?}",
+                impact.streams.len(),
+                impact.entries,
+                impact.bytes,
+                selector
+            );
+        }
+
+        Ok(impact)
+    }
+
+    /// Determine which streams `relabel` would rewrite.
+    ///
+    /// Each stream in the returned plan should then be rewritten with
+    /// [`relabel_stream`](Self::relabel_stream).
+    ///
+    /// # Errors
+    ///
+    /// If `relabel`'s selector or labels are empty, an error of kind
+    /// [`io::ErrorKind::InvalidInput`] is returned.
+    pub fn relabel_plan(&self, relabel: &relabel::Relabel) -> io::Result<relabel::Plan> {
+        if relabel.selector.is_empty() || relabel.labels.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "selector and labels must not be empty",
+            ));
+        }
+
+        let mut plan = relabel::Plan::default();
+        for metadata in self.metadata.values() {
+            if !relabel.selects(metadata) {
+                continue;
+            }
+            let sorted = metadata.clone().into_iter().collect();
+            if self.holds.is_held(metadata) {
+                plan.held.push(sorted);
+            } else {
+                plan.streams.push(sorted);
+            }
+        }
+        plan.streams.sort();
+        plan.held.sort();
+        Ok(plan)
+    }
+
+    /// Rewrite the stream with `metadata` according to `relabel`.
+    ///
+    /// Moved entries are appended to their new stream before they're removed from this one, so an
+    /// interrupted rewrite may duplicate entries but never loses them. Returns `None` if the stream
+    /// no longer exists, is no longer selected, or is subject to a legal hold.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when reading or writing log files.
+    pub fn relabel_stream(
+        &mut self,
+        metadata: &BTreeMap<String, String>,
+        relabel: &relabel::Relabel,
+    ) -> io::Result<Option<relabel::Outcome>> {
+        let metadata: HashMap<_, _> = metadata.clone().into_iter().collect();
+        let key = Self::hash(&metadata);
+        if !self.files.contains_key(&key)
+            || !relabel.selects(&metadata)
+            || self.holds.is_held(&metadata)
+        {
+            return Ok(None);
+        }
+
+        let (records, _) = self
+            .read_records_from(&key, 0, usize::MAX, &mut QueryStats::default())?
+            .unwrap_or_default();
+        let target = relabel.apply(&metadata);
+        if Self::hash(&target) == key {
+            return Ok(Some(relabel::Outcome {
+                moved: 0,
+                kept: records.len(),
+            }));
+        }
+
+        let (moved, kept): (Vec<_>, Vec<_>) = records
+            .into_iter()
+            .map(|(_, timestamp, line)| (timestamp, line))
+            .partition(|(_, line)| {
+                relabel
+                    .predicate
+                    .as_ref()
+                    .map_or(true, |predicate| predicate.matches(line))
+            });
+        let outcome = relabel::Outcome {
+            moved: moved.len(),
+            kept: kept.len(),
+        };
+        if moved.is_empty() {
+            return Ok(Some(outcome));
+        }
+
+        for (timestamp, line) in &moved {
+            self.append_line(&target, *timestamp, line)?;
+        }
+        if kept.is_empty() {
+            self.remove(&key)?;
+        } else {
+            self.rewrite(&key, &kept)?;
+        }
+
+        log::info!(
+            target: hold::AUDIT_TARGET,
+            "Moved {} entries from {:?} to {:?}",
+            outcome.moved,
+            metadata,
+            target
+        );
+        Ok(Some(outcome))
+    }
+
+    /// Replace the contents of the log file for `key` with `records` of timestamps and lines.
+    fn rewrite(&mut self, key: &str, records: &[(Timestamp, String)]) -> io::Result<()> {
+        let mut contents = Vec::new();
+        for (i, (timestamp, line)) in records.iter().enumerate() {
+            if i != 0 {
+                contents.push(DATA_FILE_RECORD_SEPARATOR);
+            }
+            push_timestamp(&mut contents, *timestamp);
+            contents.extend_from_slice(line.as_bytes());
+        }
+
+        // Write a temporary file and rename it, so a crash can't leave a partial log file.
+        let mut path = self.path(key);
+        path.set_extension(DATA_FILE_EXTENSION);
+        let mut temporary_path = path.clone();
+        temporary_path.set_extension("tmp");
+        fs::write(&temporary_path, &contents)?;
+        fs::rename(&temporary_path, &path)?;
+
+        let file = OpenOptions::new().append(true).read(true).open(&path)?;
+        self.files.insert(key.to_string(), file);
+
+        if let (Some(cache), Some(metadata)) = (&mut self.cache, self.metadata.get(key)) {
+            cache
+                .get_mut()
+                .expect("cache lock poisoned")
+                .invalidate(metadata);
+        }
+        self.rebuild_bloom_filter(key.to_string())?;
+        self.annotations.remove_stream(&self.data_directory, key)
+    }
+
+    /// The path of the log file for `key`, without an extension.
+    fn path(&self, key: &str) -> PathBuf {
+        self.directories
+            .get(key)
+            .unwrap_or(&self.data_directory)
+            .join(key)
+    }
+
+    fn remove(&mut self, key: &str) -> io::Result<()> {
+        self.files.remove(key);
+        self.blooms.remove(key);
+        self.dirty_blooms.remove(key);
+
+        if let Some(metadata) = self.metadata.remove(key) {
+            if let Some(cache) = &mut self.cache {
+                cache
+                    .get_mut()
+                    .expect("cache lock poisoned")
+                    .invalidate(&metadata);
+            }
+
+            for meta in metadata {
+                if let hash_map::Entry::Occupied(mut keys) = self.index.entry(meta) {
+                    keys.get_mut().remove(key);
+                    if keys.get().is_empty() {
+                        keys.remove();
+                    }
+                }
+            }
+        }
+
+        let mut path = self.path(key);
+        self.directories.remove(key);
+        path.set_extension(DATA_FILE_EXTENSION);
+        fs::remove_file(&path)?;
+        path.set_extension(METADATA_FILE_EXTENSION);
+        fs::remove_file(&path)?;
+        path.set_extension(BLOOM_FILE_EXTENSION);
+        match fs::remove_file(&path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+
+        self.annotations.remove_stream(&self.data_directory, key)
+    }
+
+    /// Read up to `limit` lines from the log file for `key`, starting at byte `offset`.
+    ///
+    /// If there are more lines in the file after the last line read, the offset of the next line is
+    /// also returned. The work done is accumulated into `stats`.
+    fn read_from(
+        &self,
+        key: &str,
+        offset: u64,
+        limit: usize,
+        stats: &mut QueryStats,
+    ) -> io::Result<Option<(Vec<String>, Option<u64>)>> {
+        Ok(self
+            .read_records_from(key, offset, limit, stats)?
+            .map(|(records, next)| {
+                let lines = records.into_iter().map(|(_, _, line)| line).collect();
+                (lines, next)
+            }))
+    }
+
+    /// Like [`read_from`](Self::read_from), but also returning the offset and timestamp of each
+    /// line.
+    #[allow(clippy::type_complexity)]
+    fn read_records_from(
+        &self,
+        key: &str,
+        offset: u64,
+        limit: usize,
+        stats: &mut QueryStats,
+    ) -> io::Result<Option<(Vec<(u64, Timestamp, String)>, Option<u64>)>> {
+        let mut file = match self.files.get(key) {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        stats.streams += 1;
+
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+        let mut lines = Vec::new();
+        let mut position = offset;
+
+        while lines.len() < limit {
+            let mut line_bytes = Vec::new();
+            let bytes_read = reader.read_until(DATA_FILE_RECORD_SEPARATOR, &mut line_bytes)?;
+            if bytes_read == 0 {
+                return Ok(Some((lines, None)));
+            }
+            let line_offset = position;
+            position += bytes_read as u64;
+            stats.bytes_scanned += bytes_read as u64;
+
+            // Separators are only written before subsequent records, so a trailing separator tells
+            // us there's more to read.
+            let has_next = line_bytes.last() == Some(&DATA_FILE_RECORD_SEPARATOR);
+            if has_next {
+                line_bytes.pop();
+            }
+            let (timestamp, line) = Self::split_record(key, &line_bytes)?;
+            stats.bytes_returned += line.len() as u64;
+            lines.push((line_offset, timestamp, line.to_string()));
+
+            if !has_next {
+                return Ok(Some((lines, None)));
+            }
+        }
+
+        Ok(Some((lines, Some(position))))
+    }
+
+    /// Split a record read from the log file for `key` into its timestamp and line.
+    fn split_record<'a>(key: &str, record: &'a [u8]) -> io::Result<(Timestamp, &'a str)> {
+        let timestamp = record
+            .get(..TIMESTAMP_LEN)
+            .and_then(|timestamp| std::str::from_utf8(timestamp).ok())
+            .and_then(|timestamp| Timestamp::from_str_radix(timestamp, 16).ok())
+            .ok_or_else(|| {
+                Self::error(format!(
+                    "corrupt data file for key {}: invalid timestamp",
+                    key
+                ))
+            })?;
+        let line = std::str::from_utf8(&record[TIMESTAMP_LEN..]).map_err(|error| {
+            Self::error(format!(
+                "corrupt data file for key {}: invalid utf8: {}",
+                key, error
+            ))
+        })?;
+        Ok((timestamp, line))
+    }
+
+    fn hash(metadata: &HashMap<String, String>) -> String {
+        let mut digest = [0_u8; 16];
+        for (key, value) in metadata.iter() {
+            let mut context = md5::Context::new();
+            context.consume(key);
+            context.consume(value);
+            let entry_digest = context.compute();
+
+            for (digest_byte, entry_byte) in digest.iter_mut().zip(entry_digest.iter()) {
+                *digest_byte ^= entry_byte;
+            }
+        }
+        format!("{:x}", md5::Digest(digest))
+    }
+
+    /// Determine the type and key of the file at `path`, or why it isn't a database file.
+    fn classify(path: &Path, metadata: &fs::Metadata) -> Result<(FileType, String), String> {
+        let file_type = match path.extension().and_then(OsStr::to_str) {
+            Some(DATA_FILE_EXTENSION) => FileType::DataFile,
+            Some(METADATA_FILE_EXTENSION) => FileType::MetadataFile,
+            Some(BLOOM_FILE_EXTENSION) => FileType::BloomFile,
+            _ => {
+                return Err(format!(
+                    "extension must be `{}`, `{}`, or `{}`",
+                    DATA_FILE_EXTENSION, METADATA_FILE_EXTENSION, BLOOM_FILE_EXTENSION
+                ))
+            }
+        };
+
+        if !metadata.is_file() {
+            return Err("not a file".to_string());
+        }
+
+        match path.file_stem().map(OsStr::to_str) {
+            None => Err("empty file stem".to_string()),
+            Some(None) => Err("non-utf8 file name".to_string()),
+            Some(Some(key_hash)) => Ok((file_type, key_hash.to_string())),
+        }
+    }
+
+    fn error(message: String) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, message)
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        if let Err(error) = self.persist_bloom_filters() {
+            log::warn!("Failed to persist bloom filters: {}", error);
+        }
+    }
+}
+
+/// Append `timestamp` to `record`, as the start of a record (see [`TIMESTAMP_LEN`]).
+fn push_timestamp(record: &mut Vec<u8>, timestamp: Timestamp) {
+    record.extend_from_slice(format!("{:016x}", timestamp).as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Write};
+    use std::time::{Duration, SystemTime};
+
+    use regex::Regex;
+
+    use crate::test::{self, log_entry, temp_database};
+
+    use super::filter::LineFilter;
+    use super::format;
+    use super::hold::Hold;
+    use super::limits::OversizedLinePolicy;
+    use super::partition::{self, Partition, Partitioning};
+    use super::recovery::UnknownFilePolicy;
+    use super::relabel::{Outcome, Predicate, Relabel};
+    use super::{Config, Cursor, Database, Direction, Selection};
+
+    #[test]
+    fn test_new_db() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+
+        assert_eq!(database.query("foo", "bar")?, None);
+
+        database.write(&log_entry("line1", &[("foo", "bar")]))?;
+        assert_eq!(
+            database.query("foo", "bar")?,
+            Some(vec!["line1".to_string()])
+        );
+
+        database.write(&log_entry("line2", &[("foo", "bar")]))?;
+        assert_eq!(
+            database.query("foo", "bar")?,
+            Some(vec!["line1".to_string(), "line2".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_existing_db() -> test::Result {
+        let (tempdir, mut database) = temp_database()?;
+
+        database.write(&log_entry("line1", &[("foo", "bar")]))?;
+        database.write(&log_entry("line2", &[("foo", "bar")]))?;
+        drop(database);
+
+        let config = Config {
+            data_directory: tempdir.path().to_path_buf(),
+            retention: vec![],
+            query_cache_capacity: 0,
+            max_concurrent_queries: None,
+            max_line_size: None,
+            oversized_line_policy: OversizedLinePolicy::default(),
+            unknown_file_policy: UnknownFilePolicy::default(),
+            partitioning: None,
+        };
+        let database = Database::open(config)?;
+
+        assert_eq!(
+            database.query("foo", "bar")?,
+            Some(vec!["line1".to_string(), "line2".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_metadata() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+
+        database.write(&log_entry("line1", &[]))?;
+        database.write(&log_entry("line2", &[("hello", "world")]))?;
+        database.write(&log_entry("line2", &[("hello", "foo")]))?;
+
+        assert_eq!(
+            database.query("hello", "world")?,
+            Some(vec!["line2".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_retention() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let config = Config {
+            data_directory: tempdir.path().to_path_buf(),
+            retention: vec!["namespace=payments:30d".parse()?, ":3d".parse()?],
+            query_cache_capacity: 0,
+            max_concurrent_queries: None,
+            max_line_size: None,
+            oversized_line_policy: OversizedLinePolicy::default(),
+            unknown_file_policy: UnknownFilePolicy::default(),
+            partitioning: None,
+        };
+        let mut database = Database::open(config)?;
+
+        database.write(&log_entry("line1", &[("namespace", "payments")]))?;
+        database.write(&log_entry("line2", &[("namespace", "other")]))?;
+
+        let now = SystemTime::now();
+        assert_eq!(database.apply_retention(now)?, 0);
+
+        let now = now + Duration::from_secs(4 * 24 * 60 * 60);
+        assert_eq!(database.apply_retention(now)?, 1);
+        assert_eq!(database.files_len(), 1);
+        assert_eq!(
+            database.query("namespace", "payments")?,
+            Some(vec!["line1".to_string()])
+        );
+        assert_eq!(database.query("namespace", "other")?, None);
+        assert_eq!(tempdir.path().read_dir()?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_partitioning() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let volume = tempfile::tempdir()?;
+        let config = || Config {
+            data_directory: tempdir.path().to_path_buf(),
+            retention: vec![],
+            query_cache_capacity: 0,
+            max_concurrent_queries: None,
+            max_line_size: None,
+            oversized_line_policy: OversizedLinePolicy::default(),
+            unknown_file_policy: UnknownFilePolicy::Fail,
+            partitioning: Some(Partitioning {
+                label: "namespace".to_string(),
+                partitions: vec![Partition {
+                    value: "payments".to_string(),
+                    directory: Some(volume.path().to_path_buf()),
+                    retention: vec![],
+                    max_bytes: Some(10),
+                }],
+            }),
+        };
+        let mut database = Database::open(config())?;
+
+        database.write(&log_entry(
+            "0123456789ab",
+            &[("namespace", "payments"), ("app", "a")],
+        ))?;
+        database.write(&log_entry("b", &[("namespace", "payments"), ("app", "b")]))?;
+        database.write(&log_entry("line", &[("namespace", "other")]))?;
+        database.write(&log_entry("line", &[("app", "c")]))?;
+
+        // Data and metadata files, plus persisted bloom filters.
+        drop(database);
+        assert_eq!(volume.path().read_dir()?.count(), 6);
+        assert_eq!(
+            tempdir
+                .path()
+                .join(partition::PARTITIONS_DIRECTORY_NAME)
+                .join("other")
+                .read_dir()?
+                .count(),
+            3
+        );
+
+        // Partitions should be found when re-opening the database.
+        let mut database = Database::open(config())?;
+        assert_eq!(database.files_len(), 4);
+        assert_eq!(
+            database.query("namespace", "other")?,
+            Some(vec!["line".to_string()])
+        );
+
+        database.place_hold(Hold {
+            selector: vec![("app".to_string(), "b".to_string())]
+                .into_iter()
+                .collect(),
+            reason: "case 123".to_string(),
+            placed_at: 0,
+        })?;
+        assert_eq!(database.apply_retention(SystemTime::now())?, 1);
+        assert_eq!(database.query("app", "a")?, None);
+        assert_eq!(database.query("app", "b")?, Some(vec!["b".to_string()]));
+        assert_eq!(volume.path().read_dir()?.count(), 3);
+
+        Ok(())
     }
 
-    fn error(message: String) -> io::Error {
-        io::Error::new(io::ErrorKind::Other, message)
+    #[test]
+    fn test_legal_hold() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let config = || Config {
+            data_directory: tempdir.path().to_path_buf(),
+            retention: vec![":1d".parse().unwrap()],
+            query_cache_capacity: 0,
+            max_concurrent_queries: None,
+            max_line_size: None,
+            oversized_line_policy: OversizedLinePolicy::default(),
+            unknown_file_policy: UnknownFilePolicy::default(),
+            partitioning: None,
+        };
+        let mut database = Database::open(config())?;
+
+        database.write(&log_entry("line1", &[("namespace", "payments")]))?;
+        database.write(&log_entry("line2", &[("namespace", "other")]))?;
+
+        let selector: BTreeMap<_, _> = vec![("namespace".to_string(), "payments".to_string())]
+            .into_iter()
+            .collect();
+        database.place_hold(Hold {
+            selector: selector.clone(),
+            reason: "case 123".to_string(),
+            placed_at: 0,
+        })?;
+
+        // Holds should be restored when re-opening the database.
+        drop(database);
+        let mut database = Database::open(config())?;
+        assert_eq!(database.holds().len(), 1);
+
+        let later = SystemTime::now() + Duration::from_secs(2 * 24 * 60 * 60);
+        assert_eq!(database.apply_retention(later)?, 1);
+        assert_eq!(
+            database.query("namespace", "payments")?,
+            Some(vec!["line1".to_string()])
+        );
+
+        assert!(database.release_hold(&selector)?.is_some());
+        assert!(database.holds().is_empty());
+        assert_eq!(database.apply_retention(later)?, 1);
+        assert_eq!(database.query("namespace", "payments")?, None);
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::test::{self, log_entry, temp_database};
+    #[test]
+    fn test_delete() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+
+        database.write(&log_entry(
+            "line1",
+            &[("namespace", "payments"), ("pod", "a")],
+        ))?;
+        database.write(&log_entry(
+            "line2",
+            &[("namespace", "payments"), ("pod", "a")],
+        ))?;
+        database.write(&log_entry(
+            "line3",
+            &[("namespace", "payments"), ("pod", "b")],
+        ))?;
+        database.write(&log_entry("line4", &[("namespace", "other"), ("pod", "a")]))?;
+
+        let selector = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect()
+        };
+        database.place_hold(Hold {
+            selector: selector(&[("pod", "b")]),
+            reason: "case 123".to_string(),
+            placed_at: 0,
+        })?;
+
+        assert!(database.delete(&BTreeMap::new(), true).is_err());
+
+        let impact = database.delete(&selector(&[("namespace", "payments")]), true)?;
+        assert!(impact.dry_run);
+        assert_eq!(impact.streams.len(), 1);
+        assert_eq!(
+            impact.streams[0].metadata,
+            selector(&[("namespace", "payments"), ("pod", "a")])
+        );
+        assert_eq!(impact.streams[0].entries, 2);
+        assert_eq!(impact.entries, 2);
+        assert_eq!(impact.bytes, 43);
+        assert_eq!(
+            impact.held,
+            vec![selector(&[("namespace", "payments"), ("pod", "b")])]
+        );
+        assert_eq!(database.query("namespace", "payments")?.unwrap().len(), 3);
+
+        let deleted = database.delete(&selector(&[("namespace", "payments")]), false)?;
+        assert!(!deleted.dry_run);
+        assert_eq!(deleted.streams, impact.streams);
+        assert_eq!(
+            database.query("namespace", "payments")?,
+            Some(vec!["line3".to_string()])
+        );
+        assert_eq!(
+            database.query("namespace", "other")?,
+            Some(vec!["line4".to_string()])
+        );
 
-    use super::{Config, Database};
+        Ok(())
+    }
 
     #[test]
-    fn test_new_db() -> test::Result {
+    fn test_relabel() -> test::Result {
         let (_tempdir, mut database) = temp_database()?;
 
-        assert_eq!(database.query("foo", "bar")?, None);
+        database.write(&log_entry("a1", &[("app", "old"), ("pod", "a")]))?;
+        database.write(&log_entry("a2", &[("app", "old"), ("pod", "a")]))?;
+        database.write(&log_entry("b1", &[("app", "new"), ("pod", "a")]))?;
+        database.write(&log_entry("c1", &[("app", "old"), ("pod", "c")]))?;
 
-        database.write(&log_entry("line1", &[("foo", "bar")]))?;
+        let labels = |pairs: &[(&str, Option<&str>)]| -> BTreeMap<String, Option<String>> {
+            pairs
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), v.map(str::to_string)))
+                .collect()
+        };
+        let selector = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect()
+        };
+        database.place_hold(Hold {
+            selector: selector(&[("pod", "c")]),
+            reason: "case 123".to_string(),
+            placed_at: 0,
+        })?;
+
+        // Merging moves every entry onto the new labels.
+        let merge = Relabel {
+            selector: selector(&[("app", "old")]),
+            labels: labels(&[("app", Some("new"))]),
+            predicate: None,
+        };
+        assert!(database
+            .relabel_plan(&Relabel {
+                labels: BTreeMap::new(),
+                ..merge.clone()
+            })
+            .is_err());
+        let plan = database.relabel_plan(&merge)?;
         assert_eq!(
-            database.query("foo", "bar")?,
-            Some(vec!["line1".to_string()])
+            plan.streams,
+            vec![selector(&[("app", "old"), ("pod", "a")])]
+        );
+        assert_eq!(plan.held, vec![selector(&[("app", "old"), ("pod", "c")])]);
+        assert_eq!(
+            database.relabel_stream(&plan.streams[0], &merge)?,
+            Some(Outcome { moved: 2, kept: 0 })
         );
+        assert_eq!(database.relabel_stream(&plan.held[0], &merge)?, None);
+        assert_eq!(
+            database.query("app", "new")?,
+            Some(vec!["b1".to_string(), "a1".to_string(), "a2".to_string()])
+        );
+        assert_eq!(database.query("app", "old")?, Some(vec!["c1".to_string()]));
 
-        database.write(&log_entry("line2", &[("foo", "bar")]))?;
+        // Splitting moves only matching entries, and labels can be removed.
+        let split = Relabel {
+            selector: selector(&[("app", "new")]),
+            labels: labels(&[("pod", None), ("tier", Some("batch"))]),
+            predicate: Some(Predicate::Line(LineFilter::Contains("1".to_string()))),
+        };
+        let plan = database.relabel_plan(&split)?;
         assert_eq!(
-            database.query("foo", "bar")?,
-            Some(vec!["line1".to_string(), "line2".to_string()])
+            database.relabel_stream(&plan.streams[0], &split)?,
+            Some(Outcome { moved: 2, kept: 1 })
+        );
+        assert_eq!(
+            database.query("tier", "batch")?,
+            Some(vec!["b1".to_string(), "a1".to_string()])
+        );
+        assert_eq!(database.query("app", "new")?.unwrap().len(), 3);
+        assert_eq!(database.query("pod", "a")?, Some(vec!["a2".to_string()]));
+
+        // A merge that doesn't change the labels leaves the stream alone.
+        assert_eq!(
+            database.relabel_stream(
+                &selector(&[("app", "new"), ("pod", "a")]),
+                &Relabel {
+                    selector: selector(&[("app", "new")]),
+                    labels: labels(&[("app", Some("new"))]),
+                    predicate: None,
+                },
+            )?,
+            Some(Outcome { moved: 0, kept: 1 })
         );
 
         Ok(())
     }
 
     #[test]
-    fn test_existing_db() -> test::Result {
-        let (tempdir, mut database) = temp_database()?;
+    fn test_query_page() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+
+        database.write(&log_entry("line1", &[("foo", "bar"), ("a", "1")]))?;
+        database.write(&log_entry("line2", &[("foo", "bar"), ("a", "1")]))?;
+        database.write(&log_entry("line3", &[("foo", "bar"), ("a", "2")]))?;
+
+        let mut lines = Vec::new();
+        let mut cursor: Option<Cursor> = None;
+        loop {
+            let page = database
+                .query_page("foo", "bar", cursor.as_ref(), 2)?
+                .expect("expected some results");
+            assert!(page.lines.len() <= 2);
+            lines.extend(page.lines);
+
+            // Round-trip the cursor through its opaque representation.
+            cursor = match page.next {
+                Some(next) => Some(next.to_string().parse()?),
+                None => break,
+            };
+        }
+
+        lines.sort();
+        assert_eq!(lines, vec!["line1", "line2", "line3"]);
+
+        assert_eq!(database.query_page("foo", "baz", None, 2)?, None);
+        assert_eq!(
+            database
+                .query_page("foo", "bar", None, 0)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!("nonsense".parse::<Cursor>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_page_filtered() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+
+        for line in &["error 1", "ok 2", "error 3", "error 4", "ok 5"] {
+            database.write(&log_entry(line, &[("foo", "bar")]))?;
+        }
+        database.write(&log_entry("error 6", &[("foo", "bar"), ("a", "1")]))?;
+
+        let filter = LineFilter::Contains("error".to_string());
+        let pages = |direction| -> io::Result<Vec<Vec<String>>> {
+            let mut pages = Vec::new();
+            let mut cursor = None;
+            loop {
+                let page = database
+                    .query_page_filtered(
+                        "foo",
+                        "bar",
+                        Some(&filter),
+                        cursor.as_ref(),
+                        2,
+                        direction,
+                    )?
+                    .expect("expected some results");
+                pages.push(page.lines);
+                cursor = match page.next {
+                    Some(next) => Some(next),
+                    None => break,
+                };
+            }
+            Ok(pages)
+        };
+
+        let forward: Vec<_> = pages(Direction::Forward)?.concat();
+        let mut backward: Vec<_> = pages(Direction::Backward)?.concat();
+        assert_eq!(forward.len(), 4);
+        assert!(forward.iter().all(|line| line.starts_with("error")));
+
+        // Backward pages return the same lines, newest first within each log file.
+        backward.reverse();
+        assert_eq!(backward, forward);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_selection() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+
+        database.write(&log_entry("line1", &[("ns", "prod"), ("app", "api")]))?;
+        database.write(&log_entry("line2", &[("ns", "prod"), ("app", "api")]))?;
+        database.write(&log_entry("line3", &[("ns", "prod"), ("app", "web")]))?;
+        database.write(&log_entry("line4", &[("ns", "dev"), ("app", "api")]))?;
+
+        let matcher = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let mut selection = Selection {
+            matchers: vec![matcher("ns", "prod"), matcher("app", "api")],
+            ..Selection::default()
+        };
+        let (lines, stats) = database.query_selection(&selection)?;
+        assert_eq!(lines, vec!["line1", "line2"]);
+        assert_eq!(stats.streams, 1);
+
+        selection.limit = Some(1);
+        assert_eq!(database.query_selection(&selection)?.0, vec!["line1"]);
+
+        selection.limit = None;
+        selection.start = Some(SystemTime::now() + Duration::from_secs(60));
+        assert!(database.query_selection(&selection)?.0.is_empty());
+
+        selection.start = None;
+        selection.matchers.push(matcher("app", "web"));
+        assert!(database.query_selection(&selection)?.0.is_empty());
+
+        assert!(database.query_selection(&Selection::default()).is_err());
+
+        let selection = Selection {
+            matchers: vec![matcher("app", "api")],
+            filters: vec![LineFilter::Not(Box::new(LineFilter::Contains(
+                "2".to_string(),
+            )))],
+            ..Selection::default()
+        };
+        let (mut lines, _) = database.query_selection(&selection)?;
+        lines.sort();
+        assert_eq!(lines, vec!["line1", "line4"]);
+        assert_eq!(database.count_selection(&selection)?.0, 2);
+
+        let selection = Selection {
+            matchers: vec![matcher("ns", "prod"), matcher("app", "api")],
+            limit: Some(1),
+            filters: vec![LineFilter::Contains("line".to_string())],
+            ..selection
+        };
+        assert_eq!(database.query_selection(&selection)?.0, vec!["line1"]);
+        assert_eq!(database.count_selection(&selection)?.0, 2);
+        assert!(database.count_selection(&Selection::default()).is_err());
+
+        // Only entries logged within the selection's range are counted.
+        let mut entry = log_entry("line5", &[("ns", "prod"), ("app", "api")]);
+        entry.timestamp = Some(1_000);
+        database.write(&entry)?;
+        assert_eq!(database.count_selection(&selection)?.0, 3);
+        let selection = Selection {
+            start: Some(SystemTime::now() - Duration::from_secs(60)),
+            ..selection
+        };
+        assert_eq!(database.count_selection(&selection)?.0, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_distinct() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+
+        for user in &["a", "b", "a", "c"] {
+            let line = format!(r#"{{"user":{{"id":"{}"}},"level":"error"}}"#, user);
+            database.write(&log_entry(&line, &[("ns", "prod"), ("app", "api")]))?;
+        }
+        database.write(&log_entry(
+            r#"{"user":{"id":1}}"#,
+            &[("ns", "prod"), ("app", "web")],
+        ))?;
+        database.write(&log_entry("not json", &[("ns", "prod"), ("app", "web")]))?;
+        database.write(&log_entry(r#"{"user":{"id":"d"}}"#, &[("ns", "dev")]))?;
+
+        let selection = Selection {
+            matchers: vec![("ns".to_string(), "prod".to_string())],
+            ..Selection::default()
+        };
+        let (count, stats) = database.count_distinct(&selection, "user.id", 12)?;
+        assert_eq!(count.estimate, 4);
+        assert_eq!(count.lines, 5);
+        assert_eq!(count.precision, 12);
+        assert_eq!(stats.streams, 2);
+        assert_eq!(stats.bytes_returned, 0);
+
+        assert_eq!(
+            database.count_distinct(&selection, "level", 12)?.0.estimate,
+            1
+        );
+        assert!(database.count_distinct(&selection, "user.id", 2).is_err());
+        assert!(database
+            .count_distinct(&Selection::default(), "user.id", 12)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_subscribe() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+
+        database.write(&log_entry("before", &[("ns", "prod")]))?;
+        let subscription = database
+            .subscribe(vec![("ns".to_string(), "prod".to_string())])
+            .unwrap();
+        let mut line1 = log_entry("line1", &[("ns", "prod"), ("app", "api")]);
+        line1.timestamp = Some(1_000);
+        database.write(&line1)?;
+        database.write(&log_entry("line2", &[("ns", "dev")]))?;
+        database.write(&log_entry("line3", &[("ns", "prod")]))?;
+
+        let entry = async_std::task::block_on(subscription.recv()).unwrap();
+        assert_eq!(*entry, line1);
+        let entry = async_std::task::block_on(subscription.recv()).unwrap();
+        assert_eq!(entry.line, "line3");
+        assert!(entry.timestamp.is_some());
+
+        for _ in 0..=super::tail::BUFFER_SIZE {
+            database.write(&log_entry("flood", &[("ns", "prod")]))?;
+        }
+        assert_eq!(subscription.dropped(), 1);
+
+        drop(database);
+        let mut received = 0;
+        while async_std::task::block_on(subscription.recv()).is_some() {
+            received += 1;
+        }
+        assert_eq!(received, super::tail::BUFFER_SIZE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_cache() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let config = Config {
+            data_directory: tempdir.path().to_path_buf(),
+            retention: vec![],
+            query_cache_capacity: 8,
+            max_concurrent_queries: None,
+            max_line_size: None,
+            oversized_line_policy: OversizedLinePolicy::default(),
+            unknown_file_policy: UnknownFilePolicy::default(),
+            partitioning: None,
+        };
+        let mut database = Database::open(config)?;
 
         database.write(&log_entry("line1", &[("foo", "bar")]))?;
+
+        let (lines, stats) = database.query_with_stats("foo", "bar")?;
+        assert_eq!(lines, Some(vec!["line1".to_string()]));
+        assert_ne!(stats.bytes_scanned, 0);
+
+        let (lines, stats) = database.query_with_stats("foo", "bar")?;
+        assert_eq!(lines, Some(vec!["line1".to_string()]));
+        assert_eq!(stats.bytes_scanned, 0);
+
         database.write(&log_entry("line2", &[("foo", "bar")]))?;
+
+        let (lines, stats) = database.query_with_stats("foo", "bar")?;
+        assert_eq!(lines, Some(vec!["line1".to_string(), "line2".to_string()]));
+        assert_ne!(stats.bytes_scanned, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bloom_filters() -> test::Result {
+        let (tempdir, mut database) = temp_database()?;
+
+        database.write(&log_entry("connection refused", &[("foo", "bar")]))?;
+
+        assert!(database.may_contain("foo", "bar", " refused"));
+        assert!(!database.may_contain("foo", "bar", " accepted "));
+        assert!(!database.may_contain("foo", "baz", " refused"));
+
         drop(database);
 
         let config = Config {
             data_directory: tempdir.path().to_path_buf(),
+            retention: vec![],
+            query_cache_capacity: 0,
+            max_concurrent_queries: None,
+            max_line_size: None,
+            oversized_line_policy: OversizedLinePolicy::default(),
+            unknown_file_policy: UnknownFilePolicy::default(),
+            partitioning: None,
         };
-        let database = Database::open(config)?;
+        let mut database = Database::open(config)?;
+
+        assert!(database.may_contain("foo", "bar", " refused"));
+        assert!(!database.may_contain("foo", "bar", " accepted "));
+
+        database.rebuild_bloom_filters()?;
+        assert!(database.may_contain("foo", "bar", " refused"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_stats_and_compaction() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+
+        database.write(&log_entry("line1", &[("foo", "bar"), ("a", "1")]))?;
+        database.write(&log_entry("line2", &[("foo", "bar"), ("a", "2")]))?;
+
+        let stats = database.index_stats();
+        assert_eq!(stats["foo"].values, 1);
+        assert_eq!(stats["foo"].references, 2);
+        assert_eq!(stats["a"].values, 2);
+        assert_eq!(stats["a"].references, 2);
+        assert!(stats["a"].estimated_bytes > 0);
+
+        // Nothing to compact in a consistent index.
+        assert_eq!(database.compact_index(), 0);
+
+        // Simulate a log file that's gone missing without its index entries being cleaned up.
+        let key = database.metadata.keys().next().unwrap().clone();
+        database.files.remove(&key);
+
+        assert_eq!(database.compact_index(), 2);
+        let stats = database.index_stats();
+        assert_eq!(stats["foo"].references, 1);
+        assert_eq!(stats["a"].values, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_filtered() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
 
+        database.write(&log_entry(
+            "connection refused",
+            &[("foo", "bar"), ("a", "1")],
+        ))?;
+        database.write(&log_entry(
+            "connection accepted by upstream",
+            &[("foo", "bar"), ("a", "1")],
+        ))?;
+        database.write(&log_entry("all good", &[("foo", "bar"), ("a", "2")]))?;
+
+        let filter = LineFilter::Contains("refused".to_string());
+        let (lines, stats) = database.query_filtered("foo", "bar", Some(&filter))?;
+        assert_eq!(lines, Some(vec!["connection refused".to_string()]));
+        assert_eq!(stats.bytes_returned, 18);
+
+        // The log file for `a=2` can't contain ` accepted ` according to its bloom filter.
+        let filter = LineFilter::Contains(" accepted ".to_string());
+        let (lines, stats) = database.query_filtered("foo", "bar", Some(&filter))?;
         assert_eq!(
-            database.query("foo", "bar")?,
-            Some(vec!["line1".to_string(), "line2".to_string()])
+            lines,
+            Some(vec!["connection accepted by upstream".to_string()])
         );
+        assert_eq!(stats.streams, 1);
+
+        let filter = LineFilter::Regex(Regex::new("^(all|connection) (good|refused)$")?);
+        let (lines, _) = database.query_filtered("foo", "bar", Some(&filter))?;
+        let mut lines = lines.unwrap();
+        lines.sort();
+        assert_eq!(lines, vec!["all good", "connection refused"]);
 
         Ok(())
     }
 
     #[test]
-    fn test_query_metadata() -> test::Result {
+    fn test_metrics() -> test::Result {
         let (_tempdir, mut database) = temp_database()?;
 
-        database.write(&log_entry("line1", &[]))?;
-        database.write(&log_entry("line2", &[("hello", "world")]))?;
-        database.write(&log_entry("line2", &[("hello", "foo")]))?;
+        database.write(&log_entry("hello", &[("foo", "bar")]))?;
+        database.write(&log_entry("world", &[("foo", "bar")]))?;
+        database.write(&log_entry("!", &[("foo", "baz")]))?;
+        database.query("foo", "bar")?;
+
+        let metrics = database.metrics();
+        assert_eq!(metrics.entries_written, 3);
+        assert_eq!(metrics.bytes_written, 12);
+        assert_eq!(metrics.active_streams, 2);
+        assert_eq!(metrics.open_file_handles, 2);
+        assert_eq!(metrics.query_latency.count, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_retention() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+        database.retention = vec!["namespace=payments:30d".parse()?, ":3d".parse()?];
 
+        database.write(&log_entry("paid", &[("namespace", "payments")]))?;
+        database.write(&log_entry("other", &[("namespace", "other")]))?;
+
+        let mut retention = database.stream_retention();
+        retention.sort_by_key(|stream| stream.max_age);
+        let max_ages: Vec<_> = retention.iter().map(|stream| stream.max_age).collect();
         assert_eq!(
-            database.query("hello", "world")?,
-            Some(vec!["line2".to_string()])
+            max_ages,
+            vec![
+                Some(Duration::from_secs(3 * 24 * 60 * 60)),
+                Some(Duration::from_secs(30 * 24 * 60 * 60)),
+            ]
+        );
+        assert_eq!(retention[1].metadata["namespace"], "payments");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_oversized_lines() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+        database.max_line_size = Some(5);
+
+        database.write(&log_entry("hello world", &[("foo", "bar")]))?;
+        database.oversized_line_policy = OversizedLinePolicy::Split;
+        database.write(&log_entry("hello world", &[("foo", "bar")]))?;
+        database.oversized_line_policy = OversizedLinePolicy::Drop;
+        database.write(&log_entry("hello world", &[("foo", "bar")]))?;
+        database.write(&log_entry("ok", &[("foo", "bar")]))?;
+
+        assert_eq!(
+            database.query("foo", "bar")?,
+            Some(vec![
+                "hello".to_string(),
+                "hello".to_string(),
+                " worl".to_string(),
+                "d".to_string(),
+                "ok".to_string(),
+            ])
+        );
+        assert_eq!(database.metrics().oversized_entries, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_version() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        let config = || Config {
+            data_directory: tempdir.path().to_path_buf(),
+            retention: vec![],
+            query_cache_capacity: 0,
+            max_concurrent_queries: None,
+            max_line_size: None,
+            oversized_line_policy: OversizedLinePolicy::default(),
+            unknown_file_policy: UnknownFilePolicy::default(),
+            partitioning: None,
+        };
+
+        // A data directory written before the format was versioned.
+        let key = Database::hash(&log_entry("", &[("foo", "bar")]).metadata);
+        let mut contents = b"hello".to_vec();
+        contents.push(super::DATA_FILE_RECORD_SEPARATOR);
+        contents.extend_from_slice(b"world");
+        fs::write(tempdir.path().join(format!("{}.dat", key)), contents)?;
+        fs::write(
+            tempdir.path().join(format!("{}.json", key)),
+            r#"{"foo":"bar"}"#,
+        )?;
+        assert!(Database::open(config()).is_err());
+
+        assert_eq!(format::migrate(tempdir.path(), None)?, vec![0, 1]);
+        let mut database = Database::open(config())?;
+        assert_eq!(
+            database.query("foo", "bar")?,
+            Some(vec!["hello".to_string(), "world".to_string()])
+        );
+
+        // Migrated entries are timestamped, and new entries keep their own timestamps.
+        let mut entry = log_entry("again", &[("foo", "bar")]);
+        entry.timestamp = Some(1_000);
+        database.write(&entry)?;
+        let entries = database.query_entries("foo", "bar", None)?.0.unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries[0].timestamp > 1_000);
+        assert_eq!(entries[0].timestamp, entries[1].timestamp);
+        assert_eq!(entries[2].timestamp, 1_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovery_report() -> test::Result {
+        let (tempdir, mut database) = temp_database()?;
+        database.write(&log_entry("hello", &[("foo", "bar")]))?;
+        database.write(&log_entry("orphan", &[("foo", "baz")]))?;
+        drop(database);
+
+        let key_bar = Database::hash(&log_entry("", &[("foo", "bar")]).metadata);
+        let key_baz = Database::hash(&log_entry("", &[("foo", "baz")]).metadata);
+
+        // Simulate a crash after writing a separator, and a lost metadata file.
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(tempdir.path().join(format!("{}.dat", key_bar)))?;
+        file.write_all(&[super::DATA_FILE_RECORD_SEPARATOR])?;
+        fs::remove_file(tempdir.path().join(format!("{}.json", key_baz)))?;
+
+        let database = Database::open(Config {
+            data_directory: tempdir.path().to_path_buf(),
+            retention: vec![],
+            query_cache_capacity: 0,
+            max_concurrent_queries: None,
+            max_line_size: None,
+            oversized_line_policy: OversizedLinePolicy::default(),
+            unknown_file_policy: UnknownFilePolicy::default(),
+            partitioning: None,
+        })?;
+
+        let report = database.recovery_report();
+        assert_eq!(report.streams, 1);
+        assert_eq!(report.repaired_data_files, vec![(key_bar, 1)]);
+        assert_eq!(report.orphaned_data_files, vec![key_baz]);
+        assert!(report.orphaned_metadata_files.is_empty());
+        assert_eq!(
+            database.query("foo", "bar")?,
+            Some(vec!["hello".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_files() -> test::Result {
+        let (tempdir, mut database) = temp_database()?;
+        database.write(&log_entry("hello", &[("foo", "bar")]))?;
+        drop(database);
+
+        let swap_file = tempdir.path().join(".swp");
+        fs::write(&swap_file, "junk")?;
+        fs::create_dir(tempdir.path().join("subdir.dat"))?;
+
+        let config = |unknown_file_policy| Config {
+            data_directory: tempdir.path().to_path_buf(),
+            retention: vec![],
+            query_cache_capacity: 0,
+            max_concurrent_queries: None,
+            max_line_size: None,
+            oversized_line_policy: OversizedLinePolicy::default(),
+            unknown_file_policy,
+        };
+
+        assert!(Database::open(config(UnknownFilePolicy::Fail)).is_err());
+
+        let database = Database::open(config(UnknownFilePolicy::Ignore))?;
+        let mut skipped = database.recovery_report().skipped_files.clone();
+        skipped.sort();
+        assert_eq!(
+            skipped,
+            vec![swap_file.clone(), tempdir.path().join("subdir.dat")]
+        );
+        assert!(swap_file.exists());
+        drop(database);
+
+        let database = Database::open(config(UnknownFilePolicy::Quarantine))?;
+        assert_eq!(database.recovery_report().skipped_files.len(), 2);
+        assert!(!swap_file.exists());
+        assert!(tempdir.path().join("quarantine/.swp").exists());
+        assert_eq!(
+            database.query("foo", "bar")?,
+            Some(vec!["hello".to_string()])
         );
+        drop(database);
+
+        let database = Database::open(config(UnknownFilePolicy::Fail))?;
+        assert!(database.recovery_report().is_clean());
 
         Ok(())
     }
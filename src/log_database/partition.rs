@@ -0,0 +1,247 @@
+// src/log_database/partition.rs
+//! Partitioning of the on-disk layout by a label, for per-team volumes and quotas.
+//!
+//! With a [`Partitioning`] configured, the log files of streams with the partition `label` are
+//! stored in a subdirectory per value of the label, under [`PARTITIONS_DIRECTORY_NAME`] in the data
+//! directory (e.g. `partitions/payments/` for `namespace=payments`). Streams without the label stay
+//! in the data directory itself.
+//!
+//! Individual values can be configured with a [`Partition`], to:
+//!
+//! - store the partition in another `directory` (e.g. a separately mounted volume),
+//! - apply their own `retention` rules instead of the database's, and
+//! - cap the total size of the partition's log files at `max_bytes`. When retention is applied,
+//!   the least recently written streams in the partition are removed until it fits, except for
+//!   streams under a legal [`Hold`](super::hold::Hold).
+//!
+//! Partitions are configured with JSON like the following, where every field other than `value`
+//! is optional:
+//!
+//! ```json
+//! {
+//!   "value": "payments",
+//!   "directory": "/mnt/payments",
+//!   "retention": ["level=debug:1d", ":30d"],
+//!   "max_bytes": 10000000000
+//! }
+//! ```
+//!
+//! Streams stay where they were created, so changing the partitioning only affects new streams.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::retention::{self, Rule};
+
+/// The name of the subdirectory of the data directory that holds partitions by default.
+pub const PARTITIONS_DIRECTORY_NAME: &str = "partitions";
+
+/// How to partition log files by a label.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Partitioning {
+    /// The label to partition by.
+    pub label: String,
+
+    /// Configuration for specific values of the label.
+    pub partitions: Vec<Partition>,
+}
+
+/// Configuration for the partition of a single value of the partition label.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    /// The value of the partition label.
+    pub value: String,
+
+    /// The directory to store the partition in, if not the default subdirectory.
+    pub directory: Option<PathBuf>,
+
+    /// Retention rules for the partition, in priority order, used instead of the database's.
+    ///
+    /// If empty, the database's rules apply.
+    pub retention: Vec<Rule>,
+
+    /// The maximum total size of the partition's log files, in bytes.
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PartitionConfig {
+    value: String,
+    #[serde(default)]
+    directory: Option<PathBuf>,
+    #[serde(default)]
+    retention: Vec<String>,
+    #[serde(default)]
+    max_bytes: Option<u64>,
+}
+
+impl FromStr for Partition {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let config: PartitionConfig =
+            serde_json::from_str(input).map_err(|error| format!("invalid partition: {}", error))?;
+        let retention = config
+            .retention
+            .iter()
+            .map(|rule| rule.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|error| format!("invalid partition `{}`: {}", config.value, error))?;
+        Ok(Self {
+            value: config.value,
+            directory: config.directory,
+            retention,
+            max_bytes: config.max_bytes,
+        })
+    }
+}
+
+impl Partitioning {
+    /// The value of the partition label in `metadata`, if it has one.
+    #[must_use]
+    pub fn value<'m>(&self, metadata: &'m HashMap<String, String>) -> Option<&'m str> {
+        metadata.get(&self.label).map(String::as_str)
+    }
+
+    /// The configuration for the partition of `value`, if any.
+    #[must_use]
+    pub fn partition(&self, value: &str) -> Option<&Partition> {
+        self.partitions
+            .iter()
+            .find(|partition| partition.value == value)
+    }
+
+    /// The directory in which to store log files for streams with `metadata`.
+    #[must_use]
+    pub fn directory(&self, data_directory: &Path, metadata: &HashMap<String, String>) -> PathBuf {
+        match self.value(metadata) {
+            None => data_directory.to_path_buf(),
+            Some(value) => match self
+                .partition(value)
+                .and_then(|partition| partition.directory.as_ref())
+            {
+                Some(directory) => directory.clone(),
+                None => data_directory
+                    .join(PARTITIONS_DIRECTORY_NAME)
+                    .join(directory_name(value)),
+            },
+        }
+    }
+
+    /// Every directory that may contain partitions' log files.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when listing the default partitions directory.
+    pub fn directories(&self, data_directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let mut directories = Vec::new();
+        let default = data_directory.join(PARTITIONS_DIRECTORY_NAME);
+        if default.is_dir() {
+            for entry in std::fs::read_dir(&default)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    directories.push(path);
+                }
+            }
+        }
+        for partition in &self.partitions {
+            if let Some(directory) = &partition.directory {
+                if directory.is_dir() && !directories.contains(directory) {
+                    directories.push(directory.clone());
+                }
+            }
+        }
+        Ok(directories)
+    }
+
+    /// Find the retention rule that applies to a stream with `metadata`, preferring the rules of
+    /// its partition over the database's `rules`.
+    pub(super) fn find_rule<'r>(
+        &'r self,
+        rules: &'r [Rule],
+        metadata: &HashMap<String, String>,
+    ) -> Option<&'r Rule> {
+        let partition_rules = self
+            .value(metadata)
+            .and_then(|value| self.partition(value))
+            .map(|partition| &partition.retention[..])
+            .filter(|rules| !rules.is_empty());
+        retention::find_rule(partition_rules.unwrap_or(rules), metadata)
+    }
+}
+
+/// The name of the default directory for the partition of `value`.
+///
+/// Characters other than ASCII letters, digits, `-`, and `_` are percent-encoded, so values can't
+/// escape the partitions directory or collide with each other.
+#[must_use]
+pub fn directory_name(value: &str) -> String {
+    let mut name = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            name.push(char::from(byte));
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    if name.is_empty() {
+        name.push('%');
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use super::{directory_name, Partition, Partitioning};
+
+    #[test]
+    fn partition_directories() {
+        let partitioning = Partitioning {
+            label: "ns".to_string(),
+            partitions: vec![
+                r#"{"value": "payments", "directory": "/mnt/payments", "retention": [":1d"]}"#
+                    .parse()
+                    .unwrap(),
+            ],
+        };
+        let metadata = |value: &str| {
+            let mut metadata = HashMap::new();
+            metadata.insert("ns".to_string(), value.to_string());
+            metadata
+        };
+
+        let root = Path::new("/data");
+        assert_eq!(
+            partitioning.directory(root, &metadata("payments")),
+            PathBuf::from("/mnt/payments")
+        );
+        assert_eq!(
+            partitioning.directory(root, &metadata("../etc")),
+            PathBuf::from("/data/partitions/%2E%2E%2Fetc")
+        );
+        assert_eq!(partitioning.directory(root, &HashMap::new()), root);
+
+        let rules = vec![":7d".parse().unwrap()];
+        assert_eq!(
+            partitioning
+                .find_rule(&rules, &metadata("payments"))
+                .map(|rule| rule.max_age),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+        assert_eq!(
+            partitioning
+                .find_rule(&rules, &metadata("other"))
+                .map(|rule| rule.max_age),
+            Some(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+
+        assert_eq!(directory_name(""), "%");
+        assert!(r#"{"value": "x", "retention": ["bad"]}"#.parse::<Partition>().is_err());
+    }
+}
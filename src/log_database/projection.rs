@@ -0,0 +1,186 @@
+// src/log_database/projection.rs
+//! Reformatting of returned log lines, so clients can fetch only what they need from large JSON
+//! lines.
+//!
+//! A [`Projection`] is applied to each line of a query's results, treating the line as a JSON
+//! object (lines that aren't JSON objects have no fields):
+//!
+//! - [`Projection::fields`] (e.g. `level,http.status`) outputs a JSON object of only the given
+//!   fields, like `{"level":"error","http.status":500}`. Missing fields are left out.
+//! - [`Projection::template`] (e.g. `{level}: {message}`) outputs the template with fields
+//!   substituted. String fields are substituted as-is, other fields as JSON, and missing fields as
+//!   nothing. `{{` and `}}` in a template are a literal `{` and `}`.
+//!
+//! Field names may use `.` to refer to fields of nested objects (e.g. `http.status`).
+
+/// A reformatting of log lines.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Projection {
+    /// Output a JSON object of these fields.
+    Fields(Vec<String>),
+
+    /// Output a template with fields substituted.
+    Template(Vec<Segment>),
+}
+
+/// A part of a [`Projection::Template`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Segment {
+    /// Text to output as-is.
+    Literal(String),
+
+    /// A field to substitute.
+    Field(String),
+}
+
+impl Projection {
+    /// Parse a comma-separated list of fields.
+    ///
+    /// # Errors
+    ///
+    /// A message is returned if the list or any field is empty.
+    pub fn fields(fields: &str) -> Result<Self, String> {
+        let fields: Vec<_> = fields.split(',').map(str::trim).collect();
+        if fields.iter().any(|field| field.is_empty()) {
+            return Err(format!(
+                "invalid fields {:?}: empty field",
+                fields.join(",")
+            ));
+        }
+        Ok(Self::Fields(
+            fields.into_iter().map(str::to_string).collect(),
+        ))
+    }
+
+    /// Parse a template, like `{level}: {message}`.
+    ///
+    /// # Errors
+    ///
+    /// A message is returned if the template has an unmatched brace or an empty field.
+    pub fn template(template: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => {
+                                return Err(format!("invalid format {:?}: unclosed '{{'", template))
+                            }
+                            Some(c) => field.push(c),
+                        }
+                    }
+                    if field.is_empty() {
+                        return Err(format!("invalid format {:?}: empty field", template));
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field(field));
+                }
+                '}' => return Err(format!("invalid format {:?}: unmatched '}}'", template)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self::Template(segments))
+    }
+
+    /// Reformat `line`.
+    #[must_use]
+    pub fn apply(&self, line: &str) -> String {
+        let object = match serde_json::from_str(line) {
+            Ok(serde_json::Value::Object(object)) => object,
+            _ => serde_json::Map::new(),
+        };
+
+        match self {
+            Self::Fields(fields) => {
+                let projected: serde_json::Map<_, _> = fields
+                    .iter()
+                    .filter_map(|field| Some((field.clone(), lookup(&object, field)?.clone())))
+                    .collect();
+                serde_json::Value::Object(projected).to_string()
+            }
+            Self::Template(segments) => {
+                let mut output = String::new();
+                for segment in segments {
+                    match segment {
+                        Segment::Literal(literal) => output.push_str(literal),
+                        Segment::Field(field) => match lookup(&object, field) {
+                            Some(serde_json::Value::String(value)) => output.push_str(value),
+                            Some(value) => output.push_str(&value.to_string()),
+                            None => {}
+                        },
+                    }
+                }
+                output
+            }
+        }
+    }
+}
+
+/// The value of the (possibly nested) field `name` of the JSON `line`, if it has one.
+///
+/// String values are returned as-is, and other values as JSON.
+#[must_use]
+pub fn field(line: &str, name: &str) -> Option<String> {
+    let object = match serde_json::from_str(line) {
+        Ok(serde_json::Value::Object(object)) => object,
+        _ => return None,
+    };
+    match lookup(&object, name)? {
+        serde_json::Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Look up the (possibly nested) `field` of `object`.
+fn lookup<'a>(
+    object: &'a serde_json::Map<String, serde_json::Value>,
+    field: &str,
+) -> Option<&'a serde_json::Value> {
+    let mut path = field.split('.');
+    let mut value = object.get(path.next()?)?;
+    for name in path {
+        value = value.as_object()?.get(name)?;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Projection;
+
+    #[test]
+    fn projections() {
+        let line = r#"{"level":"error","message":"refused","http":{"status":503},"big":"..."}"#;
+
+        let fields = Projection::fields("level, http.status,missing").unwrap();
+        assert_eq!(fields.apply(line), r#"{"http.status":503,"level":"error"}"#);
+        assert_eq!(fields.apply("not json"), "{}");
+        assert!(Projection::fields("level,").is_err());
+
+        let template =
+            Projection::template("{{{level}}} {message} ({http.status}){missing}").unwrap();
+        assert_eq!(template.apply(line), "{error} refused (503)");
+        assert_eq!(template.apply("not json"), "{}  ()");
+        assert!(Projection::template("{level").is_err());
+        assert!(Projection::template("level}").is_err());
+        assert!(Projection::template("{}").is_err());
+    }
+}
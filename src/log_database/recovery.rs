@@ -0,0 +1,280 @@
+// src/log_database/recovery.rs
+//! Recovery of the log [`Database`](super::Database) when it's opened.
+//!
+//! If the process dies part way through a write, a data file can be left ending with a record
+//! separator but no record, or with a record cut off part way through its timestamp or a
+//! multi-byte character.
+//! These are repaired when the database is opened, and everything found is summarised in a
+//! [`RecoveryReport`].
+//!
+//! Files that don't belong in the data directory (e.g. editor swap files or `.DS_Store`) are
+//! handled according to an [`UnknownFilePolicy`].
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::{DATA_FILE_RECORD_SEPARATOR, TIMESTAMP_LEN};
+
+/// The name of the subdirectory of the data directory into which unknown files are moved.
+pub const QUARANTINE_DIRECTORY_NAME: &str = "quarantine";
+
+/// What to do with unrecognised files in the data directory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnknownFilePolicy {
+    /// Fail to open the database.
+    Fail,
+
+    /// Leave the file where it is.
+    Ignore,
+
+    /// Move the file into the [`QUARANTINE_DIRECTORY_NAME`] subdirectory.
+    Quarantine,
+}
+
+impl Default for UnknownFilePolicy {
+    fn default() -> Self {
+        Self::Ignore
+    }
+}
+
+impl FromStr for UnknownFilePolicy {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "fail" => Ok(Self::Fail),
+            "ignore" => Ok(Self::Ignore),
+            "quarantine" => Ok(Self::Quarantine),
+            _ => Err(format!(
+                "invalid unknown file policy `{}`: must be `fail`, `ignore`, or `quarantine`",
+                input
+            )),
+        }
+    }
+}
+
+/// A summary of what was found (and repaired) when opening a database.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct RecoveryReport {
+    /// The number of streams discovered.
+    pub streams: usize,
+
+    /// The keys of data files without a metadata file.
+    ///
+    /// These files are kept, but can't be queried.
+    pub orphaned_data_files: Vec<String>,
+
+    /// The keys of metadata files without a data file.
+    ///
+    /// A data file will be created if the stream is written to again.
+    pub orphaned_metadata_files: Vec<String>,
+
+    /// The keys of data files with a truncated trailing record, and the number of bytes removed to
+    /// repair them.
+    pub repaired_data_files: Vec<(String, u64)>,
+
+    /// The paths of unrecognised files that were skipped.
+    ///
+    /// These are the original paths, even if the files were moved into quarantine.
+    pub skipped_files: Vec<PathBuf>,
+}
+
+impl RecoveryReport {
+    /// Check whether anything unexpected was found.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_data_files.is_empty()
+            && self.orphaned_metadata_files.is_empty()
+            && self.repaired_data_files.is_empty()
+            && self.skipped_files.is_empty()
+    }
+}
+
+/// Handle the unrecognised file at `path` according to `policy`.
+///
+/// `reason` describes why the file wasn't recognised. Unless the policy is
+/// [`Fail`](UnknownFilePolicy::Fail), the file is added to `report`.
+pub(super) fn skip_unknown_file(
+    data_directory: &Path,
+    path: &Path,
+    reason: &str,
+    policy: UnknownFilePolicy,
+    report: &mut RecoveryReport,
+) -> io::Result<()> {
+    match policy {
+        UnknownFilePolicy::Fail => {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("invalid data file {}: {}", path.display(), reason),
+            ))
+        }
+        UnknownFilePolicy::Ignore => {}
+        UnknownFilePolicy::Quarantine => {
+            let quarantine = data_directory.join(QUARANTINE_DIRECTORY_NAME);
+            fs::create_dir_all(&quarantine)?;
+            if let Some(file_name) = path.file_name() {
+                fs::rename(path, quarantine.join(file_name))?;
+            }
+        }
+    }
+
+    report.skipped_files.push(path.to_path_buf());
+    Ok(())
+}
+
+/// Remove any truncated trailing record from `file`, returning the number of bytes removed.
+pub(super) fn repair_tail(file: &mut File) -> io::Result<u64> {
+    let original_len = file.metadata()?.len();
+
+    let mut tail = Vec::with_capacity(8);
+    file.seek(SeekFrom::Start(original_len.saturating_sub(8)))?;
+    file.read_to_end(&mut tail)?;
+    let len = original_len - (tail.len() - repaired_len(&tail)) as u64;
+
+    let start = len.saturating_sub(TIMESTAMP_LEN as u64 + 1);
+    let mut tail = Vec::with_capacity(TIMESTAMP_LEN + 1);
+    file.seek(SeekFrom::Start(start))?;
+    file.by_ref().take(len - start).read_to_end(&mut tail)?;
+    let len = start + timestamped_len(&tail, start == 0) as u64;
+
+    let removed = original_len - len;
+    if removed != 0 {
+        file.set_len(len)?;
+    }
+    Ok(removed)
+}
+
+/// The length `tail` should have, after removing a trailing record cut off part way through its
+/// timestamp.
+///
+/// `tail` must be the last (up to `TIMESTAMP_LEN + 1`) bytes of a data file, and `whole_file`
+/// whether it's the entire file.
+fn timestamped_len(tail: &[u8], whole_file: bool) -> usize {
+    let (start, record) = match tail
+        .iter()
+        .rposition(|byte| *byte == DATA_FILE_RECORD_SEPARATOR)
+    {
+        Some(separator) => (separator, &tail[separator + 1..]),
+        None if whole_file => (0, tail),
+        None => return tail.len(),
+    };
+    if !record.is_empty()
+        && record.len() < TIMESTAMP_LEN
+        && record.iter().all(u8::is_ascii_hexdigit)
+    {
+        start
+    } else {
+        tail.len()
+    }
+}
+
+/// The length `tail` should have, after removing any truncated trailing record.
+///
+/// `tail` must be the last (up to 8) bytes of a data file.
+fn repaired_len(tail: &[u8]) -> usize {
+    let end = tail.len();
+    let last = match tail.last() {
+        Some(last) => *last,
+        None => return 0,
+    };
+
+    // A lead byte with none of its continuation bytes.
+    if last >= 0xC0 {
+        return end - 1;
+    }
+
+    // Find the start of the trailing run of continuation bytes (at most 4).
+    let mut start = end;
+    while start > 0 && end - start < 4 && tail[start - 1] & 0xC0 == 0x80 {
+        start -= 1;
+    }
+    let continuations = end - start;
+    if continuations == 0 {
+        return end;
+    }
+
+    // Continuation bytes with no lead byte. The separator is a continuation byte, so this is a
+    // separator written without its record.
+    if start == 0 || tail[start - 1] < 0xC0 {
+        return if last == DATA_FILE_RECORD_SEPARATOR {
+            end - 1
+        } else {
+            end
+        };
+    }
+
+    let lead = tail[start - 1];
+    let expected = if lead >= 0xF0 {
+        3
+    } else if lead >= 0xE0 {
+        2
+    } else {
+        1
+    };
+
+    if continuations < expected {
+        // A character cut off part way through.
+        start - 1
+    } else if continuations == expected + 1 && last == DATA_FILE_RECORD_SEPARATOR {
+        // A complete character followed by a separator without its record.
+        end - 1
+    } else {
+        end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{repaired_len, timestamped_len, UnknownFilePolicy};
+
+    const SEP: u8 = super::DATA_FILE_RECORD_SEPARATOR;
+
+    #[test]
+    fn keeps_complete_records() {
+        assert_eq!(repaired_len(b"hello"), 5);
+        assert_eq!(repaired_len("ē".as_bytes()), 2);
+        assert_eq!(repaired_len(&[b'a', SEP, b'b']), 3);
+        assert_eq!(repaired_len(b""), 0);
+    }
+
+    #[test]
+    fn removes_dangling_separator() {
+        assert_eq!(repaired_len(&[b'a', SEP]), 1);
+
+        // `ē` ends with the same byte as the separator.
+        let mut tail = "ē".as_bytes().to_vec();
+        tail.push(SEP);
+        assert_eq!(repaired_len(&tail), 2);
+    }
+
+    #[test]
+    fn removes_truncated_character() {
+        let bytes = "a€".as_bytes();
+        assert_eq!(repaired_len(&bytes[..3]), 1);
+        assert_eq!(repaired_len(&bytes[..2]), 1);
+    }
+
+    #[test]
+    fn removes_truncated_timestamp() {
+        let mut tail = b"0175".to_vec();
+        assert_eq!(timestamped_len(&tail, true), 0);
+        assert_eq!(timestamped_len(&tail, false), 4);
+
+        tail.insert(0, SEP);
+        assert_eq!(timestamped_len(&tail, false), 0);
+
+        let complete = b"00000175e0b1c000".to_vec();
+        assert_eq!(timestamped_len(&complete, true), 16);
+        let mut tail = vec![b'a', SEP];
+        tail.extend_from_slice(&complete);
+        assert_eq!(timestamped_len(&tail, false), 18);
+    }
+
+    #[test]
+    fn parse_unknown_file_policies() {
+        assert_eq!("quarantine".parse(), Ok(UnknownFilePolicy::Quarantine));
+        assert!("delete".parse::<UnknownFilePolicy>().is_err());
+    }
+}
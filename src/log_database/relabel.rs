@@ -0,0 +1,131 @@
+// src/log_database/relabel.rs
+//! Moving entries between streams of the log [`Database`](super::Database), e.g. after a change
+//! to relabelling rules.
+//!
+//! A [`Relabel`] selects streams by their metadata and sets (or removes) labels on them:
+//!
+//! - Without a predicate it *merges* streams: every entry of each selected stream is moved to the
+//!   stream with the relabelled metadata, so streams that end up with the same labels are combined.
+//! - With a [`Predicate`] it *splits* streams: only the matching entries are moved, and the rest
+//!   stay where they were.
+//!
+//! Streams are rewritten one at a time with
+//! [`Database::relabel_stream`](super::Database::relabel_stream), so that a long migration can run
+//! in the background without blocking writes. Streams subject to a legal hold are never rewritten.
+//!
+//! Moved entries are appended to their new stream, and annotations on the entries of rewritten
+//! streams are removed (since their IDs change).
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::filter::LineFilter;
+
+/// Which entries of a stream a [`Relabel`] moves.
+#[derive(Clone, Debug)]
+pub enum Predicate {
+    /// Move entries whose line matches the filter.
+    Line(LineFilter),
+
+    /// Move entries whose line is a JSON object with the field `name` equal to `value`.
+    ///
+    /// Non-string field values are compared by their JSON representation (e.g. `500` or `true`).
+    Field {
+        /// The name of the field.
+        name: String,
+
+        /// The value the field must have.
+        value: String,
+    },
+}
+
+impl Predicate {
+    /// Check whether `line` matches this predicate.
+    #[must_use]
+    pub fn matches(&self, line: &str) -> bool {
+        match self {
+            Self::Line(filter) => filter.matches(line),
+            Self::Field { name, value } => match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(serde_json::Value::Object(mut object)) => match object.remove(name) {
+                    Some(serde_json::Value::String(field)) => field == *value,
+                    Some(field) => field.to_string() == *value,
+                    None => false,
+                },
+                _ => false,
+            },
+        }
+    }
+}
+
+/// A rewrite of the metadata of the streams matching a selector.
+#[derive(Clone, Debug)]
+pub struct Relabel {
+    /// The `(key, value)` pairs that a stream's metadata must contain to be rewritten.
+    pub selector: BTreeMap<String, String>,
+
+    /// The labels to set on moved entries, or to remove if `None`.
+    pub labels: BTreeMap<String, Option<String>>,
+
+    /// Which entries to move, or `None` to move every entry (merging streams).
+    pub predicate: Option<Predicate>,
+}
+
+impl Relabel {
+    /// Check whether a stream with `metadata` is selected.
+    pub(super) fn selects(&self, metadata: &HashMap<String, String>) -> bool {
+        self.selector
+            .iter()
+            .all(|(key, value)| metadata.get(key) == Some(value))
+    }
+
+    /// The metadata of the stream that entries with `metadata` are moved to.
+    pub(super) fn apply(&self, metadata: &HashMap<String, String>) -> HashMap<String, String> {
+        let mut relabelled = metadata.clone();
+        for (key, value) in &self.labels {
+            match value {
+                Some(value) => relabelled.insert(key.clone(), value.clone()),
+                None => relabelled.remove(key),
+            };
+        }
+        relabelled
+    }
+}
+
+/// The streams that a [`Relabel`] will rewrite, from
+/// [`Database::relabel_plan`](super::Database::relabel_plan).
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct Plan {
+    /// The metadata of the streams to rewrite, in order.
+    pub streams: Vec<BTreeMap<String, String>>,
+
+    /// The metadata of selected streams that are skipped because of a legal hold.
+    pub held: Vec<BTreeMap<String, String>>,
+}
+
+/// The result of rewriting a single stream with
+/// [`Database::relabel_stream`](super::Database::relabel_stream).
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize)]
+pub struct Outcome {
+    /// The number of entries moved to another stream.
+    pub moved: usize,
+
+    /// The number of entries left in the stream.
+    pub kept: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Predicate;
+
+    #[test]
+    fn field_predicates() {
+        let predicate = Predicate::Field {
+            name: "status".to_string(),
+            value: "500".to_string(),
+        };
+        assert!(predicate.matches(r#"{"status":500}"#));
+        assert!(predicate.matches(r#"{"status":"500"}"#));
+        assert!(!predicate.matches(r#"{"status":200}"#));
+        assert!(!predicate.matches(r#"{"code":500}"#));
+        assert!(!predicate.matches("status=500"));
+    }
+}
@@ -0,0 +1,183 @@
+// src/log_database/retention.rs
+//! Retention rules for the log [`Database`](super::Database).
+//!
+//! Retention is configured as an ordered list of [`Rule`]s. When the retention job runs, each
+//! stream is checked against the rules in order and the first rule whose selector matches the
+//! stream's metadata determines how long the stream is kept. Streams that match no rules are kept
+//! indefinitely.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A retention rule for streams matching a label selector.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    /// The `(key, value)` pairs that a stream's metadata must contain for the rule to apply.
+    ///
+    /// An empty selector matches every stream, which is useful as a final catch-all rule.
+    pub selector: Vec<(String, String)>,
+
+    /// How long a matching stream is kept after it was last written to.
+    pub max_age: Duration,
+}
+
+impl Rule {
+    /// Check whether this rule applies to a stream with the given `metadata`.
+    #[must_use]
+    pub fn matches(&self, metadata: &HashMap<String, String>) -> bool {
+        self.selector
+            .iter()
+            .all(|(key, value)| metadata.get(key) == Some(value))
+    }
+}
+
+/// The retention that applies to a single stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamRetention {
+    /// The stream's metadata.
+    pub metadata: HashMap<String, String>,
+
+    /// How long the stream is kept after it was last written to, from the first matching rule.
+    ///
+    /// `None` if no rule matches, in which case the stream is kept indefinitely.
+    pub max_age: Option<Duration>,
+
+    /// Whether the stream is exempt from retention due to a legal [`Hold`](super::hold::Hold).
+    pub held: bool,
+}
+
+/// Parse a rule from a string like `namespace=payments,app=api:30d`.
+///
+/// The selector part may be empty (e.g. `:3d`), in which case the rule matches all streams.
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut parts = input.rsplitn(2, ':');
+
+        // `unwrap` is OK because `rsplitn` always yields at least one item.
+        let max_age = parts.next().unwrap();
+        let selector = parts
+            .next()
+            .ok_or_else(|| format!("invalid retention rule {}: missing `:<max age>`", input))?;
+
+        let max_age = parse_duration(max_age)
+            .map_err(|error| format!("invalid retention rule {}: {}", input, error))?;
+
+        let selector = selector
+            .split(',')
+            .filter(|matcher| !matcher.is_empty())
+            .map(|matcher| {
+                let mut parts = matcher.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) if !key.is_empty() => {
+                        Ok((key.to_string(), value.to_string()))
+                    }
+                    _ => Err(format!(
+                        "invalid retention rule {}: matcher `{}` must be `key=value`",
+                        input, matcher
+                    )),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Rule { selector, max_age })
+    }
+}
+
+/// Find the first rule in `rules` that applies to a stream with the given `metadata`.
+pub(super) fn find_rule<'r>(
+    rules: &'r [Rule],
+    metadata: &HashMap<String, String>,
+) -> Option<&'r Rule> {
+    rules.iter().find(|rule| rule.matches(metadata))
+}
+
+/// Parse a duration like `250ms`, `30s`, `15m`, `12h`, or `30d`.
+///
+/// A bare number is interpreted as seconds.
+///
+/// # Errors
+///
+/// Returns a description of the problem if `input` is not a valid duration.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let (number, multiplier) = if let Some(number) = input.strip_suffix("ms") {
+        (number, 1)
+    } else {
+        match input.char_indices().last() {
+            Some((index, 's')) => (&input[..index], 1000),
+            Some((index, 'm')) => (&input[..index], 60 * 1000),
+            Some((index, 'h')) => (&input[..index], 60 * 60 * 1000),
+            Some((index, 'd')) => (&input[..index], 24 * 60 * 60 * 1000),
+            _ => (input, 1000),
+        }
+    };
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`", input))?;
+
+    number
+        .checked_mul(multiplier)
+        .map(Duration::from_millis)
+        .ok_or_else(|| format!("duration `{}` is too large", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{find_rule, parse_duration, Rule};
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!(
+            "namespace=payments,app=api:30d".parse(),
+            Ok(Rule {
+                selector: vec![
+                    ("namespace".to_string(), "payments".to_string()),
+                    ("app".to_string(), "api".to_string())
+                ],
+                max_age: Duration::from_secs(30 * 24 * 60 * 60),
+            })
+        );
+        assert_eq!(
+            ":3d".parse(),
+            Ok(Rule {
+                selector: vec![],
+                max_age: Duration::from_secs(3 * 24 * 60 * 60),
+            })
+        );
+        assert!("namespace=payments".parse::<Rule>().is_err());
+        assert!("namespace:3d".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert!(parse_duration("soon").is_err());
+    }
+
+    #[test]
+    fn rules_evaluated_in_order() -> Result<(), String> {
+        let rules: Vec<Rule> = vec!["namespace=payments:30d".parse()?, ":3d".parse()?];
+
+        let payments = vec![("namespace".to_string(), "payments".to_string())]
+            .into_iter()
+            .collect();
+        let other = vec![("namespace".to_string(), "other".to_string())]
+            .into_iter()
+            .collect();
+
+        assert_eq!(find_rule(&rules, &payments), Some(&rules[0]));
+        assert_eq!(find_rule(&rules, &other), Some(&rules[1]));
+        assert_eq!(find_rule(&rules[..1], &other), None);
+
+        Ok(())
+    }
+}
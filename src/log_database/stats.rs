@@ -0,0 +1,161 @@
+// src/log_database/stats.rs
+//! Periodic statistics, persisted for capacity planning.
+//!
+//! A [`StatsRecorder`] periodically writes a [`StatsRecord`] summarising the preceding period
+//! (bytes ingested, streams created and expired, and queries made) into a dedicated internal
+//! stream, identified by the [`STATS_STREAM_KEY`]=[`STATS_STREAM_VALUE`] metadata. Since the
+//! history is stored in the database itself, it's subject to the same retention rules as any other
+//! stream, and survives restarts.
+//!
+//! A [`DatabaseStats`] summarises the database's current contents and disk usage on demand.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::LogEntry;
+
+use super::metrics::DatabaseMetrics;
+use super::Database;
+
+/// The metadata key of the internal stream in which statistics are stored.
+pub const STATS_STREAM_KEY: &str = "monitoring_rs_internal";
+
+/// The metadata value of the internal stream in which statistics are stored.
+pub const STATS_STREAM_VALUE: &str = "stats";
+
+/// The statistics for a single period.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct StatsRecord {
+    /// The start of the period, in seconds since the Unix epoch.
+    pub start: u64,
+
+    /// The end of the period, in seconds since the Unix epoch.
+    pub end: u64,
+
+    /// The number of entries written during the period.
+    pub entries_ingested: u64,
+
+    /// The number of bytes written to log files during the period.
+    pub bytes_ingested: u64,
+
+    /// The number of streams created during the period.
+    pub streams_created: u64,
+
+    /// The number of streams removed by retention during the period.
+    pub streams_expired: u64,
+
+    /// The number of streams stored at the end of the period.
+    pub active_streams: usize,
+
+    /// The number of queries made during the period.
+    pub queries: u64,
+}
+
+/// A point-in-time summary of the database, taken with [`Database::stats`].
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct DatabaseStats {
+    /// The total size of the log, metadata, and bloom filter files of every stream, in bytes.
+    pub disk_bytes: u64,
+
+    /// The number of distinct values of each metadata key.
+    pub cardinality: BTreeMap<String, usize>,
+
+    /// The database's metrics.
+    pub metrics: DatabaseMetrics,
+}
+
+/// Records [`StatsRecord`]s for the periods between calls to [`record`](Self::record).
+#[derive(Debug)]
+pub struct StatsRecorder {
+    start: SystemTime,
+    baseline: DatabaseMetrics,
+}
+
+impl StatsRecorder {
+    /// Start recording statistics for `database`, from `now`.
+    #[must_use]
+    pub fn new(database: &Database, now: SystemTime) -> Self {
+        Self {
+            start: now,
+            baseline: database.metrics(),
+        }
+    }
+
+    /// Write the statistics for the period since the last call (or [`new`](Self::new)) to
+    /// `database`, and start a new period from `now`.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that occurs when writing to the database.
+    pub fn record(&mut self, database: &mut Database, now: SystemTime) -> io::Result<StatsRecord> {
+        let metrics = database.metrics();
+        let record = StatsRecord {
+            start: unix_seconds(self.start),
+            end: unix_seconds(now),
+            entries_ingested: metrics.entries_written - self.baseline.entries_written,
+            bytes_ingested: metrics.bytes_written - self.baseline.bytes_written,
+            streams_created: metrics.streams_created - self.baseline.streams_created,
+            streams_expired: metrics.streams_expired - self.baseline.streams_expired,
+            active_streams: metrics.active_streams,
+            queries: metrics.query_latency.count - self.baseline.query_latency.count,
+        };
+
+        let mut metadata = HashMap::new();
+        metadata.insert(STATS_STREAM_KEY.to_string(), STATS_STREAM_VALUE.to_string());
+        database.write(&LogEntry {
+            line: serde_json::to_string(&record)?,
+            metadata,
+            timestamp: None,
+        })?;
+
+        // Start the next period after our own write, so it isn't counted.
+        self.start = now;
+        self.baseline = database.metrics();
+        Ok(record)
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::test::{self, log_entry, temp_database};
+
+    use super::StatsRecorder;
+
+    #[test]
+    fn record_history() -> test::Result {
+        let (_tempdir, mut database) = temp_database()?;
+        let mut recorder = StatsRecorder::new(&database, UNIX_EPOCH);
+
+        database.write(&log_entry("hello", &[("foo", "bar")]))?;
+        database.write(&log_entry("world", &[("foo", "bar")]))?;
+        database.query("foo", "bar")?;
+        let first = recorder.record(&mut database, UNIX_EPOCH + Duration::from_secs(60))?;
+
+        database.write(&log_entry("again", &[("foo", "baz")]))?;
+        let second = recorder.record(&mut database, UNIX_EPOCH + Duration::from_secs(120))?;
+
+        assert_eq!((first.start, first.end), (0, 60));
+        assert_eq!(first.entries_ingested, 2);
+        assert_eq!(first.bytes_ingested, 11);
+        assert_eq!(first.streams_created, 1);
+        assert_eq!(first.queries, 1);
+
+        assert_eq!((second.start, second.end), (60, 120));
+        assert_eq!(second.entries_ingested, 1);
+        assert_eq!(second.streams_created, 1);
+        assert_eq!(second.queries, 0);
+
+        assert_eq!(database.stats_history()?, vec![first, second]);
+
+        Ok(())
+    }
+}
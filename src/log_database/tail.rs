@@ -0,0 +1,134 @@
+// src/log_database/tail.rs
+//! Subscriptions to entries as they're written to the log [`Database`](super::Database).
+//!
+//! [`Database::subscribe`](super::Database::subscribe) returns a [`Subscription`] that receives
+//! every subsequently written entry whose metadata contains all of its matchers. Writes never wait
+//! for subscribers: each subscription buffers up to [`BUFFER_SIZE`] entries, and entries written
+//! while a subscription's buffer is full are dropped (and counted) for that subscription alone.
+//!
+//! At most [`MAX_SUBSCRIPTIONS`] subscriptions may be open at once. A subscription stops counting
+//! towards the limit as soon as it (and all its clones) are dropped. Each published entry is
+//! allocated once and shared between the subscriptions it matches.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::record::Timestamp;
+use crate::LogEntry;
+
+/// The number of entries buffered for each subscription.
+pub const BUFFER_SIZE: usize = 1024;
+
+/// The maximum number of open subscriptions.
+pub const MAX_SUBSCRIPTIONS: usize = 256;
+
+/// A subscription to entries written to the database.
+///
+/// Entries are no longer sent once the subscription (and all its clones) are dropped.
+#[derive(Clone, Debug)]
+pub struct Subscription {
+    receiver: async_channel::Receiver<Arc<LogEntry>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// Receive the next matching entry, waiting until one is written.
+    ///
+    /// Returns `None` if the database has been dropped.
+    pub async fn recv(&self) -> Option<Arc<LogEntry>> {
+        self.receiver.recv().await.ok()
+    }
+
+    /// The number of entries that have been dropped because the subscription wasn't keeping up.
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    matchers: Vec<(String, String)>,
+    sender: async_channel::Sender<Arc<LogEntry>>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// The subscriptions to a database.
+#[derive(Debug, Default)]
+pub(super) struct Subscribers(Vec<Subscriber>);
+
+impl Subscribers {
+    /// Add a subscription, or return `None` if there are already [`MAX_SUBSCRIPTIONS`].
+    pub(super) fn subscribe(&mut self, matchers: Vec<(String, String)>) -> Option<Subscription> {
+        self.0.retain(|subscriber| !subscriber.sender.is_closed());
+        if self.0.len() >= MAX_SUBSCRIPTIONS {
+            return None;
+        }
+
+        let (sender, receiver) = async_channel::bounded(BUFFER_SIZE);
+        let dropped = Arc::new(AtomicU64::new(0));
+        self.0.push(Subscriber {
+            matchers,
+            sender,
+            dropped: Arc::clone(&dropped),
+        });
+        Some(Subscription { receiver, dropped })
+    }
+
+    /// Send an entry to matching subscribers, forgetting any that have been dropped.
+    pub(super) fn publish(
+        &mut self,
+        metadata: &HashMap<String, String>,
+        timestamp: Timestamp,
+        line: &str,
+    ) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        let mut entry = None;
+        self.0.retain(|subscriber| {
+            let matches = subscriber
+                .matchers
+                .iter()
+                .all(|(key, value)| metadata.get(key) == Some(value));
+            if !matches {
+                return !subscriber.sender.is_closed();
+            }
+
+            let entry = entry.get_or_insert_with(|| {
+                Arc::new(LogEntry {
+                    line: line.to_string(),
+                    metadata: metadata.clone(),
+                    timestamp: Some(timestamp),
+                })
+            });
+            match subscriber.sender.try_send(Arc::clone(entry)) {
+                Ok(()) => true,
+                Err(async_channel::TrySendError::Full(_)) => {
+                    subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(async_channel::TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Subscribers, MAX_SUBSCRIPTIONS};
+
+    #[test]
+    fn limits_open_subscriptions() {
+        let mut subscribers = Subscribers::default();
+        let subscriptions: Vec<_> = (0..MAX_SUBSCRIPTIONS)
+            .map(|_| subscribers.subscribe(Vec::new()).unwrap())
+            .collect();
+        assert!(subscribers.subscribe(Vec::new()).is_none());
+
+        drop(subscriptions);
+        assert!(subscribers.subscribe(Vec::new()).is_some());
+    }
+}
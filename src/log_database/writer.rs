@@ -0,0 +1,306 @@
+// src/log_database/writer.rs
+//! A bounded queue between collection and the log [`Database`].
+//!
+//! Writes can be slow, e.g. when the disk is busy or while retention holds the database lock. A
+//! [`Writer`] writes entries to the database in a background task, so that collection can carry on
+//! reading (and draining its watch queues) while a write is waiting. At most [`Config::capacity`]
+//! entries are queued, and [`Config::overflow`] decides what happens when the queue is full:
+//! collection can wait for space ([`Overflow::Block`]), or the oldest queued entry can be dropped
+//! ([`Overflow::DropOldest`]) and counted in [`DROPPED_ENTRIES`], so collection never stalls.
+
+use std::io;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_std::sync::RwLock;
+use async_std::task::{self, JoinHandle};
+use log::warn;
+
+use crate::metrics::Metric;
+use crate::LogEntry;
+
+use super::Database;
+
+/// The most entries written while holding the database lock, so queries aren't starved.
+const MAX_WRITE_BATCH: usize = 1000;
+
+/// The number of entries dropped because the write queue was full.
+pub static DROPPED_ENTRIES: Metric = Metric::counter(
+    "monitoring_rs_write_queue_dropped_entries_total",
+    "Number of entries dropped because the database write queue was full.",
+);
+
+/// What to do with entries when the write queue is full.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Overflow {
+    /// Wait for space in the queue, pausing collection.
+    Block,
+
+    /// Drop the oldest queued entry to make space.
+    DropOldest,
+}
+
+impl Default for Overflow {
+    fn default() -> Self {
+        Self::Block
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "block" => Ok(Self::Block),
+            "drop-oldest" => Ok(Self::DropOldest),
+            _ => Err(format!(
+                "invalid write queue overflow `{}`: must be `block` or `drop-oldest`",
+                input
+            )),
+        }
+    }
+}
+
+/// The configuration of a [`Writer`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// The maximum number of entries waiting to be written.
+    pub capacity: NonZeroUsize,
+
+    /// What to do with entries when the queue is full.
+    pub overflow: Overflow,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            capacity: NonZeroUsize::new(10_000).unwrap(),
+            overflow: Overflow::default(),
+        }
+    }
+}
+
+/// Statistics about a [`Writer`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// The number of entries written to the database.
+    pub entries_written: u64,
+
+    /// The number of entries dropped because the queue was full.
+    pub entries_dropped: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    entries_written: AtomicU64,
+    entries_dropped: AtomicU64,
+}
+
+impl Counters {
+    fn stats(&self) -> Stats {
+        Stats {
+            entries_written: self.entries_written.load(Ordering::Relaxed),
+            entries_dropped: self.entries_dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Writes entries to a [`Database`] in a background task.
+#[derive(Debug)]
+pub struct Writer {
+    overflow: Overflow,
+    sender: async_channel::Sender<LogEntry>,
+
+    /// Used to drop the oldest entry when the queue is full.
+    receiver: async_channel::Receiver<LogEntry>,
+    counters: Arc<Counters>,
+    task: JoinHandle<io::Result<()>>,
+}
+
+impl Writer {
+    /// Start writing entries to `database`.
+    #[must_use]
+    pub fn spawn(database: Arc<RwLock<Database>>, config: Config) -> Self {
+        let (sender, receiver) = async_channel::bounded(config.capacity.get());
+        let counters = Arc::new(Counters::default());
+        let task = task::spawn(run(database, receiver.clone(), Arc::clone(&counters)));
+        Self {
+            overflow: config.overflow,
+            sender,
+            receiver,
+            counters,
+            task,
+        }
+    }
+
+    /// Queue `entry` to be written, according to the configured [`Overflow`] if the queue is full.
+    ///
+    /// Returns `false` if writing has stopped due to an error, which is returned by
+    /// [`close`](Self::close).
+    pub async fn send(&self, entry: LogEntry) -> bool {
+        match self.overflow {
+            Overflow::Block => self.sender.send(entry).await.is_ok(),
+            Overflow::DropOldest => self.send_dropping_oldest(entry),
+        }
+    }
+
+    /// Like [`send`](Self::send), but blocks the current thread while waiting for space.
+    pub fn write(&self, entry: LogEntry) -> bool {
+        task::block_on(self.send(entry))
+    }
+
+    fn send_dropping_oldest(&self, mut entry: LogEntry) -> bool {
+        loop {
+            match self.sender.try_send(entry) {
+                Ok(()) => return true,
+                Err(async_channel::TrySendError::Full(rejected)) => {
+                    // The writer may have made space in the meantime.
+                    if self.receiver.try_recv().is_ok() {
+                        self.counters
+                            .entries_dropped
+                            .fetch_add(1, Ordering::Relaxed);
+                        DROPPED_ENTRIES.inc();
+                    }
+                    entry = rejected;
+                }
+                Err(async_channel::TrySendError::Closed(_)) => return false,
+            }
+        }
+    }
+
+    /// Statistics about the writer so far.
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        self.counters.stats()
+    }
+
+    /// Write any queued entries, then stop, returning the final statistics.
+    ///
+    /// # Errors
+    ///
+    /// Propagates any `io::Error` that stopped writing.
+    pub async fn close(self) -> io::Result<Stats> {
+        self.sender.close();
+        self.task.await?;
+        let stats = self.counters.stats();
+        if stats.entries_dropped != 0 {
+            warn!(
+                "Dropped {} entries because the write queue was full",
+                stats.entries_dropped
+            );
+        }
+        Ok(stats)
+    }
+}
+
+async fn run(
+    database: Arc<RwLock<Database>>,
+    receiver: async_channel::Receiver<LogEntry>,
+    counters: Arc<Counters>,
+) -> io::Result<()> {
+    let result = write_all(&database, &receiver, &counters).await;
+
+    // Stop further entries from being queued, so `Writer::write` notices the failure.
+    receiver.close();
+    result
+}
+
+async fn write_all(
+    database: &RwLock<Database>,
+    receiver: &async_channel::Receiver<LogEntry>,
+    counters: &Counters,
+) -> io::Result<()> {
+    while let Ok(entry) = receiver.recv().await {
+        let mut database = database.write().await;
+        database.write(&entry)?;
+        let mut written = 1;
+
+        // Write whatever else has been queued while we have the lock.
+        while written < MAX_WRITE_BATCH {
+            match receiver.try_recv() {
+                Ok(entry) => database.write(&entry)?,
+                Err(_) => break,
+            }
+            written += 1;
+        }
+        counters
+            .entries_written
+            .fetch_add(written as u64, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+    use std::sync::Arc;
+
+    use async_std::sync::RwLock;
+    use async_std::task;
+
+    use crate::test::{self, log_entry, temp_database};
+
+    use super::{Config, Overflow, Stats, Writer};
+
+    #[test]
+    fn write_entries() -> test::Result {
+        let (_tempdir, database) = temp_database()?;
+        let database = Arc::new(RwLock::new(database));
+
+        let writer = Writer::spawn(Arc::clone(&database), Config::default());
+        for line in &["one", "two", "three"] {
+            assert!(writer.write(log_entry(line, &[("foo", "bar")])));
+        }
+        assert_eq!(
+            task::block_on(writer.close())?,
+            Stats {
+                entries_written: 3,
+                entries_dropped: 0,
+            }
+        );
+        assert_eq!(
+            task::block_on(database.read()).query("foo", "bar")?,
+            Some(vec![
+                "one".to_string(),
+                "two".to_string(),
+                "three".to_string()
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn drop_oldest_entries() -> test::Result {
+        let (_tempdir, database) = temp_database()?;
+        let database = Arc::new(RwLock::new(database));
+
+        let writer = Writer::spawn(
+            Arc::clone(&database),
+            Config {
+                capacity: NonZeroUsize::new(2).unwrap(),
+                overflow: Overflow::DropOldest,
+            },
+        );
+
+        // Stall the writer while entries are queued. It may already have taken the first entry.
+        let guard = task::block_on(database.write());
+        for line in &["one", "two", "three", "four", "five"] {
+            assert!(writer.write(log_entry(line, &[("foo", "bar")])));
+        }
+        drop(guard);
+
+        let stats = task::block_on(writer.close())?;
+        assert_eq!(stats.entries_written + stats.entries_dropped, 5);
+        assert!(stats.entries_dropped >= 2);
+
+        let lines = task::block_on(database.read())
+            .query("foo", "bar")?
+            .unwrap();
+        assert!(lines.ends_with(&["four".to_string(), "five".to_string()]));
+
+        Ok(())
+    }
+}
@@ -1,44 +1,641 @@
 // main.rs
-#[macro_use]
-extern crate clap;
-
 use std::env;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use async_std::prelude::FutureExt;
+use async_std::stream::StreamExt;
 use async_std::sync::RwLock;
 use async_std::task;
+use log::{error, info, warn};
+use regex::Regex;
 use structopt::StructOpt;
 
-use monitoring_rs::log_collector::Collector;
-use monitoring_rs::log_database::{self, Database};
+use monitoring_rs::api::auth::{AuthProvider, StaticToken, StaticTokens};
+use monitoring_rs::api::export::DirectoryStore;
+use monitoring_rs::api::listen::{self, ListenAddr, SocketOptions};
+use monitoring_rs::api::oidc::{Oidc, OidcConfig};
+use monitoring_rs::database::{self as event_database, migrate, Engine};
+use monitoring_rs::jobs::notify::{redact_url, Notifier, Webhooks};
+use monitoring_rs::jobs::report::Report;
+use monitoring_rs::jobs::{JobState, Jobs};
+use monitoring_rs::log_collector::access_log::{self, AccessLogFormat, AccessLogParser};
+use monitoring_rs::log_collector::diagnostics::Diagnostics;
+use monitoring_rs::log_collector::geoip::{self, GeoIp};
+use monitoring_rs::log_collector::json::{self, JsonParser};
+use monitoring_rs::log_collector::multiline::{self, Multiline};
+use monitoring_rs::log_collector::secrets::{self, SecretDetector, SecretMode};
+use monitoring_rs::log_collector::templates::{self, DerivedLabels};
+use monitoring_rs::log_collector::{AsyncCollector, Merged, Unblocked};
+use monitoring_rs::log_database::limits::OversizedLinePolicy;
+use monitoring_rs::log_database::partition::{Partition, Partitioning};
+use monitoring_rs::log_database::recovery::UnknownFilePolicy;
+use monitoring_rs::log_database::stats::StatsRecorder;
+use monitoring_rs::log_database::writer::{self, Overflow, Writer};
+use monitoring_rs::log_database::{self, retention, Database};
+use monitoring_rs::manifest::Manifest;
+use monitoring_rs::sink::{self, delivery::Delivery};
 use monitoring_rs::{api, log_collector};
 
 /// Minimal Kubernetes monitoring pipeline.
 #[derive(StructOpt)]
 struct Args {
-    /// The log collector to use.
-    #[structopt(long, default_value, env, possible_values = &CollectorArg::variants())]
-    log_collector: CollectorArg,
+    /// A log collector to use, as a collector type (e.g. `directory`) or a JSON object like
+    /// `{"type": "directory", "root_path": "/var/log/app"}` or
+    /// `{"type": "syslog", "udp": "0.0.0.0:514", "labels": {"source": "syslog"}}` (see
+    /// `monitoring_rs::log_collector`).
+    ///
+    /// This can be given multiple times to run several collectors at once. `--root-path`,
+    /// `--ownership-marker`, `--backfill-compressed`, and `--checkpoint-positions` are added to the
+    /// options of each `directory` and `kubernetes` collector (and `--once` to each `directory`
+    /// collector) if they're given and not already present. Each collector gets its own checkpoint
+    /// files, numbered by its position after the first (e.g. `.position-checkpoints.1`).
+    #[structopt(
+        long = "log-collector",
+        env = "LOG_COLLECTOR",
+        default_value = "kubernetes",
+        number_of_values = 1,
+        parse(try_from_str = parse_collector)
+    )]
+    log_collectors: Vec<serde_json::Value>,
 
-    /// The root path to watch.
-    #[structopt(long, env, required_if("log-collector", "Directory"))]
+    /// The root path to watch (required by the `directory` collector).
+    #[structopt(long, env)]
     root_path: Option<PathBuf>,
+
+    /// Write an ownership marker into the root path, to detect other collectors collecting the same
+    /// files.
+    #[structopt(long, env)]
+    ownership_marker: bool,
+
+    /// Read compressed (`.gz` or `.zst`) rotated log files found in the root path at startup.
+    ///
+    /// How much of each file has been read is checkpointed, so files aren't read twice.
+    #[structopt(long, env)]
+    backfill_compressed: bool,
+
+    /// Checkpoint how far each log file has been read, so that after a restart files resume where
+    /// they left off rather than skipping what was written in the meantime.
+    #[structopt(long, env)]
+    checkpoint_positions: bool,
+
+    /// Read the log files currently in the root path, flush them to the database, and exit, rather
+    /// than watching for changes (only supported by the `directory` collector).
+    ///
+    /// The API isn't served. How much of each file has been read is checkpointed, so running again
+    /// (e.g. from cron) only reads what has been written since.
+    #[structopt(long, env)]
+    once: bool,
+
+    /// A retention rule, as `<key>=<value>,...:<max age>` (e.g. `namespace=payments:30d`).
+    ///
+    /// Rules are evaluated in the order given, and the first matching rule applies. An empty
+    /// selector (e.g. `:3d`) matches all streams. Streams matching no rule are kept forever.
+    #[structopt(
+        long = "retention-rule",
+        env = "RETENTION_RULES",
+        value_delimiter = ";",
+        number_of_values = 1
+    )]
+    retention_rules: Vec<retention::Rule>,
+
+    /// How often to apply retention rules.
+    #[structopt(long, env, default_value = "5m", parse(try_from_str = retention::parse_duration))]
+    retention_interval: Duration,
+
+    /// How often to record statistics (for `GET /stats/history`).
+    #[structopt(long, env, default_value = "1d", parse(try_from_str = retention::parse_duration))]
+    stats_interval: Duration,
+
+    /// The number of query results to cache in memory (`0` disables the cache).
+    #[structopt(long, env, default_value = "0")]
+    query_cache_capacity: usize,
+
+    /// The maximum number of log queries to run at once (unlimited if not set). Further queries
+    /// are rejected with `429 Too Many Requests`.
+    #[structopt(long, env)]
+    max_concurrent_queries: Option<usize>,
+
+    /// The maximum length of a stored line, in bytes (unlimited if not set).
+    #[structopt(long, env)]
+    max_line_size: Option<usize>,
+
+    /// What to do with lines longer than `--max-line-size`: `truncate`, `drop`, or `split`.
+    #[structopt(long, env, default_value = "truncate")]
+    oversized_line_policy: OversizedLinePolicy,
+
+    /// The maximum number of collected entries waiting to be written to the database.
+    ///
+    /// This and `--write-queue-overflow` only apply when collected entries are written directly to
+    /// the local database. With `--sink`, each sink buffers entries instead (up to its
+    /// `buffer_size`), and collection waits while a sink's buffer is full.
+    #[structopt(long, env, default_value = "10000")]
+    write_queue_size: NonZeroUsize,
+
+    /// What to do with collected entries when `--write-queue-size` entries are already waiting to
+    /// be written: `block` (pause collection until there's space) or `drop-oldest` (drop the
+    /// oldest waiting entry, counted in `monitoring_rs_write_queue_dropped_entries_total`).
+    #[structopt(long, env, default_value = "block")]
+    write_queue_overflow: Overflow,
+
+    /// What to do with unrecognised files in the data directory: `fail`, `ignore`, or `quarantine`
+    /// (move them into a `quarantine` subdirectory).
+    #[structopt(long, env, default_value = "ignore")]
+    unknown_file_policy: UnknownFilePolicy,
+
+    /// A label to partition the data directory by, storing each value's streams in its own
+    /// subdirectory (see `monitoring_rs::log_database::partition`).
+    #[structopt(long, env)]
+    partition_label: Option<String>,
+
+    /// Configuration for a partition of `--partition-label`, as a JSON object like
+    /// `{"value": "payments", "directory": "/mnt/payments", "retention": [":30d"], "max_bytes":
+    /// 10000000000}`.
+    ///
+    /// This can be given multiple times to configure several partitions.
+    #[structopt(
+        long = "partition",
+        env = "PARTITIONS",
+        value_delimiter = ";",
+        number_of_values = 1
+    )]
+    partitions: Vec<Partition>,
+
+    /// Queries taking at least this long are recorded in the slow query log.
+    #[structopt(long, env, default_value = "1s", parse(try_from_str = retention::parse_duration))]
+    slow_query_threshold: Duration,
+
+    /// The maximum size of a `POST /push` request body, in bytes.
+    #[structopt(long, env, default_value = "10485760")]
+    max_push_body_size: usize,
+
+    /// The maximum number of lines in a page of `GET /logs/:key/:value` results (when a `limit`,
+    /// `cursor`, or `direction` is given).
+    #[structopt(long, env, default_value = "10000")]
+    max_page_size: usize,
+
+    /// The maximum size, in bytes, of the lines in a query response. Pages are cut short to fit,
+    /// and other queries with larger responses are rejected. Unlimited if not set.
+    #[structopt(long, env)]
+    max_response_bytes: Option<usize>,
+
+    /// A browser origin (like `https://dashboard.example.com`, or `*` for any) allowed to make
+    /// cross-origin requests to the API. CORS is disabled if not set.
+    ///
+    /// This can be given multiple times to allow several origins.
+    #[structopt(
+        long = "cors-allowed-origin",
+        env = "CORS_ALLOWED_ORIGINS",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    cors_allowed_origins: Vec<String>,
+
+    /// The methods allowed in cross-origin requests.
+    #[structopt(
+        long,
+        env,
+        default_value = "GET,POST,PUT,DELETE",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    cors_allowed_methods: Vec<String>,
+
+    /// The request headers allowed in cross-origin requests.
+    #[structopt(
+        long,
+        env,
+        default_value = "Authorization,Content-Type,X-Request-ID",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    cors_allowed_headers: Vec<String>,
+
+    /// How long browsers may cache the result of a CORS preflight request.
+    #[structopt(long, env, default_value = "1h", parse(try_from_str = retention::parse_duration))]
+    cors_max_age: Duration,
+
+    /// The sustained number of `GET` requests per second allowed from each API client. Rate
+    /// limiting is disabled if not set.
+    #[structopt(long, env)]
+    rate_limit: Option<NonZeroU32>,
+
+    /// The number of `GET` requests each API client may make at once, when rate limiting. Defaults
+    /// to the rate limit.
+    #[structopt(long, env)]
+    rate_limit_burst: Option<NonZeroU32>,
+
+    /// How API clients are identified when rate limiting: `ip`, or `token` for the tenant and
+    /// scopes of their authenticated token (or their IP address without one).
+    #[structopt(long, env, default_value = "ip")]
+    rate_limit_key: api::rate_limit::Key,
+
+    /// A directory (e.g. a mounted object storage bucket) to which `POST /exports` writes query
+    /// results. Exports are disabled if not set.
+    #[structopt(long, env)]
+    export_directory: Option<PathBuf>,
+
+    /// A key with which to sign export manifests (with HMAC-SHA256), so exports can be proven
+    /// untampered with `verify`. Manifests are unsigned if not set.
+    #[structopt(long, env, hide_env_values = true)]
+    export_signing_key: Option<String>,
+
+    /// Parse HTTP access log lines into metadata (e.g. `http_status`): `auto`, `clf`, `combined`,
+    /// or `nginx-json`. Parsing is disabled if not set.
+    #[structopt(long, env)]
+    access_log_format: Option<AccessLogFormat>,
+
+    /// The access log fields to add to metadata: `method`, `path`, `status`, `latency`, and
+    /// `user_agent_family`.
+    ///
+    /// Each distinct set of metadata is a separate stream, so `path` and `latency` can create very
+    /// many streams.
+    #[structopt(
+        long = "access-log-field",
+        env = "ACCESS_LOG_FIELDS",
+        default_value = "method,path,status,latency,user_agent_family",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    access_log_fields: Vec<access_log::Field>,
+
+    /// A field of JSON log lines to add to metadata, like `level` or `log.level` (for a nested
+    /// field). This can be given multiple times (or separated by `,`). JSON parsing is disabled if
+    /// not set.
+    ///
+    /// The line is replaced by the message (see `--json-message-key`), if the object has one. Each
+    /// distinct set of metadata is a separate stream, so fields like `trace_id` can create very
+    /// many streams.
+    #[structopt(
+        long = "json-field",
+        env = "JSON_FIELDS",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    json_fields: Vec<String>,
+
+    /// The fields of JSON log lines that may hold the message, in the order they're tried.
+    #[structopt(
+        long = "json-message-key",
+        env = "JSON_MESSAGE_KEYS",
+        default_value = "message,msg",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    json_message_keys: Vec<String>,
+
+    /// Check log lines for likely secrets (e.g. API keys): `flag` labels lines containing them with
+    /// `secret=likely`, and `redact` also replaces the secrets with `[redacted]`. Detection is
+    /// disabled if not set.
+    #[structopt(long, env)]
+    secret_detection: Option<SecretMode>,
+
+    /// The minimum entropy, in bits per character, for a token to be considered a secret.
+    #[structopt(long, env, default_value = "4.0")]
+    secret_min_entropy: f64,
+
+    /// The minimum length of a token to be checked for entropy.
+    #[structopt(long, env, default_value = "20")]
+    secret_min_length: usize,
+
+    /// A MaxMind DB file (e.g. GeoLite2 Country or ASN) to look up IP addresses in. This can be
+    /// given multiple times to use several databases.
+    ///
+    /// Databases are reloaded when they change. Enrichment is disabled if not set.
+    #[structopt(
+        long = "geoip-database",
+        env = "GEOIP_DATABASES",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    geoip_databases: Vec<PathBuf>,
+
+    /// A field to extract an IP address from for enrichment: a metadata key, or `line` for the
+    /// first IP address in the log line. This can be given multiple times.
+    ///
+    /// For a field `client_ip`, metadata keys `client_ip_country`, `client_ip_asn`, and
+    /// `client_ip_as_org` are added.
+    #[structopt(
+        long = "geoip-field",
+        env = "GEOIP_FIELDS",
+        default_value = "line",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    geoip_fields: Vec<String>,
+
+    /// How often to check `--geoip-database` files for changes.
+    #[structopt(long, env, default_value = "1m", parse(try_from_str = retention::parse_duration))]
+    geoip_reload_interval: Duration,
+
+    /// A label to derive from a template over other metadata, like
+    /// `service={namespace}/{container_name}`. This can be given multiple times (or separated by
+    /// `;`), and templates can refer to other derived labels.
+    ///
+    /// Labels are derived after other enrichment, and skipped for entries missing any of the keys
+    /// in their template.
+    #[structopt(
+        long = "derived-label",
+        env = "DERIVED_LABELS",
+        value_delimiter = ";",
+        number_of_values = 1
+    )]
+    derived_labels: Vec<templates::Rule>,
+
+    /// The maximum length, in bytes, of a derived label's value. Longer values are truncated.
+    #[structopt(long, env, default_value = "256")]
+    derived_label_max_length: usize,
+
+    /// A regex matching the first line of a multi-line message, like a stack trace. Following
+    /// lines are merged into the same entry (see `monitoring_rs::log_collector::multiline`).
+    ///
+    /// Merging is disabled if not set.
+    #[structopt(long, env)]
+    multiline_start: Option<Regex>,
+
+    /// A regex matching the lines that continue a multi-line message (e.g. `^\s` for indented
+    /// lines). Defaults to any line that doesn't match `--multiline-start`.
+    #[structopt(long, env)]
+    multiline_continuation: Option<Regex>,
+
+    /// How long to wait for further lines of a multi-line message before storing it.
+    #[structopt(long, env, default_value = "1s", parse(try_from_str = retention::parse_duration))]
+    multiline_timeout: Duration,
+
+    /// The maximum number of lines merged into one entry.
+    #[structopt(long, env, default_value = "500")]
+    multiline_max_lines: usize,
+
+    /// A destination for collected entries, as a JSON object like
+    /// `{"type": "http", "url": "http://aggregator:8000"}` (see `monitoring_rs::sink`).
+    ///
+    /// This can be given multiple times to send entries to several sinks. If no sinks are given,
+    /// collected entries are written to the local database, which can be included explicitly
+    /// with `{"type": "database"}`.
+    #[structopt(
+        long = "sink",
+        env = "SINKS",
+        value_delimiter = ";",
+        number_of_values = 1
+    )]
+    sinks: Vec<serde_json::Value>,
+
+    /// A URL to post a summary to when a background job finishes (e.g. a Slack incoming webhook).
+    ///
+    /// This can be given multiple times to notify several URLs.
+    #[structopt(
+        long,
+        env = "JOB_WEBHOOKS",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    job_webhook: Vec<surf::Url>,
+
+    /// The job states to notify `--job-webhook` of: `completed`, `failed`, or `cancelled`.
+    #[structopt(
+        long,
+        env,
+        default_value = "completed,failed",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    job_webhook_states: Vec<JobState>,
+
+    /// A saved query to run periodically and summarise to a webhook or SMTP relay, as a JSON
+    /// object like `{"name": "errors", "every": "1d", "query": {"match": ["ns=prod"]}, "target":
+    /// {"webhook": "https://..."}}` (see `monitoring_rs::jobs::report`).
+    ///
+    /// This can be given multiple times to schedule several reports. The `REPORTS` environment
+    /// variable holds a single report, since report JSON may itself contain any delimiter.
+    #[structopt(long = "report", env = "REPORTS", number_of_values = 1)]
+    reports: Vec<Report>,
+
+    /// A static API token, as `<token>:<tenant>:<scope>,<scope>` (e.g. `s3cret:payments:read`).
+    ///
+    /// The scopes are `read`, `write`, `export`, and `admin`, and the tenant may be empty. This can
+    /// be given multiple times. If neither this nor `--oidc-issuer` is given, authentication is
+    /// disabled.
+    #[structopt(
+        long = "auth-token",
+        env = "AUTH_TOKENS",
+        value_delimiter = ";",
+        number_of_values = 1
+    )]
+    auth_tokens: Vec<StaticToken>,
+
+    /// The URL of an OpenID Connect issuer, whose JWTs are accepted as API tokens.
+    #[structopt(long, env)]
+    oidc_issuer: Option<String>,
+
+    /// The audience that OpenID Connect tokens must be issued for.
+    ///
+    /// Without this, tokens the issuer grants to any of its clients are accepted, so it should
+    /// almost always be given; a warning is logged at startup if it isn't.
+    #[structopt(long, env)]
+    oidc_audience: Option<String>,
+
+    /// The OpenID Connect token claim that identifies the tenant.
+    #[structopt(long, env, default_value = "tenant")]
+    oidc_tenant_claim: String,
+
+    /// The OpenID Connect token claim that lists the granted scopes.
+    #[structopt(long, env, default_value = "scope")]
+    oidc_scope_claim: String,
+
+    /// How often to refresh the OpenID Connect issuer's signing keys.
+    #[structopt(long, env, default_value = "1h", parse(try_from_str = retention::parse_duration))]
+    oidc_jwks_refresh_interval: Duration,
+
+    /// An address for the API to listen on, as `<host>:<port>` or `unix:<path>`.
+    ///
+    /// This can be given multiple times to listen on several addresses.
+    #[structopt(
+        long,
+        env,
+        default_value = "0.0.0.0:8000",
+        value_delimiter = ",",
+        number_of_values = 1
+    )]
+    listen: Vec<ListenAddr>,
+
+    /// An address for the admin API (`/admin`, `/debug`, `/config`, and `/metrics`) to listen on.
+    ///
+    /// If given, the admin API is only served on these addresses, and not on `--listen`.
+    #[structopt(long, env, value_delimiter = ",", number_of_values = 1)]
+    admin_listen: Vec<ListenAddr>,
+
+    /// The TCP keepalive interval for API connections (disabled if not set).
+    #[structopt(long, env, parse(try_from_str = retention::parse_duration))]
+    tcp_keepalive: Option<Duration>,
+
+    /// The maximum number of pending API connections on each TCP listener.
+    #[structopt(long, env, default_value = "1024")]
+    listen_backlog: i32,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Copy all events from one event storage engine to another, then exit.
+    ///
+    /// Migrations can be resumed by running the same command again.
+    Migrate(MigrateArgs),
+
+    /// Merge the events from other event databases' logs into one event database, then exit.
+    ///
+    /// Used to combine events collected on several nodes, or restored from backups. Imports are
+    /// checkpointed, so importing a log again only imports events appended to it since.
+    Import(ImportArgs),
+
+    /// Verify an export against its manifest, then exit.
+    ///
+    /// Fails if any file listed in the manifest is missing or modified, or if a signing key is
+    /// given and the manifest isn't validly signed with it.
+    Verify(VerifyArgs),
 }
 
-arg_enum! {
-    enum CollectorArg {
-        Directory,
-        Kubernetes,
+#[derive(StructOpt)]
+struct MigrateArgs {
+    /// The storage engine to copy from (`json` or `sanakirja`).
+    #[structopt(long)]
+    from: Engine,
+
+    /// The storage engine to copy to (`json` or `sanakirja`).
+    #[structopt(long)]
+    to: Engine,
+
+    /// The path of the data to copy from.
+    #[structopt(long)]
+    source: PathBuf,
+
+    /// The path to copy the data to.
+    #[structopt(long)]
+    destination: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct ImportArgs {
+    /// The logs of the databases to import (which must not be open).
+    #[structopt(long = "source", required = true)]
+    sources: Vec<PathBuf>,
+
+    /// The path of the database to import into.
+    #[structopt(long)]
+    destination: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct VerifyArgs {
+    /// The path of the manifest (e.g. `exports/1.manifest.json`). Files are found relative to it.
+    #[structopt(long)]
+    manifest: PathBuf,
+
+    /// The key the manifest was signed with.
+    #[structopt(long, env = "EXPORT_SIGNING_KEY", hide_env_values = true)]
+    signing_key: Option<String>,
+}
+
+impl Args {
+    /// The effective configuration, for `GET /config`.
+    ///
+    /// This should include every argument, so that it reflects the complete configuration.
+    fn effective_config(&self) -> io::Result<serde_json::Value> {
+        let retention_rules: Vec<_> = self
+            .retention_rules
+            .iter()
+            .map(|rule| {
+                serde_json::json!({
+                    "selector": rule.selector,
+                    "max_age": format!("{:?}", rule.max_age),
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "log_collectors": self.log_collectors,
+            "root_path": self.root_path,
+            "data_directory": data_directory()?,
+            "ownership_marker": self.ownership_marker,
+            "backfill_compressed": self.backfill_compressed,
+            "checkpoint_positions": self.checkpoint_positions,
+            "once": self.once,
+            "retention_rules": retention_rules,
+            "retention_interval": format!("{:?}", self.retention_interval),
+            "stats_interval": format!("{:?}", self.stats_interval),
+            "query_cache_capacity": self.query_cache_capacity,
+            "max_concurrent_queries": self.max_concurrent_queries,
+            "max_line_size": self.max_line_size,
+            "oversized_line_policy": format!("{:?}", self.oversized_line_policy),
+            "write_queue_size": self.write_queue_size,
+            "write_queue_overflow": format!("{:?}", self.write_queue_overflow),
+            "unknown_file_policy": format!("{:?}", self.unknown_file_policy),
+            "partition_label": self.partition_label,
+            "partitions": format!("{:?}", self.partitions),
+            "slow_query_threshold": format!("{:?}", self.slow_query_threshold),
+            "max_push_body_size": self.max_push_body_size,
+            "max_page_size": self.max_page_size,
+            "max_response_bytes": self.max_response_bytes,
+            "cors_allowed_origins": self.cors_allowed_origins,
+            "cors_allowed_methods": self.cors_allowed_methods,
+            "cors_allowed_headers": self.cors_allowed_headers,
+            "cors_max_age": format!("{:?}", self.cors_max_age),
+            "rate_limit": self.rate_limit,
+            "rate_limit_burst": self.rate_limit_burst,
+            "rate_limit_key": format!("{:?}", self.rate_limit_key),
+            "export_directory": self.export_directory,
+            "export_signing_key": self.export_signing_key.is_some(),
+            "access_log_format": self.access_log_format.map(|format| format!("{:?}", format)),
+            "access_log_fields": format!("{:?}", self.access_log_fields),
+            "json_fields": self.json_fields,
+            "json_message_keys": self.json_message_keys,
+            "secret_detection": self.secret_detection.map(|mode| format!("{:?}", mode)),
+            "secret_min_entropy": self.secret_min_entropy,
+            "secret_min_length": self.secret_min_length,
+            "geoip_databases": self.geoip_databases,
+            "geoip_fields": self.geoip_fields,
+            "geoip_reload_interval": format!("{:?}", self.geoip_reload_interval),
+            "derived_labels": format!("{:?}", self.derived_labels),
+            "derived_label_max_length": self.derived_label_max_length,
+            "multiline_start": self.multiline_start.as_ref().map(Regex::as_str),
+            "multiline_continuation": self.multiline_continuation.as_ref().map(Regex::as_str),
+            "multiline_timeout": format!("{:?}", self.multiline_timeout),
+            "multiline_max_lines": self.multiline_max_lines,
+            "sinks": self.sinks.iter().map(sink::redact).collect::<Vec<_>>(),
+            "job_webhook": self.job_webhook.iter().map(redact_url).collect::<Vec<_>>(),
+            "job_webhook_states": self.job_webhook_states,
+            "reports": self
+                .reports
+                .iter()
+                .map(|report| {
+                    format!("{} every {:?} to {}", report.name, report.every, report.target)
+                })
+                .collect::<Vec<_>>(),
+            "auth_tokens": self.auth_tokens.len(),
+            "oidc_issuer": self.oidc_issuer,
+            "oidc_audience": self.oidc_audience,
+            "oidc_tenant_claim": self.oidc_tenant_claim,
+            "oidc_scope_claim": self.oidc_scope_claim,
+            "oidc_jwks_refresh_interval": format!("{:?}", self.oidc_jwks_refresh_interval),
+            "listen": self.listen.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "admin_listen": self.admin_listen.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "tcp_keepalive": self.tcp_keepalive.map(|keepalive| format!("{:?}", keepalive)),
+            "listen_backlog": self.listen_backlog,
+        }))
     }
 }
 
-impl Default for CollectorArg {
-    fn default() -> Self {
-        Self::Kubernetes
+/// Parse a `--log-collector`, either a JSON object or just a (case-insensitive) collector type.
+fn parse_collector(arg: &str) -> Result<serde_json::Value, String> {
+    if arg.trim_start().starts_with('{') {
+        serde_json::from_str(arg).map_err(|error| error.to_string())
+    } else {
+        Ok(serde_json::json!({ "type": arg.to_lowercase() }))
     }
 }
 
@@ -47,55 +644,574 @@ async fn main() -> io::Result<()> {
     env_logger::init();
 
     let args = Args::from_args();
+    match &args.command {
+        Some(Command::Migrate(migrate_args)) => return run_migration(migrate_args),
+        Some(Command::Import(import_args)) => return run_import(import_args),
+        Some(Command::Verify(verify_args)) => return run_verify(verify_args),
+        None => {}
+    }
 
-    let collector = init_collector(args)?;
+    let effective_config = args.effective_config()?;
+    let auth_providers = init_auth_providers(&args);
 
-    let database = init_database()?;
+    let jobs = Arc::new(if args.job_webhook.is_empty() {
+        Jobs::default()
+    } else {
+        let webhooks = Webhooks::new(args.job_webhook.clone(), args.job_webhook_states.clone());
+        Jobs::with_notifier(Arc::new(webhooks) as Arc<dyn Notifier>)
+    });
+    let diagnostics = Arc::new(Diagnostics::new());
+    let collector = init_collectors(&args, Arc::clone(&diagnostics))?;
+    let access_log_parser = args.access_log_format.map(|format| {
+        Arc::new(AccessLogParser::new(access_log::Config {
+            format,
+            fields: args.access_log_fields.clone(),
+        }))
+    });
+    let json_parser = if args.json_fields.is_empty() {
+        None
+    } else {
+        Some(Arc::new(JsonParser::new(json::Config {
+            fields: args.json_fields.clone(),
+            message_keys: args.json_message_keys.clone(),
+        })))
+    };
+    let secret_detector = args.secret_detection.map(|mode| {
+        Arc::new(SecretDetector::new(secrets::Config {
+            mode,
+            min_entropy: args.secret_min_entropy,
+            min_length: args.secret_min_length,
+        }))
+    });
+    let geoip = if args.geoip_databases.is_empty() {
+        None
+    } else {
+        Some(Arc::new(GeoIp::open(geoip::Config {
+            databases: args.geoip_databases.clone(),
+            fields: args.geoip_fields.clone(),
+            reload_interval: args.geoip_reload_interval,
+        })?))
+    };
+    let derived_labels = if args.derived_labels.is_empty() {
+        None
+    } else {
+        let derived_labels = DerivedLabels::new(templates::Config {
+            rules: args.derived_labels.clone(),
+            max_length: args.derived_label_max_length,
+        })
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        Some(Arc::new(derived_labels))
+    };
+
+    let partitioning = match args.partition_label {
+        Some(label) => Some(Partitioning {
+            label,
+            partitions: args.partitions,
+        }),
+        None if args.partitions.is_empty() => None,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--partition requires --partition-label",
+            ))
+        }
+    };
+    let database = init_database(
+        args.retention_rules,
+        args.query_cache_capacity,
+        args.max_concurrent_queries,
+        args.max_line_size,
+        args.oversized_line_policy,
+        args.unknown_file_policy,
+        partitioning,
+    )?;
+
+    let sink_context = sink::Context {
+        database: Some(Arc::clone(&database)),
+    };
+    let registry = sink::Registry::default();
+    let deliveries = args
+        .sinks
+        .iter()
+        .map(|config| {
+            let (sink, config) = registry
+                .build(config, &sink_context)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+            Ok(Delivery::spawn(sink, config))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let writer_config = writer::Config {
+        capacity: args.write_queue_size,
+        overflow: args.write_queue_overflow,
+    };
+    let writer = if deliveries.is_empty() {
+        Some(Writer::spawn(Arc::clone(&database), writer_config))
+    } else {
+        None
+    };
+
+    if args.once {
+        run_collector(
+            collector,
+            writer,
+            deliveries,
+            access_log_parser,
+            json_parser,
+            secret_detector,
+            geoip,
+            derived_labels,
+        )
+        .await?;
+        let flushed = database.write().await.flush()?;
+        info!(
+            "Collected all current logs and flushed {} log files",
+            flushed
+        );
+        return Ok(());
+    }
+
+    let api_config = api::Config {
+        slow_query_threshold: args.slow_query_threshold,
+        collector_diagnostics: diagnostics,
+        max_push_body_size: args.max_push_body_size,
+        max_page_size: args.max_page_size,
+        max_response_bytes: args.max_response_bytes,
+        cors: if args.cors_allowed_origins.is_empty() {
+            None
+        } else {
+            Some(api::cors::Config {
+                allowed_origins: args.cors_allowed_origins.clone(),
+                allowed_methods: args.cors_allowed_methods.clone(),
+                allowed_headers: args.cors_allowed_headers.clone(),
+                max_age: args.cors_max_age,
+            })
+        },
+        effective_config,
+        export_store: args.export_directory.map(|directory| {
+            Arc::new(DirectoryStore::new(directory)) as Arc<dyn api::export::ObjectStore>
+        }),
+        export_signing_key: args.export_signing_key.map(String::into_bytes),
+        jobs: Arc::clone(&jobs),
+        auth_providers,
+        access_log_parser: access_log_parser.clone(),
+        json_parser: json_parser.clone(),
+        secret_detector: secret_detector.clone(),
+        geoip: geoip.clone(),
+        derived_labels: derived_labels.clone(),
+        clock: SystemTime::now,
+        rate_limit: args
+            .rate_limit
+            .map(|requests_per_second| api::rate_limit::Config {
+                requests_per_second,
+                burst: args.rate_limit_burst.unwrap_or(requests_per_second),
+                key: args.rate_limit_key,
+            }),
+        events: None,
+    };
+    let socket_options = SocketOptions {
+        keepalive: args.tcp_keepalive,
+        backlog: args.listen_backlog,
+    };
+    let listener = listen::bind(&args.listen, &socket_options)?;
+    let api_handle = if args.admin_listen.is_empty() {
+        task::spawn(api::server(Arc::clone(&database), api_config).listen(listener))
+    } else {
+        let admin_listener = listen::bind(&args.admin_listen, &socket_options)?;
+        let (public, admin) = api::split_servers(Arc::clone(&database), api_config);
+        task::spawn(async move {
+            public
+                .listen(listener)
+                .try_join(admin.listen(admin_listener))
+                .await?;
+            Ok::<_, io::Error>(())
+        })
+    };
+
+    for report in args.reports {
+        task::spawn(report.run(Arc::clone(&database), Arc::clone(&jobs)));
+    }
+
+    let retention_handle = task::spawn(run_retention(
+        Arc::clone(&database),
+        args.retention_interval,
+        Arc::clone(&jobs),
+    ));
+
+    let stats_handle = task::spawn(run_stats(Arc::clone(&database), args.stats_interval, jobs));
+
+    let collector_handle = task::spawn(run_collector(
+        collector,
+        writer,
+        deliveries,
+        access_log_parser,
+        json_parser,
+        secret_detector,
+        geoip,
+        derived_labels,
+    ));
+
+    api_handle
+        .try_join(collector_handle)
+        .try_join(retention_handle)
+        .try_join(stats_handle)
+        .await?;
+
+    Ok(())
+}
 
-    let api_handle = api::server(Arc::clone(&database)).listen("0.0.0.0:8000");
+fn run_migration(args: &MigrateArgs) -> io::Result<()> {
+    let open = |engine: Engine, path: &PathBuf| {
+        engine
+            .open(path)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, format!("{:?}", error)))
+    };
+    let source = open(args.from, &args.source)?;
+    let destination = open(args.to, &args.destination)?;
 
-    let collector_handle = task::spawn(blocking::unblock(move || {
-        run_collector(collector, database)
-    }));
+    let report = migrate::migrate(&*source, &*destination, &mut |progress| {
+        info!(
+            "Migrated {} of {} events",
+            progress.processed, progress.total
+        );
+    })?;
+    info!(
+        "Migration complete: {} events copied, {} already present, and verified",
+        report.copied, report.skipped
+    );
+    Ok(())
+}
 
-    api_handle.try_join(collector_handle).await?;
+fn run_import(args: &ImportArgs) -> io::Result<()> {
+    let destination = event_database::Database::open(&args.destination)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, format!("{:?}", error)))?;
+    for source in &args.sources {
+        let events = destination.import(source)?;
+        info!("Imported {} events from {}", events, source.display());
+    }
+    destination.close()
+}
 
+fn run_verify(args: &VerifyArgs) -> io::Result<()> {
+    let manifest: Manifest = serde_json::from_slice(&fs::read(&args.manifest)?)?;
+    let directory = args.manifest.parent().unwrap_or_else(|| Path::new(""));
+    let problems = manifest.verify(directory, args.signing_key.as_ref().map(String::as_bytes))?;
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} failed verification", args.manifest.display()),
+        ));
+    }
+    info!(
+        "Verified {} files{}",
+        manifest.files.len(),
+        if args.signing_key.is_some() {
+            " and the manifest's signature"
+        } else {
+            ""
+        }
+    );
     Ok(())
 }
 
-fn init_database() -> io::Result<Arc<RwLock<Database>>> {
-    let mut data_directory = env::current_dir()?;
-    data_directory.push(".data");
+/// The directory in which the log database is stored.
+fn data_directory() -> io::Result<PathBuf> {
+    Ok(env::current_dir()?.join(".data"))
+}
+
+/// The file in which the `index`th collector's checkpoints of backfilled files are stored.
+///
+/// This is outside the data directory, which only contains the log database's own files.
+fn backfill_checkpoints_path(index: usize) -> io::Result<PathBuf> {
+    checkpoints_path(".backfill-checkpoints", index)
+}
+
+/// The file in which the `index`th collector's checkpoints of followed files' positions are
+/// stored.
+fn position_checkpoints_path(index: usize) -> io::Result<PathBuf> {
+    checkpoints_path(".position-checkpoints", index)
+}
+
+/// The checkpoint file `name` of the `index`th collector. The first collector's has no suffix, so
+/// it's unchanged from when only one collector could be run.
+fn checkpoints_path(name: &str, index: usize) -> io::Result<PathBuf> {
+    let name = match index {
+        0 => name.to_string(),
+        index => format!("{}.{}", name, index),
+    };
+    Ok(env::current_dir()?.join(name))
+}
+
+fn init_database(
+    retention: Vec<retention::Rule>,
+    query_cache_capacity: usize,
+    max_concurrent_queries: Option<usize>,
+    max_line_size: Option<usize>,
+    oversized_line_policy: OversizedLinePolicy,
+    unknown_file_policy: UnknownFilePolicy,
+    partitioning: Option<Partitioning>,
+) -> io::Result<Arc<RwLock<Database>>> {
+    let data_directory = data_directory()?;
     fs::create_dir_all(&data_directory)?;
 
-    let config = log_database::Config { data_directory };
+    for version in log_database::format::migrate(&data_directory, partitioning.as_ref())? {
+        info!("Migrated data directory from format version {}", version);
+    }
+
+    let config = log_database::Config {
+        data_directory,
+        retention,
+        query_cache_capacity,
+        max_concurrent_queries,
+        max_line_size,
+        oversized_line_policy,
+        unknown_file_policy,
+        partitioning,
+    };
     let database = Database::open(config)?;
     Ok(Arc::new(RwLock::new(database)))
 }
 
-fn init_collector(args: Args) -> io::Result<Box<dyn Collector + Send>> {
-    match args.log_collector {
-        CollectorArg::Directory => {
-            use log_collector::directory::{self, Config};
-            Ok(Box::new(directory::initialize(Config {
-                // We can `unwrap` because we expect presence to be validated by structopt.
-                root_path: args.root_path.unwrap(),
-            })?))
-        }
-        CollectorArg::Kubernetes => {
-            use log_collector::kubernetes::{self, Config};
-            Ok(Box::new(kubernetes::initialize(Config {
-                root_path: args.root_path,
-            })?))
+fn init_auth_providers(args: &Args) -> Vec<Arc<dyn AuthProvider>> {
+    let mut providers: Vec<Arc<dyn AuthProvider>> = Vec::new();
+    if !args.auth_tokens.is_empty() {
+        providers.push(Arc::new(StaticTokens::new(args.auth_tokens.clone())));
+    }
+    if let Some(issuer) = &args.oidc_issuer {
+        if args.oidc_audience.is_none() {
+            warn!(
+                "--oidc-audience is not set, so tokens from {} will be accepted whatever their audience",
+                issuer
+            );
         }
+        providers.push(Arc::new(Oidc::new(OidcConfig {
+            issuer: issuer.clone(),
+            audience: args.oidc_audience.clone(),
+            tenant_claim: args.oidc_tenant_claim.clone(),
+            scope_claim: args.oidc_scope_claim.clone(),
+            jwks_refresh_interval: args.oidc_jwks_refresh_interval,
+        })));
+    }
+    providers
+}
+
+/// Initialize every `--log-collector`, merging their entries.
+fn init_collectors(args: &Args, diagnostics: Arc<Diagnostics>) -> io::Result<Merged> {
+    let registry = log_collector::Registry::default();
+    let context = log_collector::Context { diagnostics };
+
+    let mut collectors = Vec::new();
+    for (index, config) in args.log_collectors.iter().enumerate() {
+        let config = collector_config(args, index, config)?;
+        let collector: Box<dyn AsyncCollector + Send + Unpin> = match &args.multiline_start {
+            // Merging waits for lines on its own thread, so it needs a blocking collector.
+            Some(start) => Box::new(Unblocked::new(Multiline::new(
+                registry
+                    .initialize(&config, &context)
+                    .map_err(collector_error)?,
+                multiline::Config {
+                    start: start.clone(),
+                    continuation: args.multiline_continuation.clone(),
+                    timeout: args.multiline_timeout,
+                    max_lines: args.multiline_max_lines,
+                },
+            ))),
+            None => registry
+                .initialize_async(&config, &context)
+                .map_err(collector_error)?,
+        };
+        collectors.push(collector);
     }
+    Ok(Merged::new(collectors))
 }
 
-fn run_collector(collector: Box<dyn Collector>, database: Arc<RwLock<Database>>) -> io::Result<()> {
-    for entry in collector {
-        let entry = entry?;
-        let mut database = task::block_on(database.write());
-        database.write(&entry)?;
+/// The `index`th `--log-collector` configuration, with the options given by other arguments added.
+///
+/// Only the file collectors (`directory` and `kubernetes`) accept these options, and only the
+/// `directory` collector can run `--once`.
+fn collector_config(
+    args: &Args,
+    index: usize,
+    config: &serde_json::Value,
+) -> io::Result<serde_json::Value> {
+    let mut config = config.clone();
+    let kind = config
+        .get("type")
+        .and_then(serde_json::Value::as_str)
+        .map(str::to_string);
+    if args.once && kind.as_deref() != Some("directory") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "`--once` is only supported by the `directory` collector, not {:?}",
+                kind.unwrap_or_default()
+            ),
+        ));
+    }
+    if !matches!(kind.as_deref(), Some("directory") | Some("kubernetes")) {
+        return Ok(config);
+    }
+    let options = match config.as_object_mut() {
+        Some(options) => options,
+        None => return Ok(config),
+    };
+
+    if let Some(root_path) = &args.root_path {
+        options
+            .entry("root_path")
+            .or_insert_with(|| serde_json::json!(root_path));
+    }
+    if args.ownership_marker {
+        options.entry("ownership_marker").or_insert(true.into());
+    }
+    if args.backfill_compressed {
+        options.entry("backfill_compressed").or_insert(true.into());
+        options
+            .entry("backfill_checkpoints")
+            .or_insert(serde_json::json!(backfill_checkpoints_path(index)?));
+    }
+    if args.checkpoint_positions && !args.once {
+        options
+            .entry("position_checkpoints")
+            .or_insert(serde_json::json!(position_checkpoints_path(index)?));
+    }
+    if args.once {
+        options.entry("once").or_insert(true.into());
+        options
+            .entry("backfill_checkpoints")
+            .or_insert(serde_json::json!(backfill_checkpoints_path(index)?));
+    }
+    Ok(config)
+}
+
+fn collector_error(error: log_collector::Error) -> io::Error {
+    match error {
+        log_collector::Error::Io(error) => error,
+        error => io::Error::new(io::ErrorKind::InvalidInput, error),
+    }
+}
+
+/// Collect entries from `collector`, sending them to `deliveries` (or to `writer` if there are
+/// none).
+async fn run_collector(
+    mut collector: Merged,
+    mut writer: Option<Writer>,
+    deliveries: Vec<Delivery>,
+    access_log_parser: Option<Arc<AccessLogParser>>,
+    json_parser: Option<Arc<JsonParser>>,
+    secret_detector: Option<Arc<SecretDetector>>,
+    geoip: Option<Arc<GeoIp>>,
+    derived_labels: Option<Arc<DerivedLabels>>,
+) -> io::Result<()> {
+    while let Some(entry) = collector.next().await {
+        let mut entry = entry?;
+        if let Some(parser) = &access_log_parser {
+            parser.parse(&mut entry);
+        }
+        if let Some(parser) = &json_parser {
+            parser.parse(&mut entry);
+        }
+        if let Some(detector) = &secret_detector {
+            detector.inspect(&mut entry);
+        }
+        if let Some(geoip) = &geoip {
+            geoip.enrich(&mut entry);
+        }
+        if let Some(derived_labels) = &derived_labels {
+            derived_labels.derive(&mut entry);
+        }
+        if let Some(queue) = &writer {
+            if !queue.send(entry).await {
+                // The writer only stops if writing fails, and closing it returns the error.
+                // `unwrap` is OK since we just used it.
+                let writer = writer.take().unwrap();
+                return writer.close().await.map(|_| ());
+            }
+            continue;
+        }
+        for delivery in &deliveries {
+            delivery.push(entry.clone()).await;
+        }
+    }
+
+    if let Some(writer) = writer {
+        let stats = writer.close().await?;
+        info!("Wrote {} entries to the database", stats.entries_written);
+    }
+    for delivery in deliveries {
+        let stats = delivery.close().await;
+        info!(
+            "Delivered {} entries in {} batches ({} dropped)",
+            stats.entries_sent, stats.batches_sent, stats.entries_dropped
+        );
     }
     Ok(())
 }
+
+async fn run_stats(
+    database: Arc<RwLock<Database>>,
+    interval: Duration,
+    jobs: Arc<Jobs>,
+) -> io::Result<()> {
+    let mut recorder = StatsRecorder::new(&*database.read().await, SystemTime::now());
+    loop {
+        task::sleep(interval).await;
+
+        let job = jobs.start("stats", "record statistics".to_string());
+        let mut database = database.write().await;
+        let record = match recorder.record(&mut database, SystemTime::now()) {
+            Ok(record) => record,
+            // As for retention, a failed recording is retried at the next interval rather than
+            // taking down the API and collection.
+            Err(error) => {
+                error!("Failed to record statistics: {}", error);
+                job.fail(&error);
+                continue;
+            }
+        };
+        info!(
+            "Recorded statistics: {} bytes ingested, {} queries",
+            record.bytes_ingested, record.queries
+        );
+        job.complete(serde_json::json!({
+            "bytes_ingested": record.bytes_ingested,
+            "queries": record.queries,
+        }));
+    }
+}
+
+async fn run_retention(
+    database: Arc<RwLock<Database>>,
+    interval: Duration,
+    jobs: Arc<Jobs>,
+) -> io::Result<()> {
+    loop {
+        task::sleep(interval).await;
+
+        let job = jobs.start("retention", "apply retention rules".to_string());
+        let mut database = database.write().await;
+        let removed = match database.apply_retention(SystemTime::now()) {
+            Ok(removed) => removed,
+            // One failed pass (e.g. an unreadable file) shouldn't take down the API and
+            // collection, so the next pass tries again.
+            Err(error) => {
+                error!("Failed to apply retention rules: {}", error);
+                job.fail(&error);
+                continue;
+            }
+        };
+        let mut dropped = 0;
+        if removed != 0 {
+            info!("Retention removed {} expired log files", removed);
+
+            dropped = database.compact_index();
+            info!("Index compaction dropped {} references", dropped);
+        }
+        job.complete(serde_json::json!({
+            "removed_files": removed,
+            "dropped_index_references": dropped,
+        }));
+    }
+}
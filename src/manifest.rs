@@ -0,0 +1,337 @@
+// src/manifest.rs
+//! Manifests that make archives (e.g. exports) verifiable.
+//!
+//! A [`Manifest`] lists the files in an archive with their sizes and SHA-256 checksums, and can be
+//! signed with an HMAC-SHA256 key. Manifests are stored as JSON alongside the files they list,
+//! which are named relative to the manifest's directory:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "created_at": 1612345678,
+//!   "files": [{"name": "1.ndjson", "size": 1234, "sha256": "..."}],
+//!   "signature": {"algorithm": "hmac-sha256", "value": "..."}
+//! }
+//! ```
+//!
+//! The signature covers the version, creation time, and files, so a signed archive can be proven
+//! untampered by anyone with the key (see [`Manifest::verify`]).
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+
+/// The current version of the manifest format.
+pub const VERSION: u32 = 1;
+
+/// The only supported signature algorithm.
+const SIGNATURE_ALGORITHM: &str = "hmac-sha256";
+
+/// A file listed in a [`Manifest`].
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct File {
+    /// The path of the file, relative to the manifest's directory.
+    pub name: String,
+
+    /// The size of the file, in bytes.
+    pub size: u64,
+
+    /// The hex-encoded SHA-256 checksum of the file.
+    pub sha256: String,
+}
+
+/// The signature of a [`Manifest`].
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Signature {
+    /// The signature algorithm (always `hmac-sha256`).
+    pub algorithm: String,
+
+    /// The hex-encoded signature.
+    pub value: String,
+}
+
+/// A list of the files in an archive, with their checksums.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Manifest {
+    /// The version of the manifest format.
+    pub version: u32,
+
+    /// When the manifest was created, in seconds since the Unix epoch.
+    pub created_at: u64,
+
+    /// The files in the archive.
+    pub files: Vec<File>,
+
+    /// The signature of the manifest, if it was signed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Signature>,
+}
+
+/// A problem found when verifying an archive against its [`Manifest`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    /// A listed file is missing.
+    Missing(String),
+
+    /// A listed file's size or checksum doesn't match.
+    Modified(String),
+
+    /// The manifest isn't signed, but a key was given.
+    Unsigned,
+
+    /// The manifest's signature doesn't match the key, or the manifest has been modified.
+    InvalidSignature,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Missing(name) => write!(f, "{} is missing", name),
+            Self::Modified(name) => write!(f, "{} has been modified", name),
+            Self::Unsigned => write!(f, "the manifest is not signed"),
+            Self::InvalidSignature => write!(f, "the manifest's signature is invalid"),
+        }
+    }
+}
+
+/// Writes a file to an inner writer, computing its [`File`] entry as it's written.
+///
+/// This allows archives to be listed in a manifest without holding their files in memory.
+pub struct FileWriter<W> {
+    name: String,
+    inner: W,
+    size: u64,
+    sha256: Sha256,
+}
+
+impl<W: io::Write> FileWriter<W> {
+    /// Construct a writer for the file `name`, writing its contents to `inner`.
+    #[must_use]
+    pub fn new(name: impl Into<String>, inner: W) -> Self {
+        Self {
+            name: name.into(),
+            inner,
+            size: 0,
+            sha256: Sha256::new(),
+        }
+    }
+
+    /// The number of bytes written so far.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Finish writing the file, returning its entry and the inner writer.
+    #[must_use]
+    pub fn finish(self) -> (File, W) {
+        let file = File {
+            name: self.name,
+            size: self.size,
+            sha256: hex(&self.sha256.finalize()),
+        };
+        (file, self.inner)
+    }
+}
+
+impl<W: io::Write> io::Write for FileWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.sha256.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Manifest {
+    /// Construct a manifest for `files`, given as `(name, contents)` pairs, signed with `key` if
+    /// given.
+    #[must_use]
+    pub fn new<'a>(
+        files: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+        key: Option<&[u8]>,
+    ) -> Self {
+        let files = files
+            .into_iter()
+            .map(|(name, contents)| File {
+                name: name.to_string(),
+                size: contents.len() as u64,
+                sha256: hex(&Sha256::digest(contents)),
+            })
+            .collect();
+        Self::from_files(files, key)
+    }
+
+    /// Construct a manifest for `files` whose entries have already been computed (e.g. with a
+    /// [`FileWriter`]), signed with `key` if given.
+    #[must_use]
+    pub fn from_files(files: Vec<File>, key: Option<&[u8]>) -> Self {
+        let mut manifest = Self {
+            version: VERSION,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            files,
+            signature: None,
+        };
+        if let Some(key) = key {
+            manifest.signature = Some(Signature {
+                algorithm: SIGNATURE_ALGORITHM.to_string(),
+                value: hex(&manifest.mac(key).finalize().into_bytes()),
+            });
+        }
+        manifest
+    }
+
+    /// Verify the files in `directory` against the manifest, and its signature against `key` if
+    /// given.
+    ///
+    /// Returns the problems found, which is empty if the archive is intact.
+    ///
+    /// # Errors
+    ///
+    /// Any [`io::Error`]s encountered when reading files, other than files not being found, are
+    /// returned.
+    pub fn verify(&self, directory: &Path, key: Option<&[u8]>) -> io::Result<Vec<Problem>> {
+        let mut problems = Vec::new();
+
+        if let Some(key) = key {
+            match &self.signature {
+                None => problems.push(Problem::Unsigned),
+                Some(signature) => {
+                    let valid = signature.algorithm == SIGNATURE_ALGORITHM
+                        && unhex(&signature.value)
+                            .map_or(false, |value| self.mac(key).verify(&value).is_ok());
+                    if !valid {
+                        problems.push(Problem::InvalidSignature);
+                    }
+                }
+            }
+        }
+
+        for file in &self.files {
+            let contents = match fs::read(directory.join(&file.name)) {
+                Ok(contents) => contents,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    problems.push(Problem::Missing(file.name.clone()));
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            if contents.len() as u64 != file.size || hex(&Sha256::digest(&contents)) != file.sha256
+            {
+                problems.push(Problem::Modified(file.name.clone()));
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// The MAC of the signed fields of the manifest.
+    fn mac(&self, key: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts any key length");
+        mac.update(&self.version.to_be_bytes());
+        mac.update(&self.created_at.to_be_bytes());
+        for file in &self.files {
+            mac.update(&(file.name.len() as u64).to_be_bytes());
+            mac.update(file.name.as_bytes());
+            mac.update(&file.size.to_be_bytes());
+            mac.update(file.sha256.as_bytes());
+        }
+        mac
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use crate::test;
+
+    use super::{FileWriter, Manifest, Problem};
+
+    #[test]
+    fn verify_archives() -> test::Result {
+        let tempdir = tempfile::tempdir()?;
+        fs::write(tempdir.path().join("1.ndjson"), "{\"line\":\"hello\"}\n")?;
+        let manifest = Manifest::new(
+            vec![("1.ndjson", &b"{\"line\":\"hello\"}\n"[..])],
+            Some(&b"secret"[..]),
+        );
+
+        // Round-trip through JSON, as the manifest would be stored.
+        let manifest: Manifest = serde_json::from_slice(&serde_json::to_vec(&manifest)?)?;
+        assert_eq!(
+            manifest.verify(tempdir.path(), Some(&b"secret"[..]))?,
+            vec![]
+        );
+        assert_eq!(manifest.verify(tempdir.path(), None)?, vec![]);
+        assert_eq!(
+            manifest.verify(tempdir.path(), Some(&b"wrong"[..]))?,
+            vec![Problem::InvalidSignature]
+        );
+
+        let mut tampered = manifest.clone();
+        tampered.files[0].size += 1;
+        assert_eq!(
+            tampered.verify(tempdir.path(), Some(&b"secret"[..]))?,
+            vec![
+                Problem::InvalidSignature,
+                Problem::Modified("1.ndjson".to_string())
+            ]
+        );
+
+        fs::write(tempdir.path().join("1.ndjson"), "{\"line\":\"howdy\"}\n")?;
+        assert_eq!(
+            manifest.verify(tempdir.path(), None)?,
+            vec![Problem::Modified("1.ndjson".to_string())]
+        );
+
+        fs::remove_file(tempdir.path().join("1.ndjson"))?;
+        let unsigned = Manifest::new(vec![("1.ndjson", &b""[..])], None);
+        assert_eq!(
+            unsigned.verify(tempdir.path(), Some(&b"secret"[..]))?,
+            vec![Problem::Unsigned, Problem::Missing("1.ndjson".to_string())]
+        );
+
+        Ok(())
+    }
+    #[test]
+    fn write_files_incrementally() -> test::Result {
+        let mut writer = FileWriter::new("1.ndjson", Vec::new());
+        writer.write_all(b"{\"line\":\"hello\"}\n")?;
+        writer.write_all(b"{\"line\":\"world\"}\n")?;
+        assert_eq!(writer.size(), 34);
+
+        let (file, contents) = writer.finish();
+        let expected = Manifest::new(vec![("1.ndjson", &contents[..])], None);
+        assert_eq!(vec![file], expected.files);
+
+        Ok(())
+    }
+}
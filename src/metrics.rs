@@ -0,0 +1,291 @@
+// src/metrics.rs
+//! Process-wide metrics for `monitoring-rs`.
+//!
+//! Metrics are declared as `static`s next to the code that updates them, and listed in [`ALL`] so
+//! that they can be rendered (in the Prometheus text format) by [`render`].
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use crate::log_collector;
+use crate::log_database;
+use crate::log_database::metrics::{DatabaseMetrics, LatencyHistogram};
+
+/// All the metrics that are rendered by [`render`].
+static ALL: &[&Metric] = &[
+    &log_collector::ownership::DUPLICATE_COLLECTION_DETECTED,
+    &log_collector::ownership::DUPLICATE_COLLECTION_ACTIVE,
+    &log_collector::directory::PERMISSION_DENIED_FILES,
+    &log_collector::directory::PERMISSION_DENIED_TOTAL,
+    &log_collector::directory::SPILLOVER_FILES,
+    &log_database::writer::DROPPED_ENTRIES,
+];
+
+/// A snapshot of the requests to a single API route and method.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteMetrics {
+    /// The route template (e.g. `/logs/:key/*value`).
+    pub route: &'static str,
+
+    /// The request method.
+    pub method: String,
+
+    /// The number of responses in each status class (e.g. `2xx`).
+    pub responses: BTreeMap<String, u64>,
+
+    /// The latency of requests.
+    pub latency: LatencyHistogram,
+}
+
+/// A named metric.
+pub struct Metric {
+    name: &'static str,
+    help: &'static str,
+    value: Value,
+}
+
+enum Value {
+    Counter(AtomicU64),
+    Gauge(AtomicI64),
+}
+
+impl Metric {
+    /// Declare a counter, which can only increase.
+    #[must_use]
+    pub const fn counter(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: Value::Counter(AtomicU64::new(0)),
+        }
+    }
+
+    /// Declare a gauge, which can be set to any value.
+    #[must_use]
+    pub const fn gauge(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: Value::Gauge(AtomicI64::new(0)),
+        }
+    }
+
+    /// Increment a counter by `n`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric is not a counter.
+    pub fn add(&self, n: u64) {
+        match &self.value {
+            Value::Counter(value) => {
+                value.fetch_add(n, Ordering::Relaxed);
+            }
+            Value::Gauge(_) => panic!("metric {} is not a counter", self.name),
+        }
+    }
+
+    /// Increment a counter by 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric is not a counter.
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    /// Set the value of a gauge.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the metric is not a gauge.
+    pub fn set(&self, n: i64) {
+        match &self.value {
+            Value::Gauge(value) => value.store(n, Ordering::Relaxed),
+            Value::Counter(_) => panic!("metric {} is not a gauge", self.name),
+        }
+    }
+
+    /// The current value of the metric.
+    #[must_use]
+    pub fn get(&self) -> i128 {
+        match &self.value {
+            Value::Counter(value) => i128::from(value.load(Ordering::Relaxed)),
+            Value::Gauge(value) => i128::from(value.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.value {
+            Value::Counter(_) => "counter",
+            Value::Gauge(_) => "gauge",
+        }
+    }
+}
+
+/// Render all metrics in the Prometheus text exposition format.
+#[must_use]
+pub fn render() -> String {
+    let mut output = String::new();
+    for metric in ALL {
+        write_header(&mut output, metric.name, metric.help, metric.kind());
+        // `unwrap` is OK because writing to a `String` cannot fail.
+        writeln!(output, "{} {}", metric.name, metric.get()).unwrap();
+    }
+    output
+}
+
+/// Render a [`DatabaseMetrics`] snapshot in the Prometheus text exposition format.
+#[must_use]
+pub fn render_database(metrics: &DatabaseMetrics) -> String {
+    let mut output = String::new();
+    let mut simple = |name, help, kind, value: u64| {
+        write_header(&mut output, name, help, kind);
+        writeln!(output, "{} {}", name, value).unwrap();
+    };
+    simple(
+        "monitoring_rs_database_entries_written_total",
+        "Number of entries written since the database was opened.",
+        "counter",
+        metrics.entries_written,
+    );
+    simple(
+        "monitoring_rs_database_bytes_written_total",
+        "Number of bytes written to log files since the database was opened.",
+        "counter",
+        metrics.bytes_written,
+    );
+    simple(
+        "monitoring_rs_database_oversized_entries_total",
+        "Number of entries longer than the maximum line size since the database was opened.",
+        "counter",
+        metrics.oversized_entries,
+    );
+    simple(
+        "monitoring_rs_database_active_streams",
+        "Number of streams currently stored.",
+        "gauge",
+        metrics.active_streams as u64,
+    );
+    simple(
+        "monitoring_rs_database_streams_created_total",
+        "Number of streams created since the database was opened.",
+        "counter",
+        metrics.streams_created,
+    );
+    simple(
+        "monitoring_rs_database_streams_expired_total",
+        "Number of streams removed by retention since the database was opened.",
+        "counter",
+        metrics.streams_expired,
+    );
+    simple(
+        "monitoring_rs_database_open_file_handles",
+        "Number of log file handles currently held open.",
+        "gauge",
+        metrics.open_file_handles as u64,
+    );
+
+    let name = "monitoring_rs_database_query_duration_seconds";
+    write_header(&mut output, name, "Latency of queries.", "histogram");
+    write_histogram(&mut output, name, "", &metrics.query_latency);
+
+    output
+}
+
+/// Render [`RouteMetrics`] snapshots in the Prometheus text exposition format.
+#[must_use]
+pub fn render_routes(routes: &[RouteMetrics]) -> String {
+    let mut output = String::new();
+
+    let name = "monitoring_rs_api_requests_total";
+    write_header(
+        &mut output,
+        name,
+        "Number of API requests, by route, method, and status class.",
+        "counter",
+    );
+    for route in routes {
+        for (status, count) in &route.responses {
+            writeln!(
+                output,
+                "{}{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                name, route.route, route.method, status, count
+            )
+            .unwrap();
+        }
+    }
+
+    let name = "monitoring_rs_api_request_duration_seconds";
+    write_header(
+        &mut output,
+        name,
+        "Latency of API requests, by route and method.",
+        "histogram",
+    );
+    for route in routes {
+        let labels = format!("route=\"{}\",method=\"{}\"", route.route, route.method);
+        write_histogram(&mut output, name, &labels, &route.latency);
+    }
+
+    output
+}
+
+/// Render the number of secrets detected in each stream in the Prometheus text exposition format.
+///
+/// See [`SecretDetector::detections`](log_collector::secrets::SecretDetector::detections).
+#[must_use]
+pub fn render_secrets(detections: &BTreeMap<String, u64>) -> String {
+    let mut output = String::new();
+
+    let name = "monitoring_rs_secrets_detected_total";
+    write_header(
+        &mut output,
+        name,
+        "Number of likely secrets detected in log lines, by stream.",
+        "counter",
+    );
+    for (stream, count) in detections {
+        let stream = stream.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(output, "{}{{stream=\"{}\"}} {}", name, stream, count).unwrap();
+    }
+
+    output
+}
+
+/// Write the samples of a histogram, with optional comma-separated `labels`.
+fn write_histogram(output: &mut String, name: &str, labels: &str, histogram: &LatencyHistogram) {
+    let separator = if labels.is_empty() { "" } else { "," };
+    for bucket in &histogram.buckets {
+        writeln!(
+            output,
+            "{}_bucket{{{}{}le=\"{}\"}} {}",
+            name, labels, separator, bucket.le_seconds, bucket.count
+        )
+        .unwrap();
+    }
+    writeln!(
+        output,
+        "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+        name, labels, separator, histogram.count
+    )
+    .unwrap();
+    if labels.is_empty() {
+        writeln!(output, "{}_sum {}", name, histogram.sum_seconds).unwrap();
+        writeln!(output, "{}_count {}", name, histogram.count).unwrap();
+    } else {
+        writeln!(
+            output,
+            "{}_sum{{{}}} {}",
+            name, labels, histogram.sum_seconds
+        )
+        .unwrap();
+        writeln!(output, "{}_count{{{}}} {}", name, labels, histogram.count).unwrap();
+    }
+}
+
+fn write_header(output: &mut String, name: &str, help: &str, kind: &str) {
+    // `unwrap` is OK because writing to a `String` cannot fail.
+    writeln!(output, "# HELP {} {}", name, help).unwrap();
+    writeln!(output, "# TYPE {} {}", name, kind).unwrap();
+}
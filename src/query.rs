@@ -0,0 +1,352 @@
+// src/query.rs
+//! A small, LogQL-style query language for logs.
+//!
+//! A query is a stream selector, optionally followed by line filters:
+//!
+//! ```text
+//! {namespace="payments", app="api"} |= "error" != "timeout" |~ `status=5\d\d`
+//! ```
+//!
+//! - The selector selects the streams whose metadata contains every `key="value"` pair. Only `=`
+//!   matchers are supported, since streams are only indexed by their exact metadata.
+//! - `|= "text"` and `!= "text"` keep the lines that do, or don't, contain `text`.
+//! - `|~ "regex"` and `!~ "regex"` keep the lines that do, or don't, match `regex`.
+//!
+//! Wrapping a query in `count_over_time(<query> [<duration>])` (e.g.
+//! `count_over_time({app="api"} |= "error" [5m])`) counts the matching lines logged within the
+//! duration instead (see [`Database::count_selection`]).
+//!
+//! Strings are either double-quoted, with `\"`, `\\`, `\n`, and `\t` escapes, or backtick-quoted
+//! without escapes (which suits regular expressions). Durations are as for
+//! [`retention::parse_duration`].
+//!
+//! [`Database::count_selection`]: crate::log_database::Database::count_selection
+
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+
+use crate::log_collector::paths;
+use crate::log_database::filter::LineFilter;
+use crate::log_database::{retention, Selection};
+
+/// A parsed query.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    /// Return the lines matching a [`LogQuery`].
+    Logs(LogQuery),
+
+    /// Count the lines matching a [`LogQuery`].
+    CountOverTime {
+        /// The lines to count.
+        logs: LogQuery,
+
+        /// Only count lines logged within this duration.
+        range: Duration,
+    },
+}
+
+impl Query {
+    /// The [`LogQuery`] selecting the query's lines.
+    #[must_use]
+    pub fn logs(&self) -> &LogQuery {
+        match self {
+            Self::Logs(logs) | Self::CountOverTime { logs, .. } => logs,
+        }
+    }
+
+    /// The [`Selection`] of the query's lines, when evaluated at `now`.
+    #[must_use]
+    pub fn selection(&self, now: SystemTime) -> Selection {
+        let start = match self {
+            Self::Logs(_) => None,
+            Self::CountOverTime { range, .. } => {
+                Some(now.checked_sub(*range).unwrap_or(UNIX_EPOCH))
+            }
+        };
+        let logs = self.logs();
+        Selection {
+            matchers: logs.matchers.clone(),
+            filters: logs.filters.clone(),
+            start,
+            ..Selection::default()
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { input, position: 0 };
+        let query = parser.query()?;
+        parser.skip_whitespace();
+        if parser.rest().is_empty() {
+            Ok(query)
+        } else {
+            Err(parser.error("unexpected input"))
+        }
+    }
+}
+
+/// A stream selector and line filters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogQuery {
+    /// The `(key, value)` pairs that a stream's metadata must all contain.
+    pub matchers: Vec<(String, String)>,
+
+    /// Filters that lines must all match.
+    pub filters: Vec<LineFilter>,
+}
+
+/// A recursive descent parser for [`Query`]s.
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn query(&mut self) -> Result<Query, String> {
+        if !self.eat("count_over_time") {
+            return Ok(Query::Logs(self.log_query()?));
+        }
+        self.expect("(")?;
+        let logs = self.log_query()?;
+        self.expect("[")?;
+        let range = self.duration()?;
+        self.expect("]")?;
+        self.expect(")")?;
+        Ok(Query::CountOverTime { logs, range })
+    }
+
+    fn log_query(&mut self) -> Result<LogQuery, String> {
+        self.expect("{")?;
+        let mut matchers = Vec::new();
+        loop {
+            let key = self.label()?;
+            if self.eat("=~") || self.eat("!=") || self.eat("!~") {
+                return Err(self.error("only `=` matchers are supported"));
+            }
+            self.expect("=")?;
+            let value = self.string()?;
+            // Normalise `path`s the same way collectors do.
+            let value = if key == "path" {
+                paths::normalize_str(&value)
+            } else {
+                value
+            };
+            matchers.push((key, value));
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect("}")?;
+
+        let mut filters = Vec::new();
+        loop {
+            let filter = if self.eat("|=") {
+                LineFilter::Contains(self.string()?)
+            } else if self.eat("!=") {
+                LineFilter::Not(Box::new(LineFilter::Contains(self.string()?)))
+            } else if self.eat("|~") {
+                LineFilter::Regex(self.regex()?)
+            } else if self.eat("!~") {
+                LineFilter::Not(Box::new(LineFilter::Regex(self.regex()?)))
+            } else {
+                break;
+            };
+            filters.push(filter);
+        }
+
+        Ok(LogQuery { matchers, filters })
+    }
+
+    /// Parse a label name, like `app` or `kubernetes.pod_name`.
+    fn label(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a label name"));
+        }
+        self.position += len;
+        Ok(rest[..len].to_string())
+    }
+
+    /// Parse a double- or backtick-quoted string.
+    fn string(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        if self.eat("`") {
+            let len = self
+                .rest()
+                .find('`')
+                .ok_or_else(|| self.error("unterminated string"))?;
+            let string = self.rest()[..len].to_string();
+            self.position += len + 1;
+            return Ok(string);
+        }
+        if !self.eat("\"") {
+            return Err(self.error("expected a string"));
+        }
+
+        let mut string = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += index + 1;
+                    return Ok(string);
+                }
+                '\\' => match chars.next() {
+                    Some((_, '"')) => string.push('"'),
+                    Some((_, '\\')) => string.push('\\'),
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 't')) => string.push('\t'),
+                    Some((index, c)) => {
+                        self.position += index;
+                        return Err(self.error(&format!("invalid escape `\\{}`", c)));
+                    }
+                    None => break,
+                },
+                c => string.push(c),
+            }
+        }
+        self.position = self.input.len();
+        Err(self.error("unterminated string"))
+    }
+
+    fn regex(&mut self) -> Result<Regex, String> {
+        let pattern = self.string()?;
+        Regex::new(&pattern).map_err(|error| format!("invalid regex `{}`: {}", pattern, error))
+    }
+
+    fn duration(&mut self) -> Result<Duration, String> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c == ']' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let duration = retention::parse_duration(&rest[..len])?;
+        self.position += len;
+        Ok(duration)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consume `token`, after any whitespace, if the input continues with it.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", token)))
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        if self.rest().is_empty() {
+            format!("invalid query: {} at end of query", message)
+        } else {
+            format!(
+                "invalid query: {} at offset {} of query",
+                message, self.position
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use regex::Regex;
+
+    use crate::log_database::filter::LineFilter;
+
+    use super::{LogQuery, Query};
+
+    #[test]
+    fn parse_queries() {
+        let matcher = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let contains = |text: &str| LineFilter::Contains(text.to_string());
+        let regex = |pattern: &str| LineFilter::Regex(Regex::new(pattern).unwrap());
+        let not = |filter| LineFilter::Not(Box::new(filter));
+
+        assert_eq!(
+            r#"{app="api"}"#.parse(),
+            Ok(Query::Logs(LogQuery {
+                matchers: vec![matcher("app", "api")],
+                filters: vec![],
+            }))
+        );
+        assert_eq!(
+            r#" { namespace = "pay\"ments" ,app="api" } |= "error" != "time\\out"
+                |~ `status=5\d\d` !~ "^DEBUG" "#
+                .parse(),
+            Ok(Query::Logs(LogQuery {
+                matchers: vec![matcher("namespace", "pay\"ments"), matcher("app", "api")],
+                filters: vec![
+                    contains("error"),
+                    not(contains("time\\out")),
+                    regex(r"status=5\d\d"),
+                    not(regex("^DEBUG")),
+                ],
+            }))
+        );
+        assert_eq!(
+            r#"{path="/var/log//app.log"}"#.parse::<Query>().unwrap().logs().matchers,
+            vec![matcher("path", "/var/log/app.log")]
+        );
+
+        let query: Query = r#"count_over_time({app="api"} |= "error" [5m])"#.parse().unwrap();
+        assert_eq!(
+            query,
+            Query::CountOverTime {
+                logs: LogQuery {
+                    matchers: vec![matcher("app", "api")],
+                    filters: vec![contains("error")],
+                },
+                range: Duration::from_secs(300),
+            }
+        );
+        let now = SystemTime::now();
+        let selection = query.selection(now);
+        assert_eq!(selection.start, Some(now - Duration::from_secs(300)));
+        assert_eq!(selection.filters, vec![contains("error")]);
+
+        for query in &[
+            "",
+            "{}",
+            r#"{app=~"api"}"#,
+            r#"{app="api"} |= error"#,
+            r#"{app="api"} |~ "(""#,
+            r#"{app="api""#,
+            r#"{app="api} |= "error""#,
+            r#"{app="\d"}"#,
+            r#"{app="api"} extra"#,
+            r#"count_over_time({app="api"})"#,
+            r#"count_over_time({app="api"} [5x])"#,
+        ] {
+            assert!(query.parse::<Query>().is_err(), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+// src/record.rs
+//! The record model shared by collectors, both databases, and the API.
+//!
+//! The crate grew two representations of the same thing: collectors produce [`LogEntry`]s (a line
+//! and metadata, but no timestamp) for the [`log_database`](crate::log_database), while the event
+//! [`database`](crate::database) stores [`Event`]s (a timestamp and data, with the stream's
+//! [`Labels`] held separately). A [`Record`] has all three parts – a timestamp, labels, and a body
+//! – so it can be converted to and from either without losing information the other needs.
+//!
+//! New code should prefer `Record`. The conversions below are shims for code that still uses the
+//! older types:
+//!
+//! - `Record::from(LogEntry)` uses the entry's timestamp, or the current time if it has none, and
+//!   `LogEntry::from(Record)` keeps the timestamp (replacing invalid UTF-8 in the body).
+//! - [`Record::from_event`] and [`Record::into_event`] convert to and from events and their labels.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::database::{Event, Labels, Value};
+use crate::LogEntry;
+
+/// A point in time, in milliseconds since the Unix epoch.
+pub type Timestamp = u64;
+
+/// A timestamped, labelled log line or event.
+///
+/// Records serialize as JSON objects like
+/// `{"timestamp": 1600000000000, "labels": {"namespace": "payments"}, "body": "..."}`, with an
+/// optional `value` for metric samples. Bodies that aren't valid UTF-8 are serialized lossily.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Record {
+    /// When the record was produced.
+    pub timestamp: Timestamp,
+
+    /// The labels identifying the record's stream.
+    #[serde(default)]
+    pub labels: Labels,
+
+    /// The record's content (e.g. a log line).
+    #[serde(with = "body")]
+    pub body: Vec<u8>,
+
+    /// The sampled value, if the record is a metric sample.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+}
+
+impl Record {
+    /// Construct a record.
+    #[must_use]
+    pub fn new(timestamp: Timestamp, labels: Labels, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            timestamp,
+            labels,
+            body: body.into(),
+            value: None,
+        }
+    }
+
+    /// Construct a record timestamped with the current time.
+    #[must_use]
+    pub fn now(labels: Labels, body: impl Into<Vec<u8>>) -> Self {
+        Self::new(now(), labels, body)
+    }
+
+    /// The body as text, replacing any invalid UTF-8.
+    #[must_use]
+    pub fn body_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+
+    /// Construct a record from an `event` in the stream identified by `labels`.
+    #[must_use]
+    pub fn from_event(labels: Labels, event: Event) -> Self {
+        Self {
+            timestamp: event.timestamp(),
+            labels,
+            value: event.value(),
+            body: event.into_data(),
+        }
+    }
+
+    /// Split the record into its stream's labels and an event.
+    #[must_use]
+    pub fn into_event(self) -> (Labels, Event) {
+        let mut event = Event::new(self.timestamp, self.body);
+        if let Some(value) = self.value {
+            event = event.with_value(value);
+        }
+        (self.labels, event)
+    }
+}
+
+impl From<LogEntry> for Record {
+    fn from(entry: LogEntry) -> Self {
+        Self::new(
+            entry.timestamp.unwrap_or_else(now),
+            entry.metadata.into_iter().collect(),
+            entry.line,
+        )
+    }
+}
+
+impl From<Record> for LogEntry {
+    fn from(record: Record) -> Self {
+        let line = match String::from_utf8(record.body) {
+            Ok(line) => line,
+            Err(error) => String::from_utf8_lossy(error.as_bytes()).into_owned(),
+        };
+        Self {
+            line,
+            metadata: record.labels.into_iter().collect(),
+            timestamp: Some(record.timestamp),
+        }
+    }
+}
+
+/// The current time, as a [`Timestamp`].
+#[must_use]
+pub fn now() -> Timestamp {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() * 1000 + u64::from(since.subsec_millis()))
+        .unwrap_or_default()
+}
+
+/// Parse an RFC 3339 timestamp, like `2020-09-13T12:26:40.000Z` or `2020-09-13T14:26:40+02:00`.
+///
+/// Fractions of a second beyond milliseconds are truncated. Returns `None` if `value` is invalid,
+/// or before the Unix epoch.
+#[must_use]
+pub fn parse_rfc3339(value: &str) -> Option<Timestamp> {
+    let bytes = value.as_bytes();
+    let separators = [(4, b'-'), (7, b'-'), (10, b'T'), (13, b':'), (16, b':')];
+    if bytes.len() < 20
+        || separators
+            .iter()
+            .any(|(index, separator)| bytes[*index] != *separator)
+    {
+        return None;
+    }
+    let field = |start, end| field_of(value, start, end);
+    let (year, month, day) = (field(0, 4)?, field(5, 7)?, field(8, 10)?);
+    let (hour, minute, second) = (field(11, 13)?, field(14, 16)?, field(17, 19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let rest = &value[19..];
+    let (fraction, offset) = match rest.strip_prefix('.') {
+        Some(rest) => {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        }
+        None => ("", rest),
+    };
+    if rest.starts_with('.') && fraction.is_empty() {
+        return None;
+    }
+    let offset_secs = match offset {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match offset.as_bytes().first() {
+                Some(b'+') => 1,
+                Some(b'-') => -1,
+                _ => return None,
+            };
+            let offset = &offset[1..];
+            if offset.len() != 5 || offset.as_bytes()[2] != b':' {
+                return None;
+            }
+            let (hours, minutes) = (field_of(offset, 0, 2)?, field_of(offset, 3, 5)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset_secs;
+    let millis: i64 = format!("{:0<3.3}", fraction).parse().ok()?;
+    Timestamp::try_from(secs.checked_mul(1000)? + millis).ok()
+}
+
+/// Parse the digits of `value[start..end]`.
+fn field_of(value: &str, start: usize, end: usize) -> Option<i64> {
+    let digits = value.get(start..end)?;
+    if digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        digits.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// The number of days from the Unix epoch to the given date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Count from March, so leap days fall at the end of the year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// (De)serialization of bodies as strings.
+mod body {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from_utf8_lossy(body))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        Ok(String::deserialize(deserializer)?.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{Event, Value};
+    use crate::test::log_entry;
+    use crate::LogEntry;
+
+    use super::{parse_rfc3339, Record};
+
+    #[test]
+    fn convert_log_entries() {
+        let record = Record::from(log_entry("hello", &[("namespace", "payments")]));
+        assert!(record.timestamp > 0);
+        assert_eq!(record.labels["namespace"], "payments");
+        assert_eq!(record.body_str(), "hello");
+
+        let timestamp = record.timestamp;
+        let entry = LogEntry::from(record);
+        assert_eq!(
+            entry,
+            LogEntry {
+                timestamp: Some(timestamp),
+                ..log_entry("hello", &[("namespace", "payments")])
+            }
+        );
+        assert_eq!(Record::from(entry).timestamp, timestamp);
+
+        let record = Record::new(0, Default::default(), vec![b'h', 0xff]);
+        assert_eq!(LogEntry::from(record).line, "h\u{fffd}");
+    }
+
+    #[test]
+    fn parse_rfc3339_timestamps() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_rfc3339("2021-01-01T00:00:00.123456789Z"),
+            Some(1_609_459_200_123)
+        );
+        assert_eq!(
+            parse_rfc3339("2021-01-01T02:30:00+02:30"),
+            Some(1_609_459_200_000)
+        );
+        assert_eq!(
+            parse_rfc3339("2020-12-31T19:00:00.5-05:00"),
+            Some(1_609_459_200_500)
+        );
+        for value in &[
+            "2021-01-01T00:00:00",
+            "2021-01-01T00:00:00.Z",
+            "2021-01-01T00:00:00+0200",
+            "2021-01-01 00:00:00Z",
+            "1970-01-01T00:00:00+00:01",
+        ] {
+            assert_eq!(parse_rfc3339(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn convert_events() {
+        let labels = [("l1".to_string(), "v1".to_string())]
+            .iter()
+            .cloned()
+            .collect();
+        let event = Event::new(5, b"e1".to_vec()).with_value(Value::Gauge(1.5));
+
+        let record = Record::from_event(labels, event.clone());
+        assert_eq!(record.timestamp, 5);
+        assert_eq!(record.body, b"e1");
+        assert_eq!(record.value, Some(Value::Gauge(1.5)));
+
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": 5,
+                "labels": { "l1": "v1" },
+                "body": "e1",
+                "value": { "gauge": 1.5 },
+            })
+        );
+        assert_eq!(serde_json::from_value::<Record>(json).unwrap(), record);
+
+        let (labels, converted) = record.into_event();
+        assert_eq!(labels["l1"], "v1");
+        assert_eq!(converted, event);
+    }
+}
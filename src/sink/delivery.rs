@@ -0,0 +1,285 @@
+// src/sink/delivery.rs
+//! Batching, retries, and buffering shared by every [`Sink`].
+//!
+//! A [`Delivery`] runs a sink in a background task. Entries [pushed](Delivery::push) to it are
+//! buffered (up to [`Config::buffer_size`] entries), and sent in batches of up to
+//! [`Config::batch_size`] entries, or whatever has been buffered after [`Config::batch_timeout`]
+//! has passed since the first entry in the batch. Failed batches are retried up to
+//! [`Config::max_retries`] times, with exponential backoff, before they're dropped.
+//!
+//! Statistics about the delivery are available from [`Delivery::stats`].
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::future::timeout;
+use async_std::task::{self, JoinHandle};
+use log::warn;
+
+use crate::LogEntry;
+
+use super::Sink;
+
+/// The configuration of a [`Delivery`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The maximum number of entries in a batch.
+    pub batch_size: usize,
+
+    /// The longest time to wait for a batch to fill up before sending it.
+    pub batch_timeout: Duration,
+
+    /// The number of entries to buffer while earlier batches are being sent.
+    pub buffer_size: usize,
+
+    /// The number of times to retry a failed batch before dropping it.
+    pub max_retries: u32,
+
+    /// How long to wait before the first retry. This doubles after every failed retry.
+    pub retry_backoff: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            batch_size: 1000,
+            batch_timeout: Duration::from_secs(1),
+            buffer_size: 10_000,
+            max_retries: 5,
+            retry_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Statistics about a [`Delivery`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize)]
+pub struct Stats {
+    /// The number of batches that have been acknowledged.
+    pub batches_sent: u64,
+
+    /// The number of entries that have been acknowledged.
+    pub entries_sent: u64,
+
+    /// The number of attempts to send a batch that failed.
+    pub failed_attempts: u64,
+
+    /// The number of entries dropped, either because the buffer was full or because their batch
+    /// failed too many times.
+    pub entries_dropped: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    batches_sent: AtomicU64,
+    entries_sent: AtomicU64,
+    failed_attempts: AtomicU64,
+    entries_dropped: AtomicU64,
+}
+
+/// A sink running in a background task.
+#[derive(Debug)]
+pub struct Delivery {
+    sender: async_channel::Sender<LogEntry>,
+    counters: Arc<Counters>,
+    task: JoinHandle<()>,
+}
+
+impl Delivery {
+    /// Start delivering entries to `sink`.
+    #[must_use]
+    pub fn spawn(sink: Box<dyn Sink>, config: Config) -> Self {
+        let (sender, receiver) = async_channel::bounded(config.buffer_size);
+        let counters = Arc::new(Counters::default());
+        let task = task::spawn(run(sink, config, receiver, Arc::clone(&counters)));
+        Self {
+            sender,
+            counters,
+            task,
+        }
+    }
+
+    /// Queue `entry` for delivery, waiting for space if the buffer is full.
+    pub async fn push(&self, entry: LogEntry) {
+        // The receiver is only dropped once the sender has been closed by `close`.
+        let _ = self.sender.send(entry).await;
+    }
+
+    /// Queue `entry` for delivery, dropping it if the buffer is full.
+    ///
+    /// Returns whether the entry was queued.
+    pub fn try_push(&self, entry: LogEntry) -> bool {
+        let queued = self.sender.try_send(entry).is_ok();
+        if !queued {
+            self.counters
+                .entries_dropped
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        queued
+    }
+
+    /// Statistics about the delivery so far.
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        Stats {
+            batches_sent: self.counters.batches_sent.load(Ordering::Relaxed),
+            entries_sent: self.counters.entries_sent.load(Ordering::Relaxed),
+            failed_attempts: self.counters.failed_attempts.load(Ordering::Relaxed),
+            entries_dropped: self.counters.entries_dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Send any buffered entries, then stop, returning the final statistics.
+    pub async fn close(self) -> Stats {
+        self.sender.close();
+        self.task.await;
+        self.stats()
+    }
+}
+
+async fn run(
+    sink: Box<dyn Sink>,
+    config: Config,
+    receiver: async_channel::Receiver<LogEntry>,
+    counters: Arc<Counters>,
+) {
+    let mut batch = Vec::with_capacity(config.batch_size);
+    loop {
+        // Wait indefinitely for the first entry of a batch, then up to the timeout for the rest.
+        match receiver.recv().await {
+            Ok(entry) => batch.push(entry),
+            Err(_) => return,
+        }
+        let fill = async {
+            while batch.len() < config.batch_size {
+                match receiver.recv().await {
+                    Ok(entry) => batch.push(entry),
+                    Err(_) => break,
+                }
+            }
+        };
+        let _ = timeout(config.batch_timeout, fill).await;
+
+        send(&*sink, &config, &batch, &counters).await;
+        batch.clear();
+    }
+}
+
+/// Send `batch`, retrying according to `config`.
+async fn send(sink: &dyn Sink, config: &Config, batch: &[LogEntry], counters: &Counters) {
+    let mut backoff = config.retry_backoff;
+    for attempt in 0..=config.max_retries {
+        match sink.send(batch).await {
+            Ok(()) => {
+                counters.batches_sent.fetch_add(1, Ordering::Relaxed);
+                counters
+                    .entries_sent
+                    .fetch_add(batch.len() as u64, Ordering::Relaxed);
+                return;
+            }
+            Err(error) => {
+                counters.failed_attempts.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "Failed to send batch of {} entries to {:?} (attempt {}): {}",
+                    batch.len(),
+                    sink,
+                    attempt + 1,
+                    error
+                );
+            }
+        }
+        if attempt < config.max_retries {
+            task::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+    counters
+        .entries_dropped
+        .fetch_add(batch.len() as u64, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::test::log_entry;
+    use crate::LogEntry;
+
+    use super::super::Sink;
+    use super::{Config, Delivery, Stats};
+
+    /// A sink that records batches, failing the first `failures` attempts.
+    #[derive(Debug)]
+    struct Recording {
+        batches: Arc<Mutex<Vec<usize>>>,
+        failures: AtomicUsize,
+    }
+
+    #[tide::utils::async_trait]
+    impl Sink for Recording {
+        async fn send(&self, batch: &[LogEntry]) -> io::Result<()> {
+            let failures = self.failures.load(Ordering::Relaxed);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::Relaxed);
+                return Err(io::Error::new(io::ErrorKind::Other, "unavailable"));
+            }
+            self.batches.lock().unwrap().push(batch.len());
+            Ok(())
+        }
+    }
+
+    #[async_std::test]
+    async fn batch_and_retry() {
+        let batches = Arc::default();
+        let sink = Recording {
+            batches: Arc::clone(&batches),
+            failures: AtomicUsize::new(1),
+        };
+        let config = Config {
+            batch_size: 2,
+            batch_timeout: Duration::from_millis(10),
+            retry_backoff: Duration::from_millis(1),
+            ..Config::default()
+        };
+        let delivery = Delivery::spawn(Box::new(sink), config);
+
+        for line in &["a", "b", "c"] {
+            delivery.push(log_entry(line, &[("foo", "bar")])).await;
+        }
+        let stats = delivery.close().await;
+
+        assert_eq!(*batches.lock().unwrap(), vec![2, 1]);
+        assert_eq!(
+            stats,
+            Stats {
+                batches_sent: 2,
+                entries_sent: 3,
+                failed_attempts: 1,
+                entries_dropped: 0,
+            }
+        );
+    }
+
+    #[async_std::test]
+    async fn drop_after_retries() {
+        let sink = Recording {
+            batches: Arc::default(),
+            failures: AtomicUsize::new(usize::MAX),
+        };
+        let config = Config {
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(1),
+            batch_timeout: Duration::from_millis(1),
+            ..Config::default()
+        };
+        let delivery = Delivery::spawn(Box::new(sink), config);
+
+        delivery.push(log_entry("a", &[])).await;
+        let stats = delivery.close().await;
+        assert_eq!(stats.failed_attempts, 3);
+        assert_eq!(stats.entries_dropped, 1);
+    }
+}
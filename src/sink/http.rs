@@ -0,0 +1,108 @@
+// src/sink/http.rs
+//! A [`Sink`] that forwards entries to another instance's `POST /push`.
+//!
+//! Configured with `{"type": "http", "url": "http://aggregator:8000"}`, and optionally a `token`
+//! to send as a bearer token. Batches are sent as newline-delimited JSON using the current push
+//! [`protocol`] version, so an aggregator that's too old to understand them rejects them
+//! explicitly (and they're retried until it's upgraded, or delivery gives up).
+
+use std::fmt;
+use std::io;
+
+use crate::api::protocol;
+use crate::LogEntry;
+
+use super::{Context, Error, Options, Sink};
+
+/// A sink that forwards entries to another instance over HTTP.
+pub struct Http {
+    url: surf::Url,
+    token: Option<String>,
+}
+
+impl fmt::Debug for Http {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Http")
+            .field("url", &self.url.as_str())
+            .field("token", &self.token.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+impl Http {
+    /// Construct a sink that pushes to the instance at `url`, optionally with a bearer `token`.
+    ///
+    /// # Errors
+    ///
+    /// If `url` can't be a base URL, an error message is returned.
+    pub fn new(url: &surf::Url, token: Option<String>) -> Result<Self, String> {
+        let url = url
+            .join("push")
+            .map_err(|error| format!("invalid URL {}: {}", url, error))?;
+        Ok(Self { url, token })
+    }
+
+    /// Construct a sink from its options (see [`Constructor`](super::Constructor)).
+    ///
+    /// # Errors
+    ///
+    /// If `url` is missing or invalid, or `token` isn't a string, an [`Error::Options`] is
+    /// returned.
+    pub fn from_options(options: &Options, _context: &Context) -> Result<Box<dyn Sink>, Error> {
+        let error = |message: String| Error::Options {
+            kind: "http".to_string(),
+            message,
+        };
+        let url = match options.get("url") {
+            Some(serde_json::Value::String(url)) => url,
+            _ => return Err(error("`url` must be a string".to_string())),
+        };
+        let url = surf::Url::parse(url)
+            .map_err(|parse_error| error(format!("invalid URL {}: {}", url, parse_error)))?;
+        let token = match options.get("token") {
+            None => None,
+            Some(serde_json::Value::String(token)) => Some(token.clone()),
+            Some(_) => return Err(error("`token` must be a string".to_string())),
+        };
+        Ok(Box::new(Self::new(&url, token).map_err(error)?))
+    }
+}
+
+#[tide::utils::async_trait]
+impl Sink for Http {
+    async fn send(&self, batch: &[LogEntry]) -> io::Result<()> {
+        let mut body = String::new();
+        for entry in batch {
+            let entry = serde_json::json!({ "line": entry.line, "metadata": entry.metadata });
+            body.push_str(&entry.to_string());
+            body.push('\n');
+        }
+
+        let mut request = surf::post(self.url.clone())
+            .header(
+                protocol::VERSION_HEADER,
+                protocol::CURRENT_VERSION.to_string(),
+            )
+            .body(body);
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+
+        let mut response = request
+            .await
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error.to_string()))?;
+        if response.status().is_success() {
+            return Ok(());
+        }
+        let body = response.body_string().await.unwrap_or_default();
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "push to {} failed with status {}: {}",
+                self.url,
+                response.status(),
+                body
+            ),
+        ))
+    }
+}
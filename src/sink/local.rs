@@ -0,0 +1,61 @@
+// src/sink/local.rs
+//! A [`Sink`] that writes entries to the local log [`Database`].
+//!
+//! Configured with `{"type": "database"}`. There are no options, but a database must be available
+//! in the [`Context`].
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use async_std::sync::RwLock;
+
+use crate::log_database::Database;
+use crate::LogEntry;
+
+use super::{Context, Error, Options, Sink};
+
+/// A sink that writes entries to the local log database.
+pub struct Local {
+    database: Arc<RwLock<Database>>,
+}
+
+impl Local {
+    /// Construct a sink that writes to `database`.
+    #[must_use]
+    pub fn new(database: Arc<RwLock<Database>>) -> Self {
+        Self { database }
+    }
+
+    /// Construct a sink from its options (see [`Constructor`](super::Constructor)).
+    ///
+    /// # Errors
+    ///
+    /// If `context` has no database, an [`Error::Options`] is returned.
+    pub fn from_options(_options: &Options, context: &Context) -> Result<Box<dyn Sink>, Error> {
+        match &context.database {
+            Some(database) => Ok(Box::new(Self::new(Arc::clone(database)))),
+            None => Err(Error::Options {
+                kind: "database".to_string(),
+                message: "no local database is available".to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for Local {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Local").finish()
+    }
+}
+
+#[tide::utils::async_trait]
+impl Sink for Local {
+    async fn send(&self, batch: &[LogEntry]) -> io::Result<()> {
+        let mut database = self.database.write().await;
+        for entry in batch {
+            database.write(entry)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+// sink/mod.rs
+
+//! Destinations for collected log entries.
+//!
+//! A [`Sink`] sends batches of entries somewhere (the local [log database](local), another
+//! aggregator over [HTTP](http), ...). Sinks only implement sending a single batch: batching,
+//! retries, buffering, and statistics are shared by running every sink through a
+//! [`Delivery`](delivery::Delivery).
+//!
+//! Sinks are configured with JSON objects like `{"type": "http", "url": "...", "batch_size": 500}`.
+//! A [`Registry`] maps each `type` to a constructor for the sink, which is given the remaining
+//! fields (except those for [delivery](delivery::Config), which are common to every sink).
+
+pub mod delivery;
+pub mod http;
+pub mod local;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::sync::RwLock;
+
+use crate::log_database::Database;
+use crate::LogEntry;
+
+/// A destination for batches of log entries.
+///
+/// Delivery is at-least-once: returning `Ok` acknowledges that the whole batch has been accepted,
+/// and returning an error means the batch may be sent again (so a batch that was partially
+/// accepted before failing may be duplicated).
+#[tide::utils::async_trait]
+pub trait Sink: fmt::Debug + Send + Sync {
+    /// Send a batch of entries.
+    ///
+    /// # Errors
+    ///
+    /// Any error means the batch was not acknowledged, and may be retried.
+    async fn send(&self, batch: &[LogEntry]) -> io::Result<()>;
+}
+
+/// The fields of a sink's configuration, excluding `type` and the common delivery fields.
+pub type Options = serde_json::Map<String, serde_json::Value>;
+
+/// What a sink may need from the rest of the process when it's constructed.
+#[derive(Clone, Default)]
+pub struct Context {
+    /// The local log database, if one is open.
+    pub database: Option<Arc<RwLock<Database>>>,
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("database", &self.database.is_some())
+            .finish()
+    }
+}
+
+/// Constructs a sink from its options.
+pub type Constructor = fn(&Options, &Context) -> Result<Box<dyn Sink>, Error>;
+
+/// An error in a sink's configuration.
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration isn't a JSON object with a string `type`.
+    Invalid(String),
+
+    /// No sink is registered for the `type`.
+    UnknownType(String),
+
+    /// The sink's options are invalid.
+    Options {
+        /// The sink's type.
+        kind: String,
+
+        /// Why the options are invalid.
+        message: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Invalid(message) => write!(f, "invalid sink configuration: {}", message),
+            Self::UnknownType(kind) => write!(f, "unknown sink type `{}`", kind),
+            Self::Options { kind, message } => write!(f, "invalid `{}` sink: {}", kind, message),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A mapping from sink `type`s to their [`Constructor`]s.
+#[derive(Clone)]
+pub struct Registry {
+    constructors: BTreeMap<String, Constructor>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.kinds()).finish()
+    }
+}
+
+impl Default for Registry {
+    /// A registry with the built-in sinks: `database` ([`local::Local`]) and `http`
+    /// ([`http::Http`]).
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register("database", local::Local::from_options);
+        registry.register("http", http::Http::from_options);
+        registry
+    }
+}
+
+impl Registry {
+    /// A registry with no sinks.
+    #[must_use]
+    pub fn empty() -> Self {
+        Self {
+            constructors: BTreeMap::new(),
+        }
+    }
+
+    /// Register a sink `type`, replacing any existing registration.
+    pub fn register(&mut self, kind: &str, constructor: Constructor) {
+        self.constructors.insert(kind.to_string(), constructor);
+    }
+
+    /// The registered sink types.
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.constructors.keys().map(String::as_str)
+    }
+
+    /// Construct a sink, and its delivery configuration, from a JSON `config` object.
+    ///
+    /// # Errors
+    ///
+    /// If `config` is invalid, or its `type` isn't registered, an [`Error`] is returned.
+    pub fn build(
+        &self,
+        config: &serde_json::Value,
+        context: &Context,
+    ) -> Result<(Box<dyn Sink>, delivery::Config), Error> {
+        let mut options = match config {
+            serde_json::Value::Object(options) => options.clone(),
+            _ => return Err(Error::Invalid("expected a JSON object".to_string())),
+        };
+        let kind = match options.remove("type") {
+            Some(serde_json::Value::String(kind)) => kind,
+            _ => return Err(Error::Invalid("`type` must be a string".to_string())),
+        };
+        let constructor = self
+            .constructors
+            .get(&kind)
+            .ok_or_else(|| Error::UnknownType(kind.clone()))?;
+
+        let delivery = delivery_config(&mut options).map_err(|message| Error::Options {
+            kind: kind.clone(),
+            message,
+        })?;
+        let sink = constructor(&options, context)?;
+        Ok((sink, delivery))
+    }
+}
+
+/// A copy of a sink's JSON `config` that's safe to show, e.g. on `GET /config`, with any `token`
+/// replaced by `"***"`.
+#[must_use]
+pub fn redact(config: &serde_json::Value) -> serde_json::Value {
+    let mut config = config.clone();
+    if let Some(token) = config.get_mut("token") {
+        *token = serde_json::json!("***");
+    }
+    config
+}
+
+/// Remove the common delivery fields from `options`.
+fn delivery_config(options: &mut Options) -> Result<delivery::Config, String> {
+    let mut config = delivery::Config::default();
+    let mut take = |name: &str| -> Result<Option<u64>, String> {
+        match options.remove(name) {
+            None => Ok(None),
+            Some(value) => value
+                .as_u64()
+                .map(Some)
+                .ok_or_else(|| format!("`{}` must be a non-negative integer", name)),
+        }
+    };
+    if let Some(batch_size) = take("batch_size")? {
+        config.batch_size = usize::try_from(batch_size).map_err(|error| error.to_string())?;
+    }
+    if let Some(batch_timeout_ms) = take("batch_timeout_ms")? {
+        config.batch_timeout = Duration::from_millis(batch_timeout_ms);
+    }
+    if let Some(buffer_size) = take("buffer_size")? {
+        config.buffer_size = usize::try_from(buffer_size).map_err(|error| error.to_string())?;
+    }
+    if let Some(max_retries) = take("max_retries")? {
+        config.max_retries = u32::try_from(max_retries).map_err(|error| error.to_string())?;
+    }
+    if let Some(retry_backoff_ms) = take("retry_backoff_ms")? {
+        config.retry_backoff = Duration::from_millis(retry_backoff_ms);
+    }
+    if config.batch_size == 0 || config.buffer_size == 0 {
+        return Err("`batch_size` and `buffer_size` must be positive".to_string());
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{redact, Context, Error, Registry};
+
+    #[test]
+    fn build_sinks() {
+        let registry = Registry::default();
+        assert_eq!(
+            registry.kinds().collect::<Vec<_>>(),
+            vec!["database", "http"]
+        );
+
+        let config = serde_json::json!({
+            "type": "http",
+            "url": "http://aggregator:8000",
+            "batch_size": 10,
+            "batch_timeout_ms": 50,
+        });
+        let (sink, delivery) = registry.build(&config, &Context::default()).unwrap();
+        assert!(format!("{:?}", sink).contains("aggregator"));
+        assert_eq!(delivery.batch_size, 10);
+        assert_eq!(delivery.batch_timeout, Duration::from_millis(50));
+
+        // Tokens aren't shown in debug output or the redacted configuration.
+        let config = serde_json::json!({
+            "type": "http",
+            "url": "http://aggregator:8000",
+            "token": "s3cret",
+        });
+        let (sink, _) = registry.build(&config, &Context::default()).unwrap();
+        assert!(!format!("{:?}", sink).contains("s3cret"));
+        assert_eq!(
+            redact(&config),
+            serde_json::json!({
+                "type": "http",
+                "url": "http://aggregator:8000",
+                "token": "***",
+            })
+        );
+
+        let build = |config| registry.build(&config, &Context::default()).unwrap_err();
+        assert!(matches!(
+            build(serde_json::json!({ "type": "kafka" })),
+            Error::UnknownType(kind) if kind == "kafka"
+        ));
+        assert!(matches!(
+            build(serde_json::json!({ "url": "http://aggregator:8000" })),
+            Error::Invalid(_)
+        ));
+        assert!(matches!(
+            build(serde_json::json!({ "type": "http" })),
+            Error::Options { .. }
+        ));
+        assert!(matches!(
+            build(serde_json::json!({ "type": "http", "url": "x", "batch_size": -1 })),
+            Error::Options { .. }
+        ));
+        // The local database sink needs a database.
+        assert!(matches!(
+            build(serde_json::json!({ "type": "database" })),
+            Error::Options { .. }
+        ));
+    }
+}
@@ -24,6 +24,13 @@ pub fn temp_database() -> io::Result<(TempDir, Database)> {
     let tempdir = tempfile::tempdir()?;
     let config = log_database::Config {
         data_directory: tempdir.path().to_path_buf(),
+        retention: vec![],
+        query_cache_capacity: 0,
+        max_concurrent_queries: None,
+        max_line_size: None,
+        oversized_line_policy: log_database::limits::OversizedLinePolicy::default(),
+        unknown_file_policy: log_database::recovery::UnknownFilePolicy::default(),
+        partitioning: None,
     };
     Ok((tempdir, Database::open(config)?))
 }
@@ -39,5 +46,6 @@ pub fn log_entry(line: &str, metadata: &[(&str, &str)]) -> LogEntry {
             .iter()
             .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
             .collect(),
+        timestamp: None,
     }
 }